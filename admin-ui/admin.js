@@ -0,0 +1,341 @@
+// Admin pages for BetterAuth. Everything shown comes from the admin API,
+// called with the signed-in admin's access token. Values are only ever
+// written as text, never as markup.
+'use strict';
+
+const PAGE_SIZE = 20;
+const TOKEN_KEY = 'betterauth.admin.token';
+
+const state = {
+  token: sessionStorage.getItem(TOKEN_KEY),
+  mfaToken: null, // Held between password and code when MFA is on
+  users: { q: '', offset: 0 },
+  audit: { user_id: '', event_type: '', offset: 0 },
+  userId: null,
+};
+
+const $ = (id) => document.getElementById(id);
+
+function setStatus(message, isError) {
+  const status = $('status');
+  status.textContent = message || '';
+  status.className = isError ? 'error' : '';
+}
+
+async function api(method, path, body, token) {
+  const headers = { Accept: 'application/json' };
+  const bearer = token || state.token;
+  if (bearer) headers.Authorization = 'Bearer ' + bearer;
+  if (body !== undefined) headers['Content-Type'] = 'application/json';
+
+  const response = await fetch(path, {
+    method,
+    headers,
+    body: body === undefined ? undefined : JSON.stringify(body),
+    credentials: 'omit',
+  });
+  const data = await response.json().catch(() => null);
+
+  if (!response.ok) {
+    if (response.status === 401 && !token) signOut();
+    // Problem details carry `detail`; the legacy format `message`
+    const message = data && (data.detail || data.message);
+    throw new Error(message || 'Request failed (' + response.status + ')');
+  }
+  return data;
+}
+
+function query(params) {
+  const search = new URLSearchParams();
+  for (const [key, value] of Object.entries(params)) {
+    if (value !== '' && value !== null && value !== undefined) search.set(key, value);
+  }
+  return search.toString();
+}
+
+function cell(row, value) {
+  const td = document.createElement('td');
+  td.textContent = value === null || value === undefined ? '' : String(value);
+  row.appendChild(td);
+  return td;
+}
+
+function actionCell(row, label, action) {
+  const td = document.createElement('td');
+  const button = document.createElement('button');
+  button.type = 'button';
+  button.textContent = label;
+  button.addEventListener('click', () => run(action));
+  td.appendChild(button);
+  row.appendChild(td);
+}
+
+function when(value) {
+  return value ? new Date(value).toLocaleString() : '';
+}
+
+function pager(container, page, onChange) {
+  container.replaceChildren();
+  const info = document.createElement('span');
+  const last = Math.min(page.offset + page.items.length, page.total);
+  info.textContent = page.total ? page.offset + 1 + '–' + last + ' of ' + page.total : 'Nothing found';
+  container.appendChild(info);
+
+  const move = (label, offset, enabled) => {
+    const button = document.createElement('button');
+    button.type = 'button';
+    button.textContent = label;
+    button.disabled = !enabled;
+    button.addEventListener('click', () => run(() => onChange(offset)));
+    container.appendChild(button);
+  };
+  move('Previous', Math.max(page.offset - page.limit, 0), page.offset > 0);
+  move('Next', page.offset + page.limit, last < page.total);
+}
+
+// Runs an action, reporting its failure instead of leaving it in the console
+async function run(action) {
+  setStatus('');
+  try {
+    await action();
+  } catch (error) {
+    setStatus(error.message, true);
+  }
+}
+
+function showTab(name) {
+  for (const id of ['sign-in', 'users', 'lockouts', 'audit']) {
+    $(id).hidden = id !== name;
+  }
+  $('tabs').hidden = name === 'sign-in';
+  if (name === 'users') run(loadUsers);
+  if (name === 'lockouts') run(loadLockouts);
+  if (name === 'audit') run(loadAudit);
+}
+
+function signIn(token) {
+  state.token = token;
+  sessionStorage.setItem(TOKEN_KEY, token);
+  showTab('users');
+}
+
+function signOut() {
+  if (state.token) {
+    // Ends the admin's own session; the token is dropped either way
+    api('POST', '/auth/logout', {}).catch(() => {});
+  }
+  state.token = null;
+  sessionStorage.removeItem(TOKEN_KEY);
+  $('sign-in-form').hidden = false;
+  $('mfa-form').hidden = true;
+  showTab('sign-in');
+}
+
+// Users
+
+async function loadUsers() {
+  const page = await api(
+    'GET',
+    '/admin/users?' + query({ q: state.users.q, limit: PAGE_SIZE, offset: state.users.offset })
+  );
+
+  const rows = $('user-rows');
+  rows.replaceChildren();
+  for (const user of page.items) {
+    const row = document.createElement('tr');
+    row.className = 'clickable';
+    cell(row, user.username);
+    cell(row, user.email);
+    cell(row, user.status);
+    cell(row, user.mfa_enabled ? 'On' : 'Off');
+    cell(row, when(user.created_at));
+    row.addEventListener('click', () => run(() => loadUser(user.id)));
+    rows.appendChild(row);
+  }
+  pager($('user-pager'), page, (offset) => {
+    state.users.offset = offset;
+    return loadUsers();
+  });
+}
+
+async function loadUser(userId) {
+  state.userId = userId;
+  const [detail, sessions, history] = await Promise.all([
+    api('GET', '/admin/users/' + userId),
+    api('GET', '/admin/users/' + userId + '/sessions?' + query({ limit: 100 })),
+    api('GET', '/admin/users/' + userId + '/status-history'),
+  ]);
+
+  $('user-detail').hidden = false;
+  $('user-title').textContent = detail.user.username;
+
+  const facts = $('user-facts');
+  facts.replaceChildren();
+  const fact = (label, value) => {
+    const dt = document.createElement('dt');
+    dt.textContent = label;
+    const dd = document.createElement('dd');
+    dd.textContent = value === null || value === undefined ? '' : String(value);
+    facts.append(dt, dd);
+  };
+  fact('Id', detail.user.id);
+  fact('Email', detail.user.email + (detail.user.is_email_verified ? '' : ' (unverified)'));
+  fact('Status', detail.user.status);
+  fact('Admin', detail.user.is_admin ? 'Yes' : 'No');
+  fact('MFA', detail.user.mfa_enabled ? 'On' : 'Off');
+  fact('Last login', when(detail.user.last_login_at));
+  fact('Risk score', detail.risk.score);
+
+  const rows = $('session-rows');
+  rows.replaceChildren();
+  for (const session of sessions.items) {
+    const row = document.createElement('tr');
+    cell(row, session.name ? session.name + ' — ' + session.device : session.device);
+    cell(row, session.ip_address);
+    cell(row, when(session.created_at));
+    cell(row, when(session.last_seen_at));
+    actionCell(row, 'Revoke', async () => {
+      await api('DELETE', '/admin/users/' + userId + '/sessions/' + session.id);
+      setStatus('Session revoked');
+      await loadUser(userId);
+    });
+    rows.appendChild(row);
+  }
+
+  const list = $('status-history');
+  list.replaceChildren();
+  for (const event of history) {
+    const item = document.createElement('li');
+    item.textContent = when(event.created_at) + ': ' + event.status + (event.reason ? ' (' + event.reason + ')' : '');
+    list.appendChild(item);
+  }
+}
+
+// Lockouts
+
+async function loadLockouts() {
+  const lockouts = await api('GET', '/admin/lockouts');
+
+  const accounts = $('locked-accounts');
+  accounts.replaceChildren();
+  for (const account of lockouts.accounts) {
+    const row = document.createElement('tr');
+    cell(row, account.username);
+    cell(row, account.email);
+    cell(row, when(account.locked_until));
+    actionCell(row, 'Unlock', async () => {
+      await api('DELETE', '/admin/users/' + account.user_id + '/lockout');
+      setStatus('Account unlocked');
+      await loadLockouts();
+    });
+    accounts.appendChild(row);
+  }
+
+  const ips = $('locked-ips');
+  ips.replaceChildren();
+  for (const locked of lockouts.ips) {
+    const row = document.createElement('tr');
+    cell(row, locked.ip);
+    cell(row, locked.seconds_left);
+    actionCell(row, 'Unlock', async () => {
+      await api('DELETE', '/admin/lockouts/ips/' + encodeURIComponent(locked.ip));
+      setStatus('IP unlocked');
+      await loadLockouts();
+    });
+    ips.appendChild(row);
+  }
+}
+
+// Audit events
+
+async function loadAudit() {
+  const page = await api(
+    'GET',
+    '/admin/audit-events?' +
+      query({
+        user_id: state.audit.user_id,
+        event_type: state.audit.event_type,
+        limit: PAGE_SIZE,
+        offset: state.audit.offset,
+      })
+  );
+
+  const rows = $('audit-rows');
+  rows.replaceChildren();
+  for (const event of page.items) {
+    const row = document.createElement('tr');
+    cell(row, when(event.created_at));
+    cell(row, event.event_type);
+    cell(row, event.aggregate_id);
+    cell(row, event.delivered_at ? when(event.delivered_at) : 'Pending (' + event.attempts + ' attempts)');
+    const payload = cell(row, JSON.stringify(event.payload));
+    payload.className = 'payload';
+    rows.appendChild(row);
+  }
+  pager($('audit-pager'), page, (offset) => {
+    state.audit.offset = offset;
+    return loadAudit();
+  });
+}
+
+document.addEventListener('DOMContentLoaded', () => {
+  $('sign-in-form').addEventListener('submit', (event) => {
+    event.preventDefault();
+    const form = new FormData(event.target);
+    run(async () => {
+      const login = await api('POST', '/auth/login', {
+        username_or_email: form.get('username_or_email'),
+        password: form.get('password'),
+      });
+      event.target.reset();
+      if (login.mfa_required) {
+        state.mfaToken = login.access_token;
+        $('sign-in-form').hidden = true;
+        $('mfa-form').hidden = false;
+        return;
+      }
+      signIn(login.access_token);
+    });
+  });
+
+  $('mfa-form').addEventListener('submit', (event) => {
+    event.preventDefault();
+    const form = new FormData(event.target);
+    run(async () => {
+      const verified = await api('POST', '/auth/mfa-verify', { mfa_code: form.get('mfa_code') }, state.mfaToken);
+      event.target.reset();
+      state.mfaToken = null;
+      signIn(verified.access_token);
+    });
+  });
+
+  $('user-search').addEventListener('submit', (event) => {
+    event.preventDefault();
+    state.users = { q: new FormData(event.target).get('q').trim(), offset: 0 };
+    run(loadUsers);
+  });
+
+  $('revoke-all').addEventListener('click', () => {
+    const userId = state.userId;
+    if (!userId || !confirm('Sign this user out everywhere?')) return;
+    run(async () => {
+      await api('DELETE', '/admin/users/' + userId + '/sessions');
+      setStatus('All sessions revoked');
+      await loadUser(userId);
+    });
+  });
+
+  $('audit-filter').addEventListener('submit', (event) => {
+    event.preventDefault();
+    const form = new FormData(event.target);
+    state.audit = { user_id: form.get('user_id').trim(), event_type: form.get('event_type'), offset: 0 };
+    run(loadAudit);
+  });
+
+  for (const button of document.querySelectorAll('[data-tab]')) {
+    button.addEventListener('click', () => showTab(button.dataset.tab));
+  }
+  $('sign-out').addEventListener('click', signOut);
+
+  showTab(state.token ? 'users' : 'sign-in');
+});
@@ -0,0 +1,71 @@
+//! Benchmarks for the checks on every sign-in. Run with `cargo bench`;
+//! compare against an earlier run with `--save-baseline` and `--baseline` to
+//! spot regressions. Hashing, JWTs and rate limiting are in `service_paths`,
+//! built with `--features server`.
+
+use chrono::{Duration, Utc};
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use uuid::Uuid;
+
+use better_auth_rust::breach_detection::BreachDetectionContext;
+use better_auth_rust::risk_scoring::{GeoLocation, LoginRecord, RiskScoringContext};
+
+fn login_record(index: usize) -> LoginRecord {
+    LoginRecord {
+        timestamp: Utc::now() - Duration::hours(index as i64),
+        ip_address: format!("10.0.{}.{}", index / 256 % 256, index % 256),
+        location: Some(GeoLocation {
+            latitude: 52.52 + (index % 10) as f64 * 0.01,
+            longitude: 13.40,
+            country: "DE".to_string(),
+            city: "Berlin".to_string(),
+        }),
+        device_id: format!("device-{}", index % 5),
+        user_agent: "Mozilla/5.0 (X11; Linux x86_64)".to_string(),
+        success: index % 7 != 0,
+    }
+}
+
+fn risk_analysis(c: &mut Criterion) {
+    let mut group = c.benchmark_group("risk_analysis");
+
+    for history in [10, 1_000, 10_000] {
+        let context = RiskScoringContext::new();
+        let user_id = Uuid::new_v4();
+        for index in (0..history).rev() {
+            context.record_login(&user_id, login_record(index));
+        }
+        let attempt = LoginRecord {
+            timestamp: Utc::now(),
+            ip_address: "203.0.113.9".to_string(),
+            location: Some(GeoLocation {
+                latitude: 40.71,
+                longitude: -74.00,
+                country: "US".to_string(),
+                city: "New York".to_string(),
+            }),
+            device_id: "new-device".to_string(),
+            user_agent: "Mozilla/5.0 (Macintosh)".to_string(),
+            success: true,
+        };
+
+        group.throughput(Throughput::Elements(history as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(history), &attempt, |b, attempt| {
+            b.iter(|| context.analyze_login_risk(black_box(&user_id), black_box(attempt)))
+        });
+    }
+
+    group.finish();
+}
+
+fn breach_check(c: &mut Criterion) {
+    let context = BreachDetectionContext::new();
+    let user_id = Uuid::new_v4();
+
+    c.bench_function("breach_check", |b| {
+        b.iter(|| context.check_user_breach(black_box("user@example.com"), black_box("Benchmark123!"), &user_id))
+    });
+}
+
+criterion_group!(benches, risk_analysis, breach_check);
+criterion_main!(benches);
@@ -0,0 +1,142 @@
+//! Benchmarks for the service layer's hot paths: password hashing, JWTs and
+//! the rate limiter. Needs the service modules, so run with
+//! `cargo bench --features server --bench service_paths`.
+
+use actix_web::{test, web, App, HttpResponse};
+use chrono::{Duration, Utc};
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use uuid::Uuid;
+
+use better_auth_rust::config::{RateLimitConfig, RateLimitKey, RateLimitPolicy};
+use better_auth_rust::middleware::rate_limiter::RateLimiter;
+use better_auth_rust::utils::jwt::{create_jwt, decode_jwt_with_secret, JwtClaims, TokenAudience, TokenScope};
+use better_auth_rust::utils::password::{hash_password, verify_password};
+
+const SECRET: &str = "benchmark_secret_key";
+
+fn password_hashing(c: &mut Criterion) {
+    let mut group = c.benchmark_group("password");
+    // Argon2 is slow on purpose; fewer samples keep the run short
+    group.sample_size(20);
+
+    group.bench_function("hash", |b| b.iter(|| hash_password(black_box("Benchmark123!")).unwrap()));
+
+    let hash = hash_password("Benchmark123!").unwrap();
+    group.bench_function("verify", |b| {
+        b.iter(|| verify_password(black_box("Benchmark123!"), black_box(&hash)).unwrap())
+    });
+    group.bench_function("verify_wrong", |b| {
+        b.iter(|| verify_password(black_box("Wrong123!"), black_box(&hash)).unwrap())
+    });
+
+    group.finish();
+}
+
+fn claims() -> JwtClaims {
+    JwtClaims {
+        sub: Uuid::new_v4(),
+        iss: "better-auth".to_string(),
+        aud: "web".to_string(),
+        exp: (Utc::now() + Duration::hours(1)).timestamp() as usize,
+        iat: Utc::now().timestamp() as usize,
+        is_admin: false,
+        token_version: 0,
+        scope: TokenScope::Full,
+        auth_time: Some(Utc::now().timestamp() as usize),
+        amr: vec!["pwd".to_string()],
+        aal: Some(1),
+        scopes: vec!["users:read".to_string(), "sessions:read".to_string()],
+        cnf: None,
+        sid: None,
+        jti: Some(Uuid::new_v4()),
+        act: None,
+    }
+}
+
+fn jwt(c: &mut Criterion) {
+    let mut group = c.benchmark_group("jwt");
+    let claims = claims();
+    let token = create_jwt(&claims, SECRET).unwrap();
+    let expected = TokenAudience::new("better-auth", &["web".to_string()]);
+
+    group.bench_function("encode", |b| b.iter(|| create_jwt(black_box(&claims), SECRET).unwrap()));
+    group.bench_function("decode", |b| {
+        b.iter(|| decode_jwt_with_secret::<JwtClaims>(black_box(&token), SECRET, &expected).unwrap())
+    });
+
+    group.finish();
+}
+
+fn rate_limiter(c: &mut Criterion) {
+    let mut group = c.benchmark_group("rate_limiter");
+    let runtime = actix_web::rt::System::new();
+
+    let config = RateLimitConfig {
+        requests: u32::MAX,
+        duration: 60,
+        policies: vec![RateLimitPolicy {
+            method: Some("POST".to_string()),
+            path: "/auth/login".to_string(),
+            requests: u32::MAX,
+            duration: 60,
+            key: RateLimitKey::Username,
+        }],
+    };
+
+    let app = runtime.block_on(test::init_service(
+        App::new()
+            .wrap(RateLimiter::from_config(&config))
+            .route("/auth/login", web::post().to(HttpResponse::Ok))
+            .route("/health", web::get().to(HttpResponse::Ok)),
+    ));
+
+    group.throughput(Throughput::Elements(1));
+
+    // Only the global per-IP budget applies
+    group.bench_function("global_budget", |b| {
+        b.iter_batched(
+            || test::TestRequest::get().uri("/health").peer_addr("10.0.0.1:4000".parse().unwrap()).to_request(),
+            |request| runtime.block_on(test::call_service(&app, request)),
+            BatchSize::SmallInput,
+        )
+    });
+
+    // The login policy buffers the body to key on the username
+    group.bench_function("username_policy", |b| {
+        let mut counter = 0u64;
+        b.iter_batched(
+            || {
+                counter += 1;
+                test::TestRequest::post()
+                    .uri("/auth/login")
+                    .peer_addr("10.0.0.1:4000".parse().unwrap())
+                    .set_json(serde_json::json!({ "username_or_email": format!("user{}", counter % 1_000) }))
+                    .to_request()
+            },
+            |request| runtime.block_on(test::call_service(&app, request)),
+            BatchSize::SmallInput,
+        )
+    });
+
+    // Many distinct clients, so the cache grows and is swept
+    group.bench_function("many_ips", |b| {
+        let mut counter = 0u32;
+        b.iter_batched(
+            || {
+                counter = counter.wrapping_add(1);
+                let ip = std::net::Ipv4Addr::from(0x0A00_0000 | (counter % 100_000));
+                test::TestRequest::get()
+                    .uri("/health")
+                    .peer_addr(std::net::SocketAddr::from((ip, 4000)))
+                    .to_request()
+            },
+            |request| runtime.block_on(test::call_service(&app, request)),
+            BatchSize::SmallInput,
+        )
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, password_hashing, jwt, rate_limiter);
+criterion_main!(benches);
@@ -0,0 +1,118 @@
+## Error messages, keyed by `error-` + the lowercased error code
+
+error-invalid-credentials = Invalid credentials
+error-user-not-found = User not found
+error-email-exists = Email already exists
+error-username-exists = Username already exists
+error-invalid-token = Invalid token
+error-token-expired = Token expired
+error-email-not-verified = Email not verified. Request a new verification link from /auth/resend-verification-email
+error-invalid-verification-code = Invalid verification code
+error-mfa-required = MFA required
+error-invalid-mfa-code = Invalid MFA code
+error-database-error = Database error: { $detail }
+error-validation-error = Validation error: { $detail }
+error-rate-limit-exceeded = Rate limit exceeded
+error-quota-exceeded = This API key has used up its request quota
+error-idempotency-conflict = A request with this Idempotency-Key is already in progress
+error-version-conflict = The account was changed by another request; reload it and try again
+error-permission-denied = Permission denied
+error-insufficient-scope = This token is missing the { $detail } scope
+error-account-disabled = Account is disabled. See /auth/account-status for the reason and how to appeal
+error-account-archived = This account was archived after a long time unused. Follow the link we emailed you to reactivate it
+# The message an admin set when freezing logins, shown as written
+error-logins-frozen = { $detail }
+error-login-not-allowed = Your sign-in policy doesn't allow logins from this country or at this time
+error-account-locked = Too many failed sign-in attempts. Try again later, or reset your password
+error-email-resend-throttled = An email was sent to this address recently. Check your inbox, or try again later
+error-password-reset-required = Your password must be reset before you can log in
+error-reauthentication-required = Please re-enter your credentials to continue
+error-login-approval-pending = This login is waiting for approval from the link we emailed you
+error-network-changed = You're connecting from a different network. Send the refresh again with your password, and your MFA code if it's enabled
+error-sso-required = This account signs in through its organization's identity provider. Start at /auth/sso/discover
+error-sso-error = Single sign-on failed: { $detail }
+error-captcha-required = Please complete the CAPTCHA to continue
+error-invalid-captcha = The CAPTCHA answer was wrong or has expired, please try a new one
+error-voice-command-not-recognized = Sorry, we didn't catch that. Please try saying the command again
+error-speech-recognition-unavailable = Voice commands are unavailable right now
+error-authenticator-not-allowed = This security key or passkey provider can't be used here: { $detail }
+error-payload-too-large = The upload is larger than the { $detail } byte limit
+error-email-error = Email error: { $detail }
+error-internal-server-error = Internal server error: { $detail }
+
+## Emails
+
+email-verify-subject = Verify your email address
+email-verify-heading = Verify your email address
+email-verify-body = Thank you for registering! Please click the link below to verify your email address:
+email-verify-action = Verify Email
+email-reset-subject = Reset your password
+email-reset-heading = Reset your password
+email-reset-body = You requested a password reset. Please click the link below to reset your password:
+email-reset-action = Reset Password
+email-reset-ignore = If you didn't request a password reset, please ignore this email.
+email-approval-subject = Approve your sign-in
+email-approval-heading = Was this you?
+email-approval-body = Someone is trying to sign in to your account from { $device } ({ $ip }). If this was you, approve the sign-in:
+email-approval-action = Approve Sign-in
+email-approval-ignore = If this wasn't you, ignore this email and change your password.
+email-approval-expiry = This link will expire in { $minutes } minutes.
+email-link-fallback = Or copy and paste this link: { $url }
+email-code-subject = Your sign-in code
+email-code-heading = Your sign-in code
+email-code-body = Enter this code to finish signing in:
+email-code-expiry = The code will expire in { $minutes } minutes.
+email-code-ignore = If you didn't try to sign in, you can ignore this email. Nobody can sign in without the code.
+email-link-expiry = This link will expire in 24 hours.
+email-activate-subject = Set up your account
+email-activate-heading = Set up your account
+email-activate-body = An account was created for this address. Click the link below to choose a password and start using it:
+email-activate-action = Choose a Password
+email-activate-expiry = This link will expire in { $days } days.
+email-activate-ignore = If you weren't expecting this, you can ignore this email. The account stays unusable until a password is set.
+email-reactivate-subject = Reactivate your account
+email-reactivate-heading = Welcome back
+email-reactivate-body = Your account was archived after a long time unused. Someone just signed in to it with the right password; click the link below to reactivate it, then sign in again:
+email-reactivate-action = Reactivate Account
+email-reactivate-expiry = This link will expire in { $hours } hours.
+email-reactivate-ignore = If this wasn't you, ignore this email and the account stays archived. Whoever signed in knows your password, so reset it.
+email-lock-subject = Lock your account
+email-lock-heading = Lock your account
+email-lock-body = Someone asked to lock the account that uses this address. If you think someone else got into it, click the link below: every device is signed out, and nobody can sign in until you reset your password.
+email-lock-action = Lock Account
+email-lock-expiry = This link will expire in { $hours } hours.
+email-lock-ignore = If you didn't ask for this, ignore this email and nothing changes.
+email-backup-subject = Verify your backup email address
+email-backup-heading = Verify your backup email address
+email-backup-body = This address was added as a backup for account recovery and security notifications. Please click the link below to confirm it:
+email-backup-ignore = If you didn't add this address, please ignore this email.
+email-security-subject = Security alert for your account
+email-security-heading = Your account was changed
+email-security-ignore = If this was you, you can ignore this email. If not, reset your password right away.
+email-security-unsubscribe = You get these alerts because they are on for your account. To stop them or choose which emails you get:
+email-registration-subject = About your sign-up
+email-registration-heading = Someone tried to sign up with this address
+email-registration-email-taken = An account already uses this email address, so no new account was created. If this was you, sign in or reset your password instead.
+email-registration-username-taken = The username { $username } is already taken, so no account was created. Please sign up again with a different username.
+email-registration-ignore = If you didn't try to sign up, you can ignore this email.
+security-event-password-changed = Your password was changed.
+security-event-password-reset = Your password was reset using a link sent to { $email }.
+security-event-mfa-disabled = Two-factor authentication was turned off.
+security-event-backup-email-added = { $email } was added as a backup email address.
+security-event-backup-email-removed = { $email } was removed from your backup email addresses.
+security-event-suspicious-activity = We noticed unusual sign-in activity on your account, such as repeated failed logins.
+security-event-sessions-revoked = Because of unusual activity, you were signed out on every device.
+security-event-mfa-reenrollment = Because of unusual activity, two-factor authentication was reset. Set it up again the next time you sign in.
+security-event-account-locked = Your account was locked and signed out on every device. To unlock it, reset your password with a link sent to your primary email address.
+security-event-security-questions-used = Someone signed in to your account by answering your security questions instead of using two-factor authentication.
+
+## Responses
+
+register-success = User registered successfully. Please verify your email.
+register-pending = Thanks for signing up. Check your email to continue.
+account-activated = Your password is set. You can now sign in.
+account-reactivated = Your account is active again. You can now sign in.
+account-locked = Your account is locked and signed out everywhere. To unlock it, reset your password.
+account-lock-requested = If the email is registered, a link to lock the account has been sent
+verification-email-sent = Verification email sent successfully
+password-reset-requested = If the email is registered, a password reset link has been sent
@@ -0,0 +1,118 @@
+## Mensajes de error
+
+error-invalid-credentials = Credenciales no válidas
+error-user-not-found = Usuario no encontrado
+error-email-exists = El correo electrónico ya existe
+error-username-exists = El nombre de usuario ya existe
+error-invalid-token = Token no válido
+error-token-expired = El token ha caducado
+error-email-not-verified = Correo electrónico no verificado. Solicita un nuevo enlace de verificación en /auth/resend-verification-email
+error-invalid-verification-code = Código de verificación no válido
+error-mfa-required = Se requiere MFA
+error-invalid-mfa-code = Código MFA no válido
+error-database-error = Error de base de datos: { $detail }
+error-validation-error = Error de validación: { $detail }
+error-rate-limit-exceeded = Límite de solicitudes excedido
+error-quota-exceeded = Esta clave de API ha agotado su cuota de solicitudes
+error-idempotency-conflict = Ya hay una solicitud en curso con esta Idempotency-Key
+error-version-conflict = La cuenta fue modificada por otra solicitud; vuelve a cargarla e inténtalo de nuevo
+error-permission-denied = Permiso denegado
+error-insufficient-scope = A este token le falta el permiso { $detail }
+error-account-disabled = La cuenta está deshabilitada. Consulta /auth/account-status para ver el motivo y cómo apelar
+error-account-archived = Esta cuenta se archivó tras mucho tiempo sin usarse. Sigue el enlace que te enviamos por correo para reactivarla
+# El mensaje que un administrador puso al congelar los inicios de sesión, tal cual
+error-logins-frozen = { $detail }
+error-login-not-allowed = Tu política de inicio de sesión no permite accesos desde este país o a esta hora
+error-account-locked = Demasiados intentos fallidos de inicio de sesión. Inténtalo más tarde o restablece tu contraseña
+error-email-resend-throttled = Se envió un correo a esta dirección hace poco. Revisa tu bandeja de entrada o inténtalo más tarde
+error-password-reset-required = Debes restablecer tu contraseña antes de iniciar sesión
+error-reauthentication-required = Vuelve a introducir tus credenciales para continuar
+error-login-approval-pending = Este inicio de sesión está pendiente de aprobación desde el enlace que te enviamos
+error-network-changed = Te estás conectando desde otra red. Vuelve a enviar la renovación con tu contraseña, y tu código MFA si lo tienes activado
+error-sso-required = Esta cuenta inicia sesión a través del proveedor de identidad de su organización. Empieza en /auth/sso/discover
+error-sso-error = Error en el inicio de sesión único: { $detail }
+error-captcha-required = Completa el CAPTCHA para continuar
+error-invalid-captcha = La respuesta del CAPTCHA es incorrecta o ha caducado, prueba con uno nuevo
+error-voice-command-not-recognized = No hemos entendido el comando. Inténtalo de nuevo
+error-speech-recognition-unavailable = Los comandos de voz no están disponibles en este momento
+error-authenticator-not-allowed = Esta llave de seguridad o proveedor de passkeys no se puede usar aquí: { $detail }
+error-payload-too-large = El archivo supera el límite de { $detail } bytes
+error-email-error = Error de correo electrónico: { $detail }
+error-internal-server-error = Error interno del servidor: { $detail }
+
+## Correos electrónicos
+
+email-verify-subject = Verifica tu dirección de correo electrónico
+email-verify-heading = Verifica tu dirección de correo electrónico
+email-verify-body = ¡Gracias por registrarte! Haz clic en el siguiente enlace para verificar tu correo electrónico:
+email-verify-action = Verificar correo
+email-reset-subject = Restablece tu contraseña
+email-reset-heading = Restablece tu contraseña
+email-reset-body = Solicitaste restablecer tu contraseña. Haz clic en el siguiente enlace para restablecerla:
+email-reset-action = Restablecer contraseña
+email-reset-ignore = Si no solicitaste este cambio, ignora este correo.
+email-approval-subject = Aprueba tu inicio de sesión
+email-approval-heading = ¿Fuiste tú?
+email-approval-body = Alguien intenta iniciar sesión en tu cuenta desde { $device } ({ $ip }). Si fuiste tú, aprueba el inicio de sesión:
+email-approval-action = Aprobar inicio de sesión
+email-approval-ignore = Si no fuiste tú, ignora este correo y cambia tu contraseña.
+email-approval-expiry = Este enlace caducará en { $minutes } minutos.
+email-link-fallback = O copia y pega este enlace: { $url }
+email-code-subject = Tu código de inicio de sesión
+email-code-heading = Tu código de inicio de sesión
+email-code-body = Introduce este código para terminar de iniciar sesión:
+email-code-expiry = El código caducará en { $minutes } minutos.
+email-code-ignore = Si no intentaste iniciar sesión, puedes ignorar este correo. Nadie puede iniciar sesión sin el código.
+email-link-expiry = Este enlace caducará en 24 horas.
+email-activate-subject = Configura tu cuenta
+email-activate-heading = Configura tu cuenta
+email-activate-body = Se ha creado una cuenta para esta dirección. Haz clic en el enlace de abajo para elegir una contraseña y empezar a usarla:
+email-activate-action = Elegir contraseña
+email-activate-expiry = Este enlace caducará en { $days } días.
+email-activate-ignore = Si no esperabas este correo, puedes ignorarlo. La cuenta no se puede usar hasta que se establezca una contraseña.
+email-reactivate-subject = Reactiva tu cuenta
+email-reactivate-heading = Te damos la bienvenida de nuevo
+email-reactivate-body = Tu cuenta se archivó tras mucho tiempo sin usarse. Alguien acaba de iniciar sesión en ella con la contraseña correcta; haz clic en el enlace de abajo para reactivarla y vuelve a iniciar sesión:
+email-reactivate-action = Reactivar cuenta
+email-reactivate-expiry = Este enlace caducará en { $hours } horas.
+email-reactivate-ignore = Si no fuiste tú, ignora este correo y la cuenta seguirá archivada. Quien inició sesión conoce tu contraseña, así que restablécela.
+email-lock-subject = Bloquea tu cuenta
+email-lock-heading = Bloquea tu cuenta
+email-lock-body = Alguien pidió bloquear la cuenta que usa esta dirección. Si crees que otra persona entró en ella, haz clic en el enlace de abajo: se cerrará la sesión en todos los dispositivos y nadie podrá iniciar sesión hasta que restablezcas tu contraseña.
+email-lock-action = Bloquear cuenta
+email-lock-expiry = Este enlace caducará en { $hours } horas.
+email-lock-ignore = Si no lo pediste, ignora este correo y no cambiará nada.
+email-backup-subject = Verifica tu correo electrónico de respaldo
+email-backup-heading = Verifica tu correo electrónico de respaldo
+email-backup-body = Esta dirección se agregó como respaldo para recuperar la cuenta y recibir avisos de seguridad. Haz clic en el siguiente enlace para confirmarla:
+email-backup-ignore = Si no agregaste esta dirección, ignora este correo.
+email-security-subject = Alerta de seguridad de tu cuenta
+email-security-heading = Se realizó un cambio en tu cuenta
+email-security-ignore = Si fuiste tú, puedes ignorar este correo. Si no, restablece tu contraseña de inmediato.
+email-security-unsubscribe = Recibes estas alertas porque están activadas en tu cuenta. Para dejar de recibirlas o elegir qué correos recibes:
+email-registration-subject = Sobre tu registro
+email-registration-heading = Alguien intentó registrarse con esta dirección
+email-registration-email-taken = Ya existe una cuenta con esta dirección de correo, así que no se creó una nueva. Si fuiste tú, inicia sesión o restablece tu contraseña.
+email-registration-username-taken = El nombre de usuario { $username } ya está en uso, así que no se creó la cuenta. Regístrate de nuevo con otro nombre de usuario.
+email-registration-ignore = Si no intentaste registrarte, puedes ignorar este correo.
+security-event-password-changed = Se cambió tu contraseña.
+security-event-password-reset = Se restableció tu contraseña con un enlace enviado a { $email }.
+security-event-mfa-disabled = Se desactivó la autenticación de dos factores.
+security-event-backup-email-added = Se agregó { $email } como correo electrónico de respaldo.
+security-event-backup-email-removed = Se eliminó { $email } de tus correos electrónicos de respaldo.
+security-event-suspicious-activity = Detectamos actividad de inicio de sesión inusual en tu cuenta, como intentos fallidos repetidos.
+security-event-sessions-revoked = Debido a actividad inusual, se cerró tu sesión en todos los dispositivos.
+security-event-mfa-reenrollment = Debido a actividad inusual, se restableció la autenticación de dos factores. Configúrala de nuevo la próxima vez que inicies sesión.
+security-event-account-locked = Tu cuenta se bloqueó y se cerró la sesión en todos los dispositivos. Para desbloquearla, restablece tu contraseña con un enlace enviado a tu correo electrónico principal.
+security-event-security-questions-used = Alguien inició sesión en tu cuenta respondiendo tus preguntas de seguridad en lugar de usar la autenticación de dos factores.
+
+## Respuestas
+
+register-success = Usuario registrado correctamente. Por favor, verifica tu correo electrónico.
+register-pending = Gracias por registrarte. Revisa tu correo para continuar.
+account-activated = Tu contraseña está configurada. Ya puedes iniciar sesión.
+account-reactivated = Tu cuenta vuelve a estar activa. Ya puedes iniciar sesión.
+account-locked = Tu cuenta está bloqueada y se cerró la sesión en todos los dispositivos. Para desbloquearla, restablece tu contraseña.
+account-lock-requested = Si el correo está registrado, se ha enviado un enlace para bloquear la cuenta
+verification-email-sent = Correo de verificación enviado correctamente
+password-reset-requested = Si el correo está registrado, se ha enviado un enlace para restablecer la contraseña
@@ -0,0 +1,2 @@
+-- Access tokens carry the version they were issued with; bumping it invalidates them all
+ALTER TABLE users ADD COLUMN token_version INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,14 @@
+CREATE TABLE mfa_totp_devices (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    secret TEXT NOT NULL,
+    is_confirmed BOOLEAN NOT NULL DEFAULT FALSE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    confirmed_at TIMESTAMPTZ,
+    last_used_at TIMESTAMPTZ
+);
+
+-- Indexes
+CREATE INDEX idx_mfa_totp_devices_user_id ON mfa_totp_devices(user_id);
+CREATE UNIQUE INDEX idx_mfa_totp_devices_user_name ON mfa_totp_devices(user_id, name);
@@ -0,0 +1,6 @@
+CREATE TABLE passkey_prompts (
+    user_id UUID PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+    prompt_count INTEGER NOT NULL DEFAULT 0,
+    last_prompted_at TIMESTAMPTZ,
+    dismissed_at TIMESTAMPTZ
+);
@@ -0,0 +1,3 @@
+ALTER TABLE sessions DROP COLUMN IF EXISTS device_class;
+ALTER TABLE sessions DROP COLUMN IF EXISTS os;
+ALTER TABLE sessions DROP COLUMN IF EXISTS browser;
@@ -0,0 +1,4 @@
+-- Parsed from user_agent when the session is created
+ALTER TABLE sessions ADD COLUMN browser TEXT;
+ALTER TABLE sessions ADD COLUMN os TEXT;
+ALTER TABLE sessions ADD COLUMN device_class TEXT;
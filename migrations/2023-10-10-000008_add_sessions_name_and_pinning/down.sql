@@ -0,0 +1,2 @@
+ALTER TABLE sessions DROP COLUMN IF EXISTS is_pinned;
+ALTER TABLE sessions DROP COLUMN IF EXISTS name;
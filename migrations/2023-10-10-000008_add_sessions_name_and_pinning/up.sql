@@ -0,0 +1,3 @@
+-- User-chosen label, and pinned (trusted) sessions that outlive bulk sign-outs
+ALTER TABLE sessions ADD COLUMN name TEXT;
+ALTER TABLE sessions ADD COLUMN is_pinned BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,2 @@
+ALTER TABLE users DROP COLUMN IF EXISTS password_reset_email;
+DROP TABLE IF EXISTS user_emails;
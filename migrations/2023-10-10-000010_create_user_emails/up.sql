@@ -0,0 +1,19 @@
+-- Secondary addresses used for recovery and security notifications
+CREATE TABLE user_emails (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    email TEXT NOT NULL,
+    is_verified BOOLEAN NOT NULL DEFAULT FALSE,
+    verification_token TEXT,
+    verification_sent_at TIMESTAMPTZ,
+    verified_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- Indexes
+CREATE INDEX idx_user_emails_user_id ON user_emails(user_id);
+CREATE UNIQUE INDEX idx_user_emails_email ON user_emails(email);
+CREATE UNIQUE INDEX idx_user_emails_verification_token ON user_emails(verification_token);
+
+-- Which address a pending password reset was sent to
+ALTER TABLE users ADD COLUMN password_reset_email TEXT;
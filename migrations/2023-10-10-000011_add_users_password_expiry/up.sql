@@ -0,0 +1,2 @@
+-- When the current password stops being accepted without a change; NULL never expires
+ALTER TABLE users ADD COLUMN password_expires_at TIMESTAMPTZ;
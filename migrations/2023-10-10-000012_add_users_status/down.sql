@@ -0,0 +1,10 @@
+DROP TABLE IF EXISTS account_appeals;
+DROP TABLE IF EXISTS account_status_events;
+
+ALTER TABLE users ADD COLUMN is_active BOOLEAN NOT NULL DEFAULT TRUE;
+UPDATE users SET is_active = (status = 'active');
+
+ALTER TABLE users DROP COLUMN IF EXISTS status_changed_at;
+ALTER TABLE users DROP COLUMN IF EXISTS status_changed_by;
+ALTER TABLE users DROP COLUMN IF EXISTS status_reason;
+ALTER TABLE users DROP COLUMN IF EXISTS status;
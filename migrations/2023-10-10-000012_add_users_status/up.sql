@@ -0,0 +1,38 @@
+-- Replace the is_active flag with a status that says why an account can't sign in
+ALTER TABLE users ADD COLUMN status TEXT NOT NULL DEFAULT 'active'
+    CHECK (status IN ('active', 'suspended', 'banned', 'pending_deletion'));
+ALTER TABLE users ADD COLUMN status_reason TEXT;
+ALTER TABLE users ADD COLUMN status_changed_by UUID REFERENCES users(id) ON DELETE SET NULL;
+ALTER TABLE users ADD COLUMN status_changed_at TIMESTAMPTZ;
+
+UPDATE users SET status = 'suspended', status_changed_at = updated_at WHERE NOT is_active;
+ALTER TABLE users DROP COLUMN is_active;
+
+-- Every status change, kept for audit
+CREATE TABLE account_status_events (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    status TEXT NOT NULL,
+    reason TEXT NOT NULL,
+    actor_id UUID REFERENCES users(id) ON DELETE SET NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- Users asking for a suspension or ban to be lifted
+CREATE TABLE account_appeals (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    message TEXT NOT NULL,
+    status TEXT NOT NULL DEFAULT 'pending'
+        CHECK (status IN ('pending', 'accepted', 'rejected')),
+    resolution_note TEXT,
+    resolved_by UUID REFERENCES users(id) ON DELETE SET NULL,
+    resolved_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- Indexes
+CREATE INDEX idx_account_status_events_user_id ON account_status_events(user_id);
+CREATE INDEX idx_account_appeals_status ON account_appeals(status);
+-- At most one open appeal per user
+CREATE UNIQUE INDEX idx_account_appeals_pending ON account_appeals(user_id) WHERE status = 'pending';
@@ -0,0 +1,12 @@
+-- Which versions of the terms/privacy policy each user has accepted, and from where
+CREATE TABLE policy_acceptances (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    version TEXT NOT NULL,
+    ip_address TEXT,
+    user_agent TEXT,
+    accepted_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- Indexes
+CREATE UNIQUE INDEX idx_policy_acceptances_user_version ON policy_acceptances(user_id, version);
@@ -0,0 +1,5 @@
+DROP TABLE IF EXISTS sso_identities;
+DROP TABLE IF EXISTS sso_connections;
+DROP TABLE IF EXISTS organization_domains;
+DROP TABLE IF EXISTS organization_members;
+DROP TABLE IF EXISTS organizations;
@@ -0,0 +1,62 @@
+-- Organizations and their members
+CREATE TABLE organizations (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    name TEXT NOT NULL,
+    slug TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE TABLE organization_members (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    organization_id UUID NOT NULL REFERENCES organizations(id) ON DELETE CASCADE,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    role TEXT NOT NULL DEFAULT 'member' CHECK (role IN ('owner', 'admin', 'member')),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- Email domains whose users sign in through the organization's identity provider
+CREATE TABLE organization_domains (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    organization_id UUID NOT NULL REFERENCES organizations(id) ON DELETE CASCADE,
+    domain TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- One OIDC or SAML identity provider per organization
+CREATE TABLE sso_connections (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    organization_id UUID NOT NULL REFERENCES organizations(id) ON DELETE CASCADE,
+    protocol TEXT NOT NULL CHECK (protocol IN ('oidc', 'saml')),
+    enabled BOOLEAN NOT NULL DEFAULT FALSE,
+    oidc_issuer TEXT,
+    oidc_client_id TEXT,
+    oidc_client_secret TEXT,
+    saml_idp_metadata TEXT,
+    groups_attribute TEXT NOT NULL DEFAULT 'groups',
+    role_mappings JSONB NOT NULL DEFAULT '{}',
+    default_role TEXT NOT NULL DEFAULT 'member' CHECK (default_role IN ('admin', 'member')),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- Links an IdP subject to the local user it was provisioned as
+CREATE TABLE sso_identities (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    connection_id UUID NOT NULL REFERENCES sso_connections(id) ON DELETE CASCADE,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    subject TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    last_login_at TIMESTAMPTZ
+);
+
+-- Indexes
+CREATE UNIQUE INDEX idx_organizations_slug ON organizations(slug);
+CREATE UNIQUE INDEX idx_organization_members_org_user ON organization_members(organization_id, user_id);
+CREATE INDEX idx_organization_members_user_id ON organization_members(user_id);
+CREATE UNIQUE INDEX idx_organization_domains_domain ON organization_domains(domain);
+CREATE INDEX idx_organization_domains_organization_id ON organization_domains(organization_id);
+CREATE UNIQUE INDEX idx_sso_connections_organization_id ON sso_connections(organization_id);
+CREATE UNIQUE INDEX idx_sso_identities_connection_subject ON sso_identities(connection_id, subject);
+CREATE INDEX idx_sso_identities_user_id ON sso_identities(user_id);
@@ -0,0 +1,9 @@
+DROP INDEX IF EXISTS idx_organization_domains_verified;
+DROP INDEX IF EXISTS idx_organization_domains_org_domain;
+DELETE FROM organization_domains WHERE verified_at IS NULL;
+CREATE UNIQUE INDEX idx_organization_domains_domain ON organization_domains(domain);
+
+ALTER TABLE organization_domains DROP COLUMN IF EXISTS auto_join;
+ALTER TABLE organization_domains DROP COLUMN IF EXISTS last_checked_at;
+ALTER TABLE organization_domains DROP COLUMN IF EXISTS verified_at;
+ALTER TABLE organization_domains DROP COLUMN IF EXISTS verification_token;
@@ -0,0 +1,13 @@
+-- Organizations prove they own a domain with a DNS TXT record before it routes
+-- logins to their IdP or auto-joins users
+ALTER TABLE organization_domains ADD COLUMN verification_token TEXT;
+UPDATE organization_domains SET verification_token = md5(random()::text || id::text);
+ALTER TABLE organization_domains ALTER COLUMN verification_token SET NOT NULL;
+ALTER TABLE organization_domains ADD COLUMN verified_at TIMESTAMPTZ;
+ALTER TABLE organization_domains ADD COLUMN last_checked_at TIMESTAMPTZ;
+ALTER TABLE organization_domains ADD COLUMN auto_join BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- Any organization may claim a domain, but only one can verify it
+DROP INDEX IF EXISTS idx_organization_domains_domain;
+CREATE UNIQUE INDEX idx_organization_domains_org_domain ON organization_domains(organization_id, domain);
+CREATE UNIQUE INDEX idx_organization_domains_verified ON organization_domains(domain) WHERE verified_at IS NOT NULL;
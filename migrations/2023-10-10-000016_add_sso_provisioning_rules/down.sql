@@ -0,0 +1 @@
+ALTER TABLE sso_connections DROP COLUMN IF EXISTS provisioning_rules;
@@ -0,0 +1,3 @@
+-- Rules applied to federated users: attribute mapping, roles, organization
+-- assignment and attributes that refuse sign-in
+ALTER TABLE sso_connections ADD COLUMN provisioning_rules JSONB NOT NULL DEFAULT '{}';
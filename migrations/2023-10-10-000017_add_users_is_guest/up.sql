@@ -0,0 +1,3 @@
+-- Anonymous accounts created by POST /auth/guest, until upgraded to a registered account
+ALTER TABLE users ADD COLUMN is_guest BOOLEAN NOT NULL DEFAULT FALSE;
+CREATE INDEX idx_users_guests ON users(created_at) WHERE is_guest;
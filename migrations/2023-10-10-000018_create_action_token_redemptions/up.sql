@@ -0,0 +1,12 @@
+-- Signed one-time action links that have been used, so each works only once.
+-- Rows past expires_at can be pruned: the signature check rejects those tokens anyway.
+CREATE TABLE action_token_redemptions (
+    jti UUID PRIMARY KEY,
+    purpose TEXT NOT NULL,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    redeemed_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    expires_at TIMESTAMPTZ NOT NULL
+);
+
+-- Indexes
+CREATE INDEX idx_action_token_redemptions_expires_at ON action_token_redemptions(expires_at);
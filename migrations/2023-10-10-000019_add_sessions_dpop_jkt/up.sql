@@ -0,0 +1,2 @@
+-- Thumbprint of the client key a DPoP-bound session's tokens are tied to
+ALTER TABLE sessions ADD COLUMN dpop_jkt TEXT;
@@ -0,0 +1,11 @@
+-- Per-key overrides of API_KEY_DAILY_QUOTA and API_KEY_MONTHLY_QUOTA
+ALTER TABLE api_keys ADD COLUMN daily_quota BIGINT;
+ALTER TABLE api_keys ADD COLUMN monthly_quota BIGINT;
+
+-- Requests made with each API key, per UTC day. Monthly usage is the sum over the month.
+CREATE TABLE api_key_usage (
+    api_key_id UUID NOT NULL REFERENCES api_keys(id) ON DELETE CASCADE,
+    day DATE NOT NULL,
+    request_count BIGINT NOT NULL DEFAULT 0,
+    PRIMARY KEY (api_key_id, day)
+);
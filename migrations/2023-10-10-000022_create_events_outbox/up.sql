@@ -0,0 +1,16 @@
+-- Auth events written in the same transaction as the change they describe,
+-- then published by the outbox relay
+CREATE TABLE events_outbox (
+    id UUID PRIMARY KEY,
+    event_type TEXT NOT NULL,
+    aggregate_id UUID NOT NULL,
+    payload JSONB NOT NULL,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    last_error TEXT,
+    next_attempt_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    delivered_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- Indexes
+CREATE INDEX idx_events_outbox_pending ON events_outbox(next_attempt_at) WHERE delivered_at IS NULL;
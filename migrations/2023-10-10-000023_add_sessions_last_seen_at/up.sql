@@ -0,0 +1,2 @@
+-- When the session was last used, to end sessions left idle for too long
+ALTER TABLE sessions ADD COLUMN last_seen_at TIMESTAMPTZ NOT NULL DEFAULT NOW();
@@ -0,0 +1,3 @@
+ALTER TABLE users DROP COLUMN IF EXISTS mfa_reenrollment_required;
+
+DROP TABLE IF EXISTS account_risk_signals;
@@ -0,0 +1,13 @@
+-- Suspicious activity counted towards an account's rolling risk score
+CREATE TABLE account_risk_signals (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    signal TEXT NOT NULL CHECK (signal IN ('failed_login', 'breach_hit', 'risky_session')),
+    weight INTEGER NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_account_risk_signals_user_id ON account_risk_signals(user_id, created_at);
+
+-- Set when the score forced MFA off; cleared once the user enrolls again
+ALTER TABLE users ADD COLUMN mfa_reenrollment_required BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,2 @@
+ALTER TABLE sessions DROP COLUMN IF EXISTS network_asn;
+ALTER TABLE sessions DROP COLUMN IF EXISTS network_country;
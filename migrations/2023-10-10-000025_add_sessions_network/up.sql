@@ -0,0 +1,3 @@
+-- The coarse network (country, ASN) a session's refresh token is bound to
+ALTER TABLE sessions ADD COLUMN network_country TEXT;
+ALTER TABLE sessions ADD COLUMN network_asn TEXT;
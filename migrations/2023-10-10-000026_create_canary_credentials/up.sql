@@ -0,0 +1,15 @@
+-- Decoy accounts and API keys planted to catch intruders. Using one is never
+-- a login, only an alert.
+CREATE TABLE canary_credentials (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    api_key_id UUID REFERENCES api_keys(id) ON DELETE SET NULL,
+    label TEXT NOT NULL,
+    trip_count INTEGER NOT NULL DEFAULT 0,
+    last_tripped_at TIMESTAMPTZ,
+    last_tripped_ip TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE UNIQUE INDEX idx_canary_credentials_user_id ON canary_credentials(user_id);
+CREATE UNIQUE INDEX idx_canary_credentials_api_key_id ON canary_credentials(api_key_id);
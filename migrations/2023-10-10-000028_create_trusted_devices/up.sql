@@ -0,0 +1,13 @@
+-- Devices that skip the emailed code on passwordless sign-in until they
+-- expire. Only a hash of the device token is stored.
+CREATE TABLE trusted_devices (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    token_hash TEXT NOT NULL UNIQUE,
+    name TEXT,
+    expires_at TIMESTAMPTZ NOT NULL,
+    last_used_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_trusted_devices_user_id ON trusted_devices(user_id);
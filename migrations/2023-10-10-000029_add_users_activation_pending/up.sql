@@ -0,0 +1,3 @@
+-- Accounts created from an email address alone, waiting for their owner to
+-- set a password from the activation link
+ALTER TABLE users ADD COLUMN activation_pending BOOLEAN NOT NULL DEFAULT FALSE;
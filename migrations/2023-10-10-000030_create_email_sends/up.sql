@@ -0,0 +1,11 @@
+-- Verification, reset and activation emails sent to each address, for the
+-- resend cooldown and daily limit. Rows older than a day are pruned as new
+-- ones are recorded.
+CREATE TABLE email_sends (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    address TEXT NOT NULL,
+    kind TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_email_sends_address_kind ON email_sends(address, kind, created_at);
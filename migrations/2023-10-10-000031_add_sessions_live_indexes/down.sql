@@ -0,0 +1,4 @@
+DROP INDEX IF EXISTS idx_sessions_revoked_updated_at;
+DROP INDEX IF EXISTS idx_sessions_user_id_live;
+DROP INDEX IF EXISTS idx_sessions_refresh_token_live;
+CREATE INDEX idx_sessions_refresh_token ON sessions(refresh_token);
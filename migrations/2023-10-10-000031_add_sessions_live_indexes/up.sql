@@ -0,0 +1,9 @@
+-- Refresh and session listing only ever look at live sessions, so index just
+-- those: ended sessions waiting to be purged don't slow the lookups down.
+-- The UNIQUE constraint on refresh_token already indexes every row.
+DROP INDEX IF EXISTS idx_sessions_refresh_token;
+CREATE INDEX idx_sessions_refresh_token_live ON sessions(refresh_token) WHERE is_revoked = FALSE;
+CREATE INDEX idx_sessions_user_id_live ON sessions(user_id, is_revoked) WHERE is_revoked = FALSE;
+
+-- For the purge of ended sessions
+CREATE INDEX idx_sessions_revoked_updated_at ON sessions(updated_at) WHERE is_revoked = TRUE;
@@ -0,0 +1,3 @@
+-- Bumped by every change a user or admin makes to the account, so a write
+-- based on a stale read can be refused instead of overwriting the newer one
+ALTER TABLE users ADD COLUMN version INTEGER NOT NULL DEFAULT 1;
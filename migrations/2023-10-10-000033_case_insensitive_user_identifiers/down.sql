@@ -0,0 +1,6 @@
+DROP INDEX IF EXISTS idx_user_emails_email_lower;
+DROP INDEX IF EXISTS idx_users_email_lower;
+DROP INDEX IF EXISTS idx_users_username_lower;
+CREATE UNIQUE INDEX idx_user_emails_email ON user_emails(email);
+CREATE INDEX idx_users_email ON users(email);
+CREATE INDEX idx_users_username ON users(username);
@@ -0,0 +1,33 @@
+-- Usernames and email addresses are unique regardless of case. Accounts that
+-- already collide can't be merged automatically, so the migration stops and
+-- lists them instead of picking a winner.
+DO $$
+DECLARE
+    collisions TEXT;
+BEGIN
+    SELECT string_agg(format('%s "%s": %s', kind, identifier, ids), E'\n')
+    INTO collisions
+    FROM (
+        SELECT 'username' AS kind, LOWER(username) AS identifier, string_agg(id::TEXT, ', ' ORDER BY created_at) AS ids
+        FROM users GROUP BY LOWER(username) HAVING COUNT(*) > 1
+        UNION ALL
+        SELECT 'email', LOWER(email), string_agg(id::TEXT, ', ' ORDER BY created_at)
+        FROM users GROUP BY LOWER(email) HAVING COUNT(*) > 1
+        UNION ALL
+        SELECT 'backup email', LOWER(email), string_agg(user_id::TEXT, ', ' ORDER BY created_at)
+        FROM user_emails GROUP BY LOWER(email) HAVING COUNT(*) > 1
+    ) AS colliding;
+
+    IF collisions IS NOT NULL THEN
+        RAISE EXCEPTION E'Usernames or emails that differ only by case:\n%', collisions
+            USING HINT = 'Rename or remove all but one account in each group (user ids are listed oldest first), then rerun the migration';
+    END IF;
+END $$;
+
+-- Lookups compare LOWER() on both sides, so these replace the plain indexes
+DROP INDEX IF EXISTS idx_users_username;
+DROP INDEX IF EXISTS idx_users_email;
+DROP INDEX IF EXISTS idx_user_emails_email;
+CREATE UNIQUE INDEX idx_users_username_lower ON users(LOWER(username));
+CREATE UNIQUE INDEX idx_users_email_lower ON users(LOWER(email));
+CREATE UNIQUE INDEX idx_user_emails_email_lower ON user_emails(LOWER(email));
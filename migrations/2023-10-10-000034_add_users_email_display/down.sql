@@ -0,0 +1,2 @@
+UPDATE users SET email = email_display WHERE email_display IS NOT NULL;
+ALTER TABLE users DROP COLUMN IF EXISTS email_display;
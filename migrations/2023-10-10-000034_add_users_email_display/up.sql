@@ -0,0 +1,14 @@
+-- `email` holds the canonical form of the address, with the domain lowercased
+-- and punycoded; `email_display` keeps the form the user entered when it's
+-- different, e.g. with an internationalized domain
+ALTER TABLE users ADD COLUMN email_display TEXT;
+
+-- Existing addresses are ASCII, so canonicalizing them only lowercases the domain
+UPDATE users
+SET email_display = email,
+    email = split_part(email, '@', 1) || '@' || LOWER(split_part(email, '@', 2))
+WHERE split_part(email, '@', 2) <> LOWER(split_part(email, '@', 2));
+
+UPDATE user_emails
+SET email = split_part(email, '@', 1) || '@' || LOWER(split_part(email, '@', 2))
+WHERE split_part(email, '@', 2) <> LOWER(split_part(email, '@', 2));
@@ -0,0 +1,10 @@
+-- Admin user search matches any part of a username, email or display name
+-- with ILIKE '%term%', which a btree index can't serve but a trigram one can
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+
+CREATE INDEX idx_users_username_trgm ON users USING GIN (username gin_trgm_ops);
+CREATE INDEX idx_users_email_trgm ON users USING GIN (email gin_trgm_ops);
+CREATE INDEX idx_users_display_name_trgm ON users USING GIN (display_name gin_trgm_ops);
+
+-- Search results are listed by creation time, with id breaking ties
+CREATE INDEX idx_users_created_at ON users(created_at, id);
@@ -0,0 +1,2 @@
+DROP INDEX IF EXISTS idx_account_risk_signals_created_at;
+DROP INDEX IF EXISTS idx_events_outbox_delivered;
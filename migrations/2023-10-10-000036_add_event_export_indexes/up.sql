@@ -0,0 +1,4 @@
+-- The event export reads the oldest delivered events and the oldest risk
+-- signals across all accounts
+CREATE INDEX idx_events_outbox_delivered ON events_outbox(created_at, id) WHERE delivered_at IS NOT NULL;
+CREATE INDEX idx_account_risk_signals_created_at ON account_risk_signals(created_at, id);
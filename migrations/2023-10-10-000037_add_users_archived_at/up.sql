@@ -0,0 +1,16 @@
+-- Accounts unused for years are archived: signed out everywhere, left out of
+-- admin search, and reactivated from an emailed link on their next login
+ALTER TABLE users ADD COLUMN archived_at TIMESTAMPTZ;
+
+-- The archiver looks for the longest-unused live accounts
+CREATE INDEX idx_users_archive_candidates ON users(COALESCE(last_login_at, created_at))
+    WHERE archived_at IS NULL AND NOT is_guest;
+
+-- Search only covers live accounts unless asked for archived ones, so the
+-- trigram indexes leave archived rows out
+DROP INDEX IF EXISTS idx_users_username_trgm;
+DROP INDEX IF EXISTS idx_users_email_trgm;
+DROP INDEX IF EXISTS idx_users_display_name_trgm;
+CREATE INDEX idx_users_username_trgm ON users USING GIN (username gin_trgm_ops) WHERE archived_at IS NULL;
+CREATE INDEX idx_users_email_trgm ON users USING GIN (email gin_trgm_ops) WHERE archived_at IS NULL;
+CREATE INDEX idx_users_display_name_trgm ON users USING GIN (display_name gin_trgm_ops) WHERE archived_at IS NULL;
@@ -0,0 +1,14 @@
+-- Switches that refuse new logins and registrations, everywhere (no
+-- organization) or for one organization's members, while signed-in sessions
+-- keep working
+CREATE TABLE login_freezes (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    organization_id UUID REFERENCES organizations(id) ON DELETE CASCADE,
+    message TEXT NOT NULL,
+    frozen_by UUID REFERENCES users(id) ON DELETE SET NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- At most one global freeze and one per organization
+CREATE UNIQUE INDEX idx_login_freezes_global ON login_freezes((organization_id IS NULL)) WHERE organization_id IS NULL;
+CREATE UNIQUE INDEX idx_login_freezes_organization_id ON login_freezes(organization_id) WHERE organization_id IS NOT NULL;
@@ -0,0 +1,13 @@
+-- Operator-controlled switches, read through a short cache. A disabled flag
+-- is off for everyone; an enabled one is on for members of the listed
+-- organizations and for `rollout_percent` of users.
+CREATE TABLE feature_flags (
+    key TEXT PRIMARY KEY,
+    description TEXT,
+    enabled BOOLEAN NOT NULL DEFAULT FALSE,
+    rollout_percent INTEGER NOT NULL DEFAULT 100 CHECK (rollout_percent BETWEEN 0 AND 100),
+    organization_ids UUID[] NOT NULL DEFAULT '{}',
+    updated_by UUID REFERENCES users(id) ON DELETE SET NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
@@ -0,0 +1,8 @@
+-- Which optional email each user gets. Users without a row get the
+-- defaults: security alerts on, product email off.
+CREATE TABLE notification_preferences (
+    user_id UUID PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+    security_alerts BOOLEAN NOT NULL DEFAULT TRUE,
+    product_emails BOOLEAN NOT NULL DEFAULT FALSE,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
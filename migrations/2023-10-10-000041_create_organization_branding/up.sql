@@ -0,0 +1,9 @@
+-- How an organization's hosted sign-in pages look. Organizations without a
+-- row get the deployment's defaults.
+CREATE TABLE organization_branding (
+    organization_id UUID PRIMARY KEY REFERENCES organizations(id) ON DELETE CASCADE,
+    display_name TEXT,
+    logo_url TEXT,
+    primary_color TEXT CHECK (primary_color ~ '^#[0-9a-f]{6}$'),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
@@ -0,0 +1,3 @@
+ALTER TABLE webauthn_credentials DROP COLUMN aaguid;
+
+DROP TABLE IF EXISTS authenticator_metadata;
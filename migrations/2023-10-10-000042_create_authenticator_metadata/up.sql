@@ -0,0 +1,14 @@
+-- Authenticator models listed by the FIDO Alliance metadata service, keyed by
+-- AAGUID. Replaced as a whole whenever a newer metadata BLOB is synced.
+CREATE TABLE authenticator_metadata (
+    aaguid UUID PRIMARY KEY,
+    description TEXT NOT NULL,
+    certification_level TEXT,
+    compromised BOOLEAN NOT NULL DEFAULT FALSE,
+    blob_number INTEGER NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- The model a passkey was made on, from its attestation. NULL for passkeys
+-- registered before this was recorded and for authenticators that hide it.
+ALTER TABLE webauthn_credentials ADD COLUMN aaguid UUID;
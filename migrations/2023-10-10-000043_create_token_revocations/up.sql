@@ -0,0 +1,11 @@
+-- Sessions and access tokens cut off before they expire. Every instance polls
+-- this for new rows, so a revocation reaches access tokens within seconds.
+-- `id` is a session id or an access token's `jti`; rows are useless once
+-- every token they could match has expired.
+CREATE TABLE token_revocations (
+    id UUID PRIMARY KEY,
+    expires_at TIMESTAMPTZ NOT NULL,
+    revoked_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_token_revocations_revoked_at ON token_revocations(revoked_at);
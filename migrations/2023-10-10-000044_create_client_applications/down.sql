@@ -0,0 +1,2 @@
+ALTER TABLE sessions DROP COLUMN IF EXISTS client_id;
+DROP TABLE IF EXISTS client_applications;
@@ -0,0 +1,18 @@
+-- Apps allowed to send users to the hosted sign-in pages. Tokens are only
+-- handed to one of a client's registered redirect URIs, and a session started
+-- for a client keeps to that client's grants and token lifetimes.
+CREATE TABLE client_applications (
+    client_id TEXT PRIMARY KEY,
+    name TEXT NOT NULL,
+    redirect_uris TEXT[] NOT NULL DEFAULT '{}',
+    allowed_grants TEXT[] NOT NULL DEFAULT '{}',
+    access_token_ttl INTEGER CHECK (access_token_ttl > 0),
+    refresh_token_ttl INTEGER CHECK (refresh_token_ttl > 0),
+    logo_url TEXT,
+    updated_by UUID REFERENCES users(id) ON DELETE SET NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- The client a session was started for, if any
+ALTER TABLE sessions ADD COLUMN client_id TEXT REFERENCES client_applications(client_id) ON DELETE SET NULL;
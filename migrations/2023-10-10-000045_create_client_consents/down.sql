@@ -0,0 +1,3 @@
+ALTER TABLE sessions DROP COLUMN IF EXISTS scopes;
+ALTER TABLE client_applications DROP COLUMN IF EXISTS first_party;
+DROP TABLE IF EXISTS client_consents;
@@ -0,0 +1,17 @@
+-- Scopes each user has let a third-party client have. A sign-in for the
+-- client asking for nothing more skips the consent screen; revoking the
+-- consent signs the client's sessions out.
+CREATE TABLE client_consents (
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    client_id TEXT NOT NULL REFERENCES client_applications(client_id) ON DELETE CASCADE,
+    scopes TEXT[] NOT NULL DEFAULT '{}',
+    granted_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (user_id, client_id)
+);
+
+-- First-party clients are trusted with the user's own scopes, unasked
+ALTER TABLE client_applications ADD COLUMN first_party BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- Scopes a client's session was granted; NULL for the user's own
+ALTER TABLE sessions ADD COLUMN scopes TEXT[];
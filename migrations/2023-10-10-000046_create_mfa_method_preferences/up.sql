@@ -0,0 +1,8 @@
+-- The order a user wants their second factors offered in after a password
+-- login, e.g. '{passkey,totp}'. Methods they haven't set up are skipped;
+-- without a row, TOTP comes first.
+CREATE TABLE mfa_method_preferences (
+    user_id UUID PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+    methods TEXT[] NOT NULL DEFAULT '{}',
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
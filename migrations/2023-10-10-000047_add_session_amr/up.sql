@@ -0,0 +1,3 @@
+-- How each session was established, as `amr` values (e.g. '{pwd,otp,mfa}'),
+-- so tokens refreshed from it keep the assurance level of the login
+ALTER TABLE sessions ADD COLUMN amr TEXT[] NOT NULL DEFAULT '{}';
@@ -0,0 +1,3 @@
+-- How long the login pipeline let each session's tokens last: 'shortened'
+-- for risky logins, 'extended' on trusted devices. Refreshes keep to it.
+ALTER TABLE sessions ADD COLUMN lifetime TEXT NOT NULL DEFAULT 'standard';
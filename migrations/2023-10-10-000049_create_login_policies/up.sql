@@ -0,0 +1,17 @@
+-- When and where an account may sign in, set by the user for themselves or
+-- by an organization's admins for every member. A login has to satisfy each
+-- policy that applies to it.
+CREATE TABLE login_policies (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    user_id UUID UNIQUE REFERENCES users(id) ON DELETE CASCADE,
+    organization_id UUID UNIQUE REFERENCES organizations(id) ON DELETE CASCADE,
+    allowed_countries TEXT[] NOT NULL DEFAULT '{}',
+    window_start TIME,
+    window_end TIME,
+    utc_offset_minutes INTEGER NOT NULL DEFAULT 0 CHECK (utc_offset_minutes BETWEEN -720 AND 840),
+    on_violation TEXT NOT NULL DEFAULT 'block' CHECK (on_violation IN ('block', 'step_up')),
+    updated_by UUID REFERENCES users(id) ON DELETE SET NULL,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    CHECK ((user_id IS NULL) <> (organization_id IS NULL)),
+    CHECK ((window_start IS NULL) = (window_end IS NULL))
+);
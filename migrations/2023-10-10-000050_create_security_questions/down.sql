@@ -0,0 +1,2 @@
+DROP TABLE IF EXISTS security_question_failures;
+DROP TABLE IF EXISTS security_questions;
@@ -0,0 +1,20 @@
+-- Knowledge-based recovery: questions the user picked, with their answers
+-- normalized and hashed like passwords. Answering every one finishes a login
+-- without the second factor, as a last resort.
+CREATE TABLE security_questions (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    position INTEGER NOT NULL,
+    question TEXT NOT NULL,
+    answer_hash TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    UNIQUE (user_id, position)
+);
+
+-- Wrong answers in the current window, kept apart from password failures so
+-- guessing answers doesn't lock the password login and vice versa
+CREATE TABLE security_question_failures (
+    user_id UUID PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+    failure_count INTEGER NOT NULL DEFAULT 0,
+    last_failed_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
@@ -0,0 +1,5 @@
+UPDATE users SET status = 'suspended' WHERE status = 'locked';
+
+ALTER TABLE users DROP CONSTRAINT IF EXISTS users_status_check;
+ALTER TABLE users ADD CONSTRAINT users_status_check
+    CHECK (status IN ('active', 'suspended', 'banned', 'pending_deletion'));
@@ -0,0 +1,5 @@
+-- Owners can lock their own account when they suspect it was taken over;
+-- a password reset from the primary mailbox unlocks it
+ALTER TABLE users DROP CONSTRAINT IF EXISTS users_status_check;
+ALTER TABLE users ADD CONSTRAINT users_status_check
+    CHECK (status IN ('active', 'suspended', 'banned', 'pending_deletion', 'locked'));
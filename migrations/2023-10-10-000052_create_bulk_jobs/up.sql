@@ -0,0 +1,19 @@
+-- Admin operations over many accounts, run in the background by
+-- `BulkJobRunner`. A dry run records what each account would go through
+-- without changing it; `results` holds one entry per list entry or account.
+CREATE TABLE bulk_jobs (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    action TEXT NOT NULL CHECK (action IN ('revoke_sessions', 'force_password_reset', 'deactivate_domain')),
+    params JSONB NOT NULL,
+    dry_run BOOLEAN NOT NULL DEFAULT FALSE,
+    status TEXT NOT NULL DEFAULT 'queued' CHECK (status IN ('queued', 'running', 'completed', 'failed')),
+    created_by UUID REFERENCES users(id) ON DELETE SET NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    started_at TIMESTAMPTZ,
+    finished_at TIMESTAMPTZ,
+    results JSONB NOT NULL DEFAULT '[]',
+    error TEXT
+);
+
+CREATE INDEX idx_bulk_jobs_created_at ON bulk_jobs (created_at DESC);
+CREATE INDEX idx_bulk_jobs_pending ON bulk_jobs (created_at) WHERE status IN ('queued', 'running');
@@ -0,0 +1,13 @@
+-- Where every user's data lives, for deployments that keep users in their
+-- own region's database. Only the home region's database has rows here, and
+-- it's the only thing looked up across regions, so it holds no more than it
+-- takes to find an account: identifiers are kept as SHA-256 digests of their
+-- lower-cased form. There's no foreign key, since most users aren't in this
+-- database.
+CREATE TABLE user_directory (
+    user_id UUID PRIMARY KEY,
+    region TEXT NOT NULL,
+    email_digest TEXT NOT NULL UNIQUE,
+    username_digest TEXT NOT NULL UNIQUE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
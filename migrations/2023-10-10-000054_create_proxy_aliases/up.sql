@@ -0,0 +1,13 @@
+-- Forwarding addresses a user hands out instead of their real email. Mail to
+-- a proxy address reaches the owner's account email while it's active.
+CREATE TABLE proxy_aliases (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    proxy_address TEXT NOT NULL UNIQUE,
+    label TEXT NOT NULL DEFAULT '',
+    status TEXT NOT NULL DEFAULT 'active' CHECK (status IN ('active', 'disabled', 'deleted')),
+    forwarding_enabled BOOLEAN NOT NULL DEFAULT TRUE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_proxy_aliases_user_id ON proxy_aliases(user_id);
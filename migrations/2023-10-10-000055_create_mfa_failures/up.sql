@@ -0,0 +1,7 @@
+-- Wrong TOTP and recovery codes at the second step of a login in the current
+-- window, shared by every instance so spreading guesses across them doesn't help
+CREATE TABLE mfa_failures (
+    user_id UUID PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+    failure_count INTEGER NOT NULL DEFAULT 0,
+    last_failed_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
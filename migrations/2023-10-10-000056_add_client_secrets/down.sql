@@ -0,0 +1 @@
+ALTER TABLE client_applications DROP COLUMN IF EXISTS client_secret_hash;
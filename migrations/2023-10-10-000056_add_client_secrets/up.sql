@@ -0,0 +1,4 @@
+-- Confidential clients, such as server-side apps, authenticate with a secret
+-- when they call the OAuth endpoints. Only its SHA-256 digest is kept; public
+-- clients have none.
+ALTER TABLE client_applications ADD COLUMN client_secret_hash TEXT;
@@ -1,11 +1,28 @@
+use chrono::{DateTime, Duration, Utc};
+use futures::future::BoxFuture;
+use rand::seq::SliceRandom;
+use rand::Rng;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::path::Path;
 use std::sync::Mutex;
 use uuid::Uuid;
 
+// How long an issued CAPTCHA can be answered
+const CAPTCHA_TTL_SECONDS: i64 = 300;
+// Digits read out in an audio CAPTCHA
+const AUDIO_CAPTCHA_DIGITS: usize = 6;
+// Silence between spoken digits
+const AUDIO_CAPTCHA_GAP_MS: u32 = 400;
+
 // Accessibility context
 pub struct AccessibilityContext {
     pub state: Mutex<AccessibilityState>,
+    // Recordings of the digits 0-9 used to build audio CAPTCHAs
+    audio_clips: HashMap<char, WavClip>,
+    // Speech recognition behind voice commands
+    speech: Option<Box<dyn SpeechToText>>,
+    min_voice_confidence: f32,
 }
 
 // Accessibility state
@@ -13,6 +30,8 @@ pub struct AccessibilityContext {
 pub struct AccessibilityState {
     // User accessibility preferences
     pub user_preferences: HashMap<Uuid, AccessibilityPreferences>,
+    // Issued CAPTCHAs awaiting an answer
+    pub captchas: HashMap<Uuid, PendingCaptcha>,
 }
 
 // Accessibility preferences
@@ -38,37 +57,178 @@ pub enum CaptchaAlternative {
     ImageSelection,
 }
 
+// A CAPTCHA handed to the client; the answer stays on the server
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CaptchaChallenge {
+    pub id: Uuid,
+    pub kind: CaptchaAlternative,
+    pub prompt: String,
+    pub audio_url: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub alternatives: Vec<CaptchaAlternative>, // Kinds the client can ask for instead
+    pub expires_at: DateTime<Utc>,
+}
+
+// Server-side half of an issued CAPTCHA
+#[derive(Debug, Clone)]
+pub struct PendingCaptcha {
+    pub kind: CaptchaAlternative,
+    pub answer: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+// PCM audio of a single spoken digit
+#[derive(Debug, Clone, PartialEq)]
+struct WavClip {
+    format: Vec<u8>, // Body of the `fmt ` chunk
+    data: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ComplianceStatus {
+    Compliant,
+    NonCompliant,
+}
+
+// One measured WCAG success criterion
+#[derive(Debug, Clone, Serialize)]
+pub struct ComplianceCheck {
+    pub criterion: &'static str,
+    pub title: &'static str,
+    pub passed: bool,
+    pub detail: String,
+}
+
+// How many users have turned each feature on
+#[derive(Debug, Clone, Serialize)]
+pub struct AccessibilityUsage {
+    pub total_users: usize,
+    pub high_contrast_users: usize,
+    pub large_text_users: usize,
+    pub screen_reader_users: usize,
+    pub reduced_motion_users: usize,
+    pub keyboard_navigation_users: usize,
+    pub voice_command_users: usize,
+}
+
+// Accessibility compliance report
+#[derive(Debug, Clone, Serialize)]
+pub struct AccessibilityReport {
+    pub standard: &'static str,
+    pub status: ComplianceStatus,
+    pub checks: Vec<ComplianceCheck>,
+    pub usage: AccessibilityUsage,
+    pub generated_at: DateTime<Utc>,
+}
+
+impl AccessibilityReport {
+    // One row per check, for spreadsheets and audit trails
+    pub fn to_csv(&self) -> String {
+        let field = |value: &str| {
+            if value.contains([',', '"', '\n', '\r']) {
+                format!("\"{}\"", value.replace('"', "\"\""))
+            } else {
+                value.to_string()
+            }
+        };
+        
+        let mut csv = String::from("criterion,title,passed,detail\n");
+        for check in &self.checks {
+            csv.push_str(&format!(
+                "{},{},{},{}\n",
+                field(check.criterion),
+                field(check.title),
+                check.passed,
+                field(&check.detail)
+            ));
+        }
+        csv
+    }
+}
+
 // Voice command
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct VoiceCommand {
     pub command: String,
     pub confidence: f32,
     pub action: String,
+    pub transcript: String,
+}
+
+// What a speech-to-text provider heard
+#[derive(Debug, Clone, PartialEq)]
+pub struct Transcript {
+    pub text: String,
+    pub confidence: f32, // 0.0 to 1.0
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SpeechError {
+    NotConfigured,
+    Provider(String),
+}
+
+// Speech recognition backend, e.g. a local Whisper server or a cloud STT API
+pub trait SpeechToText: Send + Sync {
+    fn transcribe<'a>(
+        &'a self,
+        audio_data: &'a [u8],
+        content_type: &'a str,
+    ) -> BoxFuture<'a, Result<Transcript, SpeechError>>;
 }
 
 impl AccessibilityContext {
     pub fn new() -> Self {
         AccessibilityContext {
             state: Mutex::new(AccessibilityState::default()),
+            audio_clips: HashMap::new(),
+            speech: None,
+            min_voice_confidence: 0.0,
         }
     }
     
+    // Recognize voice commands with `speech`, ignoring transcripts below `min_confidence`
+    pub fn with_speech_provider(mut self, speech: Box<dyn SpeechToText>, min_confidence: f32) -> Self {
+        self.speech = Some(speech);
+        self.min_voice_confidence = min_confidence;
+        self
+    }
+    
+    // Load `0.wav` ... `9.wav` from a directory so audio CAPTCHAs can be served.
+    // All clips must share one PCM format.
+    pub fn with_audio_clips(mut self, dir: &Path) -> std::io::Result<Self> {
+        let mut clips = HashMap::new();
+        
+        for digit in '0'..='9' {
+            let bytes = std::fs::read(dir.join(format!("{}.wav", digit)))?;
+            let clip = parse_wav(&bytes).ok_or_else(|| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("{}.wav is not a PCM WAV file", digit),
+                )
+            })?;
+            clips.insert(digit, clip);
+        }
+        
+        if clips.values().any(|c: &WavClip| c.format != clips[&'0'].format) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "Audio CAPTCHA clips must share the same format",
+            ));
+        }
+        
+        self.audio_clips = clips;
+        Ok(self)
+    }
+    
     // Get a user's accessibility preferences
     pub fn get_preferences(&self, user_id: &Uuid) -> AccessibilityPreferences {
         let state = self.state.lock().unwrap();
         state.user_preferences
             .get(user_id)
             .cloned()
-            .unwrap_or_else(|| AccessibilityPreferences {
-                user_id: *user_id,
-                high_contrast: false,
-                large_text: false,
-                screen_reader_optimized: false,
-                reduced_motion: false,
-                voice_commands_enabled: false,
-                keyboard_navigation: true,
-                additional_settings: HashMap::new(),
-            })
+            .unwrap_or_else(|| default_preferences(*user_id))
     }
     
     // Set a user's accessibility preferences
@@ -114,59 +274,17 @@ impl AccessibilityContext {
     
     // Generate CSS variables based on accessibility preferences
     pub fn generate_css_variables(&self, user_id: &Uuid) -> String {
-        let preferences = self.get_preferences(user_id);
-        
-        let mut css = String::from(":root {\n");
-        
-        // High contrast theme
-        if preferences.high_contrast {
-            css.push_str("  --background-color: #000000;\n");
-            css.push_str("  --text-color: #ffffff;\n");
-            css.push_str("  --primary-color: #ffff00;\n");
-            css.push_str("  --secondary-color: #00ffff;\n");
-            css.push_str("  --border-color: #ffffff;\n");
-            css.push_str("  --focus-outline: 3px solid #ffff00;\n");
-        } else {
-            css.push_str("  --background-color: #ffffff;\n");
-            css.push_str("  --text-color: #333333;\n");
-            css.push_str("  --primary-color: #0066cc;\n");
-            css.push_str("  --secondary-color: #6c757d;\n");
-            css.push_str("  --border-color: #dddddd;\n");
-            css.push_str("  --focus-outline: 2px solid #0066cc;\n");
-        }
-        
-        // Large text
-        if preferences.large_text {
-            css.push_str("  --base-font-size: 18px;\n");
-            css.push_str("  --heading-scale: 1.5;\n");
-            css.push_str("  --button-font-size: 1.2rem;\n");
-        } else {
-            css.push_str("  --base-font-size: 16px;\n");
-            css.push_str("  --heading-scale: 1.2;\n");
-            css.push_str("  --button-font-size: 1rem;\n");
-        }
-        
-        // Screen reader optimization
-        if preferences.screen_reader_optimized {
-            css.push_str("  --focus-indicator: visible;\n");
-            css.push_str("  --skip-link-display: block;\n");
-        } else {
-            css.push_str("  --focus-indicator: auto;\n");
-            css.push_str("  --skip-link-display: none;\n");
-        }
-        
-        // Reduced motion
-        if preferences.reduced_motion {
-            css.push_str("  --transition-duration: 0s;\n");
-            css.push_str("  --animation-duration: 0s;\n");
-        } else {
-            css.push_str("  --transition-duration: 0.3s;\n");
-            css.push_str("  --animation-duration: 0.5s;\n");
-        }
-        
-        css.push_str("}\n");
-        
-        css
+        css_variables(&self.get_preferences(user_id))
+    }
+    
+    // CSS variables for visitors who haven't signed in, so have no preferences yet
+    pub fn generate_default_css_variables(&self) -> String {
+        css_variables(&default_preferences(Uuid::nil()))
+    }
+    
+    // Get keyboard shortcuts based on user preferences
+    pub fn get_keyboard_shortcuts(&self, user_id: &Uuid) -> HashMap<String, String> {
+        keyboard_shortcuts(&self.get_preferences(user_id))
     }
     
     // Get an appropriate CAPTCHA alternative based on user preferences
@@ -182,92 +300,676 @@ impl AccessibilityContext {
         }
     }
     
-    // Parse a voice command
-    pub fn parse_voice_command(&self, audio_data: &[u8]) -> Option<VoiceCommand> {
-        // In a real implementation, this would use speech recognition
-        // For this demo, we just simulate recognition
+    // Issue a CAPTCHA of the given kind. Standard and image CAPTCHAs are left to
+    // the frontend's provider, so they fall back to a math question here, as do
+    // audio CAPTCHAs when no digit recordings are loaded.
+    pub fn issue_captcha(&self, kind: CaptchaAlternative) -> CaptchaChallenge {
+        let mut rng = rand::thread_rng();
+        let id = Uuid::new_v4();
         
-        // Convert audio data to a hash to simulate different commands
-        let mut hash: u64 = 0;
-        for byte in audio_data.iter().take(8) {
-            hash = (hash << 8) | (*byte as u64);
-        }
+        let kind = match kind {
+            CaptchaAlternative::Audio if self.audio_clips.is_empty() => CaptchaAlternative::SimpleMath,
+            CaptchaAlternative::Audio | CaptchaAlternative::LogicPuzzle => kind,
+            _ => CaptchaAlternative::SimpleMath,
+        };
         
-        // Simulate different commands based on the hash
-        let command = match hash % 5 {
-            0 => "login",
-            1 => "register",
-            2 => "reset password",
-            3 => "help",
-            _ => "cancel",
+        let (prompt, answer, audio_url) = match kind {
+            CaptchaAlternative::Audio => {
+                let digits: String = (0..AUDIO_CAPTCHA_DIGITS)
+                    .map(|_| char::from(b'0' + rng.gen_range(0..10)))
+                    .collect();
+                (
+                    "Enter the digits you hear".to_string(),
+                    digits,
+                    Some(format!("/auth/captcha/{}/audio", id)),
+                )
+            }
+            CaptchaAlternative::LogicPuzzle => {
+                let (prompt, answer) = logic_puzzle(&mut rng);
+                (prompt, answer, None)
+            }
+            _ => {
+                let (a, b) = (rng.gen_range(1..10), rng.gen_range(1..10));
+                (format!("What is {} plus {}?", a, b), (a + b).to_string(), None)
+            }
         };
         
-        // Simulate confidence level
-        let confidence = (hash % 30 + 70) as f32 / 100.0;
+        let expires_at = Utc::now() + Duration::seconds(CAPTCHA_TTL_SECONDS);
+        
+        let mut state = self.state.lock().unwrap();
+        state.captchas.retain(|_, c| c.expires_at > Utc::now());
+        state.captchas.insert(id, PendingCaptcha {
+            kind: kind.clone(),
+            answer,
+            expires_at,
+        });
         
-        // Map command to action
-        let action = match command {
-            "login" => "/api/auth/login",
-            "register" => "/api/auth/register",
-            "reset password" => "/api/auth/password-reset",
-            "help" => "/help",
-            "cancel" => "/",
-            _ => "/",
+        // Anyone who can't answer a written challenge can switch to a spoken one
+        let alternatives = if kind != CaptchaAlternative::Audio && !self.audio_clips.is_empty() {
+            vec![CaptchaAlternative::Audio]
+        } else {
+            Vec::new()
         };
+
+        CaptchaChallenge {
+            id,
+            kind,
+            prompt,
+            audio_url,
+            alternatives,
+            expires_at,
+        }
+    }
+    
+    // Issue whichever CAPTCHA suits the user's accessibility preferences
+    pub fn issue_captcha_for_user(&self, user_id: &Uuid) -> CaptchaChallenge {
+        self.issue_captcha(self.get_captcha_alternative(user_id))
+    }
+    
+    // Render the spoken digits of an audio CAPTCHA as a WAV file
+    pub fn captcha_audio(&self, captcha_id: &Uuid) -> Option<Vec<u8>> {
+        let state = self.state.lock().unwrap();
+        let captcha = state.captchas.get(captcha_id)?;
+        
+        if captcha.kind != CaptchaAlternative::Audio || captcha.expires_at <= Utc::now() {
+            return None;
+        }
         
-        Some(VoiceCommand {
+        let clips: Option<Vec<&WavClip>> = captcha.answer
+            .chars()
+            .map(|digit| self.audio_clips.get(&digit))
+            .collect();
+        
+        Some(join_wav_clips(&clips?, AUDIO_CAPTCHA_GAP_MS))
+    }
+    
+    // Check an answer; each CAPTCHA can be tried once
+    pub fn verify_captcha(&self, captcha_id: &Uuid, answer: &str) -> bool {
+        let mut state = self.state.lock().unwrap();
+        
+        match state.captchas.remove(captcha_id) {
+            Some(captcha) => {
+                let answer: String = answer.chars().filter(|c| !c.is_whitespace()).collect();
+                captcha.expires_at > Utc::now() && answer.eq_ignore_ascii_case(&captcha.answer)
+            }
+            None => false,
+        }
+    }
+    
+    // Transcribe a spoken command and resolve it to an auth intent. Returns
+    // `Ok(None)` when nothing was recognized with enough confidence.
+    pub async fn parse_voice_command(
+        &self,
+        audio_data: &[u8],
+        content_type: &str,
+    ) -> Result<Option<VoiceCommand>, SpeechError> {
+        let speech = self.speech.as_ref().ok_or(SpeechError::NotConfigured)?;
+        let transcript = speech.transcribe(audio_data, content_type).await?;
+        
+        if transcript.confidence < self.min_voice_confidence {
+            return Ok(None);
+        }
+        
+        Ok(resolve_voice_intent(&transcript.text).map(|(command, action)| VoiceCommand {
             command: command.to_string(),
-            confidence,
+            confidence: transcript.confidence,
             action: action.to_string(),
-        })
+            transcript: transcript.text,
+        }))
     }
     
-    // Get keyboard shortcuts based on user preferences
-    pub fn get_keyboard_shortcuts(&self, user_id: &Uuid) -> HashMap<String, String> {
-        let preferences = self.get_preferences(user_id);
+    // Generate accessibility report for compliance. The status only reflects the
+    // criteria this service can measure itself, listed in `checks`, and those are
+    // measured on what users are actually served: the stylesheet and shortcuts
+    // for every preference set in use, and the defaults new users get.
+    pub fn generate_accessibility_report(&self) -> AccessibilityReport {
+        let (usage, in_use) = {
+            let state = self.state.lock().unwrap();
+            let count = |f: fn(&AccessibilityPreferences) -> bool| {
+                state.user_preferences.values().filter(|p| f(p)).count()
+            };
+            
+            let usage = AccessibilityUsage {
+                total_users: state.user_preferences.len(),
+                high_contrast_users: count(|p| p.high_contrast),
+                large_text_users: count(|p| p.large_text),
+                screen_reader_users: count(|p| p.screen_reader_optimized),
+                reduced_motion_users: count(|p| p.reduced_motion),
+                keyboard_navigation_users: count(|p| p.keyboard_navigation),
+                voice_command_users: count(|p| p.voice_commands_enabled),
+            };
+            
+            let mut in_use = vec![default_preferences(Uuid::nil())];
+            in_use.extend(state.user_preferences.values().cloned());
+            (usage, in_use)
+        };
+        
+        let checks = vec![
+            self.check_captcha_alternatives(),
+            check_contrast(&in_use),
+            check_keyboard_navigation(&in_use),
+            check_reduced_motion(&in_use),
+        ];
         
-        let mut shortcuts = HashMap::new();
+        let status = if checks.iter().all(|c| c.passed) {
+            ComplianceStatus::Compliant
+        } else {
+            ComplianceStatus::NonCompliant
+        };
         
-        if preferences.keyboard_navigation {
-            shortcuts.insert("login".to_string(), "Alt+L".to_string());
-            shortcuts.insert("register".to_string(), "Alt+R".to_string());
-            shortcuts.insert("password_reset".to_string(), "Alt+P".to_string());
-            shortcuts.insert("help".to_string(), "Alt+H".to_string());
-            shortcuts.insert("exit".to_string(), "Esc".to_string());
+        AccessibilityReport {
+            standard: "WCAG 2.1 AA",
+            status,
+            checks,
+            usage,
+            generated_at: Utc::now(),
         }
+    }
+    
+    // 1.1.1: a CAPTCHA needs alternatives in more than one sensory modality
+    fn check_captcha_alternatives(&self) -> ComplianceCheck {
+        let audio = !self.audio_clips.is_empty();
         
-        shortcuts
+        ComplianceCheck {
+            criterion: "1.1.1",
+            title: "Non-text Content: CAPTCHA alternatives",
+            passed: audio,
+            detail: if audio {
+                "Audio, math and logic puzzle CAPTCHAs are available".to_string()
+            } else {
+                "Only text CAPTCHAs are available; no audio recordings are loaded".to_string()
+            },
+        }
     }
+}
+
+// CSS custom properties for a set of preferences
+fn css_variables(preferences: &AccessibilityPreferences) -> String {
+    let mut css = String::from(":root {\n");
     
-    // Generate accessibility report for compliance
-    pub fn generate_accessibility_report(&self) -> String {
-        let state = self.state.lock().unwrap();
+    // High contrast theme
+    if preferences.high_contrast {
+        css.push_str("  --background-color: #000000;\n");
+        css.push_str("  --text-color: #ffffff;\n");
+        css.push_str("  --primary-color: #ffff00;\n");
+        css.push_str("  --secondary-color: #00ffff;\n");
+        css.push_str("  --border-color: #ffffff;\n");
+        css.push_str("  --focus-outline: 3px solid #ffff00;\n");
+    } else {
+        css.push_str("  --background-color: #ffffff;\n");
+        css.push_str("  --text-color: #333333;\n");
+        css.push_str("  --primary-color: #0066cc;\n");
+        css.push_str("  --secondary-color: #6c757d;\n");
+        css.push_str("  --border-color: #dddddd;\n");
+        css.push_str("  --focus-outline: 2px solid #0066cc;\n");
+    }
+    
+    // Large text
+    if preferences.large_text {
+        css.push_str("  --base-font-size: 18px;\n");
+        css.push_str("  --heading-scale: 1.5;\n");
+        css.push_str("  --button-font-size: 1.2rem;\n");
+    } else {
+        css.push_str("  --base-font-size: 16px;\n");
+        css.push_str("  --heading-scale: 1.2;\n");
+        css.push_str("  --button-font-size: 1rem;\n");
+    }
+    
+    // Screen reader optimization
+    if preferences.screen_reader_optimized {
+        css.push_str("  --focus-indicator: visible;\n");
+        css.push_str("  --skip-link-display: block;\n");
+    } else {
+        css.push_str("  --focus-indicator: auto;\n");
+        css.push_str("  --skip-link-display: none;\n");
+    }
+    
+    // Reduced motion
+    if preferences.reduced_motion {
+        css.push_str("  --transition-duration: 0s;\n");
+        css.push_str("  --animation-duration: 0s;\n");
+    } else {
+        css.push_str("  --transition-duration: 0.3s;\n");
+        css.push_str("  --animation-duration: 0.5s;\n");
+    }
+    
+    css.push_str("}\n");
+    
+    css
+}
+
+// Shortcuts for each auth flow when keyboard navigation is on
+fn keyboard_shortcuts(preferences: &AccessibilityPreferences) -> HashMap<String, String> {
+    let mut shortcuts = HashMap::new();
+    
+    if preferences.keyboard_navigation {
+        shortcuts.insert("login".to_string(), "Alt+L".to_string());
+        shortcuts.insert("register".to_string(), "Alt+R".to_string());
+        shortcuts.insert("password_reset".to_string(), "Alt+P".to_string());
+        shortcuts.insert("help".to_string(), "Alt+H".to_string());
+        shortcuts.insert("exit".to_string(), "Esc".to_string());
+    }
+    
+    shortcuts
+}
+
+// What a user who never changed anything gets
+fn default_preferences(user_id: Uuid) -> AccessibilityPreferences {
+    AccessibilityPreferences {
+        user_id,
+        high_contrast: false,
+        large_text: false,
+        screen_reader_optimized: false,
+        reduced_motion: false,
+        voice_commands_enabled: false,
+        keyboard_navigation: true,
+        additional_settings: HashMap::new(),
+    }
+}
+
+// Value of a custom property in generated CSS
+fn css_variable<'a>(css: &'a str, name: &str) -> Option<&'a str> {
+    css.lines()
+        .filter_map(|line| line.trim().strip_prefix(name)?.strip_prefix(':'))
+        .map(|value| value.trim().trim_end_matches(';'))
+        .next()
+}
+
+// WCAG relative luminance of a `#rrggbb` color
+fn relative_luminance(hex: &str) -> Option<f64> {
+    let hex = hex.strip_prefix('#').filter(|h| h.len() == 6)?;
+    let channel = |i: usize| -> Option<f64> {
+        let c = u8::from_str_radix(&hex[i..i + 2], 16).ok()? as f64 / 255.0;
+        Some(if c <= 0.03928 { c / 12.92 } else { ((c + 0.055) / 1.055).powf(2.4) })
+    };
+    
+    Some(0.2126 * channel(0)? + 0.7152 * channel(2)? + 0.0722 * channel(4)?)
+}
+
+fn contrast_ratio(foreground: &str, background: &str) -> Option<f64> {
+    let (a, b) = (relative_luminance(foreground)?, relative_luminance(background)?);
+    Some((a.max(b) + 0.05) / (a.min(b) + 0.05))
+}
+
+// 1.4.3: text must contrast with its background by at least 4.5:1 in every
+// stylesheet served
+fn check_contrast(in_use: &[AccessibilityPreferences]) -> ComplianceCheck {
+    let mut ratios = Vec::new();
+    let mut failures = Vec::new();
+    
+    for preferences in in_use {
+        let theme = if preferences.high_contrast { "high contrast" } else { "default" };
+        let css = css_variables(preferences);
+        let background = css_variable(&css, "--background-color").unwrap_or_default();
         
-        let total_users = state.user_preferences.len();
-        let high_contrast_users = state.user_preferences.values().filter(|p| p.high_contrast).count();
-        let large_text_users = state.user_preferences.values().filter(|p| p.large_text).count();
-        let screen_reader_users = state.user_preferences.values().filter(|p| p.screen_reader_optimized).count();
+        for color in ["--text-color", "--primary-color"] {
+            let ratio = css_variable(&css, color)
+                .and_then(|fg| contrast_ratio(fg, background))
+                .unwrap_or(0.0);
+            ratios.push(ratio);
+            if ratio < 4.5 {
+                failures.push(format!("{} {} is {:.2}:1", theme, color, ratio));
+            }
+        }
+    }
+    failures.sort();
+    failures.dedup();
+    
+    let lowest = ratios.iter().cloned().fold(f64::INFINITY, f64::min);
+    
+    ComplianceCheck {
+        criterion: "1.4.3",
+        title: "Contrast (Minimum)",
+        passed: failures.is_empty(),
+        detail: if failures.is_empty() {
+            format!("Lowest text contrast is {:.2}:1 across {} preference sets", lowest, in_use.len())
+        } else {
+            format!("Below 4.5:1: {}", failures.join(", "))
+        },
+    }
+}
+
+// 2.1.1 / 2.1.2: for everyone with keyboard navigation on, every auth flow
+// has a shortcut and there is a way out
+fn check_keyboard_navigation(in_use: &[AccessibilityPreferences]) -> ComplianceCheck {
+    let mut missing = Vec::new();
+    let mut checked = 0;
+    
+    for preferences in in_use.iter().filter(|p| p.keyboard_navigation) {
+        let shortcuts = keyboard_shortcuts(preferences);
+        checked += 1;
         
-        let report = format!(
-            "Accessibility Report\n\
-            -------------------\n\
-            Total users with preferences: {}\n\
-            Users with high contrast mode: {} ({}%)\n\
-            Users with large text mode: {} ({}%)\n\
-            Users with screen reader optimization: {} ({}%)\n\
-            \n\
-            Compliance Status: WCAG 2.1 AA Compliant\n\
-            Last updated: {}\n",
-            total_users,
-            high_contrast_users,
-            if total_users > 0 { high_contrast_users * 100 / total_users } else { 0 },
-            large_text_users,
-            if total_users > 0 { large_text_users * 100 / total_users } else { 0 },
-            screen_reader_users,
-            if total_users > 0 { screen_reader_users * 100 / total_users } else { 0 },
-            chrono::Utc::now().format("%Y-%m-%d %H:%M:%S UTC")
+        missing.extend(
+            ["login", "register", "password_reset", "exit"]
+                .into_iter()
+                .filter(|flow| !shortcuts.contains_key(*flow)),
         );
         
-        report
+        let mut keys: Vec<&String> = shortcuts.values().collect();
+        keys.sort();
+        keys.dedup();
+        if keys.len() != shortcuts.len() {
+            missing.push("unique key bindings");
+        }
+    }
+    missing.sort();
+    missing.dedup();
+    
+    ComplianceCheck {
+        criterion: "2.1.1",
+        title: "Keyboard: auth flows are keyboard navigable",
+        passed: missing.is_empty(),
+        detail: if missing.is_empty() {
+            format!("Shortcuts for every flow, including Esc to exit, in {} preference sets", checked)
+        } else {
+            format!("Missing: {}", missing.join(", "))
+        },
     }
-}
\ No newline at end of file
+}
+
+// 2.3.3: users who ask for reduced motion get no transitions or animations
+fn check_reduced_motion(in_use: &[AccessibilityPreferences]) -> ComplianceCheck {
+    let asked: Vec<&AccessibilityPreferences> = in_use.iter().filter(|p| p.reduced_motion).collect();
+    let mut moving: Vec<&str> = asked
+        .iter()
+        .flat_map(|preferences| {
+            let css = css_variables(preferences);
+            ["--transition-duration", "--animation-duration"]
+                .into_iter()
+                .filter(move |name| css_variable(&css, name) != Some("0s"))
+        })
+        .collect();
+    moving.sort();
+    moving.dedup();
+    
+    ComplianceCheck {
+        criterion: "2.3.3",
+        title: "Animation from Interactions: reduced motion",
+        passed: moving.is_empty(),
+        detail: if asked.is_empty() {
+            "No users have asked for reduced motion".to_string()
+        } else if moving.is_empty() {
+            format!("Transitions and animations are disabled for every user who asked ({})", asked.len())
+        } else {
+            format!("Still animated with reduced motion: {}", moving.join(", "))
+        },
+    }
+}
+
+// Map a transcript to `(command, action)` by the phrases it contains
+fn resolve_voice_intent(transcript: &str) -> Option<(&'static str, &'static str)> {
+    const INTENTS: [(&str, &str, &[&str]); 5] = [
+        ("reset password", "/auth/password-reset", &[
+            "reset password", "reset my password", "forgot password", "forgot my password",
+        ]),
+        ("register", "/auth/register", &[
+            "register", "sign up", "create account", "create an account",
+        ]),
+        ("login", "/auth/login", &["log in", "login", "sign in", "log me in"]),
+        ("help", "/help", &["help"]),
+        ("cancel", "/", &["cancel", "stop", "go back"]),
+    ];
+    
+    let words: Vec<String> = transcript
+        .split(|c: char| !c.is_alphanumeric() && c != '\'')
+        .filter(|w| !w.is_empty())
+        .map(|w| w.to_lowercase())
+        .collect();
+    let normalized = format!(" {} ", words.join(" "));
+    
+    // Earlier intents win, so "forgot password, help me log in" resets the password
+    INTENTS.iter()
+        .find(|(_, _, phrases)| phrases.iter().any(|p| normalized.contains(&format!(" {} ", p))))
+        .map(|(command, action, _)| (*command, *action))
+}
+
+// A short question that can be answered in a word or number
+fn logic_puzzle(rng: &mut impl Rng) -> (String, String) {
+    match rng.gen_range(0..3) {
+        0 => {
+            let start = rng.gen_range(1..10);
+            let step = rng.gen_range(2..6);
+            (
+                format!(
+                    "What number comes next: {}, {}, {}, ...?",
+                    start,
+                    start + step,
+                    start + 2 * step
+                ),
+                (start + 3 * step).to_string(),
+            )
+        }
+        1 => {
+            let mut numbers: Vec<u32> = (1..50).collect();
+            numbers.shuffle(rng);
+            let numbers = &numbers[..3];
+            let largest = numbers.iter().max().unwrap();
+            (
+                format!(
+                    "Which is the largest number: {}, {} or {}?",
+                    numbers[0], numbers[1], numbers[2]
+                ),
+                largest.to_string(),
+            )
+        }
+        _ => {
+            const DAYS: [&str; 7] = [
+                "Monday", "Tuesday", "Wednesday", "Thursday", "Friday", "Saturday", "Sunday",
+            ];
+            let day = rng.gen_range(0..DAYS.len());
+            (
+                format!("What day comes after {}?", DAYS[day]),
+                DAYS[(day + 1) % DAYS.len()].to_string(),
+            )
+        }
+    }
+}
+
+// Extract the format and samples of a RIFF/WAVE file
+fn parse_wav(bytes: &[u8]) -> Option<WavClip> {
+    if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+        return None;
+    }
+    
+    let mut format = None;
+    let mut data = None;
+    let mut offset = 12;
+    
+    while offset + 8 <= bytes.len() {
+        let id = &bytes[offset..offset + 4];
+        let size = u32::from_le_bytes(bytes[offset + 4..offset + 8].try_into().ok()?) as usize;
+        let body = bytes.get(offset + 8..offset + 8 + size)?;
+        
+        match id {
+            b"fmt " => format = Some(body.to_vec()),
+            b"data" => data = Some(body.to_vec()),
+            _ => {}
+        }
+        
+        // Chunks are padded to an even length
+        offset += 8 + size + (size % 2);
+    }
+    
+    Some(WavClip {
+        format: format?,
+        data: data?,
+    })
+}
+
+// Concatenate clips that share a format, with silence between them
+fn join_wav_clips(clips: &[&WavClip], gap_ms: u32) -> Vec<u8> {
+    let format = clips.first().map(|c| c.format.clone()).unwrap_or_default();
+    
+    // Bytes per second and block alignment live at fixed offsets in `fmt `
+    let byte_rate = format.get(8..12)
+        .map(|b| u32::from_le_bytes(b.try_into().unwrap()))
+        .unwrap_or(0);
+    let block_align = format.get(12..14)
+        .map(|b| u16::from_le_bytes(b.try_into().unwrap()).max(1) as u32)
+        .unwrap_or(1);
+    let gap_len = (byte_rate * gap_ms / 1000) / block_align * block_align;
+    
+    let mut data = Vec::new();
+    for (i, clip) in clips.iter().enumerate() {
+        if i > 0 {
+            data.extend(std::iter::repeat_n(0u8, gap_len as usize));
+        }
+        data.extend_from_slice(&clip.data);
+    }
+    
+    let mut wav = Vec::with_capacity(28 + format.len() + data.len());
+    wav.extend_from_slice(b"RIFF");
+    wav.extend_from_slice(&((20 + format.len() + data.len()) as u32).to_le_bytes());
+    wav.extend_from_slice(b"WAVE");
+    wav.extend_from_slice(b"fmt ");
+    wav.extend_from_slice(&(format.len() as u32).to_le_bytes());
+    wav.extend_from_slice(&format);
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    wav.extend_from_slice(&data);
+    wav
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 8 kHz, mono, 8-bit PCM
+    fn clip(samples: &[u8]) -> WavClip {
+        let mut format = Vec::new();
+        format.extend_from_slice(&1u16.to_le_bytes());
+        format.extend_from_slice(&1u16.to_le_bytes());
+        format.extend_from_slice(&8000u32.to_le_bytes());
+        format.extend_from_slice(&8000u32.to_le_bytes());
+        format.extend_from_slice(&1u16.to_le_bytes());
+        format.extend_from_slice(&8u16.to_le_bytes());
+        WavClip { format, data: samples.to_vec() }
+    }
+
+    struct FixedTranscript(&'static str, f32);
+
+    impl SpeechToText for FixedTranscript {
+        fn transcribe<'a>(
+            &'a self,
+            _: &'a [u8],
+            _: &'a str,
+        ) -> BoxFuture<'a, Result<Transcript, SpeechError>> {
+            let transcript = Transcript { text: self.0.to_string(), confidence: self.1 };
+            Box::pin(async move { Ok(transcript) })
+        }
+    }
+
+    fn parse(context: &AccessibilityContext) -> Result<Option<VoiceCommand>, SpeechError> {
+        futures::executor::block_on(context.parse_voice_command(b"", "audio/wav"))
+    }
+
+    #[test]
+    fn test_voice_command_intents() {
+        let intent = |text| resolve_voice_intent(text).map(|(command, _)| command);
+
+        assert_eq!(intent("Please sign in"), Some("login"));
+        assert_eq!(intent("I forgot my password!"), Some("reset password"));
+        assert_eq!(intent("Create an account"), Some("register"));
+        assert_eq!(intent("What's the weather?"), None);
+        assert_eq!(resolve_voice_intent("reset my password").map(|(_, action)| action), Some("/auth/password-reset"));
+    }
+
+    #[test]
+    fn test_voice_command_confidence_threshold() {
+        let unsure = AccessibilityContext::new()
+            .with_speech_provider(Box::new(FixedTranscript("log in", 0.4)), 0.6);
+        assert!(parse(&unsure).unwrap().is_none());
+
+        let sure = AccessibilityContext::new()
+            .with_speech_provider(Box::new(FixedTranscript("log in", 0.9)), 0.6);
+        assert_eq!(parse(&sure).unwrap().unwrap().action, "/auth/login");
+
+        assert_eq!(parse(&AccessibilityContext::new()).unwrap_err(), SpeechError::NotConfigured);
+    }
+
+    #[test]
+    fn test_contrast_ratio() {
+        assert!((contrast_ratio("#000000", "#ffffff").unwrap() - 21.0).abs() < 0.01);
+        assert!((contrast_ratio("#333333", "#ffffff").unwrap() - 12.63).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_report_without_audio_is_not_compliant() {
+        let report = AccessibilityContext::new().generate_accessibility_report();
+        let failed: Vec<&str> = report.checks.iter()
+            .filter(|c| !c.passed)
+            .map(|c| c.criterion)
+            .collect();
+
+        assert_eq!(report.status, ComplianceStatus::NonCompliant);
+        assert_eq!(failed, ["1.1.1"]);
+        assert!(report.to_csv().starts_with("criterion,title,passed,detail\n1.1.1,"));
+    }
+
+    #[test]
+    fn test_report_checks_preferences_in_use() {
+        let context = AccessibilityContext::new();
+        let user_id = Uuid::new_v4();
+        let mut preferences = default_preferences(user_id);
+        preferences.high_contrast = true;
+        preferences.reduced_motion = true;
+        context.set_preferences(&user_id, preferences);
+
+        let report = context.generate_accessibility_report();
+        let detail = |criterion: &str| {
+            report.checks.iter().find(|c| c.criterion == criterion).unwrap().detail.clone()
+        };
+
+        assert_eq!(report.usage.total_users, 1);
+        assert!(detail("1.4.3").ends_with("across 2 preference sets"));
+        assert!(detail("2.1.1").ends_with("in 2 preference sets"));
+        assert_eq!(detail("2.3.3"), "Transitions and animations are disabled for every user who asked (1)");
+    }
+
+    #[test]
+    fn test_math_captcha_round_trip() {
+        let context = AccessibilityContext::new();
+        let challenge = context.issue_captcha(CaptchaAlternative::SimpleMath);
+        let answer = context.state.lock().unwrap().captchas[&challenge.id].answer.clone();
+
+        assert_eq!(challenge.kind, CaptchaAlternative::SimpleMath);
+        assert!(context.verify_captcha(&challenge.id, &format!(" {} ", answer)));
+        // Answers are single-use
+        assert!(!context.verify_captcha(&challenge.id, &answer));
+    }
+
+    #[test]
+    fn test_audio_falls_back_without_clips() {
+        let context = AccessibilityContext::new();
+        let challenge = context.issue_captcha(CaptchaAlternative::Audio);
+
+        assert_eq!(challenge.kind, CaptchaAlternative::SimpleMath);
+        assert!(context.captcha_audio(&challenge.id).is_none());
+    }
+
+    #[test]
+    fn test_written_captchas_offer_audio() {
+        let mut context = AccessibilityContext::new();
+        assert!(context.issue_captcha(CaptchaAlternative::SimpleMath).alternatives.is_empty());
+
+        context.audio_clips = ('0'..='9').map(|digit| (digit, clip(&[1]))).collect();
+        let challenge = context.issue_captcha(CaptchaAlternative::SimpleMath);
+        assert_eq!(challenge.alternatives, [CaptchaAlternative::Audio]);
+
+        let audio = context.issue_captcha(CaptchaAlternative::Audio);
+        assert_eq!(audio.kind, CaptchaAlternative::Audio);
+        assert_eq!(audio.audio_url, Some(format!("/auth/captcha/{}/audio", audio.id)));
+        assert!(audio.alternatives.is_empty());
+    }
+
+    #[test]
+    fn test_join_wav_clips() {
+        let (one, two) = (clip(&[1, 2]), clip(&[3]));
+        let wav = join_wav_clips(&[&one, &two], 1);
+        let joined = parse_wav(&wav).unwrap();
+
+        // 1ms of 8kHz 8-bit audio is 8 bytes of silence
+        assert_eq!(joined.format, one.format);
+        assert_eq!(joined.data, [&[1, 2][..], &[0; 8], &[3]].concat());
+    }
+}
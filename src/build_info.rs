@@ -0,0 +1,43 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+// Set by build.rs
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+pub const GIT_SHA: &str = env!("BUILD_GIT_SHA"); // "unknown" when built outside a checkout without GIT_SHA
+const BUILD_TIMESTAMP: &str = env!("BUILD_TIMESTAMP"); // Seconds since the epoch
+const FEATURES: &str = env!("BUILD_FEATURES"); // Comma-separated
+
+/// Which build a server is running, for telling a fleet's instances apart
+#[derive(Debug, Clone, Serialize)]
+pub struct BuildInfo {
+    pub version: &'static str,
+    pub git_sha: &'static str,
+    pub built_at: Option<DateTime<Utc>>,
+    pub features: Vec<&'static str>,
+}
+
+pub fn build_info() -> BuildInfo {
+    BuildInfo {
+        version: VERSION,
+        git_sha: GIT_SHA,
+        built_at: BUILD_TIMESTAMP
+            .parse()
+            .ok()
+            .and_then(|secs| DateTime::from_timestamp(secs, 0)),
+        features: FEATURES.split(',').filter(|feature| !feature.is_empty()).collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build_info_comes_from_the_build() {
+        let info = build_info();
+        assert_eq!(info.version, env!("CARGO_PKG_VERSION"));
+        assert!(!info.git_sha.is_empty());
+        assert!(info.built_at.is_some());
+        assert_eq!(info.features.contains(&"client"), cfg!(feature = "client"));
+    }
+}
@@ -0,0 +1,391 @@
+//! Typed async client for the public API, for Rust apps talking to a
+//! BetterAuth server. Requests and responses are the server's own models, so
+//! the two can't drift apart. Compiled behind the `client` feature.
+
+use reqwest::{Method, RequestBuilder, Response};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::models::mfa::{
+    AddTotpDeviceRequest, ConfirmTotpDeviceRequest, MfaLoginRequest, MfaMethodOrderRequest, MfaMethodsResponse,
+    MfaRecoveryCodesResponse, MfaRecoveryRequest, MfaVerifyResponse, RecoveryCodeStatus, TotpDeviceRemovedResponse, TotpDeviceResponse,
+    TotpDeviceSetupResponse,
+};
+use crate::models::pagination::{Page, PageRequest};
+use crate::models::passwordless::{
+    PasskeyEnrollStartRequest, PasskeyPromptState, PasswordlessLoginCompleteRequest, PasswordlessLoginStartRequest,
+    PasswordlessLoginStartResponse, PasswordlessRegisterCompleteRequest, PasswordlessRegisterStartRequest,
+    PasswordlessRegisterStartResponse,
+};
+use crate::models::session::{
+    LogoutRequest, LogoutResponse, RefreshTokenRequest, RefreshTokenResponse, SessionFilter, SessionResponse,
+    UpdateSessionRequest,
+};
+use crate::models::user::{
+    DisableMfaRequest, EnableMfaRequest, LoginRequest, LoginResponse, MfaSetupResponse, RegisterRequest,
+    RegisterResponse, UserResponse, VerifyMfaRequest,
+};
+use crate::utils::secret::Secret;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ClientError {
+    #[error("Request failed: {0}")]
+    Transport(#[from] reqwest::Error),
+
+    #[error(transparent)]
+    Api(#[from] ApiError),
+
+    #[error("This endpoint needs an access token")]
+    NotSignedIn,
+}
+
+/// An error answered by the server, read from either error format it can be
+/// configured with (`application/problem+json` or the legacy body)
+#[derive(Debug, Clone, Deserialize, thiserror::Error)]
+#[error("{code} ({status}): {detail}")]
+pub struct ApiError {
+    #[serde(skip)]
+    pub status: u16,
+    #[serde(alias = "error")]
+    pub code: String, // e.g. "INVALID_CREDENTIALS"
+    #[serde(alias = "message")]
+    pub detail: String,
+    pub trace_id: Option<String>, // Not in the legacy format
+    pub retry_after: Option<u64>,
+}
+
+impl ApiError {
+    // Bodies that aren't ours, e.g. a proxy's 502 page, keep the text as detail
+    fn from_body(status: u16, body: &[u8]) -> Self {
+        serde_json::from_slice::<ApiError>(body)
+            .map(|error| ApiError { status, ..error })
+            .unwrap_or_else(|_| ApiError {
+                status,
+                code: "UNEXPECTED_RESPONSE".to_string(),
+                detail: String::from_utf8_lossy(body).chars().take(512).collect(),
+                trace_id: None,
+                retry_after: None,
+            })
+    }
+}
+
+pub type ClientResult<T> = Result<T, ClientError>;
+
+/// A client for one server. Cheap to clone; clones share the connection pool.
+#[derive(Clone)]
+pub struct BetterAuthClient {
+    http: reqwest::Client,
+    base_url: String,
+    access_token: Option<Secret<String>>,
+}
+
+impl BetterAuthClient {
+    /// `base_url` is where the API is mounted, e.g. `https://auth.example.com`
+    pub fn new(base_url: impl Into<String>) -> Self {
+        BetterAuthClient {
+            http: reqwest::Client::new(),
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            access_token: None,
+        }
+    }
+
+    /// Use a configured `reqwest::Client`, for timeouts, proxies or TLS settings
+    pub fn with_http_client(mut self, http: reqwest::Client) -> Self {
+        self.http = http;
+        self
+    }
+
+    /// Sign the client in, usually with `access_token` from a login or refresh
+    pub fn with_access_token(mut self, access_token: impl Into<String>) -> Self {
+        self.access_token = Some(Secret::new(access_token.into()));
+        self
+    }
+
+    pub fn set_access_token(&mut self, access_token: Option<String>) {
+        self.access_token = access_token.map(Secret::new);
+    }
+
+    pub fn access_token(&self) -> Option<&str> {
+        self.access_token.as_ref().map(|token| token.expose().as_str())
+    }
+
+    // Registration and login
+
+    pub async fn register(&self, data: &RegisterRequest) -> ClientResult<RegisterResponse> {
+        self.send(self.request(Method::POST, "/auth/register").json(data)).await
+    }
+
+    /// When `mfa_required` is set, the returned token is only good for
+    /// [`mfa_verify`](Self::mfa_verify), [`mfa_passkey_start`](Self::mfa_passkey_start)
+    /// and [`mfa_recovery`](Self::mfa_recovery)
+    pub async fn login(&self, data: &LoginRequest) -> ClientResult<LoginResponse> {
+        self.send(self.request(Method::POST, "/auth/login").json(data)).await
+    }
+
+    pub async fn mfa_login(&self, data: &MfaLoginRequest) -> ClientResult<LoginResponse> {
+        self.send(self.request(Method::POST, "/auth/mfa-login").json(data)).await
+    }
+
+    pub async fn refresh_token(&self, data: &RefreshTokenRequest) -> ClientResult<RefreshTokenResponse> {
+        self.send(self.request(Method::POST, "/auth/refresh-token").json(data)).await
+    }
+
+    /// Signs out the session of `refresh_token`, or the signed-in one
+    pub async fn logout(&self, data: &LogoutRequest) -> ClientResult<LogoutResponse> {
+        let mut request = self.request(Method::POST, "/auth/logout").json(data);
+        if let Some(token) = &self.access_token {
+            request = request.bearer_auth(token.expose());
+        }
+        self.send(request).await
+    }
+
+    pub async fn logout_all(&self) -> ClientResult<LogoutResponse> {
+        self.send(self.authorized(Method::POST, "/auth/logout-all")?).await
+    }
+
+    // MFA
+
+    pub async fn mfa_setup(&self) -> ClientResult<MfaSetupResponse> {
+        self.send(self.authorized(Method::GET, "/auth/mfa-setup")?).await
+    }
+
+    pub async fn mfa_enable(&self, data: &EnableMfaRequest) -> ClientResult<MfaRecoveryCodesResponse> {
+        self.send(self.authorized(Method::POST, "/auth/mfa-enable")?.json(data)).await
+    }
+
+    pub async fn mfa_disable(&self, data: &DisableMfaRequest) -> ClientResult<UserResponse> {
+        self.send(self.authorized(Method::POST, "/auth/mfa-disable")?.json(data)).await
+    }
+
+    pub async fn mfa_verify(&self, data: &VerifyMfaRequest) -> ClientResult<MfaVerifyResponse> {
+        self.send(self.authorized(Method::POST, "/auth/mfa-verify")?.json(data)).await
+    }
+
+    pub async fn mfa_recovery(&self, data: &MfaRecoveryRequest) -> ClientResult<MfaVerifyResponse> {
+        self.send(self.authorized(Method::POST, "/auth/mfa-recovery")?.json(data)).await
+    }
+
+    /// Answer the MFA step with a passkey; sign the returned options with
+    /// the authenticator and pass the assertion to `mfa_passkey_complete`
+    pub async fn mfa_passkey_start(&self) -> ClientResult<PasswordlessLoginStartResponse> {
+        self.send(self.authorized(Method::POST, "/auth/mfa-passkey/start")?).await
+    }
+
+    pub async fn mfa_passkey_complete(
+        &self,
+        data: &PasswordlessLoginCompleteRequest,
+    ) -> ClientResult<MfaVerifyResponse> {
+        self.send(self.authorized(Method::POST, "/auth/mfa-passkey/complete")?.json(data))
+            .await
+    }
+
+    pub async fn mfa_methods(&self) -> ClientResult<MfaMethodsResponse> {
+        self.send(self.authorized(Method::GET, "/auth/mfa-methods")?).await
+    }
+
+    pub async fn set_mfa_method_order(&self, data: &MfaMethodOrderRequest) -> ClientResult<MfaMethodsResponse> {
+        self.send(self.authorized(Method::PUT, "/auth/mfa-methods")?.json(data)).await
+    }
+
+    /// Replaces every recovery code; needs a token from `reauthenticate` with an MFA code
+    pub async fn mfa_recovery_codes(&self) -> ClientResult<MfaRecoveryCodesResponse> {
+        self.send(self.authorized(Method::POST, "/auth/mfa-recovery-codes")?).await
+    }
+
+    pub async fn recovery_code_status(&self) -> ClientResult<RecoveryCodeStatus> {
+        self.send(self.authorized(Method::GET, "/auth/mfa-recovery-codes/status")?).await
+    }
+
+    pub async fn list_totp_devices(&self) -> ClientResult<Vec<TotpDeviceResponse>> {
+        self.send(self.authorized(Method::GET, "/auth/mfa-devices")?).await
+    }
+
+    pub async fn add_totp_device(&self, data: &AddTotpDeviceRequest) -> ClientResult<TotpDeviceSetupResponse> {
+        self.send(self.authorized(Method::POST, "/auth/mfa-devices")?.json(data)).await
+    }
+
+    pub async fn confirm_totp_device(
+        &self,
+        device_id: Uuid,
+        data: &ConfirmTotpDeviceRequest,
+    ) -> ClientResult<TotpDeviceResponse> {
+        let path = format!("/auth/mfa-devices/{}/confirm", device_id);
+        self.send(self.authorized(Method::POST, &path)?.json(data)).await
+    }
+
+    pub async fn remove_totp_device(&self, device_id: Uuid) -> ClientResult<TotpDeviceRemovedResponse> {
+        let path = format!("/auth/mfa-devices/{}", device_id);
+        self.send(self.authorized(Method::DELETE, &path)?).await
+    }
+
+    // Passkeys
+
+    pub async fn passwordless_register_start(
+        &self,
+        data: &PasswordlessRegisterStartRequest,
+    ) -> ClientResult<PasswordlessRegisterStartResponse> {
+        self.send(self.request(Method::POST, "/auth/passwordless-register-start").json(data))
+            .await
+    }
+
+    pub async fn passwordless_register_complete(
+        &self,
+        data: &PasswordlessRegisterCompleteRequest,
+    ) -> ClientResult<RegisterResponse> {
+        self.send(self.request(Method::POST, "/auth/passwordless-register-complete").json(data))
+            .await
+    }
+
+    pub async fn passwordless_login_start(
+        &self,
+        data: &PasswordlessLoginStartRequest,
+    ) -> ClientResult<PasswordlessLoginStartResponse> {
+        self.send(self.request(Method::POST, "/auth/passwordless-login-start").json(data))
+            .await
+    }
+
+    pub async fn passwordless_login_complete(
+        &self,
+        data: &PasswordlessLoginCompleteRequest,
+    ) -> ClientResult<LoginResponse> {
+        self.send(self.request(Method::POST, "/auth/passwordless-login-complete").json(data))
+            .await
+    }
+
+    /// Add a passkey to the signed-in account; takes a recent password login
+    pub async fn passkey_enroll_start(
+        &self,
+        data: &PasskeyEnrollStartRequest,
+    ) -> ClientResult<PasswordlessRegisterStartResponse> {
+        self.send(self.authorized(Method::POST, "/auth/passkeys/enroll-start")?.json(data))
+            .await
+    }
+
+    pub async fn passkey_enroll_complete(
+        &self,
+        data: &PasswordlessRegisterCompleteRequest,
+    ) -> ClientResult<LogoutResponse> {
+        self.send(self.authorized(Method::POST, "/auth/passkeys/enroll-complete")?.json(data))
+            .await
+    }
+
+    pub async fn dismiss_passkey_prompt(&self) -> ClientResult<PasskeyPromptState> {
+        self.send(self.authorized(Method::POST, "/auth/passkeys/prompt/dismiss")?)
+            .await
+    }
+
+    // The signed-in user and their sessions
+
+    pub async fn me(&self) -> ClientResult<UserResponse> {
+        self.send(self.authorized(Method::GET, "/users/me")?).await
+    }
+
+    pub async fn sessions(&self, page: &PageRequest, filter: &SessionFilter) -> ClientResult<Page<SessionResponse>> {
+        self.send(self.authorized(Method::GET, "/users/sessions")?.query(page).query(filter))
+            .await
+    }
+
+    pub async fn update_session(&self, session_id: Uuid, data: &UpdateSessionRequest) -> ClientResult<SessionResponse> {
+        let path = format!("/users/sessions/{}", session_id);
+        self.send(self.authorized(Method::PATCH, &path)?.json(data)).await
+    }
+
+    pub async fn revoke_session(&self, session_id: Uuid) -> ClientResult<LogoutResponse> {
+        let path = format!("/users/sessions/{}", session_id);
+        self.send(self.authorized(Method::DELETE, &path)?).await
+    }
+
+    fn request(&self, method: Method, path: &str) -> RequestBuilder {
+        self.http.request(method, format!("{}{}", self.base_url, path))
+    }
+
+    fn authorized(&self, method: Method, path: &str) -> ClientResult<RequestBuilder> {
+        let token = self.access_token.as_ref().ok_or(ClientError::NotSignedIn)?;
+        Ok(self.request(method, path).bearer_auth(token.expose()))
+    }
+
+    async fn send<T: DeserializeOwned>(&self, request: RequestBuilder) -> ClientResult<T> {
+        let response = request.send().await?;
+        read(response).await
+    }
+}
+
+async fn read<T: DeserializeOwned>(response: Response) -> ClientResult<T> {
+    let status = response.status();
+    let body = response.bytes().await?;
+
+    if !status.is_success() {
+        return Err(ApiError::from_body(status.as_u16(), &body).into());
+    }
+    serde_json::from_slice(&body).map_err(|e| {
+        ApiError {
+            status: status.as_u16(),
+            code: "UNEXPECTED_RESPONSE".to_string(),
+            detail: format!("Response didn't match the expected shape: {}", e),
+            trace_id: None,
+            retry_after: None,
+        }
+        .into()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reads_both_error_formats() {
+        let problem = ApiError::from_body(
+            401,
+            br#"{"type": "https://errors.example.com/invalid-credentials", "title": "Invalid Credentials",
+                "status": 401, "detail": "Invalid credentials", "code": "INVALID_CREDENTIALS",
+                "trace_id": "abc"}"#,
+        );
+        assert_eq!(problem.status, 401);
+        assert_eq!(problem.code, "INVALID_CREDENTIALS");
+        assert_eq!(problem.detail, "Invalid credentials");
+        assert_eq!(problem.trace_id.as_deref(), Some("abc"));
+
+        let legacy = ApiError::from_body(
+            429,
+            br#"{"error": "RATE_LIMIT_EXCEEDED", "message": "Too many requests", "status_code": 429, "retry_after": 30}"#,
+        );
+        assert_eq!(legacy.code, "RATE_LIMIT_EXCEEDED");
+        assert_eq!(legacy.detail, "Too many requests");
+        assert_eq!(legacy.retry_after, Some(30));
+
+        let proxy = ApiError::from_body(502, b"<html>Bad Gateway</html>");
+        assert_eq!(proxy.code, "UNEXPECTED_RESPONSE");
+        assert_eq!(proxy.detail, "<html>Bad Gateway</html>");
+    }
+
+    #[test]
+    fn test_requests_serialize_to_what_the_server_accepts() {
+        let request = LoginRequest {
+            username_or_email: "alice".to_string(),
+            password: Secret::new("TestPass123!".to_string()),
+            webauthn_supported: true,
+            device_token: None,
+            captcha_id: None,
+            captcha_answer: None,
+        };
+
+        let body = serde_json::to_vec(&request).unwrap();
+        let parsed: LoginRequest = serde_json::from_slice(&body).unwrap();
+        assert_eq!(parsed.username_or_email, "alice");
+        assert_eq!(parsed.password.expose(), "TestPass123!");
+        assert!(parsed.webauthn_supported);
+    }
+
+    #[test]
+    fn test_signed_in_endpoints_need_a_token() {
+        let client = BetterAuthClient::new("https://auth.example.com/");
+        assert!(matches!(client.authorized(Method::GET, "/users/me"), Err(ClientError::NotSignedIn)));
+
+        let client = client.with_access_token("token");
+        let request = client.authorized(Method::GET, "/users/me").unwrap().build().unwrap();
+        assert_eq!(request.url().as_str(), "https://auth.example.com/users/me");
+        assert_eq!(request.headers()["authorization"], "Bearer token");
+    }
+}
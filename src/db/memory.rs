@@ -1,19 +1,64 @@
 use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
 
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, NaiveDate, Utc};
 use uuid::Uuid;
 
+use crate::db::unit_of_work::{UnitOfWork, Write};
 use crate::errors::AuthError;
 use crate::models::{
-    MfaRecoveryCode, NewMfaRecoveryCode, NewSession, NewUser, Session, User,
+    AccountAppeal, AccountRiskSignal, AccountStatus, AccountStatusEvent, ActionTokenRedemption, ApiKey, ApiKeyUsage, AuditEventFilter, AuthenticatorMetadata,
+    BackupEmail, BulkJob, BulkJobStatus, CanaryCredential, ClientApplication, ClientConsent, DirectoryEntry, EmailSend, EventType, FeatureFlag, GuestUpgrade, LoginFreeze, LoginPolicy, LoginPolicyScope, NewLoginPolicy, MfaRecoveryCode, NotificationPreferences, NewAccountAppeal, NewAccountRiskSignal, NewActionTokenRedemption,
+    NewApiKey, NewAuthenticatorMetadata, NewBackupEmail, NewBulkJob, NewCanaryCredential, NewClientApplication, NewClientConsent, NewDirectoryEntry, NewEmailSend, NewFeatureFlag, NewLoginFreeze, NewMfaRecoveryCode, NewNotificationPreferences, NewOrganization, NewOrganizationBranding, NewOrganizationDomain,
+    NewOrganizationMember, NewOutboxEvent, NewPolicyAcceptance, NewSession, NewSsoConnection,
+    NewSsoIdentity, NewTotpDevice, NewUser, Organization, OrganizationBranding, OrganizationDomain, OrganizationMember,
+    OrganizationRole, OutboxEvent, PageRequest, NewPasskey, NewProxyAlias, Passkey, PasskeyPromptState, PolicyAcceptance, ProxyAlias, PROXY_ALIAS_ACTIVE, PROXY_ALIAS_DELETED,
+    MfaFailures, ProfileChanges, NewSecurityQuestion, SecurityQuestion, SecurityQuestionFailures, Session, SessionChanges, SessionFilter, SessionSort, SessionTableStats, SortOrder, SsoConnection,
+    SsoIdentity, TokenRevocation, NewTokenRevocation, TotpDevice, NewTrustedDevice, TrustedDevice, User, UserFilter, UserSort,
 };
+use crate::utils::user_agent::DeviceInfo;
 
 // In-memory database for testing/development
 pub struct MemoryDb {
     users: Arc<Mutex<HashMap<Uuid, User>>>,
     sessions: Arc<Mutex<HashMap<Uuid, Session>>>,
+    token_revocations: Arc<Mutex<HashMap<Uuid, TokenRevocation>>>,
     recovery_codes: Arc<Mutex<HashMap<Uuid, MfaRecoveryCode>>>,
+    totp_devices: Arc<Mutex<HashMap<Uuid, TotpDevice>>>,
+    passkey_prompts: Arc<Mutex<HashMap<Uuid, PasskeyPromptState>>>,
+    mfa_method_preferences: Arc<Mutex<HashMap<Uuid, Vec<String>>>>,
+    backup_emails: Arc<Mutex<HashMap<Uuid, BackupEmail>>>,
+    status_events: Arc<Mutex<HashMap<Uuid, AccountStatusEvent>>>,
+    appeals: Arc<Mutex<HashMap<Uuid, AccountAppeal>>>,
+    risk_signals: Arc<Mutex<HashMap<Uuid, AccountRiskSignal>>>,
+    policy_acceptances: Arc<Mutex<HashMap<Uuid, PolicyAcceptance>>>,
+    organizations: Arc<Mutex<HashMap<Uuid, Organization>>>,
+    organization_members: Arc<Mutex<HashMap<Uuid, OrganizationMember>>>,
+    organization_domains: Arc<Mutex<HashMap<Uuid, OrganizationDomain>>>,
+    organization_branding: Arc<Mutex<HashMap<Uuid, OrganizationBranding>>>,
+    sso_connections: Arc<Mutex<HashMap<Uuid, SsoConnection>>>,
+    sso_identities: Arc<Mutex<HashMap<Uuid, SsoIdentity>>>,
+    action_token_redemptions: Arc<Mutex<HashMap<Uuid, ActionTokenRedemption>>>,
+    api_keys: Arc<Mutex<HashMap<Uuid, ApiKey>>>,
+    api_key_usage: Arc<Mutex<HashMap<(Uuid, NaiveDate), i64>>>,
+    canaries: Arc<Mutex<HashMap<Uuid, CanaryCredential>>>,
+    login_freezes: Arc<Mutex<HashMap<Uuid, LoginFreeze>>>,
+    login_policies: Arc<Mutex<HashMap<Uuid, LoginPolicy>>>,
+    bulk_jobs: Arc<Mutex<HashMap<Uuid, BulkJob>>>,
+    user_directory: Arc<Mutex<HashMap<Uuid, DirectoryEntry>>>,
+    security_questions: Arc<Mutex<HashMap<Uuid, SecurityQuestion>>>,
+    security_question_failures: Arc<Mutex<HashMap<Uuid, SecurityQuestionFailures>>>, // By user
+    mfa_failures: Arc<Mutex<HashMap<Uuid, MfaFailures>>>, // By user
+    feature_flags: Arc<Mutex<HashMap<String, FeatureFlag>>>,
+    client_applications: Arc<Mutex<HashMap<String, ClientApplication>>>,
+    client_consents: Arc<Mutex<HashMap<(Uuid, String), ClientConsent>>>,
+    authenticator_metadata: Arc<Mutex<HashMap<Uuid, AuthenticatorMetadata>>>,
+    notification_preferences: Arc<Mutex<HashMap<Uuid, NotificationPreferences>>>,
+    trusted_devices: Arc<Mutex<HashMap<Uuid, TrustedDevice>>>,
+    email_sends: Arc<Mutex<Vec<EmailSend>>>,
+    outbox: Arc<Mutex<HashMap<Uuid, OutboxEvent>>>,
+    passkeys: Arc<Mutex<Vec<Passkey>>>,
+    proxy_aliases: Arc<Mutex<HashMap<Uuid, ProxyAlias>>>,
 }
 
 impl MemoryDb {
@@ -21,12 +66,59 @@ impl MemoryDb {
         MemoryDb {
             users: Arc::new(Mutex::new(HashMap::new())),
             sessions: Arc::new(Mutex::new(HashMap::new())),
+            token_revocations: Arc::new(Mutex::new(HashMap::new())),
             recovery_codes: Arc::new(Mutex::new(HashMap::new())),
+            totp_devices: Arc::new(Mutex::new(HashMap::new())),
+            passkey_prompts: Arc::new(Mutex::new(HashMap::new())),
+            mfa_method_preferences: Arc::new(Mutex::new(HashMap::new())),
+            backup_emails: Arc::new(Mutex::new(HashMap::new())),
+            status_events: Arc::new(Mutex::new(HashMap::new())),
+            appeals: Arc::new(Mutex::new(HashMap::new())),
+            risk_signals: Arc::new(Mutex::new(HashMap::new())),
+            policy_acceptances: Arc::new(Mutex::new(HashMap::new())),
+            organizations: Arc::new(Mutex::new(HashMap::new())),
+            organization_members: Arc::new(Mutex::new(HashMap::new())),
+            organization_domains: Arc::new(Mutex::new(HashMap::new())),
+            organization_branding: Arc::new(Mutex::new(HashMap::new())),
+            sso_connections: Arc::new(Mutex::new(HashMap::new())),
+            sso_identities: Arc::new(Mutex::new(HashMap::new())),
+            action_token_redemptions: Arc::new(Mutex::new(HashMap::new())),
+            api_keys: Arc::new(Mutex::new(HashMap::new())),
+            api_key_usage: Arc::new(Mutex::new(HashMap::new())),
+            canaries: Arc::new(Mutex::new(HashMap::new())),
+            login_freezes: Arc::new(Mutex::new(HashMap::new())),
+            login_policies: Arc::new(Mutex::new(HashMap::new())),
+            bulk_jobs: Arc::new(Mutex::new(HashMap::new())),
+            user_directory: Arc::new(Mutex::new(HashMap::new())),
+            security_questions: Arc::new(Mutex::new(HashMap::new())),
+            security_question_failures: Arc::new(Mutex::new(HashMap::new())),
+            mfa_failures: Arc::new(Mutex::new(HashMap::new())),
+            feature_flags: Arc::new(Mutex::new(HashMap::new())),
+            client_applications: Arc::new(Mutex::new(HashMap::new())),
+            client_consents: Arc::new(Mutex::new(HashMap::new())),
+            authenticator_metadata: Arc::new(Mutex::new(HashMap::new())),
+            notification_preferences: Arc::new(Mutex::new(HashMap::new())),
+            trusted_devices: Arc::new(Mutex::new(HashMap::new())),
+            email_sends: Arc::new(Mutex::new(Vec::new())),
+            outbox: Arc::new(Mutex::new(HashMap::new())),
+            passkeys: Arc::new(Mutex::new(Vec::new())),
+            proxy_aliases: Arc::new(Mutex::new(HashMap::new())),
         }
     }
 
     // User methods
-    pub async fn create_user(&self, user: NewUser) -> Result<User, AuthError> {
+    pub async fn create_user(&self, user: NewUser, event: NewOutboxEvent) -> Result<User, AuthError> {
+        // Same as the unique indexes on `users` in Postgres
+        if self
+            .users
+            .lock()
+            .unwrap()
+            .values()
+            .any(|existing| same_identifier(&existing.username, &user.username) || same_identifier(&existing.email, &user.email))
+        {
+            return Err(AuthError::DatabaseError("Insert user error: username or email already taken".into()));
+        }
+
         let now = Utc::now();
         let user = User {
             id: user.id,
@@ -43,14 +135,35 @@ impl MemoryDb {
             created_at: now,
             updated_at: now,
             last_login_at: None,
-            is_active: user.is_active,
             is_admin: user.is_admin,
+            token_version: 0,
+            display_name: None,
+            avatar_url: None,
+            locale: None,
+            timezone: None,
+            metadata: serde_json::json!({}),
+            password_reset_email: None,
+            password_expires_at: user.password_expires_at,
+            status: AccountStatus::Active.as_str().to_string(),
+            status_reason: None,
+            status_changed_by: None,
+            status_changed_at: None,
+            is_guest: user.is_guest,
+            mfa_reenrollment_required: false,
+            failed_login_count: 0,
+            last_failed_login_at: None,
+            locked_until: None,
+            activation_pending: user.activation_pending,
+            version: 1,
+            email_display: user.email_display,
+            archived_at: None,
         };
 
         {
             let mut users = self.users.lock().unwrap();
             users.insert(user.id, user.clone());
         }
+        self.enqueue_event(event);
 
         Ok(user)
     }
@@ -67,7 +180,7 @@ impl MemoryDb {
         let users = self.users.lock().unwrap();
         users
             .values()
-            .find(|user| user.username == username)
+            .find(|user| same_identifier(&user.username, username))
             .cloned()
             .ok_or(AuthError::UserNotFound)
     }
@@ -76,46 +189,80 @@ impl MemoryDb {
         let users = self.users.lock().unwrap();
         users
             .values()
-            .find(|user| user.email == email)
+            .find(|user| same_identifier(&user.email, email))
             .cloned()
             .ok_or(AuthError::UserNotFound)
     }
 
-    pub async fn find_user_by_username_or_email(&self, username_or_email: &str) -> Result<User, AuthError> {
-        let users = self.users.lock().unwrap();
-        users
+    pub async fn find_users_by_email_domain(&self, domain: &str) -> Result<Vec<User>, AuthError> {
+        let suffix = format!("@{}", domain);
+        let mut users: Vec<User> = self
+            .users
+            .lock()
+            .unwrap()
             .values()
-            .find(|user| user.username == username_or_email || user.email == username_or_email)
+            .filter(|user| user.email.to_lowercase().ends_with(&suffix))
             .cloned()
-            .ok_or(AuthError::UserNotFound)
+            .collect();
+        users.sort_by_key(|user| user.created_at);
+        Ok(users)
     }
 
-    pub async fn find_user_by_verification_token(&self, token: &str) -> Result<User, AuthError> {
+    pub async fn find_user_by_username_or_email(&self, username_or_email: &str) -> Result<User, AuthError> {
         let users = self.users.lock().unwrap();
         users
             .values()
-            .find(|user| user.email_verification_token.as_deref() == Some(token))
+            .find(|user| same_identifier(&user.username, username_or_email) || same_identifier(&user.email, username_or_email))
             .cloned()
-            .ok_or(AuthError::InvalidToken)
+            .ok_or(AuthError::UserNotFound)
     }
 
-    pub async fn find_user_by_reset_token(&self, token: &str) -> Result<User, AuthError> {
+    pub async fn user_exists_by_username(&self, username: &str) -> Result<bool, AuthError> {
         let users = self.users.lock().unwrap();
-        users
-            .values()
-            .find(|user| user.password_reset_token.as_deref() == Some(token))
-            .cloned()
-            .ok_or(AuthError::InvalidToken)
+        Ok(users.values().any(|user| same_identifier(&user.username, username)))
     }
 
-    pub async fn user_exists_by_username(&self, username: &str) -> Result<bool, AuthError> {
+    pub async fn user_exists_by_email(&self, email: &str) -> Result<bool, AuthError> {
         let users = self.users.lock().unwrap();
-        Ok(users.values().any(|user| user.username == username))
+        Ok(users.values().any(|user| same_identifier(&user.email, email)))
     }
 
-    pub async fn user_exists_by_email(&self, email: &str) -> Result<bool, AuthError> {
+    pub async fn search_users(
+        &self,
+        query: Option<&str>,
+        filter: &UserFilter,
+        page: &PageRequest,
+    ) -> Result<(Vec<User>, i64), AuthError> {
         let users = self.users.lock().unwrap();
-        Ok(users.values().any(|user| user.email == email))
+        let mut found: Vec<User> = users
+            .values()
+            .filter(|user| query.map_or(true, |term| matches_search(user, term)))
+            .filter(|user| filter.matches(user))
+            .cloned()
+            .collect();
+
+        // Ties are broken by id so pages stay stable
+        found.sort_by(|a, b| {
+            let ordering = match filter.sort {
+                UserSort::CreatedAt => a.created_at.cmp(&b.created_at),
+                UserSort::Username => a.username.to_lowercase().cmp(&b.username.to_lowercase()),
+            }
+            .then_with(|| a.id.cmp(&b.id));
+
+            match page.order {
+                SortOrder::Asc => ordering,
+                SortOrder::Desc => ordering.reverse(),
+            }
+        });
+
+        let total = found.len() as i64;
+        let found = found
+            .into_iter()
+            .skip(page.offset as usize)
+            .take(page.limit as usize)
+            .collect();
+
+        Ok((found, total))
     }
 
     pub async fn update_last_login(&self, id: Uuid) -> Result<(), AuthError> {
@@ -129,104 +276,237 @@ impl MemoryDb {
         }
     }
 
-    pub async fn update_verification_token(&self, id: Uuid, token: &str) -> Result<(), AuthError> {
+    pub async fn update_password(
+        &self,
+        id: Uuid,
+        password_hash: &str,
+        expires_at: Option<DateTime<Utc>>,
+        event: NewOutboxEvent,
+    ) -> Result<(), AuthError> {
         let mut users = self.users.lock().unwrap();
         if let Some(user) = users.get_mut(&id) {
-            user.email_verification_token = Some(token.to_string());
-            user.email_verification_sent_at = Some(Utc::now());
+            user.password_hash = password_hash.to_string();
+            user.password_reset_token = None;
+            user.password_reset_sent_at = None;
+            user.password_reset_email = None;
+            user.password_expires_at = expires_at;
             user.updated_at = Utc::now();
+            self.enqueue_event(event);
             Ok(())
         } else {
             Err(AuthError::UserNotFound)
         }
     }
 
-    pub async fn update_password_reset_token(&self, id: Uuid, token: &str) -> Result<(), AuthError> {
+    pub async fn expire_passwords(&self, user_ids: Option<Vec<Uuid>>) -> Result<Vec<Uuid>, AuthError> {
         let mut users = self.users.lock().unwrap();
-        if let Some(user) = users.get_mut(&id) {
-            user.password_reset_token = Some(token.to_string());
-            user.password_reset_sent_at = Some(Utc::now());
-            user.updated_at = Utc::now();
-            Ok(())
-        } else {
-            Err(AuthError::UserNotFound)
+        let now = Utc::now();
+        let mut expired = Vec::new();
+
+        for user in users.values_mut() {
+            if user_ids.as_ref().map_or(true, |ids| ids.contains(&user.id)) {
+                user.password_expires_at = Some(now);
+                user.updated_at = now;
+                expired.push(user.id);
+            }
         }
+
+        Ok(expired)
     }
 
-    pub async fn update_password(&self, id: Uuid, password_hash: &str) -> Result<(), AuthError> {
+    pub async fn bump_token_version(&self, id: Uuid) -> Result<i32, AuthError> {
         let mut users = self.users.lock().unwrap();
         if let Some(user) = users.get_mut(&id) {
-            user.password_hash = password_hash.to_string();
-            user.password_reset_token = None;
-            user.password_reset_sent_at = None;
+            user.token_version += 1;
             user.updated_at = Utc::now();
-            Ok(())
+            Ok(user.token_version)
         } else {
             Err(AuthError::UserNotFound)
         }
     }
 
-    pub async fn verify_email(&self, id: Uuid) -> Result<User, AuthError> {
+    pub async fn archive_inactive_users(&self, inactive_before: DateTime<Utc>, limit: i64) -> Result<Vec<Uuid>, AuthError> {
+        let now = Utc::now();
+        let mut users = self.users.lock().unwrap();
+        let ids: Vec<Uuid> = users
+            .values()
+            .filter(|user| user.archived_at.is_none() && !user.is_guest)
+            .filter(|user| user.last_login_at.unwrap_or(user.created_at) < inactive_before)
+            .map(|user| user.id)
+            .take(limit.max(0) as usize)
+            .collect();
+
+        for id in &ids {
+            let user = users.get_mut(id).unwrap();
+            user.archived_at = Some(now);
+            user.token_version += 1;
+            user.version += 1;
+            user.updated_at = now;
+        }
+        drop(users);
+
+        for session in self.sessions.lock().unwrap().values_mut() {
+            if ids.contains(&session.user_id) && !session.is_revoked {
+                session.is_revoked = true;
+                session.updated_at = now;
+            }
+        }
+        for id in &ids {
+            self.enqueue_event(NewOutboxEvent::new(EventType::Archived, *id, serde_json::json!({ "reason": "inactive" })));
+        }
+        Ok(ids)
+    }
+
+    pub async fn reactivate_user(&self, id: Uuid, event: NewOutboxEvent) -> Result<User, AuthError> {
+        let mut users = self.users.lock().unwrap();
+        let user = users
+            .get_mut(&id)
+            .filter(|user| user.archived_at.is_some())
+            .ok_or(AuthError::UserNotFound)?;
+
+        user.archived_at = None;
+        user.version += 1;
+        user.updated_at = Utc::now();
+        self.enqueue_event(event);
+        Ok(user.clone())
+    }
+
+    pub async fn activate_user(
+        &self,
+        id: Uuid,
+        password_hash: &str,
+        expires_at: Option<DateTime<Utc>>,
+        event: NewOutboxEvent,
+    ) -> Result<User, AuthError> {
+        let mut users = self.users.lock().unwrap();
+        let user = users
+            .get_mut(&id)
+            .filter(|user| user.activation_pending)
+            .ok_or(AuthError::UserNotFound)?;
+
+        user.password_hash = password_hash.to_string();
+        user.password_expires_at = expires_at;
+        user.is_email_verified = true;
+        user.email_verification_token = None;
+        user.email_verification_sent_at = None;
+        user.activation_pending = false;
+        user.updated_at = Utc::now();
+        self.enqueue_event(event);
+        Ok(user.clone())
+    }
+
+    pub async fn verify_email(&self, id: Uuid, event: NewOutboxEvent) -> Result<User, AuthError> {
         let mut users = self.users.lock().unwrap();
         if let Some(user) = users.get_mut(&id) {
             user.is_email_verified = true;
             user.email_verification_token = None;
             user.email_verification_sent_at = None;
             user.updated_at = Utc::now();
+            self.enqueue_event(event);
             Ok(user.clone())
         } else {
             Err(AuthError::UserNotFound)
         }
     }
 
-    pub async fn update_mfa_secret(&self, id: Uuid, secret: &str) -> Result<(), AuthError> {
+    pub async fn update_profile(
+        &self,
+        id: Uuid,
+        expected_version: Option<i32>,
+        changes: ProfileChanges,
+    ) -> Result<User, AuthError> {
         let mut users = self.users.lock().unwrap();
-        if let Some(user) = users.get_mut(&id) {
-            user.mfa_secret = Some(secret.to_string());
-            user.updated_at = Utc::now();
-            Ok(())
-        } else {
-            Err(AuthError::UserNotFound)
+        let user = users.get_mut(&id).ok_or(AuthError::UserNotFound)?;
+        check_version(user, expected_version)?;
+
+        if let Some(display_name) = changes.display_name {
+            user.display_name = display_name;
+        }
+        if let Some(avatar_url) = changes.avatar_url {
+            user.avatar_url = avatar_url;
+        }
+        if let Some(locale) = changes.locale {
+            user.locale = locale;
+        }
+        if let Some(timezone) = changes.timezone {
+            user.timezone = timezone;
         }
+        if let Some(metadata) = changes.metadata {
+            user.metadata = metadata;
+        }
+        user.version += 1;
+        user.updated_at = Utc::now();
+
+        Ok(user.clone())
     }
 
-    pub async fn enable_mfa(&self, id: Uuid) -> Result<(), AuthError> {
+    pub async fn upgrade_guest_user(
+        &self,
+        id: Uuid,
+        upgrade: GuestUpgrade,
+        event: NewOutboxEvent,
+    ) -> Result<User, AuthError> {
         let mut users = self.users.lock().unwrap();
-        if let Some(user) = users.get_mut(&id) {
-            user.mfa_enabled = true;
-            user.updated_at = Utc::now();
-            Ok(())
-        } else {
-            Err(AuthError::UserNotFound)
+        if users.values().any(|other| {
+            other.id != id
+                && (same_identifier(&other.username, &upgrade.username) || same_identifier(&other.email, &upgrade.email))
+        }) {
+            return Err(AuthError::ValidationError("Username or email is already taken".into()));
         }
+        let user = users
+            .get_mut(&id)
+            .filter(|user| user.is_guest)
+            .ok_or_else(|| AuthError::ValidationError("Account is already registered".into()))?;
+
+        user.username = upgrade.username;
+        user.email = upgrade.email;
+        user.email_display = upgrade.email_display;
+        user.password_hash = upgrade.password_hash;
+        user.email_verification_sent_at = upgrade.email_verification_sent_at;
+        user.password_expires_at = upgrade.password_expires_at;
+        user.is_guest = false;
+        user.token_version += 1;
+        user.version += 1;
+        user.updated_at = Utc::now();
+        self.enqueue_event(event);
+
+        Ok(user.clone())
     }
 
-    pub async fn disable_mfa(&self, id: Uuid) -> Result<User, AuthError> {
+    pub async fn update_mfa_secret(&self, id: Uuid, expected_version: Option<i32>, secret: &str) -> Result<(), AuthError> {
         let mut users = self.users.lock().unwrap();
-        if let Some(user) = users.get_mut(&id) {
-            user.mfa_enabled = false;
-            user.mfa_secret = None;
-            user.updated_at = Utc::now();
-            Ok(user.clone())
-        } else {
-            Err(AuthError::UserNotFound)
-        }
+        let user = users.get_mut(&id).ok_or(AuthError::UserNotFound)?;
+        check_version(user, expected_version)?;
+        user.mfa_secret = Some(secret.to_string());
+        user.version += 1;
+        user.updated_at = Utc::now();
+        Ok(())
+    }
+
+    pub async fn enable_mfa(&self, id: Uuid, expected_version: Option<i32>) -> Result<(), AuthError> {
+        let mut users = self.users.lock().unwrap();
+        let user = users.get_mut(&id).ok_or(AuthError::UserNotFound)?;
+        check_version(user, expected_version)?;
+        user.mfa_enabled = true;
+        user.version += 1;
+        user.updated_at = Utc::now();
+        Ok(())
+    }
+
+    pub async fn disable_mfa(&self, id: Uuid, expected_version: Option<i32>) -> Result<User, AuthError> {
+        let mut users = self.users.lock().unwrap();
+        let user = users.get_mut(&id).ok_or(AuthError::UserNotFound)?;
+        check_version(user, expected_version)?;
+        user.mfa_enabled = false;
+        user.mfa_secret = None;
+        user.version += 1;
+        user.updated_at = Utc::now();
+        Ok(user.clone())
     }
 
     // Session methods
     pub async fn create_session(&self, session: NewSession) -> Result<Session, AuthError> {
-        let now = Utc::now();
-        let session = Session {
-            id: session.id,
-            user_id: session.user_id,
-            refresh_token: session.refresh_token,
-            user_agent: session.user_agent,
-            ip_address: session.ip_address,
-            expires_at: session.expires_at,
-            created_at: now,
-            updated_at: now,
-            is_revoked: false,
-        };
+        let session = Self::new_session(session, Utc::now());
 
         {
             let mut sessions = self.sessions.lock().unwrap();
@@ -253,15 +533,57 @@ impl MemoryDb {
             .ok_or(AuthError::InvalidToken)
     }
 
-    pub async fn find_sessions_by_user_id(&self, user_id: Uuid) -> Result<Vec<Session>, AuthError> {
+    pub async fn find_sessions_by_user_id(
+        &self,
+        user_id: Uuid,
+        filter: &SessionFilter,
+        page: &PageRequest,
+    ) -> Result<(Vec<Session>, i64), AuthError> {
         let sessions = self.sessions.lock().unwrap();
-        let user_sessions: Vec<Session> = sessions
+        let mut user_sessions: Vec<Session> = sessions
             .values()
             .filter(|session| session.user_id == user_id && !session.is_revoked)
+            .filter(|session| filter.matches(session))
             .cloned()
             .collect();
 
-        Ok(user_sessions)
+        // Ties are broken by id so pages stay stable
+        user_sessions.sort_by(|a, b| {
+            let ordering = match filter.sort {
+                SessionSort::CreatedAt => a.created_at.cmp(&b.created_at),
+                SessionSort::ExpiresAt => a.expires_at.cmp(&b.expires_at),
+            }
+            .then_with(|| a.id.cmp(&b.id));
+
+            match page.order {
+                SortOrder::Asc => ordering,
+                SortOrder::Desc => ordering.reverse(),
+            }
+        });
+
+        let total = user_sessions.len() as i64;
+        let user_sessions = user_sessions
+            .into_iter()
+            .skip(page.offset as usize)
+            .take(page.limit as usize)
+            .collect();
+
+        Ok((user_sessions, total))
+    }
+
+    pub async fn find_session_devices(&self, user_id: Uuid) -> Result<Vec<DeviceInfo>, AuthError> {
+        let mut devices: Vec<DeviceInfo> = Vec::new();
+        for session in self.sessions.lock().unwrap().values().filter(|s| s.user_id == user_id) {
+            let device = DeviceInfo {
+                browser: session.browser.clone(),
+                os: session.os.clone(),
+                device_class: session.device_class.clone(),
+            };
+            if !devices.contains(&device) {
+                devices.push(device);
+            }
+        }
+        Ok(devices)
     }
 
     pub async fn revoke_session(&self, id: Uuid) -> Result<(), AuthError> {
@@ -275,10 +597,34 @@ impl MemoryDb {
         }
     }
 
-    pub async fn revoke_all_sessions(&self, user_id: Uuid) -> Result<(), AuthError> {
+    pub async fn update_session(&self, id: Uuid, changes: SessionChanges) -> Result<Session, AuthError> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let session = sessions.get_mut(&id).ok_or(AuthError::InvalidToken)?;
+
+        if let Some(name) = changes.name {
+            session.name = name;
+        }
+        if let Some(is_pinned) = changes.is_pinned {
+            session.is_pinned = is_pinned;
+        }
+        if let Some(expires_at) = changes.expires_at {
+            session.expires_at = expires_at;
+        }
+        if let Some(client_id) = changes.client_id {
+            session.client_id = client_id;
+        }
+        if let Some(scopes) = changes.scopes {
+            session.scopes = scopes;
+        }
+        session.updated_at = Utc::now();
+
+        Ok(session.clone())
+    }
+
+    pub async fn revoke_all_sessions(&self, user_id: Uuid, include_pinned: bool) -> Result<(), AuthError> {
         let mut sessions = self.sessions.lock().unwrap();
         for session in sessions.values_mut() {
-            if session.user_id == user_id {
+            if session.user_id == user_id && (include_pinned || !session.is_pinned) {
                 session.is_revoked = true;
                 session.updated_at = Utc::now();
             }
@@ -286,24 +632,108 @@ impl MemoryDb {
         Ok(())
     }
 
-    // MFA Recovery codes methods
-    pub async fn create_recovery_code(&self, code: NewMfaRecoveryCode) -> Result<MfaRecoveryCode, AuthError> {
+    pub async fn touch_session(&self, id: Uuid, seen_at: DateTime<Utc>) -> Result<(), AuthError> {
+        let mut sessions = self.sessions.lock().unwrap();
+        if let Some(session) = sessions.get_mut(&id).filter(|session| !session.is_revoked) {
+            session.last_seen_at = seen_at;
+        }
+        Ok(())
+    }
+
+    pub async fn revoke_idle_sessions(&self, user_id: Uuid, idle_since: DateTime<Utc>) -> Result<usize, AuthError> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let mut revoked = 0;
+        for session in sessions.values_mut() {
+            if session.user_id == user_id && !session.is_revoked && session.last_seen_at < idle_since {
+                session.is_revoked = true;
+                session.updated_at = Utc::now();
+                revoked += 1;
+            }
+        }
+        Ok(revoked)
+    }
+
+    pub async fn purge_sessions(&self, ended_before: DateTime<Utc>, limit: i64) -> Result<usize, AuthError> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let ended: Vec<Uuid> = sessions
+            .values()
+            .filter(|s| (s.is_revoked && s.updated_at < ended_before) || s.expires_at < ended_before)
+            .map(|s| s.id)
+            .take(limit.max(0) as usize)
+            .collect();
+
+        for id in &ended {
+            sessions.remove(id);
+        }
+        Ok(ended.len())
+    }
+
+    pub async fn create_token_revocations(&self, revocations: Vec<NewTokenRevocation>) -> Result<(), AuthError> {
+        let mut stored = self.token_revocations.lock().unwrap();
         let now = Utc::now();
-        let recovery_code = MfaRecoveryCode {
-            id: code.id,
-            user_id: code.user_id,
-            code: code.code,
-            is_used: false,
-            used_at: None,
-            created_at: now,
+        stored.retain(|_, r| r.expires_at >= now);
+
+        for revocation in revocations {
+            stored.entry(revocation.id).or_insert(TokenRevocation {
+                id: revocation.id,
+                expires_at: revocation.expires_at,
+                revoked_at: now,
+            });
+        }
+        Ok(())
+    }
+
+    pub async fn find_token_revocations(&self, since: DateTime<Utc>) -> Result<Vec<TokenRevocation>, AuthError> {
+        let now = Utc::now();
+        Ok(self
+            .token_revocations
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|r| r.revoked_at >= since && r.expires_at > now)
+            .cloned()
+            .collect())
+    }
+
+    pub async fn session_table_stats(&self, now: DateTime<Utc>) -> Result<SessionTableStats, AuthError> {
+        let sessions = self.sessions.lock().unwrap();
+        let mut stats = SessionTableStats {
+            total: sessions.len() as i64,
+            ..Default::default()
         };
+        for session in sessions.values() {
+            if session.is_revoked {
+                stats.revoked += 1;
+            } else if session.expires_at <= now {
+                stats.expired += 1;
+            } else {
+                stats.live += 1;
+            }
+        }
+        Ok(stats)
+    }
 
-        {
-            let mut codes = self.recovery_codes.lock().unwrap();
-            codes.insert(recovery_code.id, recovery_code.clone());
+    // MFA Recovery codes methods
+    pub async fn create_recovery_codes(&self, codes: Vec<NewMfaRecoveryCode>) -> Result<Vec<MfaRecoveryCode>, AuthError> {
+        let now = Utc::now();
+        let created: Vec<MfaRecoveryCode> = codes
+            .into_iter()
+            .map(|code| MfaRecoveryCode {
+                id: code.id,
+                user_id: code.user_id,
+                code: code.code,
+                is_used: false,
+                used_at: None,
+                created_at: now,
+            })
+            .collect();
+
+        let mut stored = self.recovery_codes.lock().unwrap();
+        for recovery_code in &created {
+            stored.insert(recovery_code.id, recovery_code.clone());
         }
 
-        Ok(recovery_code)
+        Ok(created)
     }
 
     pub async fn use_recovery_code(&self, user_id: Uuid, code: &str) -> Result<bool, AuthError> {
@@ -320,9 +750,1855 @@ impl MemoryDb {
         }
     }
 
+    pub async fn find_recovery_codes(&self, user_id: Uuid) -> Result<Vec<MfaRecoveryCode>, AuthError> {
+        let codes = self.recovery_codes.lock().unwrap();
+        Ok(codes.values().filter(|rc| rc.user_id == user_id).cloned().collect())
+    }
+
     pub async fn delete_recovery_codes(&self, user_id: Uuid) -> Result<(), AuthError> {
         let mut codes = self.recovery_codes.lock().unwrap();
         codes.retain(|_, rc| rc.user_id != user_id);
         Ok(())
     }
+
+    // TOTP device methods
+    pub async fn create_totp_device(&self, device: NewTotpDevice) -> Result<TotpDevice, AuthError> {
+        let mut devices = self.totp_devices.lock().unwrap();
+
+        if devices
+            .values()
+            .any(|d| d.user_id == device.user_id && d.name == device.name)
+        {
+            return Err(AuthError::ValidationError("A device with this name already exists".into()));
+        }
+
+        let device = TotpDevice {
+            id: device.id,
+            user_id: device.user_id,
+            name: device.name,
+            secret: device.secret,
+            is_confirmed: false,
+            created_at: Utc::now(),
+            confirmed_at: None,
+            last_used_at: None,
+        };
+        devices.insert(device.id, device.clone());
+
+        Ok(device)
+    }
+
+    pub async fn find_totp_devices_by_user_id(&self, user_id: Uuid) -> Result<Vec<TotpDevice>, AuthError> {
+        let devices = self.totp_devices.lock().unwrap();
+        let mut devices: Vec<TotpDevice> = devices
+            .values()
+            .filter(|d| d.user_id == user_id)
+            .cloned()
+            .collect();
+        devices.sort_by(|a, b| a.created_at.cmp(&b.created_at));
+        Ok(devices)
+    }
+
+    pub async fn confirm_totp_device(&self, id: Uuid) -> Result<(), AuthError> {
+        let mut devices = self.totp_devices.lock().unwrap();
+        if let Some(device) = devices.get_mut(&id) {
+            device.is_confirmed = true;
+            device.confirmed_at = Some(Utc::now());
+            Ok(())
+        } else {
+            Err(AuthError::ValidationError("TOTP device not found".into()))
+        }
+    }
+
+    pub async fn touch_totp_device(&self, id: Uuid) -> Result<(), AuthError> {
+        let mut devices = self.totp_devices.lock().unwrap();
+        if let Some(device) = devices.get_mut(&id) {
+            device.last_used_at = Some(Utc::now());
+        }
+        Ok(())
+    }
+
+    pub async fn delete_totp_device(&self, id: Uuid) -> Result<(), AuthError> {
+        let mut devices = self.totp_devices.lock().unwrap();
+        devices.remove(&id);
+        Ok(())
+    }
+
+    pub async fn delete_totp_devices(&self, user_id: Uuid) -> Result<(), AuthError> {
+        let mut devices = self.totp_devices.lock().unwrap();
+        devices.retain(|_, d| d.user_id != user_id);
+        Ok(())
+    }
+
+    // MFA method preference methods
+    pub async fn find_mfa_method_order(&self, user_id: Uuid) -> Result<Vec<String>, AuthError> {
+        let preferences = self.mfa_method_preferences.lock().unwrap();
+        Ok(preferences.get(&user_id).cloned().unwrap_or_default())
+    }
+
+    pub async fn save_mfa_method_order(&self, user_id: Uuid, methods: Vec<String>) -> Result<(), AuthError> {
+        let mut preferences = self.mfa_method_preferences.lock().unwrap();
+        preferences.insert(user_id, methods);
+        Ok(())
+    }
+
+    // Passkey prompt methods
+    pub async fn find_passkey_prompt(&self, user_id: Uuid) -> Result<Option<PasskeyPromptState>, AuthError> {
+        let prompts = self.passkey_prompts.lock().unwrap();
+        Ok(prompts.get(&user_id).cloned())
+    }
+
+    pub async fn record_passkey_prompt(&self, user_id: Uuid) -> Result<(), AuthError> {
+        let mut prompts = self.passkey_prompts.lock().unwrap();
+        let state = prompts.entry(user_id).or_insert_with(|| Self::empty_passkey_prompt(user_id));
+        state.prompt_count += 1;
+        state.last_prompted_at = Some(Utc::now());
+        Ok(())
+    }
+
+    pub async fn dismiss_passkey_prompt(&self, user_id: Uuid) -> Result<(), AuthError> {
+        let mut prompts = self.passkey_prompts.lock().unwrap();
+        let state = prompts.entry(user_id).or_insert_with(|| Self::empty_passkey_prompt(user_id));
+        state.dismissed_at = Some(Utc::now());
+        Ok(())
+    }
+
+    // Backup email methods
+    pub async fn create_backup_email(&self, email: NewBackupEmail) -> Result<BackupEmail, AuthError> {
+        let mut emails = self.backup_emails.lock().unwrap();
+
+        if emails.values().any(|e| same_identifier(&e.email, &email.email)) {
+            return Err(AuthError::EmailExists);
+        }
+
+        let email = BackupEmail {
+            id: email.id,
+            user_id: email.user_id,
+            email: email.email,
+            is_verified: false,
+            verification_token: email.verification_token,
+            verification_sent_at: email.verification_sent_at,
+            verified_at: None,
+            created_at: Utc::now(),
+        };
+        emails.insert(email.id, email.clone());
+
+        Ok(email)
+    }
+
+    pub async fn find_backup_emails_by_user_id(&self, user_id: Uuid) -> Result<Vec<BackupEmail>, AuthError> {
+        let emails = self.backup_emails.lock().unwrap();
+        let mut emails: Vec<BackupEmail> = emails
+            .values()
+            .filter(|e| e.user_id == user_id)
+            .cloned()
+            .collect();
+        emails.sort_by(|a, b| a.created_at.cmp(&b.created_at));
+        Ok(emails)
+    }
+
+    pub async fn find_backup_email_by_address(&self, email: &str) -> Result<Option<BackupEmail>, AuthError> {
+        let emails = self.backup_emails.lock().unwrap();
+        Ok(emails.values().find(|e| same_identifier(&e.email, email)).cloned())
+    }
+
+    pub async fn find_backup_email_by_token(&self, token: &str) -> Result<BackupEmail, AuthError> {
+        let emails = self.backup_emails.lock().unwrap();
+        emails
+            .values()
+            .find(|e| e.verification_token.as_deref() == Some(token))
+            .cloned()
+            .ok_or(AuthError::InvalidToken)
+    }
+
+    pub async fn verify_backup_email(&self, id: Uuid) -> Result<BackupEmail, AuthError> {
+        let mut emails = self.backup_emails.lock().unwrap();
+        let email = emails.get_mut(&id).ok_or(AuthError::InvalidToken)?;
+
+        email.is_verified = true;
+        email.verification_token = None;
+        email.verification_sent_at = None;
+        email.verified_at = Some(Utc::now());
+
+        Ok(email.clone())
+    }
+
+    pub async fn delete_backup_email(&self, id: Uuid) -> Result<(), AuthError> {
+        let mut emails = self.backup_emails.lock().unwrap();
+        emails.remove(&id);
+        Ok(())
+    }
+
+    // Account status methods
+    pub async fn set_account_status(
+        &self,
+        user_id: Uuid,
+        expected_version: Option<i32>,
+        status: AccountStatus,
+        reason: &str,
+        actor_id: Option<Uuid>,
+        event: NewOutboxEvent,
+    ) -> Result<User, AuthError> {
+        let now = Utc::now();
+        let user = {
+            let mut users = self.users.lock().unwrap();
+            let user = users.get_mut(&user_id).ok_or(AuthError::UserNotFound)?;
+            check_version(user, expected_version)?;
+            user.status = status.as_str().to_string();
+            user.status_reason = Some(reason.to_string());
+            user.status_changed_by = actor_id;
+            user.status_changed_at = Some(now);
+            user.version += 1;
+            user.updated_at = now;
+            user.clone()
+        };
+
+        let status_event = AccountStatusEvent {
+            id: Uuid::new_v4(),
+            user_id,
+            status: status.as_str().to_string(),
+            reason: reason.to_string(),
+            actor_id,
+            created_at: now,
+        };
+        self.status_events.lock().unwrap().insert(status_event.id, status_event);
+        self.enqueue_event(event);
+
+        Ok(user)
+    }
+
+    pub async fn find_account_status_events(
+        &self,
+        user_id: Uuid,
+        page: &PageRequest,
+    ) -> Result<(Vec<AccountStatusEvent>, i64), AuthError> {
+        let events = self.status_events.lock().unwrap();
+        let mut events: Vec<AccountStatusEvent> = events
+            .values()
+            .filter(|e| e.user_id == user_id)
+            .cloned()
+            .collect();
+
+        // Ties are broken by id so pages stay stable
+        events.sort_by(|a, b| {
+            let ordering = a.created_at.cmp(&b.created_at).then_with(|| a.id.cmp(&b.id));
+            match page.order {
+                SortOrder::Asc => ordering,
+                SortOrder::Desc => ordering.reverse(),
+            }
+        });
+
+        let total = events.len() as i64;
+        let events = events
+            .into_iter()
+            .skip(page.offset as usize)
+            .take(page.limit as usize)
+            .collect();
+        Ok((events, total))
+    }
+
+    // Account risk methods
+    pub async fn record_account_risk_signal(&self, signal: NewAccountRiskSignal) -> Result<AccountRiskSignal, AuthError> {
+        let signal = AccountRiskSignal {
+            id: signal.id,
+            user_id: signal.user_id,
+            signal: signal.signal,
+            weight: signal.weight,
+            created_at: Utc::now(),
+        };
+
+        self.risk_signals.lock().unwrap().insert(signal.id, signal.clone());
+        Ok(signal)
+    }
+
+    pub async fn find_account_risk_signals(
+        &self,
+        user_id: Uuid,
+        since: DateTime<Utc>,
+    ) -> Result<Vec<AccountRiskSignal>, AuthError> {
+        let signals = self.risk_signals.lock().unwrap();
+        let mut signals: Vec<AccountRiskSignal> = signals
+            .values()
+            .filter(|s| s.user_id == user_id && s.created_at >= since)
+            .cloned()
+            .collect();
+        signals.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+        Ok(signals)
+    }
+
+    pub async fn find_account_risk_signals_before(
+        &self,
+        before: DateTime<Utc>,
+        limit: i64,
+    ) -> Result<Vec<AccountRiskSignal>, AuthError> {
+        let signals = self.risk_signals.lock().unwrap();
+        let mut signals: Vec<AccountRiskSignal> = signals
+            .values()
+            .filter(|s| s.created_at < before)
+            .cloned()
+            .collect();
+        signals.sort_by(|a, b| a.created_at.cmp(&b.created_at).then_with(|| a.id.cmp(&b.id)));
+        signals.truncate(limit.max(0) as usize);
+        Ok(signals)
+    }
+
+    pub async fn delete_account_risk_signals(&self, ids: Vec<Uuid>) -> Result<usize, AuthError> {
+        let mut signals = self.risk_signals.lock().unwrap();
+        Ok(ids.iter().filter(|id| signals.remove(id).is_some()).count())
+    }
+
+    pub async fn set_mfa_reenrollment_required(&self, user_id: Uuid, required: bool) -> Result<User, AuthError> {
+        let mut users = self.users.lock().unwrap();
+        let user = users.get_mut(&user_id).ok_or(AuthError::UserNotFound)?;
+        user.mfa_reenrollment_required = required;
+        user.updated_at = Utc::now();
+        Ok(user.clone())
+    }
+
+    // Brute force methods
+    pub async fn record_failed_login(&self, user_id: Uuid, window_start: DateTime<Utc>) -> Result<i32, AuthError> {
+        let mut users = self.users.lock().unwrap();
+        let user = users.get_mut(&user_id).ok_or(AuthError::UserNotFound)?;
+        user.failed_login_count = match user.last_failed_login_at {
+            Some(at) if at >= window_start => user.failed_login_count + 1,
+            _ => 1,
+        };
+        user.last_failed_login_at = Some(Utc::now());
+        Ok(user.failed_login_count)
+    }
+
+    pub async fn lock_account(&self, user_id: Uuid, until: DateTime<Utc>) -> Result<(), AuthError> {
+        let mut users = self.users.lock().unwrap();
+        let user = users.get_mut(&user_id).ok_or(AuthError::UserNotFound)?;
+        user.failed_login_count = 0;
+        user.locked_until = Some(until);
+        Ok(())
+    }
+
+    pub async fn clear_failed_logins(&self, user_id: Uuid) -> Result<(), AuthError> {
+        let mut users = self.users.lock().unwrap();
+        let user = users.get_mut(&user_id).ok_or(AuthError::UserNotFound)?;
+        user.failed_login_count = 0;
+        user.last_failed_login_at = None;
+        user.locked_until = None;
+        Ok(())
+    }
+
+    pub async fn find_locked_users(&self, now: DateTime<Utc>) -> Result<Vec<User>, AuthError> {
+        let mut locked: Vec<User> = self
+            .users
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|user| user.locked_until.map_or(false, |until| until > now))
+            .cloned()
+            .collect();
+        locked.sort_by(|a, b| b.locked_until.cmp(&a.locked_until).then_with(|| a.id.cmp(&b.id)));
+        Ok(locked)
+    }
+
+    // Appeal methods
+    pub async fn create_account_appeal(&self, appeal: NewAccountAppeal) -> Result<AccountAppeal, AuthError> {
+        let mut appeals = self.appeals.lock().unwrap();
+
+        if appeals.values().any(|a| a.user_id == appeal.user_id && a.status == "pending") {
+            return Err(AuthError::ValidationError("An appeal is already pending".into()));
+        }
+
+        let appeal = AccountAppeal {
+            id: appeal.id,
+            user_id: appeal.user_id,
+            message: appeal.message,
+            status: "pending".to_string(),
+            resolution_note: None,
+            resolved_by: None,
+            resolved_at: None,
+            created_at: Utc::now(),
+        };
+        appeals.insert(appeal.id, appeal.clone());
+
+        Ok(appeal)
+    }
+
+    pub async fn find_latest_account_appeal(&self, user_id: Uuid) -> Result<Option<AccountAppeal>, AuthError> {
+        let appeals = self.appeals.lock().unwrap();
+        Ok(appeals
+            .values()
+            .filter(|a| a.user_id == user_id)
+            .max_by_key(|a| a.created_at)
+            .cloned())
+    }
+
+    pub async fn find_pending_account_appeals(&self) -> Result<Vec<AccountAppeal>, AuthError> {
+        let appeals = self.appeals.lock().unwrap();
+        let mut appeals: Vec<AccountAppeal> = appeals
+            .values()
+            .filter(|a| a.status == "pending")
+            .cloned()
+            .collect();
+        appeals.sort_by(|a, b| a.created_at.cmp(&b.created_at));
+        Ok(appeals)
+    }
+
+    pub async fn resolve_account_appeal(
+        &self,
+        id: Uuid,
+        accepted: bool,
+        note: &str,
+        admin_id: Uuid,
+    ) -> Result<Option<AccountAppeal>, AuthError> {
+        let mut appeals = self.appeals.lock().unwrap();
+        let appeal = match appeals.get_mut(&id).filter(|a| a.status == "pending") {
+            Some(appeal) => appeal,
+            None => return Ok(None),
+        };
+
+        appeal.status = if accepted { "accepted" } else { "rejected" }.to_string();
+        appeal.resolution_note = Some(note.to_string());
+        appeal.resolved_by = Some(admin_id);
+        appeal.resolved_at = Some(Utc::now());
+
+        Ok(Some(appeal.clone()))
+    }
+
+    // Policy acceptance methods
+    pub async fn has_accepted_policy(&self, user_id: Uuid, version: &str) -> Result<bool, AuthError> {
+        let acceptances = self.policy_acceptances.lock().unwrap();
+        Ok(acceptances
+            .values()
+            .any(|a| a.user_id == user_id && a.version == version))
+    }
+
+    pub async fn record_policy_acceptance(&self, acceptance: NewPolicyAcceptance) -> Result<(), AuthError> {
+        let mut acceptances = self.policy_acceptances.lock().unwrap();
+
+        if acceptances
+            .values()
+            .any(|a| a.user_id == acceptance.user_id && a.version == acceptance.version)
+        {
+            return Ok(());
+        }
+
+        acceptances.insert(
+            acceptance.id,
+            PolicyAcceptance {
+                id: acceptance.id,
+                user_id: acceptance.user_id,
+                version: acceptance.version,
+                ip_address: acceptance.ip_address,
+                user_agent: acceptance.user_agent,
+                accepted_at: Utc::now(),
+            },
+        );
+
+        Ok(())
+    }
+
+    // Organization methods
+    pub async fn create_organization(
+        &self,
+        organization: NewOrganization,
+        owner_id: Uuid,
+    ) -> Result<Organization, AuthError> {
+        let now = Utc::now();
+        let organization = {
+            let mut organizations = self.organizations.lock().unwrap();
+
+            if organizations.values().any(|o| o.slug == organization.slug) {
+                return Err(AuthError::ValidationError("Organization slug is already taken".into()));
+            }
+
+            let organization = Organization {
+                id: organization.id,
+                name: organization.name,
+                slug: organization.slug,
+                created_at: now,
+                updated_at: now,
+            };
+            organizations.insert(organization.id, organization.clone());
+            organization
+        };
+
+        let owner = OrganizationMember {
+            id: Uuid::new_v4(),
+            organization_id: organization.id,
+            user_id: owner_id,
+            role: OrganizationRole::Owner.as_str().to_string(),
+            created_at: now,
+            updated_at: now,
+        };
+        self.organization_members.lock().unwrap().insert(owner.id, owner);
+
+        Ok(organization)
+    }
+
+    pub async fn find_organization_by_id(&self, id: Uuid) -> Result<Option<Organization>, AuthError> {
+        let organizations = self.organizations.lock().unwrap();
+        Ok(organizations.get(&id).cloned())
+    }
+
+    pub async fn find_organization_by_slug(&self, slug: &str) -> Result<Option<Organization>, AuthError> {
+        let organizations = self.organizations.lock().unwrap();
+        Ok(organizations.values().find(|o| o.slug == slug).cloned())
+    }
+
+    pub async fn find_user_organizations(
+        &self,
+        user_id: Uuid,
+    ) -> Result<Vec<(Organization, OrganizationMember)>, AuthError> {
+        let organizations = self.organizations.lock().unwrap();
+        let members = self.organization_members.lock().unwrap();
+        let mut result: Vec<(Organization, OrganizationMember)> = members
+            .values()
+            .filter(|m| m.user_id == user_id)
+            .filter_map(|m| {
+                organizations
+                    .get(&m.organization_id)
+                    .map(|o| (o.clone(), m.clone()))
+            })
+            .collect();
+        result.sort_by(|a, b| a.0.name.cmp(&b.0.name));
+
+        Ok(result)
+    }
+
+    pub async fn find_organization_member(
+        &self,
+        organization_id: Uuid,
+        user_id: Uuid,
+    ) -> Result<Option<OrganizationMember>, AuthError> {
+        let members = self.organization_members.lock().unwrap();
+        Ok(members
+            .values()
+            .find(|m| m.organization_id == organization_id && m.user_id == user_id)
+            .cloned())
+    }
+
+    pub async fn add_organization_member(
+        &self,
+        member: NewOrganizationMember,
+    ) -> Result<OrganizationMember, AuthError> {
+        let mut members = self.organization_members.lock().unwrap();
+
+        if members
+            .values()
+            .any(|m| m.organization_id == member.organization_id && m.user_id == member.user_id)
+        {
+            return Err(AuthError::ValidationError("User is already a member".into()));
+        }
+
+        let now = Utc::now();
+        let member = OrganizationMember {
+            id: member.id,
+            organization_id: member.organization_id,
+            user_id: member.user_id,
+            role: member.role,
+            created_at: now,
+            updated_at: now,
+        };
+        members.insert(member.id, member.clone());
+
+        Ok(member)
+    }
+
+    pub async fn update_organization_member_role(
+        &self,
+        id: Uuid,
+        role: OrganizationRole,
+    ) -> Result<OrganizationMember, AuthError> {
+        let mut members = self.organization_members.lock().unwrap();
+        let member = members
+            .get_mut(&id)
+            .ok_or_else(|| AuthError::DatabaseError("Organization member not found".into()))?;
+
+        member.role = role.as_str().to_string();
+        member.updated_at = Utc::now();
+
+        Ok(member.clone())
+    }
+
+    pub async fn find_organization_branding(
+        &self,
+        organization_id: Uuid,
+    ) -> Result<Option<OrganizationBranding>, AuthError> {
+        Ok(self.organization_branding.lock().unwrap().get(&organization_id).cloned())
+    }
+
+    pub async fn save_organization_branding(
+        &self,
+        branding: NewOrganizationBranding,
+    ) -> Result<OrganizationBranding, AuthError> {
+        let branding = OrganizationBranding {
+            organization_id: branding.organization_id,
+            display_name: branding.display_name,
+            logo_url: branding.logo_url,
+            primary_color: branding.primary_color,
+            updated_at: Utc::now(),
+        };
+        self.organization_branding
+            .lock()
+            .unwrap()
+            .insert(branding.organization_id, branding.clone());
+
+        Ok(branding)
+    }
+
+    // Organization domain methods
+    pub async fn find_organization_domains(
+        &self,
+        organization_id: Uuid,
+    ) -> Result<Vec<OrganizationDomain>, AuthError> {
+        let domains = self.organization_domains.lock().unwrap();
+        let mut domains: Vec<OrganizationDomain> = domains
+            .values()
+            .filter(|d| d.organization_id == organization_id)
+            .cloned()
+            .collect();
+        domains.sort_by(|a, b| a.domain.cmp(&b.domain));
+
+        Ok(domains)
+    }
+
+    pub async fn find_organization_domain_by_id(&self, id: Uuid) -> Result<Option<OrganizationDomain>, AuthError> {
+        let domains = self.organization_domains.lock().unwrap();
+        Ok(domains.get(&id).cloned())
+    }
+
+    pub async fn find_verified_organization_domain(&self, domain: &str) -> Result<Option<OrganizationDomain>, AuthError> {
+        let domains = self.organization_domains.lock().unwrap();
+        Ok(domains
+            .values()
+            .find(|d| d.domain == domain && d.is_verified())
+            .cloned())
+    }
+
+    pub async fn create_organization_domain(
+        &self,
+        domain: NewOrganizationDomain,
+    ) -> Result<OrganizationDomain, AuthError> {
+        let mut domains = self.organization_domains.lock().unwrap();
+
+        if domains
+            .values()
+            .any(|d| d.organization_id == domain.organization_id && d.domain == domain.domain)
+        {
+            return Err(AuthError::ValidationError("Domain has already been added".into()));
+        }
+
+        let domain = OrganizationDomain {
+            id: domain.id,
+            organization_id: domain.organization_id,
+            domain: domain.domain,
+            created_at: Utc::now(),
+            verification_token: domain.verification_token,
+            verified_at: None,
+            last_checked_at: None,
+            auto_join: domain.auto_join,
+        };
+        domains.insert(domain.id, domain.clone());
+
+        Ok(domain)
+    }
+
+    pub async fn record_organization_domain_check(
+        &self,
+        id: Uuid,
+        verified_at: Option<DateTime<Utc>>,
+    ) -> Result<OrganizationDomain, AuthError> {
+        let mut domains = self.organization_domains.lock().unwrap();
+
+        let name = domains
+            .get(&id)
+            .map(|d| d.domain.clone())
+            .ok_or_else(|| AuthError::DatabaseError("Organization domain not found".into()))?;
+        if verified_at.is_some()
+            && domains.values().any(|d| d.id != id && d.domain == name && d.is_verified())
+        {
+            return Err(AuthError::ValidationError(
+                "Domain is already verified by another organization".into(),
+            ));
+        }
+
+        let domain = domains.get_mut(&id).unwrap();
+        domain.verified_at = verified_at;
+        domain.last_checked_at = Some(Utc::now());
+
+        Ok(domain.clone())
+    }
+
+    pub async fn set_organization_domain_auto_join(
+        &self,
+        id: Uuid,
+        auto_join: bool,
+    ) -> Result<OrganizationDomain, AuthError> {
+        let mut domains = self.organization_domains.lock().unwrap();
+        let domain = domains
+            .get_mut(&id)
+            .ok_or_else(|| AuthError::DatabaseError("Organization domain not found".into()))?;
+
+        domain.auto_join = auto_join;
+
+        Ok(domain.clone())
+    }
+
+    pub async fn delete_organization_domain(&self, id: Uuid) -> Result<(), AuthError> {
+        self.organization_domains.lock().unwrap().remove(&id);
+        Ok(())
+    }
+
+    // SSO connection methods
+    pub async fn find_sso_connection(&self, id: Uuid) -> Result<Option<SsoConnection>, AuthError> {
+        let connections = self.sso_connections.lock().unwrap();
+        Ok(connections.get(&id).cloned())
+    }
+
+    pub async fn find_sso_connection_by_organization(
+        &self,
+        organization_id: Uuid,
+    ) -> Result<Option<SsoConnection>, AuthError> {
+        let connections = self.sso_connections.lock().unwrap();
+        Ok(connections
+            .values()
+            .find(|c| c.organization_id == organization_id)
+            .cloned())
+    }
+
+    pub async fn save_sso_connection(&self, connection: NewSsoConnection) -> Result<SsoConnection, AuthError> {
+        let mut connections = self.sso_connections.lock().unwrap();
+        let now = Utc::now();
+
+        // One connection per organization; saving again replaces it
+        let existing = connections
+            .values()
+            .find(|c| c.organization_id == connection.organization_id)
+            .cloned();
+        if let Some(existing) = &existing {
+            connections.remove(&existing.id);
+        }
+
+        let connection = SsoConnection {
+            id: connection.id,
+            organization_id: connection.organization_id,
+            protocol: connection.protocol,
+            enabled: connection.enabled,
+            oidc_issuer: connection.oidc_issuer,
+            oidc_client_id: connection.oidc_client_id,
+            oidc_client_secret: connection.oidc_client_secret,
+            saml_idp_metadata: connection.saml_idp_metadata,
+            groups_attribute: connection.groups_attribute,
+            role_mappings: connection.role_mappings,
+            default_role: connection.default_role,
+            created_at: existing.map(|c| c.created_at).unwrap_or(now),
+            updated_at: now,
+            provisioning_rules: connection.provisioning_rules,
+        };
+        connections.insert(connection.id, connection.clone());
+
+        Ok(connection)
+    }
+
+    pub async fn delete_sso_connection(&self, organization_id: Uuid) -> Result<(), AuthError> {
+        let removed: Vec<Uuid> = {
+            let mut connections = self.sso_connections.lock().unwrap();
+            let ids: Vec<Uuid> = connections
+                .values()
+                .filter(|c| c.organization_id == organization_id)
+                .map(|c| c.id)
+                .collect();
+            for id in &ids {
+                connections.remove(id);
+            }
+            ids
+        };
+
+        self.sso_identities
+            .lock()
+            .unwrap()
+            .retain(|_, i| !removed.contains(&i.connection_id));
+
+        Ok(())
+    }
+
+    // SSO identity methods
+    pub async fn find_sso_identity(
+        &self,
+        connection_id: Uuid,
+        subject: &str,
+    ) -> Result<Option<SsoIdentity>, AuthError> {
+        let identities = self.sso_identities.lock().unwrap();
+        Ok(identities
+            .values()
+            .find(|i| i.connection_id == connection_id && i.subject == subject)
+            .cloned())
+    }
+
+    pub async fn create_sso_identity(&self, identity: NewSsoIdentity) -> Result<SsoIdentity, AuthError> {
+        let mut identities = self.sso_identities.lock().unwrap();
+
+        if identities
+            .values()
+            .any(|i| i.connection_id == identity.connection_id && i.subject == identity.subject)
+        {
+            return Err(AuthError::DatabaseError("SSO identity already linked".into()));
+        }
+
+        let identity = SsoIdentity {
+            id: identity.id,
+            connection_id: identity.connection_id,
+            user_id: identity.user_id,
+            subject: identity.subject,
+            created_at: Utc::now(),
+            last_login_at: None,
+        };
+        identities.insert(identity.id, identity.clone());
+
+        Ok(identity)
+    }
+
+    pub async fn record_sso_login(&self, identity_id: Uuid) -> Result<(), AuthError> {
+        let mut identities = self.sso_identities.lock().unwrap();
+        if let Some(identity) = identities.get_mut(&identity_id) {
+            identity.last_login_at = Some(Utc::now());
+        }
+
+        Ok(())
+    }
+
+    // Action token methods
+    pub async fn redeem_action_token(&self, redemption: NewActionTokenRedemption) -> Result<(), AuthError> {
+        let mut redemptions = self.action_token_redemptions.lock().unwrap();
+        if redemptions.contains_key(&redemption.jti) {
+            return Err(AuthError::InvalidToken);
+        }
+
+        redemptions.insert(
+            redemption.jti,
+            ActionTokenRedemption {
+                jti: redemption.jti,
+                purpose: redemption.purpose,
+                user_id: redemption.user_id,
+                redeemed_at: Utc::now(),
+                expires_at: redemption.expires_at,
+            },
+        );
+
+        Ok(())
+    }
+
+    pub async fn record_email_send(&self, send: NewEmailSend, prune_before: DateTime<Utc>) -> Result<(), AuthError> {
+        let mut sends = self.email_sends.lock().unwrap();
+        sends.retain(|s| s.address != send.address || s.kind != send.kind || s.created_at >= prune_before);
+        sends.push(EmailSend {
+            id: send.id,
+            address: send.address,
+            kind: send.kind,
+            created_at: Utc::now(),
+        });
+        Ok(())
+    }
+
+    pub async fn find_email_sends_since(
+        &self,
+        address: &str,
+        kind: &str,
+        since: DateTime<Utc>,
+    ) -> Result<Vec<DateTime<Utc>>, AuthError> {
+        let sends = self.email_sends.lock().unwrap();
+        let mut times: Vec<DateTime<Utc>> = sends
+            .iter()
+            .filter(|s| s.address == address && s.kind == kind && s.created_at >= since)
+            .map(|s| s.created_at)
+            .collect();
+        times.sort_by(|a, b| b.cmp(a));
+        Ok(times)
+    }
+
+    // API key methods
+    pub async fn create_api_key(&self, key: NewApiKey) -> Result<ApiKey, AuthError> {
+        let key = ApiKey {
+            id: key.id,
+            user_id: key.user_id,
+            name: key.name,
+            key_prefix: key.key_prefix,
+            key_hash: key.key_hash,
+            scopes: key.scopes,
+            expires_at: key.expires_at,
+            last_used_at: None,
+            created_at: Utc::now(),
+            daily_quota: None,
+            monthly_quota: None,
+        };
+        self.api_keys.lock().unwrap().insert(key.id, key.clone());
+
+        Ok(key)
+    }
+
+    pub async fn find_api_keys_by_user_id(&self, user_id: Uuid) -> Result<Vec<ApiKey>, AuthError> {
+        let keys = self.api_keys.lock().unwrap();
+        let mut keys: Vec<ApiKey> = keys.values().filter(|k| k.user_id == user_id).cloned().collect();
+        keys.sort_by(|a, b| a.created_at.cmp(&b.created_at));
+        Ok(keys)
+    }
+
+    pub async fn find_api_key_by_hash(&self, key_hash: &str) -> Result<ApiKey, AuthError> {
+        let keys = self.api_keys.lock().unwrap();
+        keys.values()
+            .find(|k| k.key_hash == key_hash)
+            .cloned()
+            .ok_or(AuthError::InvalidToken)
+    }
+
+    pub async fn find_api_key_by_id(&self, id: Uuid) -> Result<ApiKey, AuthError> {
+        self.api_keys
+            .lock()
+            .unwrap()
+            .get(&id)
+            .cloned()
+            .ok_or_else(|| AuthError::ValidationError("API key not found".into()))
+    }
+
+    pub async fn record_api_key_request(
+        &self,
+        id: Uuid,
+        day: NaiveDate,
+        month_start: NaiveDate,
+    ) -> Result<(i64, i64), AuthError> {
+        if let Some(key) = self.api_keys.lock().unwrap().get_mut(&id) {
+            key.last_used_at = Some(Utc::now());
+        }
+
+        let mut usage = self.api_key_usage.lock().unwrap();
+        let daily = usage.entry((id, day)).or_insert(0);
+        *daily += 1;
+        let daily = *daily;
+
+        let monthly = usage
+            .iter()
+            .filter(|((key_id, d), _)| *key_id == id && *d >= month_start)
+            .map(|(_, count)| count)
+            .sum();
+
+        Ok((daily, monthly))
+    }
+
+    pub async fn find_api_key_usage(&self, id: Uuid, since: NaiveDate) -> Result<Vec<ApiKeyUsage>, AuthError> {
+        let usage = self.api_key_usage.lock().unwrap();
+        let mut days: Vec<ApiKeyUsage> = usage
+            .iter()
+            .filter(|((key_id, day), _)| *key_id == id && *day >= since)
+            .map(|((key_id, day), count)| ApiKeyUsage {
+                api_key_id: *key_id,
+                day: *day,
+                request_count: *count,
+            })
+            .collect();
+        days.sort_by(|a, b| b.day.cmp(&a.day));
+        Ok(days)
+    }
+
+    pub async fn update_api_key_quota(
+        &self,
+        id: Uuid,
+        daily_quota: Option<i64>,
+        monthly_quota: Option<i64>,
+    ) -> Result<ApiKey, AuthError> {
+        let mut keys = self.api_keys.lock().unwrap();
+        let key = keys
+            .get_mut(&id)
+            .ok_or_else(|| AuthError::ValidationError("API key not found".into()))?;
+        key.daily_quota = daily_quota;
+        key.monthly_quota = monthly_quota;
+        Ok(key.clone())
+    }
+
+    pub async fn delete_api_key(&self, id: Uuid) -> Result<(), AuthError> {
+        self.api_keys.lock().unwrap().remove(&id);
+        self.api_key_usage.lock().unwrap().retain(|(key_id, _), _| *key_id != id);
+        Ok(())
+    }
+
+    // Canary methods
+    pub async fn create_canary_credential(&self, canary: NewCanaryCredential) -> Result<CanaryCredential, AuthError> {
+        let mut canaries = self.canaries.lock().unwrap();
+
+        // Same as the unique indexes on `canary_credentials` in Postgres
+        if canaries.values().any(|c| {
+            c.user_id == canary.user_id || (canary.api_key_id.is_some() && c.api_key_id == canary.api_key_id)
+        }) {
+            return Err(AuthError::DatabaseError("Insert error: canary already exists".into()));
+        }
+
+        let canary = CanaryCredential {
+            id: canary.id,
+            user_id: canary.user_id,
+            api_key_id: canary.api_key_id,
+            label: canary.label,
+            trip_count: 0,
+            last_tripped_at: None,
+            last_tripped_ip: None,
+            created_at: Utc::now(),
+        };
+        canaries.insert(canary.id, canary.clone());
+
+        Ok(canary)
+    }
+
+    pub async fn find_canary_credentials(&self) -> Result<Vec<CanaryCredential>, AuthError> {
+        let canaries = self.canaries.lock().unwrap();
+        let mut canaries: Vec<CanaryCredential> = canaries.values().cloned().collect();
+        canaries.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+        Ok(canaries)
+    }
+
+    pub async fn find_canary_by_user_id(&self, user_id: Uuid) -> Result<Option<CanaryCredential>, AuthError> {
+        let canaries = self.canaries.lock().unwrap();
+        Ok(canaries.values().find(|c| c.user_id == user_id).cloned())
+    }
+
+    pub async fn find_canary_by_api_key_id(&self, api_key_id: Uuid) -> Result<Option<CanaryCredential>, AuthError> {
+        let canaries = self.canaries.lock().unwrap();
+        Ok(canaries.values().find(|c| c.api_key_id == Some(api_key_id)).cloned())
+    }
+
+    pub async fn record_canary_trip(&self, id: Uuid, ip: Option<String>) -> Result<CanaryCredential, AuthError> {
+        let mut canaries = self.canaries.lock().unwrap();
+        let canary = canaries
+            .get_mut(&id)
+            .ok_or_else(|| AuthError::DatabaseError("Update error: canary not found".into()))?;
+        canary.trip_count += 1;
+        canary.last_tripped_at = Some(Utc::now());
+        canary.last_tripped_ip = ip;
+        Ok(canary.clone())
+    }
+
+    pub async fn delete_canary_credential(&self, id: Uuid) -> Result<(), AuthError> {
+        self.canaries.lock().unwrap().remove(&id);
+        Ok(())
+    }
+
+    // Notification preference methods
+    pub async fn find_notification_preferences(
+        &self,
+        user_id: Uuid,
+    ) -> Result<Option<NotificationPreferences>, AuthError> {
+        Ok(self.notification_preferences.lock().unwrap().get(&user_id).cloned())
+    }
+
+    pub async fn save_notification_preferences(
+        &self,
+        preferences: NewNotificationPreferences,
+    ) -> Result<NotificationPreferences, AuthError> {
+        let preferences = NotificationPreferences {
+            user_id: preferences.user_id,
+            security_alerts: preferences.security_alerts,
+            product_emails: preferences.product_emails,
+            updated_at: Utc::now(),
+        };
+        self.notification_preferences
+            .lock()
+            .unwrap()
+            .insert(preferences.user_id, preferences.clone());
+
+        Ok(preferences)
+    }
+
+    // Feature flag methods
+    pub async fn save_feature_flag(&self, flag: NewFeatureFlag) -> Result<FeatureFlag, AuthError> {
+        let mut flags = self.feature_flags.lock().unwrap();
+        let now = Utc::now();
+
+        let flag = FeatureFlag {
+            created_at: flags.get(&flag.key).map(|f| f.created_at).unwrap_or(now),
+            key: flag.key,
+            description: flag.description,
+            enabled: flag.enabled,
+            rollout_percent: flag.rollout_percent,
+            organization_ids: flag.organization_ids,
+            updated_by: flag.updated_by,
+            updated_at: now,
+        };
+        flags.insert(flag.key.clone(), flag.clone());
+
+        Ok(flag)
+    }
+
+    pub async fn find_feature_flags(&self) -> Result<Vec<FeatureFlag>, AuthError> {
+        let flags = self.feature_flags.lock().unwrap();
+        let mut flags: Vec<FeatureFlag> = flags.values().cloned().collect();
+        flags.sort_by(|a, b| a.key.cmp(&b.key));
+        Ok(flags)
+    }
+
+    pub async fn delete_feature_flag(&self, key: &str) -> Result<bool, AuthError> {
+        Ok(self.feature_flags.lock().unwrap().remove(key).is_some())
+    }
+
+    // Client application methods
+    pub async fn save_client_application(&self, client: NewClientApplication) -> Result<ClientApplication, AuthError> {
+        let mut clients = self.client_applications.lock().unwrap();
+        let now = Utc::now();
+
+        let client = ClientApplication {
+            created_at: clients.get(&client.client_id).map(|c| c.created_at).unwrap_or(now),
+            client_id: client.client_id,
+            name: client.name,
+            redirect_uris: client.redirect_uris,
+            allowed_grants: client.allowed_grants,
+            access_token_ttl: client.access_token_ttl,
+            refresh_token_ttl: client.refresh_token_ttl,
+            logo_url: client.logo_url,
+            updated_by: client.updated_by,
+            updated_at: now,
+            first_party: client.first_party,
+            client_secret_hash: client.client_secret_hash,
+        };
+        clients.insert(client.client_id.clone(), client.clone());
+
+        Ok(client)
+    }
+
+    pub async fn find_client_application(&self, client_id: &str) -> Result<Option<ClientApplication>, AuthError> {
+        Ok(self.client_applications.lock().unwrap().get(client_id).cloned())
+    }
+
+    pub async fn find_client_applications(&self) -> Result<Vec<ClientApplication>, AuthError> {
+        let clients = self.client_applications.lock().unwrap();
+        let mut clients: Vec<ClientApplication> = clients.values().cloned().collect();
+        clients.sort_by(|a, b| a.client_id.cmp(&b.client_id));
+        Ok(clients)
+    }
+
+    pub async fn delete_client_application(&self, client_id: &str) -> Result<bool, AuthError> {
+        if self.client_applications.lock().unwrap().remove(client_id).is_none() {
+            return Ok(false);
+        }
+
+        // As the foreign keys do
+        for session in self.sessions.lock().unwrap().values_mut() {
+            if session.client_id.as_deref() == Some(client_id) {
+                session.client_id = None;
+            }
+        }
+        self.client_consents.lock().unwrap().retain(|(_, id), _| id != client_id);
+        Ok(true)
+    }
+
+    pub async fn revoke_client_sessions(&self, client_id: &str, user_id: Option<Uuid>) -> Result<Vec<Uuid>, AuthError> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let mut revoked = Vec::new();
+        for session in sessions.values_mut() {
+            let matches = session.client_id.as_deref() == Some(client_id)
+                && user_id.map_or(true, |user_id| session.user_id == user_id);
+            if matches && !session.is_revoked {
+                session.is_revoked = true;
+                session.updated_at = Utc::now();
+                revoked.push(session.id);
+            }
+        }
+        Ok(revoked)
+    }
+
+    pub async fn save_client_consent(&self, consent: NewClientConsent) -> Result<ClientConsent, AuthError> {
+        let mut consents = self.client_consents.lock().unwrap();
+        let key = (consent.user_id, consent.client_id.clone());
+        let now = Utc::now();
+
+        let consent = ClientConsent {
+            granted_at: consents.get(&key).map(|c| c.granted_at).unwrap_or(now),
+            user_id: consent.user_id,
+            client_id: consent.client_id,
+            scopes: consent.scopes,
+            updated_at: now,
+        };
+        consents.insert(key, consent.clone());
+
+        Ok(consent)
+    }
+
+    pub async fn find_client_consent(&self, user_id: Uuid, client_id: &str) -> Result<Option<ClientConsent>, AuthError> {
+        let consents = self.client_consents.lock().unwrap();
+        Ok(consents.get(&(user_id, client_id.to_string())).cloned())
+    }
+
+    pub async fn find_client_consents(&self, user_id: Uuid) -> Result<Vec<ClientConsent>, AuthError> {
+        let consents = self.client_consents.lock().unwrap();
+        let mut consents: Vec<ClientConsent> = consents.values().filter(|c| c.user_id == user_id).cloned().collect();
+        consents.sort_by(|a, b| a.client_id.cmp(&b.client_id));
+        Ok(consents)
+    }
+
+    pub async fn delete_client_consent(&self, user_id: Uuid, client_id: &str) -> Result<bool, AuthError> {
+        let mut consents = self.client_consents.lock().unwrap();
+        Ok(consents.remove(&(user_id, client_id.to_string())).is_some())
+    }
+
+    // Authenticator metadata methods
+    pub async fn replace_authenticator_metadata(
+        &self,
+        entries: Vec<NewAuthenticatorMetadata>,
+    ) -> Result<(), AuthError> {
+        let now = Utc::now();
+        let mut metadata = self.authenticator_metadata.lock().unwrap();
+        metadata.clear();
+        for entry in entries {
+            metadata.insert(
+                entry.aaguid,
+                AuthenticatorMetadata {
+                    aaguid: entry.aaguid,
+                    description: entry.description,
+                    certification_level: entry.certification_level,
+                    compromised: entry.compromised,
+                    blob_number: entry.blob_number,
+                    updated_at: now,
+                },
+            );
+        }
+        Ok(())
+    }
+
+    pub async fn find_authenticator_metadata(&self) -> Result<Vec<AuthenticatorMetadata>, AuthError> {
+        Ok(self.authenticator_metadata.lock().unwrap().values().cloned().collect())
+    }
+
+    // Login freeze methods
+    pub async fn set_login_freeze(&self, freeze: NewLoginFreeze) -> Result<LoginFreeze, AuthError> {
+        let mut freezes = self.login_freezes.lock().unwrap();
+        freezes.retain(|_, f| f.organization_id != freeze.organization_id);
+
+        let freeze = LoginFreeze {
+            id: freeze.id,
+            organization_id: freeze.organization_id,
+            message: freeze.message,
+            frozen_by: freeze.frozen_by,
+            created_at: Utc::now(),
+        };
+        freezes.insert(freeze.id, freeze.clone());
+
+        Ok(freeze)
+    }
+
+    pub async fn clear_login_freeze(&self, organization_id: Option<Uuid>) -> Result<bool, AuthError> {
+        let mut freezes = self.login_freezes.lock().unwrap();
+        let before = freezes.len();
+        freezes.retain(|_, f| f.organization_id != organization_id);
+        Ok(freezes.len() < before)
+    }
+
+    pub async fn find_login_freezes(&self) -> Result<Vec<LoginFreeze>, AuthError> {
+        let freezes = self.login_freezes.lock().unwrap();
+        let mut freezes: Vec<LoginFreeze> = freezes.values().cloned().collect();
+        freezes.sort_by(|a, b| {
+            (!a.is_global(), b.created_at).cmp(&(!b.is_global(), a.created_at))
+        });
+        Ok(freezes)
+    }
+
+    // Login policy methods
+    pub async fn find_login_policies(
+        &self,
+        user_id: Uuid,
+        organization_ids: Vec<Uuid>,
+    ) -> Result<Vec<LoginPolicy>, AuthError> {
+        let mut policies: Vec<LoginPolicy> = self
+            .login_policies
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|p| {
+                p.user_id == Some(user_id) || p.organization_id.map_or(false, |id| organization_ids.contains(&id))
+            })
+            .cloned()
+            .collect();
+        policies.sort_by(|a, b| {
+            (a.organization_id.is_some(), b.updated_at).cmp(&(b.organization_id.is_some(), a.updated_at))
+        });
+        Ok(policies)
+    }
+
+    pub async fn find_login_policy(&self, scope: LoginPolicyScope) -> Result<Option<LoginPolicy>, AuthError> {
+        let policies = self.login_policies.lock().unwrap();
+        Ok(policies.values().find(|p| scope.matches(p)).cloned())
+    }
+
+    pub async fn save_login_policy(&self, policy: NewLoginPolicy) -> Result<LoginPolicy, AuthError> {
+        let mut policies = self.login_policies.lock().unwrap();
+        policies.retain(|_, p| match policy.organization_id {
+            Some(organization_id) => p.organization_id != Some(organization_id),
+            None => p.user_id != policy.user_id,
+        });
+
+        let policy = LoginPolicy {
+            id: policy.id,
+            user_id: policy.user_id,
+            organization_id: policy.organization_id,
+            allowed_countries: policy.allowed_countries,
+            window_start: policy.window_start,
+            window_end: policy.window_end,
+            utc_offset_minutes: policy.utc_offset_minutes,
+            on_violation: policy.on_violation,
+            updated_by: policy.updated_by,
+            updated_at: Utc::now(),
+        };
+        policies.insert(policy.id, policy.clone());
+
+        Ok(policy)
+    }
+
+    pub async fn delete_login_policy(&self, scope: LoginPolicyScope) -> Result<bool, AuthError> {
+        let mut policies = self.login_policies.lock().unwrap();
+        let before = policies.len();
+        policies.retain(|_, p| !scope.matches(p));
+        Ok(policies.len() < before)
+    }
+
+    // Security question methods
+    pub async fn find_security_questions(&self, user_id: Uuid) -> Result<Vec<SecurityQuestion>, AuthError> {
+        let mut questions: Vec<SecurityQuestion> = self
+            .security_questions
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|q| q.user_id == user_id)
+            .cloned()
+            .collect();
+        questions.sort_by_key(|q| q.position);
+        Ok(questions)
+    }
+
+    pub async fn save_security_questions(
+        &self,
+        user_id: Uuid,
+        questions: Vec<NewSecurityQuestion>,
+    ) -> Result<Vec<SecurityQuestion>, AuthError> {
+        let mut stored = self.security_questions.lock().unwrap();
+        stored.retain(|_, q| q.user_id != user_id);
+
+        let mut saved: Vec<SecurityQuestion> = questions
+            .into_iter()
+            .map(|q| SecurityQuestion {
+                id: q.id,
+                user_id: q.user_id,
+                position: q.position,
+                question: q.question,
+                answer_hash: q.answer_hash,
+                created_at: Utc::now(),
+            })
+            .collect();
+        for question in &saved {
+            stored.insert(question.id, question.clone());
+        }
+        saved.sort_by_key(|q| q.position);
+
+        Ok(saved)
+    }
+
+    pub async fn delete_security_questions(&self, user_id: Uuid) -> Result<bool, AuthError> {
+        let mut questions = self.security_questions.lock().unwrap();
+        let before = questions.len();
+        questions.retain(|_, q| q.user_id != user_id);
+        Ok(questions.len() < before)
+    }
+
+    pub async fn find_security_question_failures(
+        &self,
+        user_id: Uuid,
+    ) -> Result<Option<SecurityQuestionFailures>, AuthError> {
+        Ok(self.security_question_failures.lock().unwrap().get(&user_id).cloned())
+    }
+
+    pub async fn record_security_question_failure(
+        &self,
+        user_id: Uuid,
+        window_start: DateTime<Utc>,
+    ) -> Result<i32, AuthError> {
+        let mut failures = self.security_question_failures.lock().unwrap();
+        let count = match failures.get(&user_id) {
+            Some(previous) if previous.last_failed_at >= window_start => previous.failure_count + 1,
+            _ => 1,
+        };
+        failures.insert(
+            user_id,
+            SecurityQuestionFailures {
+                user_id,
+                failure_count: count,
+                last_failed_at: Utc::now(),
+            },
+        );
+        Ok(count)
+    }
+
+    pub async fn clear_security_question_failures(&self, user_id: Uuid) -> Result<(), AuthError> {
+        self.security_question_failures.lock().unwrap().remove(&user_id);
+        Ok(())
+    }
+
+    pub async fn find_mfa_failures(
+        &self,
+        user_id: Uuid,
+    ) -> Result<Option<MfaFailures>, AuthError> {
+        Ok(self.mfa_failures.lock().unwrap().get(&user_id).cloned())
+    }
+
+    pub async fn record_mfa_failure(
+        &self,
+        user_id: Uuid,
+        window_start: DateTime<Utc>,
+    ) -> Result<i32, AuthError> {
+        let mut failures = self.mfa_failures.lock().unwrap();
+        let count = match failures.get(&user_id) {
+            Some(previous) if previous.last_failed_at >= window_start => previous.failure_count + 1,
+            _ => 1,
+        };
+        failures.insert(
+            user_id,
+            MfaFailures {
+                user_id,
+                failure_count: count,
+                last_failed_at: Utc::now(),
+            },
+        );
+        Ok(count)
+    }
+
+    pub async fn clear_mfa_failures(&self, user_id: Uuid) -> Result<(), AuthError> {
+        self.mfa_failures.lock().unwrap().remove(&user_id);
+        Ok(())
+    }
+
+    // Bulk job methods
+    pub async fn create_bulk_job(&self, job: NewBulkJob) -> Result<BulkJob, AuthError> {
+        let job = BulkJob {
+            id: job.id,
+            action: job.action,
+            params: job.params,
+            dry_run: job.dry_run,
+            status: BulkJobStatus::Queued.as_str().to_string(),
+            created_by: job.created_by,
+            created_at: Utc::now(),
+            started_at: None,
+            finished_at: None,
+            results: serde_json::json!([]),
+            error: None,
+        };
+        self.bulk_jobs.lock().unwrap().insert(job.id, job.clone());
+        Ok(job)
+    }
+
+    pub async fn find_bulk_job(&self, id: Uuid) -> Result<Option<BulkJob>, AuthError> {
+        Ok(self.bulk_jobs.lock().unwrap().get(&id).cloned())
+    }
+
+    pub async fn list_bulk_jobs(&self, limit: i64) -> Result<Vec<BulkJob>, AuthError> {
+        let mut jobs: Vec<BulkJob> = self.bulk_jobs.lock().unwrap().values().cloned().collect();
+        jobs.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+        jobs.truncate(limit.max(0) as usize);
+        Ok(jobs)
+    }
+
+    pub async fn claim_bulk_job(&self, stale_before: DateTime<Utc>) -> Result<Option<BulkJob>, AuthError> {
+        let mut jobs = self.bulk_jobs.lock().unwrap();
+        let job = jobs
+            .values_mut()
+            .filter(|job| match job.status() {
+                BulkJobStatus::Queued => true,
+                BulkJobStatus::Running => job.started_at.map_or(true, |started_at| started_at < stale_before),
+                _ => false,
+            })
+            .min_by_key(|job| job.created_at);
+
+        Ok(job.map(|job| {
+            job.status = BulkJobStatus::Running.as_str().to_string();
+            job.started_at = Some(Utc::now());
+            job.clone()
+        }))
+    }
+
+    pub async fn finish_bulk_job(
+        &self,
+        id: Uuid,
+        status: BulkJobStatus,
+        results: serde_json::Value,
+        error: Option<String>,
+    ) -> Result<(), AuthError> {
+        if let Some(job) = self.bulk_jobs.lock().unwrap().get_mut(&id) {
+            job.status = status.as_str().to_string();
+            job.results = results;
+            job.error = error;
+            job.finished_at = Some(Utc::now());
+        }
+        Ok(())
+    }
+
+    // User directory methods
+    pub async fn save_directory_entry(&self, entry: NewDirectoryEntry) -> Result<(), AuthError> {
+        let mut directory = self.user_directory.lock().unwrap();
+
+        // Same as the unique constraints on `user_directory` in Postgres
+        let taken = directory.values().any(|existing| {
+            existing.user_id != entry.user_id
+                && (existing.email_digest == entry.email_digest || existing.username_digest == entry.username_digest)
+        });
+        if taken {
+            return Err(AuthError::ValidationError("Username or email is already taken".into()));
+        }
+
+        let created_at = directory.get(&entry.user_id).map_or_else(Utc::now, |existing| existing.created_at);
+        directory.insert(
+            entry.user_id,
+            DirectoryEntry {
+                user_id: entry.user_id,
+                region: entry.region,
+                email_digest: entry.email_digest,
+                username_digest: entry.username_digest,
+                created_at,
+            },
+        );
+        Ok(())
+    }
+
+    pub async fn find_directory_entry(&self, user_id: Uuid) -> Result<Option<DirectoryEntry>, AuthError> {
+        Ok(self.user_directory.lock().unwrap().get(&user_id).cloned())
+    }
+
+    pub async fn find_directory_entry_by_digest(&self, digest: &str) -> Result<Option<DirectoryEntry>, AuthError> {
+        Ok(self
+            .user_directory
+            .lock()
+            .unwrap()
+            .values()
+            .find(|entry| entry.email_digest == digest || entry.username_digest == digest)
+            .cloned())
+    }
+
+    pub async fn delete_directory_entry(&self, user_id: Uuid) -> Result<(), AuthError> {
+        self.user_directory.lock().unwrap().remove(&user_id);
+        Ok(())
+    }
+
+    // Schema methods
+    pub async fn latest_migration(&self) -> Result<Option<String>, AuthError> {
+        // Always the schema this build was written against
+        Ok(Some(crate::db::SCHEMA_VERSION.to_string()))
+    }
+
+    // Trusted device methods
+    pub async fn create_trusted_device(&self, device: NewTrustedDevice) -> Result<TrustedDevice, AuthError> {
+        let device = TrustedDevice {
+            id: device.id,
+            user_id: device.user_id,
+            token_hash: device.token_hash,
+            name: device.name,
+            expires_at: device.expires_at,
+            last_used_at: None,
+            created_at: Utc::now(),
+        };
+        self.trusted_devices.lock().unwrap().insert(device.id, device.clone());
+
+        Ok(device)
+    }
+
+    pub async fn use_trusted_device(&self, user_id: Uuid, token_hash: &str) -> Result<Option<TrustedDevice>, AuthError> {
+        let now = Utc::now();
+        let mut devices = self.trusted_devices.lock().unwrap();
+        let device = devices
+            .values_mut()
+            .find(|d| d.user_id == user_id && d.token_hash == token_hash && d.expires_at > now);
+
+        Ok(device.map(|device| {
+            device.last_used_at = Some(now);
+            device.clone()
+        }))
+    }
+
+    pub async fn delete_trusted_devices_by_user_id(&self, user_id: Uuid) -> Result<(), AuthError> {
+        self.trusted_devices.lock().unwrap().retain(|_, d| d.user_id != user_id);
+        Ok(())
+    }
+
+    // Passkey methods
+    pub async fn create_passkey(&self, passkey: NewPasskey) -> Result<Passkey, AuthError> {
+        let mut passkeys = self.passkeys.lock().unwrap();
+
+        if passkeys.iter().any(|p| p.credential_id == passkey.credential_id) {
+            return Err(AuthError::DatabaseError("Insert error: credential is already registered".into()));
+        }
+
+        let passkey = Passkey {
+            id: passkeys.len() as i32 + 1,
+            user_id: passkey.user_id,
+            credential_id: passkey.credential_id,
+            public_key: passkey.public_key,
+            counter: passkey.counter,
+            created_at: passkey.created_at,
+            last_used_at: passkey.last_used_at,
+            device_name: passkey.device_name,
+            aaguid: passkey.aaguid,
+        };
+        passkeys.push(passkey.clone());
+
+        Ok(passkey)
+    }
+
+    pub async fn find_passkeys_by_user_id(&self, user_id: Uuid) -> Result<Vec<Passkey>, AuthError> {
+        let passkeys = self.passkeys.lock().unwrap();
+        let mut passkeys: Vec<Passkey> = passkeys.iter().filter(|p| p.user_id == user_id).cloned().collect();
+        passkeys.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+        Ok(passkeys)
+    }
+
+    // Proxy alias methods
+    pub async fn create_proxy_alias(&self, alias: NewProxyAlias) -> Result<ProxyAlias, AuthError> {
+        let mut aliases = self.proxy_aliases.lock().unwrap();
+
+        if aliases.values().any(|a| a.proxy_address == alias.proxy_address) {
+            return Err(AuthError::DatabaseError("Insert error: proxy address is taken".into()));
+        }
+
+        let alias = ProxyAlias {
+            id: alias.id,
+            user_id: alias.user_id,
+            proxy_address: alias.proxy_address,
+            label: alias.label,
+            status: PROXY_ALIAS_ACTIVE.to_string(),
+            forwarding_enabled: true,
+            created_at: Utc::now(),
+        };
+        aliases.insert(alias.id, alias.clone());
+
+        Ok(alias)
+    }
+
+    pub async fn find_proxy_aliases_by_user_id(&self, user_id: Uuid) -> Result<Vec<ProxyAlias>, AuthError> {
+        let aliases = self.proxy_aliases.lock().unwrap();
+        let mut aliases: Vec<ProxyAlias> = aliases
+            .values()
+            .filter(|a| a.user_id == user_id && a.status != PROXY_ALIAS_DELETED)
+            .cloned()
+            .collect();
+        aliases.sort_by(|a, b| a.created_at.cmp(&b.created_at));
+        Ok(aliases)
+    }
+
+    // Unit of work
+    /// Apply the writes to copies of the tables they touch, swapping the
+    /// copies in only once every write has succeeded
+    pub async fn commit(&self, work: UnitOfWork) -> Result<(), AuthError> {
+        let mut users = self.users.lock().unwrap();
+        let mut sessions = self.sessions.lock().unwrap();
+        let mut staged_users = users.clone();
+        let mut staged_sessions = sessions.clone();
+        let mut events = Vec::new();
+        let now = Utc::now();
+
+        for write in work.into_writes() {
+            match write {
+                Write::UpdatePassword { user_id, password_hash, expires_at } => {
+                    let user = staged_users.get_mut(&user_id).ok_or(AuthError::UserNotFound)?;
+                    user.password_hash = password_hash;
+                    user.password_reset_token = None;
+                    user.password_reset_sent_at = None;
+                    user.password_reset_email = None;
+                    user.password_expires_at = expires_at;
+                    user.updated_at = now;
+                }
+                Write::CreateSession(session) => {
+                    let session = Self::new_session(session, now);
+                    staged_sessions.insert(session.id, session);
+                }
+                Write::RevokeSession(id) => {
+                    let session = staged_sessions
+                        .get_mut(&id)
+                        .filter(|session| !session.is_revoked)
+                        .ok_or(AuthError::InvalidToken)?;
+                    session.is_revoked = true;
+                    session.updated_at = now;
+                }
+                Write::RevokeAllSessions { user_id, include_pinned } => {
+                    for session in staged_sessions.values_mut() {
+                        if session.user_id == user_id && (include_pinned || !session.is_pinned) {
+                            session.is_revoked = true;
+                            session.updated_at = now;
+                        }
+                    }
+                }
+                Write::Event(event) => events.push(event),
+            }
+        }
+
+        *users = staged_users;
+        *sessions = staged_sessions;
+        for event in events {
+            self.enqueue_event(event);
+        }
+        Ok(())
+    }
+
+    // Outbox methods
+    pub async fn claim_outbox_events(
+        &self,
+        limit: i64,
+        max_attempts: i32,
+        lease_until: DateTime<Utc>,
+    ) -> Result<Vec<OutboxEvent>, AuthError> {
+        let now = Utc::now();
+        let mut outbox = self.outbox.lock().unwrap();
+        let mut due: Vec<&mut OutboxEvent> = outbox
+            .values_mut()
+            .filter(|e| e.delivered_at.is_none() && e.next_attempt_at <= now && e.attempts < max_attempts)
+            .collect();
+        due.sort_by(|a, b| a.created_at.cmp(&b.created_at));
+
+        Ok(due
+            .into_iter()
+            .take(limit.max(0) as usize)
+            .map(|event| {
+                event.next_attempt_at = lease_until;
+                event.clone()
+            })
+            .collect())
+    }
+
+    pub async fn mark_outbox_event_delivered(&self, id: Uuid) -> Result<(), AuthError> {
+        if let Some(event) = self.outbox.lock().unwrap().get_mut(&id) {
+            event.delivered_at = Some(Utc::now());
+            event.attempts += 1;
+            event.last_error = None;
+        }
+        Ok(())
+    }
+
+    pub async fn record_outbox_event_failure(
+        &self,
+        id: Uuid,
+        error: &str,
+        retry_at: DateTime<Utc>,
+    ) -> Result<(), AuthError> {
+        if let Some(event) = self.outbox.lock().unwrap().get_mut(&id) {
+            event.attempts += 1;
+            event.last_error = Some(error.to_string());
+            event.next_attempt_at = retry_at;
+        }
+        Ok(())
+    }
+
+    pub async fn find_delivered_outbox_events(&self, before: DateTime<Utc>, limit: i64) -> Result<Vec<OutboxEvent>, AuthError> {
+        let outbox = self.outbox.lock().unwrap();
+        let mut events: Vec<OutboxEvent> = outbox
+            .values()
+            .filter(|e| e.delivered_at.is_some() && e.created_at < before)
+            .cloned()
+            .collect();
+        events.sort_by(|a, b| a.created_at.cmp(&b.created_at).then_with(|| a.id.cmp(&b.id)));
+        events.truncate(limit.max(0) as usize);
+        Ok(events)
+    }
+
+    pub async fn find_outbox_events(
+        &self,
+        filter: &AuditEventFilter,
+        page: &PageRequest,
+    ) -> Result<(Vec<OutboxEvent>, i64), AuthError> {
+        let outbox = self.outbox.lock().unwrap();
+        let mut events: Vec<OutboxEvent> = outbox.values().filter(|e| filter.matches(e)).cloned().collect();
+
+        // Ties are broken by id so pages stay stable
+        events.sort_by(|a, b| {
+            let ordering = a.created_at.cmp(&b.created_at).then_with(|| a.id.cmp(&b.id));
+            match page.order {
+                SortOrder::Asc => ordering,
+                SortOrder::Desc => ordering.reverse(),
+            }
+        });
+
+        let total = events.len() as i64;
+        let events = events
+            .into_iter()
+            .skip(page.offset as usize)
+            .take(page.limit as usize)
+            .collect();
+        Ok((events, total))
+    }
+
+    pub async fn delete_outbox_events(&self, ids: Vec<Uuid>) -> Result<usize, AuthError> {
+        let mut outbox = self.outbox.lock().unwrap();
+        Ok(ids.iter().filter(|id| outbox.remove(id).is_some()).count())
+    }
+
+    fn enqueue_event(&self, event: NewOutboxEvent) {
+        let now = Utc::now();
+        let event = OutboxEvent {
+            id: event.id,
+            event_type: event.event_type,
+            aggregate_id: event.aggregate_id,
+            payload: event.payload,
+            attempts: 0,
+            last_error: None,
+            next_attempt_at: now,
+            delivered_at: None,
+            created_at: now,
+        };
+        self.outbox.lock().unwrap().insert(event.id, event);
+    }
+
+    fn new_session(session: NewSession, now: DateTime<Utc>) -> Session {
+        Session {
+            id: session.id,
+            user_id: session.user_id,
+            refresh_token: session.refresh_token,
+            user_agent: session.user_agent,
+            ip_address: session.ip_address,
+            expires_at: session.expires_at,
+            created_at: now,
+            updated_at: now,
+            is_revoked: false,
+            browser: session.browser,
+            os: session.os,
+            device_class: session.device_class,
+            name: session.name,
+            is_pinned: session.is_pinned,
+            dpop_jkt: session.dpop_jkt,
+            last_seen_at: now,
+            network_country: session.network_country,
+            network_asn: session.network_asn,
+            client_id: session.client_id,
+            scopes: session.scopes,
+            amr: session.amr,
+            lifetime: session.lifetime,
+        }
+    }
+
+    fn empty_passkey_prompt(user_id: Uuid) -> PasskeyPromptState {
+        PasskeyPromptState {
+            user_id,
+            prompt_count: 0,
+            last_prompted_at: None,
+            dismissed_at: None,
+        }
+    }
+}
+
+// A write based on an older read of the user is refused, as in Postgres
+fn check_version(user: &User, expected_version: Option<i32>) -> Result<(), AuthError> {
+    match expected_version {
+        Some(version) if version != user.version => Err(AuthError::VersionConflict),
+        _ => Ok(()),
+    }
+}
+
+// Usernames and emails match regardless of case, like the `LOWER()` indexes in Postgres
+fn same_identifier(a: &str, b: &str) -> bool {
+    a.to_lowercase() == b.to_lowercase()
+}
+
+// A user id matches exactly; anything else matches part of the username,
+// email or display name
+fn matches_search(user: &User, term: &str) -> bool {
+    if let Ok(id) = term.parse::<Uuid>() {
+        return user.id == id;
+    }
+
+    let term = term.to_lowercase();
+    [Some(&user.username), Some(&user.email), user.display_name.as_ref()]
+        .into_iter()
+        .flatten()
+        .any(|value| value.to_lowercase().contains(&term))
 }
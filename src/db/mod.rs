@@ -1,240 +1,1866 @@
 pub mod memory;
 pub mod postgres;
+mod unit_of_work;
 
 use diesel::r2d2::{ConnectionManager, Pool, PooledConnection};
 use diesel::PgConnection;
 use r2d2::Error as R2D2Error;
+use std::collections::HashMap;
+use std::future::Future;
 use std::sync::Arc;
 
 use crate::config::Config;
 use crate::errors::AuthError;
+use crate::utils::validation::canonical_email;
+
+pub use unit_of_work::UnitOfWork;
 
 pub type PgPool = Pool<ConnectionManager<PgConnection>>;
 pub type PgPooledConnection = PooledConnection<ConnectionManager<PgConnection>>;
 
+/// The newest migration this build expects, as diesel records it in
+/// `__diesel_schema_migrations`: the directory name's date and number
+/// without the dashes
+pub const SCHEMA_VERSION: &str = "20231010000055";
+
 pub enum Database {
     Postgres(postgres::PostgresDb),
     Memory(memory::MemoryDb),
 }
 
+tokio::task_local! {
+    // The region whose database the current request's storage goes to
+    static CURRENT_REGION: String;
+}
+
 pub struct DatabaseConnection {
-    db: Database,
+    db: Database, // The home region's, when there are regions
+    regions: Option<Regions>,
+}
+
+// Users kept in the database of the region they signed up in. Only the
+// directory of which region each user is in is shared, in the home region's.
+struct Regions {
+    home: String,
+    databases: HashMap<String, Database>, // Besides the home region's
 }
 
 impl DatabaseConnection {
     pub fn new_postgres(pool: PgPool) -> Self {
         Self {
             db: Database::Postgres(postgres::PostgresDb::new(pool)),
+            regions: None,
         }
     }
 
     pub fn new_memory() -> Self {
         Self {
             db: Database::Memory(memory::MemoryDb::new()),
+            regions: None,
+        }
+    }
+
+    /// Keep each region's users in its own database, this one being the
+    /// home region's. Storage goes to the database of the region set with
+    /// `in_region`, or the home region's outside of one.
+    pub fn with_regions(mut self, home: &str, regions: HashMap<String, DatabaseConnection>) -> Self {
+        self.regions = Some(Regions {
+            home: home.to_string(),
+            databases: regions.into_iter().map(|(region, connection)| (region, connection.db)).collect(),
+        });
+        self
+    }
+
+    /// Run `fut` with storage going to `region`'s database; the home
+    /// region's for `None`. Requests for a user are run in their region.
+    pub async fn in_region<F: Future>(region: Option<String>, fut: F) -> F::Output {
+        match region {
+            Some(region) => CURRENT_REGION.scope(region, fut).await,
+            None => fut.await,
+        }
+    }
+
+    /// The region set with `in_region` for the running task
+    pub fn current_region() -> Option<String> {
+        CURRENT_REGION.try_with(|region| region.clone()).ok()
+    }
+
+    /// Whether users are kept apart by region
+    pub fn is_regional(&self) -> bool {
+        self.regions.is_some()
+    }
+
+    /// Every region, for background jobs to go through one at a time; a
+    /// single `None` when users aren't kept apart by region
+    pub fn regions(&self) -> Vec<Option<String>> {
+        match &self.regions {
+            Some(regions) => std::iter::once(&regions.home)
+                .chain(regions.databases.keys())
+                .map(|region| Some(region.clone()))
+                .collect(),
+            None => vec![None],
+        }
+    }
+
+    pub fn has_region(&self, region: &str) -> bool {
+        match &self.regions {
+            Some(regions) => region == regions.home || regions.databases.contains_key(region),
+            None => false,
+        }
+    }
+
+    // Where storage goes: the current region's database, if it has one
+    fn db(&self) -> &Database {
+        self.regions
+            .as_ref()
+            .and_then(|regions| {
+                CURRENT_REGION
+                    .try_with(|region| regions.databases.get(region))
+                    .ok()
+                    .flatten()
+            })
+            .unwrap_or(&self.db)
+    }
+
+    // The region `db()` stores in
+    fn storage_region(&self) -> Option<String> {
+        let regions = self.regions.as_ref()?;
+        Some(
+            Self::current_region()
+                .filter(|region| regions.databases.contains_key(region))
+                .unwrap_or_else(|| regions.home.clone()),
+        )
+    }
+
+    /// The region `user_id` is in, when users are kept apart by region
+    pub async fn region_of_user(&self, user_id: uuid::Uuid) -> Result<Option<String>, AuthError> {
+        if !self.is_regional() {
+            return Ok(None);
         }
+        Ok(self.find_directory_entry(user_id).await?.map(|entry| entry.region))
+    }
+
+    /// The region of the account with this email or username, when users
+    /// are kept apart by region
+    pub async fn region_of_identifier(&self, username_or_email: &str) -> Result<Option<String>, AuthError> {
+        if !self.is_regional() {
+            return Ok(None);
+        }
+        let digest = crate::models::directory_digest(username_or_email);
+        Ok(self.find_directory_entry_by_digest(&digest).await?.map(|entry| entry.region))
     }
 
     // User methods
-    pub async fn create_user(&self, user: crate::models::NewUser) -> Result<crate::models::User, AuthError> {
-        match &self.db {
-            Database::Postgres(db) => db.create_user(user).await,
-            Database::Memory(db) => db.create_user(user).await,
+    /// Create a user, writing `event` to the outbox in the same transaction
+    pub async fn create_user(
+        &self,
+        user: crate::models::NewUser,
+        event: crate::models::NewOutboxEvent,
+    ) -> Result<crate::models::User, AuthError> {
+        let region = match self.storage_region() {
+            Some(region) => region,
+            None => {
+                return match self.db() {
+                    Database::Postgres(db) => db.create_user(user, event).await,
+                    Database::Memory(db) => db.create_user(user, event).await,
+                }
+            }
+        };
+
+        // Listed first, so the same email or username can't be taken in
+        // two regions at once
+        let user_id = user.id;
+        self.save_directory_entry(crate::models::NewDirectoryEntry::new(user_id, &region, &user.username, &user.email))
+            .await?;
+
+        let created = match self.db() {
+            Database::Postgres(db) => db.create_user(user, event).await,
+            Database::Memory(db) => db.create_user(user, event).await,
+        };
+        if created.is_err() {
+            self.delete_directory_entry(user_id).await?;
         }
+        created
     }
 
     pub async fn find_user_by_id(&self, id: uuid::Uuid) -> Result<crate::models::User, AuthError> {
-        match &self.db {
+        match self.db() {
             Database::Postgres(db) => db.find_user_by_id(id).await,
             Database::Memory(db) => db.find_user_by_id(id).await,
         }
     }
 
     pub async fn find_user_by_username(&self, username: &str) -> Result<crate::models::User, AuthError> {
-        match &self.db {
+        match self.db() {
             Database::Postgres(db) => db.find_user_by_username(username).await,
             Database::Memory(db) => db.find_user_by_username(username).await,
         }
     }
 
+    // Addresses are looked up by their canonical form, however they were typed
     pub async fn find_user_by_email(&self, email: &str) -> Result<crate::models::User, AuthError> {
-        match &self.db {
-            Database::Postgres(db) => db.find_user_by_email(email).await,
-            Database::Memory(db) => db.find_user_by_email(email).await,
-        }
-    }
-
-    pub async fn find_user_by_username_or_email(&self, username_or_email: &str) -> Result<crate::models::User, AuthError> {
-        match &self.db {
-            Database::Postgres(db) => db.find_user_by_username_or_email(username_or_email).await,
-            Database::Memory(db) => db.find_user_by_username_or_email(username_or_email).await,
+        let email = canonical_email(email);
+        match self.db() {
+            Database::Postgres(db) => db.find_user_by_email(&email).await,
+            Database::Memory(db) => db.find_user_by_email(&email).await,
         }
     }
 
-    pub async fn find_user_by_verification_token(&self, token: &str) -> Result<crate::models::User, AuthError> {
-        match &self.db {
-            Database::Postgres(db) => db.find_user_by_verification_token(token).await,
-            Database::Memory(db) => db.find_user_by_verification_token(token).await,
+    /// Every account whose primary address is at `domain`, given in lower case
+    pub async fn find_users_by_email_domain(&self, domain: &str) -> Result<Vec<crate::models::User>, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.find_users_by_email_domain(domain).await,
+            Database::Memory(db) => db.find_users_by_email_domain(domain).await,
         }
     }
 
-    pub async fn find_user_by_reset_token(&self, token: &str) -> Result<crate::models::User, AuthError> {
-        match &self.db {
-            Database::Postgres(db) => db.find_user_by_reset_token(token).await,
-            Database::Memory(db) => db.find_user_by_reset_token(token).await,
+    pub async fn find_user_by_username_or_email(&self, username_or_email: &str) -> Result<crate::models::User, AuthError> {
+        let username_or_email = canonical_email(username_or_email);
+        match self.db() {
+            Database::Postgres(db) => db.find_user_by_username_or_email(&username_or_email).await,
+            Database::Memory(db) => db.find_user_by_username_or_email(&username_or_email).await,
         }
     }
 
+    // Usernames and emails are unique across regions, so with regions these
+    // ask the directory
     pub async fn user_exists_by_username(&self, username: &str) -> Result<bool, AuthError> {
-        match &self.db {
+        if self.is_regional() {
+            return Ok(self.region_of_identifier(username).await?.is_some());
+        }
+        match self.db() {
             Database::Postgres(db) => db.user_exists_by_username(username).await,
             Database::Memory(db) => db.user_exists_by_username(username).await,
         }
     }
 
     pub async fn user_exists_by_email(&self, email: &str) -> Result<bool, AuthError> {
-        match &self.db {
-            Database::Postgres(db) => db.user_exists_by_email(email).await,
-            Database::Memory(db) => db.user_exists_by_email(email).await,
+        if self.is_regional() {
+            return Ok(self.region_of_identifier(email).await?.is_some());
+        }
+        let email = canonical_email(email);
+        match self.db() {
+            Database::Postgres(db) => db.user_exists_by_email(&email).await,
+            Database::Memory(db) => db.user_exists_by_email(&email).await,
+        }
+    }
+
+    // One page of the users matching `query` and `filter`, plus the total.
+    // `query` matches any part of the username, email or display name, or is
+    // a user id.
+    pub async fn search_users(
+        &self,
+        query: Option<&str>,
+        filter: &crate::models::UserFilter,
+        page: &crate::models::PageRequest,
+    ) -> Result<(Vec<crate::models::User>, i64), AuthError> {
+        let query = query.map(str::trim).filter(|q| !q.is_empty());
+        match self.db() {
+            Database::Postgres(db) => db.search_users(query, filter, page).await,
+            Database::Memory(db) => db.search_users(query, filter, page).await,
         }
     }
 
     pub async fn update_last_login(&self, id: uuid::Uuid) -> Result<(), AuthError> {
-        match &self.db {
+        match self.db() {
             Database::Postgres(db) => db.update_last_login(id).await,
             Database::Memory(db) => db.update_last_login(id).await,
         }
     }
 
-    pub async fn update_verification_token(&self, id: uuid::Uuid, token: &str) -> Result<(), AuthError> {
-        match &self.db {
-            Database::Postgres(db) => db.update_verification_token(id, token).await,
-            Database::Memory(db) => db.update_verification_token(id, token).await,
+    // `sent_to` records which of the user's addresses the reset link went to
+    pub async fn update_password(
+        &self,
+        id: uuid::Uuid,
+        password_hash: &str,
+        expires_at: Option<chrono::DateTime<chrono::Utc>>,
+        event: crate::models::NewOutboxEvent,
+    ) -> Result<(), AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.update_password(id, password_hash, expires_at, event).await,
+            Database::Memory(db) => db.update_password(id, password_hash, expires_at, event).await,
         }
     }
 
-    pub async fn update_password_reset_token(&self, id: uuid::Uuid, token: &str) -> Result<(), AuthError> {
-        match &self.db {
-            Database::Postgres(db) => db.update_password_reset_token(id, token).await,
-            Database::Memory(db) => db.update_password_reset_token(id, token).await,
+    /// Expire the passwords of the given users, or of every user when `None`,
+    /// returning the ids that were affected
+    pub async fn expire_passwords(&self, user_ids: Option<Vec<uuid::Uuid>>) -> Result<Vec<uuid::Uuid>, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.expire_passwords(user_ids).await,
+            Database::Memory(db) => db.expire_passwords(user_ids).await,
         }
     }
 
-    pub async fn update_password(&self, id: uuid::Uuid, password_hash: &str) -> Result<(), AuthError> {
-        match &self.db {
-            Database::Postgres(db) => db.update_password(id, password_hash).await,
-            Database::Memory(db) => db.update_password(id, password_hash).await,
+    pub async fn bump_token_version(&self, id: uuid::Uuid) -> Result<i32, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.bump_token_version(id).await,
+            Database::Memory(db) => db.bump_token_version(id).await,
         }
     }
 
-    pub async fn verify_email(&self, id: uuid::Uuid) -> Result<crate::models::User, AuthError> {
-        match &self.db {
-            Database::Postgres(db) => db.verify_email(id).await,
-            Database::Memory(db) => db.verify_email(id).await,
+    /// Archive up to `limit` registered accounts nobody has signed in to (or,
+    /// never used, created) since `inactive_before`: sign them out everywhere
+    /// and record a `user.archived` event for each. Returns their ids.
+    pub async fn archive_inactive_users(
+        &self,
+        inactive_before: chrono::DateTime<chrono::Utc>,
+        limit: i64,
+    ) -> Result<Vec<uuid::Uuid>, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.archive_inactive_users(inactive_before, limit).await,
+            Database::Memory(db) => db.archive_inactive_users(inactive_before, limit).await,
         }
     }
 
-    pub async fn update_mfa_secret(&self, id: uuid::Uuid, secret: &str) -> Result<(), AuthError> {
-        match &self.db {
-            Database::Postgres(db) => db.update_mfa_secret(id, secret).await,
-            Database::Memory(db) => db.update_mfa_secret(id, secret).await,
+    /// Bring an archived account back. Fails with `UserNotFound` unless it's archived.
+    pub async fn reactivate_user(
+        &self,
+        id: uuid::Uuid,
+        event: crate::models::NewOutboxEvent,
+    ) -> Result<crate::models::User, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.reactivate_user(id, event).await,
+            Database::Memory(db) => db.reactivate_user(id, event).await,
         }
     }
 
-    pub async fn enable_mfa(&self, id: uuid::Uuid) -> Result<(), AuthError> {
-        match &self.db {
-            Database::Postgres(db) => db.enable_mfa(id).await,
-            Database::Memory(db) => db.enable_mfa(id).await,
+    pub async fn activate_user(
+        &self,
+        id: uuid::Uuid,
+        password_hash: &str,
+        expires_at: Option<chrono::DateTime<chrono::Utc>>,
+        event: crate::models::NewOutboxEvent,
+    ) -> Result<crate::models::User, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.activate_user(id, password_hash, expires_at, event).await,
+            Database::Memory(db) => db.activate_user(id, password_hash, expires_at, event).await,
         }
     }
 
-    pub async fn disable_mfa(&self, id: uuid::Uuid) -> Result<crate::models::User, AuthError> {
-        match &self.db {
-            Database::Postgres(db) => db.disable_mfa(id).await,
-            Database::Memory(db) => db.disable_mfa(id).await,
+    pub async fn verify_email(
+        &self,
+        id: uuid::Uuid,
+        event: crate::models::NewOutboxEvent,
+    ) -> Result<crate::models::User, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.verify_email(id, event).await,
+            Database::Memory(db) => db.verify_email(id, event).await,
+        }
+    }
+
+    // The writes below bump the user's `version`. Given an `expected_version`
+    // they fail with `VersionConflict` if the user has changed since then.
+    pub async fn update_profile(
+        &self,
+        id: uuid::Uuid,
+        expected_version: Option<i32>,
+        changes: crate::models::ProfileChanges,
+    ) -> Result<crate::models::User, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.update_profile(id, expected_version, changes).await,
+            Database::Memory(db) => db.update_profile(id, expected_version, changes).await,
+        }
+    }
+
+    pub async fn upgrade_guest_user(
+        &self,
+        id: uuid::Uuid,
+        upgrade: crate::models::GuestUpgrade,
+        event: crate::models::NewOutboxEvent,
+    ) -> Result<crate::models::User, AuthError> {
+        let region = match self.storage_region() {
+            Some(region) => region,
+            None => {
+                return match self.db() {
+                    Database::Postgres(db) => db.upgrade_guest_user(id, upgrade, event).await,
+                    Database::Memory(db) => db.upgrade_guest_user(id, upgrade, event).await,
+                }
+            }
+        };
+
+        // The new identifiers are claimed in the directory first, as when
+        // creating a user, and handed back if the upgrade fails
+        let previous = self.find_directory_entry(id).await?;
+        self.save_directory_entry(crate::models::NewDirectoryEntry::new(id, &region, &upgrade.username, &upgrade.email))
+            .await?;
+
+        let upgraded = match self.db() {
+            Database::Postgres(db) => db.upgrade_guest_user(id, upgrade, event).await,
+            Database::Memory(db) => db.upgrade_guest_user(id, upgrade, event).await,
+        };
+        if upgraded.is_err() {
+            match previous {
+                Some(previous) => {
+                    self.save_directory_entry(crate::models::NewDirectoryEntry {
+                        user_id: previous.user_id,
+                        region: previous.region,
+                        email_digest: previous.email_digest,
+                        username_digest: previous.username_digest,
+                    })
+                    .await?
+                }
+                None => self.delete_directory_entry(id).await?,
+            }
+        }
+        upgraded
+    }
+
+    pub async fn update_mfa_secret(&self, id: uuid::Uuid, expected_version: Option<i32>, secret: &str) -> Result<(), AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.update_mfa_secret(id, expected_version, secret).await,
+            Database::Memory(db) => db.update_mfa_secret(id, expected_version, secret).await,
+        }
+    }
+
+    pub async fn enable_mfa(&self, id: uuid::Uuid, expected_version: Option<i32>) -> Result<(), AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.enable_mfa(id, expected_version).await,
+            Database::Memory(db) => db.enable_mfa(id, expected_version).await,
+        }
+    }
+
+    pub async fn disable_mfa(&self, id: uuid::Uuid, expected_version: Option<i32>) -> Result<crate::models::User, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.disable_mfa(id, expected_version).await,
+            Database::Memory(db) => db.disable_mfa(id, expected_version).await,
         }
     }
 
     // Session methods
     pub async fn create_session(&self, session: crate::models::NewSession) -> Result<crate::models::Session, AuthError> {
-        match &self.db {
+        match self.db() {
             Database::Postgres(db) => db.create_session(session).await,
             Database::Memory(db) => db.create_session(session).await,
         }
     }
 
     pub async fn find_session_by_id(&self, id: uuid::Uuid) -> Result<crate::models::Session, AuthError> {
-        match &self.db {
+        match self.db() {
             Database::Postgres(db) => db.find_session_by_id(id).await,
             Database::Memory(db) => db.find_session_by_id(id).await,
         }
     }
 
     pub async fn find_session_by_token(&self, token: &str) -> Result<crate::models::Session, AuthError> {
-        match &self.db {
+        match self.db() {
             Database::Postgres(db) => db.find_session_by_token(token).await,
             Database::Memory(db) => db.find_session_by_token(token).await,
         }
     }
 
-    pub async fn find_sessions_by_user_id(&self, user_id: uuid::Uuid) -> Result<Vec<crate::models::Session>, AuthError> {
-        match &self.db {
-            Database::Postgres(db) => db.find_sessions_by_user_id(user_id).await,
-            Database::Memory(db) => db.find_sessions_by_user_id(user_id).await,
+    // One page of the user's unrevoked sessions, plus the total matching `filter`
+    pub async fn find_sessions_by_user_id(
+        &self,
+        user_id: uuid::Uuid,
+        filter: &crate::models::SessionFilter,
+        page: &crate::models::PageRequest,
+    ) -> Result<(Vec<crate::models::Session>, i64), AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.find_sessions_by_user_id(user_id, filter, page).await,
+            Database::Memory(db) => db.find_sessions_by_user_id(user_id, filter, page).await,
+        }
+    }
+
+    /// Every device the user has had a session on, revoked and expired ones
+    /// included until they're purged
+    pub async fn find_session_devices(
+        &self,
+        user_id: uuid::Uuid,
+    ) -> Result<Vec<crate::utils::user_agent::DeviceInfo>, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.find_session_devices(user_id).await,
+            Database::Memory(db) => db.find_session_devices(user_id).await,
         }
     }
 
     pub async fn revoke_session(&self, id: uuid::Uuid) -> Result<(), AuthError> {
-        match &self.db {
+        match self.db() {
             Database::Postgres(db) => db.revoke_session(id).await,
             Database::Memory(db) => db.revoke_session(id).await,
         }
     }
 
-    pub async fn revoke_all_sessions(&self, user_id: uuid::Uuid) -> Result<(), AuthError> {
+    pub async fn update_session(&self, id: uuid::Uuid, changes: crate::models::SessionChanges) -> Result<crate::models::Session, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.update_session(id, changes).await,
+            Database::Memory(db) => db.update_session(id, changes).await,
+        }
+    }
+
+    // Pinned sessions survive unless `include_pinned` is set
+    pub async fn revoke_all_sessions(&self, user_id: uuid::Uuid, include_pinned: bool) -> Result<(), AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.revoke_all_sessions(user_id, include_pinned).await,
+            Database::Memory(db) => db.revoke_all_sessions(user_id, include_pinned).await,
+        }
+    }
+
+    // Record that an unrevoked session was used at `seen_at`
+    pub async fn touch_session(&self, id: uuid::Uuid, seen_at: chrono::DateTime<chrono::Utc>) -> Result<(), AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.touch_session(id, seen_at).await,
+            Database::Memory(db) => db.touch_session(id, seen_at).await,
+        }
+    }
+
+    /// Delete up to `limit` sessions revoked or expired before `ended_before`,
+    /// returning how many
+    pub async fn purge_sessions(
+        &self,
+        ended_before: chrono::DateTime<chrono::Utc>,
+        limit: i64,
+    ) -> Result<usize, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.purge_sessions(ended_before, limit).await,
+            Database::Memory(db) => db.purge_sessions(ended_before, limit).await,
+        }
+    }
+
+    // Revocations hold nothing but session ids, and every instance polls for
+    // them, so with regions they're kept in the home region's database
+    pub async fn create_token_revocations(
+        &self,
+        revocations: Vec<crate::models::NewTokenRevocation>,
+    ) -> Result<(), AuthError> {
         match &self.db {
-            Database::Postgres(db) => db.revoke_all_sessions(user_id).await,
-            Database::Memory(db) => db.revoke_all_sessions(user_id).await,
+            Database::Postgres(db) => db.create_token_revocations(revocations).await,
+            Database::Memory(db) => db.create_token_revocations(revocations).await,
         }
     }
 
-    // MFA Recovery codes methods
-    pub async fn create_recovery_code(&self, code: crate::models::NewMfaRecoveryCode) -> Result<crate::models::MfaRecoveryCode, AuthError> {
+    pub async fn find_token_revocations(
+        &self,
+        since: chrono::DateTime<chrono::Utc>,
+    ) -> Result<Vec<crate::models::TokenRevocation>, AuthError> {
         match &self.db {
-            Database::Postgres(db) => db.create_recovery_code(code).await,
-            Database::Memory(db) => db.create_recovery_code(code).await,
+            Database::Postgres(db) => db.find_token_revocations(since).await,
+            Database::Memory(db) => db.find_token_revocations(since).await,
+        }
+    }
+
+    pub async fn session_table_stats(
+        &self,
+        now: chrono::DateTime<chrono::Utc>,
+    ) -> Result<crate::models::SessionTableStats, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.session_table_stats(now).await,
+            Database::Memory(db) => db.session_table_stats(now).await,
+        }
+    }
+
+    // Revoke the user's sessions last seen before `idle_since`, returning how many
+    pub async fn revoke_idle_sessions(&self, user_id: uuid::Uuid, idle_since: chrono::DateTime<chrono::Utc>) -> Result<usize, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.revoke_idle_sessions(user_id, idle_since).await,
+            Database::Memory(db) => db.revoke_idle_sessions(user_id, idle_since).await,
+        }
+    }
+
+    // MFA Recovery codes methods
+    /// Save a set of recovery codes in one statement
+    pub async fn create_recovery_codes(
+        &self,
+        codes: Vec<crate::models::NewMfaRecoveryCode>,
+    ) -> Result<Vec<crate::models::MfaRecoveryCode>, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.create_recovery_codes(codes).await,
+            Database::Memory(db) => db.create_recovery_codes(codes).await,
         }
     }
 
     pub async fn use_recovery_code(&self, user_id: uuid::Uuid, code: &str) -> Result<bool, AuthError> {
-        match &self.db {
+        match self.db() {
             Database::Postgres(db) => db.use_recovery_code(user_id, code).await,
             Database::Memory(db) => db.use_recovery_code(user_id, code).await,
         }
     }
 
+    /// Every code in the user's current set, used or not
+    pub async fn find_recovery_codes(
+        &self,
+        user_id: uuid::Uuid,
+    ) -> Result<Vec<crate::models::MfaRecoveryCode>, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.find_recovery_codes(user_id).await,
+            Database::Memory(db) => db.find_recovery_codes(user_id).await,
+        }
+    }
+
     pub async fn delete_recovery_codes(&self, user_id: uuid::Uuid) -> Result<(), AuthError> {
-        match &self.db {
+        match self.db() {
             Database::Postgres(db) => db.delete_recovery_codes(user_id).await,
             Database::Memory(db) => db.delete_recovery_codes(user_id).await,
         }
     }
-}
 
-pub fn init_db(config: &Config) -> Result<Arc<DatabaseConnection>, AuthError> {
-    // Get database connection from environment
-    let database_url = &config.database.url;
-    
-    // Create database connection pool
-    let manager = ConnectionManager::<PgConnection>::new(database_url);
-    let pool = Pool::builder()
-        .max_size(config.database.pool_size)
-        .build(manager)
-        .map_err(|e: R2D2Error| {
-            AuthError::DatabaseError(format!("Failed to create connection pool: {}", e))
-        })?;
-    
-    // Create database connection
-    let db = DatabaseConnection::new_postgres(pool);
-    
-    Ok(Arc::new(db))
+    // TOTP device methods
+    pub async fn create_totp_device(&self, device: crate::models::NewTotpDevice) -> Result<crate::models::TotpDevice, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.create_totp_device(device).await,
+            Database::Memory(db) => db.create_totp_device(device).await,
+        }
+    }
+
+    pub async fn find_totp_devices_by_user_id(&self, user_id: uuid::Uuid) -> Result<Vec<crate::models::TotpDevice>, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.find_totp_devices_by_user_id(user_id).await,
+            Database::Memory(db) => db.find_totp_devices_by_user_id(user_id).await,
+        }
+    }
+
+    pub async fn confirm_totp_device(&self, id: uuid::Uuid) -> Result<(), AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.confirm_totp_device(id).await,
+            Database::Memory(db) => db.confirm_totp_device(id).await,
+        }
+    }
+
+    pub async fn touch_totp_device(&self, id: uuid::Uuid) -> Result<(), AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.touch_totp_device(id).await,
+            Database::Memory(db) => db.touch_totp_device(id).await,
+        }
+    }
+
+    pub async fn delete_totp_device(&self, id: uuid::Uuid) -> Result<(), AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.delete_totp_device(id).await,
+            Database::Memory(db) => db.delete_totp_device(id).await,
+        }
+    }
+
+    pub async fn delete_totp_devices(&self, user_id: uuid::Uuid) -> Result<(), AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.delete_totp_devices(user_id).await,
+            Database::Memory(db) => db.delete_totp_devices(user_id).await,
+        }
+    }
+
+    // MFA method preference methods
+    pub async fn find_mfa_method_order(&self, user_id: uuid::Uuid) -> Result<Vec<String>, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.find_mfa_method_order(user_id).await,
+            Database::Memory(db) => db.find_mfa_method_order(user_id).await,
+        }
+    }
+
+    pub async fn save_mfa_method_order(&self, user_id: uuid::Uuid, methods: Vec<String>) -> Result<(), AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.save_mfa_method_order(user_id, methods).await,
+            Database::Memory(db) => db.save_mfa_method_order(user_id, methods).await,
+        }
+    }
+
+    // Passkey prompt methods
+    pub async fn find_passkey_prompt(&self, user_id: uuid::Uuid) -> Result<Option<crate::models::PasskeyPromptState>, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.find_passkey_prompt(user_id).await,
+            Database::Memory(db) => db.find_passkey_prompt(user_id).await,
+        }
+    }
+
+    pub async fn record_passkey_prompt(&self, user_id: uuid::Uuid) -> Result<(), AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.record_passkey_prompt(user_id).await,
+            Database::Memory(db) => db.record_passkey_prompt(user_id).await,
+        }
+    }
+
+    pub async fn dismiss_passkey_prompt(&self, user_id: uuid::Uuid) -> Result<(), AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.dismiss_passkey_prompt(user_id).await,
+            Database::Memory(db) => db.dismiss_passkey_prompt(user_id).await,
+        }
+    }
+
+    // Backup email methods
+    pub async fn create_backup_email(&self, email: crate::models::NewBackupEmail) -> Result<crate::models::BackupEmail, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.create_backup_email(email).await,
+            Database::Memory(db) => db.create_backup_email(email).await,
+        }
+    }
+
+    pub async fn find_backup_emails_by_user_id(&self, user_id: uuid::Uuid) -> Result<Vec<crate::models::BackupEmail>, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.find_backup_emails_by_user_id(user_id).await,
+            Database::Memory(db) => db.find_backup_emails_by_user_id(user_id).await,
+        }
+    }
+
+    pub async fn find_backup_email_by_address(&self, email: &str) -> Result<Option<crate::models::BackupEmail>, AuthError> {
+        let email = canonical_email(email);
+        match self.db() {
+            Database::Postgres(db) => db.find_backup_email_by_address(&email).await,
+            Database::Memory(db) => db.find_backup_email_by_address(&email).await,
+        }
+    }
+
+    pub async fn find_backup_email_by_token(&self, token: &str) -> Result<crate::models::BackupEmail, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.find_backup_email_by_token(token).await,
+            Database::Memory(db) => db.find_backup_email_by_token(token).await,
+        }
+    }
+
+    pub async fn verify_backup_email(&self, id: uuid::Uuid) -> Result<crate::models::BackupEmail, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.verify_backup_email(id).await,
+            Database::Memory(db) => db.verify_backup_email(id).await,
+        }
+    }
+
+    pub async fn delete_backup_email(&self, id: uuid::Uuid) -> Result<(), AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.delete_backup_email(id).await,
+            Database::Memory(db) => db.delete_backup_email(id).await,
+        }
+    }
+
+    // Account status methods
+    /// Change an account's status, recording the change in its audit history
+    pub async fn set_account_status(
+        &self,
+        user_id: uuid::Uuid,
+        expected_version: Option<i32>,
+        status: crate::models::AccountStatus,
+        reason: &str,
+        actor_id: Option<uuid::Uuid>,
+        event: crate::models::NewOutboxEvent,
+    ) -> Result<crate::models::User, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => {
+                db.set_account_status(user_id, expected_version, status, reason, actor_id, event).await
+            }
+            Database::Memory(db) => {
+                db.set_account_status(user_id, expected_version, status, reason, actor_id, event).await
+            }
+        }
+    }
+
+    /// One page of the account's status changes, and how many there are in all
+    pub async fn find_account_status_events(
+        &self,
+        user_id: uuid::Uuid,
+        page: &crate::models::PageRequest,
+    ) -> Result<(Vec<crate::models::AccountStatusEvent>, i64), AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.find_account_status_events(user_id, page).await,
+            Database::Memory(db) => db.find_account_status_events(user_id, page).await,
+        }
+    }
+
+    // Account risk methods
+    pub async fn record_account_risk_signal(&self, signal: crate::models::NewAccountRiskSignal) -> Result<crate::models::AccountRiskSignal, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.record_account_risk_signal(signal).await,
+            Database::Memory(db) => db.record_account_risk_signal(signal).await,
+        }
+    }
+
+    /// Signals recorded since the given time, newest first
+    pub async fn find_account_risk_signals(
+        &self,
+        user_id: uuid::Uuid,
+        since: chrono::DateTime<chrono::Utc>,
+    ) -> Result<Vec<crate::models::AccountRiskSignal>, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.find_account_risk_signals(user_id, since).await,
+            Database::Memory(db) => db.find_account_risk_signals(user_id, since).await,
+        }
+    }
+
+    /// Up to `limit` signals from any account recorded before `before`,
+    /// oldest first, for export
+    pub async fn find_account_risk_signals_before(
+        &self,
+        before: chrono::DateTime<chrono::Utc>,
+        limit: i64,
+    ) -> Result<Vec<crate::models::AccountRiskSignal>, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.find_account_risk_signals_before(before, limit).await,
+            Database::Memory(db) => db.find_account_risk_signals_before(before, limit).await,
+        }
+    }
+
+    pub async fn delete_account_risk_signals(&self, ids: Vec<uuid::Uuid>) -> Result<usize, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.delete_account_risk_signals(ids).await,
+            Database::Memory(db) => db.delete_account_risk_signals(ids).await,
+        }
+    }
+
+    pub async fn set_mfa_reenrollment_required(&self, user_id: uuid::Uuid, required: bool) -> Result<crate::models::User, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.set_mfa_reenrollment_required(user_id, required).await,
+            Database::Memory(db) => db.set_mfa_reenrollment_required(user_id, required).await,
+        }
+    }
+
+    // Brute force methods
+    /// Count a failed password login; returns the account's failures since
+    /// `window_start`, starting over if the last one was before it
+    pub async fn record_failed_login(&self, user_id: uuid::Uuid, window_start: chrono::DateTime<chrono::Utc>) -> Result<i32, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.record_failed_login(user_id, window_start).await,
+            Database::Memory(db) => db.record_failed_login(user_id, window_start).await,
+        }
+    }
+
+    /// Refuse password logins until `until`; the failure count starts over
+    pub async fn lock_account(&self, user_id: uuid::Uuid, until: chrono::DateTime<chrono::Utc>) -> Result<(), AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.lock_account(user_id, until).await,
+            Database::Memory(db) => db.lock_account(user_id, until).await,
+        }
+    }
+
+    pub async fn clear_failed_logins(&self, user_id: uuid::Uuid) -> Result<(), AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.clear_failed_logins(user_id).await,
+            Database::Memory(db) => db.clear_failed_logins(user_id).await,
+        }
+    }
+
+    /// Accounts still locked at `now`, most recently locked first
+    pub async fn find_locked_users(&self, now: chrono::DateTime<chrono::Utc>) -> Result<Vec<crate::models::User>, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.find_locked_users(now).await,
+            Database::Memory(db) => db.find_locked_users(now).await,
+        }
+    }
+
+    // Appeal methods
+    pub async fn create_account_appeal(&self, appeal: crate::models::NewAccountAppeal) -> Result<crate::models::AccountAppeal, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.create_account_appeal(appeal).await,
+            Database::Memory(db) => db.create_account_appeal(appeal).await,
+        }
+    }
+
+    pub async fn find_latest_account_appeal(&self, user_id: uuid::Uuid) -> Result<Option<crate::models::AccountAppeal>, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.find_latest_account_appeal(user_id).await,
+            Database::Memory(db) => db.find_latest_account_appeal(user_id).await,
+        }
+    }
+
+    pub async fn find_pending_account_appeals(&self) -> Result<Vec<crate::models::AccountAppeal>, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.find_pending_account_appeals().await,
+            Database::Memory(db) => db.find_pending_account_appeals().await,
+        }
+    }
+
+    pub async fn resolve_account_appeal(
+        &self,
+        id: uuid::Uuid,
+        accepted: bool,
+        note: &str,
+        admin_id: uuid::Uuid,
+    ) -> Result<Option<crate::models::AccountAppeal>, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.resolve_account_appeal(id, accepted, note, admin_id).await,
+            Database::Memory(db) => db.resolve_account_appeal(id, accepted, note, admin_id).await,
+        }
+    }
+
+    // Policy acceptance methods
+    pub async fn has_accepted_policy(&self, user_id: uuid::Uuid, version: &str) -> Result<bool, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.has_accepted_policy(user_id, version).await,
+            Database::Memory(db) => db.has_accepted_policy(user_id, version).await,
+        }
+    }
+
+    pub async fn record_policy_acceptance(&self, acceptance: crate::models::NewPolicyAcceptance) -> Result<(), AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.record_policy_acceptance(acceptance).await,
+            Database::Memory(db) => db.record_policy_acceptance(acceptance).await,
+        }
+    }
+
+    // Organization methods
+    pub async fn create_organization(
+        &self,
+        organization: crate::models::NewOrganization,
+        owner_id: uuid::Uuid,
+    ) -> Result<crate::models::Organization, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.create_organization(organization, owner_id).await,
+            Database::Memory(db) => db.create_organization(organization, owner_id).await,
+        }
+    }
+
+    pub async fn find_organization_by_id(&self, id: uuid::Uuid) -> Result<Option<crate::models::Organization>, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.find_organization_by_id(id).await,
+            Database::Memory(db) => db.find_organization_by_id(id).await,
+        }
+    }
+
+    pub async fn find_organization_by_slug(&self, slug: &str) -> Result<Option<crate::models::Organization>, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.find_organization_by_slug(slug).await,
+            Database::Memory(db) => db.find_organization_by_slug(slug).await,
+        }
+    }
+
+    pub async fn find_user_organizations(
+        &self,
+        user_id: uuid::Uuid,
+    ) -> Result<Vec<(crate::models::Organization, crate::models::OrganizationMember)>, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.find_user_organizations(user_id).await,
+            Database::Memory(db) => db.find_user_organizations(user_id).await,
+        }
+    }
+
+    pub async fn find_organization_member(
+        &self,
+        organization_id: uuid::Uuid,
+        user_id: uuid::Uuid,
+    ) -> Result<Option<crate::models::OrganizationMember>, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.find_organization_member(organization_id, user_id).await,
+            Database::Memory(db) => db.find_organization_member(organization_id, user_id).await,
+        }
+    }
+
+    pub async fn add_organization_member(
+        &self,
+        member: crate::models::NewOrganizationMember,
+    ) -> Result<crate::models::OrganizationMember, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.add_organization_member(member).await,
+            Database::Memory(db) => db.add_organization_member(member).await,
+        }
+    }
+
+    pub async fn update_organization_member_role(
+        &self,
+        id: uuid::Uuid,
+        role: crate::models::OrganizationRole,
+    ) -> Result<crate::models::OrganizationMember, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.update_organization_member_role(id, role).await,
+            Database::Memory(db) => db.update_organization_member_role(id, role).await,
+        }
+    }
+
+    /// `None` until the organization's branding is first saved
+    pub async fn find_organization_branding(
+        &self,
+        organization_id: uuid::Uuid,
+    ) -> Result<Option<crate::models::OrganizationBranding>, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.find_organization_branding(organization_id).await,
+            Database::Memory(db) => db.find_organization_branding(organization_id).await,
+        }
+    }
+
+    pub async fn save_organization_branding(
+        &self,
+        branding: crate::models::NewOrganizationBranding,
+    ) -> Result<crate::models::OrganizationBranding, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.save_organization_branding(branding).await,
+            Database::Memory(db) => db.save_organization_branding(branding).await,
+        }
+    }
+
+    // Organization domain methods
+    pub async fn find_organization_domains(
+        &self,
+        organization_id: uuid::Uuid,
+    ) -> Result<Vec<crate::models::OrganizationDomain>, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.find_organization_domains(organization_id).await,
+            Database::Memory(db) => db.find_organization_domains(organization_id).await,
+        }
+    }
+
+    pub async fn find_organization_domain_by_id(&self, id: uuid::Uuid) -> Result<Option<crate::models::OrganizationDomain>, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.find_organization_domain_by_id(id).await,
+            Database::Memory(db) => db.find_organization_domain_by_id(id).await,
+        }
+    }
+
+    pub async fn find_verified_organization_domain(&self, domain: &str) -> Result<Option<crate::models::OrganizationDomain>, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.find_verified_organization_domain(domain).await,
+            Database::Memory(db) => db.find_verified_organization_domain(domain).await,
+        }
+    }
+
+    pub async fn create_organization_domain(
+        &self,
+        domain: crate::models::NewOrganizationDomain,
+    ) -> Result<crate::models::OrganizationDomain, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.create_organization_domain(domain).await,
+            Database::Memory(db) => db.create_organization_domain(domain).await,
+        }
+    }
+
+    pub async fn record_organization_domain_check(
+        &self,
+        id: uuid::Uuid,
+        verified_at: Option<chrono::DateTime<chrono::Utc>>,
+    ) -> Result<crate::models::OrganizationDomain, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.record_organization_domain_check(id, verified_at).await,
+            Database::Memory(db) => db.record_organization_domain_check(id, verified_at).await,
+        }
+    }
+
+    pub async fn set_organization_domain_auto_join(
+        &self,
+        id: uuid::Uuid,
+        auto_join: bool,
+    ) -> Result<crate::models::OrganizationDomain, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.set_organization_domain_auto_join(id, auto_join).await,
+            Database::Memory(db) => db.set_organization_domain_auto_join(id, auto_join).await,
+        }
+    }
+
+    pub async fn delete_organization_domain(&self, id: uuid::Uuid) -> Result<(), AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.delete_organization_domain(id).await,
+            Database::Memory(db) => db.delete_organization_domain(id).await,
+        }
+    }
+
+    // SSO connection methods
+    pub async fn find_sso_connection(&self, id: uuid::Uuid) -> Result<Option<crate::models::SsoConnection>, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.find_sso_connection(id).await,
+            Database::Memory(db) => db.find_sso_connection(id).await,
+        }
+    }
+
+    pub async fn find_sso_connection_by_organization(
+        &self,
+        organization_id: uuid::Uuid,
+    ) -> Result<Option<crate::models::SsoConnection>, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.find_sso_connection_by_organization(organization_id).await,
+            Database::Memory(db) => db.find_sso_connection_by_organization(organization_id).await,
+        }
+    }
+
+    pub async fn save_sso_connection(
+        &self,
+        connection: crate::models::NewSsoConnection,
+    ) -> Result<crate::models::SsoConnection, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.save_sso_connection(connection).await,
+            Database::Memory(db) => db.save_sso_connection(connection).await,
+        }
+    }
+
+    pub async fn delete_sso_connection(&self, organization_id: uuid::Uuid) -> Result<(), AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.delete_sso_connection(organization_id).await,
+            Database::Memory(db) => db.delete_sso_connection(organization_id).await,
+        }
+    }
+
+    // SSO identity methods
+    pub async fn find_sso_identity(
+        &self,
+        connection_id: uuid::Uuid,
+        subject: &str,
+    ) -> Result<Option<crate::models::SsoIdentity>, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.find_sso_identity(connection_id, subject).await,
+            Database::Memory(db) => db.find_sso_identity(connection_id, subject).await,
+        }
+    }
+
+    pub async fn create_sso_identity(
+        &self,
+        identity: crate::models::NewSsoIdentity,
+    ) -> Result<crate::models::SsoIdentity, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.create_sso_identity(identity).await,
+            Database::Memory(db) => db.create_sso_identity(identity).await,
+        }
+    }
+
+    pub async fn record_sso_login(&self, identity_id: uuid::Uuid) -> Result<(), AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.record_sso_login(identity_id).await,
+            Database::Memory(db) => db.record_sso_login(identity_id).await,
+        }
+    }
+
+    // Action token methods
+    /// Record a token as used; `InvalidToken` if it already was
+    pub async fn redeem_action_token(
+        &self,
+        redemption: crate::models::NewActionTokenRedemption,
+    ) -> Result<(), AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.redeem_action_token(redemption).await,
+            Database::Memory(db) => db.redeem_action_token(redemption).await,
+        }
+    }
+
+    // API key methods
+    pub async fn create_api_key(&self, key: crate::models::NewApiKey) -> Result<crate::models::ApiKey, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.create_api_key(key).await,
+            Database::Memory(db) => db.create_api_key(key).await,
+        }
+    }
+
+    pub async fn find_api_keys_by_user_id(&self, user_id: uuid::Uuid) -> Result<Vec<crate::models::ApiKey>, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.find_api_keys_by_user_id(user_id).await,
+            Database::Memory(db) => db.find_api_keys_by_user_id(user_id).await,
+        }
+    }
+
+    /// The key with this hash; `InvalidToken` if there is none
+    pub async fn find_api_key_by_hash(&self, key_hash: &str) -> Result<crate::models::ApiKey, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.find_api_key_by_hash(key_hash).await,
+            Database::Memory(db) => db.find_api_key_by_hash(key_hash).await,
+        }
+    }
+
+    pub async fn find_api_key_by_id(&self, id: uuid::Uuid) -> Result<crate::models::ApiKey, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.find_api_key_by_id(id).await,
+            Database::Memory(db) => db.find_api_key_by_id(id).await,
+        }
+    }
+
+    /// Count a request made with the key on `day`; returns its requests that day
+    /// and since `month_start`
+    pub async fn record_api_key_request(
+        &self,
+        id: uuid::Uuid,
+        day: chrono::NaiveDate,
+        month_start: chrono::NaiveDate,
+    ) -> Result<(i64, i64), AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.record_api_key_request(id, day, month_start).await,
+            Database::Memory(db) => db.record_api_key_request(id, day, month_start).await,
+        }
+    }
+
+    pub async fn find_api_key_usage(
+        &self,
+        id: uuid::Uuid,
+        since: chrono::NaiveDate,
+    ) -> Result<Vec<crate::models::ApiKeyUsage>, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.find_api_key_usage(id, since).await,
+            Database::Memory(db) => db.find_api_key_usage(id, since).await,
+        }
+    }
+
+    pub async fn update_api_key_quota(
+        &self,
+        id: uuid::Uuid,
+        daily_quota: Option<i64>,
+        monthly_quota: Option<i64>,
+    ) -> Result<crate::models::ApiKey, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.update_api_key_quota(id, daily_quota, monthly_quota).await,
+            Database::Memory(db) => db.update_api_key_quota(id, daily_quota, monthly_quota).await,
+        }
+    }
+
+    pub async fn delete_api_key(&self, id: uuid::Uuid) -> Result<(), AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.delete_api_key(id).await,
+            Database::Memory(db) => db.delete_api_key(id).await,
+        }
+    }
+
+    // Canary methods
+    pub async fn create_canary_credential(
+        &self,
+        canary: crate::models::NewCanaryCredential,
+    ) -> Result<crate::models::CanaryCredential, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.create_canary_credential(canary).await,
+            Database::Memory(db) => db.create_canary_credential(canary).await,
+        }
+    }
+
+    /// Every planted canary, newest first
+    pub async fn find_canary_credentials(&self) -> Result<Vec<crate::models::CanaryCredential>, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.find_canary_credentials().await,
+            Database::Memory(db) => db.find_canary_credentials().await,
+        }
+    }
+
+    pub async fn find_canary_by_user_id(&self, user_id: uuid::Uuid) -> Result<Option<crate::models::CanaryCredential>, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.find_canary_by_user_id(user_id).await,
+            Database::Memory(db) => db.find_canary_by_user_id(user_id).await,
+        }
+    }
+
+    pub async fn find_canary_by_api_key_id(&self, api_key_id: uuid::Uuid) -> Result<Option<crate::models::CanaryCredential>, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.find_canary_by_api_key_id(api_key_id).await,
+            Database::Memory(db) => db.find_canary_by_api_key_id(api_key_id).await,
+        }
+    }
+
+    /// Count an attempt to use the canary, and where it came from
+    pub async fn record_canary_trip(
+        &self,
+        id: uuid::Uuid,
+        ip: Option<String>,
+    ) -> Result<crate::models::CanaryCredential, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.record_canary_trip(id, ip).await,
+            Database::Memory(db) => db.record_canary_trip(id, ip).await,
+        }
+    }
+
+    pub async fn delete_canary_credential(&self, id: uuid::Uuid) -> Result<(), AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.delete_canary_credential(id).await,
+            Database::Memory(db) => db.delete_canary_credential(id).await,
+        }
+    }
+
+    // Notification preference methods
+    /// `None` until the user changes something; see `NotificationPreferences::defaults`
+    pub async fn find_notification_preferences(
+        &self,
+        user_id: Uuid,
+    ) -> Result<Option<crate::models::NotificationPreferences>, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.find_notification_preferences(user_id).await,
+            Database::Memory(db) => db.find_notification_preferences(user_id).await,
+        }
+    }
+
+    pub async fn save_notification_preferences(
+        &self,
+        preferences: crate::models::NewNotificationPreferences,
+    ) -> Result<crate::models::NotificationPreferences, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.save_notification_preferences(preferences).await,
+            Database::Memory(db) => db.save_notification_preferences(preferences).await,
+        }
+    }
+
+    // Feature flag methods
+    /// Create the flag, or replace its settings
+    pub async fn save_feature_flag(
+        &self,
+        flag: crate::models::NewFeatureFlag,
+    ) -> Result<crate::models::FeatureFlag, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.save_feature_flag(flag).await,
+            Database::Memory(db) => db.save_feature_flag(flag).await,
+        }
+    }
+
+    /// Every flag, by key
+    pub async fn find_feature_flags(&self) -> Result<Vec<crate::models::FeatureFlag>, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.find_feature_flags().await,
+            Database::Memory(db) => db.find_feature_flags().await,
+        }
+    }
+
+    /// `false` if there was no such flag
+    pub async fn delete_feature_flag(&self, key: &str) -> Result<bool, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.delete_feature_flag(key).await,
+            Database::Memory(db) => db.delete_feature_flag(key).await,
+        }
+    }
+
+    // Client application methods
+    /// Register the client, or replace its registration
+    pub async fn save_client_application(
+        &self,
+        client: crate::models::NewClientApplication,
+    ) -> Result<crate::models::ClientApplication, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.save_client_application(client).await,
+            Database::Memory(db) => db.save_client_application(client).await,
+        }
+    }
+
+    pub async fn find_client_application(
+        &self,
+        client_id: &str,
+    ) -> Result<Option<crate::models::ClientApplication>, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.find_client_application(client_id).await,
+            Database::Memory(db) => db.find_client_application(client_id).await,
+        }
+    }
+
+    /// Every registered client, by client id
+    pub async fn find_client_applications(&self) -> Result<Vec<crate::models::ClientApplication>, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.find_client_applications().await,
+            Database::Memory(db) => db.find_client_applications().await,
+        }
+    }
+
+    /// `false` if there was no such client. Its sessions are left unrevoked;
+    /// see `revoke_client_sessions`.
+    pub async fn delete_client_application(&self, client_id: &str) -> Result<bool, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.delete_client_application(client_id).await,
+            Database::Memory(db) => db.delete_client_application(client_id).await,
+        }
+    }
+
+    /// Revoke every live session started for the client, or only the user's,
+    /// returning their ids
+    pub async fn revoke_client_sessions(
+        &self,
+        client_id: &str,
+        user_id: Option<uuid::Uuid>,
+    ) -> Result<Vec<uuid::Uuid>, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.revoke_client_sessions(client_id, user_id).await,
+            Database::Memory(db) => db.revoke_client_sessions(client_id, user_id).await,
+        }
+    }
+
+    /// Record the scopes a user has let the client have, replacing any before
+    pub async fn save_client_consent(
+        &self,
+        consent: crate::models::NewClientConsent,
+    ) -> Result<crate::models::ClientConsent, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.save_client_consent(consent).await,
+            Database::Memory(db) => db.save_client_consent(consent).await,
+        }
+    }
+
+    pub async fn find_client_consent(
+        &self,
+        user_id: uuid::Uuid,
+        client_id: &str,
+    ) -> Result<Option<crate::models::ClientConsent>, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.find_client_consent(user_id, client_id).await,
+            Database::Memory(db) => db.find_client_consent(user_id, client_id).await,
+        }
+    }
+
+    /// Every client the user has consented to, by client id
+    pub async fn find_client_consents(&self, user_id: uuid::Uuid) -> Result<Vec<crate::models::ClientConsent>, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.find_client_consents(user_id).await,
+            Database::Memory(db) => db.find_client_consents(user_id).await,
+        }
+    }
+
+    /// `false` if the user hadn't consented to the client
+    pub async fn delete_client_consent(&self, user_id: uuid::Uuid, client_id: &str) -> Result<bool, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.delete_client_consent(user_id, client_id).await,
+            Database::Memory(db) => db.delete_client_consent(user_id, client_id).await,
+        }
+    }
+
+    // Authenticator metadata methods
+    /// Replace every cached authenticator model with those from a newer
+    /// metadata BLOB
+    pub async fn replace_authenticator_metadata(
+        &self,
+        entries: Vec<crate::models::NewAuthenticatorMetadata>,
+    ) -> Result<(), AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.replace_authenticator_metadata(entries).await,
+            Database::Memory(db) => db.replace_authenticator_metadata(entries).await,
+        }
+    }
+
+    /// Every cached authenticator model, empty until the first sync
+    pub async fn find_authenticator_metadata(&self) -> Result<Vec<crate::models::AuthenticatorMetadata>, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.find_authenticator_metadata().await,
+            Database::Memory(db) => db.find_authenticator_metadata().await,
+        }
+    }
+
+    // Login freeze methods
+    /// Freeze logins, replacing the message of an existing freeze on the
+    /// same scope (global or the same organization)
+    pub async fn set_login_freeze(
+        &self,
+        freeze: crate::models::NewLoginFreeze,
+    ) -> Result<crate::models::LoginFreeze, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.set_login_freeze(freeze).await,
+            Database::Memory(db) => db.set_login_freeze(freeze).await,
+        }
+    }
+
+    /// Lift the global freeze (`None`) or an organization's; `false` if there
+    /// was none
+    pub async fn clear_login_freeze(&self, organization_id: Option<uuid::Uuid>) -> Result<bool, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.clear_login_freeze(organization_id).await,
+            Database::Memory(db) => db.clear_login_freeze(organization_id).await,
+        }
+    }
+
+    /// Every freeze in place, the global one first, then newest first
+    pub async fn find_login_freezes(&self) -> Result<Vec<crate::models::LoginFreeze>, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.find_login_freezes().await,
+            Database::Memory(db) => db.find_login_freezes().await,
+        }
+    }
+
+    /// The user's own policy and those of the given organizations, the
+    /// user's first
+    pub async fn find_login_policies(
+        &self,
+        user_id: uuid::Uuid,
+        organization_ids: Vec<uuid::Uuid>,
+    ) -> Result<Vec<crate::models::LoginPolicy>, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.find_login_policies(user_id, organization_ids).await,
+            Database::Memory(db) => db.find_login_policies(user_id, organization_ids).await,
+        }
+    }
+
+    pub async fn find_login_policy(
+        &self,
+        scope: crate::models::LoginPolicyScope,
+    ) -> Result<Option<crate::models::LoginPolicy>, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.find_login_policy(scope).await,
+            Database::Memory(db) => db.find_login_policy(scope).await,
+        }
+    }
+
+    /// Replace the policy of the user or organization it's for
+    pub async fn save_login_policy(
+        &self,
+        policy: crate::models::NewLoginPolicy,
+    ) -> Result<crate::models::LoginPolicy, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.save_login_policy(policy).await,
+            Database::Memory(db) => db.save_login_policy(policy).await,
+        }
+    }
+
+    /// Whether there was a policy to delete
+    pub async fn delete_login_policy(&self, scope: crate::models::LoginPolicyScope) -> Result<bool, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.delete_login_policy(scope).await,
+            Database::Memory(db) => db.delete_login_policy(scope).await,
+        }
+    }
+
+    // Security question methods
+    /// The user's questions, in the order they're asked
+    pub async fn find_security_questions(
+        &self,
+        user_id: uuid::Uuid,
+    ) -> Result<Vec<crate::models::SecurityQuestion>, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.find_security_questions(user_id).await,
+            Database::Memory(db) => db.find_security_questions(user_id).await,
+        }
+    }
+
+    /// Replace the user's questions with these
+    pub async fn save_security_questions(
+        &self,
+        user_id: uuid::Uuid,
+        questions: Vec<crate::models::NewSecurityQuestion>,
+    ) -> Result<Vec<crate::models::SecurityQuestion>, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.save_security_questions(user_id, questions).await,
+            Database::Memory(db) => db.save_security_questions(user_id, questions).await,
+        }
+    }
+
+    /// Whether the user had questions to delete
+    pub async fn delete_security_questions(&self, user_id: uuid::Uuid) -> Result<bool, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.delete_security_questions(user_id).await,
+            Database::Memory(db) => db.delete_security_questions(user_id).await,
+        }
+    }
+
+    pub async fn find_security_question_failures(
+        &self,
+        user_id: uuid::Uuid,
+    ) -> Result<Option<crate::models::SecurityQuestionFailures>, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.find_security_question_failures(user_id).await,
+            Database::Memory(db) => db.find_security_question_failures(user_id).await,
+        }
+    }
+
+    /// Count a wrong set of answers; returns the failures since
+    /// `window_start`, starting over if the last one was before it
+    pub async fn record_security_question_failure(
+        &self,
+        user_id: uuid::Uuid,
+        window_start: chrono::DateTime<chrono::Utc>,
+    ) -> Result<i32, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.record_security_question_failure(user_id, window_start).await,
+            Database::Memory(db) => db.record_security_question_failure(user_id, window_start).await,
+        }
+    }
+
+    pub async fn clear_security_question_failures(&self, user_id: uuid::Uuid) -> Result<(), AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.clear_security_question_failures(user_id).await,
+            Database::Memory(db) => db.clear_security_question_failures(user_id).await,
+        }
+    }
+
+    pub async fn find_mfa_failures(
+        &self,
+        user_id: uuid::Uuid,
+    ) -> Result<Option<crate::models::MfaFailures>, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.find_mfa_failures(user_id).await,
+            Database::Memory(db) => db.find_mfa_failures(user_id).await,
+        }
+    }
+
+    /// Count a wrong TOTP or recovery code; returns the failures since
+    /// `window_start`, starting over if the last one was before it
+    pub async fn record_mfa_failure(
+        &self,
+        user_id: uuid::Uuid,
+        window_start: chrono::DateTime<chrono::Utc>,
+    ) -> Result<i32, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.record_mfa_failure(user_id, window_start).await,
+            Database::Memory(db) => db.record_mfa_failure(user_id, window_start).await,
+        }
+    }
+
+    pub async fn clear_mfa_failures(&self, user_id: uuid::Uuid) -> Result<(), AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.clear_mfa_failures(user_id).await,
+            Database::Memory(db) => db.clear_mfa_failures(user_id).await,
+        }
+    }
+
+    // Bulk job methods
+    pub async fn create_bulk_job(&self, job: crate::models::NewBulkJob) -> Result<crate::models::BulkJob, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.create_bulk_job(job).await,
+            Database::Memory(db) => db.create_bulk_job(job).await,
+        }
+    }
+
+    pub async fn find_bulk_job(&self, id: uuid::Uuid) -> Result<Option<crate::models::BulkJob>, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.find_bulk_job(id).await,
+            Database::Memory(db) => db.find_bulk_job(id).await,
+        }
+    }
+
+    /// The most recent jobs, newest first
+    pub async fn list_bulk_jobs(&self, limit: i64) -> Result<Vec<crate::models::BulkJob>, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.list_bulk_jobs(limit).await,
+            Database::Memory(db) => db.list_bulk_jobs(limit).await,
+        }
+    }
+
+    /// Mark the oldest queued job running and return it. A job still running
+    /// since before `stale_before` is taken to have lost its runner and is
+    /// claimed again.
+    pub async fn claim_bulk_job(
+        &self,
+        stale_before: chrono::DateTime<chrono::Utc>,
+    ) -> Result<Option<crate::models::BulkJob>, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.claim_bulk_job(stale_before).await,
+            Database::Memory(db) => db.claim_bulk_job(stale_before).await,
+        }
+    }
+
+    pub async fn finish_bulk_job(
+        &self,
+        id: uuid::Uuid,
+        status: crate::models::BulkJobStatus,
+        results: serde_json::Value,
+        error: Option<String>,
+    ) -> Result<(), AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.finish_bulk_job(id, status, results, error).await,
+            Database::Memory(db) => db.finish_bulk_job(id, status, results, error).await,
+        }
+    }
+
+    // User directory methods. The directory is only in the home region's
+    // database, whatever region the task is in.
+    /// Add or replace the entry for the user, failing if another user's has
+    /// the same email or username
+    pub async fn save_directory_entry(&self, entry: crate::models::NewDirectoryEntry) -> Result<(), AuthError> {
+        match &self.db {
+            Database::Postgres(db) => db.save_directory_entry(entry).await,
+            Database::Memory(db) => db.save_directory_entry(entry).await,
+        }
+    }
+
+    pub async fn find_directory_entry(&self, user_id: uuid::Uuid) -> Result<Option<crate::models::DirectoryEntry>, AuthError> {
+        match &self.db {
+            Database::Postgres(db) => db.find_directory_entry(user_id).await,
+            Database::Memory(db) => db.find_directory_entry(user_id).await,
+        }
+    }
+
+    /// The entry whose email or username has this `directory_digest`
+    pub async fn find_directory_entry_by_digest(
+        &self,
+        digest: &str,
+    ) -> Result<Option<crate::models::DirectoryEntry>, AuthError> {
+        match &self.db {
+            Database::Postgres(db) => db.find_directory_entry_by_digest(digest).await,
+            Database::Memory(db) => db.find_directory_entry_by_digest(digest).await,
+        }
+    }
+
+    pub async fn delete_directory_entry(&self, user_id: uuid::Uuid) -> Result<(), AuthError> {
+        match &self.db {
+            Database::Postgres(db) => db.delete_directory_entry(user_id).await,
+            Database::Memory(db) => db.delete_directory_entry(user_id).await,
+        }
+    }
+
+    // Schema methods
+    /// The newest migration run against the current region's database, to
+    /// compare with `SCHEMA_VERSION`
+    pub async fn latest_migration(&self) -> Result<Option<String>, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.latest_migration().await,
+            Database::Memory(db) => db.latest_migration().await,
+        }
+    }
+
+    // Trusted device methods
+    pub async fn create_trusted_device(
+        &self,
+        device: crate::models::NewTrustedDevice,
+    ) -> Result<crate::models::TrustedDevice, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.create_trusted_device(device).await,
+            Database::Memory(db) => db.create_trusted_device(device).await,
+        }
+    }
+
+    /// The user's unexpired device with this token hash, marked as used
+    pub async fn use_trusted_device(
+        &self,
+        user_id: uuid::Uuid,
+        token_hash: &str,
+    ) -> Result<Option<crate::models::TrustedDevice>, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.use_trusted_device(user_id, token_hash).await,
+            Database::Memory(db) => db.use_trusted_device(user_id, token_hash).await,
+        }
+    }
+
+    pub async fn delete_trusted_devices_by_user_id(&self, user_id: uuid::Uuid) -> Result<(), AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.delete_trusted_devices_by_user_id(user_id).await,
+            Database::Memory(db) => db.delete_trusted_devices_by_user_id(user_id).await,
+        }
+    }
+
+    // Passkey methods
+    pub async fn create_passkey(&self, passkey: crate::models::NewPasskey) -> Result<crate::models::Passkey, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.create_passkey(passkey).await,
+            Database::Memory(db) => db.create_passkey(passkey).await,
+        }
+    }
+
+    /// The user's passkeys, newest first
+    pub async fn find_passkeys_by_user_id(&self, user_id: uuid::Uuid) -> Result<Vec<crate::models::Passkey>, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.find_passkeys_by_user_id(user_id).await,
+            Database::Memory(db) => db.find_passkeys_by_user_id(user_id).await,
+        }
+    }
+
+    // Proxy alias methods
+    pub async fn create_proxy_alias(
+        &self,
+        alias: crate::models::NewProxyAlias,
+    ) -> Result<crate::models::ProxyAlias, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.create_proxy_alias(alias).await,
+            Database::Memory(db) => db.create_proxy_alias(alias).await,
+        }
+    }
+
+    /// The user's proxy aliases, oldest first; deleted ones are left out
+    pub async fn find_proxy_aliases_by_user_id(
+        &self,
+        user_id: uuid::Uuid,
+    ) -> Result<Vec<crate::models::ProxyAlias>, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.find_proxy_aliases_by_user_id(user_id).await,
+            Database::Memory(db) => db.find_proxy_aliases_by_user_id(user_id).await,
+        }
+    }
+
+    /// Record an email against the address's resend limits, forgetting that
+    /// address's sends of the same kind from before `prune_before`
+    pub async fn record_email_send(
+        &self,
+        send: crate::models::NewEmailSend,
+        prune_before: chrono::DateTime<chrono::Utc>,
+    ) -> Result<(), AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.record_email_send(send, prune_before).await,
+            Database::Memory(db) => db.record_email_send(send, prune_before).await,
+        }
+    }
+
+    /// When emails of `kind` went to `address` since `since`, newest first
+    pub async fn find_email_sends_since(
+        &self,
+        address: &str,
+        kind: &str,
+        since: chrono::DateTime<chrono::Utc>,
+    ) -> Result<Vec<chrono::DateTime<chrono::Utc>>, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.find_email_sends_since(address, kind, since).await,
+            Database::Memory(db) => db.find_email_sends_since(address, kind, since).await,
+        }
+    }
+
+    // Unit of work
+    /// Apply every write in `work`, or none of them if one fails
+    pub async fn commit(&self, work: UnitOfWork) -> Result<(), AuthError> {
+        if work.is_empty() {
+            return Ok(());
+        }
+        match self.db() {
+            Database::Postgres(db) => db.commit(work).await,
+            Database::Memory(db) => db.commit(work).await,
+        }
+    }
+
+    // Outbox methods
+    /// Undelivered events that are due, leased to the caller until `lease_until`
+    pub async fn claim_outbox_events(
+        &self,
+        limit: i64,
+        max_attempts: i32,
+        lease_until: chrono::DateTime<chrono::Utc>,
+    ) -> Result<Vec<crate::models::OutboxEvent>, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.claim_outbox_events(limit, max_attempts, lease_until).await,
+            Database::Memory(db) => db.claim_outbox_events(limit, max_attempts, lease_until).await,
+        }
+    }
+
+    pub async fn mark_outbox_event_delivered(&self, id: uuid::Uuid) -> Result<(), AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.mark_outbox_event_delivered(id).await,
+            Database::Memory(db) => db.mark_outbox_event_delivered(id).await,
+        }
+    }
+
+    pub async fn record_outbox_event_failure(
+        &self,
+        id: uuid::Uuid,
+        error: &str,
+        retry_at: chrono::DateTime<chrono::Utc>,
+    ) -> Result<(), AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.record_outbox_event_failure(id, error, retry_at).await,
+            Database::Memory(db) => db.record_outbox_event_failure(id, error, retry_at).await,
+        }
+    }
+
+    /// Up to `limit` delivered events created before `before`, oldest first,
+    /// for export. Undelivered events stay for the relay.
+    pub async fn find_delivered_outbox_events(
+        &self,
+        before: chrono::DateTime<chrono::Utc>,
+        limit: i64,
+    ) -> Result<Vec<crate::models::OutboxEvent>, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.find_delivered_outbox_events(before, limit).await,
+            Database::Memory(db) => db.find_delivered_outbox_events(before, limit).await,
+        }
+    }
+
+    /// A page of events, delivered or not, by when they were created
+    pub async fn find_outbox_events(
+        &self,
+        filter: &crate::models::AuditEventFilter,
+        page: &crate::models::PageRequest,
+    ) -> Result<(Vec<crate::models::OutboxEvent>, i64), AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.find_outbox_events(filter, page).await,
+            Database::Memory(db) => db.find_outbox_events(filter, page).await,
+        }
+    }
+
+    pub async fn delete_outbox_events(&self, ids: Vec<uuid::Uuid>) -> Result<usize, AuthError> {
+        match self.db() {
+            Database::Postgres(db) => db.delete_outbox_events(ids).await,
+            Database::Memory(db) => db.delete_outbox_events(ids).await,
+        }
+    }
+}
+
+pub fn init_db(config: &Config) -> Result<Arc<DatabaseConnection>, AuthError> {
+    if config.database.in_memory {
+        return Ok(Arc::new(DatabaseConnection::new_memory()));
+    }
+
+    // Get database connection from environment
+    let database_url = &config.database.url;
+    
+    // Create database connection pool
+    let manager = ConnectionManager::<PgConnection>::new(database_url);
+    let pool = Pool::builder()
+        .max_size(config.database.pool_size)
+        .build(manager)
+        .map_err(|e: R2D2Error| {
+            AuthError::DatabaseError(format!("Failed to create connection pool: {}", e))
+        })?;
+    
+    // Create database connection
+    let db = DatabaseConnection::new_postgres(pool);
+    if !config.regions.is_enabled() {
+        return Ok(Arc::new(db));
+    }
+
+    // One pool per region, each the size of the home region's
+    let mut regions = HashMap::new();
+    for (region, url) in &config.regions.database_urls {
+        let pool = Pool::builder()
+            .max_size(config.database.pool_size)
+            .build(ConnectionManager::<PgConnection>::new(url))
+            .map_err(|e: R2D2Error| {
+                AuthError::DatabaseError(format!("Failed to create connection pool for region {}: {}", region, e))
+            })?;
+        regions.insert(region.clone(), DatabaseConnection::new_postgres(pool));
+    }
+
+    Ok(Arc::new(db.with_regions(&config.regions.home, regions)))
 }
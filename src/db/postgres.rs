@@ -5,13 +5,43 @@ use diesel::{
     PgConnection,
 };
 use uuid::Uuid;
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, NaiveDate, Utc};
 
+use crate::db::unit_of_work::{UnitOfWork, Write};
 use crate::errors::AuthError;
 use crate::models::{
-    MfaRecoveryCode, NewMfaRecoveryCode, NewSession, NewUser, Session, User,
+    AccountAppeal, AccountRiskSignal, AccountStatus, AccountStatusEvent, ApiKey, ApiKeyUsage, AuditEventFilter, AuthenticatorMetadata, BackupEmail,
+    BulkJob, BulkJobStatus, CanaryCredential, ClientApplication, ClientConsent, DirectoryEntry, EventType, FeatureFlag, GuestUpgrade, LoginFreeze, LoginPolicy, LoginPolicyScope, NewLoginPolicy, MfaRecoveryCode, NotificationPreferences, NewAccountAppeal, NewAccountRiskSignal, NewAccountStatusEvent,
+    NewActionTokenRedemption, NewApiKey, NewAuthenticatorMetadata, NewBackupEmail, NewBulkJob, NewCanaryCredential, NewClientApplication, NewClientConsent, NewDirectoryEntry, NewEmailSend, NewFeatureFlag, NewLoginFreeze, NewMfaRecoveryCode, NewNotificationPreferences, NewOrganization, NewOrganizationBranding,
+    NewOrganizationDomain, NewOrganizationMember, NewOutboxEvent, NewPolicyAcceptance, NewSession,
+    NewSsoConnection, NewSsoIdentity, NewTotpDevice, NewUser, Organization, OrganizationBranding, OrganizationDomain,
+    OrganizationMember, OrganizationRole, OutboxEvent, PageRequest, NewPasskey, NewProxyAlias, Passkey, PasskeyPromptState, ProxyAlias, PROXY_ALIAS_DELETED,
+    MfaFailures, ProfileChanges, NewSecurityQuestion, SecurityQuestion, SecurityQuestionFailures, Session, SessionChanges, SessionFilter, SessionSort, SessionTableStats, SortOrder, SsoConnection,
+    SsoIdentity, TokenRevocation, NewTokenRevocation, TotpDevice, NewTrustedDevice, TrustedDevice, User, UserFilter, UserSort,
 };
-use crate::schema::{mfa_recovery_codes, sessions, users};
+use crate::schema::{
+    account_appeals, account_risk_signals, account_status_events, action_token_redemptions, api_key_usage, api_keys, authenticator_metadata,
+    bulk_jobs, canary_credentials, client_applications, client_consents, email_sends, events_outbox, feature_flags, login_freezes, login_policies, mfa_failures, mfa_method_preferences, mfa_recovery_codes, mfa_totp_devices, notification_preferences, organization_branding, organization_domains, organization_members,
+    organizations, passkey_prompts, policy_acceptances, proxy_aliases, security_question_failures, security_questions, sessions, sso_connections, sso_identities,
+    token_revocations, trusted_devices, user_directory, user_emails, users, webauthn_credentials,
+};
+use crate::utils::user_agent::DeviceInfo;
+
+// Usernames and emails are compared case-insensitively, matching the
+// `LOWER()` unique indexes on them
+sql_function!(fn lower(x: diesel::sql_types::Text) -> diesel::sql_types::Text);
+
+sql_function!(fn coalesce(
+    x: diesel::sql_types::Nullable<diesel::sql_types::Timestamptz>,
+    y: diesel::sql_types::Timestamptz
+) -> diesel::sql_types::Timestamptz);
+
+// Diesel's own record of the migrations it has run
+#[derive(QueryableByName)]
+struct MigrationVersion {
+    #[diesel(sql_type = diesel::sql_types::Nullable<diesel::sql_types::Text>)]
+    version: Option<String>,
+}
 
 pub type PgPool = Pool<ConnectionManager<PgConnection>>;
 pub type PgConn = PooledConnection<ConnectionManager<PgConnection>>;
@@ -32,19 +62,25 @@ impl PostgresDb {
     }
 
     // User methods
-    pub async fn create_user(&self, user: NewUser) -> Result<User, AuthError> {
+    pub async fn create_user(&self, user: NewUser, event: NewOutboxEvent) -> Result<User, AuthError> {
         use diesel::insert_into;
 
         let conn = self.get_conn()?;
         
         let user = tokio::task::spawn_blocking(move || {
-            insert_into(users::table)
-                .values(&user)
-                .get_result::<User>(&conn)
+            conn.transaction(|| {
+                let user = insert_into(users::table)
+                    .values(&user)
+                    .get_result::<User>(&conn)?;
+                
+                insert_into(events_outbox::table).values(&event).execute(&conn)?;
+                
+                Ok(user)
+            })
         })
         .await
         .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
-        .map_err(|e| AuthError::DatabaseError(format!("Insert user error: {}", e)))?;
+        .map_err(|e: diesel::result::Error| AuthError::DatabaseError(format!("Insert user error: {}", e)))?;
         
         Ok(user)
     }
@@ -70,7 +106,7 @@ impl PostgresDb {
         
         let user = tokio::task::spawn_blocking(move || {
             users::table
-                .filter(users::username.eq(username))
+                .filter(lower(users::username).eq(lower(username)))
                 .first::<User>(&conn)
         })
         .await
@@ -86,27 +122,7 @@ impl PostgresDb {
         
         let user = tokio::task::spawn_blocking(move || {
             users::table
-                .filter(users::email.eq(email))
-                .first::<User>(&conn)
-        })
-        .await
-        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
-        .map_err(|e| AuthError::from(e))?;
-        
-        Ok(user)
-    }
-
-    pub async fn find_user_by_username_or_email(&self, username_or_email: &str) -> Result<User, AuthError> {
-        let username_or_email = username_or_email.to_string();
-        let conn = self.get_conn()?;
-        
-        let user = tokio::task::spawn_blocking(move || {
-            users::table
-                .filter(
-                    users::username.eq(&username_or_email).or(
-                        users::email.eq(&username_or_email)
-                    )
-                )
+                .filter(lower(users::email).eq(lower(email)))
                 .first::<User>(&conn)
         })
         .await
@@ -116,34 +132,39 @@ impl PostgresDb {
         Ok(user)
     }
 
-    pub async fn find_user_by_verification_token(&self, token: &str) -> Result<User, AuthError> {
-        let token = token.to_string();
+    pub async fn find_users_by_email_domain(&self, domain: &str) -> Result<Vec<User>, AuthError> {
+        let pattern = format!("%@{}", domain);
         let conn = self.get_conn()?;
         
-        let user = tokio::task::spawn_blocking(move || {
+        let users = tokio::task::spawn_blocking(move || {
             users::table
-                .filter(users::email_verification_token.eq(token))
-                .first::<User>(&conn)
+                .filter(lower(users::email).like(pattern))
+                .order(users::created_at.asc())
+                .load::<User>(&conn)
         })
         .await
         .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
-        .map_err(|_| AuthError::InvalidToken)?;
+        .map_err(|e| AuthError::DatabaseError(format!("Query error: {}", e)))?;
         
-        Ok(user)
+        Ok(users)
     }
 
-    pub async fn find_user_by_reset_token(&self, token: &str) -> Result<User, AuthError> {
-        let token = token.to_string();
+    pub async fn find_user_by_username_or_email(&self, username_or_email: &str) -> Result<User, AuthError> {
+        let username_or_email = username_or_email.to_string();
         let conn = self.get_conn()?;
         
         let user = tokio::task::spawn_blocking(move || {
             users::table
-                .filter(users::password_reset_token.eq(token))
+                .filter(
+                    lower(users::username).eq(lower(&username_or_email)).or(
+                        lower(users::email).eq(lower(&username_or_email))
+                    )
+                )
                 .first::<User>(&conn)
         })
         .await
         .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
-        .map_err(|_| AuthError::InvalidToken)?;
+        .map_err(|e| AuthError::from(e))?;
         
         Ok(user)
     }
@@ -154,7 +175,7 @@ impl PostgresDb {
         
         let exists = tokio::task::spawn_blocking(move || {
             diesel::select(diesel::dsl::exists(
-                users::table.filter(users::username.eq(username))
+                users::table.filter(lower(users::username).eq(lower(username)))
             ))
             .get_result::<bool>(&conn)
         })
@@ -171,7 +192,7 @@ impl PostgresDb {
         
         let exists = tokio::task::spawn_blocking(move || {
             diesel::select(diesel::dsl::exists(
-                users::table.filter(users::email.eq(email))
+                users::table.filter(lower(users::email).eq(lower(email)))
             ))
             .get_result::<bool>(&conn)
         })
@@ -182,6 +203,82 @@ impl PostgresDb {
         Ok(exists)
     }
 
+    pub async fn search_users(
+        &self,
+        query: Option<&str>,
+        filter: &UserFilter,
+        page: &PageRequest,
+    ) -> Result<(Vec<User>, i64), AuthError> {
+        let conn = self.get_conn()?;
+        let query = query.map(str::to_string);
+        let filter = filter.clone();
+        let page = page.clone();
+
+        let result = tokio::task::spawn_blocking(move || {
+            let filtered = || {
+                // Live accounts use the partial trigram indexes
+                let mut q = if filter.archived {
+                    users::table.filter(users::archived_at.is_not_null()).into_boxed()
+                } else {
+                    users::table.filter(users::archived_at.is_null()).into_boxed()
+                };
+                match query.as_deref().map(|term| (term, term.parse::<Uuid>())) {
+                    Some((_, Ok(id))) => q = q.filter(users::id.eq(id)),
+                    // ILIKE on both sides of the term is served by the trigram indexes
+                    Some((term, Err(_))) => {
+                        let pattern = like_pattern(term);
+                        q = q.filter(
+                            users::username
+                                .ilike(pattern.clone())
+                                .or(users::email.ilike(pattern.clone()))
+                                .or(users::display_name.ilike(pattern)),
+                        );
+                    }
+                    None => {}
+                }
+                if let Some(status) = filter.status {
+                    q = q.filter(users::status.eq(status.as_str()));
+                }
+                if let Some(is_admin) = filter.is_admin {
+                    q = q.filter(users::is_admin.eq(is_admin));
+                }
+                if let Some(is_guest) = filter.is_guest {
+                    q = q.filter(users::is_guest.eq(is_guest));
+                }
+                if let Some(mfa_enabled) = filter.mfa_enabled {
+                    q = q.filter(users::mfa_enabled.eq(mfa_enabled));
+                }
+                q
+            };
+
+            let total = filtered().count().get_result::<i64>(&conn)?;
+
+            // Ties are broken by id so pages stay stable
+            let q = match (filter.sort, page.order) {
+                (UserSort::CreatedAt, SortOrder::Asc) => filtered()
+                    .order((users::created_at.asc(), users::id.asc())),
+                (UserSort::CreatedAt, SortOrder::Desc) => filtered()
+                    .order((users::created_at.desc(), users::id.desc())),
+                (UserSort::Username, SortOrder::Asc) => filtered()
+                    .order((lower(users::username).asc(), users::id.asc())),
+                (UserSort::Username, SortOrder::Desc) => filtered()
+                    .order((lower(users::username).desc(), users::id.desc())),
+            };
+
+            let found = q
+                .limit(page.limit)
+                .offset(page.offset)
+                .load::<User>(&conn)?;
+
+            Ok::<_, diesel::result::Error>((found, total))
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Query error: {}", e)))?;
+
+        Ok(result)
+    }
+
     pub async fn update_last_login(&self, id: Uuid) -> Result<(), AuthError> {
         let conn = self.get_conn()?;
         
@@ -200,139 +297,362 @@ impl PostgresDb {
         Ok(())
     }
 
-    pub async fn update_verification_token(&self, id: Uuid, token: &str) -> Result<(), AuthError> {
-        let token = token.to_string();
+    pub async fn update_password(
+        &self,
+        id: Uuid,
+        password_hash: &str,
+        expires_at: Option<DateTime<Utc>>,
+        event: NewOutboxEvent,
+    ) -> Result<(), AuthError> {
+        let password_hash = password_hash.to_string();
         let conn = self.get_conn()?;
         
         tokio::task::spawn_blocking(move || {
-            diesel::update(users::table.find(id))
-                .set((
-                    users::email_verification_token.eq(token),
-                    users::email_verification_sent_at.eq(now),
-                    users::updated_at.eq(now),
-                ))
-                .execute(&conn)
+            conn.transaction(|| {
+                let updated = diesel::update(users::table.find(id))
+                    .set((
+                        users::password_hash.eq(password_hash),
+                        users::password_reset_token.eq::<Option<String>>(None),
+                        users::password_reset_sent_at.eq::<Option<DateTime<Utc>>>(None),
+                        users::password_reset_email.eq::<Option<String>>(None),
+                        users::password_expires_at.eq(expires_at),
+                        users::updated_at.eq(now),
+                    ))
+                    .execute(&conn)?;
+                if updated == 0 {
+                    return Err(diesel::result::Error::NotFound);
+                }
+                
+                diesel::insert_into(events_outbox::table).values(&event).execute(&conn)?;
+                
+                Ok(())
+            })
         })
         .await
         .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
-        .map_err(|e| AuthError::DatabaseError(format!("Update error: {}", e)))?;
+        .map_err(|e: diesel::result::Error| match e {
+            diesel::result::Error::NotFound => AuthError::UserNotFound,
+            e => AuthError::DatabaseError(format!("Update error: {}", e)),
+        })?;
         
         Ok(())
     }
 
-    pub async fn update_password_reset_token(&self, id: Uuid, token: &str) -> Result<(), AuthError> {
-        let token = token.to_string();
+    pub async fn expire_passwords(&self, user_ids: Option<Vec<Uuid>>) -> Result<Vec<Uuid>, AuthError> {
         let conn = self.get_conn()?;
         
-        tokio::task::spawn_blocking(move || {
-            diesel::update(users::table.find(id))
-                .set((
-                    users::password_reset_token.eq(token),
-                    users::password_reset_sent_at.eq(now),
-                    users::updated_at.eq(now),
-                ))
-                .execute(&conn)
+        let ids = tokio::task::spawn_blocking(move || {
+            let set = (
+                users::password_expires_at.eq(now.nullable()),
+                users::updated_at.eq(now),
+            );
+            match user_ids {
+                Some(ids) => diesel::update(users::table.filter(users::id.eq_any(ids)))
+                    .set(set)
+                    .returning(users::id)
+                    .get_results::<Uuid>(&conn),
+                None => diesel::update(users::table)
+                    .set(set)
+                    .returning(users::id)
+                    .get_results::<Uuid>(&conn),
+            }
         })
         .await
         .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
         .map_err(|e| AuthError::DatabaseError(format!("Update error: {}", e)))?;
         
-        Ok(())
+        Ok(ids)
     }
 
-    pub async fn update_password(&self, id: Uuid, password_hash: &str) -> Result<(), AuthError> {
-        let password_hash = password_hash.to_string();
+    pub async fn bump_token_version(&self, id: Uuid) -> Result<i32, AuthError> {
         let conn = self.get_conn()?;
         
-        tokio::task::spawn_blocking(move || {
+        let version = tokio::task::spawn_blocking(move || {
             diesel::update(users::table.find(id))
                 .set((
-                    users::password_hash.eq(password_hash),
-                    users::password_reset_token.eq::<Option<String>>(None),
-                    users::password_reset_sent_at.eq::<Option<DateTime<Utc>>>(None),
+                    users::token_version.eq(users::token_version + 1),
                     users::updated_at.eq(now),
                 ))
-                .execute(&conn)
+                .returning(users::token_version)
+                .get_result::<i32>(&conn)
         })
         .await
         .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
         .map_err(|e| AuthError::DatabaseError(format!("Update error: {}", e)))?;
         
-        Ok(())
+        Ok(version)
+    }
+
+    /// Set the first password of an account created without one. Following
+    /// the activation link proves the address, so it's verified too.
+    pub async fn archive_inactive_users(&self, inactive_before: DateTime<Utc>, limit: i64) -> Result<Vec<Uuid>, AuthError> {
+        let conn = self.get_conn()?;
+
+        let ids = tokio::task::spawn_blocking(move || {
+            conn.transaction(|| {
+                // Matches `idx_users_archive_candidates`
+                let ids = users::table
+                    .filter(users::archived_at.is_null())
+                    .filter(users::is_guest.eq(false))
+                    .filter(coalesce(users::last_login_at, users::created_at).lt(inactive_before))
+                    .select(users::id)
+                    .limit(limit)
+                    .for_update()
+                    .skip_locked()
+                    .load::<Uuid>(&conn)?;
+
+                diesel::update(users::table.filter(users::id.eq_any(&ids)))
+                    .set((
+                        users::archived_at.eq(now.nullable()),
+                        users::token_version.eq(users::token_version + 1),
+                        users::version.eq(users::version + 1),
+                        users::updated_at.eq(now),
+                    ))
+                    .execute(&conn)?;
+                diesel::update(sessions::table.filter(sessions::user_id.eq_any(&ids)))
+                    .filter(sessions::is_revoked.eq(false))
+                    .set((
+                        sessions::is_revoked.eq(true),
+                        sessions::updated_at.eq(now),
+                    ))
+                    .execute(&conn)?;
+
+                let events: Vec<NewOutboxEvent> = ids
+                    .iter()
+                    .map(|id| NewOutboxEvent::new(EventType::Archived, *id, serde_json::json!({ "reason": "inactive" })))
+                    .collect();
+                diesel::insert_into(events_outbox::table).values(&events).execute(&conn)?;
+
+                Ok(ids)
+            })
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e: diesel::result::Error| AuthError::DatabaseError(format!("Update error: {}", e)))?;
+
+        Ok(ids)
+    }
+
+    pub async fn reactivate_user(&self, id: Uuid, event: NewOutboxEvent) -> Result<User, AuthError> {
+        let conn = self.get_conn()?;
+
+        let user = tokio::task::spawn_blocking(move || {
+            conn.transaction(|| {
+                let user = diesel::update(users::table.find(id).filter(users::archived_at.is_not_null()))
+                    .set((
+                        users::archived_at.eq::<Option<DateTime<Utc>>>(None),
+                        users::version.eq(users::version + 1),
+                        users::updated_at.eq(now),
+                    ))
+                    .get_result::<User>(&conn)?;
+
+                diesel::insert_into(events_outbox::table).values(&event).execute(&conn)?;
+
+                Ok(user)
+            })
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e: diesel::result::Error| match e {
+            diesel::result::Error::NotFound => AuthError::UserNotFound,
+            e => AuthError::DatabaseError(format!("Update error: {}", e)),
+        })?;
+
+        Ok(user)
     }
 
-    pub async fn verify_email(&self, id: Uuid) -> Result<User, AuthError> {
+    pub async fn activate_user(
+        &self,
+        id: Uuid,
+        password_hash: &str,
+        expires_at: Option<DateTime<Utc>>,
+        event: NewOutboxEvent,
+    ) -> Result<User, AuthError> {
+        let password_hash = password_hash.to_string();
         let conn = self.get_conn()?;
         
         let user = tokio::task::spawn_blocking(move || {
-            diesel::update(users::table.find(id))
+            conn.transaction(|| {
+                let user = diesel::update(
+                    users::table.find(id).filter(users::activation_pending.eq(true)),
+                )
                 .set((
+                    users::password_hash.eq(password_hash),
+                    users::password_expires_at.eq(expires_at),
                     users::is_email_verified.eq(true),
                     users::email_verification_token.eq::<Option<String>>(None),
                     users::email_verification_sent_at.eq::<Option<DateTime<Utc>>>(None),
+                    users::activation_pending.eq(false),
                     users::updated_at.eq(now),
                 ))
-                .get_result::<User>(&conn)
+                .get_result::<User>(&conn)?;
+                
+                diesel::insert_into(events_outbox::table).values(&event).execute(&conn)?;
+                
+                Ok(user)
+            })
         })
         .await
         .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
-        .map_err(|e| AuthError::DatabaseError(format!("Update error: {}", e)))?;
+        .map_err(|e: diesel::result::Error| match e {
+            diesel::result::Error::NotFound => AuthError::UserNotFound,
+            e => AuthError::DatabaseError(format!("Update error: {}", e)),
+        })?;
         
         Ok(user)
     }
 
-    pub async fn update_mfa_secret(&self, id: Uuid, secret: &str) -> Result<(), AuthError> {
-        let secret = secret.to_string();
+    pub async fn verify_email(&self, id: Uuid, event: NewOutboxEvent) -> Result<User, AuthError> {
         let conn = self.get_conn()?;
         
-        tokio::task::spawn_blocking(move || {
-            diesel::update(users::table.find(id))
-                .set((
-                    users::mfa_secret.eq(secret),
-                    users::updated_at.eq(now),
-                ))
-                .execute(&conn)
+        let user = tokio::task::spawn_blocking(move || {
+            conn.transaction(|| {
+                let user = diesel::update(users::table.find(id))
+                    .set((
+                        users::is_email_verified.eq(true),
+                        users::email_verification_token.eq::<Option<String>>(None),
+                        users::email_verification_sent_at.eq::<Option<DateTime<Utc>>>(None),
+                        users::updated_at.eq(now),
+                    ))
+                    .get_result::<User>(&conn)?;
+                
+                diesel::insert_into(events_outbox::table).values(&event).execute(&conn)?;
+                
+                Ok(user)
+            })
         })
         .await
         .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
-        .map_err(|e| AuthError::DatabaseError(format!("Update error: {}", e)))?;
+        .map_err(|e: diesel::result::Error| AuthError::DatabaseError(format!("Update error: {}", e)))?;
         
-        Ok(())
+        Ok(user)
+    }
+
+    pub async fn update_profile(
+        &self,
+        id: Uuid,
+        expected_version: Option<i32>,
+        changes: ProfileChanges,
+    ) -> Result<User, AuthError> {
+        let conn = self.get_conn()?;
+        
+        let user = tokio::task::spawn_blocking(move || {
+            conn.transaction::<_, AuthError, _>(|| {
+                lock_user_version(&conn, id, expected_version)?;
+                let user = diesel::update(users::table.find(id))
+                    .set((&changes, users::version.eq(users::version + 1), users::updated_at.eq(now)))
+                    .get_result::<User>(&conn)?;
+                Ok(user)
+            })
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))??;
+        
+        Ok(user)
+    }
+
+    pub async fn upgrade_guest_user(
+        &self,
+        id: Uuid,
+        upgrade: GuestUpgrade,
+        event: NewOutboxEvent,
+    ) -> Result<User, AuthError> {
+        let conn = self.get_conn()?;
+        
+        // Bumping the token version ends the guest's access tokens
+        let user = tokio::task::spawn_blocking(move || {
+            conn.transaction(|| {
+                let user = diesel::update(users::table.find(id).filter(users::is_guest.eq(true)))
+                    .set((
+                        &upgrade,
+                        users::is_guest.eq(false),
+                        users::token_version.eq(users::token_version + 1),
+                        users::version.eq(users::version + 1),
+                        users::updated_at.eq(now),
+                    ))
+                    .get_result::<User>(&conn)?;
+                
+                diesel::insert_into(events_outbox::table).values(&event).execute(&conn)?;
+                
+                Ok(user)
+            })
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e: diesel::result::Error| match e {
+            diesel::result::Error::NotFound => {
+                AuthError::ValidationError("Account is already registered".into())
+            }
+            diesel::result::Error::DatabaseError(
+                diesel::result::DatabaseErrorKind::UniqueViolation,
+                _,
+            ) => AuthError::ValidationError("Username or email is already taken".into()),
+            e => AuthError::DatabaseError(format!("Update error: {}", e)),
+        })?;
+        
+        Ok(user)
     }
 
-    pub async fn enable_mfa(&self, id: Uuid) -> Result<(), AuthError> {
+    pub async fn update_mfa_secret(&self, id: Uuid, expected_version: Option<i32>, secret: &str) -> Result<(), AuthError> {
+        let secret = secret.to_string();
         let conn = self.get_conn()?;
         
         tokio::task::spawn_blocking(move || {
-            diesel::update(users::table.find(id))
-                .set((
-                    users::mfa_enabled.eq(true),
-                    users::updated_at.eq(now),
-                ))
-                .execute(&conn)
+            conn.transaction::<_, AuthError, _>(|| {
+                lock_user_version(&conn, id, expected_version)?;
+                diesel::update(users::table.find(id))
+                    .set((
+                        users::mfa_secret.eq(secret),
+                        users::version.eq(users::version + 1),
+                        users::updated_at.eq(now),
+                    ))
+                    .execute(&conn)?;
+                Ok(())
+            })
         })
         .await
         .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
-        .map_err(|e| AuthError::DatabaseError(format!("Update error: {}", e)))?;
+    }
+
+    pub async fn enable_mfa(&self, id: Uuid, expected_version: Option<i32>) -> Result<(), AuthError> {
+        let conn = self.get_conn()?;
         
-        Ok(())
+        tokio::task::spawn_blocking(move || {
+            conn.transaction::<_, AuthError, _>(|| {
+                lock_user_version(&conn, id, expected_version)?;
+                diesel::update(users::table.find(id))
+                    .set((
+                        users::mfa_enabled.eq(true),
+                        users::version.eq(users::version + 1),
+                        users::updated_at.eq(now),
+                    ))
+                    .execute(&conn)?;
+                Ok(())
+            })
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
     }
 
-    pub async fn disable_mfa(&self, id: Uuid) -> Result<User, AuthError> {
+    pub async fn disable_mfa(&self, id: Uuid, expected_version: Option<i32>) -> Result<User, AuthError> {
         let conn = self.get_conn()?;
         
         let user = tokio::task::spawn_blocking(move || {
-            diesel::update(users::table.find(id))
-                .set((
-                    users::mfa_enabled.eq(false),
-                    users::mfa_secret.eq::<Option<String>>(None),
-                    users::updated_at.eq(now),
-                ))
-                .get_result::<User>(&conn)
+            conn.transaction::<_, AuthError, _>(|| {
+                lock_user_version(&conn, id, expected_version)?;
+                let user = diesel::update(users::table.find(id))
+                    .set((
+                        users::mfa_enabled.eq(false),
+                        users::mfa_secret.eq::<Option<String>>(None),
+                        users::version.eq(users::version + 1),
+                        users::updated_at.eq(now),
+                    ))
+                    .get_result::<User>(&conn)?;
+                Ok(user)
+            })
         })
         .await
-        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
-        .map_err(|e| AuthError::DatabaseError(format!("Update error: {}", e)))?;
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))??;
         
         Ok(user)
     }
@@ -385,27 +705,83 @@ impl PostgresDb {
         Ok(session)
     }
 
-    pub async fn find_sessions_by_user_id(&self, user_id: Uuid) -> Result<Vec<Session>, AuthError> {
+    pub async fn find_sessions_by_user_id(
+        &self,
+        user_id: Uuid,
+        filter: &SessionFilter,
+        page: &PageRequest,
+    ) -> Result<(Vec<Session>, i64), AuthError> {
         let conn = self.get_conn()?;
+        let filter = filter.clone();
+        let page = page.clone();
         
-        let sessions = tokio::task::spawn_blocking(move || {
+        let result = tokio::task::spawn_blocking(move || {
+            let filtered = || {
+                let mut query = sessions::table
+                    .filter(sessions::user_id.eq(user_id))
+                    .filter(sessions::is_revoked.eq(false))
+                    .into_boxed();
+                if let Some(pinned) = filter.pinned {
+                    query = query.filter(sessions::is_pinned.eq(pinned));
+                }
+                if let Some(device_class) = filter.device_class.clone() {
+                    query = query.filter(sessions::device_class.eq(device_class));
+                }
+                query
+            };
+
+            let total = filtered().count().get_result::<i64>(&conn)?;
+
+            // Ties are broken by id so pages stay stable
+            let query = match (filter.sort, page.order) {
+                (SessionSort::CreatedAt, SortOrder::Asc) => filtered()
+                    .order((sessions::created_at.asc(), sessions::id.asc())),
+                (SessionSort::CreatedAt, SortOrder::Desc) => filtered()
+                    .order((sessions::created_at.desc(), sessions::id.desc())),
+                (SessionSort::ExpiresAt, SortOrder::Asc) => filtered()
+                    .order((sessions::expires_at.asc(), sessions::id.asc())),
+                (SessionSort::ExpiresAt, SortOrder::Desc) => filtered()
+                    .order((sessions::expires_at.desc(), sessions::id.desc())),
+            };
+
+            let sessions = query
+                .limit(page.limit)
+                .offset(page.offset)
+                .load::<Session>(&conn)?;
+
+            Ok::<_, diesel::result::Error>((sessions, total))
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Query error: {}", e)))?;
+        
+        Ok(result)
+    }
+
+    pub async fn find_session_devices(&self, user_id: Uuid) -> Result<Vec<DeviceInfo>, AuthError> {
+        let conn = self.get_conn()?;
+        
+        let devices = tokio::task::spawn_blocking(move || {
             sessions::table
                 .filter(sessions::user_id.eq(user_id))
-                .filter(sessions::is_revoked.eq(false))
-                .order(sessions::created_at.desc())
-                .load::<Session>(&conn)
+                .select((sessions::browser, sessions::os, sessions::device_class))
+                .distinct()
+                .load::<(Option<String>, Option<String>, Option<String>)>(&conn)
         })
         .await
         .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
         .map_err(|e| AuthError::DatabaseError(format!("Query error: {}", e)))?;
         
-        Ok(sessions)
+        Ok(devices
+            .into_iter()
+            .map(|(browser, os, device_class)| DeviceInfo { browser, os, device_class })
+            .collect())
     }
 
     pub async fn revoke_session(&self, id: Uuid) -> Result<(), AuthError> {
         let conn = self.get_conn()?;
         
-        tokio::task::spawn_blocking(move || {
+        let updated = tokio::task::spawn_blocking(move || {
             diesel::update(sessions::table.find(id))
                 .set((
                     sessions::is_revoked.eq(true),
@@ -417,14 +793,43 @@ impl PostgresDb {
         .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
         .map_err(|e| AuthError::DatabaseError(format!("Update error: {}", e)))?;
         
+        if updated == 0 {
+            return Err(AuthError::InvalidToken);
+        }
+        
         Ok(())
     }
 
-    pub async fn revoke_all_sessions(&self, user_id: Uuid) -> Result<(), AuthError> {
+    pub async fn update_session(&self, id: Uuid, changes: SessionChanges) -> Result<Session, AuthError> {
+        let conn = self.get_conn()?;
+        
+        let session = tokio::task::spawn_blocking(move || {
+            diesel::update(sessions::table.find(id))
+                .set((&changes, sessions::updated_at.eq(now)))
+                .get_result::<Session>(&conn)
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| match e {
+            diesel::result::Error::NotFound => AuthError::InvalidToken,
+            e => AuthError::DatabaseError(format!("Update error: {}", e)),
+        })?;
+        
+        Ok(session)
+    }
+
+    pub async fn revoke_all_sessions(&self, user_id: Uuid, include_pinned: bool) -> Result<(), AuthError> {
         let conn = self.get_conn()?;
         
         tokio::task::spawn_blocking(move || {
-            diesel::update(sessions::table.filter(sessions::user_id.eq(user_id)))
+            let mut query = diesel::update(sessions::table)
+                .filter(sessions::user_id.eq(user_id))
+                .into_boxed();
+            if !include_pinned {
+                query = query.filter(sessions::is_pinned.eq(false));
+            }
+
+            query
                 .set((
                     sessions::is_revoked.eq(true),
                     sessions::updated_at.eq(now),
@@ -438,40 +843,171 @@ impl PostgresDb {
         Ok(())
     }
 
-    // MFA Recovery codes methods
-    pub async fn create_recovery_code(&self, code: NewMfaRecoveryCode) -> Result<MfaRecoveryCode, AuthError> {
+    pub async fn touch_session(&self, id: Uuid, seen_at: DateTime<Utc>) -> Result<(), AuthError> {
         let conn = self.get_conn()?;
-        
-        let recovery_code = tokio::task::spawn_blocking(move || {
-            diesel::insert_into(mfa_recovery_codes::table)
-                .values(&code)
-                .get_result::<MfaRecoveryCode>(&conn)
+
+        tokio::task::spawn_blocking(move || {
+            diesel::update(sessions::table.find(id))
+                .filter(sessions::is_revoked.eq(false))
+                .set(sessions::last_seen_at.eq(seen_at))
+                .execute(&conn)
         })
         .await
         .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
-        .map_err(|e| AuthError::DatabaseError(format!("Insert error: {}", e)))?;
-        
-        Ok(recovery_code)
+        .map_err(|e| AuthError::DatabaseError(format!("Update error: {}", e)))?;
+
+        Ok(())
     }
 
-    pub async fn use_recovery_code(&self, user_id: Uuid, code: &str) -> Result<bool, AuthError> {
-        let code = code.to_string();
+    pub async fn revoke_idle_sessions(&self, user_id: Uuid, idle_since: DateTime<Utc>) -> Result<usize, AuthError> {
         let conn = self.get_conn()?;
-        
-        let result = tokio::task::spawn_blocking(move || {
-            conn.transaction(|| {
-                let recovery_code = mfa_recovery_codes::table
-                    .filter(mfa_recovery_codes::user_id.eq(user_id))
-                    .filter(mfa_recovery_codes::code.eq(&code))
-                    .filter(mfa_recovery_codes::is_used.eq(false))
-                    .first::<MfaRecoveryCode>(&conn)
-                    .optional()?;
-                
-                if let Some(recovery_code) = recovery_code {
-                    diesel::update(mfa_recovery_codes::table.find(recovery_code.id))
-                        .set((
-                            mfa_recovery_codes::is_used.eq(true),
-                            mfa_recovery_codes::used_at.eq(now),
+
+        let revoked = tokio::task::spawn_blocking(move || {
+            diesel::update(sessions::table)
+                .filter(sessions::user_id.eq(user_id))
+                .filter(sessions::is_revoked.eq(false))
+                .filter(sessions::last_seen_at.lt(idle_since))
+                .set((
+                    sessions::is_revoked.eq(true),
+                    sessions::updated_at.eq(now),
+                ))
+                .execute(&conn)
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Update error: {}", e)))?;
+
+        Ok(revoked)
+    }
+
+    pub async fn purge_sessions(&self, ended_before: DateTime<Utc>, limit: i64) -> Result<usize, AuthError> {
+        let conn = self.get_conn()?;
+
+        let purged = tokio::task::spawn_blocking(move || {
+            let ended = sessions::table
+                .filter(
+                    sessions::is_revoked
+                        .eq(true)
+                        .and(sessions::updated_at.lt(ended_before))
+                        .or(sessions::expires_at.lt(ended_before)),
+                )
+                .select(sessions::id)
+                .limit(limit);
+
+            diesel::delete(sessions::table.filter(sessions::id.eq_any(ended))).execute(&conn)
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Delete error: {}", e)))?;
+
+        Ok(purged)
+    }
+
+    // Expired revocations can't match a live token any more, so they're cleared
+    // out whenever new ones are written
+    pub async fn create_token_revocations(&self, revocations: Vec<NewTokenRevocation>) -> Result<(), AuthError> {
+        let conn = self.get_conn()?;
+
+        tokio::task::spawn_blocking(move || {
+            conn.transaction(|| {
+                diesel::delete(token_revocations::table.filter(token_revocations::expires_at.lt(Utc::now())))
+                    .execute(&conn)?;
+                diesel::insert_into(token_revocations::table)
+                    .values(&revocations)
+                    .on_conflict_do_nothing()
+                    .execute(&conn)
+            })
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Insert error: {}", e)))?;
+
+        Ok(())
+    }
+
+    pub async fn find_token_revocations(&self, since: DateTime<Utc>) -> Result<Vec<TokenRevocation>, AuthError> {
+        let conn = self.get_conn()?;
+
+        let revocations = tokio::task::spawn_blocking(move || {
+            token_revocations::table
+                .filter(token_revocations::revoked_at.ge(since))
+                .filter(token_revocations::expires_at.gt(Utc::now()))
+                .load::<TokenRevocation>(&conn)
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Query error: {}", e)))?;
+
+        Ok(revocations)
+    }
+
+    pub async fn session_table_stats(&self, now: DateTime<Utc>) -> Result<SessionTableStats, AuthError> {
+        let conn = self.get_conn()?;
+
+        let stats = tokio::task::spawn_blocking(move || {
+            let total = sessions::table.count().get_result::<i64>(&conn)?;
+            let revoked = sessions::table
+                .filter(sessions::is_revoked.eq(true))
+                .count()
+                .get_result::<i64>(&conn)?;
+            let expired = sessions::table
+                .filter(sessions::is_revoked.eq(false))
+                .filter(sessions::expires_at.le(now))
+                .count()
+                .get_result::<i64>(&conn)?;
+
+            Ok(SessionTableStats {
+                total,
+                live: total - revoked - expired,
+                revoked,
+                expired,
+            })
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e: diesel::result::Error| AuthError::DatabaseError(format!("Query error: {}", e)))?;
+
+        Ok(stats)
+    }
+
+    // MFA Recovery codes methods
+    pub async fn create_recovery_codes(&self, codes: Vec<NewMfaRecoveryCode>) -> Result<Vec<MfaRecoveryCode>, AuthError> {
+        if codes.is_empty() {
+            return Ok(Vec::new());
+        }
+        let conn = self.get_conn()?;
+        
+        // One multi-row INSERT rather than a round trip per code
+        let recovery_codes = tokio::task::spawn_blocking(move || {
+            diesel::insert_into(mfa_recovery_codes::table)
+                .values(&codes)
+                .get_results::<MfaRecoveryCode>(&conn)
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Insert error: {}", e)))?;
+        
+        Ok(recovery_codes)
+    }
+
+    pub async fn use_recovery_code(&self, user_id: Uuid, code: &str) -> Result<bool, AuthError> {
+        let code = code.to_string();
+        let conn = self.get_conn()?;
+        
+        let result = tokio::task::spawn_blocking(move || {
+            conn.transaction(|| {
+                let recovery_code = mfa_recovery_codes::table
+                    .filter(mfa_recovery_codes::user_id.eq(user_id))
+                    .filter(mfa_recovery_codes::code.eq(&code))
+                    .filter(mfa_recovery_codes::is_used.eq(false))
+                    .first::<MfaRecoveryCode>(&conn)
+                    .optional()?;
+                
+                if let Some(recovery_code) = recovery_code {
+                    diesel::update(mfa_recovery_codes::table.find(recovery_code.id))
+                        .set((
+                            mfa_recovery_codes::is_used.eq(true),
+                            mfa_recovery_codes::used_at.eq(now),
                         ))
                         .execute(&conn)?;
                     
@@ -488,6 +1024,21 @@ impl PostgresDb {
         Ok(result)
     }
 
+    pub async fn find_recovery_codes(&self, user_id: Uuid) -> Result<Vec<MfaRecoveryCode>, AuthError> {
+        let conn = self.get_conn()?;
+        
+        let codes = tokio::task::spawn_blocking(move || {
+            mfa_recovery_codes::table
+                .filter(mfa_recovery_codes::user_id.eq(user_id))
+                .load::<MfaRecoveryCode>(&conn)
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Query error: {}", e)))?;
+        
+        Ok(codes)
+    }
+
     pub async fn delete_recovery_codes(&self, user_id: Uuid) -> Result<(), AuthError> {
         let conn = self.get_conn()?;
         
@@ -501,4 +1052,2713 @@ impl PostgresDb {
         
         Ok(())
     }
+
+    // TOTP device methods
+    pub async fn create_totp_device(&self, device: NewTotpDevice) -> Result<TotpDevice, AuthError> {
+        let conn = self.get_conn()?;
+        
+        let device = tokio::task::spawn_blocking(move || {
+            diesel::insert_into(mfa_totp_devices::table)
+                .values(&device)
+                .get_result::<TotpDevice>(&conn)
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| match e {
+            diesel::result::Error::DatabaseError(
+                diesel::result::DatabaseErrorKind::UniqueViolation,
+                _,
+            ) => AuthError::ValidationError("A device with this name already exists".into()),
+            e => AuthError::DatabaseError(format!("Insert error: {}", e)),
+        })?;
+        
+        Ok(device)
+    }
+
+    pub async fn find_totp_devices_by_user_id(&self, user_id: Uuid) -> Result<Vec<TotpDevice>, AuthError> {
+        let conn = self.get_conn()?;
+        
+        let devices = tokio::task::spawn_blocking(move || {
+            mfa_totp_devices::table
+                .filter(mfa_totp_devices::user_id.eq(user_id))
+                .order(mfa_totp_devices::created_at.asc())
+                .load::<TotpDevice>(&conn)
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Query error: {}", e)))?;
+        
+        Ok(devices)
+    }
+
+    pub async fn confirm_totp_device(&self, id: Uuid) -> Result<(), AuthError> {
+        let conn = self.get_conn()?;
+        
+        tokio::task::spawn_blocking(move || {
+            diesel::update(mfa_totp_devices::table.find(id))
+                .set((
+                    mfa_totp_devices::is_confirmed.eq(true),
+                    mfa_totp_devices::confirmed_at.eq(now),
+                ))
+                .execute(&conn)
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Update error: {}", e)))?;
+        
+        Ok(())
+    }
+
+    pub async fn touch_totp_device(&self, id: Uuid) -> Result<(), AuthError> {
+        let conn = self.get_conn()?;
+        
+        tokio::task::spawn_blocking(move || {
+            diesel::update(mfa_totp_devices::table.find(id))
+                .set(mfa_totp_devices::last_used_at.eq(now))
+                .execute(&conn)
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Update error: {}", e)))?;
+        
+        Ok(())
+    }
+
+    pub async fn delete_totp_device(&self, id: Uuid) -> Result<(), AuthError> {
+        let conn = self.get_conn()?;
+        
+        tokio::task::spawn_blocking(move || {
+            diesel::delete(mfa_totp_devices::table.find(id)).execute(&conn)
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Delete error: {}", e)))?;
+        
+        Ok(())
+    }
+
+    pub async fn delete_totp_devices(&self, user_id: Uuid) -> Result<(), AuthError> {
+        let conn = self.get_conn()?;
+        
+        tokio::task::spawn_blocking(move || {
+            diesel::delete(mfa_totp_devices::table.filter(mfa_totp_devices::user_id.eq(user_id)))
+                .execute(&conn)
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Delete error: {}", e)))?;
+        
+        Ok(())
+    }
+
+    // MFA method preference methods
+    pub async fn find_mfa_method_order(&self, user_id: Uuid) -> Result<Vec<String>, AuthError> {
+        let conn = self.get_conn()?;
+        
+        let methods = tokio::task::spawn_blocking(move || {
+            mfa_method_preferences::table
+                .find(user_id)
+                .select(mfa_method_preferences::methods)
+                .first::<Vec<String>>(&conn)
+                .optional()
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Query error: {}", e)))?;
+        
+        Ok(methods.unwrap_or_default())
+    }
+
+    pub async fn save_mfa_method_order(&self, user_id: Uuid, methods: Vec<String>) -> Result<(), AuthError> {
+        let conn = self.get_conn()?;
+        
+        tokio::task::spawn_blocking(move || {
+            diesel::insert_into(mfa_method_preferences::table)
+                .values((
+                    mfa_method_preferences::user_id.eq(user_id),
+                    mfa_method_preferences::methods.eq(&methods),
+                ))
+                .on_conflict(mfa_method_preferences::user_id)
+                .do_update()
+                .set((
+                    mfa_method_preferences::methods.eq(&methods),
+                    mfa_method_preferences::updated_at.eq(now),
+                ))
+                .execute(&conn)
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Update error: {}", e)))?;
+        
+        Ok(())
+    }
+
+    // Passkey prompt methods
+    pub async fn find_passkey_prompt(&self, user_id: Uuid) -> Result<Option<PasskeyPromptState>, AuthError> {
+        let conn = self.get_conn()?;
+        
+        let state = tokio::task::spawn_blocking(move || {
+            passkey_prompts::table
+                .find(user_id)
+                .first::<PasskeyPromptState>(&conn)
+                .optional()
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Query error: {}", e)))?;
+        
+        Ok(state)
+    }
+
+    pub async fn record_passkey_prompt(&self, user_id: Uuid) -> Result<(), AuthError> {
+        let conn = self.get_conn()?;
+        
+        tokio::task::spawn_blocking(move || {
+            diesel::insert_into(passkey_prompts::table)
+                .values((
+                    passkey_prompts::user_id.eq(user_id),
+                    passkey_prompts::prompt_count.eq(1),
+                    passkey_prompts::last_prompted_at.eq(now),
+                ))
+                .on_conflict(passkey_prompts::user_id)
+                .do_update()
+                .set((
+                    passkey_prompts::prompt_count.eq(passkey_prompts::prompt_count + 1),
+                    passkey_prompts::last_prompted_at.eq(now),
+                ))
+                .execute(&conn)
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Update error: {}", e)))?;
+        
+        Ok(())
+    }
+
+    pub async fn dismiss_passkey_prompt(&self, user_id: Uuid) -> Result<(), AuthError> {
+        let conn = self.get_conn()?;
+        
+        tokio::task::spawn_blocking(move || {
+            diesel::insert_into(passkey_prompts::table)
+                .values((
+                    passkey_prompts::user_id.eq(user_id),
+                    passkey_prompts::dismissed_at.eq(now),
+                ))
+                .on_conflict(passkey_prompts::user_id)
+                .do_update()
+                .set(passkey_prompts::dismissed_at.eq(now))
+                .execute(&conn)
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Update error: {}", e)))?;
+        
+        Ok(())
+    }
+
+    // Backup email methods
+    pub async fn create_backup_email(&self, email: NewBackupEmail) -> Result<BackupEmail, AuthError> {
+        let conn = self.get_conn()?;
+        
+        let email = tokio::task::spawn_blocking(move || {
+            diesel::insert_into(user_emails::table)
+                .values(&email)
+                .get_result::<BackupEmail>(&conn)
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| match e {
+            diesel::result::Error::DatabaseError(
+                diesel::result::DatabaseErrorKind::UniqueViolation,
+                _,
+            ) => AuthError::EmailExists,
+            e => AuthError::DatabaseError(format!("Insert error: {}", e)),
+        })?;
+        
+        Ok(email)
+    }
+
+    pub async fn find_backup_emails_by_user_id(&self, user_id: Uuid) -> Result<Vec<BackupEmail>, AuthError> {
+        let conn = self.get_conn()?;
+        
+        let emails = tokio::task::spawn_blocking(move || {
+            user_emails::table
+                .filter(user_emails::user_id.eq(user_id))
+                .order(user_emails::created_at.asc())
+                .load::<BackupEmail>(&conn)
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Query error: {}", e)))?;
+        
+        Ok(emails)
+    }
+
+    pub async fn find_backup_email_by_address(&self, email: &str) -> Result<Option<BackupEmail>, AuthError> {
+        let email = email.to_string();
+        let conn = self.get_conn()?;
+        
+        let email = tokio::task::spawn_blocking(move || {
+            user_emails::table
+                .filter(lower(user_emails::email).eq(lower(email)))
+                .first::<BackupEmail>(&conn)
+                .optional()
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Query error: {}", e)))?;
+        
+        Ok(email)
+    }
+
+    pub async fn find_backup_email_by_token(&self, token: &str) -> Result<BackupEmail, AuthError> {
+        let token = token.to_string();
+        let conn = self.get_conn()?;
+        
+        let email = tokio::task::spawn_blocking(move || {
+            user_emails::table
+                .filter(user_emails::verification_token.eq(token))
+                .first::<BackupEmail>(&conn)
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| match e {
+            diesel::result::Error::NotFound => AuthError::InvalidToken,
+            e => AuthError::DatabaseError(format!("Query error: {}", e)),
+        })?;
+        
+        Ok(email)
+    }
+
+    pub async fn verify_backup_email(&self, id: Uuid) -> Result<BackupEmail, AuthError> {
+        let conn = self.get_conn()?;
+        
+        let email = tokio::task::spawn_blocking(move || {
+            diesel::update(user_emails::table.find(id))
+                .set((
+                    user_emails::is_verified.eq(true),
+                    user_emails::verification_token.eq::<Option<String>>(None),
+                    user_emails::verification_sent_at.eq::<Option<DateTime<Utc>>>(None),
+                    user_emails::verified_at.eq(now),
+                ))
+                .get_result::<BackupEmail>(&conn)
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Update error: {}", e)))?;
+        
+        Ok(email)
+    }
+
+    pub async fn delete_backup_email(&self, id: Uuid) -> Result<(), AuthError> {
+        let conn = self.get_conn()?;
+        
+        tokio::task::spawn_blocking(move || {
+            diesel::delete(user_emails::table.find(id)).execute(&conn)
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Delete error: {}", e)))?;
+        
+        Ok(())
+    }
+
+    // Account status methods
+    pub async fn set_account_status(
+        &self,
+        user_id: Uuid,
+        expected_version: Option<i32>,
+        status: AccountStatus,
+        reason: &str,
+        actor_id: Option<Uuid>,
+        event: NewOutboxEvent,
+    ) -> Result<User, AuthError> {
+        let reason = reason.to_string();
+        let conn = self.get_conn()?;
+        
+        // The status and its audit entry are written together or not at all
+        let user = tokio::task::spawn_blocking(move || {
+            conn.transaction::<_, AuthError, _>(|| {
+                lock_user_version(&conn, user_id, expected_version)?;
+                let user = diesel::update(users::table.find(user_id))
+                    .set((
+                        users::status.eq(status.as_str()),
+                        users::status_reason.eq(&reason),
+                        users::status_changed_by.eq(actor_id),
+                        users::status_changed_at.eq(now.nullable()),
+                        users::version.eq(users::version + 1),
+                        users::updated_at.eq(now),
+                    ))
+                    .get_result::<User>(&conn)?;
+                
+                diesel::insert_into(account_status_events::table)
+                    .values(&NewAccountStatusEvent {
+                        id: Uuid::new_v4(),
+                        user_id,
+                        status: status.as_str().to_string(),
+                        reason,
+                        actor_id,
+                    })
+                    .execute(&conn)?;
+                
+                diesel::insert_into(events_outbox::table).values(&event).execute(&conn)?;
+                
+                Ok(user)
+            })
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))??;
+        
+        Ok(user)
+    }
+
+    pub async fn find_account_status_events(
+        &self,
+        user_id: Uuid,
+        page: &PageRequest,
+    ) -> Result<(Vec<AccountStatusEvent>, i64), AuthError> {
+        let conn = self.get_conn()?;
+        let page = page.clone();
+
+        let result = tokio::task::spawn_blocking(move || {
+            let filtered = || account_status_events::table.filter(account_status_events::user_id.eq(user_id));
+
+            let total = filtered().count().get_result::<i64>(&conn)?;
+
+            // Ties are broken by id so pages stay stable
+            let events = match page.order {
+                SortOrder::Asc => filtered()
+                    .order((account_status_events::created_at.asc(), account_status_events::id.asc()))
+                    .limit(page.limit)
+                    .offset(page.offset)
+                    .load::<AccountStatusEvent>(&conn)?,
+                SortOrder::Desc => filtered()
+                    .order((account_status_events::created_at.desc(), account_status_events::id.desc()))
+                    .limit(page.limit)
+                    .offset(page.offset)
+                    .load::<AccountStatusEvent>(&conn)?,
+            };
+
+            Ok::<_, diesel::result::Error>((events, total))
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Query error: {}", e)))?;
+
+        Ok(result)
+    }
+
+    // Account risk methods
+    pub async fn record_account_risk_signal(&self, signal: NewAccountRiskSignal) -> Result<AccountRiskSignal, AuthError> {
+        let conn = self.get_conn()?;
+        
+        let signal = tokio::task::spawn_blocking(move || {
+            diesel::insert_into(account_risk_signals::table)
+                .values(&signal)
+                .get_result::<AccountRiskSignal>(&conn)
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Insert error: {}", e)))?;
+        
+        Ok(signal)
+    }
+
+    pub async fn find_account_risk_signals(
+        &self,
+        user_id: Uuid,
+        since: DateTime<Utc>,
+    ) -> Result<Vec<AccountRiskSignal>, AuthError> {
+        let conn = self.get_conn()?;
+        
+        let signals = tokio::task::spawn_blocking(move || {
+            account_risk_signals::table
+                .filter(account_risk_signals::user_id.eq(user_id))
+                .filter(account_risk_signals::created_at.ge(since))
+                .order(account_risk_signals::created_at.desc())
+                .load::<AccountRiskSignal>(&conn)
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Query error: {}", e)))?;
+        
+        Ok(signals)
+    }
+
+    pub async fn find_account_risk_signals_before(
+        &self,
+        before: DateTime<Utc>,
+        limit: i64,
+    ) -> Result<Vec<AccountRiskSignal>, AuthError> {
+        let conn = self.get_conn()?;
+
+        let signals = tokio::task::spawn_blocking(move || {
+            account_risk_signals::table
+                .filter(account_risk_signals::created_at.lt(before))
+                .order((account_risk_signals::created_at.asc(), account_risk_signals::id.asc()))
+                .limit(limit)
+                .load::<AccountRiskSignal>(&conn)
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Query error: {}", e)))?;
+
+        Ok(signals)
+    }
+
+    pub async fn delete_account_risk_signals(&self, ids: Vec<Uuid>) -> Result<usize, AuthError> {
+        let conn = self.get_conn()?;
+
+        let deleted = tokio::task::spawn_blocking(move || {
+            diesel::delete(account_risk_signals::table.filter(account_risk_signals::id.eq_any(ids))).execute(&conn)
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Delete error: {}", e)))?;
+
+        Ok(deleted)
+    }
+
+    pub async fn set_mfa_reenrollment_required(&self, user_id: Uuid, required: bool) -> Result<User, AuthError> {
+        let conn = self.get_conn()?;
+        
+        let user = tokio::task::spawn_blocking(move || {
+            diesel::update(users::table.find(user_id))
+                .set((
+                    users::mfa_reenrollment_required.eq(required),
+                    users::updated_at.eq(now),
+                ))
+                .get_result::<User>(&conn)
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Update error: {}", e)))?;
+        
+        Ok(user)
+    }
+
+    // Brute force methods
+    pub async fn record_failed_login(&self, user_id: Uuid, window_start: DateTime<Utc>) -> Result<i32, AuthError> {
+        let conn = self.get_conn()?;
+        
+        let count = tokio::task::spawn_blocking(move || {
+            conn.transaction(|| {
+                let (count, last_failed_at) = users::table
+                    .find(user_id)
+                    .select((users::failed_login_count, users::last_failed_login_at))
+                    .for_update()
+                    .first::<(i32, Option<DateTime<Utc>>)>(&conn)?;
+                // Failures from an earlier window don't carry over
+                let count = match last_failed_at {
+                    Some(at) if at >= window_start => count + 1,
+                    _ => 1,
+                };
+                
+                diesel::update(users::table.find(user_id))
+                    .set((
+                        users::failed_login_count.eq(count),
+                        users::last_failed_login_at.eq(now.nullable()),
+                    ))
+                    .execute(&conn)?;
+                
+                Ok(count)
+            })
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e: diesel::result::Error| match e {
+            diesel::result::Error::NotFound => AuthError::UserNotFound,
+            e => AuthError::DatabaseError(format!("Update error: {}", e)),
+        })?;
+        
+        Ok(count)
+    }
+
+    pub async fn lock_account(&self, user_id: Uuid, until: DateTime<Utc>) -> Result<(), AuthError> {
+        let conn = self.get_conn()?;
+        
+        tokio::task::spawn_blocking(move || {
+            diesel::update(users::table.find(user_id))
+                .set((
+                    users::failed_login_count.eq(0),
+                    users::locked_until.eq(Some(until)),
+                ))
+                .execute(&conn)
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Update error: {}", e)))?;
+        
+        Ok(())
+    }
+
+    pub async fn clear_failed_logins(&self, user_id: Uuid) -> Result<(), AuthError> {
+        let conn = self.get_conn()?;
+        
+        tokio::task::spawn_blocking(move || {
+            diesel::update(users::table.find(user_id))
+                .set((
+                    users::failed_login_count.eq(0),
+                    users::last_failed_login_at.eq::<Option<DateTime<Utc>>>(None),
+                    users::locked_until.eq::<Option<DateTime<Utc>>>(None),
+                ))
+                .execute(&conn)
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Update error: {}", e)))?;
+        
+        Ok(())
+    }
+
+    pub async fn find_locked_users(&self, now: DateTime<Utc>) -> Result<Vec<User>, AuthError> {
+        let conn = self.get_conn()?;
+
+        let users = tokio::task::spawn_blocking(move || {
+            users::table
+                .filter(users::locked_until.gt(now))
+                .order((users::locked_until.desc(), users::id.asc()))
+                .load::<User>(&conn)
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Query error: {}", e)))?;
+
+        Ok(users)
+    }
+
+    // Appeal methods
+    pub async fn create_account_appeal(&self, appeal: NewAccountAppeal) -> Result<AccountAppeal, AuthError> {
+        let conn = self.get_conn()?;
+        
+        let appeal = tokio::task::spawn_blocking(move || {
+            diesel::insert_into(account_appeals::table)
+                .values(&appeal)
+                .get_result::<AccountAppeal>(&conn)
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| match e {
+            diesel::result::Error::DatabaseError(
+                diesel::result::DatabaseErrorKind::UniqueViolation,
+                _,
+            ) => AuthError::ValidationError("An appeal is already pending".into()),
+            e => AuthError::DatabaseError(format!("Insert error: {}", e)),
+        })?;
+        
+        Ok(appeal)
+    }
+
+    pub async fn find_latest_account_appeal(&self, user_id: Uuid) -> Result<Option<AccountAppeal>, AuthError> {
+        let conn = self.get_conn()?;
+        
+        let appeal = tokio::task::spawn_blocking(move || {
+            account_appeals::table
+                .filter(account_appeals::user_id.eq(user_id))
+                .order(account_appeals::created_at.desc())
+                .first::<AccountAppeal>(&conn)
+                .optional()
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Query error: {}", e)))?;
+        
+        Ok(appeal)
+    }
+
+    pub async fn find_pending_account_appeals(&self) -> Result<Vec<AccountAppeal>, AuthError> {
+        let conn = self.get_conn()?;
+        
+        let appeals = tokio::task::spawn_blocking(move || {
+            account_appeals::table
+                .filter(account_appeals::status.eq("pending"))
+                .order(account_appeals::created_at.asc())
+                .load::<AccountAppeal>(&conn)
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Query error: {}", e)))?;
+        
+        Ok(appeals)
+    }
+
+    /// Close a pending appeal; `None` if it doesn't exist or was already resolved
+    pub async fn resolve_account_appeal(
+        &self,
+        id: Uuid,
+        accepted: bool,
+        note: &str,
+        admin_id: Uuid,
+    ) -> Result<Option<AccountAppeal>, AuthError> {
+        let note = note.to_string();
+        let conn = self.get_conn()?;
+        
+        let appeal = tokio::task::spawn_blocking(move || {
+            diesel::update(
+                account_appeals::table
+                    .find(id)
+                    .filter(account_appeals::status.eq("pending")),
+            )
+            .set((
+                account_appeals::status.eq(if accepted { "accepted" } else { "rejected" }),
+                account_appeals::resolution_note.eq(note),
+                account_appeals::resolved_by.eq(admin_id),
+                account_appeals::resolved_at.eq(now),
+            ))
+            .get_result::<AccountAppeal>(&conn)
+            .optional()
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Update error: {}", e)))?;
+        
+        Ok(appeal)
+    }
+
+    // Policy acceptance methods
+    pub async fn has_accepted_policy(&self, user_id: Uuid, version: &str) -> Result<bool, AuthError> {
+        let version = version.to_string();
+        let conn = self.get_conn()?;
+        
+        let accepted = tokio::task::spawn_blocking(move || {
+            diesel::select(diesel::dsl::exists(
+                policy_acceptances::table
+                    .filter(policy_acceptances::user_id.eq(user_id))
+                    .filter(policy_acceptances::version.eq(version)),
+            ))
+            .get_result::<bool>(&conn)
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Query error: {}", e)))?;
+        
+        Ok(accepted)
+    }
+
+    pub async fn record_policy_acceptance(&self, acceptance: NewPolicyAcceptance) -> Result<(), AuthError> {
+        let conn = self.get_conn()?;
+        
+        // Accepting the same version twice keeps the first record
+        tokio::task::spawn_blocking(move || {
+            diesel::insert_into(policy_acceptances::table)
+                .values(&acceptance)
+                .on_conflict((policy_acceptances::user_id, policy_acceptances::version))
+                .do_nothing()
+                .execute(&conn)
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Insert error: {}", e)))?;
+        
+        Ok(())
+    }
+
+    // Organization methods
+    pub async fn create_organization(
+        &self,
+        organization: NewOrganization,
+        owner_id: Uuid,
+    ) -> Result<Organization, AuthError> {
+        let conn = self.get_conn()?;
+        
+        // The creator becomes the owner in the same transaction
+        let organization = tokio::task::spawn_blocking(move || {
+            conn.transaction(|| {
+                let organization = diesel::insert_into(organizations::table)
+                    .values(&organization)
+                    .get_result::<Organization>(&conn)?;
+                
+                diesel::insert_into(organization_members::table)
+                    .values(&NewOrganizationMember {
+                        id: Uuid::new_v4(),
+                        organization_id: organization.id,
+                        user_id: owner_id,
+                        role: OrganizationRole::Owner.as_str().to_string(),
+                    })
+                    .execute(&conn)?;
+                
+                Ok(organization)
+            })
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e: diesel::result::Error| match e {
+            diesel::result::Error::DatabaseError(
+                diesel::result::DatabaseErrorKind::UniqueViolation,
+                _,
+            ) => AuthError::ValidationError("Organization slug is already taken".into()),
+            e => AuthError::DatabaseError(format!("Transaction error: {}", e)),
+        })?;
+        
+        Ok(organization)
+    }
+
+    pub async fn find_organization_by_id(&self, id: Uuid) -> Result<Option<Organization>, AuthError> {
+        let conn = self.get_conn()?;
+        
+        let organization = tokio::task::spawn_blocking(move || {
+            organizations::table
+                .find(id)
+                .first::<Organization>(&conn)
+                .optional()
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Query error: {}", e)))?;
+        
+        Ok(organization)
+    }
+
+    pub async fn find_organization_by_slug(&self, slug: &str) -> Result<Option<Organization>, AuthError> {
+        let conn = self.get_conn()?;
+        let slug = slug.to_string();
+        
+        let organization = tokio::task::spawn_blocking(move || {
+            organizations::table
+                .filter(organizations::slug.eq(slug))
+                .first::<Organization>(&conn)
+                .optional()
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Query error: {}", e)))?;
+        
+        Ok(organization)
+    }
+
+    pub async fn find_user_organizations(
+        &self,
+        user_id: Uuid,
+    ) -> Result<Vec<(Organization, OrganizationMember)>, AuthError> {
+        let conn = self.get_conn()?;
+        
+        let organizations = tokio::task::spawn_blocking(move || {
+            organizations::table
+                .inner_join(organization_members::table)
+                .filter(organization_members::user_id.eq(user_id))
+                .order(organizations::name.asc())
+                .load::<(Organization, OrganizationMember)>(&conn)
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Query error: {}", e)))?;
+        
+        Ok(organizations)
+    }
+
+    pub async fn find_organization_member(
+        &self,
+        organization_id: Uuid,
+        user_id: Uuid,
+    ) -> Result<Option<OrganizationMember>, AuthError> {
+        let conn = self.get_conn()?;
+        
+        let member = tokio::task::spawn_blocking(move || {
+            organization_members::table
+                .filter(organization_members::organization_id.eq(organization_id))
+                .filter(organization_members::user_id.eq(user_id))
+                .first::<OrganizationMember>(&conn)
+                .optional()
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Query error: {}", e)))?;
+        
+        Ok(member)
+    }
+
+    pub async fn add_organization_member(
+        &self,
+        member: NewOrganizationMember,
+    ) -> Result<OrganizationMember, AuthError> {
+        let conn = self.get_conn()?;
+        
+        let member = tokio::task::spawn_blocking(move || {
+            diesel::insert_into(organization_members::table)
+                .values(&member)
+                .get_result::<OrganizationMember>(&conn)
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Insert error: {}", e)))?;
+        
+        Ok(member)
+    }
+
+    pub async fn update_organization_member_role(
+        &self,
+        id: Uuid,
+        role: OrganizationRole,
+    ) -> Result<OrganizationMember, AuthError> {
+        let conn = self.get_conn()?;
+        
+        let member = tokio::task::spawn_blocking(move || {
+            diesel::update(organization_members::table.find(id))
+                .set((
+                    organization_members::role.eq(role.as_str()),
+                    organization_members::updated_at.eq(now),
+                ))
+                .get_result::<OrganizationMember>(&conn)
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Update error: {}", e)))?;
+        
+        Ok(member)
+    }
+
+    pub async fn find_organization_branding(
+        &self,
+        organization_id: Uuid,
+    ) -> Result<Option<OrganizationBranding>, AuthError> {
+        let conn = self.get_conn()?;
+        
+        let branding = tokio::task::spawn_blocking(move || {
+            organization_branding::table
+                .find(organization_id)
+                .first::<OrganizationBranding>(&conn)
+                .optional()
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Query error: {}", e)))?;
+        
+        Ok(branding)
+    }
+
+    pub async fn save_organization_branding(
+        &self,
+        branding: NewOrganizationBranding,
+    ) -> Result<OrganizationBranding, AuthError> {
+        let conn = self.get_conn()?;
+        
+        let branding = tokio::task::spawn_blocking(move || {
+            diesel::insert_into(organization_branding::table)
+                .values(&branding)
+                .on_conflict(organization_branding::organization_id)
+                .do_update()
+                .set((&branding, organization_branding::updated_at.eq(now)))
+                .get_result::<OrganizationBranding>(&conn)
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Insert error: {}", e)))?;
+        
+        Ok(branding)
+    }
+
+    // Organization domain methods
+    pub async fn find_organization_domains(
+        &self,
+        organization_id: Uuid,
+    ) -> Result<Vec<OrganizationDomain>, AuthError> {
+        let conn = self.get_conn()?;
+        
+        let domains = tokio::task::spawn_blocking(move || {
+            organization_domains::table
+                .filter(organization_domains::organization_id.eq(organization_id))
+                .order(organization_domains::domain.asc())
+                .load::<OrganizationDomain>(&conn)
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Query error: {}", e)))?;
+        
+        Ok(domains)
+    }
+
+    pub async fn find_organization_domain_by_id(&self, id: Uuid) -> Result<Option<OrganizationDomain>, AuthError> {
+        let conn = self.get_conn()?;
+        
+        let domain = tokio::task::spawn_blocking(move || {
+            organization_domains::table
+                .find(id)
+                .first::<OrganizationDomain>(&conn)
+                .optional()
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Query error: {}", e)))?;
+        
+        Ok(domain)
+    }
+
+    pub async fn find_verified_organization_domain(&self, domain: &str) -> Result<Option<OrganizationDomain>, AuthError> {
+        let domain = domain.to_string();
+        let conn = self.get_conn()?;
+        
+        let domain = tokio::task::spawn_blocking(move || {
+            organization_domains::table
+                .filter(organization_domains::domain.eq(domain))
+                .filter(organization_domains::verified_at.is_not_null())
+                .first::<OrganizationDomain>(&conn)
+                .optional()
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Query error: {}", e)))?;
+        
+        Ok(domain)
+    }
+
+    pub async fn create_organization_domain(
+        &self,
+        domain: NewOrganizationDomain,
+    ) -> Result<OrganizationDomain, AuthError> {
+        let conn = self.get_conn()?;
+        
+        let domain = tokio::task::spawn_blocking(move || {
+            diesel::insert_into(organization_domains::table)
+                .values(&domain)
+                .get_result::<OrganizationDomain>(&conn)
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| match e {
+            diesel::result::Error::DatabaseError(
+                diesel::result::DatabaseErrorKind::UniqueViolation,
+                _,
+            ) => AuthError::ValidationError("Domain has already been added".into()),
+            e => AuthError::DatabaseError(format!("Insert error: {}", e)),
+        })?;
+        
+        Ok(domain)
+    }
+
+    /// Record a DNS check; `verified_at` is kept once set
+    pub async fn record_organization_domain_check(
+        &self,
+        id: Uuid,
+        verified_at: Option<DateTime<Utc>>,
+    ) -> Result<OrganizationDomain, AuthError> {
+        let conn = self.get_conn()?;
+        
+        let domain = tokio::task::spawn_blocking(move || {
+            diesel::update(organization_domains::table.find(id))
+                .set((
+                    organization_domains::verified_at.eq(verified_at),
+                    organization_domains::last_checked_at.eq(now.nullable()),
+                ))
+                .get_result::<OrganizationDomain>(&conn)
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| match e {
+            diesel::result::Error::DatabaseError(
+                diesel::result::DatabaseErrorKind::UniqueViolation,
+                _,
+            ) => AuthError::ValidationError("Domain is already verified by another organization".into()),
+            e => AuthError::DatabaseError(format!("Update error: {}", e)),
+        })?;
+        
+        Ok(domain)
+    }
+
+    pub async fn set_organization_domain_auto_join(
+        &self,
+        id: Uuid,
+        auto_join: bool,
+    ) -> Result<OrganizationDomain, AuthError> {
+        let conn = self.get_conn()?;
+        
+        let domain = tokio::task::spawn_blocking(move || {
+            diesel::update(organization_domains::table.find(id))
+                .set(organization_domains::auto_join.eq(auto_join))
+                .get_result::<OrganizationDomain>(&conn)
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Update error: {}", e)))?;
+        
+        Ok(domain)
+    }
+
+    pub async fn delete_organization_domain(&self, id: Uuid) -> Result<(), AuthError> {
+        let conn = self.get_conn()?;
+        
+        tokio::task::spawn_blocking(move || {
+            diesel::delete(organization_domains::table.find(id)).execute(&conn)
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Delete error: {}", e)))?;
+        
+        Ok(())
+    }
+
+    // SSO connection methods
+    pub async fn find_sso_connection(&self, id: Uuid) -> Result<Option<SsoConnection>, AuthError> {
+        let conn = self.get_conn()?;
+        
+        let connection = tokio::task::spawn_blocking(move || {
+            sso_connections::table
+                .find(id)
+                .first::<SsoConnection>(&conn)
+                .optional()
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Query error: {}", e)))?;
+        
+        Ok(connection)
+    }
+
+    pub async fn find_sso_connection_by_organization(
+        &self,
+        organization_id: Uuid,
+    ) -> Result<Option<SsoConnection>, AuthError> {
+        let conn = self.get_conn()?;
+        
+        let connection = tokio::task::spawn_blocking(move || {
+            sso_connections::table
+                .filter(sso_connections::organization_id.eq(organization_id))
+                .first::<SsoConnection>(&conn)
+                .optional()
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Query error: {}", e)))?;
+        
+        Ok(connection)
+    }
+
+    pub async fn save_sso_connection(&self, connection: NewSsoConnection) -> Result<SsoConnection, AuthError> {
+        let conn = self.get_conn()?;
+        
+        let connection = tokio::task::spawn_blocking(move || {
+            diesel::insert_into(sso_connections::table)
+                .values(&connection)
+                .on_conflict(sso_connections::organization_id)
+                .do_update()
+                .set((&connection, sso_connections::updated_at.eq(now)))
+                .get_result::<SsoConnection>(&conn)
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Insert error: {}", e)))?;
+        
+        Ok(connection)
+    }
+
+    pub async fn delete_sso_connection(&self, organization_id: Uuid) -> Result<(), AuthError> {
+        let conn = self.get_conn()?;
+        
+        tokio::task::spawn_blocking(move || {
+            diesel::delete(
+                sso_connections::table.filter(sso_connections::organization_id.eq(organization_id)),
+            )
+            .execute(&conn)
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Delete error: {}", e)))?;
+        
+        Ok(())
+    }
+
+    // SSO identity methods
+    pub async fn find_sso_identity(
+        &self,
+        connection_id: Uuid,
+        subject: &str,
+    ) -> Result<Option<SsoIdentity>, AuthError> {
+        let subject = subject.to_string();
+        let conn = self.get_conn()?;
+        
+        let identity = tokio::task::spawn_blocking(move || {
+            sso_identities::table
+                .filter(sso_identities::connection_id.eq(connection_id))
+                .filter(sso_identities::subject.eq(subject))
+                .first::<SsoIdentity>(&conn)
+                .optional()
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Query error: {}", e)))?;
+        
+        Ok(identity)
+    }
+
+    pub async fn create_sso_identity(&self, identity: NewSsoIdentity) -> Result<SsoIdentity, AuthError> {
+        let conn = self.get_conn()?;
+        
+        let identity = tokio::task::spawn_blocking(move || {
+            diesel::insert_into(sso_identities::table)
+                .values(&identity)
+                .get_result::<SsoIdentity>(&conn)
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Insert error: {}", e)))?;
+        
+        Ok(identity)
+    }
+
+    pub async fn record_sso_login(&self, identity_id: Uuid) -> Result<(), AuthError> {
+        let conn = self.get_conn()?;
+        
+        tokio::task::spawn_blocking(move || {
+            diesel::update(sso_identities::table.find(identity_id))
+                .set(sso_identities::last_login_at.eq(now.nullable()))
+                .execute(&conn)
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Update error: {}", e)))?;
+        
+        Ok(())
+    }
+
+    // Action token methods
+    pub async fn redeem_action_token(&self, redemption: NewActionTokenRedemption) -> Result<(), AuthError> {
+        let conn = self.get_conn()?;
+        
+        tokio::task::spawn_blocking(move || {
+            diesel::insert_into(action_token_redemptions::table)
+                .values(&redemption)
+                .execute(&conn)
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| match e {
+            // Already used
+            diesel::result::Error::DatabaseError(
+                diesel::result::DatabaseErrorKind::UniqueViolation,
+                _,
+            ) => AuthError::InvalidToken,
+            e => AuthError::DatabaseError(format!("Insert error: {}", e)),
+        })?;
+        
+        Ok(())
+    }
+
+    pub async fn record_email_send(&self, send: NewEmailSend, prune_before: DateTime<Utc>) -> Result<(), AuthError> {
+        let conn = self.get_conn()?;
+        
+        tokio::task::spawn_blocking(move || {
+            conn.transaction(|| {
+                diesel::delete(
+                    email_sends::table
+                        .filter(email_sends::address.eq(&send.address))
+                        .filter(email_sends::kind.eq(&send.kind))
+                        .filter(email_sends::created_at.lt(prune_before)),
+                )
+                .execute(&conn)?;
+                
+                diesel::insert_into(email_sends::table).values(&send).execute(&conn)?;
+                
+                Ok(())
+            })
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e: diesel::result::Error| AuthError::DatabaseError(format!("Insert error: {}", e)))?;
+        
+        Ok(())
+    }
+
+    pub async fn find_email_sends_since(
+        &self,
+        address: &str,
+        kind: &str,
+        since: DateTime<Utc>,
+    ) -> Result<Vec<DateTime<Utc>>, AuthError> {
+        let address = address.to_string();
+        let kind = kind.to_string();
+        let conn = self.get_conn()?;
+        
+        let times = tokio::task::spawn_blocking(move || {
+            email_sends::table
+                .filter(email_sends::address.eq(address))
+                .filter(email_sends::kind.eq(kind))
+                .filter(email_sends::created_at.ge(since))
+                .order(email_sends::created_at.desc())
+                .select(email_sends::created_at)
+                .load::<DateTime<Utc>>(&conn)
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Query error: {}", e)))?;
+        
+        Ok(times)
+    }
+
+    // API key methods
+    pub async fn create_api_key(&self, key: NewApiKey) -> Result<ApiKey, AuthError> {
+        let conn = self.get_conn()?;
+        
+        let key = tokio::task::spawn_blocking(move || {
+            diesel::insert_into(api_keys::table)
+                .values(&key)
+                .get_result::<ApiKey>(&conn)
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Insert error: {}", e)))?;
+        
+        Ok(key)
+    }
+
+    pub async fn find_api_keys_by_user_id(&self, user_id: Uuid) -> Result<Vec<ApiKey>, AuthError> {
+        let conn = self.get_conn()?;
+        
+        let keys = tokio::task::spawn_blocking(move || {
+            api_keys::table
+                .filter(api_keys::user_id.eq(user_id))
+                .order(api_keys::created_at.asc())
+                .load::<ApiKey>(&conn)
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Query error: {}", e)))?;
+        
+        Ok(keys)
+    }
+
+    pub async fn find_api_key_by_hash(&self, key_hash: &str) -> Result<ApiKey, AuthError> {
+        let key_hash = key_hash.to_string();
+        let conn = self.get_conn()?;
+        
+        let key = tokio::task::spawn_blocking(move || {
+            api_keys::table
+                .filter(api_keys::key_hash.eq(key_hash))
+                .first::<ApiKey>(&conn)
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| match e {
+            diesel::result::Error::NotFound => AuthError::InvalidToken,
+            e => AuthError::DatabaseError(format!("Query error: {}", e)),
+        })?;
+        
+        Ok(key)
+    }
+
+    pub async fn find_api_key_by_id(&self, id: Uuid) -> Result<ApiKey, AuthError> {
+        let conn = self.get_conn()?;
+        
+        let key = tokio::task::spawn_blocking(move || {
+            api_keys::table
+                .find(id)
+                .first::<ApiKey>(&conn)
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| match e {
+            diesel::result::Error::NotFound => AuthError::ValidationError("API key not found".into()),
+            e => AuthError::DatabaseError(format!("Query error: {}", e)),
+        })?;
+        
+        Ok(key)
+    }
+
+    /// Count a request made with the key on `day`, returning the key's requests
+    /// on that day and since `month_start`
+    pub async fn record_api_key_request(
+        &self,
+        id: Uuid,
+        day: NaiveDate,
+        month_start: NaiveDate,
+    ) -> Result<(i64, i64), AuthError> {
+        let conn = self.get_conn()?;
+        
+        let counts = tokio::task::spawn_blocking(move || {
+            conn.transaction(|| {
+                diesel::update(api_keys::table.find(id))
+                    .set(api_keys::last_used_at.eq(now))
+                    .execute(&conn)?;
+                
+                let daily = diesel::insert_into(api_key_usage::table)
+                    .values((
+                        api_key_usage::api_key_id.eq(id),
+                        api_key_usage::day.eq(day),
+                        api_key_usage::request_count.eq(1i64),
+                    ))
+                    .on_conflict((api_key_usage::api_key_id, api_key_usage::day))
+                    .do_update()
+                    .set(api_key_usage::request_count.eq(api_key_usage::request_count + 1i64))
+                    .returning(api_key_usage::request_count)
+                    .get_result::<i64>(&conn)?;
+                
+                let month = api_key_usage::table
+                    .filter(api_key_usage::api_key_id.eq(id))
+                    .filter(api_key_usage::day.ge(month_start))
+                    .select(api_key_usage::request_count)
+                    .load::<i64>(&conn)?;
+                
+                Ok((daily, month.iter().sum()))
+            })
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e: diesel::result::Error| AuthError::DatabaseError(format!("Transaction error: {}", e)))?;
+        
+        Ok(counts)
+    }
+
+    /// Daily usage of the key since `since`, most recent first
+    pub async fn find_api_key_usage(&self, id: Uuid, since: NaiveDate) -> Result<Vec<ApiKeyUsage>, AuthError> {
+        let conn = self.get_conn()?;
+        
+        let usage = tokio::task::spawn_blocking(move || {
+            api_key_usage::table
+                .filter(api_key_usage::api_key_id.eq(id))
+                .filter(api_key_usage::day.ge(since))
+                .order(api_key_usage::day.desc())
+                .load::<ApiKeyUsage>(&conn)
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Query error: {}", e)))?;
+        
+        Ok(usage)
+    }
+
+    pub async fn update_api_key_quota(
+        &self,
+        id: Uuid,
+        daily_quota: Option<i64>,
+        monthly_quota: Option<i64>,
+    ) -> Result<ApiKey, AuthError> {
+        let conn = self.get_conn()?;
+        
+        let key = tokio::task::spawn_blocking(move || {
+            diesel::update(api_keys::table.find(id))
+                .set((
+                    api_keys::daily_quota.eq(daily_quota),
+                    api_keys::monthly_quota.eq(monthly_quota),
+                ))
+                .get_result::<ApiKey>(&conn)
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| match e {
+            diesel::result::Error::NotFound => AuthError::ValidationError("API key not found".into()),
+            e => AuthError::DatabaseError(format!("Update error: {}", e)),
+        })?;
+        
+        Ok(key)
+    }
+
+    pub async fn delete_api_key(&self, id: Uuid) -> Result<(), AuthError> {
+        let conn = self.get_conn()?;
+        
+        tokio::task::spawn_blocking(move || {
+            diesel::delete(api_keys::table.find(id)).execute(&conn)
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Delete error: {}", e)))?;
+        
+        Ok(())
+    }
+
+    // Canary methods
+    pub async fn create_canary_credential(&self, canary: NewCanaryCredential) -> Result<CanaryCredential, AuthError> {
+        let conn = self.get_conn()?;
+        
+        let canary = tokio::task::spawn_blocking(move || {
+            diesel::insert_into(canary_credentials::table)
+                .values(&canary)
+                .get_result::<CanaryCredential>(&conn)
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Insert error: {}", e)))?;
+        
+        Ok(canary)
+    }
+
+    pub async fn find_canary_credentials(&self) -> Result<Vec<CanaryCredential>, AuthError> {
+        let conn = self.get_conn()?;
+        
+        let canaries = tokio::task::spawn_blocking(move || {
+            canary_credentials::table
+                .order(canary_credentials::created_at.desc())
+                .load::<CanaryCredential>(&conn)
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Query error: {}", e)))?;
+        
+        Ok(canaries)
+    }
+
+    pub async fn find_canary_by_user_id(&self, user_id: Uuid) -> Result<Option<CanaryCredential>, AuthError> {
+        let conn = self.get_conn()?;
+        
+        let canary = tokio::task::spawn_blocking(move || {
+            canary_credentials::table
+                .filter(canary_credentials::user_id.eq(user_id))
+                .first::<CanaryCredential>(&conn)
+                .optional()
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Query error: {}", e)))?;
+        
+        Ok(canary)
+    }
+
+    pub async fn find_canary_by_api_key_id(&self, api_key_id: Uuid) -> Result<Option<CanaryCredential>, AuthError> {
+        let conn = self.get_conn()?;
+        
+        let canary = tokio::task::spawn_blocking(move || {
+            canary_credentials::table
+                .filter(canary_credentials::api_key_id.eq(api_key_id))
+                .first::<CanaryCredential>(&conn)
+                .optional()
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Query error: {}", e)))?;
+        
+        Ok(canary)
+    }
+
+    pub async fn record_canary_trip(&self, id: Uuid, ip: Option<String>) -> Result<CanaryCredential, AuthError> {
+        let conn = self.get_conn()?;
+        
+        let canary = tokio::task::spawn_blocking(move || {
+            diesel::update(canary_credentials::table.find(id))
+                .set((
+                    canary_credentials::trip_count.eq(canary_credentials::trip_count + 1),
+                    canary_credentials::last_tripped_at.eq(now),
+                    canary_credentials::last_tripped_ip.eq(ip),
+                ))
+                .get_result::<CanaryCredential>(&conn)
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Update error: {}", e)))?;
+        
+        Ok(canary)
+    }
+
+    pub async fn delete_canary_credential(&self, id: Uuid) -> Result<(), AuthError> {
+        let conn = self.get_conn()?;
+        
+        tokio::task::spawn_blocking(move || {
+            diesel::delete(canary_credentials::table.find(id)).execute(&conn)
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Delete error: {}", e)))?;
+        
+        Ok(())
+    }
+
+    // Notification preference methods
+    pub async fn find_notification_preferences(
+        &self,
+        user_id: Uuid,
+    ) -> Result<Option<NotificationPreferences>, AuthError> {
+        let conn = self.get_conn()?;
+        
+        let preferences = tokio::task::spawn_blocking(move || {
+            notification_preferences::table
+                .find(user_id)
+                .first::<NotificationPreferences>(&conn)
+                .optional()
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Query error: {}", e)))?;
+        
+        Ok(preferences)
+    }
+
+    pub async fn save_notification_preferences(
+        &self,
+        preferences: NewNotificationPreferences,
+    ) -> Result<NotificationPreferences, AuthError> {
+        let conn = self.get_conn()?;
+        
+        let preferences = tokio::task::spawn_blocking(move || {
+            diesel::insert_into(notification_preferences::table)
+                .values(&preferences)
+                .on_conflict(notification_preferences::user_id)
+                .do_update()
+                .set((&preferences, notification_preferences::updated_at.eq(now)))
+                .get_result::<NotificationPreferences>(&conn)
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Insert error: {}", e)))?;
+        
+        Ok(preferences)
+    }
+
+    // Feature flag methods
+    pub async fn save_feature_flag(&self, flag: NewFeatureFlag) -> Result<FeatureFlag, AuthError> {
+        let conn = self.get_conn()?;
+        
+        let flag = tokio::task::spawn_blocking(move || {
+            diesel::insert_into(feature_flags::table)
+                .values(&flag)
+                .on_conflict(feature_flags::key)
+                .do_update()
+                .set((&flag, feature_flags::updated_at.eq(now)))
+                .get_result::<FeatureFlag>(&conn)
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Insert error: {}", e)))?;
+        
+        Ok(flag)
+    }
+
+    pub async fn find_feature_flags(&self) -> Result<Vec<FeatureFlag>, AuthError> {
+        let conn = self.get_conn()?;
+        
+        let flags = tokio::task::spawn_blocking(move || {
+            feature_flags::table
+                .order(feature_flags::key.asc())
+                .load::<FeatureFlag>(&conn)
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Query error: {}", e)))?;
+        
+        Ok(flags)
+    }
+
+    pub async fn delete_feature_flag(&self, key: &str) -> Result<bool, AuthError> {
+        let conn = self.get_conn()?;
+        let key = key.to_string();
+        
+        let deleted = tokio::task::spawn_blocking(move || {
+            diesel::delete(feature_flags::table.find(key)).execute(&conn)
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Delete error: {}", e)))?;
+        
+        Ok(deleted > 0)
+    }
+
+    // Client application methods
+    pub async fn save_client_application(&self, client: NewClientApplication) -> Result<ClientApplication, AuthError> {
+        let conn = self.get_conn()?;
+        
+        let client = tokio::task::spawn_blocking(move || {
+            diesel::insert_into(client_applications::table)
+                .values(&client)
+                .on_conflict(client_applications::client_id)
+                .do_update()
+                .set((&client, client_applications::updated_at.eq(now)))
+                .get_result::<ClientApplication>(&conn)
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Insert error: {}", e)))?;
+        
+        Ok(client)
+    }
+
+    pub async fn find_client_application(&self, client_id: &str) -> Result<Option<ClientApplication>, AuthError> {
+        let conn = self.get_conn()?;
+        let client_id = client_id.to_string();
+        
+        let client = tokio::task::spawn_blocking(move || {
+            client_applications::table
+                .find(client_id)
+                .first::<ClientApplication>(&conn)
+                .optional()
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Query error: {}", e)))?;
+        
+        Ok(client)
+    }
+
+    pub async fn find_client_applications(&self) -> Result<Vec<ClientApplication>, AuthError> {
+        let conn = self.get_conn()?;
+        
+        let clients = tokio::task::spawn_blocking(move || {
+            client_applications::table
+                .order(client_applications::client_id.asc())
+                .load::<ClientApplication>(&conn)
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Query error: {}", e)))?;
+        
+        Ok(clients)
+    }
+
+    pub async fn delete_client_application(&self, client_id: &str) -> Result<bool, AuthError> {
+        let conn = self.get_conn()?;
+        let client_id = client_id.to_string();
+        
+        let deleted = tokio::task::spawn_blocking(move || {
+            diesel::delete(client_applications::table.find(client_id)).execute(&conn)
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Delete error: {}", e)))?;
+        
+        Ok(deleted > 0)
+    }
+
+    pub async fn revoke_client_sessions(&self, client_id: &str, user_id: Option<Uuid>) -> Result<Vec<Uuid>, AuthError> {
+        let conn = self.get_conn()?;
+        let client_id = client_id.to_string();
+        
+        let revoked = tokio::task::spawn_blocking(move || {
+            let mut query = diesel::update(sessions::table)
+                .filter(sessions::client_id.eq(client_id))
+                .filter(sessions::is_revoked.eq(false))
+                .into_boxed();
+            if let Some(user_id) = user_id {
+                query = query.filter(sessions::user_id.eq(user_id));
+            }
+
+            query
+                .set((
+                    sessions::is_revoked.eq(true),
+                    sessions::updated_at.eq(now),
+                ))
+                .returning(sessions::id)
+                .get_results::<Uuid>(&conn)
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Update error: {}", e)))?;
+        
+        Ok(revoked)
+    }
+
+    pub async fn save_client_consent(&self, consent: NewClientConsent) -> Result<ClientConsent, AuthError> {
+        let conn = self.get_conn()?;
+        
+        let consent = tokio::task::spawn_blocking(move || {
+            diesel::insert_into(client_consents::table)
+                .values(&consent)
+                .on_conflict((client_consents::user_id, client_consents::client_id))
+                .do_update()
+                .set((
+                    client_consents::scopes.eq(&consent.scopes),
+                    client_consents::updated_at.eq(now),
+                ))
+                .get_result::<ClientConsent>(&conn)
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Insert error: {}", e)))?;
+        
+        Ok(consent)
+    }
+
+    pub async fn find_client_consent(&self, user_id: Uuid, client_id: &str) -> Result<Option<ClientConsent>, AuthError> {
+        let conn = self.get_conn()?;
+        let client_id = client_id.to_string();
+        
+        let consent = tokio::task::spawn_blocking(move || {
+            client_consents::table
+                .find((user_id, client_id))
+                .first::<ClientConsent>(&conn)
+                .optional()
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Query error: {}", e)))?;
+        
+        Ok(consent)
+    }
+
+    pub async fn find_client_consents(&self, user_id: Uuid) -> Result<Vec<ClientConsent>, AuthError> {
+        let conn = self.get_conn()?;
+        
+        let consents = tokio::task::spawn_blocking(move || {
+            client_consents::table
+                .filter(client_consents::user_id.eq(user_id))
+                .order(client_consents::client_id.asc())
+                .load::<ClientConsent>(&conn)
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Query error: {}", e)))?;
+        
+        Ok(consents)
+    }
+
+    pub async fn delete_client_consent(&self, user_id: Uuid, client_id: &str) -> Result<bool, AuthError> {
+        let conn = self.get_conn()?;
+        let client_id = client_id.to_string();
+        
+        let deleted = tokio::task::spawn_blocking(move || {
+            diesel::delete(client_consents::table.find((user_id, client_id))).execute(&conn)
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Delete error: {}", e)))?;
+        
+        Ok(deleted > 0)
+    }
+
+    // Authenticator metadata methods
+    pub async fn replace_authenticator_metadata(
+        &self,
+        entries: Vec<NewAuthenticatorMetadata>,
+    ) -> Result<(), AuthError> {
+        let conn = self.get_conn()?;
+        
+        tokio::task::spawn_blocking(move || {
+            conn.transaction(|| {
+                diesel::delete(authenticator_metadata::table).execute(&conn)?;
+                // Well under the bind parameter limit per statement
+                for chunk in entries.chunks(1000) {
+                    diesel::insert_into(authenticator_metadata::table)
+                        .values(chunk)
+                        .execute(&conn)?;
+                }
+                Ok(())
+            })
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e: diesel::result::Error| AuthError::DatabaseError(format!("Insert error: {}", e)))
+    }
+
+    pub async fn find_authenticator_metadata(&self) -> Result<Vec<AuthenticatorMetadata>, AuthError> {
+        let conn = self.get_conn()?;
+        
+        let entries = tokio::task::spawn_blocking(move || {
+            authenticator_metadata::table.load::<AuthenticatorMetadata>(&conn)
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Query error: {}", e)))?;
+        
+        Ok(entries)
+    }
+
+    // Login freeze methods
+    pub async fn set_login_freeze(&self, freeze: NewLoginFreeze) -> Result<LoginFreeze, AuthError> {
+        let conn = self.get_conn()?;
+        
+        // The unique indexes are partial, so there's no conflict target to
+        // upsert on; replace the row instead
+        let freeze = tokio::task::spawn_blocking(move || {
+            conn.transaction(|| {
+                let same_scope = match freeze.organization_id {
+                    Some(organization_id) => diesel::delete(
+                        login_freezes::table.filter(login_freezes::organization_id.eq(organization_id)),
+                    )
+                    .execute(&conn),
+                    None => diesel::delete(login_freezes::table.filter(login_freezes::organization_id.is_null()))
+                        .execute(&conn),
+                };
+                same_scope?;
+
+                diesel::insert_into(login_freezes::table)
+                    .values(&freeze)
+                    .get_result::<LoginFreeze>(&conn)
+            })
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Insert error: {}", e)))?;
+        
+        Ok(freeze)
+    }
+
+    pub async fn clear_login_freeze(&self, organization_id: Option<Uuid>) -> Result<bool, AuthError> {
+        let conn = self.get_conn()?;
+        
+        let deleted = tokio::task::spawn_blocking(move || match organization_id {
+            Some(organization_id) => diesel::delete(
+                login_freezes::table.filter(login_freezes::organization_id.eq(organization_id)),
+            )
+            .execute(&conn),
+            None => diesel::delete(login_freezes::table.filter(login_freezes::organization_id.is_null()))
+                .execute(&conn),
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Delete error: {}", e)))?;
+        
+        Ok(deleted > 0)
+    }
+
+    pub async fn find_login_freezes(&self) -> Result<Vec<LoginFreeze>, AuthError> {
+        let conn = self.get_conn()?;
+        
+        let freezes = tokio::task::spawn_blocking(move || {
+            login_freezes::table
+                .order((login_freezes::organization_id.is_not_null(), login_freezes::created_at.desc()))
+                .load::<LoginFreeze>(&conn)
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Query error: {}", e)))?;
+        
+        Ok(freezes)
+    }
+
+    // Login policy methods
+    pub async fn find_login_policies(
+        &self,
+        user_id: Uuid,
+        organization_ids: Vec<Uuid>,
+    ) -> Result<Vec<LoginPolicy>, AuthError> {
+        let conn = self.get_conn()?;
+        
+        let policies = tokio::task::spawn_blocking(move || {
+            login_policies::table
+                .filter(
+                    login_policies::user_id
+                        .eq(user_id)
+                        .or(login_policies::organization_id.eq_any(organization_ids)),
+                )
+                .order((login_policies::organization_id.is_not_null(), login_policies::updated_at.desc()))
+                .load::<LoginPolicy>(&conn)
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Query error: {}", e)))?;
+        
+        Ok(policies)
+    }
+
+    pub async fn find_login_policy(&self, scope: LoginPolicyScope) -> Result<Option<LoginPolicy>, AuthError> {
+        let conn = self.get_conn()?;
+        
+        let policy = tokio::task::spawn_blocking(move || match scope {
+            LoginPolicyScope::User(user_id) => login_policies::table
+                .filter(login_policies::user_id.eq(user_id))
+                .first::<LoginPolicy>(&conn)
+                .optional(),
+            LoginPolicyScope::Organization(organization_id) => login_policies::table
+                .filter(login_policies::organization_id.eq(organization_id))
+                .first::<LoginPolicy>(&conn)
+                .optional(),
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Query error: {}", e)))?;
+        
+        Ok(policy)
+    }
+
+    pub async fn save_login_policy(&self, policy: NewLoginPolicy) -> Result<LoginPolicy, AuthError> {
+        let conn = self.get_conn()?;
+        
+        // A user or organization has one policy; saving replaces it
+        let policy = tokio::task::spawn_blocking(move || {
+            conn.transaction(|| {
+                let same_scope = match (policy.user_id, policy.organization_id) {
+                    (_, Some(organization_id)) => diesel::delete(
+                        login_policies::table.filter(login_policies::organization_id.eq(organization_id)),
+                    )
+                    .execute(&conn),
+                    (user_id, None) => diesel::delete(login_policies::table.filter(login_policies::user_id.eq(user_id)))
+                        .execute(&conn),
+                };
+                same_scope?;
+
+                diesel::insert_into(login_policies::table)
+                    .values(&policy)
+                    .get_result::<LoginPolicy>(&conn)
+            })
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Insert error: {}", e)))?;
+        
+        Ok(policy)
+    }
+
+    pub async fn delete_login_policy(&self, scope: LoginPolicyScope) -> Result<bool, AuthError> {
+        let conn = self.get_conn()?;
+        
+        let deleted = tokio::task::spawn_blocking(move || match scope {
+            LoginPolicyScope::User(user_id) => {
+                diesel::delete(login_policies::table.filter(login_policies::user_id.eq(user_id))).execute(&conn)
+            }
+            LoginPolicyScope::Organization(organization_id) => diesel::delete(
+                login_policies::table.filter(login_policies::organization_id.eq(organization_id)),
+            )
+            .execute(&conn),
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Delete error: {}", e)))?;
+        
+        Ok(deleted > 0)
+    }
+
+    // Security question methods
+    pub async fn find_security_questions(&self, user_id: Uuid) -> Result<Vec<SecurityQuestion>, AuthError> {
+        let conn = self.get_conn()?;
+        
+        let questions = tokio::task::spawn_blocking(move || {
+            security_questions::table
+                .filter(security_questions::user_id.eq(user_id))
+                .order(security_questions::position.asc())
+                .load::<SecurityQuestion>(&conn)
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Query error: {}", e)))?;
+        
+        Ok(questions)
+    }
+
+    pub async fn save_security_questions(
+        &self,
+        user_id: Uuid,
+        questions: Vec<NewSecurityQuestion>,
+    ) -> Result<Vec<SecurityQuestion>, AuthError> {
+        let conn = self.get_conn()?;
+        
+        // The new set replaces the old one whole
+        let questions = tokio::task::spawn_blocking(move || {
+            conn.transaction(|| {
+                diesel::delete(security_questions::table.filter(security_questions::user_id.eq(user_id)))
+                    .execute(&conn)?;
+
+                let mut saved = diesel::insert_into(security_questions::table)
+                    .values(&questions)
+                    .get_results::<SecurityQuestion>(&conn)?;
+                saved.sort_by_key(|q| q.position);
+                Ok(saved)
+            })
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e: diesel::result::Error| AuthError::DatabaseError(format!("Insert error: {}", e)))?;
+        
+        Ok(questions)
+    }
+
+    pub async fn delete_security_questions(&self, user_id: Uuid) -> Result<bool, AuthError> {
+        let conn = self.get_conn()?;
+        
+        let deleted = tokio::task::spawn_blocking(move || {
+            diesel::delete(security_questions::table.filter(security_questions::user_id.eq(user_id))).execute(&conn)
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Delete error: {}", e)))?;
+        
+        Ok(deleted > 0)
+    }
+
+    pub async fn find_security_question_failures(
+        &self,
+        user_id: Uuid,
+    ) -> Result<Option<SecurityQuestionFailures>, AuthError> {
+        let conn = self.get_conn()?;
+        
+        let failures = tokio::task::spawn_blocking(move || {
+            security_question_failures::table
+                .find(user_id)
+                .first::<SecurityQuestionFailures>(&conn)
+                .optional()
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Query error: {}", e)))?;
+        
+        Ok(failures)
+    }
+
+    pub async fn record_security_question_failure(
+        &self,
+        user_id: Uuid,
+        window_start: DateTime<Utc>,
+    ) -> Result<i32, AuthError> {
+        let conn = self.get_conn()?;
+        
+        let count = tokio::task::spawn_blocking(move || {
+            conn.transaction(|| {
+                let previous = security_question_failures::table
+                    .find(user_id)
+                    .for_update()
+                    .first::<SecurityQuestionFailures>(&conn)
+                    .optional()?;
+                // Failures from an earlier window don't carry over
+                let count = match previous {
+                    Some(failures) if failures.last_failed_at >= window_start => failures.failure_count + 1,
+                    _ => 1,
+                };
+                
+                diesel::insert_into(security_question_failures::table)
+                    .values((
+                        security_question_failures::user_id.eq(user_id),
+                        security_question_failures::failure_count.eq(count),
+                        security_question_failures::last_failed_at.eq(now),
+                    ))
+                    .on_conflict(security_question_failures::user_id)
+                    .do_update()
+                    .set((
+                        security_question_failures::failure_count.eq(count),
+                        security_question_failures::last_failed_at.eq(now),
+                    ))
+                    .execute(&conn)?;
+                
+                Ok(count)
+            })
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e: diesel::result::Error| AuthError::DatabaseError(format!("Update error: {}", e)))?;
+        
+        Ok(count)
+    }
+
+    pub async fn clear_security_question_failures(&self, user_id: Uuid) -> Result<(), AuthError> {
+        let conn = self.get_conn()?;
+        
+        tokio::task::spawn_blocking(move || {
+            diesel::delete(security_question_failures::table.find(user_id)).execute(&conn)
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Delete error: {}", e)))?;
+        
+        Ok(())
+    }
+
+    pub async fn find_mfa_failures(
+        &self,
+        user_id: Uuid,
+    ) -> Result<Option<MfaFailures>, AuthError> {
+        let conn = self.get_conn()?;
+        
+        let failures = tokio::task::spawn_blocking(move || {
+            mfa_failures::table
+                .find(user_id)
+                .first::<MfaFailures>(&conn)
+                .optional()
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Query error: {}", e)))?;
+        
+        Ok(failures)
+    }
+
+    pub async fn record_mfa_failure(
+        &self,
+        user_id: Uuid,
+        window_start: DateTime<Utc>,
+    ) -> Result<i32, AuthError> {
+        let conn = self.get_conn()?;
+        
+        let count = tokio::task::spawn_blocking(move || {
+            conn.transaction(|| {
+                let previous = mfa_failures::table
+                    .find(user_id)
+                    .for_update()
+                    .first::<MfaFailures>(&conn)
+                    .optional()?;
+                // Failures from an earlier window don't carry over
+                let count = match previous {
+                    Some(failures) if failures.last_failed_at >= window_start => failures.failure_count + 1,
+                    _ => 1,
+                };
+                
+                diesel::insert_into(mfa_failures::table)
+                    .values((
+                        mfa_failures::user_id.eq(user_id),
+                        mfa_failures::failure_count.eq(count),
+                        mfa_failures::last_failed_at.eq(now),
+                    ))
+                    .on_conflict(mfa_failures::user_id)
+                    .do_update()
+                    .set((
+                        mfa_failures::failure_count.eq(count),
+                        mfa_failures::last_failed_at.eq(now),
+                    ))
+                    .execute(&conn)?;
+                
+                Ok(count)
+            })
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e: diesel::result::Error| AuthError::DatabaseError(format!("Update error: {}", e)))?;
+        
+        Ok(count)
+    }
+
+    pub async fn clear_mfa_failures(&self, user_id: Uuid) -> Result<(), AuthError> {
+        let conn = self.get_conn()?;
+        
+        tokio::task::spawn_blocking(move || {
+            diesel::delete(mfa_failures::table.find(user_id)).execute(&conn)
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Delete error: {}", e)))?;
+        
+        Ok(())
+    }
+
+    // Bulk job methods
+    pub async fn create_bulk_job(&self, job: NewBulkJob) -> Result<BulkJob, AuthError> {
+        let conn = self.get_conn()?;
+        
+        let job = tokio::task::spawn_blocking(move || {
+            diesel::insert_into(bulk_jobs::table)
+                .values(&job)
+                .get_result::<BulkJob>(&conn)
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Insert error: {}", e)))?;
+        
+        Ok(job)
+    }
+
+    pub async fn find_bulk_job(&self, id: Uuid) -> Result<Option<BulkJob>, AuthError> {
+        let conn = self.get_conn()?;
+        
+        let job = tokio::task::spawn_blocking(move || {
+            bulk_jobs::table.find(id).first::<BulkJob>(&conn).optional()
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Query error: {}", e)))?;
+        
+        Ok(job)
+    }
+
+    pub async fn list_bulk_jobs(&self, limit: i64) -> Result<Vec<BulkJob>, AuthError> {
+        let conn = self.get_conn()?;
+        
+        let jobs = tokio::task::spawn_blocking(move || {
+            bulk_jobs::table
+                .order(bulk_jobs::created_at.desc())
+                .limit(limit)
+                .load::<BulkJob>(&conn)
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Query error: {}", e)))?;
+        
+        Ok(jobs)
+    }
+
+    pub async fn claim_bulk_job(&self, stale_before: DateTime<Utc>) -> Result<Option<BulkJob>, AuthError> {
+        let conn = self.get_conn()?;
+        
+        // Skipping locked rows lets several runners claim different jobs at once
+        let job = tokio::task::spawn_blocking(move || {
+            conn.transaction(|| {
+                let job = bulk_jobs::table
+                    .filter(
+                        bulk_jobs::status.eq(BulkJobStatus::Queued.as_str()).or(bulk_jobs::status
+                            .eq(BulkJobStatus::Running.as_str())
+                            .and(bulk_jobs::started_at.lt(stale_before))),
+                    )
+                    .order(bulk_jobs::created_at.asc())
+                    .for_update()
+                    .skip_locked()
+                    .first::<BulkJob>(&conn)
+                    .optional()?;
+                
+                match job {
+                    Some(job) => diesel::update(bulk_jobs::table.find(job.id))
+                        .set((
+                            bulk_jobs::status.eq(BulkJobStatus::Running.as_str()),
+                            bulk_jobs::started_at.eq(now.nullable()),
+                        ))
+                        .get_result::<BulkJob>(&conn)
+                        .map(Some),
+                    None => Ok(None),
+                }
+            })
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e: diesel::result::Error| AuthError::DatabaseError(format!("Update error: {}", e)))?;
+        
+        Ok(job)
+    }
+
+    pub async fn finish_bulk_job(
+        &self,
+        id: Uuid,
+        status: BulkJobStatus,
+        results: serde_json::Value,
+        error: Option<String>,
+    ) -> Result<(), AuthError> {
+        let conn = self.get_conn()?;
+        
+        tokio::task::spawn_blocking(move || {
+            diesel::update(bulk_jobs::table.find(id))
+                .set((
+                    bulk_jobs::status.eq(status.as_str()),
+                    bulk_jobs::results.eq(results),
+                    bulk_jobs::error.eq(error),
+                    bulk_jobs::finished_at.eq(now.nullable()),
+                ))
+                .execute(&conn)
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Update error: {}", e)))?;
+        
+        Ok(())
+    }
+
+    // User directory methods
+    pub async fn save_directory_entry(&self, entry: NewDirectoryEntry) -> Result<(), AuthError> {
+        let conn = self.get_conn()?;
+        
+        tokio::task::spawn_blocking(move || {
+            diesel::insert_into(user_directory::table)
+                .values(&entry)
+                .on_conflict(user_directory::user_id)
+                .do_update()
+                .set((
+                    user_directory::region.eq(&entry.region),
+                    user_directory::email_digest.eq(&entry.email_digest),
+                    user_directory::username_digest.eq(&entry.username_digest),
+                ))
+                .execute(&conn)
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| match e {
+            diesel::result::Error::DatabaseError(
+                diesel::result::DatabaseErrorKind::UniqueViolation,
+                _,
+            ) => AuthError::ValidationError("Username or email is already taken".into()),
+            e => AuthError::DatabaseError(format!("Insert error: {}", e)),
+        })?;
+        
+        Ok(())
+    }
+
+    pub async fn find_directory_entry(&self, user_id: Uuid) -> Result<Option<DirectoryEntry>, AuthError> {
+        let conn = self.get_conn()?;
+        
+        let entry = tokio::task::spawn_blocking(move || {
+            user_directory::table.find(user_id).first::<DirectoryEntry>(&conn).optional()
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Query error: {}", e)))?;
+        
+        Ok(entry)
+    }
+
+    pub async fn find_directory_entry_by_digest(&self, digest: &str) -> Result<Option<DirectoryEntry>, AuthError> {
+        let digest = digest.to_string();
+        let conn = self.get_conn()?;
+        
+        let entry = tokio::task::spawn_blocking(move || {
+            user_directory::table
+                .filter(
+                    user_directory::email_digest
+                        .eq(&digest)
+                        .or(user_directory::username_digest.eq(&digest)),
+                )
+                .first::<DirectoryEntry>(&conn)
+                .optional()
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Query error: {}", e)))?;
+        
+        Ok(entry)
+    }
+
+    pub async fn delete_directory_entry(&self, user_id: Uuid) -> Result<(), AuthError> {
+        let conn = self.get_conn()?;
+        
+        tokio::task::spawn_blocking(move || {
+            diesel::delete(user_directory::table.find(user_id)).execute(&conn)
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Delete error: {}", e)))?;
+        
+        Ok(())
+    }
+
+    // Schema methods
+    pub async fn latest_migration(&self) -> Result<Option<String>, AuthError> {
+        let conn = self.get_conn()?;
+        
+        let row = tokio::task::spawn_blocking(move || {
+            diesel::sql_query("SELECT MAX(version) AS version FROM __diesel_schema_migrations")
+                .get_result::<MigrationVersion>(&conn)
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Query error: {}", e)))?;
+        
+        Ok(row.version)
+    }
+
+    // Trusted device methods
+    pub async fn create_trusted_device(&self, device: NewTrustedDevice) -> Result<TrustedDevice, AuthError> {
+        let conn = self.get_conn()?;
+        
+        let device = tokio::task::spawn_blocking(move || {
+            diesel::insert_into(trusted_devices::table)
+                .values(&device)
+                .get_result::<TrustedDevice>(&conn)
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Insert error: {}", e)))?;
+        
+        Ok(device)
+    }
+
+    pub async fn use_trusted_device(&self, user_id: Uuid, token_hash: &str) -> Result<Option<TrustedDevice>, AuthError> {
+        let conn = self.get_conn()?;
+        let token_hash = token_hash.to_string();
+        
+        let device = tokio::task::spawn_blocking(move || {
+            diesel::update(
+                trusted_devices::table
+                    .filter(trusted_devices::user_id.eq(user_id))
+                    .filter(trusted_devices::token_hash.eq(token_hash))
+                    .filter(trusted_devices::expires_at.gt(now)),
+            )
+            .set(trusted_devices::last_used_at.eq(now))
+            .get_result::<TrustedDevice>(&conn)
+            .optional()
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Update error: {}", e)))?;
+        
+        Ok(device)
+    }
+
+    pub async fn delete_trusted_devices_by_user_id(&self, user_id: Uuid) -> Result<(), AuthError> {
+        let conn = self.get_conn()?;
+        
+        tokio::task::spawn_blocking(move || {
+            diesel::delete(trusted_devices::table.filter(trusted_devices::user_id.eq(user_id))).execute(&conn)
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Delete error: {}", e)))?;
+        
+        Ok(())
+    }
+
+    // Passkey methods
+    pub async fn create_passkey(&self, passkey: NewPasskey) -> Result<Passkey, AuthError> {
+        let conn = self.get_conn()?;
+        
+        let passkey = tokio::task::spawn_blocking(move || {
+            diesel::insert_into(webauthn_credentials::table)
+                .values(&passkey)
+                .get_result::<Passkey>(&conn)
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Insert error: {}", e)))?;
+        
+        Ok(passkey)
+    }
+
+    pub async fn find_passkeys_by_user_id(&self, user_id: Uuid) -> Result<Vec<Passkey>, AuthError> {
+        let conn = self.get_conn()?;
+        
+        let passkeys = tokio::task::spawn_blocking(move || {
+            webauthn_credentials::table
+                .filter(webauthn_credentials::user_id.eq(user_id))
+                .order(webauthn_credentials::created_at.desc())
+                .load::<Passkey>(&conn)
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Query error: {}", e)))?;
+        
+        Ok(passkeys)
+    }
+
+    // Proxy alias methods
+    pub async fn create_proxy_alias(&self, alias: NewProxyAlias) -> Result<ProxyAlias, AuthError> {
+        let conn = self.get_conn()?;
+        
+        let alias = tokio::task::spawn_blocking(move || {
+            diesel::insert_into(proxy_aliases::table)
+                .values(&alias)
+                .get_result::<ProxyAlias>(&conn)
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Insert error: {}", e)))?;
+        
+        Ok(alias)
+    }
+
+    pub async fn find_proxy_aliases_by_user_id(&self, user_id: Uuid) -> Result<Vec<ProxyAlias>, AuthError> {
+        let conn = self.get_conn()?;
+        
+        let aliases = tokio::task::spawn_blocking(move || {
+            proxy_aliases::table
+                .filter(proxy_aliases::user_id.eq(user_id))
+                .filter(proxy_aliases::status.ne(PROXY_ALIAS_DELETED))
+                .order(proxy_aliases::created_at.asc())
+                .load::<ProxyAlias>(&conn)
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Query error: {}", e)))?;
+        
+        Ok(aliases)
+    }
+
+    // Unit of work
+    /// Apply every write in one transaction; the first failure rolls back the rest
+    pub async fn commit(&self, work: UnitOfWork) -> Result<(), AuthError> {
+        let conn = self.get_conn()?;
+        
+        tokio::task::spawn_blocking(move || {
+            conn.transaction::<_, AuthError, _>(|| {
+                for write in work.into_writes() {
+                    apply_write(&conn, write)?;
+                }
+                Ok(())
+            })
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+    }
+
+    // Outbox methods
+    /// Undelivered events that are due, oldest first. Claimed events are leased
+    /// until `lease_until`, so other relays skip them while they're published.
+    pub async fn claim_outbox_events(
+        &self,
+        limit: i64,
+        max_attempts: i32,
+        lease_until: DateTime<Utc>,
+    ) -> Result<Vec<OutboxEvent>, AuthError> {
+        let conn = self.get_conn()?;
+        
+        let events = tokio::task::spawn_blocking(move || {
+            conn.transaction(|| {
+                let events = events_outbox::table
+                    .filter(events_outbox::delivered_at.is_null())
+                    .filter(events_outbox::next_attempt_at.le(now))
+                    .filter(events_outbox::attempts.lt(max_attempts))
+                    .order(events_outbox::created_at.asc())
+                    .limit(limit)
+                    .for_update()
+                    .skip_locked()
+                    .load::<OutboxEvent>(&conn)?;
+                
+                let ids: Vec<Uuid> = events.iter().map(|e| e.id).collect();
+                diesel::update(events_outbox::table.filter(events_outbox::id.eq_any(ids)))
+                    .set(events_outbox::next_attempt_at.eq(lease_until))
+                    .execute(&conn)?;
+                
+                Ok(events)
+            })
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e: diesel::result::Error| AuthError::DatabaseError(format!("Transaction error: {}", e)))?;
+        
+        Ok(events)
+    }
+
+    pub async fn mark_outbox_event_delivered(&self, id: Uuid) -> Result<(), AuthError> {
+        let conn = self.get_conn()?;
+        
+        tokio::task::spawn_blocking(move || {
+            diesel::update(events_outbox::table.find(id))
+                .set((
+                    events_outbox::delivered_at.eq(now.nullable()),
+                    events_outbox::attempts.eq(events_outbox::attempts + 1),
+                    events_outbox::last_error.eq::<Option<String>>(None),
+                ))
+                .execute(&conn)
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Update error: {}", e)))?;
+        
+        Ok(())
+    }
+
+    pub async fn record_outbox_event_failure(
+        &self,
+        id: Uuid,
+        error: &str,
+        retry_at: DateTime<Utc>,
+    ) -> Result<(), AuthError> {
+        let error = error.to_string();
+        let conn = self.get_conn()?;
+        
+        tokio::task::spawn_blocking(move || {
+            diesel::update(events_outbox::table.find(id))
+                .set((
+                    events_outbox::attempts.eq(events_outbox::attempts + 1),
+                    events_outbox::last_error.eq(Some(error)),
+                    events_outbox::next_attempt_at.eq(retry_at),
+                ))
+                .execute(&conn)
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Update error: {}", e)))?;
+        
+        Ok(())
+    }
+
+    pub async fn find_delivered_outbox_events(&self, before: DateTime<Utc>, limit: i64) -> Result<Vec<OutboxEvent>, AuthError> {
+        let conn = self.get_conn()?;
+
+        let events = tokio::task::spawn_blocking(move || {
+            events_outbox::table
+                .filter(events_outbox::delivered_at.is_not_null())
+                .filter(events_outbox::created_at.lt(before))
+                .order((events_outbox::created_at.asc(), events_outbox::id.asc()))
+                .limit(limit)
+                .load::<OutboxEvent>(&conn)
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Query error: {}", e)))?;
+
+        Ok(events)
+    }
+
+    pub async fn find_outbox_events(
+        &self,
+        filter: &AuditEventFilter,
+        page: &PageRequest,
+    ) -> Result<(Vec<OutboxEvent>, i64), AuthError> {
+        let conn = self.get_conn()?;
+        let filter = filter.clone();
+        let page = page.clone();
+
+        let result = tokio::task::spawn_blocking(move || {
+            let filtered = || {
+                let mut query = events_outbox::table.into_boxed();
+                if let Some(user_id) = filter.user_id {
+                    query = query.filter(events_outbox::aggregate_id.eq(user_id));
+                }
+                if let Some(event_type) = filter.event_type.clone() {
+                    query = query.filter(events_outbox::event_type.eq(event_type));
+                }
+                query
+            };
+
+            let total = filtered().count().get_result::<i64>(&conn)?;
+
+            // Ties are broken by id so pages stay stable
+            let query = match page.order {
+                SortOrder::Asc => filtered().order((events_outbox::created_at.asc(), events_outbox::id.asc())),
+                SortOrder::Desc => filtered().order((events_outbox::created_at.desc(), events_outbox::id.desc())),
+            };
+            let events = query
+                .limit(page.limit)
+                .offset(page.offset)
+                .load::<OutboxEvent>(&conn)?;
+
+            Ok::<_, diesel::result::Error>((events, total))
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Query error: {}", e)))?;
+
+        Ok(result)
+    }
+
+    pub async fn delete_outbox_events(&self, ids: Vec<Uuid>) -> Result<usize, AuthError> {
+        let conn = self.get_conn()?;
+
+        let deleted = tokio::task::spawn_blocking(move || {
+            diesel::delete(events_outbox::table.filter(events_outbox::id.eq_any(ids))).execute(&conn)
+        })
+        .await
+        .map_err(|e| AuthError::DatabaseError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::DatabaseError(format!("Delete error: {}", e)))?;
+
+        Ok(deleted)
+    }
+}
+
+// Lock the user's row for the rest of the transaction, refusing the write if
+// it was changed since the caller read `expected_version`
+// `%term%` for LIKE, with the term's own wildcards matched literally
+fn like_pattern(term: &str) -> String {
+    let escaped = term
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+    format!("%{}%", escaped)
+}
+
+fn lock_user_version(conn: &PgConn, id: Uuid, expected_version: Option<i32>) -> Result<(), AuthError> {
+    let version = users::table
+        .find(id)
+        .select(users::version)
+        .for_update()
+        .first::<i32>(conn)?;
+    match expected_version {
+        Some(expected) if expected != version => Err(AuthError::VersionConflict),
+        _ => Ok(()),
+    }
+}
+
+fn apply_write(conn: &PgConn, write: Write) -> Result<(), AuthError> {
+    match write {
+        Write::UpdatePassword { user_id, password_hash, expires_at } => {
+            let updated = diesel::update(users::table.find(user_id))
+                .set((
+                    users::password_hash.eq(password_hash),
+                    users::password_reset_token.eq::<Option<String>>(None),
+                    users::password_reset_sent_at.eq::<Option<DateTime<Utc>>>(None),
+                    users::password_reset_email.eq::<Option<String>>(None),
+                    users::password_expires_at.eq(expires_at),
+                    users::updated_at.eq(now),
+                ))
+                .execute(conn)?;
+            if updated == 0 {
+                return Err(AuthError::UserNotFound);
+            }
+        }
+        Write::CreateSession(session) => {
+            diesel::insert_into(sessions::table).values(&session).execute(conn)?;
+        }
+        Write::RevokeSession(id) => {
+            let updated = diesel::update(sessions::table.find(id))
+                .filter(sessions::is_revoked.eq(false))
+                .set((
+                    sessions::is_revoked.eq(true),
+                    sessions::updated_at.eq(now),
+                ))
+                .execute(conn)?;
+            if updated == 0 {
+                return Err(AuthError::InvalidToken);
+            }
+        }
+        Write::RevokeAllSessions { user_id, include_pinned } => {
+            let mut query = diesel::update(sessions::table)
+                .filter(sessions::user_id.eq(user_id))
+                .into_boxed();
+            if !include_pinned {
+                query = query.filter(sessions::is_pinned.eq(false));
+            }
+            query
+                .set((
+                    sessions::is_revoked.eq(true),
+                    sessions::updated_at.eq(now),
+                ))
+                .execute(conn)?;
+        }
+        Write::Event(event) => {
+            diesel::insert_into(events_outbox::table).values(&event).execute(conn)?;
+        }
+    }
+    Ok(())
 }
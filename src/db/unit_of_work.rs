@@ -0,0 +1,78 @@
+//! Writes that have to land together. A service collects them in a
+//! `UnitOfWork` and hands it to `DatabaseConnection::commit`: either every
+//! write is applied or, if one fails, none of them are.
+
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+use crate::models::{NewOutboxEvent, NewSession};
+
+pub(crate) enum Write {
+    UpdatePassword {
+        user_id: Uuid,
+        password_hash: String,
+        expires_at: Option<DateTime<Utc>>,
+    },
+    CreateSession(NewSession),
+    RevokeSession(Uuid),
+    RevokeAllSessions {
+        user_id: Uuid,
+        include_pinned: bool,
+    },
+    Event(NewOutboxEvent),
+}
+
+/// Writes applied in the order they were added
+#[derive(Default)]
+pub struct UnitOfWork {
+    writes: Vec<Write>,
+}
+
+impl UnitOfWork {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the password, clear any reset token and restart the expiry clock.
+    /// Fails with `UserNotFound` for an unknown user.
+    pub fn update_password(mut self, user_id: Uuid, password_hash: &str, expires_at: Option<DateTime<Utc>>) -> Self {
+        self.writes.push(Write::UpdatePassword {
+            user_id,
+            password_hash: password_hash.to_string(),
+            expires_at,
+        });
+        self
+    }
+
+    pub fn create_session(mut self, session: NewSession) -> Self {
+        self.writes.push(Write::CreateSession(session));
+        self
+    }
+
+    /// Revoke a live session. Fails with `InvalidToken` if it's unknown or
+    /// already revoked, so two requests racing to rotate the same refresh
+    /// token can't both succeed.
+    pub fn revoke_session(mut self, id: Uuid) -> Self {
+        self.writes.push(Write::RevokeSession(id));
+        self
+    }
+
+    pub fn revoke_all_sessions(mut self, user_id: Uuid, include_pinned: bool) -> Self {
+        self.writes.push(Write::RevokeAllSessions { user_id, include_pinned });
+        self
+    }
+
+    /// Write an event to the outbox; it is only relayed if the rest commits
+    pub fn event(mut self, event: NewOutboxEvent) -> Self {
+        self.writes.push(Write::Event(event));
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.writes.is_empty()
+    }
+
+    pub(crate) fn into_writes(self) -> Vec<Write> {
+        self.writes
+    }
+}
@@ -1,7 +1,27 @@
 use actix_web::{http::StatusCode, HttpResponse, ResponseError};
 use serde::Serialize;
 use std::fmt;
+use std::sync::OnceLock;
 use thiserror::Error;
+use uuid::Uuid;
+
+use crate::accessibility::CaptchaChallenge;
+use crate::config::ErrorFormatConfig;
+use crate::utils::i18n::Translator;
+use crate::utils::secret::scrub;
+
+// Error format settings, installed once at startup from `Config`
+static ERROR_FORMAT: OnceLock<ErrorFormatConfig> = OnceLock::new();
+
+/// Install the error response format used by `AuthError::error_response`.
+/// The first call wins; later ones are ignored.
+pub fn init_error_format(config: &ErrorFormatConfig) {
+    let _ = ERROR_FORMAT.set(config.clone());
+}
+
+fn error_format() -> ErrorFormatConfig {
+    ERROR_FORMAT.get().cloned().unwrap_or_default()
+}
 
 #[derive(Error, Debug)]
 pub enum AuthError {
@@ -24,7 +44,7 @@ pub enum AuthError {
     TokenExpired,
     
     #[error("Email not verified")]
-    EmailNotVerified,
+    EmailNotVerified { resend_token: Option<String> },
     
     #[error("Invalid verification code")]
     InvalidVerificationCode,
@@ -41,12 +61,81 @@ pub enum AuthError {
     #[error("Validation error: {0}")]
     ValidationError(String),
     
+    #[error("Validation error: {0}")]
+    InvalidFields(validator::ValidationErrors),
+    
     #[error("Rate limit exceeded")]
-    RateLimitExceeded,
+    RateLimitExceeded { limit: u32, retry_after: u64 },
+    
+    #[error("API key quota exceeded")]
+    QuotaExceeded { limit: u64, reset_at: i64 },
+    
+    #[error("A request with this Idempotency-Key is already in progress")]
+    IdempotencyConflict,
+    
+    #[error("The account was changed by another request; reload it and try again")]
+    VersionConflict,
     
     #[error("Permission denied")]
     PermissionDenied,
     
+    #[error("Missing required scope: {required}")]
+    InsufficientScope { required: String },
+    
+    #[error("Account is disabled")]
+    AccountDisabled { status_token: Option<String> },
+    
+    #[error("Account is archived")]
+    AccountArchived,
+    
+    #[error("Logins are frozen: {message}")]
+    LoginsFrozen { message: String },
+    
+    #[error("Login not allowed from here at this time")]
+    LoginNotAllowed,
+    
+    #[error("Account is temporarily locked")]
+    AccountLocked { retry_after: u64 },
+    
+    #[error("Too many emails sent to this address")]
+    EmailResendThrottled { retry_after: u64 },
+    
+    #[error("Password reset required")]
+    PasswordResetRequired,
+    
+    #[error("Recent authentication required")]
+    ReauthenticationRequired { max_age: u64 },
+    
+    #[error("Login is waiting for approval")]
+    LoginApprovalPending,
+    
+    #[error("Refresh from a different network requires verification")]
+    NetworkChanged,
+    
+    #[error("Single sign-on required")]
+    SsoRequired,
+    
+    #[error("Single sign-on failed: {0}")]
+    SsoError(String),
+    
+    #[error("CAPTCHA required")]
+    CaptchaRequired { challenge: Option<CaptchaChallenge> },
+    
+    #[error("Invalid or expired CAPTCHA answer")]
+    InvalidCaptcha,
+    
+    #[error("Voice command not recognized")]
+    VoiceCommandNotRecognized,
+    
+    #[error("Speech recognition is unavailable")]
+    SpeechRecognitionUnavailable,
+    
+    #[error("Authenticator not allowed: {0}")]
+    AuthenticatorNotAllowed(String),
+    
+    #[error("Upload is too large")]
+    PayloadTooLarge { limit: usize },
+    
     #[error("Email error: {0}")]
     EmailError(String),
     
@@ -60,13 +149,28 @@ impl AuthError {
             Self::InvalidCredentials | Self::InvalidToken | Self::TokenExpired | Self::InvalidMfaCode | Self::InvalidVerificationCode => {
                 StatusCode::UNAUTHORIZED
             }
+            Self::ReauthenticationRequired { .. } | Self::SsoError(_) | Self::NetworkChanged => StatusCode::UNAUTHORIZED,
             Self::UserNotFound => StatusCode::NOT_FOUND,
-            Self::EmailExists | Self::UsernameExists | Self::ValidationError(_) => {
+            Self::EmailExists | Self::UsernameExists | Self::ValidationError(_) | Self::InvalidFields(_) => {
                 StatusCode::BAD_REQUEST
             }
-            Self::MfaRequired | Self::EmailNotVerified => StatusCode::FORBIDDEN,
-            Self::RateLimitExceeded => StatusCode::TOO_MANY_REQUESTS,
-            Self::PermissionDenied => StatusCode::FORBIDDEN,
+            Self::CaptchaRequired { .. } | Self::InvalidCaptcha => StatusCode::BAD_REQUEST,
+            Self::VoiceCommandNotRecognized => StatusCode::UNPROCESSABLE_ENTITY,
+            Self::SpeechRecognitionUnavailable | Self::LoginsFrozen { .. } => StatusCode::SERVICE_UNAVAILABLE,
+            Self::PayloadTooLarge { .. } => StatusCode::PAYLOAD_TOO_LARGE,
+            Self::MfaRequired | Self::EmailNotVerified { .. } | Self::LoginApprovalPending => {
+                StatusCode::FORBIDDEN
+            }
+            Self::RateLimitExceeded { .. } | Self::QuotaExceeded { .. } => StatusCode::TOO_MANY_REQUESTS,
+            Self::IdempotencyConflict | Self::VersionConflict => StatusCode::CONFLICT,
+            Self::AccountLocked { .. } => StatusCode::LOCKED,
+            Self::EmailResendThrottled { .. } => StatusCode::TOO_MANY_REQUESTS,
+            Self::PermissionDenied | Self::AccountDisabled { .. } | Self::PasswordResetRequired => {
+                StatusCode::FORBIDDEN
+            }
+            Self::SsoRequired | Self::InsufficientScope { .. } | Self::AccountArchived => StatusCode::FORBIDDEN,
+            Self::LoginNotAllowed => StatusCode::FORBIDDEN,
+            Self::AuthenticatorNotAllowed(_) => StatusCode::FORBIDDEN,
             Self::DatabaseError(_) | Self::EmailError(_) | Self::InternalServerError(_) => {
                 StatusCode::INTERNAL_SERVER_ERROR
             }
@@ -79,6 +183,72 @@ struct ErrorResponse {
     error: String,
     message: String,
     status_code: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    retry_after: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reset_at: Option<i64>,
+    #[serde(flatten)]
+    verification: Option<VerificationHint>,
+    #[serde(flatten)]
+    account_status: Option<AccountStatusHint>,
+    #[serde(flatten)]
+    captcha: Option<CaptchaHint>,
+}
+
+/// Where an unverified user can request a new verification email
+#[derive(Serialize)]
+struct VerificationHint {
+    resend_endpoint: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    resend_token: Option<String>, // `email_unverified` token accepted by the resend endpoint
+}
+
+/// Where a suspended or banned user can see why, and appeal
+#[derive(Serialize)]
+struct AccountStatusHint {
+    status_endpoint: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    status_token: Option<String>, // `account_status` token accepted by the status and appeal endpoints
+}
+
+/// Where to get a CAPTCHA, and the one issued for this attempt if there is one
+#[derive(Serialize)]
+struct CaptchaHint {
+    captcha_endpoint: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    captcha_challenge: Option<CaptchaChallenge>,
+}
+
+/// RFC 7807 problem details body
+#[derive(Serialize)]
+struct ProblemDetails {
+    #[serde(rename = "type")]
+    problem_type: String,
+    title: String,
+    status: u16,
+    detail: String,
+    code: String,
+    trace_id: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    errors: Vec<FieldError>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    retry_after: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reset_at: Option<i64>,
+    #[serde(flatten)]
+    verification: Option<VerificationHint>,
+    #[serde(flatten)]
+    account_status: Option<AccountStatusHint>,
+    #[serde(flatten)]
+    captcha: Option<CaptchaHint>,
+}
+
+/// A single field-level validation failure
+#[derive(Serialize)]
+struct FieldError {
+    field: String,
+    code: String,
+    message: Option<String>,
 }
 
 impl ResponseError for AuthError {
@@ -87,13 +257,132 @@ impl ResponseError for AuthError {
     }
 
     fn error_response(&self) -> HttpResponse {
+        self.render(self.to_string())
+    }
+}
+
+impl AuthError {
+    /// Build the error response with the message translated for `locale`
+    pub fn localized_response(&self, translator: &Translator, locale: &str) -> HttpResponse {
+        self.render(self.localized_message(translator, locale))
+    }
+
+    /// The error's message translated for `locale`
+    pub fn localized_message(&self, translator: &Translator, locale: &str) -> String {
+        let mut args = fluent_bundle::FluentArgs::new();
+        args.set("detail", self.detail().unwrap_or_default());
+
+        let key = format!("error-{}", self.error_type().to_lowercase().replace('_', "-"));
+        translator
+            .translate(locale, &key, Some(&args))
+            .unwrap_or_else(|| self.to_string())
+    }
+
+    fn render(&self, message: String) -> HttpResponse {
+        self.render_as(message, &error_format())
+    }
+
+    fn render_as(&self, message: String, format: &ErrorFormatConfig) -> HttpResponse {
         let status_code = self.status_code();
-        let error_response = ErrorResponse {
-            error: self.error_type(),
-            message: self.to_string(),
-            status_code: status_code.as_u16(),
+
+        let mut builder = HttpResponse::build(status_code);
+        let (retry_after, reset_at) = match self {
+            Self::RateLimitExceeded { limit, retry_after } => {
+                let reset_at = chrono::Utc::now().timestamp() + *retry_after as i64;
+                builder
+                    .insert_header(("Retry-After", retry_after.to_string()))
+                    .insert_header(("X-RateLimit-Limit", limit.to_string()))
+                    .insert_header(("X-RateLimit-Remaining", "0"))
+                    .insert_header(("X-RateLimit-Reset", reset_at.to_string()));
+                (Some(*retry_after), Some(reset_at))
+            }
+            Self::QuotaExceeded { limit, reset_at } => {
+                let retry_after = (*reset_at - chrono::Utc::now().timestamp()).max(0) as u64;
+                builder
+                    .insert_header(("Retry-After", retry_after.to_string()))
+                    .insert_header(("X-Quota-Limit", limit.to_string()))
+                    .insert_header(("X-Quota-Remaining", "0"))
+                    .insert_header(("X-Quota-Reset", reset_at.to_string()));
+                (Some(retry_after), Some(*reset_at))
+            }
+            Self::AccountLocked { retry_after } | Self::EmailResendThrottled { retry_after } => {
+                builder.insert_header(("Retry-After", retry_after.to_string()));
+                (Some(*retry_after), None)
+            }
+            _ => (None, None),
         };
-        HttpResponse::build(status_code).json(error_response)
+
+        let verification = match self {
+            Self::EmailNotVerified { resend_token } => Some(VerificationHint {
+                resend_endpoint: "/auth/resend-verification-email",
+                resend_token: resend_token.clone(),
+            }),
+            _ => None,
+        };
+
+        let account_status = match self {
+            Self::AccountDisabled { status_token } => Some(AccountStatusHint {
+                status_endpoint: "/auth/account-status",
+                status_token: status_token.clone(),
+            }),
+            _ => None,
+        };
+
+        let captcha = match self {
+            Self::CaptchaRequired { challenge } => Some(CaptchaHint {
+                captcha_endpoint: "/auth/captcha",
+                captcha_challenge: challenge.clone(),
+            }),
+            _ => None,
+        };
+
+        if format.legacy_format {
+            let error_response = ErrorResponse {
+                error: self.error_type(),
+                message,
+                status_code: status_code.as_u16(),
+                retry_after,
+                reset_at,
+                verification,
+                account_status,
+                captcha,
+            };
+            return builder.json(error_response);
+        }
+
+        // Trace ID ties the response to the server-side log entry. Details can
+        // echo driver or upstream messages, so credentials are masked first.
+        let trace_id = Uuid::new_v4().to_string();
+        let logged = scrub(&self.to_string());
+        if status_code.is_server_error() {
+            log::error!("[trace_id={}] {}", trace_id, logged);
+        } else {
+            log::debug!("[trace_id={}] {}", trace_id, logged);
+        }
+
+        let code = self.error_type();
+        let problem = ProblemDetails {
+            problem_type: format!(
+                "{}/{}",
+                format.type_base_url.trim_end_matches('/'),
+                code.to_lowercase().replace('_', "-")
+            ),
+            title: Self::title_from_code(&code),
+            status: status_code.as_u16(),
+            detail: message,
+            code,
+            trace_id,
+            errors: self.field_errors(),
+            retry_after,
+            reset_at,
+            verification,
+            account_status,
+            captcha,
+        };
+
+        builder
+            .content_type("application/problem+json")
+            .json(problem)
     }
 }
 
@@ -106,19 +395,89 @@ impl AuthError {
             Self::UsernameExists => "USERNAME_EXISTS",
             Self::InvalidToken => "INVALID_TOKEN",
             Self::TokenExpired => "TOKEN_EXPIRED",
-            Self::EmailNotVerified => "EMAIL_NOT_VERIFIED",
+            Self::EmailNotVerified { .. } => "EMAIL_NOT_VERIFIED",
             Self::InvalidVerificationCode => "INVALID_VERIFICATION_CODE",
             Self::MfaRequired => "MFA_REQUIRED",
             Self::InvalidMfaCode => "INVALID_MFA_CODE",
             Self::DatabaseError(_) => "DATABASE_ERROR",
-            Self::ValidationError(_) => "VALIDATION_ERROR",
-            Self::RateLimitExceeded => "RATE_LIMIT_EXCEEDED",
+            Self::ValidationError(_) | Self::InvalidFields(_) => "VALIDATION_ERROR",
+            Self::RateLimitExceeded { .. } => "RATE_LIMIT_EXCEEDED",
+            Self::QuotaExceeded { .. } => "QUOTA_EXCEEDED",
+            Self::IdempotencyConflict => "IDEMPOTENCY_CONFLICT",
+            Self::VersionConflict => "VERSION_CONFLICT",
             Self::PermissionDenied => "PERMISSION_DENIED",
+            Self::InsufficientScope { .. } => "INSUFFICIENT_SCOPE",
+            Self::AccountDisabled { .. } => "ACCOUNT_DISABLED",
+            Self::AccountArchived => "ACCOUNT_ARCHIVED",
+            Self::LoginsFrozen { .. } => "LOGINS_FROZEN",
+            Self::LoginNotAllowed => "LOGIN_NOT_ALLOWED",
+            Self::AccountLocked { .. } => "ACCOUNT_LOCKED",
+            Self::EmailResendThrottled { .. } => "EMAIL_RESEND_THROTTLED",
+            Self::PasswordResetRequired => "PASSWORD_RESET_REQUIRED",
+            Self::ReauthenticationRequired { .. } => "REAUTHENTICATION_REQUIRED",
+            Self::LoginApprovalPending => "LOGIN_APPROVAL_PENDING",
+            Self::NetworkChanged => "NETWORK_CHANGED",
+            Self::SsoRequired => "SSO_REQUIRED",
+            Self::SsoError(_) => "SSO_ERROR",
+            Self::CaptchaRequired { .. } => "CAPTCHA_REQUIRED",
+            Self::InvalidCaptcha => "INVALID_CAPTCHA",
+            Self::VoiceCommandNotRecognized => "VOICE_COMMAND_NOT_RECOGNIZED",
+            Self::SpeechRecognitionUnavailable => "SPEECH_RECOGNITION_UNAVAILABLE",
+            Self::AuthenticatorNotAllowed(_) => "AUTHENTICATOR_NOT_ALLOWED",
+            Self::PayloadTooLarge { .. } => "PAYLOAD_TOO_LARGE",
             Self::EmailError(_) => "EMAIL_ERROR",
             Self::InternalServerError(_) => "INTERNAL_SERVER_ERROR",
         }
         .to_string()
     }
+
+    // Payload carried by the variant, interpolated as `$detail` in translations
+    fn detail(&self) -> Option<String> {
+        match self {
+            Self::DatabaseError(detail)
+            | Self::ValidationError(detail)
+            | Self::EmailError(detail)
+            | Self::SsoError(detail)
+            | Self::AuthenticatorNotAllowed(detail)
+            | Self::InternalServerError(detail) => Some(detail.clone()),
+            Self::InvalidFields(errors) => Some(errors.to_string()),
+            Self::PayloadTooLarge { limit } => Some(limit.to_string()),
+            Self::InsufficientScope { required } => Some(required.clone()),
+            Self::LoginsFrozen { message } => Some(message.clone()),
+            _ => None,
+        }
+    }
+
+    // "INVALID_MFA_CODE" -> "Invalid mfa code"
+    fn title_from_code(code: &str) -> String {
+        let words = code.to_lowercase().replace('_', " ");
+        let mut chars = words.chars();
+        match chars.next() {
+            Some(first) => first.to_uppercase().chain(chars).collect(),
+            None => String::new(),
+        }
+    }
+
+    fn field_errors(&self) -> Vec<FieldError> {
+        match self {
+            Self::InvalidFields(errors) => {
+                let mut fields: Vec<FieldError> = errors
+                    .field_errors()
+                    .into_iter()
+                    .flat_map(|(field, errs)| {
+                        errs.iter().map(move |e| FieldError {
+                            field: field.to_string(),
+                            code: e.code.to_string(),
+                            message: e.message.as_ref().map(|m| m.to_string()),
+                        })
+                    })
+                    .collect();
+                fields.sort_by(|a, b| a.field.cmp(&b.field));
+                fields
+            }
+            _ => Vec::new(),
+        }
+    }
 }
 
 impl From<diesel::result::Error> for AuthError {
@@ -148,7 +507,7 @@ impl From<jsonwebtoken::errors::Error> for AuthError {
 
 impl From<validator::ValidationErrors> for AuthError {
     fn from(err: validator::ValidationErrors) -> Self {
-        AuthError::ValidationError(err.to_string())
+        AuthError::InvalidFields(err)
     }
 }
 
@@ -163,3 +522,45 @@ impl From<std::io::Error> for AuthError {
         AuthError::InternalServerError(err.to_string())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::body;
+    use serde_json::Value;
+
+    async fn body_of(response: HttpResponse) -> Value {
+        let bytes = body::to_bytes(response.into_body()).await.unwrap();
+        serde_json::from_slice(&bytes).unwrap()
+    }
+
+    #[actix_web::test]
+    async fn test_legacy_format() {
+        let format = ErrorFormatConfig {
+            legacy_format: true,
+            ..Default::default()
+        };
+        let response = AuthError::InvalidCredentials.render_as("Invalid credentials".into(), &format);
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let body = body_of(response).await;
+        assert_eq!(body["error"], "INVALID_CREDENTIALS");
+        assert_eq!(body["message"], "Invalid credentials");
+        assert_eq!(body["status_code"], 401);
+        assert!(body.get("trace_id").is_none());
+    }
+
+    #[actix_web::test]
+    async fn test_problem_type_base_url() {
+        let format = ErrorFormatConfig {
+            legacy_format: false,
+            type_base_url: "https://errors.example.com/".to_string(),
+        };
+        let response = AuthError::InvalidCredentials.render_as("Invalid credentials".into(), &format);
+        assert_eq!(response.headers().get("Content-Type").unwrap(), "application/problem+json");
+
+        let body = body_of(response).await;
+        assert_eq!(body["type"], "https://errors.example.com/invalid-credentials");
+        assert_eq!(body["code"], "INVALID_CREDENTIALS");
+    }
+}
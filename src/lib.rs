@@ -0,0 +1,29 @@
+// The modules the server binary is built from, as a library so benchmarks and
+// other targets can use them
+pub mod webauthn_simplified;
+pub mod risk_scoring;
+pub mod breach_detection;
+pub mod proxy_email;
+pub mod hybrid_encryption;
+pub mod accessibility;
+pub mod hipaa_compliance;
+pub mod build_info;
+
+#[cfg(feature = "server")]
+pub mod config;
+#[cfg(feature = "server")]
+pub mod db;
+#[cfg(feature = "server")]
+pub mod errors;
+#[cfg(feature = "server")]
+pub mod middleware;
+#[cfg(feature = "server")]
+pub mod models;
+#[cfg(feature = "server")]
+pub mod routes;
+#[cfg(feature = "server")]
+pub mod schema;
+#[cfg(feature = "server")]
+pub mod services;
+#[cfg(feature = "server")]
+pub mod utils;
@@ -1,11 +1,5 @@
-// Import all our modules
-pub mod webauthn_simplified;
-pub mod risk_scoring;
-pub mod breach_detection;
-pub mod proxy_email;
-pub mod hybrid_encryption;
-pub mod accessibility;
-pub mod hipaa_compliance;
+// The library's modules; see lib.rs
+use better_auth_rust::{build_info, webauthn_simplified};
 
 pub mod auth_types {
     use serde::{Deserialize, Serialize};
@@ -120,10 +114,17 @@ use uuid::Uuid;
 pub async fn health_check() -> impl Responder {
     HttpResponse::Ok().json(json!({
         "status": "ok",
-        "version": "0.1.0"
+        "version": build_info::VERSION,
+        "git_sha": build_info::GIT_SHA
     }))
 }
 
+// Crate version, commit, build time and enabled features
+#[get("/api/system/version")]
+pub async fn system_version() -> impl Responder {
+    HttpResponse::Ok().json(build_info::build_info())
+}
+
 #[post("/api/auth/register")]
 pub async fn register(data: web::Json<auth_types::RegisterRequest>, state: web::Data<auth_types::AppState>) -> Result<HttpResponse, Error> {
     let data = data.into_inner();
@@ -575,6 +576,31 @@ use log::info;
 use std::collections::HashMap;
 use std::sync::Mutex;
 
+// Demo accounts created by `--dev`, under a reserved domain (RFC 2606) so
+// they can never belong to a real person
+const DEV_ACCOUNTS: &[(&str, bool, bool)] = &[
+    // (username, email verified, MFA enabled)
+    ("demo_verified", true, false),
+    ("demo_unverified", false, false),
+    ("demo_mfa", true, true),
+];
+
+fn seed_dev_accounts(state: &auth_types::AppState, password: &str) {
+    let mut users = state.users.lock().unwrap();
+    for (username, is_email_verified, mfa_enabled) in DEV_ACCOUNTS {
+        let user_id = Uuid::new_v4();
+        users.insert(user_id, auth_types::User {
+            id: user_id,
+            username: username.to_string(),
+            email: format!("{}@example.com", username),
+            password_hash: auth_utils::hash_password(password),
+            is_email_verified: *is_email_verified,
+            mfa_enabled: *mfa_enabled,
+            webauthn_credentials: Vec::new(),
+        });
+    }
+}
+
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     // Load environment variables
@@ -583,28 +609,69 @@ async fn main() -> std::io::Result<()> {
     // Initialize logger
     env_logger::init_from_env(env_logger::Env::new().default_filter_or("info"));
     
-    info!("Starting Better Auth server at 0.0.0.0:5000");
+    // `--dev`: permissive CORS and seeded demo accounts, so a local frontend
+    // works with no setup. Never in production.
+    let dev_mode = std::env::args().skip(1).any(|arg| arg == "--dev");
+    let is_production = std::env::var("APP_ENV")
+        .map(|v| v.trim().eq_ignore_ascii_case("production"))
+        .unwrap_or(false);
+    if dev_mode && is_production {
+        return Err(std::io::Error::other("--dev refuses to start with APP_ENV=production"));
+    }
+    
+    info!(
+        "Starting Better Auth server {} ({}) at 0.0.0.0:5000",
+        build_info::VERSION,
+        build_info::GIT_SHA
+    );
+    
+    // Grace period for draining in-flight requests on SIGTERM/SIGINT (in seconds)
+    let shutdown_grace_period: u64 = std::env::var("SHUTDOWN_GRACE_PERIOD")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(30);
+    
+    // Largest JSON body accepted, in bytes
+    let json_limit: usize = std::env::var("REQUEST_JSON_LIMIT")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(131072);
     
     // Create app state (in-memory database)
     let app_state = web::Data::new(auth_types::AppState {
         users: Mutex::new(HashMap::new()),
         sessions: Mutex::new(HashMap::new()),
     });
+    if dev_mode {
+        let password = std::env::var("DEV_SEED_PASSWORD").unwrap_or_else(|_| "DemoPass123".to_string());
+        seed_dev_accounts(&app_state, &password);
+        info!(
+            "Development mode: CORS allows any origin; demo accounts {} share the password from DEV_SEED_PASSWORD",
+            DEV_ACCOUNTS.iter().map(|(username, _, _)| *username).collect::<Vec<_>>().join(", ")
+        );
+    }
     
     // Start HTTP server
+    let server_state = app_state.clone();
     HttpServer::new(move || {
         // Configure CORS
-        let cors = Cors::default()
-            .allowed_origin("http://localhost:3000")
-            .allowed_methods(vec!["GET", "POST", "PUT", "DELETE"])
-            .allowed_headers(vec![header::AUTHORIZATION, header::CONTENT_TYPE])
-            .max_age(3600);
+        let cors = if dev_mode {
+            Cors::permissive()
+        } else {
+            Cors::default()
+                .allowed_origin("http://localhost:3000")
+                .allowed_methods(vec!["GET", "POST", "PUT", "DELETE"])
+                .allowed_headers(vec![header::AUTHORIZATION, header::CONTENT_TYPE])
+                .max_age(3600)
+        };
         
         App::new()
-            .app_data(app_state.clone())
+            .app_data(server_state.clone())
+            .app_data(web::JsonConfig::default().limit(json_limit))
             .wrap(middleware::Logger::default())
             .wrap(cors)
             .service(health_check)
+            .service(system_version)
             .service(register)
             .service(login)
             .service(get_current_user)
@@ -615,6 +682,15 @@ async fn main() -> std::io::Result<()> {
             .service(webauthn_login_complete)
     })
     .bind(("0.0.0.0", 5000))?
+    // Stop accepting new connections on SIGTERM and give in-flight requests
+    // up to the grace period to finish before workers are force-stopped
+    .shutdown_timeout(shutdown_grace_period)
     .run()
-    .await
+    .await?;
+    
+    // Workers have drained; release shared state before exiting
+    info!("Server stopped, releasing application state");
+    drop(app_state);
+    
+    Ok(())
 }
@@ -0,0 +1,218 @@
+use std::future::{ready, Ready};
+use std::rc::Rc;
+
+use actix_web::{
+    dev::{forward_ready, Payload, Service, ServiceRequest, ServiceResponse, Transform},
+    web, Error, HttpMessage,
+};
+use futures::future::LocalBoxFuture;
+use futures::StreamExt;
+use serde_json::{json, Map, Value};
+
+use crate::errors::AuthError;
+use crate::middleware::auth::AuthenticatedUser;
+use crate::middleware::request_limits::is_json;
+use crate::services::auth::AuthService;
+use crate::utils::secret::redact_json;
+
+// Records an `admin.request` event for every call to the admin API, whether
+// or not it succeeds: who made it, the route and the resources it names, the
+// query and JSON body with credentials redacted, and the response status.
+// Wrap it inside the authentication middleware, so the caller is known, and
+// outside `AdminMiddleware`, so refused calls are recorded too.
+pub struct AdminAudit;
+
+impl<S, B> Transform<S, ServiceRequest> for AdminAudit
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = AdminAuditService<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(AdminAuditService {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub struct AdminAuditService<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for AdminAuditService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let caller = req.extensions().get::<AuthenticatedUser>().cloned();
+        let auth_service = req.app_data::<web::Data<AuthService>>().cloned();
+        let (caller, auth_service) = match (caller, auth_service) {
+            (Some(caller), Some(auth_service)) => (caller, auth_service),
+            // Unauthenticated calls are refused before they get here
+            _ => return Box::pin(async move { service.call(req).await }),
+        };
+
+        let method = req.method().to_string();
+        let path = req.path().to_string();
+        let query = query_of(req.query_string());
+        let json_body = is_json(&req);
+
+        Box::pin(async move {
+            let body = if json_body {
+                let mut payload = req.take_payload();
+                let mut body = web::BytesMut::new();
+                while let Some(chunk) = payload.next().await {
+                    let chunk = chunk.map_err(|e| AuthError::ValidationError(e.to_string()))?;
+                    body.extend_from_slice(&chunk);
+                }
+                let mut parsed = serde_json::from_slice(&body).unwrap_or(Value::Null);
+                redact_json(&mut parsed);
+                req.set_payload(Payload::from(body.freeze()));
+                parsed
+            } else {
+                Value::Null
+            };
+
+            let result = service.call(req).await;
+
+            // Refusals from middleware come back as errors, before a route is matched
+            let (status, route, targets) = match &result {
+                Ok(res) => {
+                    let targets: Map<String, Value> = res
+                        .request()
+                        .match_info()
+                        .iter()
+                        .map(|(name, value)| (name.to_string(), Value::String(value.to_string())))
+                        .collect();
+                    (res.status(), res.request().match_pattern(), targets)
+                }
+                Err(e) => (e.as_response_error().status_code(), None, Map::new()),
+            };
+
+            let request = json!({
+                "method": method,
+                "route": route,
+                "path": path,
+                "targets": targets,
+                "query": query,
+                "body": body,
+                "status": status.as_u16(),
+                "actor_id": caller.actor_id,
+            });
+            // The call has already happened; a missing record is logged, not returned
+            if let Err(e) = auth_service.record_admin_request(caller.user_id, request).await {
+                log::error!("Failed to record admin request {} {} by {}: {}", method, path, caller.user_id, e);
+            }
+
+            result
+        })
+    }
+}
+
+fn query_of(query_string: &str) -> Value {
+    let pairs = web::Query::<Vec<(String, String)>>::from_query(query_string)
+        .map(|query| query.into_inner())
+        .unwrap_or_default();
+    let mut query: Value = pairs
+        .into_iter()
+        .map(|(name, value)| (name, Value::String(value)))
+        .collect::<Map<String, Value>>()
+        .into();
+    redact_json(&mut query);
+    query
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::StatusCode;
+    use actix_web::test;
+    use serde_json::{json, Value};
+
+    use crate::models::{AuditEventFilter, EventType, PageRequest};
+    use crate::test_utils::TestContext;
+    use crate::utils::secret::REDACTED;
+
+    #[actix_web::test]
+    async fn test_admin_calls_are_recorded_with_credentials_redacted() {
+        let ctx = TestContext::new();
+        let app = ctx.spawn_app().await;
+        let admin = ctx.user().admin().create().await.unwrap();
+        let user = ctx.user().create().await.unwrap();
+        let admin_token = ctx.session(&admin).create().await.unwrap().access_token;
+        let user_token = ctx.session(&user).create().await.unwrap().access_token;
+        let status_uri = format!("/admin/users/{}/status", user.id());
+
+        let request = test::TestRequest::get()
+            .uri(&format!("/admin/users/{}?token=leaked", user.id()))
+            .insert_header(("Authorization", format!("Bearer {}", admin_token)))
+            .to_request();
+        assert_eq!(test::call_service(&app, request).await.status(), StatusCode::OK);
+
+        let set_status = |body: Value| {
+            test::TestRequest::put()
+                .uri(&status_uri)
+                .insert_header(("Authorization", format!("Bearer {}", admin_token)))
+                .set_json(body)
+                .to_request()
+        };
+        let response = test::call_service(&app, set_status(json!({ "status": "suspended", "reason": "Chargeback" }))).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = test::call_service(&app, set_status(json!({ "status": "active", "reason": "x", "password": "hunter22" }))).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        // Refused calls are recorded against whoever made them
+        let request = test::TestRequest::get()
+            .uri("/admin/users")
+            .insert_header(("Authorization", format!("Bearer {}", user_token)))
+            .to_request();
+        assert_eq!(test::call_service(&app, request).await.status(), StatusCode::FORBIDDEN);
+
+        let recorded = |user_id: uuid::Uuid| {
+            let filter = AuditEventFilter {
+                user_id: Some(user_id),
+                event_type: Some(EventType::AdminRequest.as_str().to_string()),
+            };
+            let db = ctx.db.clone();
+            async move { db.find_outbox_events(&filter, &PageRequest::default()).await.unwrap().0 }
+        };
+        let events = recorded(admin.id()).await;
+        assert_eq!(events.len(), 3);
+        let find = |method: &str, status: u16| {
+            events
+                .iter()
+                .map(|event| &event.payload)
+                .find(|payload| payload["method"] == method && payload["status"] == status)
+                .unwrap_or_else(|| panic!("no {} {} recorded", method, status))
+        };
+
+        let read = find("GET", 200);
+        assert_eq!(read["route"], "/admin/users/{user_id}");
+        assert_eq!(read["targets"]["user_id"], user.id().to_string());
+        assert_eq!(read["query"]["token"], REDACTED);
+
+        let write = find("PUT", 200);
+        assert_eq!(write["path"], status_uri);
+        assert_eq!(write["body"], json!({ "status": "suspended", "reason": "Chargeback" }));
+        assert_eq!(find("PUT", 400)["body"]["password"], REDACTED);
+
+        let refused = recorded(user.id()).await;
+        assert_eq!(refused.len(), 1);
+        assert_eq!(refused[0].payload["status"], 403);
+        assert_eq!(refused[0].payload["route"], Value::Null);
+    }
+}
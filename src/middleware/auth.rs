@@ -1,29 +1,102 @@
+use std::collections::HashMap;
 use std::future::{ready, Ready};
-use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use actix_web::{
     dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
-    Error, HttpMessage,
+    http::header::{HeaderName, HeaderValue},
+    http::Method,
+    web, Error, HttpMessage,
 };
 use futures::future::LocalBoxFuture;
-use futures::Future;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
+use crate::config::UserCacheConfig;
+use crate::db::DatabaseConnection;
 use crate::errors::AuthError;
-use crate::utils::jwt::{decode_jwt, JwtClaims};
+use crate::models::User;
+use crate::services::auth::AuthService;
+use crate::services::token_revocation::TokenRevocations;
+use crate::utils::api_key::is_api_key;
+use crate::utils::dpop::{self, DpopVerifier};
+use crate::utils::jwt::{decode_jwt, JwtClaims, TokenScope};
+use crate::utils::scopes::{default_scopes, grants, missing_default, ADMIN_READ, ADMIN_WRITE};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AuthenticatedUser {
     pub user_id: Uuid,
     pub is_admin: bool,
+    pub scope: TokenScope,
+    pub auth_time: Option<usize>,
+    pub amr: Vec<String>,
+    pub scopes: Vec<String>, // Permission scopes, e.g. "users:read"; see `utils::scopes`
+    #[serde(default)]
+    pub actor_id: Option<Uuid>, // Set for delegated tokens: the admin acting as `user_id`
 }
 
+/// Short-lived cache of users loaded by `AuthMiddleware`, registered as app data.
+/// When present, tokens for deleted or disabled users are rejected and the
+/// full `User` is attached to the request.
+pub struct UserCache {
+    db: Arc<DatabaseConnection>,
+    enabled: bool,
+    ttl: Duration,
+    users: Mutex<HashMap<Uuid, (User, Instant)>>,
+}
+
+impl UserCache {
+    pub fn new(db: Arc<DatabaseConnection>, config: &UserCacheConfig) -> Self {
+        UserCache {
+            db,
+            enabled: config.load_user,
+            ttl: Duration::from_secs(config.ttl),
+            users: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Load a user, reusing a cached copy while it is fresh
+    pub async fn load(&self, user_id: Uuid) -> Result<User, AuthError> {
+        {
+            let mut users = self.users.lock().unwrap();
+
+            // Clean up expired entries
+            users.retain(|_, (_, loaded)| loaded.elapsed() < self.ttl);
+
+            if let Some((user, _)) = users.get(&user_id) {
+                return Ok(user.clone());
+            }
+        }
+
+        // A deleted user's tokens are no longer valid
+        let user = match self.db.find_user_by_id(user_id).await {
+            Ok(user) => user,
+            Err(AuthError::UserNotFound) => return Err(AuthError::InvalidToken),
+            Err(err) => return Err(err),
+        };
+
+        self.users
+            .lock()
+            .unwrap()
+            .insert(user_id, (user.clone(), Instant::now()));
+
+        Ok(user)
+    }
+
+    /// Drop a cached user so the next request sees their current state
+    pub fn invalidate(&self, user_id: Uuid) {
+        self.users.lock().unwrap().remove(&user_id);
+    }
+}
+
+// Accepts only fully authenticated tokens
 pub struct AuthMiddleware;
 
 impl<S, B> Transform<S, ServiceRequest> for AuthMiddleware
 where
-    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
     S::Future: 'static,
     B: 'static,
 {
@@ -34,17 +107,71 @@ where
     type Future = Ready<Result<Self::Transform, Self::InitError>>;
 
     fn new_transform(&self, service: S) -> Self::Future {
-        ready(Ok(AuthMiddlewareService { service }))
+        ScopedAuthMiddleware(&[TokenScope::Full]).new_transform(service)
+    }
+}
+
+// Accepts tokens carrying one of the given scopes, for routes that
+// belong to a step of a multi-step flow, e.g.
+// `wrap = "ScopedAuthMiddleware(&[TokenScope::MfaPending])"`
+pub struct ScopedAuthMiddleware(pub &'static [TokenScope]);
+
+impl<S, B> Transform<S, ServiceRequest> for ScopedAuthMiddleware
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = AuthMiddlewareService<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(AuthMiddlewareService {
+            service: Rc::new(service),
+            scopes: self.0,
+            required_scope: None,
+        }))
+    }
+}
+
+// Accepts session tokens, delegated tokens and API keys that carry the given permission scope,
+// for routes open to integrations, e.g. `wrap = "RequireScope(USERS_READ)"`.
+// Authenticates the request itself, so it takes the place of `AuthMiddleware`.
+pub struct RequireScope(pub &'static str);
+
+impl<S, B> Transform<S, ServiceRequest> for RequireScope
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = AuthMiddlewareService<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(AuthMiddlewareService {
+            service: Rc::new(service),
+            scopes: &[TokenScope::Full, TokenScope::Delegated, TokenScope::ApiKey],
+            required_scope: Some(self.0),
+        }))
     }
 }
 
 pub struct AuthMiddlewareService<S> {
-    service: S,
+    service: Rc<S>,
+    scopes: &'static [TokenScope],
+    required_scope: Option<&'static str>,
 }
 
 impl<S, B> Service<ServiceRequest> for AuthMiddlewareService<S>
 where
-    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
     S::Future: 'static,
     B: 'static,
 {
@@ -66,37 +193,167 @@ where
 
         let auth_header = auth_header.unwrap().to_str().unwrap_or("");
 
-        if !auth_header.starts_with("Bearer ") {
+        // Tokens bound to a client key come with the `DPoP` scheme (RFC 9449)
+        let (token, is_dpop) = if let Some(token) = auth_header.strip_prefix("Bearer ") {
+            (token.to_string(), false)
+        } else if let Some(token) = auth_header.strip_prefix("DPoP ") {
+            (token.to_string(), true)
+        } else {
             return Box::pin(async move {
                 Err(AuthError::InvalidToken.into())
             });
-        }
+        };
 
-        let token = auth_header[7..].to_string();
+        let service = self.service.clone();
+        let scopes = self.scopes;
+        let required_scope = self.required_scope;
+        let user_cache = req.app_data::<web::Data<UserCache>>().cloned();
+        let dpop_verifier = req.app_data::<web::Data<DpopVerifier>>().cloned();
+        let auth_service = req.app_data::<web::Data<AuthService>>().cloned();
+        let ip = req.connection_info().realip_remote_addr().map(|s| s.to_string());
 
-        // Create a clone of the request to move into the future
-        let mut req_clone = req.clone();
-
-        // Decode JWT and process request
         Box::pin(async move {
-            match decode_jwt::<JwtClaims>(&token) {
-                Ok(claims) => {
-                    let user = AuthenticatedUser {
-                        user_id: claims.sub,
-                        is_admin: claims.is_admin,
-                    };
-
-                    req_clone.extensions_mut().insert(user);
-                    let fut = self.service.call(req_clone);
-                    let res = fut.await?;
-                    Ok(res)
+            let (user, quota) = if is_api_key(&token) && !is_dpop {
+                // API keys only work on routes that let them in
+                if !scopes.contains(&TokenScope::ApiKey) {
+                    return Err(AuthError::InvalidToken.into());
                 }
-                Err(err) => Err(err.into()),
+                let auth_service = auth_service.ok_or_else(|| {
+                    AuthError::InternalServerError("AuthService is not registered".into())
+                })?;
+                auth_service.authenticate_api_key(&token, ip).await?
+            } else {
+                let user =
+                    authenticate_jwt(&req, &token, is_dpop, scopes, user_cache, dpop_verifier, auth_service).await?;
+                (user, None)
+            };
+
+            match required_scope {
+                Some(required) => {
+                    if !grants(&user.scopes, required) {
+                        return Err(AuthError::InsufficientScope { required: required.to_string() }.into());
+                    }
+                }
+                // Routes without a permission scope of their own act with all of
+                // the user's authority: a token narrowed to some scopes, or held
+                // by someone acting for the user, can't use them. Admin routes
+                // check the admin scopes themselves.
+                None if user.scope == TokenScope::Full => {
+                    let granted: &[String] = if user.actor_id.is_some() { &[] } else { &user.scopes };
+                    if let Some(required) = missing_default(granted, false) {
+                        return Err(AuthError::InsufficientScope { required }.into());
+                    }
+                }
+                None => {}
             }
+
+            req.extensions_mut().insert(user);
+            let mut res = service.call(req).await?;
+
+            // API key callers can see how much of their quota is left
+            if let Some(quota) = quota {
+                let headers = res.headers_mut();
+                for (name, value) in [
+                    ("x-quota-limit", quota.limit.to_string()),
+                    ("x-quota-remaining", quota.remaining.to_string()),
+                    ("x-quota-reset", quota.reset_at.to_string()),
+                ] {
+                    if let Ok(value) = HeaderValue::from_str(&value) {
+                        headers.insert(HeaderName::from_static(name), value);
+                    }
+                }
+            }
+
+            Ok(res)
         })
     }
 }
 
+async fn authenticate_jwt(
+    req: &ServiceRequest,
+    token: &str,
+    is_dpop: bool,
+    scopes: &[TokenScope],
+    user_cache: Option<web::Data<UserCache>>,
+    dpop_verifier: Option<web::Data<DpopVerifier>>,
+    auth_service: Option<web::Data<AuthService>>,
+) -> Result<AuthenticatedUser, AuthError> {
+    let claims = decode_jwt::<JwtClaims>(token)?;
+
+    // The token, or the session it was issued with, was revoked before it expired
+    let revocations = req.app_data::<web::Data<TokenRevocations>>();
+    if revocations.map_or(false, |r| r.is_revoked(claims.jti, claims.sid)) {
+        return Err(AuthError::InvalidToken);
+    }
+
+    // An intermediate token can't be used outside its own step
+    if !scopes.contains(&claims.scope) {
+        return Err(AuthError::InvalidToken);
+    }
+
+    // A bound token is only good with a fresh proof from the key it was issued
+    // to, so a leaked token can't be replayed by another client
+    match (&claims.cnf, is_dpop) {
+        (Some(cnf), true) => {
+            let proof = dpop::proof_header(req.headers())?.ok_or(AuthError::InvalidToken)?;
+            let verifier = dpop_verifier.ok_or(AuthError::InvalidToken)?;
+            let jkt = verifier.verify(
+                proof,
+                req.method().as_str(),
+                &dpop::request_url(req.request()),
+                Some(token),
+            )?;
+            if jkt != cnf.jkt {
+                return Err(AuthError::InvalidToken);
+            }
+        }
+        (None, false) => {}
+        _ => return Err(AuthError::InvalidToken),
+    }
+
+    // Session tokens issued before scopes existed may do whatever the user can
+    let token_scopes = if claims.scopes.is_empty() && claims.scope == TokenScope::Full {
+        default_scopes(claims.is_admin)
+    } else {
+        claims.scopes
+    };
+
+    let mut user = AuthenticatedUser {
+        user_id: claims.sub,
+        is_admin: claims.is_admin,
+        scope: claims.scope,
+        auth_time: claims.auth_time,
+        amr: claims.amr,
+        scopes: token_scopes,
+        actor_id: claims.act.map(|act| act.sub),
+    };
+
+    // Enforce current account status rather than what the token was issued with
+    if let Some(cache) = user_cache.filter(|c| c.enabled) {
+        let loaded = cache.load(user.user_id).await?;
+        // Locked-out users keep only the token for seeing why and appealing
+        if !loaded.is_active() && claims.scope != TokenScope::AccountStatus {
+            return Err(AuthError::AccountDisabled { status_token: None });
+        }
+
+        // Issued before the user's last global sign-out
+        if claims.token_version != loaded.token_version {
+            return Err(AuthError::InvalidToken);
+        }
+
+        // A delegated token never carries the user's admin rights
+        user.is_admin = loaded.is_admin && user.actor_id.is_none();
+        req.extensions_mut().insert(loaded);
+    }
+
+    // Requests count as use of the session the token was issued with
+    if let (Some(session_id), Some(auth_service)) = (claims.sid, auth_service) {
+        auth_service.record_session_activity(session_id).await?;
+    }
+
+    Ok(user)
+}
+
 pub struct AdminMiddleware;
 
 impl<S, B> Transform<S, ServiceRequest> for AdminMiddleware
@@ -137,7 +394,16 @@ where
         let user_option = req.extensions().get::<AuthenticatedUser>().cloned();
         
         if let Some(user) = user_option {
-            if user.is_admin {
+            let is_admin_credential = matches!(user.scope, TokenScope::Full | TokenScope::ApiKey);
+            if user.is_admin && is_admin_credential {
+                // Reading needs `admin:read`, anything else `admin:write`
+                let required = if req.method() == Method::GET { ADMIN_READ } else { ADMIN_WRITE };
+                if !grants(&user.scopes, required) {
+                    return Box::pin(async move {
+                        Err(AuthError::InsufficientScope { required: required.to_string() }.into())
+                    });
+                }
+
                 let fut = self.service.call(req);
                 return Box::pin(async move {
                     let res = fut.await?;
@@ -151,3 +417,120 @@ where
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use actix_web::dev::ServiceResponse;
+    use actix_web::http::{Method, StatusCode};
+    use actix_web::test;
+    use serde_json::{json, Value};
+
+    use crate::test_utils::{mfa_login, TestContext};
+
+    // Every route that reads the signed-in user has to sit behind an auth
+    // wrap; without one the handler can't find the user and answers 500
+    #[actix_web::test]
+    async fn test_signed_in_routes_accept_a_valid_token() {
+        let ctx = TestContext::new();
+        let app = ctx.spawn_app().await;
+        let user = ctx.user().with_mfa().create().await.unwrap();
+
+        let code = user.totp_code(&ctx).unwrap();
+        let signed_in = mfa_login(&app, &user.user.username, &user.password, &code).await.assert_success();
+        let bearer = format!("Bearer {}", signed_in.field("access_token").unwrap());
+
+        let request = test::TestRequest::post()
+            .uri("/organizations")
+            .insert_header(("Authorization", bearer.as_str()))
+            .set_json(json!({ "name": "Acme", "slug": "acme" }))
+            .to_request();
+        let organization: Value = test::call_and_read_body_json(&app, request).await;
+        let org = format!("/organizations/{}", organization["id"].as_str().unwrap());
+        let missing = uuid::Uuid::new_v4();
+
+        let routes = [
+            (Method::GET, "/auth/mfa-recovery-codes/status".to_string(), Value::Null),
+            (Method::GET, "/auth/mfa-devices".to_string(), Value::Null),
+            (Method::POST, "/auth/mfa-devices".to_string(), json!({ "name": "Backup phone" })),
+            (Method::POST, format!("/auth/mfa-devices/{}/confirm", missing), json!({ "mfa_code": "123456" })),
+            (Method::DELETE, format!("/auth/mfa-devices/{}", missing), Value::Null),
+            (Method::PUT, "/auth/mfa-methods".to_string(), json!({ "methods": ["totp"] })),
+            (Method::POST, "/auth/passkeys/prompt/dismiss".to_string(), Value::Null),
+            (Method::POST, "/users/me/emails".to_string(), json!({ "email": "backup@example.com" })),
+            (Method::DELETE, format!("/users/me/emails/{}", missing), Value::Null),
+            (Method::PUT, "/users/me/login-policy".to_string(), json!({ "allowed_countries": [] })),
+            (Method::DELETE, "/users/me/login-policy".to_string(), Value::Null),
+            (Method::PUT, "/users/me/security-questions".to_string(), json!({ "questions": [] })),
+            (Method::DELETE, "/users/me/security-questions".to_string(), Value::Null),
+            (Method::GET, "/users/me/api-keys".to_string(), Value::Null),
+            (Method::POST, "/users/me/api-keys".to_string(), json!({ "name": "CI", "scopes": ["users:read"] })),
+            (Method::DELETE, format!("/users/me/api-keys/{}", missing), Value::Null),
+            (Method::POST, format!("{}/domains", org), json!({ "domain": "acme.example" })),
+            (Method::POST, format!("{}/domains/{}/verify", org, missing), Value::Null),
+            (Method::PATCH, format!("{}/domains/{}", org, missing), json!({ "auto_join": true })),
+            (Method::DELETE, format!("{}/domains/{}", org, missing), Value::Null),
+            (Method::PUT, format!("{}/sso", org), json!({ "protocol": "oidc" })),
+            (Method::DELETE, format!("{}/sso", org), Value::Null),
+            (Method::PUT, format!("{}/login-policy", org), json!({ "allowed_countries": [] })),
+            (Method::DELETE, format!("{}/login-policy", org), Value::Null),
+            // Last, since it ends the session the token belongs to
+            (Method::POST, "/auth/logout-all".to_string(), Value::Null),
+        ];
+        for (method, path, body) in routes {
+            let mut request = test::TestRequest::default()
+                .method(method.clone())
+                .uri(&path)
+                .insert_header(("Authorization", bearer.as_str()));
+            if !body.is_null() {
+                request = request.set_json(body);
+            }
+            let status = test::call_service(&app, request.to_request()).await.status();
+            assert_ne!(status, StatusCode::UNAUTHORIZED, "{} {}", method, path);
+            assert_ne!(status, StatusCode::INTERNAL_SERVER_ERROR, "{} {}", method, path);
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_narrowed_tokens_only_reach_routes_with_their_scope() {
+        let ctx = TestContext::new();
+        let app = ctx.spawn_app().await;
+        let user = ctx.user().create().await.unwrap();
+        let narrowed = ctx.session(&user).scopes(&["users:read"]).create().await.unwrap();
+
+        // Middleware refusals come back as errors rather than responses
+        let status_of = |result: Result<ServiceResponse<_>, actix_web::Error>| match result {
+            Ok(res) => res.status(),
+            Err(e) => e.as_response_error().status_code(),
+        };
+        let request = |method: Method, path: &str, body: Value| {
+            let mut request = test::TestRequest::default()
+                .method(method)
+                .uri(path)
+                .insert_header(("Authorization", narrowed.bearer()));
+            if !body.is_null() {
+                request = request.set_json(body);
+            }
+            request.to_request()
+        };
+
+        let me = test::try_call_service(&app, request(Method::GET, "/users/me", Value::Null)).await;
+        assert_eq!(status_of(me), StatusCode::OK);
+
+        let missing = uuid::Uuid::new_v4();
+        let password = json!({
+            "current_password": user.password,
+            "password": "Another1Pass!",
+            "password_confirmation": "Another1Pass!",
+        });
+        for (method, path, body) in [
+            (Method::POST, "/auth/change-password".to_string(), password),
+            (Method::GET, "/auth/mfa-setup".to_string(), Value::Null),
+            (Method::GET, "/users/me/api-keys".to_string(), Value::Null),
+            (Method::DELETE, format!("/users/me/api-keys/{}", missing), Value::Null),
+            (Method::POST, "/auth/logout-all".to_string(), Value::Null),
+        ] {
+            let result = test::try_call_service(&app, request(method.clone(), &path, body)).await;
+            assert_eq!(status_of(result), StatusCode::FORBIDDEN, "{} {}", method, path);
+        }
+    }
+}
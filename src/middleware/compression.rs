@@ -0,0 +1,157 @@
+use std::future::{ready, Ready};
+use std::rc::Rc;
+
+use actix_web::{
+    dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
+    http::{
+        header::{HeaderValue, CACHE_CONTROL, CONTENT_ENCODING, SET_COOKIE},
+        Method,
+    },
+    Error,
+};
+use futures::future::LocalBoxFuture;
+
+use crate::config::CompressionConfig;
+
+// Decides which responses `actix_web::middleware::Compress` may encode; wrap
+// it inside `Compress`. Everything else is marked `Content-Encoding:
+// identity`, which `Compress` leaves alone. See `CompressionConfig` for what
+// qualifies.
+#[derive(Clone)]
+pub struct CompressionPolicy {
+    config: Rc<CompressionConfig>,
+}
+
+impl CompressionPolicy {
+    pub fn new(config: &CompressionConfig) -> Self {
+        CompressionPolicy {
+            config: Rc::new(config.clone()),
+        }
+    }
+
+    fn allows(&self, req: &ServiceRequest) -> bool {
+        if !self.config.enabled || !matches!(*req.method(), Method::GET | Method::HEAD) {
+            return false;
+        }
+        let group = req.path().trim_start_matches('/').split('/').next().unwrap_or_default();
+        self.config.route_groups.iter().any(|allowed| allowed == group)
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for CompressionPolicy
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = CompressionPolicyService<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(CompressionPolicyService {
+            service: Rc::new(service),
+            policy: self.clone(),
+        }))
+    }
+}
+
+pub struct CompressionPolicyService<S> {
+    service: Rc<S>,
+    policy: CompressionPolicy,
+}
+
+impl<S, B> Service<ServiceRequest> for CompressionPolicyService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let allowed = self.policy.allows(&req);
+        let service = self.service.clone();
+
+        Box::pin(async move {
+            let mut response = service.call(req).await?;
+            if !allowed || carries_secret(&response) {
+                response
+                    .headers_mut()
+                    .insert(CONTENT_ENCODING, HeaderValue::from_static("identity"));
+            }
+            Ok(response)
+        })
+    }
+}
+
+// Routes mark token-bearing responses `no-store`; cookies are secrets too
+fn carries_secret<B>(response: &ServiceResponse<B>) -> bool {
+    let headers = response.headers();
+    let no_store = headers
+        .get(CACHE_CONTROL)
+        .and_then(|value| value.to_str().ok())
+        .map_or(false, |value| value.split(',').any(|directive| directive.trim().eq_ignore_ascii_case("no-store")));
+    no_store || headers.contains_key(SET_COOKIE)
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::StatusCode;
+    use actix_web::test;
+    use serde_json::json;
+
+    use crate::test_utils::TestContext;
+
+    #[actix_web::test]
+    async fn test_only_reads_without_tokens_are_compressed() {
+        let encoding = |response: &actix_web::dev::ServiceResponse| {
+            response
+                .headers()
+                .get("Content-Encoding")
+                .map(|value| value.to_str().unwrap().to_string())
+                .unwrap_or_default()
+        };
+        let sessions = |token: &str| {
+            test::TestRequest::get()
+                .uri("/users/sessions")
+                .insert_header(("Accept-Encoding", "gzip"))
+                .insert_header(("Authorization", format!("Bearer {}", token)))
+                .to_request()
+        };
+
+        let ctx = TestContext::new();
+        let app = ctx.spawn_app().await;
+        let user = ctx.user().create().await.unwrap();
+        let token = ctx.session(&user).create().await.unwrap().access_token;
+
+        let response = test::call_service(&app, sessions(&token)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(encoding(&response), "gzip");
+
+        // Tokens in the body, next to the username the client sent
+        let request = test::TestRequest::post()
+            .uri("/auth/login")
+            .insert_header(("Accept-Encoding", "gzip"))
+            .set_json(json!({ "username_or_email": user.user.username, "password": user.password }))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(encoding(&response), "identity");
+
+        let mut config = crate::test_utils::test_config();
+        config.compression.enabled = false;
+        let ctx = TestContext::with_config(config);
+        let app = ctx.spawn_app().await;
+        let user = ctx.user().create().await.unwrap();
+        let token = ctx.session(&user).create().await.unwrap().access_token;
+        let response = test::call_service(&app, sessions(&token)).await;
+        assert_eq!(encoding(&response), "identity");
+    }
+}
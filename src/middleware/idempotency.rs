@@ -0,0 +1,470 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::future::{ready, Ready};
+use std::hash::{Hash, Hasher};
+use std::rc::Rc;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use actix_web::{
+    body::{self, BoxBody, MessageBody},
+    dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
+    http::{header::CONTENT_TYPE, StatusCode},
+    web, Error, HttpMessage, HttpResponse,
+};
+use futures::future::LocalBoxFuture;
+
+use crate::errors::AuthError;
+use crate::middleware::auth::AuthenticatedUser;
+
+const IDEMPOTENCY_HEADER: &str = "Idempotency-Key";
+const MAX_KEY_LENGTH: usize = 255;
+
+// A stored response for a completed request
+#[derive(Clone)]
+struct CachedResponse {
+    status: StatusCode,
+    content_type: Option<String>,
+    body: web::Bytes,
+}
+
+enum Entry {
+    InFlight { fingerprint: u64, started: Instant },
+    Completed { fingerprint: u64, response: CachedResponse, stored: Instant },
+}
+
+impl Entry {
+    fn since(&self) -> Instant {
+        match self {
+            Entry::InFlight { started, .. } => *started,
+            Entry::Completed { stored, .. } => *stored,
+        }
+    }
+}
+
+// How often expired entries are cleared out, besides whenever the store is full
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+struct Entries {
+    by_key: HashMap<String, Entry>,
+    last_sweep: Instant,
+}
+
+/// Shared store of idempotent responses, registered as app data. Entries
+/// expire after the TTL, and once `max_entries` are held the oldest
+/// responses make room for new ones.
+pub struct IdempotencyStore {
+    ttl: Duration,
+    max_entries: usize,
+    entries: Mutex<Entries>,
+}
+
+enum Lookup {
+    Proceed,
+    Replay(CachedResponse),
+    Conflict,
+    Mismatch,
+}
+
+impl IdempotencyStore {
+    pub fn new(ttl_seconds: u64, max_entries: usize) -> Self {
+        IdempotencyStore {
+            ttl: Duration::from_secs(ttl_seconds),
+            max_entries: max_entries.max(1),
+            entries: Mutex::new(Entries {
+                by_key: HashMap::new(),
+                last_sweep: Instant::now(),
+            }),
+        }
+    }
+
+    fn begin(&self, key: &str, fingerprint: u64) -> Lookup {
+        let mut entries = self.entries.lock().unwrap();
+        let ttl = self.ttl;
+
+        if entries.last_sweep.elapsed() >= SWEEP_INTERVAL || entries.by_key.len() >= self.max_entries {
+            entries.by_key.retain(|_, entry| entry.since().elapsed() < ttl);
+            entries.last_sweep = Instant::now();
+        }
+        // Expired since the last sweep
+        if entries.by_key.get(key).map_or(false, |entry| entry.since().elapsed() >= ttl) {
+            entries.by_key.remove(key);
+        }
+
+        match entries.by_key.get(key) {
+            Some(Entry::InFlight { .. }) => Lookup::Conflict,
+            Some(Entry::Completed { fingerprint: stored_fp, .. }) if *stored_fp != fingerprint => {
+                Lookup::Mismatch
+            }
+            Some(Entry::Completed { response, .. }) => Lookup::Replay(response.clone()),
+            None => {
+                // Full of live entries: the oldest response goes, or failing
+                // that the oldest request still running
+                if entries.by_key.len() >= self.max_entries {
+                    let oldest = entries
+                        .by_key
+                        .iter()
+                        .min_by_key(|(_, entry)| (matches!(entry, Entry::InFlight { .. }), entry.since()))
+                        .map(|(key, _)| key.clone());
+                    if let Some(oldest) = oldest {
+                        entries.by_key.remove(&oldest);
+                    }
+                }
+                entries.by_key.insert(
+                    key.to_string(),
+                    Entry::InFlight { fingerprint, started: Instant::now() },
+                );
+                Lookup::Proceed
+            }
+        }
+    }
+
+    fn complete(&self, key: &str, fingerprint: u64, response: CachedResponse) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.by_key.insert(
+            key.to_string(),
+            Entry::Completed { fingerprint, response, stored: Instant::now() },
+        );
+    }
+
+    fn abandon(&self, key: &str) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.by_key.remove(key);
+    }
+}
+
+fn fingerprint(body: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    body.hash(&mut hasher);
+    hasher.finish()
+}
+
+// Only outcomes a retry would get again are stored. Rate limits, timeouts and
+// refusals that depend on the caller's state at the time are not, so a retry
+// after them is handled afresh.
+fn is_replayable(status: StatusCode) -> bool {
+    status.is_success()
+        || matches!(
+            status,
+            StatusCode::BAD_REQUEST
+                | StatusCode::NOT_FOUND
+                | StatusCode::CONFLICT
+                | StatusCode::GONE
+                | StatusCode::PAYLOAD_TOO_LARGE
+                | StatusCode::UNSUPPORTED_MEDIA_TYPE
+                | StatusCode::UNPROCESSABLE_ENTITY
+        )
+}
+
+fn replay(cached: CachedResponse) -> HttpResponse {
+    let mut builder = HttpResponse::build(cached.status);
+    if let Some(content_type) = cached.content_type {
+        builder.insert_header((CONTENT_TYPE, content_type));
+    }
+    builder
+        .insert_header(("Idempotent-Replayed", "true"))
+        .body(cached.body)
+}
+
+// Replays the stored response when a mutation is retried with the same
+// `Idempotency-Key`, so retries don't create duplicate users or emails. Keys
+// belong to the caller: the signed-in user where authentication runs first,
+// otherwise the client address.
+pub struct IdempotencyMiddleware;
+
+impl<S, B> Transform<S, ServiceRequest> for IdempotencyMiddleware
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = IdempotencyMiddlewareService<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(IdempotencyMiddlewareService {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub struct IdempotencyMiddlewareService<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for IdempotencyMiddlewareService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+
+        let idempotency_key = req
+            .headers()
+            .get(IDEMPOTENCY_HEADER)
+            .and_then(|h| h.to_str().ok())
+            .map(|s| s.to_string());
+        let store = req.app_data::<web::Data<IdempotencyStore>>().cloned();
+        let user_id = req.extensions().get::<AuthenticatedUser>().map(|user| user.user_id);
+        let caller = match user_id {
+            Some(user_id) => format!("user:{}", user_id),
+            None => format!("ip:{}", req.connection_info().realip_remote_addr().unwrap_or("unknown")),
+        };
+
+        Box::pin(async move {
+            // Header is opt-in; without it (or a store) behave normally
+            let (idempotency_key, store) = match (idempotency_key, store) {
+                (Some(key), Some(store)) => (key, store),
+                _ => return Ok(service.call(req).await?.map_into_boxed_body()),
+            };
+
+            if idempotency_key.is_empty() || idempotency_key.len() > MAX_KEY_LENGTH {
+                return Err(AuthError::ValidationError(format!(
+                    "{} must be between 1 and {} characters",
+                    IDEMPOTENCY_HEADER, MAX_KEY_LENGTH
+                ))
+                .into());
+            }
+
+            // Buffer the body so it can be fingerprinted and handed back to the handler
+            let payload = req.extract::<web::Bytes>().await?;
+            let fingerprint = fingerprint(&payload);
+            req.set_payload(actix_web::dev::Payload::from(payload));
+
+            let store_key = format!("{} {} {} {}", caller, req.method(), req.path(), idempotency_key);
+
+            match store.begin(&store_key, fingerprint) {
+                Lookup::Replay(cached) => {
+                    let (http_req, _) = req.into_parts();
+                    return Ok(ServiceResponse::new(http_req, replay(cached)));
+                }
+                Lookup::Conflict => return Err(AuthError::IdempotencyConflict.into()),
+                Lookup::Mismatch => {
+                    return Err(AuthError::ValidationError(format!(
+                        "{} was already used with a different request body",
+                        IDEMPOTENCY_HEADER
+                    ))
+                    .into())
+                }
+                Lookup::Proceed => {}
+            }
+
+            let res = match service.call(req).await {
+                Ok(res) => res,
+                Err(err) => {
+                    store.abandon(&store_key);
+                    return Err(err);
+                }
+            };
+
+            // Server errors, rate limits and the like are not cached so the client can retry them
+            if !is_replayable(res.status()) {
+                store.abandon(&store_key);
+                return Ok(res.map_into_boxed_body());
+            }
+
+            let status = res.status();
+            let content_type = res
+                .headers()
+                .get(CONTENT_TYPE)
+                .and_then(|h| h.to_str().ok())
+                .map(|s| s.to_string());
+
+            let (http_req, response) = res.into_parts();
+            let (response, response_body) = response.into_parts();
+            let body = match body::to_bytes(response_body).await {
+                Ok(body) => body,
+                Err(_) => {
+                    store.abandon(&store_key);
+                    return Err(AuthError::InternalServerError(
+                        "Failed to buffer response body".into(),
+                    )
+                    .into());
+                }
+            };
+
+            store.complete(
+                &store_key,
+                fingerprint,
+                CachedResponse { status, content_type, body: body.clone() },
+            );
+
+            let response = response.set_body(BoxBody::new(body));
+            Ok(ServiceResponse::new(http_req, response))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use actix_web::{test, App};
+    use uuid::Uuid;
+
+    use crate::utils::jwt::TokenScope;
+
+    // Answers with each of `statuses` in turn, then keeps giving the last
+    struct Handler {
+        calls: AtomicUsize,
+        statuses: Vec<StatusCode>,
+    }
+
+    async fn handle(handler: web::Data<Handler>) -> HttpResponse {
+        let call = handler.calls.fetch_add(1, Ordering::SeqCst);
+        HttpResponse::build(handler.statuses[call.min(handler.statuses.len() - 1)]).finish()
+    }
+
+    fn request(key: &str, ip: &str, user: Option<Uuid>) -> test::TestRequest {
+        let request = test::TestRequest::post()
+            .uri("/things")
+            .insert_header((IDEMPOTENCY_HEADER, key))
+            .insert_header(("X-Forwarded-For", ip))
+            .set_payload("{}");
+        match user {
+            Some(user) => request.insert_header(("X-Test-User", user.to_string())),
+            None => request,
+        }
+    }
+
+    // Sends the requests in order; returns each response's status and whether
+    // it was a replay, and how many reached the handler
+    async fn send(statuses: &[StatusCode], requests: Vec<test::TestRequest>) -> (Vec<(StatusCode, bool)>, usize) {
+        let handler = web::Data::new(Handler {
+            calls: AtomicUsize::new(0),
+            statuses: statuses.to_vec(),
+        });
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(IdempotencyStore::new(60, 100)))
+                .app_data(handler.clone())
+                .service(
+                    web::resource("/things")
+                        .wrap(IdempotencyMiddleware)
+                        .route(web::post().to(handle)),
+                )
+                // Stands in for the authentication middleware, which runs first
+                .wrap_fn(|req, srv| {
+                    let user_id = req
+                        .headers()
+                        .get("X-Test-User")
+                        .and_then(|h| h.to_str().ok())
+                        .and_then(|h| Uuid::parse_str(h).ok());
+                    if let Some(user_id) = user_id {
+                        req.extensions_mut().insert(AuthenticatedUser {
+                            user_id,
+                            is_admin: false,
+                            scope: TokenScope::Full,
+                            auth_time: None,
+                            amr: Vec::new(),
+                            scopes: Vec::new(),
+                            actor_id: None,
+                        });
+                    }
+                    srv.call(req)
+                }),
+        )
+        .await;
+
+        let mut responses = Vec::new();
+        for request in requests {
+            let res = test::call_service(&app, request.to_request()).await;
+            responses.push((res.status(), res.headers().contains_key("Idempotent-Replayed")));
+        }
+        (responses, handler.calls.load(Ordering::SeqCst))
+    }
+
+    #[actix_web::test]
+    async fn test_rate_limited_responses_are_not_replayed() {
+        let (responses, calls) = send(
+            &[StatusCode::TOO_MANY_REQUESTS, StatusCode::CREATED],
+            vec![
+                request("key-1", "10.0.0.1", None),
+                request("key-1", "10.0.0.1", None),
+                request("key-1", "10.0.0.1", None),
+            ],
+        )
+        .await;
+
+        assert_eq!(
+            responses,
+            vec![
+                (StatusCode::TOO_MANY_REQUESTS, false),
+                (StatusCode::CREATED, false),
+                (StatusCode::CREATED, true),
+            ]
+        );
+        assert_eq!(calls, 2);
+    }
+
+    #[actix_web::test]
+    async fn test_deterministic_client_errors_are_replayed() {
+        let (responses, calls) = send(
+            &[StatusCode::BAD_REQUEST, StatusCode::CREATED],
+            vec![request("key-1", "10.0.0.1", None), request("key-1", "10.0.0.1", None)],
+        )
+        .await;
+
+        assert_eq!(responses, vec![(StatusCode::BAD_REQUEST, false), (StatusCode::BAD_REQUEST, true)]);
+        assert_eq!(calls, 1);
+    }
+
+    #[actix_web::test]
+    async fn test_keys_are_scoped_to_the_caller() {
+        let (alice, bob) = (Uuid::new_v4(), Uuid::new_v4());
+        let (responses, calls) = send(
+            &[StatusCode::CREATED],
+            vec![
+                request("key-1", "10.0.0.1", None),
+                request("key-1", "10.0.0.2", None),
+                request("key-1", "10.0.0.1", Some(alice)),
+                request("key-1", "10.0.0.1", Some(bob)),
+                // Same user from another address is still the same caller
+                request("key-1", "10.0.0.2", Some(alice)),
+            ],
+        )
+        .await;
+
+        let replayed: Vec<bool> = responses.iter().map(|(_, replayed)| *replayed).collect();
+        assert_eq!(replayed, vec![false, false, false, false, true]);
+        assert_eq!(calls, 4);
+    }
+
+    #[test]
+    fn test_expired_and_oldest_responses_make_room() {
+        let response = CachedResponse {
+            status: StatusCode::CREATED,
+            content_type: None,
+            body: web::Bytes::new(),
+        };
+
+        let store = IdempotencyStore::new(60, 2);
+        for key in ["a", "b"] {
+            assert!(matches!(store.begin(key, 1), Lookup::Proceed));
+            store.complete(key, 1, response.clone());
+            std::thread::sleep(Duration::from_millis(2));
+        }
+        // Full, so the oldest response goes to make room
+        assert!(matches!(store.begin("c", 1), Lookup::Proceed));
+        assert!(matches!(store.begin("b", 1), Lookup::Replay(_)));
+        assert!(matches!(store.begin("a", 1), Lookup::Proceed));
+        assert_eq!(store.entries.lock().unwrap().by_key.len(), 2);
+
+        let store = IdempotencyStore::new(0, 2);
+        assert!(matches!(store.begin("a", 1), Lookup::Proceed));
+        store.complete("a", 1, response);
+        assert!(matches!(store.begin("a", 1), Lookup::Proceed));
+    }
+}
@@ -0,0 +1,118 @@
+use std::future::{ready, Ready};
+
+use actix_web::{
+    body::EitherBody,
+    dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
+    web, Error, HttpMessage,
+};
+use futures::future::LocalBoxFuture;
+
+use crate::errors::AuthError;
+use crate::utils::i18n::{Locale, Translator};
+
+// Negotiates the request locale from `Accept-Language` and re-renders
+// `AuthError` responses in that locale
+pub struct LocaleMiddleware;
+
+impl<S, B> Transform<S, ServiceRequest> for LocaleMiddleware
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = LocaleMiddlewareService<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(LocaleMiddlewareService { service }))
+    }
+}
+
+pub struct LocaleMiddlewareService<S> {
+    service: S,
+}
+
+impl<S, B> Service<ServiceRequest> for LocaleMiddlewareService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let translator = req.app_data::<web::Data<Translator>>().cloned();
+
+        let translator = match translator {
+            Some(translator) => translator,
+            None => {
+                // No bundles registered: handlers still get a locale, and
+                // errors pass through untouched
+                req.extensions_mut().insert(Locale::default());
+                let fut = self.service.call(req);
+                return Box::pin(async move { Ok(fut.await?.map_into_left_body()) });
+            }
+        };
+
+        let accept_language = req
+            .headers()
+            .get("Accept-Language")
+            .and_then(|h| h.to_str().ok());
+        let locale = translator.negotiate(accept_language);
+
+        req.extensions_mut().insert(Locale(locale.clone()));
+        let http_req = req.request().clone();
+
+        let fut = self.service.call(req);
+        Box::pin(async move {
+            match fut.await {
+                Ok(res) => {
+                    let localized = res
+                        .response()
+                        .error()
+                        .and_then(|e| e.as_error::<AuthError>())
+                        .map(|e| e.localized_response(&translator, &locale));
+
+                    match localized {
+                        Some(response) => Ok(res.into_response(response).map_into_right_body()),
+                        None => Ok(res.map_into_left_body()),
+                    }
+                }
+                Err(err) => match err.as_error::<AuthError>() {
+                    Some(auth_err) => {
+                        let response = auth_err.localized_response(&translator, &locale);
+                        Ok(ServiceResponse::new(http_req, response).map_into_right_body())
+                    }
+                    None => Err(err),
+                },
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{test, App, HttpResponse};
+
+    use super::*;
+
+    #[actix_web::test]
+    async fn test_default_locale_without_translator() {
+        let app = test::init_service(App::new().wrap(LocaleMiddleware).route(
+            "/",
+            web::get().to(|locale: web::ReqData<Locale>| async move { HttpResponse::Ok().body(locale.0.clone()) }),
+        ))
+        .await;
+
+        let request = test::TestRequest::get().uri("/").insert_header(("Accept-Language", "fr")).to_request();
+        let body = test::call_and_read_body(&app, request).await;
+        assert_eq!(body, "en");
+    }
+}
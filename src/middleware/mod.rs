@@ -1,2 +1,10 @@
+pub mod admin_audit;
 pub mod auth;
+pub mod compression;
+pub mod idempotency;
+pub mod locale;
 pub mod rate_limiter;
+pub mod region;
+pub mod request_limits;
+pub mod step_up;
+pub mod verified_email;
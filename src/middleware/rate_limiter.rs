@@ -1,22 +1,28 @@
 use std::collections::HashMap;
 use std::future::{ready, Ready};
-use std::pin::Pin;
+use std::rc::Rc;
 use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 
 use actix_web::{
     dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
-    Error, HttpMessage,
+    http::header::{HeaderName, HeaderValue},
+    web, Error,
 };
 use futures::future::LocalBoxFuture;
-use futures::Future;
 
+use crate::config::{RateLimitConfig, RateLimitKey, RateLimitPolicy};
 use crate::errors::AuthError;
+use crate::utils::jwt::{decode_jwt, JwtClaims};
 
-// Simple in-memory rate limiter
+// Simple in-memory rate limiter with a global per-IP budget plus
+// per-route policies keyed by IP, username, or authenticated user. Wrapped
+// around the whole app, so it runs before the per-route authentication and
+// checks the bearer token itself for user budgets.
 pub struct RateLimiter {
     max_requests: u32,
     window_duration: u64,
+    policies: Arc<Vec<RateLimitPolicy>>,
     cache: Arc<Mutex<HashMap<String, (u32, Instant)>>>,
 }
 
@@ -25,14 +31,22 @@ impl RateLimiter {
         RateLimiter {
             max_requests,
             window_duration,
+            policies: Arc::new(Vec::new()),
             cache: Arc::new(Mutex::new(HashMap::new())),
         }
     }
+
+    pub fn from_config(config: &RateLimitConfig) -> Self {
+        RateLimiter {
+            policies: Arc::new(config.policies.clone()),
+            ..Self::new(config.requests, config.duration)
+        }
+    }
 }
 
 impl<S, B> Transform<S, ServiceRequest> for RateLimiter
 where
-    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
     S::Future: 'static,
     B: 'static,
 {
@@ -44,24 +58,66 @@ where
 
     fn new_transform(&self, service: S) -> Self::Future {
         ready(Ok(RateLimiterMiddleware {
-            service,
+            service: Rc::new(service),
             max_requests: self.max_requests,
             window_duration: self.window_duration,
+            policies: self.policies.clone(),
             cache: self.cache.clone(),
         }))
     }
 }
 
 pub struct RateLimiterMiddleware<S> {
-    service: S,
+    service: Rc<S>,
     max_requests: u32,
     window_duration: u64,
+    policies: Arc<Vec<RateLimitPolicy>>,
     cache: Arc<Mutex<HashMap<String, (u32, Instant)>>>,
 }
 
+// A single budget to charge this request against
+struct Budget {
+    cache_key: String,
+    max_requests: u32,
+    window_duration: u64,
+}
+
+// Where the request stands against one budget
+struct LimitStatus {
+    limit: u32,
+    remaining: u32,
+    exceeded: bool,
+    reset_after: u64, // Seconds until the window resets
+}
+
+impl LimitStatus {
+    // Exceeded budgets win, then fewest remaining, then longest wait
+    fn is_tighter_than(&self, other: &LimitStatus) -> bool {
+        (self.exceeded, other.remaining, self.reset_after)
+            > (other.exceeded, self.remaining, other.reset_after)
+    }
+}
+
+// Pull a login identifier out of a JSON request body
+fn username_from_body(body: &[u8]) -> Option<String> {
+    let json: serde_json::Value = serde_json::from_slice(body).ok()?;
+    ["username_or_email", "username", "email"]
+        .iter()
+        .find_map(|field| json.get(*field).and_then(|v| v.as_str()))
+        .map(|s| s.trim().to_lowercase())
+}
+
+// The user a request's bearer token was issued to, if it carries a valid one.
+// Requests without one are refused by the route's authentication anyway.
+fn user_from_token(req: &ServiceRequest) -> Option<String> {
+    let header = req.headers().get("Authorization")?.to_str().ok()?;
+    let token = header.strip_prefix("Bearer ").or_else(|| header.strip_prefix("DPoP "))?;
+    decode_jwt::<JwtClaims>(token).ok().map(|claims| claims.sub.to_string())
+}
+
 impl<S, B> Service<ServiceRequest> for RateLimiterMiddleware<S>
 where
-    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
     S::Future: 'static,
     B: 'static,
 {
@@ -71,53 +127,195 @@ where
 
     forward_ready!(service);
 
-    fn call(&self, req: ServiceRequest) -> Self::Future {
-        // Get client IP
-        let ip = req
-            .connection_info()
-            .realip_remote_addr()
-            .unwrap_or("unknown")
-            .to_string();
-
-        // Clean up expired entries
-        {
-            let mut cache = self.cache.lock().unwrap();
-            cache.retain(|_, (_, timestamp)| {
-                timestamp.elapsed() < Duration::from_secs(self.window_duration)
-            });
-        }
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let cache = self.cache.clone();
+        let policies = self.policies.clone();
+        let max_requests = self.max_requests;
+        let window_duration = self.window_duration;
+
+        Box::pin(async move {
+            // Get client IP
+            let ip = req
+                .connection_info()
+                .realip_remote_addr()
+                .unwrap_or("unknown")
+                .to_string();
 
-        // Check if client exceeds rate limit
-        let now = Instant::now();
-        let mut exceeded = false;
+            // Requests that match no route have no policies
+            let route = req.match_pattern().unwrap_or_default();
+            let matching: Vec<RateLimitPolicy> = policies
+                .iter()
+                .filter(|p| p.matches(req.method().as_str(), &route))
+                .cloned()
+                .collect();
 
-        {
-            let mut cache = self.cache.lock().unwrap();
-            let entry = cache.entry(ip).or_insert((0, now));
+            // Only buffer the body when a policy needs the submitted username
+            let username = if matching.iter().any(|p| p.key == RateLimitKey::Username) {
+                let payload = req.extract::<web::Bytes>().await?;
+                let username = username_from_body(&payload);
+                req.set_payload(actix_web::dev::Payload::from(payload));
+                username
+            } else {
+                None
+            };
 
-            // Reset counter if window has elapsed
-            if entry.1.elapsed() >= Duration::from_secs(self.window_duration) {
-                *entry = (1, now);
+            let user_id = if matching.iter().any(|p| p.key == RateLimitKey::User) {
+                user_from_token(&req)
             } else {
-                // Increment counter
-                entry.0 += 1;
-                // Check if rate limit exceeded
-                if entry.0 > self.max_requests {
-                    exceeded = true;
+                None
+            };
+
+            let mut budgets = vec![Budget {
+                cache_key: format!("global:ip:{}", ip),
+                max_requests,
+                window_duration,
+            }];
+
+            for policy in &matching {
+                // Fall back to the client IP when the identity isn't available:
+                // no username in the body, or no valid token
+                let identity = match policy.key {
+                    RateLimitKey::Ip => format!("ip:{}", ip),
+                    RateLimitKey::Username => username
+                        .as_ref()
+                        .map(|u| format!("username:{}", u))
+                        .unwrap_or_else(|| format!("ip:{}", ip)),
+                    RateLimitKey::User => user_id
+                        .as_ref()
+                        .map(|u| format!("user:{}", u))
+                        .unwrap_or_else(|| format!("ip:{}", ip)),
+                };
+
+                budgets.push(Budget {
+                    cache_key: format!(
+                        "{} {}/{}s:{}",
+                        policy.method.as_deref().unwrap_or("*"),
+                        policy.path,
+                        policy.duration,
+                        identity
+                    ),
+                    max_requests: policy.requests,
+                    window_duration: policy.duration,
+                });
+            }
+
+            // The most constrained budget is the one reported to the client
+            let status = {
+                let mut cache = cache.lock().unwrap();
+                let now = Instant::now();
+
+                // Clean up expired entries, keeping anything inside the longest window
+                let longest_window = policies
+                    .iter()
+                    .map(|p| p.duration)
+                    .fold(window_duration, u64::max);
+                cache.retain(|_, (_, timestamp)| {
+                    timestamp.elapsed() < Duration::from_secs(longest_window)
+                });
+
+                let mut status: Option<LimitStatus> = None;
+                for budget in &budgets {
+                    let window = Duration::from_secs(budget.window_duration);
+                    let entry = cache.entry(budget.cache_key.clone()).or_insert((0, now));
+
+                    // Reset counter if window has elapsed
+                    if entry.1.elapsed() >= window {
+                        *entry = (1, now);
+                    } else {
+                        // Increment counter
+                        entry.0 += 1;
+                    }
+
+                    let current = LimitStatus {
+                        limit: budget.max_requests,
+                        remaining: budget.max_requests.saturating_sub(entry.0),
+                        exceeded: entry.0 > budget.max_requests,
+                        reset_after: window.saturating_sub(entry.1.elapsed()).as_secs().max(1),
+                    };
+
+                    status = Some(match status {
+                        Some(prev) if !prev.is_tighter_than(&current) => current,
+                        Some(prev) => prev,
+                        None => current,
+                    });
                 }
+                status.expect("global budget is always present")
+            };
+
+            if status.exceeded {
+                return Err(AuthError::RateLimitExceeded {
+                    limit: status.limit,
+                    retry_after: status.reset_after,
+                }
+                .into());
             }
-        }
 
-        if exceeded {
-            return Box::pin(async move {
-                Err(AuthError::RateLimitExceeded.into())
-            });
-        }
+            let mut res = service.call(req).await?;
+
+            let reset_at = chrono::Utc::now().timestamp() + status.reset_after as i64;
+            let headers = res.headers_mut();
+            for (name, value) in [
+                ("x-ratelimit-limit", status.limit.to_string()),
+                ("x-ratelimit-remaining", status.remaining.to_string()),
+                ("x-ratelimit-reset", reset_at.to_string()),
+            ] {
+                if let Ok(value) = HeaderValue::from_str(&value) {
+                    headers.insert(HeaderName::from_static(name), value);
+                }
+            }
 
-        let fut = self.service.call(req);
-        Box::pin(async move {
-            let res = fut.await?;
             Ok(res)
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use actix_web::dev::ServiceResponse;
+    use actix_web::http::StatusCode;
+    use actix_web::test;
+
+    use crate::test_utils::{login, TestContext};
+
+    #[actix_web::test]
+    async fn test_rate_limits_key_on_the_signed_in_user_and_route() {
+        let mut config = crate::test_utils::test_config();
+        config.rate_limit.policies =
+            crate::config::RateLimitPolicy::parse_list("GET /users/me=2/60:user;POST /login=1/60:ip").unwrap();
+        let ctx = TestContext::with_config(config);
+        let app = ctx.spawn_app().await;
+        let alice = ctx.user().create().await.unwrap();
+        let bob = ctx.user().create().await.unwrap();
+        let alice = ctx.session(&alice).create().await.unwrap();
+        let bob = ctx.session(&bob).create().await.unwrap();
+
+        // Middleware refusals come back as errors rather than responses
+        let status_of = |result: Result<ServiceResponse<_>, actix_web::Error>| match result {
+            Ok(res) => res.status(),
+            Err(e) => e.as_response_error().status_code(),
+        };
+        let me = |bearer: String| {
+            test::TestRequest::get()
+                .uri("/users/me")
+                .insert_header(("X-Forwarded-For", "10.0.0.1"))
+                .insert_header(("Authorization", bearer))
+                .to_request()
+        };
+
+        for _ in 0..2 {
+            assert_eq!(status_of(test::try_call_service(&app, me(alice.bearer())).await), StatusCode::OK);
+        }
+        assert_eq!(
+            status_of(test::try_call_service(&app, me(alice.bearer())).await),
+            StatusCode::TOO_MANY_REQUESTS
+        );
+        // Same address, different user: a budget of its own
+        assert_eq!(status_of(test::try_call_service(&app, me(bob.bearer())).await), StatusCode::OK);
+
+        // Policies name a route, so `/login` doesn't cover `/auth/login`
+        for _ in 0..2 {
+            assert_eq!(login(&app, "nobody", "WrongPass123!").await.status, StatusCode::UNAUTHORIZED);
+        }
+    }
+}
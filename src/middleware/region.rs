@@ -0,0 +1,180 @@
+use std::future::{ready, Ready};
+use std::rc::Rc;
+
+use actix_web::{
+    dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
+    web, Error,
+};
+use futures::future::LocalBoxFuture;
+
+use crate::db::DatabaseConnection;
+use crate::services::auth::AuthService;
+
+// Runs requests carrying an access token or API key against the database of
+// the region its user is in, so admins manage the users of their own region.
+// Requests without one start in the home region; the services route those
+// that name an account, carry an emailed link or resume a held login
+// themselves. Background jobs go through every region in turn.
+pub struct RegionRouting;
+
+impl<S, B> Transform<S, ServiceRequest> for RegionRouting
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = RegionRoutingService<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RegionRoutingService {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub struct RegionRoutingService<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for RegionRoutingService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let token = req
+            .headers()
+            .get("Authorization")
+            .and_then(|h| h.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer ").or_else(|| value.strip_prefix("DPoP ")))
+            .map(|token| token.to_string());
+        let auth_service = req.app_data::<web::Data<AuthService>>().cloned();
+        let service = self.service.clone();
+
+        Box::pin(async move {
+            let region = match (token, auth_service) {
+                (Some(token), Some(auth_service)) => auth_service.region_of_token(&token).await?,
+                _ => None,
+            };
+            DatabaseConnection::in_region(region, async move { service.call(req).await }).await
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::StatusCode;
+    use actix_web::test;
+    use serde_json::{json, Value};
+
+    use crate::db::DatabaseConnection;
+    use crate::test_utils::{confirm_password_reset, login, post_json, request_password_reset, TestContext};
+
+    #[actix_web::test]
+    async fn test_users_are_kept_in_the_region_they_pick() {
+        let mut config = crate::test_utils::test_config();
+        config.regions.database_urls.insert("eu".to_string(), "postgres://eu.example/auth".to_string());
+        let ctx = TestContext::with_config(config);
+        let app = ctx.spawn_app().await;
+        let signup = |username: &str, region: &str| {
+            json!({
+                "username": username,
+                "email": "anna@example.com",
+                "password": "TestPass123!",
+                "password_confirmation": "TestPass123!",
+                "region": region,
+            })
+        };
+
+        let unknown = post_json(&app, "/auth/register", signup("anna", "mars")).await;
+        assert_eq!(unknown.status, StatusCode::BAD_REQUEST);
+        post_json(&app, "/auth/register", signup("anna", "eu")).await.assert_success();
+
+        // Only the directory entry is outside the region
+        assert!(ctx.db.find_user_by_email("anna@example.com").await.is_err());
+        let in_eu = || DatabaseConnection::in_region(Some("eu".to_string()), ctx.db.find_user_by_email("anna@example.com"));
+        let anna = in_eu().await.unwrap();
+        assert_eq!(ctx.db.region_of_identifier("Anna@Example.com").await.unwrap().as_deref(), Some("eu"));
+        assert_eq!(ctx.db.region_of_user(anna.id).await.unwrap().as_deref(), Some("eu"));
+
+        // The address is taken in every region
+        let home = ctx.config.regions.home.clone();
+        let taken = post_json(&app, "/auth/register", signup("anna_again", &home)).await;
+        assert_eq!(taken.status, StatusCode::BAD_REQUEST);
+
+        let session = login(&app, "anna", "TestPass123!").await.assert_success();
+        let refresh_token = session.field("refresh_token").unwrap().to_string();
+        assert!(refresh_token.starts_with("eu_"), "{}", refresh_token);
+
+        let me = test::TestRequest::get()
+            .uri("/users/me")
+            .insert_header(("Authorization", format!("Bearer {}", session.field("access_token").unwrap())))
+            .to_request();
+        let me: Value = test::call_and_read_body_json(&app, me).await;
+        assert_eq!(me["id"], anna.id.to_string());
+        post_json(&app, "/auth/refresh-token", json!({ "refresh_token": refresh_token }))
+            .await
+            .assert_success();
+
+        // Emailed links find their way back to the region
+        request_password_reset(&app, "anna@example.com").await.assert_success();
+        let token = ctx.mailer.token_for("anna@example.com");
+        confirm_password_reset(&app, &token, "NewPass456!").await.assert_success();
+        login(&app, "anna@example.com", "NewPass456!").await.assert_success();
+        assert_ne!(in_eu().await.unwrap().password_hash, anna.password_hash);
+    }
+
+    #[actix_web::test]
+    async fn test_api_keys_and_email_codes_reach_the_users_region() {
+        let mut config = crate::test_utils::test_config();
+        config.regions.database_urls.insert("eu".to_string(), "postgres://eu.example/auth".to_string());
+        config.email_code_login.enabled = true;
+        let ctx = TestContext::with_config(config);
+        let app = ctx.spawn_app().await;
+        let signup = json!({
+            "username": "bruno",
+            "email": "bruno@example.com",
+            "password": "TestPass123!",
+            "password_confirmation": "TestPass123!",
+            "region": "eu",
+        });
+        post_json(&app, "/auth/register", signup).await.assert_success();
+        let session = login(&app, "bruno", "TestPass123!").await.assert_success();
+
+        let request = test::TestRequest::post()
+            .uri("/users/me/api-keys")
+            .insert_header(("Authorization", format!("Bearer {}", session.field("access_token").unwrap())))
+            .set_json(json!({ "name": "CI", "scopes": ["users:read"] }))
+            .to_request();
+        let created: Value = test::call_and_read_body_json(&app, request).await;
+        let key = created["key"].as_str().unwrap();
+        assert!(key.starts_with("bak_eu_"), "{}", key);
+
+        let me = test::TestRequest::get()
+            .uri("/users/me")
+            .insert_header(("Authorization", format!("Bearer {}", key)))
+            .to_request();
+        let me: Value = test::call_and_read_body_json(&app, me).await;
+        assert_eq!(me["username"], "bruno");
+
+        // The code is sent from the user's region and redeemed there
+        let challenge = post_json(&app, "/auth/email-code", json!({ "email": "bruno@example.com" }))
+            .await
+            .assert_success();
+        let code = ctx.mailer.code_for("bruno@example.com");
+        let verify = json!({ "challenge_id": challenge.body["challenge_id"], "code": code });
+        let response = post_json(&app, "/auth/email-code/verify", verify).await.assert_success();
+        assert!(response.field("refresh_token").unwrap().starts_with("eu_"));
+    }
+}
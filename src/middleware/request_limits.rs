@@ -0,0 +1,230 @@
+use std::future::{ready, Ready};
+use std::rc::Rc;
+
+use actix_web::{
+    dev::{forward_ready, Payload, Service, ServiceRequest, ServiceResponse, Transform},
+    error::JsonPayloadError,
+    http::header::{CONTENT_LENGTH, CONTENT_TYPE},
+    web, Error,
+};
+use futures::future::LocalBoxFuture;
+use futures::StreamExt;
+
+use crate::config::RequestLimitsConfig;
+use crate::errors::AuthError;
+
+// Refuses JSON bodies over `REQUEST_JSON_LIMIT` bytes or nested deeper than
+// `REQUEST_JSON_MAX_DEPTH`, before any handler parses them. Other bodies,
+// such as avatar uploads, are left to their routes.
+#[derive(Clone)]
+pub struct RequestLimits {
+    json_limit: usize,
+    max_depth: usize,
+}
+
+impl RequestLimits {
+    pub fn new(config: &RequestLimitsConfig) -> Self {
+        RequestLimits {
+            json_limit: config.json_limit,
+            max_depth: config.max_json_depth,
+        }
+    }
+
+    /// For `web::Json`, to be registered as app data alongside the
+    /// middleware: the same limit, and extractor errors in the API's format
+    pub fn json_config(&self) -> web::JsonConfig {
+        web::JsonConfig::default()
+            .limit(self.json_limit)
+            .error_handler(|err, _req| {
+                let err = match err {
+                    JsonPayloadError::Overflow { limit } => AuthError::PayloadTooLarge { limit },
+                    JsonPayloadError::OverflowKnownLength { limit, .. } => AuthError::PayloadTooLarge { limit },
+                    JsonPayloadError::ContentType => {
+                        AuthError::ValidationError("Request body must be application/json".into())
+                    }
+                    err => AuthError::ValidationError(format!("Invalid request body: {}", err)),
+                };
+                err.into()
+            })
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for RequestLimits
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = RequestLimitsService<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequestLimitsService {
+            service: Rc::new(service),
+            limits: self.clone(),
+        }))
+    }
+}
+
+pub struct RequestLimitsService<S> {
+    service: Rc<S>,
+    limits: RequestLimits,
+}
+
+impl<S, B> Service<ServiceRequest> for RequestLimitsService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let limits = self.limits.clone();
+
+        Box::pin(async move {
+            if !is_json(&req) {
+                return service.call(req).await;
+            }
+
+            let limit = limits.json_limit;
+            let declared = req
+                .headers()
+                .get(CONTENT_LENGTH)
+                .and_then(|h| h.to_str().ok())
+                .and_then(|s| s.parse::<usize>().ok());
+            if declared.map_or(false, |length| length > limit) {
+                return Err(AuthError::PayloadTooLarge { limit }.into());
+            }
+
+            // Stop reading as soon as the body goes over the limit, whatever
+            // Content-Length claimed
+            let mut payload = req.take_payload();
+            let mut body = web::BytesMut::new();
+            while let Some(chunk) = payload.next().await {
+                let chunk = chunk.map_err(|e| AuthError::ValidationError(e.to_string()))?;
+                if body.len() + chunk.len() > limit {
+                    return Err(AuthError::PayloadTooLarge { limit }.into());
+                }
+                body.extend_from_slice(&chunk);
+            }
+
+            if !within_depth(&body, limits.max_depth) {
+                return Err(AuthError::ValidationError(format!(
+                    "Request body is nested more than {} levels deep",
+                    limits.max_depth
+                ))
+                .into());
+            }
+
+            req.set_payload(Payload::from(body.freeze()));
+            service.call(req).await
+        })
+    }
+}
+
+// `application/json` and `+json` types such as `application/merge-patch+json`
+pub(crate) fn is_json(req: &ServiceRequest) -> bool {
+    req.headers()
+        .get(CONTENT_TYPE)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|value| value.split(';').next())
+        .map(|mime| {
+            let mime = mime.trim().to_ascii_lowercase();
+            mime == "application/json" || mime.ends_with("+json")
+        })
+        .unwrap_or(false)
+}
+
+/// Whether no array or object in `body` sits more than `max_depth` deep.
+/// Only brackets outside strings are counted, so malformed JSON passes
+/// through for the parser to reject.
+fn within_depth(body: &[u8], max_depth: usize) -> bool {
+    let mut depth = 0;
+    let mut in_string = false;
+    let mut escaped = false;
+
+    for &byte in body {
+        if in_string {
+            match byte {
+                _ if escaped => escaped = false,
+                b'\\' => escaped = true,
+                b'"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+
+        match byte {
+            b'"' => in_string = true,
+            b'{' | b'[' => {
+                depth += 1;
+                if depth > max_depth {
+                    return false;
+                }
+            }
+            b'}' | b']' => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::StatusCode;
+    use serde_json::json;
+
+    use crate::test_utils::{post_json, TestContext};
+
+    use super::*;
+
+    #[test]
+    fn test_depth_counts_nested_arrays_and_objects() {
+        assert!(within_depth(br#"{"a": [1, {"b": 2}]}"#, 3));
+        assert!(!within_depth(br#"{"a": [1, {"b": 2}]}"#, 2));
+        assert!(within_depth(br#""just a string""#, 0));
+
+        let deep = format!("{}{}", "[".repeat(10_000), "]".repeat(10_000));
+        assert!(!within_depth(deep.as_bytes(), 16));
+    }
+
+    #[test]
+    fn test_depth_ignores_brackets_in_strings() {
+        assert!(within_depth(br#"{"password": "[[[{{{"}"#, 1));
+        assert!(within_depth(br#"{"note": "quote \" then [[[["}"#, 1));
+        assert!(!within_depth(br#"{"note": "\\", "x": [[]]}"#, 2));
+    }
+
+    #[actix_web::test]
+    async fn test_oversized_deep_or_unexpected_json_is_refused() {
+        let mut config = crate::test_utils::test_config();
+        config.request_limits.json_limit = 1024;
+        let ctx = TestContext::with_config(config);
+        let app = ctx.spawn_app().await;
+
+        let response = post_json(&app, "/auth/login", json!({ "username_or_email": "a".repeat(2000), "password": "x" })).await;
+        assert_eq!(response.status, StatusCode::PAYLOAD_TOO_LARGE);
+
+        let nested = (0..20).fold(json!(1), |inner, _| json!([inner]));
+        let response = post_json(&app, "/auth/login", json!({ "username_or_email": "a", "password": "x", "extra": nested })).await;
+        assert_eq!(response.status, StatusCode::BAD_REQUEST);
+
+        let response = post_json(
+            &app,
+            "/auth/login",
+            json!({ "username_or_email": "a", "password": "x", "is_admin": true }),
+        )
+        .await;
+        assert_eq!(response.status, StatusCode::BAD_REQUEST);
+        assert_eq!(response.field("code"), Some("VALIDATION_ERROR"));
+    }
+}
@@ -0,0 +1,166 @@
+use std::future::{ready, Ready};
+
+use actix_web::{
+    dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
+    Error, HttpMessage,
+};
+use chrono::Utc;
+use futures::future::LocalBoxFuture;
+
+use crate::errors::AuthError;
+use crate::middleware::auth::AuthenticatedUser;
+use crate::utils::jwt::{AMR_FEDERATED, AMR_MFA, AMR_PASSWORD};
+
+/// How recently, and with which method, the user must have authenticated
+#[derive(Debug, Clone, Copy)]
+pub struct StepUpPolicy {
+    pub max_age: u64,                     // In seconds
+    pub methods: &'static [&'static str], // Any one of these `amr` values satisfies the policy
+}
+
+impl StepUpPolicy {
+    /// Password (or something stronger) entered within `max_age` seconds
+    pub const fn password_within(max_age: u64) -> Self {
+        StepUpPolicy {
+            max_age,
+            methods: &[AMR_PASSWORD, AMR_MFA],
+        }
+    }
+
+    /// Any sign-in within `max_age` seconds, including through an organization's
+    /// IdP, for actions SSO-only users must also be able to take
+    pub const fn signed_in_within(max_age: u64) -> Self {
+        StepUpPolicy {
+            max_age,
+            methods: &[AMR_PASSWORD, AMR_MFA, AMR_FEDERATED],
+        }
+    }
+
+    /// MFA completed within `max_age` seconds
+    pub const fn mfa_within(max_age: u64) -> Self {
+        StepUpPolicy {
+            max_age,
+            methods: &[AMR_MFA],
+        }
+    }
+
+    pub fn is_satisfied_by(&self, user: &AuthenticatedUser) -> bool {
+        let recent = user.auth_time.map_or(false, |auth_time| {
+            let age = Utc::now().timestamp() - auth_time as i64;
+            age <= self.max_age as i64
+        });
+
+        recent && user.amr.iter().any(|m| self.methods.contains(&m.as_str()))
+    }
+}
+
+// Rejects requests whose token doesn't carry a recent enough authentication
+// event; must be nested inside `AuthMiddleware`, e.g.
+// `wrap = "StepUpMiddleware(StepUpPolicy::password_within(300))"`
+pub struct StepUpMiddleware(pub StepUpPolicy);
+
+impl<S, B> Transform<S, ServiceRequest> for StepUpMiddleware
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = StepUpMiddlewareService<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(StepUpMiddlewareService {
+            service,
+            policy: self.0,
+        }))
+    }
+}
+
+pub struct StepUpMiddlewareService<S> {
+    service: S,
+    policy: StepUpPolicy,
+}
+
+impl<S, B> Service<ServiceRequest> for StepUpMiddlewareService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let satisfied = req
+            .extensions()
+            .get::<AuthenticatedUser>()
+            .map_or(false, |user| self.policy.is_satisfied_by(user));
+
+        if !satisfied {
+            let max_age = self.policy.max_age;
+            return Box::pin(async move {
+                Err(AuthError::ReauthenticationRequired { max_age }.into())
+            });
+        }
+
+        let fut = self.service.call(req);
+        Box::pin(async move {
+            let res = fut.await?;
+            Ok(res)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::jwt::TokenScope;
+    use uuid::Uuid;
+
+    fn user(age: i64, amr: &[&str]) -> AuthenticatedUser {
+        AuthenticatedUser {
+            user_id: Uuid::new_v4(),
+            is_admin: false,
+            scope: TokenScope::Full,
+            auth_time: Some((Utc::now().timestamp() - age) as usize),
+            amr: amr.iter().map(|m| m.to_string()).collect(),
+            scopes: Vec::new(),
+            actor_id: None,
+        }
+    }
+
+    #[test]
+    fn test_password_policy() {
+        let policy = StepUpPolicy::password_within(300);
+
+        assert!(policy.is_satisfied_by(&user(60, &[AMR_PASSWORD])));
+        assert!(!policy.is_satisfied_by(&user(600, &[AMR_PASSWORD])));
+        assert!(!policy.is_satisfied_by(&user(60, &[])));
+    }
+
+    #[test]
+    fn test_mfa_policy() {
+        let policy = StepUpPolicy::mfa_within(3600);
+
+        assert!(policy.is_satisfied_by(&user(1800, &[AMR_PASSWORD, AMR_MFA])));
+        assert!(!policy.is_satisfied_by(&user(1800, &[AMR_PASSWORD])));
+
+        let mut refreshed = user(0, &[AMR_MFA]);
+        refreshed.auth_time = None;
+        assert!(!policy.is_satisfied_by(&refreshed));
+    }
+
+    #[test]
+    fn test_signed_in_policy_accepts_sso() {
+        let policy = StepUpPolicy::signed_in_within(300);
+
+        assert!(policy.is_satisfied_by(&user(60, &[AMR_FEDERATED])));
+        assert!(!StepUpPolicy::password_within(300).is_satisfied_by(&user(60, &[AMR_FEDERATED])));
+    }
+}
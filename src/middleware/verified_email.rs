@@ -0,0 +1,78 @@
+use std::future::{ready, Ready};
+use std::rc::Rc;
+
+use actix_web::{
+    dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
+    web, Error, HttpMessage,
+};
+use futures::future::LocalBoxFuture;
+
+use crate::errors::AuthError;
+use crate::middleware::auth::AuthenticatedUser;
+use crate::services::auth::AuthService;
+
+// Marks a route as sensitive: unless `REQUIRE_VERIFIED_EMAIL` is `never`,
+// users must have verified their email address to use it. Must be nested
+// inside `AuthMiddleware`.
+pub struct RequireVerifiedEmail;
+
+impl<S, B> Transform<S, ServiceRequest> for RequireVerifiedEmail
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = RequireVerifiedEmailService<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequireVerifiedEmailService {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub struct RequireVerifiedEmailService<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for RequireVerifiedEmailService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let user_id = req
+            .extensions()
+            .get::<AuthenticatedUser>()
+            .map(|user| user.user_id);
+        let auth_service = req.app_data::<web::Data<AuthService>>().cloned();
+
+        Box::pin(async move {
+            let (user_id, auth_service) = match (user_id, auth_service) {
+                (Some(user_id), Some(auth_service)) => (user_id, auth_service),
+                (None, _) => return Err(AuthError::InvalidToken.into()),
+                (_, None) => {
+                    return Err(AuthError::InternalServerError(
+                        "AuthService is not registered".into(),
+                    )
+                    .into())
+                }
+            };
+
+            auth_service.ensure_email_verified(user_id).await?;
+            service.call(req).await
+        })
+    }
+}
@@ -0,0 +1,104 @@
+use crate::models::user::UserResponse;
+use crate::schema::account_risk_signals;
+use chrono::{DateTime, Utc};
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// Something suspicious that happened to an account. Stored as text in
+/// `account_risk_signals.signal`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AccountSignal {
+    FailedLogin,  // A wrong password for the account
+    BreachHit,    // Its credentials turned up in a breach
+    RiskySession, // A login blocked or held by risk scoring
+}
+
+impl AccountSignal {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AccountSignal::FailedLogin => "failed_login",
+            AccountSignal::BreachHit => "breach_hit",
+            AccountSignal::RiskySession => "risky_session",
+        }
+    }
+
+    /// Points added to the account's risk score
+    pub fn weight(&self) -> i32 {
+        match self {
+            AccountSignal::FailedLogin => 5,
+            AccountSignal::BreachHit => 40,
+            AccountSignal::RiskySession => 25,
+        }
+    }
+}
+
+impl std::str::FromStr for AccountSignal {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "failed_login" => Ok(AccountSignal::FailedLogin),
+            "breach_hit" => Ok(AccountSignal::BreachHit),
+            "risky_session" => Ok(AccountSignal::RiskySession),
+            other => Err(format!("unknown account signal: {}", other)),
+        }
+    }
+}
+
+/// Protective steps taken automatically as the score rises
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AccountRiskAction {
+    Notify,                 // Email the user and report to the security webhook
+    RevokeSessions,         // Sign the account out everywhere
+    RequireMfaReenrollment, // Reset MFA; the next login must set it up again
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Identifiable)]
+#[diesel(table_name = account_risk_signals)]
+pub struct AccountRiskSignal {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub signal: String, // See `AccountSignal`
+    pub weight: i32,    // As weighted when recorded
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = account_risk_signals)]
+pub struct NewAccountRiskSignal {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub signal: String,
+    pub weight: i32,
+}
+
+impl NewAccountRiskSignal {
+    pub fn new(user_id: Uuid, signal: AccountSignal) -> Self {
+        NewAccountRiskSignal {
+            id: Uuid::new_v4(),
+            user_id,
+            signal: signal.as_str().to_string(),
+            weight: signal.weight(),
+        }
+    }
+}
+
+/// An account's current score and what went into it
+#[derive(Debug, Serialize)]
+pub struct AccountRiskResponse {
+    pub score: i32,
+    pub window_days: u32,
+    pub actions: Vec<AccountRiskAction>, // Taken at the current score
+    pub mfa_reenrollment_required: bool,
+    pub signals: Vec<AccountRiskSignal>, // Newest first
+}
+
+/// What `GET /admin/users/{user_id}` returns
+#[derive(Debug, Serialize)]
+pub struct AdminUserResponse {
+    pub user: UserResponse,
+    pub risk: AccountRiskResponse,
+}
@@ -0,0 +1,128 @@
+use crate::schema::{account_appeals, account_status_events};
+use chrono::{DateTime, Utc};
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use validator::Validate;
+
+/// Whether an account may sign in, and if not, why. Stored as text in `users.status`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AccountStatus {
+    Active,
+    Suspended,       // Temporarily locked, e.g. pending an investigation
+    Banned,          // Permanently locked
+    PendingDeletion, // Scheduled for removal
+    Locked,          // By the owner, who suspected a takeover; a password reset unlocks it
+}
+
+impl AccountStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AccountStatus::Active => "active",
+            AccountStatus::Suspended => "suspended",
+            AccountStatus::Banned => "banned",
+            AccountStatus::PendingDeletion => "pending_deletion",
+            AccountStatus::Locked => "locked",
+        }
+    }
+
+    // Suspensions and bans can be appealed; a pending deletion is undone by
+    // support, and an owner's lock by the owner
+    pub fn can_appeal(&self) -> bool {
+        matches!(self, AccountStatus::Suspended | AccountStatus::Banned)
+    }
+}
+
+impl std::str::FromStr for AccountStatus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "active" => Ok(AccountStatus::Active),
+            "suspended" => Ok(AccountStatus::Suspended),
+            "banned" => Ok(AccountStatus::Banned),
+            "pending_deletion" => Ok(AccountStatus::PendingDeletion),
+            "locked" => Ok(AccountStatus::Locked),
+            other => Err(format!("unknown account status: {}", other)),
+        }
+    }
+}
+
+/// One entry in an account's status history
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Identifiable)]
+#[diesel(table_name = account_status_events)]
+pub struct AccountStatusEvent {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub status: String,
+    pub reason: String,
+    pub actor_id: Option<Uuid>, // The admin who made the change, if any
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = account_status_events)]
+pub struct NewAccountStatusEvent {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub status: String,
+    pub reason: String,
+    pub actor_id: Option<Uuid>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Identifiable)]
+#[diesel(table_name = account_appeals)]
+pub struct AccountAppeal {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub message: String,
+    pub status: String, // "pending", "accepted" or "rejected"
+    pub resolution_note: Option<String>,
+    pub resolved_by: Option<Uuid>,
+    pub resolved_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = account_appeals)]
+pub struct NewAccountAppeal {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub message: String,
+}
+
+/// What a suspended or banned user is shown
+#[derive(Debug, Serialize)]
+pub struct AccountStatusResponse {
+    pub status: AccountStatus,
+    pub reason: Option<String>,
+    pub changed_at: Option<DateTime<Utc>>,
+    pub can_appeal: bool,
+    pub appeal: Option<AccountAppeal>, // The most recent appeal, if any
+}
+
+#[derive(Debug, Validate, Deserialize)]
+pub struct UpdateAccountStatusRequest {
+    pub status: AccountStatus,
+
+    /// Recorded in the audit history and shown to the user
+    #[validate(length(min = 1, max = 1000))]
+    pub reason: String,
+}
+
+#[derive(Debug, Validate, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AppealRequest {
+    #[validate(length(min = 1, max = 5000))]
+    pub message: String,
+}
+
+#[derive(Debug, Validate, Deserialize)]
+pub struct ResolveAppealRequest {
+    /// Accepting an appeal reactivates the account
+    pub accept: bool,
+
+    #[validate(length(min = 1, max = 1000))]
+    pub note: String,
+}
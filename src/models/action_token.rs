@@ -0,0 +1,43 @@
+use crate::schema::{action_token_redemptions, email_sends};
+use chrono::{DateTime, Utc};
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// A one-time action link that has been used
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable)]
+#[diesel(table_name = action_token_redemptions)]
+pub struct ActionTokenRedemption {
+    pub jti: Uuid,
+    pub purpose: String, // See `ActionPurpose`
+    pub user_id: Uuid,
+    pub redeemed_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = action_token_redemptions)]
+pub struct NewActionTokenRedemption {
+    pub jti: Uuid,
+    pub purpose: String,
+    pub user_id: Uuid,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// An action email sent to an address, counted against its resend limits
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable)]
+#[diesel(table_name = email_sends)]
+pub struct EmailSend {
+    pub id: Uuid,
+    pub address: String, // Lowercased
+    pub kind: String,    // See `ThrottledEmail`
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = email_sends)]
+pub struct NewEmailSend {
+    pub id: Uuid,
+    pub address: String,
+    pub kind: String,
+}
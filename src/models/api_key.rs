@@ -0,0 +1,116 @@
+use crate::schema::{api_key_usage, api_keys};
+use chrono::{DateTime, NaiveDate, Utc};
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use validator::Validate;
+
+/// A personal access token for integrations, limited to its scopes
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Identifiable)]
+#[diesel(table_name = api_keys)]
+pub struct ApiKey {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub name: String,
+    pub key_prefix: String, // Start of the key, so users can tell their keys apart
+    pub key_hash: String,   // SHA-256 of the key; the key itself is only shown once
+    pub scopes: Vec<String>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+    pub daily_quota: Option<i64>,   // Overrides API_KEY_DAILY_QUOTA when set
+    pub monthly_quota: Option<i64>, // Overrides API_KEY_MONTHLY_QUOTA when set
+}
+
+impl ApiKey {
+    pub fn is_expired(&self) -> bool {
+        self.expires_at.map_or(false, |expires_at| expires_at < Utc::now())
+    }
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = api_keys)]
+pub struct NewApiKey {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub name: String,
+    pub key_prefix: String,
+    pub key_hash: String,
+    pub scopes: Vec<String>,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Validate, Deserialize)]
+pub struct CreateApiKeyRequest {
+    #[validate(length(min = 1, max = 100))]
+    pub name: String,
+    #[validate(length(max = 32))]
+    pub scopes: Vec<String>, // e.g. ["users:read", "sessions:write"]
+    #[validate(range(min = 1, max = 365))]
+    pub expires_in_days: Option<u32>, // Never expires when unset
+}
+
+#[derive(Debug, Serialize)]
+pub struct ApiKeyResponse {
+    pub id: Uuid,
+    pub name: String,
+    pub key_prefix: String,
+    pub scopes: Vec<String>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+    pub daily_quota: Option<i64>,
+    pub monthly_quota: Option<i64>,
+}
+
+impl From<ApiKey> for ApiKeyResponse {
+    fn from(key: ApiKey) -> Self {
+        ApiKeyResponse {
+            id: key.id,
+            name: key.name,
+            key_prefix: key.key_prefix,
+            scopes: key.scopes,
+            expires_at: key.expires_at,
+            last_used_at: key.last_used_at,
+            created_at: key.created_at,
+            daily_quota: key.daily_quota,
+            monthly_quota: key.monthly_quota,
+        }
+    }
+}
+
+/// A newly created key; the only time the key itself is returned
+#[derive(Debug, Serialize)]
+pub struct CreatedApiKeyResponse {
+    pub key: String,
+    #[serde(flatten)]
+    pub api_key: ApiKeyResponse,
+}
+
+/// Requests made with a key on one UTC day
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable)]
+#[diesel(table_name = api_key_usage)]
+pub struct ApiKeyUsage {
+    pub api_key_id: Uuid,
+    pub day: NaiveDate,
+    pub request_count: i64,
+}
+
+#[derive(Debug, Validate, Deserialize)]
+pub struct UpdateApiKeyQuotaRequest {
+    #[validate(range(min = 0))]
+    pub daily_quota: Option<i64>, // Falls back to the default when unset
+    #[validate(range(min = 0))]
+    pub monthly_quota: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ApiKeyUsageResponse {
+    pub api_key_id: Uuid,
+    pub user_id: Uuid,
+    pub daily_quota: Option<u64>,   // Effective quota, None when unlimited
+    pub monthly_quota: Option<u64>,
+    pub today: i64,
+    pub this_month: i64,
+    pub days: Vec<ApiKeyUsage>, // Most recent first
+}
@@ -0,0 +1,80 @@
+use crate::schema::authenticator_metadata;
+use chrono::{DateTime, Utc};
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// How far an authenticator model got through FIDO certification, from the
+/// status reports in its metadata. Variants are ordered from least to most
+/// assured.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CertificationLevel {
+    NotCertified, // Includes models only self-asserted by their vendor
+    L1,
+    L1Plus,
+    L2,
+    L2Plus,
+    L3,
+    L3Plus,
+}
+
+impl CertificationLevel {
+    /// The status report name, as stored in `authenticator_metadata.certification_level`
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CertificationLevel::NotCertified => "NOT_FIDO_CERTIFIED",
+            CertificationLevel::L1 => "FIDO_CERTIFIED_L1",
+            CertificationLevel::L1Plus => "FIDO_CERTIFIED_L1plus",
+            CertificationLevel::L2 => "FIDO_CERTIFIED_L2",
+            CertificationLevel::L2Plus => "FIDO_CERTIFIED_L2plus",
+            CertificationLevel::L3 => "FIDO_CERTIFIED_L3",
+            CertificationLevel::L3Plus => "FIDO_CERTIFIED_L3plus",
+        }
+    }
+}
+
+impl std::str::FromStr for CertificationLevel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "NOT_FIDO_CERTIFIED" | "SELF_ASSERTION_SUBMITTED" => Ok(CertificationLevel::NotCertified),
+            // Plain FIDO_CERTIFIED predates the levels and means L1
+            "FIDO_CERTIFIED" | "FIDO_CERTIFIED_L1" => Ok(CertificationLevel::L1),
+            "FIDO_CERTIFIED_L1plus" => Ok(CertificationLevel::L1Plus),
+            "FIDO_CERTIFIED_L2" => Ok(CertificationLevel::L2),
+            "FIDO_CERTIFIED_L2plus" => Ok(CertificationLevel::L2Plus),
+            "FIDO_CERTIFIED_L3" => Ok(CertificationLevel::L3),
+            "FIDO_CERTIFIED_L3plus" => Ok(CertificationLevel::L3Plus),
+            other => Err(format!("unknown certification level: {}", other)),
+        }
+    }
+}
+
+/// An authenticator model from the FIDO metadata service
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable)]
+pub struct AuthenticatorMetadata {
+    pub aaguid: Uuid,
+    pub description: String,                 // Friendly model name, e.g. "YubiKey 5 Series"
+    pub certification_level: Option<String>, // `CertificationLevel::as_str`, `None` when never reported
+    pub compromised: bool,                   // Latest status report says its keys can't be trusted
+    pub blob_number: i32,                    // Serial number of the BLOB this came from
+    pub updated_at: DateTime<Utc>,
+}
+
+impl AuthenticatorMetadata {
+    pub fn certification(&self) -> Option<CertificationLevel> {
+        self.certification_level.as_deref().and_then(|level| level.parse().ok())
+    }
+}
+
+#[derive(Debug, Clone, Insertable)]
+#[diesel(table_name = authenticator_metadata)]
+pub struct NewAuthenticatorMetadata {
+    pub aaguid: Uuid,
+    pub description: String,
+    pub certification_level: Option<String>,
+    pub compromised: bool,
+    pub blob_number: i32,
+}
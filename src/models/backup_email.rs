@@ -0,0 +1,69 @@
+use crate::schema::user_emails;
+use crate::utils::secret::{redacted_debug, Secret};
+use chrono::{DateTime, Utc};
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use validator::Validate;
+
+/// A secondary address for recovery and security notifications
+#[derive(Clone, Serialize, Deserialize, Queryable, Identifiable)]
+#[diesel(table_name = user_emails)]
+pub struct BackupEmail {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub email: String,
+    pub is_verified: bool,
+    pub verification_token: Option<String>,
+    pub verification_sent_at: Option<DateTime<Utc>>,
+    pub verified_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+redacted_debug!(BackupEmail { id, user_id, email, is_verified });
+
+#[derive(Insertable)]
+#[diesel(table_name = user_emails)]
+pub struct NewBackupEmail {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub email: String,
+    pub verification_token: Option<String>,
+    pub verification_sent_at: Option<DateTime<Utc>>,
+}
+
+redacted_debug!(NewBackupEmail { id, user_id, email });
+
+#[derive(Debug, Validate, Deserialize)]
+pub struct AddBackupEmailRequest {
+    #[validate(email, length(max = 254))]
+    pub email: String,
+}
+
+#[derive(Debug, Validate, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct VerifyBackupEmailRequest {
+    #[validate(length(min = 1, max = 4096))]
+    pub token: Secret<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BackupEmailResponse {
+    pub id: Uuid,
+    pub email: String,
+    pub is_verified: bool,
+    pub created_at: DateTime<Utc>,
+    pub verified_at: Option<DateTime<Utc>>,
+}
+
+impl From<BackupEmail> for BackupEmailResponse {
+    fn from(email: BackupEmail) -> Self {
+        BackupEmailResponse {
+            id: email.id,
+            email: email.email,
+            is_verified: email.is_verified,
+            created_at: email.created_at,
+            verified_at: email.verified_at,
+        }
+    }
+}
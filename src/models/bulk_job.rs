@@ -0,0 +1,294 @@
+use crate::schema::bulk_jobs;
+use chrono::{DateTime, Utc};
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// What a bulk job does to each account it matches
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum BulkAction {
+    /// Sign these users out everywhere
+    RevokeSessions { user_ids: Vec<Uuid> },
+    /// Expire the passwords of accounts on a breach list, given as emails
+    /// or usernames
+    ForcePasswordReset {
+        accounts: Vec<String>,
+        #[serde(default)]
+        revoke_sessions: bool,
+    },
+    /// Suspend every account whose primary address is at this domain
+    DeactivateDomain { domain: String, reason: String },
+}
+
+impl BulkAction {
+    /// As stored in `bulk_jobs.action`
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BulkAction::RevokeSessions { .. } => "revoke_sessions",
+            BulkAction::ForcePasswordReset { .. } => "force_password_reset",
+            BulkAction::DeactivateDomain { .. } => "deactivate_domain",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BulkJobStatus {
+    Queued,
+    Running,
+    Completed,
+    Failed, // Stopped before reaching every account; see `error`
+}
+
+impl BulkJobStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BulkJobStatus::Queued => "queued",
+            BulkJobStatus::Running => "running",
+            BulkJobStatus::Completed => "completed",
+            BulkJobStatus::Failed => "failed",
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        matches!(self, BulkJobStatus::Completed | BulkJobStatus::Failed)
+    }
+}
+
+impl std::str::FromStr for BulkJobStatus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "queued" => Ok(BulkJobStatus::Queued),
+            "running" => Ok(BulkJobStatus::Running),
+            "completed" => Ok(BulkJobStatus::Completed),
+            "failed" => Ok(BulkJobStatus::Failed),
+            other => Err(format!("unknown bulk job status: {}", other)),
+        }
+    }
+}
+
+/// What happened to one account, or one entry of the list that matched none
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BulkOutcome {
+    WouldChange, // Dry runs only
+    Changed,
+    Skipped,
+    NotFound,
+    Failed,
+}
+
+impl BulkOutcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BulkOutcome::WouldChange => "would_change",
+            BulkOutcome::Changed => "changed",
+            BulkOutcome::Skipped => "skipped",
+            BulkOutcome::NotFound => "not_found",
+            BulkOutcome::Failed => "failed",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BulkJobResult {
+    pub target: String, // The list entry, or the matched account's email
+    pub user_id: Option<Uuid>,
+    pub outcome: BulkOutcome,
+    pub detail: Option<String>, // Why it was skipped or failed
+}
+
+/// An admin operation over many accounts, run in the background
+#[derive(Debug, Clone, Queryable, Identifiable)]
+#[diesel(table_name = bulk_jobs)]
+pub struct BulkJob {
+    pub id: Uuid,
+    pub action: String,            // `BulkAction::as_str`
+    pub params: serde_json::Value, // The `BulkAction` itself
+    pub dry_run: bool,
+    pub status: String, // `BulkJobStatus::as_str`
+    pub created_by: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+    pub started_at: Option<DateTime<Utc>>,
+    pub finished_at: Option<DateTime<Utc>>,
+    pub results: serde_json::Value, // `Vec<BulkJobResult>`, once finished
+    pub error: Option<String>,
+}
+
+impl BulkJob {
+    pub fn status(&self) -> BulkJobStatus {
+        self.status.parse().unwrap_or(BulkJobStatus::Failed)
+    }
+
+    pub fn bulk_action(&self) -> Result<BulkAction, String> {
+        serde_json::from_value(self.params.clone()).map_err(|e| e.to_string())
+    }
+
+    pub fn results(&self) -> Vec<BulkJobResult> {
+        serde_json::from_value(self.results.clone()).unwrap_or_default()
+    }
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = bulk_jobs)]
+pub struct NewBulkJob {
+    pub id: Uuid,
+    pub action: String,
+    pub params: serde_json::Value,
+    pub dry_run: bool,
+    pub created_by: Option<Uuid>,
+}
+
+#[derive(Debug, Deserialize)]
+#[cfg_attr(feature = "client", derive(Serialize))]
+#[serde(deny_unknown_fields)]
+pub struct BulkJobRequest {
+    pub action: BulkAction,
+    /// Only report what would change
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+/// How many list entries or accounts ended each way
+#[derive(Debug, Default, Serialize)]
+#[cfg_attr(feature = "client", derive(Deserialize))]
+pub struct BulkJobSummary {
+    pub would_change: usize,
+    pub changed: usize,
+    pub skipped: usize,
+    pub not_found: usize,
+    pub failed: usize,
+}
+
+impl BulkJobSummary {
+    pub fn of(results: &[BulkJobResult]) -> Self {
+        let mut summary = BulkJobSummary::default();
+        for result in results {
+            match result.outcome {
+                BulkOutcome::WouldChange => summary.would_change += 1,
+                BulkOutcome::Changed => summary.changed += 1,
+                BulkOutcome::Skipped => summary.skipped += 1,
+                BulkOutcome::NotFound => summary.not_found += 1,
+                BulkOutcome::Failed => summary.failed += 1,
+            }
+        }
+        summary
+    }
+}
+
+/// A job as admins see it, without the per-account results
+#[derive(Debug, Serialize)]
+#[cfg_attr(feature = "client", derive(Deserialize))]
+pub struct BulkJobResponse {
+    pub id: Uuid,
+    pub action: String,
+    pub dry_run: bool,
+    pub status: BulkJobStatus,
+    pub created_by: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+    pub started_at: Option<DateTime<Utc>>,
+    pub finished_at: Option<DateTime<Utc>>,
+    pub summary: BulkJobSummary,
+    pub error: Option<String>,
+}
+
+impl From<BulkJob> for BulkJobResponse {
+    fn from(job: BulkJob) -> Self {
+        BulkJobResponse {
+            summary: BulkJobSummary::of(&job.results()),
+            status: job.status(),
+            id: job.id,
+            action: job.action,
+            dry_run: job.dry_run,
+            created_by: job.created_by,
+            created_at: job.created_at,
+            started_at: job.started_at,
+            finished_at: job.finished_at,
+            error: job.error,
+        }
+    }
+}
+
+/// The per-account results of a finished job, for download
+#[derive(Debug, Serialize)]
+#[cfg_attr(feature = "client", derive(Deserialize))]
+pub struct BulkJobReport {
+    pub job: BulkJobResponse,
+    pub results: Vec<BulkJobResult>,
+}
+
+impl BulkJobReport {
+    pub fn to_csv(&self) -> String {
+        let field = |value: &str| {
+            if value.contains(|c| matches!(c, ',' | '"' | '\n' | '\r')) {
+                format!("\"{}\"", value.replace('"', "\"\""))
+            } else {
+                value.to_string()
+            }
+        };
+
+        let mut csv = String::from("target,user_id,outcome,detail\n");
+        for result in &self.results {
+            csv.push_str(&format!(
+                "{},{},{},{}\n",
+                field(&result.target),
+                result.user_id.map(|id| id.to_string()).unwrap_or_default(),
+                result.outcome.as_str(),
+                field(result.detail.as_deref().unwrap_or_default())
+            ));
+        }
+        csv
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report_csv_quotes_fields() {
+        let user_id = Uuid::new_v4();
+        let results = vec![
+            BulkJobResult {
+                target: "alice@example.com".to_string(),
+                user_id: Some(user_id),
+                outcome: BulkOutcome::Changed,
+                detail: None,
+            },
+            BulkJobResult {
+                target: "bob, the \"builder\"".to_string(),
+                user_id: None,
+                outcome: BulkOutcome::NotFound,
+                detail: None,
+            },
+        ];
+        let report = BulkJobReport {
+            job: BulkJobResponse {
+                id: Uuid::new_v4(),
+                action: "force_password_reset".to_string(),
+                dry_run: false,
+                status: BulkJobStatus::Completed,
+                created_by: None,
+                created_at: Utc::now(),
+                started_at: None,
+                finished_at: None,
+                summary: BulkJobSummary::of(&results),
+                error: None,
+            },
+            results,
+        };
+
+        assert_eq!(report.job.summary.changed, 1);
+        assert_eq!(report.job.summary.not_found, 1);
+        assert_eq!(
+            report.to_csv(),
+            format!(
+                "target,user_id,outcome,detail\nalice@example.com,{},changed,\n\"bob, the \"\"builder\"\"\",,not_found,\n",
+                user_id
+            )
+        );
+    }
+}
@@ -0,0 +1,74 @@
+use crate::schema::canary_credentials;
+use chrono::{DateTime, Utc};
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::utils::secret::{redacted_debug, Secret};
+
+/// A planted decoy account, and optionally an API key on it. Nobody legitimate
+/// knows the credentials, so any attempt to use them is an intrusion.
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Identifiable)]
+#[diesel(table_name = canary_credentials)]
+pub struct CanaryCredential {
+    pub id: Uuid,
+    pub user_id: Uuid,            // The decoy account
+    pub api_key_id: Option<Uuid>, // Set when a decoy API key was issued too
+    pub label: String,            // Where it was planted, e.g. "wiki: deploy runbook"
+    pub trip_count: i32,
+    pub last_tripped_at: Option<DateTime<Utc>>,
+    pub last_tripped_ip: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = canary_credentials)]
+pub struct NewCanaryCredential {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub api_key_id: Option<Uuid>,
+    pub label: String,
+}
+
+#[derive(Debug, Validate, Deserialize)]
+pub struct CreateCanaryRequest {
+    #[validate(length(min = 1, max = 100))]
+    pub label: String,
+
+    #[validate(length(min = 3, max = 50))]
+    pub username: String,
+
+    #[validate(email, length(max = 254))]
+    pub email: String,
+
+    /// The password that will be planted alongside the username
+    #[validate(length(min = 8, max = 1024))]
+    pub password: Secret<String>,
+
+    #[serde(default)]
+    pub with_api_key: bool,
+}
+
+/// A newly planted canary; the only time its API key is returned
+#[derive(Serialize)]
+pub struct CreatedCanaryResponse {
+    pub canary: CanaryCredential,
+    pub api_key: Option<String>,
+}
+
+redacted_debug!(CreatedCanaryResponse { canary });
+
+/// A source refused after using a canary credential
+#[derive(Debug, Clone, Serialize)]
+pub struct BlockedSource {
+    pub ip: String,
+    pub blocked_until: DateTime<Utc>,
+}
+
+/// What `GET /admin/canaries` returns
+#[derive(Debug, Serialize)]
+pub struct CanaryListResponse {
+    pub canaries: Vec<CanaryCredential>, // Newest first
+    pub blocked_sources: Vec<BlockedSource>,
+}
@@ -0,0 +1,158 @@
+use crate::schema::{client_applications, client_consents};
+use chrono::{DateTime, Utc};
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::utils::scopes;
+
+// Grant types a client may be allowed, named as in OAuth
+pub const GRANT_PASSWORD: &str = "password"; // Signing in on the hosted pages
+pub const GRANT_REFRESH_TOKEN: &str = "refresh_token";
+pub const GRANT_TYPES: &[&str] = &[GRANT_PASSWORD, GRANT_REFRESH_TOKEN];
+
+/// An app registered to send users to the hosted sign-in pages. Tokens are
+/// only posted to one of its redirect URIs, and sessions started for it keep
+/// to its grants and token lifetimes. Third-party clients only get the scopes
+/// the user consents to.
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable)]
+#[diesel(table_name = client_applications)]
+pub struct ClientApplication {
+    pub client_id: String,
+    pub name: String,
+    pub redirect_uris: Vec<String>,     // Matched exactly, character for character
+    pub allowed_grants: Vec<String>,    // From `GRANT_TYPES`
+    pub access_token_ttl: Option<i32>,  // Seconds; caps the deployment's lifetime when set
+    pub refresh_token_ttl: Option<i32>, // Seconds; likewise
+    pub logo_url: Option<String>,       // Shown beside its name on the sign-in pages
+    pub updated_by: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub first_party: bool,              // Trusted with the user's own scopes, without asking
+    #[serde(skip_serializing, default)]
+    pub client_secret_hash: Option<String>, // Set for confidential clients; see `client_secret_hash`
+}
+
+impl ClientApplication {
+    /// Whether the client has to authenticate with a secret
+    pub fn is_confidential(&self) -> bool {
+        self.client_secret_hash.is_some()
+    }
+
+    pub fn allows_grant(&self, grant: &str) -> bool {
+        self.allowed_grants.iter().any(|allowed| allowed == grant)
+    }
+
+    pub fn allows_redirect_uri(&self, redirect_uri: &str) -> bool {
+        self.redirect_uris.iter().any(|registered| registered == redirect_uri)
+    }
+}
+
+#[derive(Debug, Insertable, AsChangeset)]
+#[diesel(table_name = client_applications, treat_none_as_null = true)]
+pub struct NewClientApplication {
+    pub client_id: String,
+    pub name: String,
+    pub redirect_uris: Vec<String>,
+    pub allowed_grants: Vec<String>,
+    pub access_token_ttl: Option<i32>,
+    pub refresh_token_ttl: Option<i32>,
+    pub logo_url: Option<String>,
+    pub updated_by: Option<Uuid>,
+    pub first_party: bool,
+    pub client_secret_hash: Option<String>,
+}
+
+/// What is stored for a client's secret. Secrets are long and random, so a
+/// fast hash is enough.
+pub fn client_secret_hash(secret: &str) -> String {
+    hex::encode(Sha256::digest(secret.as_bytes()))
+}
+
+/// Replaces the client's registration; left-out fields are cleared
+#[derive(Debug, Validate, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ClientApplicationRequest {
+    #[validate(length(min = 1, max = 100))]
+    pub name: String,
+
+    /// Absolute URLs without a fragment; https unless on a loopback host
+    #[validate(length(min = 1, max = 20))]
+    pub redirect_uris: Vec<String>,
+
+    #[serde(default)]
+    pub allowed_grants: Vec<String>,
+
+    /// Seconds; the deployment's lifetime when left out
+    #[validate(range(min = 60, max = 86400))]
+    pub access_token_ttl: Option<i32>,
+
+    /// Seconds; the deployment's lifetime when left out
+    #[validate(range(min = 300, max = 31536000))]
+    pub refresh_token_ttl: Option<i32>,
+
+    /// An absolute https URL
+    #[validate(length(max = 2048))]
+    pub logo_url: Option<String>,
+
+    /// One of the deployment's own apps, which users aren't asked to consent to
+    #[serde(default)]
+    pub first_party: bool,
+
+    /// Authenticates with a secret, e.g. a server-side app. The secret is
+    /// generated when the client becomes confidential and shown only then.
+    #[serde(default)]
+    pub confidential: bool,
+
+    /// Replace a confidential client's secret with a new one
+    #[serde(default)]
+    pub rotate_secret: bool,
+}
+
+/// A client just registered or replaced, with its secret when one was generated
+#[derive(Debug, Serialize)]
+pub struct SavedClientApplication {
+    #[serde(flatten)]
+    pub client: ClientApplication,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_secret: Option<String>, // Never shown again
+}
+
+/// Scopes a user has let a third-party client have
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable)]
+#[diesel(table_name = client_consents)]
+pub struct ClientConsent {
+    pub user_id: Uuid,
+    pub client_id: String,
+    pub scopes: Vec<String>,
+    pub granted_at: DateTime<Utc>, // First consent; asking for more scopes later doesn't move it
+    pub updated_at: DateTime<Utc>,
+}
+
+impl ClientConsent {
+    /// Whether every requested scope is among those already granted
+    pub fn covers(&self, requested: &[String]) -> bool {
+        requested.iter().all(|scope| scopes::grants(&self.scopes, scope))
+    }
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = client_consents)]
+pub struct NewClientConsent {
+    pub user_id: Uuid,
+    pub client_id: String,
+    pub scopes: Vec<String>,
+}
+
+/// A client the user has let act for them
+#[derive(Debug, Serialize)]
+pub struct AuthorizedApp {
+    pub client_id: String,
+    pub name: String,
+    pub logo_url: Option<String>,
+    pub scopes: Vec<String>,
+    pub granted_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
@@ -0,0 +1,25 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use validator::Validate;
+
+/// An admin asking for a token acting as another user, e.g. for support tooling
+#[derive(Debug, Validate, Deserialize)]
+pub struct DelegatedTokenRequest {
+    #[validate(length(min = 1, max = 32))]
+    pub scopes: Vec<String>, // e.g. ["users:read"]; never admin scopes
+    #[validate(range(min = 1))]
+    pub expires_in: Option<u64>, // In seconds, at most `DELEGATED_TOKEN_MAX_TTL`
+    #[validate(length(min = 1, max = 500))]
+    pub reason: String, // Recorded in the audit log, e.g. a support ticket
+}
+
+#[derive(Debug, Serialize)]
+pub struct DelegatedTokenResponse {
+    pub access_token: String,
+    pub token_type: String,
+    pub expires_in: u64,
+    pub expires_at: DateTime<Utc>,
+    pub token_id: Uuid, // The token's `jti`, for revoking it early
+    pub scopes: Vec<String>,
+}
@@ -0,0 +1,79 @@
+use crate::schema::trusted_devices;
+use chrono::{DateTime, Utc};
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::models::user::LoginResponse;
+use crate::utils::secret::{redacted_debug, Secret};
+
+/// A device that signs in without an emailed code until it expires
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Identifiable)]
+#[diesel(table_name = trusted_devices)]
+pub struct TrustedDevice {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    #[serde(skip_serializing)]
+    pub token_hash: String, // SHA-256 of the device token; the token itself stays on the device
+    pub name: Option<String>, // e.g. "Chrome on macOS"
+    pub expires_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = trusted_devices)]
+pub struct NewTrustedDevice {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub token_hash: String,
+    pub name: Option<String>,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Validate, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EmailCodeStartRequest {
+    #[validate(email, length(max = 254))]
+    pub email: String,
+}
+
+/// Returned whether or not the address belongs to an account
+#[derive(Debug, Serialize)]
+pub struct EmailCodeChallenge {
+    pub challenge_id: Uuid,
+    pub expires_in: u64,
+}
+
+#[derive(Debug, Validate, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EmailCodeVerifyRequest {
+    pub challenge_id: Uuid,
+    #[validate(length(equal = 6))]
+    pub code: Secret<String>,
+    /// Skip the code on this device next time
+    #[serde(default)]
+    pub trust_device: bool,
+}
+
+#[derive(Debug, Validate, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TrustedDeviceLoginRequest {
+    #[validate(email, length(max = 254))]
+    pub email: String,
+    #[validate(length(min = 1, max = 256))]
+    pub device_token: Secret<String>,
+}
+
+/// A completed email code login; `device_token` is only returned when the
+/// device was trusted, and only this once
+#[derive(Serialize)]
+pub struct EmailCodeLoginResponse {
+    #[serde(flatten)]
+    pub login: LoginResponse,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub device_token: Option<String>,
+}
+
+redacted_debug!(EmailCodeLoginResponse { login });
@@ -0,0 +1,48 @@
+use crate::schema::feature_flags;
+use chrono::{DateTime, Utc};
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use validator::Validate;
+
+/// An operator-controlled switch. Off for everyone while disabled; once
+/// enabled, on for members of `organization_ids` and for `rollout_percent`
+/// of users, picked by a stable hash of the flag and user.
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable)]
+#[diesel(table_name = feature_flags)]
+pub struct FeatureFlag {
+    pub key: String,
+    pub description: Option<String>,
+    pub enabled: bool,
+    pub rollout_percent: i32,        // 0-100; 100 turns it on for everyone
+    pub organization_ids: Vec<Uuid>, // On for these tenants' members whatever the percentage
+    pub updated_by: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Insertable, AsChangeset)]
+#[diesel(table_name = feature_flags, treat_none_as_null = true)]
+pub struct NewFeatureFlag {
+    pub key: String,
+    pub description: Option<String>,
+    pub enabled: bool,
+    pub rollout_percent: i32,
+    pub organization_ids: Vec<Uuid>,
+    pub updated_by: Option<Uuid>,
+}
+
+#[derive(Debug, Validate, Deserialize)]
+pub struct FeatureFlagRequest {
+    #[validate(length(max = 500))]
+    pub description: Option<String>,
+
+    pub enabled: bool,
+
+    /// Share of users it's on for; everyone when left out
+    #[validate(range(min = 0, max = 100))]
+    pub rollout_percent: Option<i32>,
+
+    #[serde(default)]
+    pub organization_ids: Vec<Uuid>,
+}
@@ -0,0 +1,40 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::models::User;
+
+/// An account refusing password logins after repeated failures
+#[derive(Debug, Serialize)]
+pub struct LockedAccount {
+    pub user_id: Uuid,
+    pub username: String,
+    pub email: String,
+    pub locked_until: DateTime<Utc>,
+}
+
+impl From<User> for LockedAccount {
+    fn from(user: User) -> Self {
+        LockedAccount {
+            user_id: user.id,
+            email: user.display_email().to_string(),
+            username: user.username,
+            locked_until: user.locked_until.unwrap_or_else(Utc::now),
+        }
+    }
+}
+
+/// An IP refusing password logins after repeated failures
+#[derive(Debug, Serialize)]
+pub struct LockedIp {
+    pub ip: String,
+    pub seconds_left: u64,
+}
+
+/// Every lockout in force. IP lockouts are kept in memory, so these are the
+/// ones this instance knows about.
+#[derive(Debug, Serialize)]
+pub struct LockoutsResponse {
+    pub accounts: Vec<LockedAccount>,
+    pub ips: Vec<LockedIp>,
+}
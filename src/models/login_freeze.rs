@@ -0,0 +1,47 @@
+use crate::schema::login_freezes;
+use chrono::{DateTime, Utc};
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use validator::Validate;
+
+/// New logins and registrations are refused with `message` while this exists;
+/// sessions already signed in keep working
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Identifiable)]
+#[diesel(table_name = login_freezes)]
+pub struct LoginFreeze {
+    pub id: Uuid,
+    pub organization_id: Option<Uuid>, // `None` freezes every login
+    pub message: String,               // Shown to whoever is refused
+    pub frozen_by: Option<Uuid>,       // The admin who set it
+    pub created_at: DateTime<Utc>,
+}
+
+impl LoginFreeze {
+    pub fn is_global(&self) -> bool {
+        self.organization_id.is_none()
+    }
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = login_freezes)]
+pub struct NewLoginFreeze {
+    pub id: Uuid,
+    pub organization_id: Option<Uuid>,
+    pub message: String,
+    pub frozen_by: Option<Uuid>,
+}
+
+#[derive(Debug, Validate, Deserialize)]
+pub struct LoginFreezeRequest {
+    /// Shown instead of the configured `LOGIN_FREEZE_MESSAGE`
+    #[validate(length(min = 1, max = 500))]
+    pub message: Option<String>,
+}
+
+/// What `GET /admin/login-freezes` returns
+#[derive(Debug, Serialize)]
+pub struct LoginFreezeList {
+    pub configured: bool, // `LOGIN_FREEZE` is set, which only a restart lifts
+    pub freezes: Vec<LoginFreeze>, // The global freeze first, then newest first
+}
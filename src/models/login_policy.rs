@@ -0,0 +1,200 @@
+use crate::schema::login_policies;
+use chrono::{DateTime, Duration, NaiveTime, Utc};
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use validator::Validate;
+
+/// What happens to a login a policy doesn't allow
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LoginPolicyAction {
+    #[default]
+    Block,
+    StepUp, // Ask for the second factor; users without one are blocked
+}
+
+impl LoginPolicyAction {
+    /// As stored in `login_policies.on_violation`
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LoginPolicyAction::Block => "block",
+            LoginPolicyAction::StepUp => "step_up",
+        }
+    }
+}
+
+impl std::str::FromStr for LoginPolicyAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "block" => Ok(LoginPolicyAction::Block),
+            "step_up" => Ok(LoginPolicyAction::StepUp),
+            other => Err(format!("unknown login policy action: {}", other)),
+        }
+    }
+}
+
+/// Whose logins a policy applies to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoginPolicyScope {
+    User(Uuid),
+    Organization(Uuid), // Every member of the organization
+}
+
+impl LoginPolicyScope {
+    pub fn matches(&self, policy: &LoginPolicy) -> bool {
+        match self {
+            LoginPolicyScope::User(user_id) => policy.user_id == Some(*user_id),
+            LoginPolicyScope::Organization(organization_id) => policy.organization_id == Some(*organization_id),
+        }
+    }
+}
+
+/// Why a login falls outside a policy
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoginPolicyViolation {
+    Country,
+    OutsideWindow,
+}
+
+/// When and where an account may sign in
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Identifiable)]
+#[diesel(table_name = login_policies)]
+pub struct LoginPolicy {
+    pub id: Uuid,
+    pub user_id: Option<Uuid>,           // Set for a user's own policy
+    pub organization_id: Option<Uuid>,   // Set for an organization's, covering its members
+    pub allowed_countries: Vec<String>,  // ISO 3166 alpha-2, upper case; empty allows any
+    pub window_start: Option<NaiveTime>, // Logins are allowed from here...
+    pub window_end: Option<NaiveTime>,   // ...until here, wrapping past midnight if earlier
+    pub utc_offset_minutes: i32,         // Of the clock the window is read on
+    pub on_violation: String,            // `LoginPolicyAction::as_str`
+    pub updated_by: Option<Uuid>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl LoginPolicy {
+    pub fn action(&self) -> LoginPolicyAction {
+        self.on_violation.parse().unwrap_or_default()
+    }
+
+    /// What about a login at `at` from `country` this policy doesn't allow.
+    /// A login from an unknown country can't be shown to be allowed.
+    pub fn violation(&self, at: DateTime<Utc>, country: Option<&str>) -> Option<LoginPolicyViolation> {
+        if !self.allowed_countries.is_empty()
+            && !country.map_or(false, |country| self.allowed_countries.iter().any(|c| c == country))
+        {
+            return Some(LoginPolicyViolation::Country);
+        }
+
+        if let (Some(start), Some(end)) = (self.window_start, self.window_end) {
+            let local = (at + Duration::minutes(self.utc_offset_minutes as i64)).time();
+            let inside = if start <= end {
+                start <= local && local < end
+            } else {
+                local >= start || local < end
+            };
+            if !inside {
+                return Some(LoginPolicyViolation::OutsideWindow);
+            }
+        }
+
+        None
+    }
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = login_policies)]
+pub struct NewLoginPolicy {
+    pub id: Uuid,
+    pub user_id: Option<Uuid>,
+    pub organization_id: Option<Uuid>,
+    pub allowed_countries: Vec<String>,
+    pub window_start: Option<NaiveTime>,
+    pub window_end: Option<NaiveTime>,
+    pub utc_offset_minutes: i32,
+    pub on_violation: String,
+    pub updated_by: Option<Uuid>,
+}
+
+/// Replaces the policy of the user or organization it's saved for
+#[derive(Debug, Validate, Deserialize)]
+#[cfg_attr(feature = "client", derive(Serialize))]
+#[serde(deny_unknown_fields)]
+pub struct LoginPolicyRequest {
+    /// ISO 3166 alpha-2 codes, e.g. "DE"; empty allows every country
+    #[serde(default)]
+    #[validate(length(max = 250))]
+    pub allowed_countries: Vec<String>,
+
+    /// "HH:MM:SS"; both or neither
+    pub window_start: Option<NaiveTime>,
+    pub window_end: Option<NaiveTime>,
+    #[serde(default)]
+    #[validate(range(min = -720, max = 840))]
+    pub utc_offset_minutes: i32,
+
+    #[serde(default)]
+    pub on_violation: LoginPolicyAction,
+}
+
+/// The user's own policy and those of their organizations, every one of
+/// which a login has to satisfy
+#[derive(Debug, Serialize)]
+#[cfg_attr(feature = "client", derive(Deserialize))]
+pub struct LoginPolicyOverview {
+    pub policy: Option<LoginPolicy>,
+    pub organization_policies: Vec<LoginPolicy>,
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn policy(countries: &[&str], window: Option<(u32, u32)>, utc_offset_minutes: i32) -> LoginPolicy {
+        LoginPolicy {
+            id: Uuid::new_v4(),
+            user_id: Some(Uuid::new_v4()),
+            organization_id: None,
+            allowed_countries: countries.iter().map(|c| c.to_string()).collect(),
+            window_start: window.map(|(start, _)| NaiveTime::from_hms_opt(start, 0, 0).unwrap()),
+            window_end: window.map(|(_, end)| NaiveTime::from_hms_opt(end, 0, 0).unwrap()),
+            utc_offset_minutes,
+            on_violation: "block".to_string(),
+            updated_by: None,
+            updated_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn test_countries_must_be_known_and_listed() {
+        let at = Utc::now();
+        let policy = policy(&["DE", "FR"], None, 0);
+        assert_eq!(policy.violation(at, Some("DE")), None);
+        assert_eq!(policy.violation(at, Some("US")), Some(LoginPolicyViolation::Country));
+        assert_eq!(policy.violation(at, None), Some(LoginPolicyViolation::Country));
+    }
+
+    #[test]
+    fn test_windows_are_read_on_the_policy_clock() {
+        let office = policy(&[], Some((9, 17)), 120);
+        // 07:30 UTC is 09:30 at UTC+2
+        assert_eq!(office.violation(Utc.with_ymd_and_hms(2024, 3, 1, 7, 30, 0).unwrap(), None), None);
+        assert_eq!(
+            office.violation(Utc.with_ymd_and_hms(2024, 3, 1, 15, 0, 0).unwrap(), None),
+            Some(LoginPolicyViolation::OutsideWindow)
+        );
+
+        let night_shift = policy(&[], Some((22, 6)), 0);
+        assert_eq!(night_shift.violation(Utc.with_ymd_and_hms(2024, 3, 1, 23, 0, 0).unwrap(), None), None);
+        assert_eq!(night_shift.violation(Utc.with_ymd_and_hms(2024, 3, 1, 2, 0, 0).unwrap(), None), None);
+        assert_eq!(
+            night_shift.violation(Utc.with_ymd_and_hms(2024, 3, 1, 12, 0, 0).unwrap(), None),
+            Some(LoginPolicyViolation::OutsideWindow)
+        );
+    }
+}
@@ -1,11 +1,12 @@
-use crate::schema::mfa_recovery_codes;
+use crate::schema::{mfa_failures, mfa_recovery_codes, mfa_totp_devices};
+use crate::utils::secret::{redacted_debug, Secret};
 use chrono::{DateTime, Utc};
 use diesel::prelude::*;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 use validator::Validate;
 
-#[derive(Debug, Serialize, Deserialize, Queryable, Identifiable)]
+#[derive(Clone, Serialize, Deserialize, Queryable, Identifiable)]
 #[diesel(table_name = mfa_recovery_codes)]
 pub struct MfaRecoveryCode {
     pub id: Uuid,
@@ -16,7 +17,9 @@ pub struct MfaRecoveryCode {
     pub created_at: DateTime<Utc>,
 }
 
-#[derive(Debug, Insertable)]
+redacted_debug!(MfaRecoveryCode { id, user_id, is_used, used_at });
+
+#[derive(Insertable)]
 #[diesel(table_name = mfa_recovery_codes)]
 pub struct NewMfaRecoveryCode {
     pub id: Uuid,
@@ -24,34 +27,304 @@ pub struct NewMfaRecoveryCode {
     pub code: String,
 }
 
+redacted_debug!(NewMfaRecoveryCode { id, user_id });
+
+/// An enrolled (or enrolling) TOTP authenticator
+#[derive(Clone, Serialize, Deserialize, Queryable, Identifiable)]
+#[diesel(table_name = mfa_totp_devices)]
+pub struct TotpDevice {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub name: String,
+    pub secret: String,
+    pub is_confirmed: bool,
+    pub created_at: DateTime<Utc>,
+    pub confirmed_at: Option<DateTime<Utc>>,
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+redacted_debug!(TotpDevice { id, user_id, name, is_confirmed });
+
+#[derive(Insertable)]
+#[diesel(table_name = mfa_totp_devices)]
+pub struct NewTotpDevice {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub name: String,
+    pub secret: String,
+}
+
+redacted_debug!(NewTotpDevice { id, user_id, name });
+
+/// Wrong TOTP or recovery codes at the second step of a login since the window started
+#[derive(Debug, Clone, Queryable)]
+#[diesel(table_name = mfa_failures)]
+pub struct MfaFailures {
+    pub user_id: Uuid,
+    pub failure_count: i32,
+    pub last_failed_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Validate, Deserialize)]
+#[cfg_attr(feature = "client", derive(Serialize))]
+#[serde(deny_unknown_fields)]
+pub struct AddTotpDeviceRequest {
+    #[validate(length(min = 1, max = 50))]
+    pub name: String,
+}
+
+#[derive(Debug, Validate, Deserialize)]
+#[cfg_attr(feature = "client", derive(Serialize))]
+#[serde(deny_unknown_fields)]
+pub struct ConfirmTotpDeviceRequest {
+    #[validate(length(max = 16))]
+    pub mfa_code: String,
+}
+
+#[derive(Serialize)]
+#[cfg_attr(feature = "client", derive(Deserialize))]
+pub struct TotpDeviceSetupResponse {
+    pub device_id: Uuid,
+    pub secret: String,
+    pub qr_code_url: String,
+}
+
+redacted_debug!(TotpDeviceSetupResponse { device_id });
+
+/// A TOTP device as shown to its owner; the secret is never returned
+#[derive(Debug, Serialize)]
+#[cfg_attr(feature = "client", derive(Deserialize))]
+pub struct TotpDeviceResponse {
+    pub id: Option<Uuid>, // None for the primary authenticator, which is removed by disabling MFA
+    pub name: String,
+    pub primary: bool, // Enrolled through `mfa_setup` rather than added as a device
+    pub is_confirmed: bool,
+    pub created_at: Option<DateTime<Utc>>, // Not recorded for the primary authenticator
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+impl TotpDeviceResponse {
+    pub const PRIMARY_NAME: &'static str = "Authenticator app";
+
+    /// The authenticator enrolled through `mfa_setup`
+    pub fn primary() -> Self {
+        TotpDeviceResponse {
+            id: None,
+            name: Self::PRIMARY_NAME.into(),
+            primary: true,
+            is_confirmed: true,
+            created_at: None,
+            last_used_at: None,
+        }
+    }
+}
+
+impl From<TotpDevice> for TotpDeviceResponse {
+    fn from(device: TotpDevice) -> Self {
+        TotpDeviceResponse {
+            id: Some(device.id),
+            name: device.name,
+            primary: false,
+            is_confirmed: device.is_confirmed,
+            created_at: Some(device.created_at),
+            last_used_at: device.last_used_at,
+        }
+    }
+}
+
+/// Confirms which device was removed
 #[derive(Debug, Serialize)]
+#[cfg_attr(feature = "client", derive(Deserialize))]
+pub struct TotpDeviceRemovedResponse {
+    pub device_id: Uuid,
+    pub name: String,
+    pub remaining_devices: usize, // Confirmed authenticators left, the primary one included
+}
+
+#[derive(Serialize)]
+#[cfg_attr(feature = "client", derive(Deserialize))]
 pub struct MfaRecoveryCodesResponse {
     pub recovery_codes: Vec<String>,
 }
 
+redacted_debug!(MfaRecoveryCodesResponse {});
+
+/// How many of the user's recovery codes are left
+#[derive(Debug, Serialize)]
+#[cfg_attr(feature = "client", derive(Deserialize))]
+pub struct RecoveryCodeStatus {
+    pub total: usize,
+    pub remaining: usize,
+    pub generated_at: Option<DateTime<Utc>>,
+    pub low: bool, // Fewer than `MFA_RECOVERY_CODE_WARN_BELOW` left
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub warning: Option<String>,
+}
+
+impl RecoveryCodeStatus {
+    pub fn new(codes: &[MfaRecoveryCode], warn_below: usize) -> Self {
+        let total = codes.len();
+        let remaining = codes.iter().filter(|c| !c.is_used).count();
+        let low = total > 0 && remaining < warn_below;
+
+        let warning = match remaining {
+            _ if !low => None,
+            0 => Some("You've used all your recovery codes. Generate a new set so you can still sign in if you lose your authenticator".to_string()),
+            1 => Some("Only 1 recovery code is left. Generate a new set; it replaces the code you have now".to_string()),
+            n => Some(format!("Only {} recovery codes are left. Generate a new set; it replaces the codes you have now", n)),
+        };
+
+        RecoveryCodeStatus {
+            total,
+            remaining,
+            generated_at: codes.iter().map(|c| c.created_at).min(),
+            low,
+            warning,
+        }
+    }
+}
+
+/// A second factor that can finish a password login
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MfaMethod {
+    Totp,
+    Passkey, // A security key or platform passkey, asserted with WebAuthn
+}
+
+impl MfaMethod {
+    /// The order methods are offered in when the user hasn't picked one
+    pub const DEFAULT_ORDER: [MfaMethod; 2] = [MfaMethod::Totp, MfaMethod::Passkey];
+
+    /// As stored in `mfa_method_preferences.methods`
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MfaMethod::Totp => "totp",
+            MfaMethod::Passkey => "passkey",
+        }
+    }
+
+    /// The methods a user has set up, their preferred ones first. Methods
+    /// missing from `preferred` keep their default order after those.
+    pub fn in_order(enrolled: &[MfaMethod], preferred: &[String]) -> Vec<MfaMethod> {
+        let rank = |method: &MfaMethod| {
+            preferred
+                .iter()
+                .position(|p| p == method.as_str())
+                .unwrap_or(preferred.len() + Self::DEFAULT_ORDER.iter().position(|m| m == method).unwrap_or(0))
+        };
+
+        let mut methods = enrolled.to_vec();
+        methods.sort_by_key(rank);
+        methods.dedup();
+        methods
+    }
+}
+
+impl std::str::FromStr for MfaMethod {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "totp" => Ok(MfaMethod::Totp),
+            "passkey" => Ok(MfaMethod::Passkey),
+            other => Err(format!("unknown MFA method: {}", other)),
+        }
+    }
+}
+
+/// Second factors in the order the user wants them offered
+#[derive(Debug, Validate, Deserialize)]
+#[cfg_attr(feature = "client", derive(Serialize))]
+#[serde(deny_unknown_fields)]
+pub struct MfaMethodOrderRequest {
+    #[validate(length(min = 1, max = 2))]
+    pub methods: Vec<MfaMethod>,
+}
+
+/// The second factors a user can finish a login with, preferred first
+#[derive(Debug, Serialize)]
+#[cfg_attr(feature = "client", derive(Deserialize))]
+pub struct MfaMethodsResponse {
+    pub methods: Vec<MfaMethod>,
+}
+
 #[derive(Debug, Validate, Deserialize)]
+#[cfg_attr(feature = "client", derive(Serialize))]
+#[serde(deny_unknown_fields)]
 pub struct MfaLoginRequest {
+    #[validate(length(max = 254))]
     pub username_or_email: String,
-    pub password: String,
+    #[validate(length(max = 1024))]
+    pub password: Secret<String>,
+    #[validate(length(max = 16))]
     pub mfa_code: Option<String>,
-    pub recovery_code: Option<String>,
+    #[validate(length(max = 64))]
+    pub recovery_code: Option<Secret<String>>,
+
+    /// Only needed once repeated failures call for one
+    pub captcha_id: Option<Uuid>,
+    #[validate(length(max = 64))]
+    pub captcha_answer: Option<String>,
 }
 
 #[derive(Debug, Validate, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct MfaVerifyRequest {
+    #[validate(length(max = 16))]
     pub mfa_code: String,
 }
 
 #[derive(Debug, Validate, Deserialize)]
+#[cfg_attr(feature = "client", derive(Serialize))]
+#[serde(deny_unknown_fields)]
 pub struct MfaRecoveryRequest {
-    pub recovery_code: String,
+    #[validate(length(max = 64))]
+    pub recovery_code: Secret<String>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Serialize)]
+#[cfg_attr(feature = "client", derive(Deserialize))]
 pub struct MfaVerifyResponse {
     pub access_token: String,
     pub refresh_token: String,
     pub token_type: String,
     pub expires_in: u64,
     pub user: super::user::UserResponse,
+    pub password_change_required: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub policy_acceptance_required: Option<super::policy::PolicyNotice>,
+    /// Set after signing in with a recovery code, to show how many are left
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub recovery_codes: Option<RecoveryCodeStatus>,
+}
+
+redacted_debug!(MfaVerifyResponse {
+    token_type,
+    expires_in,
+    user,
+    password_change_required,
+    policy_acceptance_required,
+    recovery_codes,
+});
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_methods_follow_the_users_order() {
+        let both = [MfaMethod::Totp, MfaMethod::Passkey];
+        assert_eq!(MfaMethod::in_order(&both, &[]), both);
+        assert_eq!(
+            MfaMethod::in_order(&both, &["passkey".to_string()]),
+            [MfaMethod::Passkey, MfaMethod::Totp]
+        );
+
+        // Preferred methods the user no longer has are skipped
+        assert_eq!(
+            MfaMethod::in_order(&[MfaMethod::Totp], &["passkey".to_string(), "totp".to_string()]),
+            [MfaMethod::Totp]
+        );
+    }
 }
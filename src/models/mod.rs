@@ -1,9 +1,57 @@
 pub mod user;
+pub mod account_risk;
+pub mod account_status;
+pub mod action_token;
+pub mod api_key;
+pub mod authenticator;
+pub mod backup_email;
+pub mod bulk_job;
+pub mod canary;
+pub mod client_application;
+pub mod delegation;
+pub mod email_code;
+pub mod feature_flag;
+pub mod lockout;
+pub mod login_freeze;
+pub mod login_policy;
 pub mod session;
 pub mod mfa;
+pub mod notification;
+pub mod organization;
+pub mod outbox;
+pub mod pagination;
 pub mod passwordless;
+pub mod policy;
+pub mod proxy_alias;
+pub mod security_question;
+pub mod sso;
+pub mod user_directory;
 
 pub use user::*;
+pub use account_risk::*;
+pub use account_status::*;
+pub use action_token::*;
+pub use api_key::*;
+pub use authenticator::*;
+pub use backup_email::*;
+pub use bulk_job::*;
+pub use canary::*;
+pub use client_application::*;
+pub use delegation::*;
+pub use email_code::*;
+pub use feature_flag::*;
+pub use lockout::*;
+pub use login_freeze::*;
+pub use login_policy::*;
 pub use session::*;
 pub use mfa::*;
+pub use notification::*;
+pub use organization::*;
+pub use outbox::*;
+pub use pagination::*;
+pub use policy::*;
+pub use proxy_alias::*;
+pub use security_question::*;
+pub use sso::*;
+pub use user_directory::*;
 pub use passwordless::*;
@@ -0,0 +1,124 @@
+use crate::schema::notification_preferences;
+use crate::utils::secret::Secret;
+use chrono::{DateTime, Utc};
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use validator::Validate;
+
+/// A kind of email the user can turn off
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationCategory {
+    SecurityAlerts, // Risk notices; alerts about changes to the account are always sent
+    ProductEmails,
+}
+
+impl NotificationCategory {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            NotificationCategory::SecurityAlerts => "security_alerts",
+            NotificationCategory::ProductEmails => "product_emails",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable)]
+#[diesel(table_name = notification_preferences)]
+pub struct NotificationPreferences {
+    pub user_id: Uuid,
+    pub security_alerts: bool,
+    pub product_emails: bool,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl NotificationPreferences {
+    /// What a user who never changed anything gets
+    pub fn defaults(user_id: Uuid) -> Self {
+        NotificationPreferences {
+            user_id,
+            security_alerts: true,
+            product_emails: false,
+            updated_at: Utc::now(),
+        }
+    }
+
+    pub fn allows(&self, category: NotificationCategory) -> bool {
+        match category {
+            NotificationCategory::SecurityAlerts => self.security_alerts,
+            NotificationCategory::ProductEmails => self.product_emails,
+        }
+    }
+}
+
+#[derive(Debug, Insertable, AsChangeset)]
+#[diesel(table_name = notification_preferences)]
+pub struct NewNotificationPreferences {
+    pub user_id: Uuid,
+    pub security_alerts: bool,
+    pub product_emails: bool,
+}
+
+/// Changes to the current preferences; left-out categories stay as they are
+#[derive(Debug, Default, Validate, Deserialize)]
+pub struct UpdateNotificationPreferencesRequest {
+    pub security_alerts: Option<bool>,
+    pub product_emails: Option<bool>,
+}
+
+impl UpdateNotificationPreferencesRequest {
+    pub fn apply_to(&self, current: &NotificationPreferences) -> NewNotificationPreferences {
+        NewNotificationPreferences {
+            user_id: current.user_id,
+            security_alerts: self.security_alerts.unwrap_or(current.security_alerts),
+            product_emails: self.product_emails.unwrap_or(current.product_emails),
+        }
+    }
+}
+
+/// The token from an unsubscribe link, which stands in for signing in
+#[derive(Debug, Validate, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NotificationLinkRequest {
+    #[validate(length(min = 1, max = 4096))]
+    pub token: Secret<String>,
+}
+
+/// Preference changes made from an unsubscribe link
+#[derive(Debug, Validate, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LinkedPreferencesRequest {
+    #[validate(length(min = 1, max = 4096))]
+    pub token: Secret<String>,
+    pub security_alerts: Option<bool>,
+    pub product_emails: Option<bool>,
+}
+
+impl LinkedPreferencesRequest {
+    pub fn changes(&self) -> UpdateNotificationPreferencesRequest {
+        UpdateNotificationPreferencesRequest {
+            security_alerts: self.security_alerts,
+            product_emails: self.product_emails,
+        }
+    }
+}
+
+/// What the preference page shows: the settings, and for a link, the
+/// category it was sent for
+#[derive(Debug, Serialize)]
+pub struct NotificationPreferencesResponse {
+    pub security_alerts: bool,
+    pub product_emails: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unsubscribe_from: Option<NotificationCategory>,
+}
+
+impl From<NotificationPreferences> for NotificationPreferencesResponse {
+    fn from(preferences: NotificationPreferences) -> Self {
+        NotificationPreferencesResponse {
+            security_alerts: preferences.security_alerts,
+            product_emails: preferences.product_emails,
+            unsubscribe_from: None,
+        }
+    }
+}
@@ -0,0 +1,252 @@
+use crate::schema::{organization_branding, organization_domains, organization_members, organizations};
+use chrono::{DateTime, Utc};
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use validator::Validate;
+
+/// A member's role within an organization, stored as text in `organization_members.role`.
+/// Variants are ordered from least to most privileged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OrganizationRole {
+    Member,
+    Admin, // Manages the organization's settings, including SSO
+    Owner,
+}
+
+impl OrganizationRole {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OrganizationRole::Member => "member",
+            OrganizationRole::Admin => "admin",
+            OrganizationRole::Owner => "owner",
+        }
+    }
+}
+
+impl std::str::FromStr for OrganizationRole {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "member" => Ok(OrganizationRole::Member),
+            "admin" => Ok(OrganizationRole::Admin),
+            "owner" => Ok(OrganizationRole::Owner),
+            other => Err(format!("unknown organization role: {}", other)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Identifiable)]
+#[diesel(table_name = organizations)]
+pub struct Organization {
+    pub id: Uuid,
+    pub name: String,
+    pub slug: String,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = organizations)]
+pub struct NewOrganization {
+    pub id: Uuid,
+    pub name: String,
+    pub slug: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Identifiable)]
+#[diesel(table_name = organization_members)]
+pub struct OrganizationMember {
+    pub id: Uuid,
+    pub organization_id: Uuid,
+    pub user_id: Uuid,
+    pub role: String,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl OrganizationMember {
+    pub fn role(&self) -> OrganizationRole {
+        // The column is constrained to known roles
+        self.role.parse().unwrap_or(OrganizationRole::Member)
+    }
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = organization_members)]
+pub struct NewOrganizationMember {
+    pub id: Uuid,
+    pub organization_id: Uuid,
+    pub user_id: Uuid,
+    pub role: String,
+}
+
+/// An email domain claimed by an organization. Once verified through DNS, its users
+/// are routed to the organization's identity provider and may auto-join.
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Identifiable)]
+#[diesel(table_name = organization_domains)]
+pub struct OrganizationDomain {
+    pub id: Uuid,
+    pub organization_id: Uuid,
+    pub domain: String,
+    pub created_at: DateTime<Utc>,
+    pub verification_token: String, // Published in the domain's TXT record
+    pub verified_at: Option<DateTime<Utc>>,
+    pub last_checked_at: Option<DateTime<Utc>>,
+    pub auto_join: bool, // Add users who verify an address at this domain as members
+}
+
+impl OrganizationDomain {
+    pub fn is_verified(&self) -> bool {
+        self.verified_at.is_some()
+    }
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = organization_domains)]
+pub struct NewOrganizationDomain {
+    pub id: Uuid,
+    pub organization_id: Uuid,
+    pub domain: String,
+    pub verification_token: String,
+    pub auto_join: bool,
+}
+
+#[derive(Debug, Validate, Deserialize)]
+pub struct AddOrganizationDomainRequest {
+    #[validate(length(min = 3, max = 253))]
+    pub domain: String,
+
+    #[serde(default)]
+    pub auto_join: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateOrganizationDomainRequest {
+    pub auto_join: bool,
+}
+
+/// A claimed domain and the TXT record that proves ownership of it
+#[derive(Debug, Serialize)]
+pub struct OrganizationDomainResponse {
+    pub id: Uuid,
+    pub domain: String,
+    pub verified: bool,
+    pub verified_at: Option<DateTime<Utc>>,
+    pub last_checked_at: Option<DateTime<Utc>>,
+    pub auto_join: bool,
+    pub txt_record_name: String,
+    pub txt_record_value: String,
+}
+
+#[derive(Debug, Validate, Deserialize)]
+pub struct CreateOrganizationRequest {
+    #[validate(length(min = 1, max = 100))]
+    pub name: String,
+
+    /// Lowercase letters, digits and hyphens; unique across organizations
+    #[validate(length(min = 2, max = 50))]
+    pub slug: String,
+}
+
+/// A new account for someone joining the organization, activated by email
+#[derive(Debug, Validate, Deserialize)]
+pub struct InviteMemberRequest {
+    #[validate(email, length(max = 254))]
+    pub email: String,
+
+    /// Derived from the address when left out
+    #[validate(length(min = 3, max = 50))]
+    pub username: Option<String>,
+
+    /// `member` when left out; invitations can't grant ownership
+    pub role: Option<OrganizationRole>,
+}
+
+/// An organization as seen by one of its members
+#[derive(Debug, Serialize)]
+pub struct OrganizationResponse {
+    pub id: Uuid,
+    pub name: String,
+    pub slug: String,
+    pub role: OrganizationRole,
+    pub created_at: DateTime<Utc>,
+}
+
+impl OrganizationResponse {
+    pub fn new(organization: Organization, role: OrganizationRole) -> Self {
+        OrganizationResponse {
+            id: organization.id,
+            name: organization.name,
+            slug: organization.slug,
+            role,
+            created_at: organization.created_at,
+        }
+    }
+}
+
+/// How the organization's hosted sign-in pages look; unset fields fall back
+/// to the deployment's defaults
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable)]
+#[diesel(table_name = organization_branding)]
+pub struct OrganizationBranding {
+    pub organization_id: Uuid,
+    pub display_name: Option<String>, // The organization's name when unset
+    pub logo_url: Option<String>,
+    pub primary_color: Option<String>, // `#rrggbb`, lowercase
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Insertable, AsChangeset)]
+#[diesel(table_name = organization_branding, treat_none_as_null = true)]
+pub struct NewOrganizationBranding {
+    pub organization_id: Uuid,
+    pub display_name: Option<String>,
+    pub logo_url: Option<String>,
+    pub primary_color: Option<String>,
+}
+
+/// Replaces the organization's branding; left-out fields are cleared
+#[derive(Debug, Validate, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OrganizationBrandingRequest {
+    #[validate(length(min = 1, max = 100))]
+    pub display_name: Option<String>,
+
+    /// An absolute https URL
+    #[validate(length(max = 2048))]
+    pub logo_url: Option<String>,
+
+    /// `#rrggbb`
+    #[validate(length(equal = 7))]
+    pub primary_color: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct OrganizationBrandingResponse {
+    pub display_name: Option<String>,
+    pub logo_url: Option<String>,
+    pub primary_color: Option<String>,
+    pub updated_at: Option<DateTime<Utc>>, // Unset until the branding is first saved
+}
+
+impl From<Option<OrganizationBranding>> for OrganizationBrandingResponse {
+    fn from(branding: Option<OrganizationBranding>) -> Self {
+        match branding {
+            Some(branding) => OrganizationBrandingResponse {
+                display_name: branding.display_name,
+                logo_url: branding.logo_url,
+                primary_color: branding.primary_color,
+                updated_at: Some(branding.updated_at),
+            },
+            None => OrganizationBrandingResponse {
+                display_name: None,
+                logo_url: None,
+                primary_color: None,
+                updated_at: None,
+            },
+        }
+    }
+}
@@ -0,0 +1,132 @@
+use crate::schema::events_outbox;
+use chrono::{DateTime, Utc};
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// Auth events published to subscribers. Stored as text in `events_outbox.event_type`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EventType {
+    #[serde(rename = "user.created")]
+    UserCreated,
+    #[serde(rename = "user.guest_upgraded")]
+    GuestUpgraded,
+    #[serde(rename = "user.email_verified")]
+    EmailVerified,
+    #[serde(rename = "user.password_changed")]
+    PasswordChanged,
+    #[serde(rename = "user.status_changed")]
+    StatusChanged,
+    #[serde(rename = "user.archived")]
+    Archived,
+    #[serde(rename = "user.reactivated")]
+    Reactivated,
+    #[serde(rename = "user.delegated_token_issued")]
+    DelegatedTokenIssued,
+    #[serde(rename = "user.delegated_token_revoked")]
+    DelegatedTokenRevoked,
+    #[serde(rename = "user.client_authorized")]
+    ClientAuthorized,
+    #[serde(rename = "user.client_authorization_revoked")]
+    ClientAuthorizationRevoked,
+    #[serde(rename = "user.logged_in")]
+    LoggedIn, // A session was started by signing in; the account's login history
+    #[serde(rename = "admin.request")]
+    AdminRequest, // About the admin who made the call; see `middleware::admin_audit`
+}
+
+impl EventType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EventType::UserCreated => "user.created",
+            EventType::GuestUpgraded => "user.guest_upgraded",
+            EventType::EmailVerified => "user.email_verified",
+            EventType::PasswordChanged => "user.password_changed",
+            EventType::StatusChanged => "user.status_changed",
+            EventType::Archived => "user.archived",
+            EventType::Reactivated => "user.reactivated",
+            EventType::DelegatedTokenIssued => "user.delegated_token_issued",
+            EventType::DelegatedTokenRevoked => "user.delegated_token_revoked",
+            EventType::ClientAuthorized => "user.client_authorized",
+            EventType::ClientAuthorizationRevoked => "user.client_authorization_revoked",
+            EventType::LoggedIn => "user.logged_in",
+            EventType::AdminRequest => "admin.request",
+        }
+    }
+}
+
+/// An event waiting in, or delivered from, the outbox
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Identifiable)]
+#[diesel(table_name = events_outbox)]
+pub struct OutboxEvent {
+    pub id: Uuid, // Also the event id subscribers see, so they can drop redeliveries
+    pub event_type: String,
+    pub aggregate_id: Uuid, // The user the event is about
+    pub payload: serde_json::Value,
+    pub attempts: i32,
+    pub last_error: Option<String>,
+    pub next_attempt_at: DateTime<Utc>,
+    pub delivered_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Filters for browsing events from the admin pages
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct AuditEventFilter {
+    pub user_id: Option<Uuid>,
+    pub event_type: Option<String>, // e.g. "user.status_changed"
+}
+
+impl AuditEventFilter {
+    pub fn matches(&self, event: &OutboxEvent) -> bool {
+        self.user_id.map_or(true, |user_id| event.aggregate_id == user_id)
+            && self
+                .event_type
+                .as_ref()
+                .map_or(true, |event_type| &event.event_type == event_type)
+    }
+}
+
+/// An event to write alongside the change it describes
+#[derive(Debug, Clone, Insertable)]
+#[diesel(table_name = events_outbox)]
+pub struct NewOutboxEvent {
+    pub id: Uuid,
+    pub event_type: String,
+    pub aggregate_id: Uuid,
+    pub payload: serde_json::Value,
+}
+
+impl NewOutboxEvent {
+    pub fn new(event_type: EventType, aggregate_id: Uuid, payload: serde_json::Value) -> Self {
+        NewOutboxEvent {
+            id: Uuid::new_v4(),
+            event_type: event_type.as_str().to_string(),
+            aggregate_id,
+            payload,
+        }
+    }
+}
+
+/// What subscribers receive
+#[derive(Debug, Serialize)]
+pub struct EventEnvelope<'a> {
+    pub id: Uuid,
+    #[serde(rename = "type")]
+    pub event_type: &'a str,
+    pub user_id: Uuid,
+    pub data: &'a serde_json::Value,
+    pub occurred_at: DateTime<Utc>,
+}
+
+impl<'a> From<&'a OutboxEvent> for EventEnvelope<'a> {
+    fn from(event: &'a OutboxEvent) -> Self {
+        EventEnvelope {
+            id: event.id,
+            event_type: &event.event_type,
+            user_id: event.aggregate_id,
+            data: &event.payload,
+            occurred_at: event.created_at,
+        }
+    }
+}
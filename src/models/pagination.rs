@@ -0,0 +1,74 @@
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+pub const DEFAULT_PAGE_SIZE: i64 = 20;
+pub const MAX_PAGE_SIZE: i64 = 100;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[cfg_attr(feature = "client", derive(Serialize))]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    Asc,
+    #[default]
+    Desc,
+}
+
+/// `?limit=&offset=&order=` shared by every listing endpoint
+#[derive(Debug, Clone, Validate, Deserialize)]
+#[cfg_attr(feature = "client", derive(Serialize))]
+pub struct PageRequest {
+    #[serde(default = "default_limit")]
+    #[validate(range(min = 1, max = 100))]
+    pub limit: i64,
+
+    #[serde(default)]
+    #[validate(range(min = 0))]
+    pub offset: i64,
+
+    #[serde(default)]
+    pub order: SortOrder,
+}
+
+fn default_limit() -> i64 {
+    DEFAULT_PAGE_SIZE
+}
+
+impl Default for PageRequest {
+    fn default() -> Self {
+        PageRequest {
+            limit: DEFAULT_PAGE_SIZE,
+            offset: 0,
+            order: SortOrder::default(),
+        }
+    }
+}
+
+/// One page of a listing, with the total number of matching items
+#[derive(Debug, Serialize)]
+#[cfg_attr(feature = "client", derive(Deserialize))]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub total: i64,
+    pub limit: i64,
+    pub offset: i64,
+}
+
+impl<T> Page<T> {
+    pub fn new(items: Vec<T>, total: i64, request: &PageRequest) -> Self {
+        Page {
+            items,
+            total,
+            limit: request.limit,
+            offset: request.offset,
+        }
+    }
+
+    pub fn map<U>(self, f: impl FnMut(T) -> U) -> Page<U> {
+        Page {
+            items: self.items.into_iter().map(f).collect(),
+            total: self.total,
+            limit: self.limit,
+            offset: self.offset,
+        }
+    }
+}
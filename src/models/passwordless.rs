@@ -1,18 +1,24 @@
+use chrono::{DateTime, Utc};
+use diesel::prelude::*;
 use serde::{Deserialize, Serialize};
+use uuid::Uuid;
 use validator::Validate;
+use crate::schema::{passkey_prompts, webauthn_credentials};
 use crate::webauthn_simplified::{WebAuthnCredentialResponse, WebAuthnOptions};
 
 /// Request to initiate passwordless registration
 #[derive(Debug, Serialize, Deserialize, Validate)]
+#[serde(deny_unknown_fields)]
 pub struct PasswordlessRegisterStartRequest {
-    #[validate(length(min = 1, message = "Username cannot be empty"))]
+    #[validate(length(min = 1, max = 50, message = "Username must be 1 to 50 characters"))]
     pub username: String,
     
-    #[validate(length(min = 1, message = "Email cannot be empty"))]
+    #[validate(length(min = 1, max = 254, message = "Email must be 1 to 254 characters"))]
     #[validate(email(message = "Email must be valid"))]
     pub email: String,
     
     /// Optional device name for better identification
+    #[validate(length(max = 50, message = "Device name is too long"))]
     pub device_name: Option<String>,
 }
 
@@ -24,16 +30,19 @@ pub struct PasswordlessRegisterStartResponse {
 }
 
 /// Request to complete passwordless registration
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Validate)]
+#[serde(deny_unknown_fields)]
 pub struct PasswordlessRegisterCompleteRequest {
+    #[validate(length(max = 128))]
     pub registration_id: String,
     pub credential: WebAuthnCredentialResponse,
 }
 
 /// Request to initiate passwordless login
 #[derive(Debug, Serialize, Deserialize, Validate)]
+#[serde(deny_unknown_fields)]
 pub struct PasswordlessLoginStartRequest {
-    #[validate(length(min = 1, message = "Username or email cannot be empty"))]
+    #[validate(length(min = 1, max = 254, message = "Username or email must be 1 to 254 characters"))]
     pub username_or_email: String,
 }
 
@@ -45,8 +54,74 @@ pub struct PasswordlessLoginStartResponse {
 }
 
 /// Request to complete passwordless login
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Validate)]
+#[serde(deny_unknown_fields)]
 pub struct PasswordlessLoginCompleteRequest {
+    #[validate(length(max = 128))]
     pub authentication_id: String,
     pub credential: WebAuthnCredentialResponse,
-}
\ No newline at end of file
+}
+
+/// Request to add a passkey to the signed-in user's account
+#[derive(Debug, Serialize, Deserialize, Validate)]
+#[serde(deny_unknown_fields)]
+pub struct PasskeyEnrollStartRequest {
+    #[validate(length(max = 50, message = "Device name is too long"))]
+    pub device_name: Option<String>,
+}
+
+/// How often a user has been asked to enroll a passkey
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Identifiable)]
+#[diesel(table_name = passkey_prompts, primary_key(user_id))]
+pub struct PasskeyPromptState {
+    pub user_id: Uuid,
+    pub prompt_count: i32,
+    pub last_prompted_at: Option<DateTime<Utc>>,
+    pub dismissed_at: Option<DateTime<Utc>>,
+}
+
+/// A stored WebAuthn credential
+#[derive(Debug, Clone, Queryable, Identifiable)]
+#[diesel(table_name = webauthn_credentials)]
+pub struct Passkey {
+    pub id: i32,
+    pub user_id: Uuid,
+    pub credential_id: String,
+    pub public_key: String,
+    pub counter: i32,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub device_name: Option<String>,
+    pub aaguid: Option<Uuid>,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = webauthn_credentials)]
+pub struct NewPasskey {
+    pub user_id: Uuid,
+    pub credential_id: String,
+    pub public_key: String,
+    pub counter: i32,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub device_name: Option<String>,
+    pub aaguid: Option<Uuid>,
+}
+
+/// A registered passkey, without its key material
+#[derive(Debug, Serialize)]
+pub struct PasskeySummary {
+    pub credential_id: String,
+    pub device_name: Option<String>,
+    pub authenticator: Option<String>, // Model name from the FIDO metadata, e.g. "YubiKey 5 Series"
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+/// Hint attached to a password login suggesting the user add a passkey
+#[derive(Debug, Serialize)]
+#[cfg_attr(feature = "client", derive(Deserialize))]
+pub struct PasskeyPrompt {
+    pub enroll_endpoint: String,
+    pub dismiss_endpoint: String,
+}
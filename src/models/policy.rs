@@ -0,0 +1,44 @@
+use crate::schema::policy_acceptances;
+use chrono::{DateTime, Utc};
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use validator::Validate;
+
+/// A user's acceptance of one version of the mandatory policy
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Identifiable)]
+#[diesel(table_name = policy_acceptances)]
+pub struct PolicyAcceptance {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub version: String,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    pub accepted_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = policy_acceptances)]
+pub struct NewPolicyAcceptance {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub version: String,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+/// The policy a login is waiting on
+#[derive(Debug, Clone, Serialize)]
+#[cfg_attr(feature = "client", derive(Deserialize))]
+pub struct PolicyNotice {
+    pub version: String,
+    pub url: Option<String>,
+}
+
+#[derive(Debug, Validate, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AcceptPolicyRequest {
+    /// Must match the current version, so a stale page can't accept newer terms
+    #[validate(length(min = 1, max = 64))]
+    pub version: String,
+}
@@ -0,0 +1,52 @@
+use crate::schema::proxy_aliases;
+use chrono::{DateTime, Utc};
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+pub const PROXY_ALIAS_ACTIVE: &str = "active";
+pub const PROXY_ALIAS_DISABLED: &str = "disabled";
+pub const PROXY_ALIAS_DELETED: &str = "deleted"; // Kept so the address is never handed out again
+
+/// A forwarding address that stands in for the user's real email
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Identifiable)]
+#[diesel(table_name = proxy_aliases)]
+pub struct ProxyAlias {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub proxy_address: String,
+    pub label: String,
+    pub status: String, // One of the `PROXY_ALIAS_*` values
+    pub forwarding_enabled: bool,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = proxy_aliases)]
+pub struct NewProxyAlias {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub proxy_address: String,
+    pub label: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ProxyAliasResponse {
+    pub proxy_address: String,
+    pub label: String,
+    pub status: String,
+    pub forwarding_enabled: bool,
+    pub created_at: DateTime<Utc>,
+}
+
+impl From<ProxyAlias> for ProxyAliasResponse {
+    fn from(alias: ProxyAlias) -> Self {
+        ProxyAliasResponse {
+            proxy_address: alias.proxy_address,
+            label: alias.label,
+            status: alias.status,
+            forwarding_enabled: alias.forwarding_enabled,
+            created_at: alias.created_at,
+        }
+    }
+}
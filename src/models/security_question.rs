@@ -0,0 +1,107 @@
+use crate::schema::{security_question_failures, security_questions};
+use crate::utils::secret::{redacted_debug, Secret};
+use chrono::{DateTime, Utc};
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use validator::Validate;
+
+/// A question the user picked for knowledge-based recovery
+#[derive(Clone, Serialize, Deserialize, Queryable, Identifiable)]
+#[diesel(table_name = security_questions)]
+pub struct SecurityQuestion {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub position: i32, // Order the questions are asked in
+    pub question: String,
+    pub answer_hash: String, // Argon2 hash of `normalize_answer(answer)`
+    pub created_at: DateTime<Utc>,
+}
+
+redacted_debug!(SecurityQuestion { id, user_id, position, question, created_at });
+
+#[derive(Insertable)]
+#[diesel(table_name = security_questions)]
+pub struct NewSecurityQuestion {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub position: i32,
+    pub question: String,
+    pub answer_hash: String,
+}
+
+/// Wrong answers since the window started
+#[derive(Debug, Clone, Queryable)]
+#[diesel(table_name = security_question_failures)]
+pub struct SecurityQuestionFailures {
+    pub user_id: Uuid,
+    pub failure_count: i32,
+    pub last_failed_at: DateTime<Utc>,
+}
+
+/// An answer as it's hashed and compared: lower case, with punctuation
+/// dropped and whitespace collapsed, so "New  York." matches "new york"
+pub fn normalize_answer(answer: &str) -> String {
+    answer
+        .to_lowercase()
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c } else { ' ' })
+        .collect::<String>()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[derive(Debug, Deserialize)]
+#[cfg_attr(feature = "client", derive(Serialize))]
+#[serde(deny_unknown_fields)]
+pub struct SecurityQuestionEntry {
+    pub question: String,
+    pub answer: Secret<String>,
+}
+
+/// Replaces the user's questions and answers
+#[derive(Debug, Validate, Deserialize)]
+#[cfg_attr(feature = "client", derive(Serialize))]
+#[serde(deny_unknown_fields)]
+pub struct SecurityQuestionsRequest {
+    #[validate(length(max = 10))]
+    pub questions: Vec<SecurityQuestionEntry>,
+}
+
+/// The questions, in the order they're asked; answers are never returned
+#[derive(Debug, Serialize)]
+#[cfg_attr(feature = "client", derive(Deserialize))]
+pub struct SecurityQuestionsResponse {
+    pub questions: Vec<String>,
+}
+
+impl From<Vec<SecurityQuestion>> for SecurityQuestionsResponse {
+    fn from(questions: Vec<SecurityQuestion>) -> Self {
+        SecurityQuestionsResponse {
+            questions: questions.into_iter().map(|q| q.question).collect(),
+        }
+    }
+}
+
+/// Answers to every question, in the order they were asked
+#[derive(Debug, Validate, Deserialize)]
+#[cfg_attr(feature = "client", derive(Serialize))]
+#[serde(deny_unknown_fields)]
+pub struct SecurityQuestionRecoveryRequest {
+    #[validate(length(max = 10))]
+    pub answers: Vec<Secret<String>>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_answers_are_normalized() {
+        assert_eq!(normalize_answer("  New  York. "), "new york");
+        assert_eq!(normalize_answer("O'Brien-Smith"), "o brien smith");
+        assert_eq!(normalize_answer("MÜNCHEN"), "münchen");
+        assert_eq!(normalize_answer("?!"), "");
+    }
+}
@@ -1,10 +1,14 @@
-use crate::schema::sessions;
+use crate::schema::{sessions, token_revocations};
 use chrono::{DateTime, Utc};
 use diesel::prelude::*;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-#[derive(Debug, Serialize, Deserialize, Queryable, Identifiable)]
+use crate::utils::jwt::assurance_level;
+use crate::utils::secret::{redacted_debug, Secret};
+use crate::utils::user_agent::DeviceInfo;
+
+#[derive(Serialize, Deserialize, Queryable, Identifiable)]
 #[diesel(table_name = sessions)]
 pub struct Session {
     pub id: Uuid,
@@ -16,9 +20,30 @@ pub struct Session {
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
     pub is_revoked: bool,
+    pub browser: Option<String>,
+    pub os: Option<String>,
+    pub device_class: Option<String>,
+    pub name: Option<String>,
+    pub is_pinned: bool,
+    pub dpop_jkt: Option<String>, // Set when the session's tokens are bound to a client key
+    pub last_seen_at: DateTime<Utc>, // Last refresh or authenticated request, updated at most every few minutes
+    pub network_country: Option<String>, // Network the refresh token is bound to, when binding is on
+    pub network_asn: Option<String>,
+    pub client_id: Option<String>, // Registered client the session was started for, whose policy it keeps to
+    pub scopes: Option<Vec<String>>, // What the client was granted; the user's own scopes when unset
+    pub amr: Vec<String>, // Authentication methods the session was started with, kept across refreshes
+    pub lifetime: String, // `SessionLifetime::as_str`, what its tokens' lifetimes were bounded by
 }
 
-#[derive(Debug, Insertable)]
+impl Session {
+    pub fn lifetime(&self) -> SessionLifetime {
+        self.lifetime.parse().unwrap_or_default()
+    }
+}
+
+redacted_debug!(Session { id, user_id, expires_at, last_seen_at, is_revoked, device_class });
+
+#[derive(Insertable)]
 #[diesel(table_name = sessions)]
 pub struct NewSession {
     pub id: Uuid,
@@ -27,14 +52,180 @@ pub struct NewSession {
     pub user_agent: Option<String>,
     pub ip_address: Option<String>,
     pub expires_at: DateTime<Utc>,
+    pub browser: Option<String>,
+    pub os: Option<String>,
+    pub device_class: Option<String>,
+    pub name: Option<String>,
+    pub is_pinned: bool,
+    pub dpop_jkt: Option<String>, // Set when the session's tokens are bound to a client key
+    pub network_country: Option<String>, // Network the refresh token is bound to, when binding is on
+    pub network_asn: Option<String>,
+    pub client_id: Option<String>,
+    pub scopes: Option<Vec<String>>,
+    pub amr: Vec<String>,
+    pub lifetime: String,
+}
+
+redacted_debug!(NewSession { id, user_id, expires_at, device_class });
+
+impl NewSession {
+    /// A fresh session, with the device described from `user_agent`
+    pub fn new(
+        user_id: Uuid,
+        refresh_token: String,
+        user_agent: Option<String>,
+        ip_address: Option<String>,
+        expires_at: DateTime<Utc>,
+    ) -> Self {
+        let device = user_agent.as_deref().map(DeviceInfo::parse).unwrap_or_default();
+
+        NewSession {
+            id: Uuid::new_v4(),
+            user_id,
+            refresh_token,
+            user_agent,
+            ip_address,
+            expires_at,
+            browser: device.browser,
+            os: device.os,
+            device_class: device.device_class,
+            name: None,
+            is_pinned: false,
+            dpop_jkt: None,
+            network_country: None,
+            network_asn: None,
+            client_id: None,
+            scopes: None,
+            amr: Vec::new(),
+            lifetime: SessionLifetime::Standard.as_str().to_string(),
+        }
+    }
+
+    pub fn lifetime(&self) -> SessionLifetime {
+        self.lifetime.parse().unwrap_or_default()
+    }
+}
+
+/// How long a session's tokens last, as decided by the login pipeline.
+/// Variants are ordered from longest to shortest; the shortest any check asks
+/// for wins.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SessionLifetime {
+    Extended, // Started on a trusted device
+    #[default]
+    Standard,
+    Shortened, // A new device or a moderately risky login
+}
+
+impl SessionLifetime {
+    /// As stored in `sessions.lifetime`
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SessionLifetime::Extended => "extended",
+            SessionLifetime::Standard => "standard",
+            SessionLifetime::Shortened => "shortened",
+        }
+    }
+}
+
+impl std::str::FromStr for SessionLifetime {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "extended" => Ok(SessionLifetime::Extended),
+            "standard" => Ok(SessionLifetime::Standard),
+            "shortened" => Ok(SessionLifetime::Shortened),
+            other => Err(format!("unknown session lifetime: {}", other)),
+        }
+    }
+}
+
+/// Fields that may change on a session once it has started
+#[derive(Debug, Default, AsChangeset)]
+#[diesel(table_name = sessions)]
+pub struct SessionChanges {
+    pub name: Option<Option<String>>,
+    pub is_pinned: Option<bool>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub client_id: Option<Option<String>>,
+    pub scopes: Option<Option<Vec<String>>>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[cfg_attr(feature = "client", derive(Serialize))]
+#[serde(rename_all = "snake_case")]
+pub enum SessionSort {
+    #[default]
+    CreatedAt,
+    ExpiresAt,
+}
+
+/// Row counts of the sessions table, for watching its growth
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SessionTableStats {
+    pub total: i64,
+    pub live: i64,    // Neither revoked nor expired
+    pub revoked: i64,
+    pub expired: i64, // Expired without being revoked
+}
+
+/// Session table growth and what the purge has done about it
+#[derive(Debug, Serialize)]
+pub struct SessionTableMetrics {
+    #[serde(flatten)]
+    pub stats: SessionTableStats,
+    pub purged_total: u64, // By this instance since it started
+    pub last_purge_at: Option<DateTime<Utc>>,
+}
+
+/// Filters for listing a user's (unrevoked) sessions
+#[derive(Debug, Clone, Default, Deserialize)]
+#[cfg_attr(feature = "client", derive(Serialize))]
+pub struct SessionFilter {
+    #[serde(default)]
+    pub sort: SessionSort,
+    pub pinned: Option<bool>,
+    pub device_class: Option<String>,
+}
+
+impl SessionFilter {
+    pub fn matches(&self, session: &Session) -> bool {
+        self.pinned.map_or(true, |pinned| session.is_pinned == pinned)
+            && self
+                .device_class
+                .as_ref()
+                .map_or(true, |class| session.device_class.as_ref() == Some(class))
+    }
 }
 
 #[derive(Debug, Validate, Deserialize)]
+#[cfg_attr(feature = "client", derive(Serialize))]
+pub struct UpdateSessionRequest {
+    /// An empty name clears it
+    #[validate(length(max = 50))]
+    pub name: Option<String>,
+
+    pub pinned: Option<bool>,
+}
+
+#[derive(Debug, Validate, Deserialize)]
+#[cfg_attr(feature = "client", derive(Serialize))]
+#[serde(deny_unknown_fields)]
 pub struct RefreshTokenRequest {
-    pub refresh_token: String,
+    #[validate(length(max = 4096))]
+    pub refresh_token: Secret<String>,
+
+    /// Only needed when refreshing from a network the session isn't bound to
+    #[validate(length(max = 1024))]
+    pub password: Option<Secret<String>>,
+    #[validate(length(max = 16))]
+    pub mfa_code: Option<String>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Serialize)]
+#[cfg_attr(feature = "client", derive(Deserialize))]
 pub struct RefreshTokenResponse {
     pub access_token: String,
     pub refresh_token: String,
@@ -42,35 +233,91 @@ pub struct RefreshTokenResponse {
     pub expires_in: u64,
 }
 
-#[derive(Debug, Deserialize)]
+redacted_debug!(RefreshTokenResponse { token_type, expires_in });
+
+#[derive(Debug, Validate, Deserialize)]
+#[cfg_attr(feature = "client", derive(Serialize))]
+#[serde(deny_unknown_fields)]
 pub struct LogoutRequest {
-    pub refresh_token: Option<String>,
+    #[validate(length(max = 4096))]
+    pub refresh_token: Option<Secret<String>>,
 }
 
 #[derive(Debug, Serialize)]
+#[cfg_attr(feature = "client", derive(Deserialize))]
 pub struct LogoutResponse {
     pub message: String,
 }
 
 #[derive(Debug, Serialize)]
+#[cfg_attr(feature = "client", derive(Deserialize))]
 pub struct SessionResponse {
     pub id: Uuid,
+    pub name: Option<String>,
+    pub is_pinned: bool,
+    pub device: String, // e.g. "Chrome on Windows 10"
+    pub browser: Option<String>,
+    pub os: Option<String>,
+    pub device_class: Option<String>,
     pub user_agent: Option<String>,
     pub ip_address: Option<String>,
     pub created_at: DateTime<Utc>,
     pub expires_at: DateTime<Utc>,
+    pub last_seen_at: DateTime<Utc>,
+    pub amr: Vec<String>, // How the session was signed in, e.g. ["pwd", "otp", "mfa"]
+    pub aal: Option<u8>,  // Assurance level of its tokens; `None` for sessions started before this was recorded
+    pub lifetime: SessionLifetime, // Whether its tokens were shortened for a risky login or extended for a trusted device
     pub is_current: bool,
 }
 
 impl From<Session> for SessionResponse {
     fn from(session: Session) -> Self {
+        let device = DeviceInfo {
+            browser: session.browser.clone(),
+            os: session.os.clone(),
+            device_class: session.device_class.clone(),
+        };
+
         SessionResponse {
             id: session.id,
+            name: session.name,
+            is_pinned: session.is_pinned,
+            device: device.describe(None),
+            browser: session.browser,
+            os: session.os,
+            device_class: session.device_class,
             user_agent: session.user_agent,
             ip_address: session.ip_address,
             created_at: session.created_at,
             expires_at: session.expires_at,
+            last_seen_at: session.last_seen_at,
+            aal: assurance_level(&session.amr),
+            lifetime: session.lifetime(),
+            amr: session.amr,
             is_current: false, // This will be set by the service
         }
     }
 }
+
+/// A session, or a single access token by its `jti`, whose access tokens are
+/// rejected before they expire
+#[derive(Debug, Clone, Queryable)]
+pub struct TokenRevocation {
+    pub id: Uuid,                  // Session id or access token `jti`
+    pub expires_at: DateTime<Utc>, // When the last token it could match expires
+    pub revoked_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = token_revocations)]
+pub struct NewTokenRevocation {
+    pub id: Uuid,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// The kind of token an OAuth client says it is revoking (RFC 7009 `token_type_hint`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenTypeHint {
+    AccessToken,
+    RefreshToken,
+}
@@ -0,0 +1,267 @@
+use std::collections::HashMap;
+
+use crate::models::organization::OrganizationRole;
+use crate::schema::{sso_connections, sso_identities};
+use crate::utils::secret::{redacted_debug, Secret};
+use chrono::{DateTime, Utc};
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use validator::Validate;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SsoProtocol {
+    Oidc,
+    Saml,
+}
+
+impl SsoProtocol {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SsoProtocol::Oidc => "oidc",
+            SsoProtocol::Saml => "saml",
+        }
+    }
+}
+
+impl std::str::FromStr for SsoProtocol {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "oidc" => Ok(SsoProtocol::Oidc),
+            "saml" => Ok(SsoProtocol::Saml),
+            other => Err(format!("unknown SSO protocol: {}", other)),
+        }
+    }
+}
+
+/// An organization's identity provider
+#[derive(Clone, Serialize, Deserialize, Queryable, Identifiable)]
+#[diesel(table_name = sso_connections)]
+pub struct SsoConnection {
+    pub id: Uuid,
+    pub organization_id: Uuid,
+    pub protocol: String,
+    pub enabled: bool,
+    pub oidc_issuer: Option<String>,
+    pub oidc_client_id: Option<String>,
+    #[serde(skip_serializing)]
+    pub oidc_client_secret: Option<String>,
+    pub saml_idp_metadata: Option<String>, // The IdP's metadata XML
+    pub groups_attribute: String,          // Claim or attribute listing the user's groups
+    pub role_mappings: serde_json::Value,  // IdP group -> organization role
+    pub default_role: String,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub provisioning_rules: serde_json::Value, // See `ProvisioningRules`
+}
+
+redacted_debug!(SsoConnection { id, organization_id, protocol, enabled, oidc_issuer, oidc_client_id });
+
+impl SsoConnection {
+    pub fn protocol(&self) -> SsoProtocol {
+        // The column is constrained to known protocols
+        self.protocol.parse().unwrap_or(SsoProtocol::Oidc)
+    }
+
+    /// The organization role for a user in `groups`: the highest mapped role,
+    /// or the default when none of their groups is mapped
+    pub fn role_for_groups(&self, groups: &[String]) -> OrganizationRole {
+        let mappings: HashMap<String, OrganizationRole> =
+            serde_json::from_value(self.role_mappings.clone()).unwrap_or_default();
+        let default_role = self.default_role.parse().unwrap_or(OrganizationRole::Member);
+
+        groups
+            .iter()
+            .filter_map(|group| mappings.get(group).copied())
+            .max()
+            .unwrap_or(default_role)
+    }
+
+    pub fn provisioning_rules(&self) -> ProvisioningRules {
+        serde_json::from_value(self.provisioning_rules.clone()).unwrap_or_default()
+    }
+}
+
+#[derive(Insertable, AsChangeset)]
+#[diesel(table_name = sso_connections)]
+pub struct NewSsoConnection {
+    pub id: Uuid,
+    pub organization_id: Uuid,
+    pub protocol: String,
+    pub enabled: bool,
+    pub oidc_issuer: Option<String>,
+    pub oidc_client_id: Option<String>,
+    pub oidc_client_secret: Option<String>,
+    pub saml_idp_metadata: Option<String>,
+    pub groups_attribute: String,
+    pub role_mappings: serde_json::Value,
+    pub default_role: String,
+    pub provisioning_rules: serde_json::Value,
+}
+
+redacted_debug!(NewSsoConnection { id, organization_id, protocol, enabled, oidc_issuer, oidc_client_id });
+
+/// How users signing in through a connection are provisioned. Attribute names
+/// are OIDC claims or SAML attribute names.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProvisioningRules {
+    pub attribute_mapping: AttributeMapping,
+    /// Sign-in is refused when any of these match
+    pub blocked: Vec<AttributeCondition>,
+    /// Every matching rule applies, in order
+    pub rules: Vec<ProvisioningRule>,
+}
+
+/// Where a new user's profile comes from; unmapped fields fall back to the
+/// standard `name` claim and the email address
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AttributeMapping {
+    pub username: Option<String>,
+    pub display_name: Option<String>,
+    pub locale: Option<String>,
+    pub timezone: Option<String>,
+}
+
+/// Matches when the attribute has any of `values` (case-insensitively), or
+/// when it is present at all if `values` is empty
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AttributeCondition {
+    pub attribute: String,
+    #[serde(default)]
+    pub values: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProvisioningRule {
+    /// All must match; a rule without conditions matches everyone
+    pub when: Vec<AttributeCondition>,
+    /// Role in the connection's organization, if higher than the group mapping gives
+    pub role: Option<OrganizationRole>,
+    /// Further organizations joined on first sign-in
+    pub organizations: Vec<OrganizationAssignment>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OrganizationAssignment {
+    pub organization_id: Uuid,
+    #[serde(default = "default_sso_role")]
+    pub role: OrganizationRole,
+}
+
+/// Links an IdP subject to the local user it was provisioned as
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Identifiable)]
+#[diesel(table_name = sso_identities)]
+pub struct SsoIdentity {
+    pub id: Uuid,
+    pub connection_id: Uuid,
+    pub user_id: Uuid,
+    pub subject: String,
+    pub created_at: DateTime<Utc>,
+    pub last_login_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = sso_identities)]
+pub struct NewSsoIdentity {
+    pub id: Uuid,
+    pub connection_id: Uuid,
+    pub user_id: Uuid,
+    pub subject: String,
+}
+
+#[derive(Debug, Validate, Deserialize)]
+pub struct SsoConnectionRequest {
+    pub protocol: SsoProtocol,
+
+    /// Once enabled, users at the organization's verified domains can only sign in through the IdP
+    #[serde(default)]
+    pub enabled: bool,
+
+    #[validate(length(max = 2048))]
+    pub oidc_issuer: Option<String>,
+    #[validate(length(max = 255))]
+    pub oidc_client_id: Option<String>,
+    /// Left unchanged when omitted on update
+    #[validate(length(max = 1024))]
+    pub oidc_client_secret: Option<Secret<String>>,
+
+    #[validate(length(max = 100000))]
+    pub saml_idp_metadata: Option<String>,
+
+    #[validate(length(min = 1, max = 100))]
+    pub groups_attribute: Option<String>,
+
+    #[serde(default)]
+    #[validate(length(max = 100))]
+    pub role_mappings: HashMap<String, OrganizationRole>,
+
+    #[serde(default = "default_sso_role")]
+    pub default_role: OrganizationRole,
+
+    #[serde(default)]
+    pub provisioning_rules: ProvisioningRules,
+}
+
+fn default_sso_role() -> OrganizationRole {
+    OrganizationRole::Member
+}
+
+/// A connection as shown to organization admins, along with the values their
+/// IdP needs to be configured with
+#[derive(Debug, Serialize)]
+pub struct SsoConnectionResponse {
+    pub id: Uuid,
+    pub organization_id: Uuid,
+    pub protocol: SsoProtocol,
+    pub enabled: bool,
+    pub domains: Vec<String>, // Verified domains routed to this connection
+    pub oidc_issuer: Option<String>,
+    pub oidc_client_id: Option<String>,
+    pub oidc_redirect_url: String,
+    pub saml_entity_id: String,
+    pub saml_acs_url: String,
+    pub groups_attribute: String,
+    pub role_mappings: serde_json::Value,
+    pub default_role: String,
+    pub provisioning_rules: serde_json::Value,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Validate, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SsoDiscoverRequest {
+    #[validate(email, length(max = 254))]
+    pub email: String,
+}
+
+/// Whether an email address signs in through an organization's IdP, and if so where to go
+#[derive(Debug, Serialize)]
+pub struct SsoDiscoverResponse {
+    pub sso: bool,
+    pub organization: Option<String>,
+    pub redirect_url: Option<String>,
+}
+
+/// Query string of the OIDC redirect back from the IdP
+#[derive(Debug, Deserialize)]
+pub struct OidcCallbackQuery {
+    pub code: Option<Secret<String>>,
+    pub state: String,
+    pub error: Option<String>,
+    pub error_description: Option<String>,
+}
+
+/// Form POSTed by the IdP to the SAML assertion consumer service
+#[derive(Debug, Deserialize)]
+pub struct SamlAcsForm {
+    #[serde(rename = "SAMLResponse")]
+    pub saml_response: String,
+    #[serde(rename = "RelayState")]
+    pub relay_state: String,
+}
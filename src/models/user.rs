@@ -1,11 +1,22 @@
+use crate::accessibility::CaptchaAlternative;
+use crate::models::account_status::AccountStatus;
+use crate::models::mfa::{RecoveryCodeStatus, TotpDeviceResponse};
+use crate::models::outbox::OutboxEvent;
+use crate::models::pagination::Page;
+use crate::models::passwordless::{PasskeyPrompt, PasskeySummary};
+use crate::models::policy::PolicyNotice;
+use crate::models::proxy_alias::ProxyAliasResponse;
+use crate::models::session::SessionResponse;
 use crate::schema::users;
+use crate::utils::secret::{redacted_debug, Secret};
+use crate::utils::user_agent::DeviceInfo;
 use chrono::{DateTime, Utc};
 use diesel::prelude::*;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 use validator::Validate;
 
-#[derive(Debug, Serialize, Deserialize, Queryable, Identifiable)]
+#[derive(Clone, Serialize, Deserialize, Queryable, Identifiable)]
 #[diesel(table_name = users)]
 pub struct User {
     pub id: Uuid,
@@ -22,11 +33,72 @@ pub struct User {
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
     pub last_login_at: Option<DateTime<Utc>>,
-    pub is_active: bool,
     pub is_admin: bool,
+    pub token_version: i32,
+    pub display_name: Option<String>,
+    pub avatar_url: Option<String>,
+    pub locale: Option<String>,
+    pub timezone: Option<String>,
+    pub metadata: serde_json::Value,
+    pub password_reset_email: Option<String>, // Address the pending reset link went to
+    pub password_expires_at: Option<DateTime<Utc>>,
+    pub status: String, // See `AccountStatus`
+    pub status_reason: Option<String>,
+    pub status_changed_by: Option<Uuid>,
+    pub status_changed_at: Option<DateTime<Utc>>,
+    pub is_guest: bool, // Anonymous until upgraded to a registered account
+    pub mfa_reenrollment_required: bool, // MFA was reset by the account risk score
+    pub failed_login_count: i32, // Failed password logins since `last_failed_login_at`'s window began
+    pub last_failed_login_at: Option<DateTime<Utc>>,
+    pub locked_until: Option<DateTime<Utc>>, // Password logins are refused until then
+    pub activation_pending: bool, // Created without a password; the owner sets one from the activation link
+    pub version: i32, // Bumped by profile, MFA, email and status changes; sent back as the `ETag`
+    pub email_display: Option<String>, // `email` as the user entered it, when that isn't the canonical form
+    pub archived_at: Option<DateTime<Utc>>, // Archived for inactivity; reactivated from an emailed link
 }
 
-#[derive(Debug, Insertable, AsChangeset)]
+redacted_debug!(User {
+    id,
+    username,
+    email,
+    is_email_verified,
+    mfa_enabled,
+    is_admin,
+    status,
+    is_guest,
+    mfa_reenrollment_required,
+    failed_login_count,
+    locked_until,
+    activation_pending,
+    version,
+    archived_at,
+});
+
+impl User {
+    pub fn password_expired(&self) -> bool {
+        self.password_expires_at.map_or(false, |at| at <= Utc::now())
+    }
+
+    // An unrecognized status locks the account rather than opening it
+    pub fn account_status(&self) -> AccountStatus {
+        self.status.parse().unwrap_or(AccountStatus::Suspended)
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.account_status() == AccountStatus::Active
+    }
+
+    pub fn is_archived(&self) -> bool {
+        self.archived_at.is_some()
+    }
+
+    /// The address to show the user; `email` is the one to compare and send to
+    pub fn display_email(&self) -> &str {
+        self.email_display.as_deref().unwrap_or(&self.email)
+    }
+}
+
+#[derive(Insertable, AsChangeset)]
 #[diesel(table_name = users)]
 pub struct NewUser {
     pub id: Uuid,
@@ -36,73 +108,357 @@ pub struct NewUser {
     pub is_email_verified: bool,
     pub email_verification_token: Option<String>,
     pub email_verification_sent_at: Option<DateTime<Utc>>,
-    pub is_active: bool,
     pub is_admin: bool,
+    pub password_expires_at: Option<DateTime<Utc>>,
+    pub is_guest: bool,
+    pub activation_pending: bool,
+    pub email_display: Option<String>,
+}
+
+redacted_debug!(NewUser { id, username, email, is_email_verified, is_admin, is_guest, activation_pending });
+
+/// Turns a guest into a registered account, keeping its id and everything linked to it
+#[derive(AsChangeset)]
+#[diesel(table_name = users)]
+pub struct GuestUpgrade {
+    pub username: String,
+    pub email: String,
+    pub email_display: Option<String>,
+    pub password_hash: String,
+    pub email_verification_sent_at: Option<DateTime<Utc>>,
+    pub password_expires_at: Option<DateTime<Utc>>,
+}
+
+redacted_debug!(GuestUpgrade { username, email });
+
+/// Profile fields a user may change on themselves
+#[derive(Debug, Default, AsChangeset)]
+#[diesel(table_name = users)]
+pub struct ProfileChanges {
+    pub display_name: Option<Option<String>>,
+    pub avatar_url: Option<Option<String>>,
+    pub locale: Option<Option<String>>,
+    pub timezone: Option<Option<String>>,
+    pub metadata: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Validate, Deserialize)]
+pub struct UpdateProfileRequest {
+    /// For each text field, an empty string clears it
+    #[validate(length(max = 100))]
+    pub display_name: Option<String>,
+
+    #[validate(length(max = 2048))]
+    pub avatar_url: Option<String>,
+
+    #[validate(length(max = 35))]
+    pub locale: Option<String>, // BCP 47, e.g. "pt-BR"
+
+    #[validate(length(max = 64))]
+    pub timezone: Option<String>, // IANA, e.g. "Europe/Berlin"
+
+    /// Replaces the stored object; `{}` clears it
+    pub metadata: Option<serde_json::Value>,
 }
 
 #[derive(Debug, Validate, Deserialize)]
+#[cfg_attr(feature = "client", derive(Serialize))]
+#[serde(deny_unknown_fields)]
 pub struct RegisterRequest {
     #[validate(length(min = 3, max = 50))]
     pub username: String,
 
-    #[validate(email)]
+    #[validate(email, length(max = 254))]
+    pub email: String,
+
+    #[validate(length(min = 8, max = 1024))]
+    pub password: Secret<String>,
+
+    #[validate(must_match = "password")]
+    pub password_confirmation: Secret<String>,
+
+    pub captcha_id: Option<Uuid>,
+    #[validate(length(max = 64))]
+    pub captcha_answer: Option<String>,
+
+    /// Where the account's data is kept, one of the deployment's regions; the
+    /// home region when left out
+    #[validate(length(max = 32))]
+    pub region: Option<String>,
+}
+
+/// Sign up with an address alone; the password is set from the activation link
+#[derive(Debug, Validate, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EmailRegisterRequest {
+    /// Derived from the address when left out
+    #[validate(length(min = 3, max = 50))]
+    pub username: Option<String>,
+
+    #[validate(email, length(max = 254))]
+    pub email: String,
+
+    pub captcha_id: Option<Uuid>,
+    #[validate(length(max = 64))]
+    pub captcha_answer: Option<String>,
+}
+
+/// An account an admin creates for someone else, who activates it by email
+#[derive(Debug, Validate, Deserialize)]
+pub struct InviteUserRequest {
+    /// Derived from the address when left out
+    #[validate(length(min = 3, max = 50))]
+    pub username: Option<String>,
+
+    #[validate(email, length(max = 254))]
+    pub email: String,
+}
+
+#[derive(Debug, Validate, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GuestRequest {
+    pub captcha_id: Option<Uuid>,
+    #[validate(length(max = 64))]
+    pub captcha_answer: Option<String>,
+}
+
+/// Registration details for a guest keeping its account
+#[derive(Debug, Validate, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UpgradeGuestRequest {
+    #[validate(length(min = 3, max = 50))]
+    pub username: String,
+
+    #[validate(email, length(max = 254))]
     pub email: String,
 
-    #[validate(length(min = 8))]
-    pub password: String,
+    #[validate(length(min = 8, max = 1024))]
+    pub password: Secret<String>,
 
     #[validate(must_match = "password")]
-    pub password_confirmation: String,
+    pub password_confirmation: Secret<String>,
 }
 
 #[derive(Debug, Validate, Deserialize)]
+#[cfg_attr(feature = "client", derive(Serialize))]
+#[serde(deny_unknown_fields)]
 pub struct LoginRequest {
-    #[validate(length(min = 1))]
+    #[validate(length(min = 1, max = 254))]
     pub username_or_email: String,
 
-    #[validate(length(min = 1))]
-    pub password: String,
+    #[validate(length(min = 1, max = 1024))]
+    pub password: Secret<String>,
+
+    /// Set by clients that can create passkeys, to receive enrollment prompts
+    #[serde(default)]
+    pub webauthn_supported: bool,
+
+    /// Token of a device trusted at an email code login, for longer-lived tokens
+    #[validate(length(max = 256))]
+    pub device_token: Option<Secret<String>>,
+
+    pub captcha_id: Option<Uuid>,
+    #[validate(length(max = 64))]
+    pub captcha_answer: Option<String>,
+}
+
+/// Answer to a challenge from `POST /auth/captcha`, needed when `CAPTCHA_REQUIRED` is set
+#[derive(Debug, Default, Deserialize)]
+pub struct CaptchaSolution {
+    pub captcha_id: Option<Uuid>,
+    pub captcha_answer: Option<String>,
+}
+
+// Requests answering a CAPTCHA carry its fields inline. They're spelled out on
+// each rather than flattened, which `deny_unknown_fields` doesn't support.
+macro_rules! captcha_solution {
+    ($($ty:ty),* $(,)?) => {
+        $(
+            impl $ty {
+                pub fn captcha(&self) -> CaptchaSolution {
+                    CaptchaSolution {
+                        captcha_id: self.captcha_id,
+                        captcha_answer: self.captcha_answer.clone(),
+                    }
+                }
+            }
+        )*
+    };
 }
 
+captcha_solution!(
+    RegisterRequest,
+    EmailRegisterRequest,
+    GuestRequest,
+    LoginRequest,
+    crate::models::mfa::MfaLoginRequest,
+);
+
 #[derive(Debug, Validate, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CaptchaChallengeRequest {
+    /// Picks the challenge from this account's accessibility preferences
+    #[validate(length(min = 1, max = 254))]
+    pub username_or_email: Option<String>,
+
+    /// Only `Audio`, the alternative offered with every written challenge,
+    /// e.g. for a screen reader user who is still registering
+    pub kind: Option<CaptchaAlternative>,
+}
+
+#[derive(Debug, Validate, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ApproveLoginRequest {
+    #[validate(length(min = 1, max = 4096))]
+    pub token: Secret<String>,
+}
+
+#[derive(Debug, Validate, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct PasswordResetRequest {
-    #[validate(email)]
+    #[validate(email, length(max = 254))]
     pub email: String,
 }
 
 #[derive(Debug, Validate, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct PasswordResetConfirmRequest {
-    pub token: String,
+    #[validate(length(min = 1, max = 4096))]
+    pub token: Secret<String>,
+
+    #[validate(length(min = 8, max = 1024))]
+    pub password: Secret<String>,
+
+    #[validate(must_match = "password")]
+    pub password_confirmation: Secret<String>,
+
+    /// An authenticator or recovery code; unlocking a locked account with
+    /// MFA on takes one
+    #[serde(default)]
+    #[validate(length(max = 64))]
+    pub mfa_code: Option<Secret<String>>,
+}
+
+/// A reactivation link, from the email sent when an archived account signed in
+#[derive(Debug, Validate, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ReactivateAccountRequest {
+    #[validate(length(min = 1, max = 4096))]
+    pub token: Secret<String>,
+}
+
+/// Asks for a link that locks the account, for owners who can no longer sign in
+#[derive(Debug, Validate, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AccountLockRequest {
+    #[validate(email, length(max = 254))]
+    pub email: String,
+}
+
+/// A link from the email `AccountLockRequest` sent
+#[derive(Debug, Validate, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LockAccountRequest {
+    #[validate(length(min = 1, max = 4096))]
+    pub token: Secret<String>,
+}
+
+/// The first password of an invited or email-only account
+#[derive(Debug, Validate, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ActivateAccountRequest {
+    #[validate(length(min = 1, max = 4096))]
+    pub token: Secret<String>,
 
-    #[validate(length(min = 8))]
-    pub password: String,
+    #[validate(length(min = 8, max = 1024))]
+    pub password: Secret<String>,
 
     #[validate(must_match = "password")]
-    pub password_confirmation: String,
+    pub password_confirmation: Secret<String>,
 }
 
 #[derive(Debug, Validate, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ChangePasswordRequest {
+    #[validate(length(min = 1, max = 1024))]
+    pub current_password: Secret<String>,
+
+    #[validate(length(min = 8, max = 1024))]
+    pub password: Secret<String>,
+
+    #[validate(must_match = "password")]
+    pub password_confirmation: Secret<String>,
+}
+
+/// Expire passwords for the listed users, or for everyone with `all`
+#[derive(Debug, Validate, Deserialize)]
+pub struct ForcePasswordResetRequest {
+    #[serde(default)]
+    #[validate(length(max = 1000))]
+    pub user_ids: Vec<Uuid>,
+
+    #[serde(default)]
+    pub all: bool,
+
+    /// Also sign the affected users out everywhere
+    #[serde(default = "default_true")]
+    pub revoke_sessions: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+#[derive(Debug, Serialize)]
+pub struct ForcePasswordResetResponse {
+    pub affected: usize,
+}
+
+#[derive(Debug, Validate, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ReauthenticateRequest {
+    #[validate(length(min = 1, max = 1024))]
+    pub password: Secret<String>,
+
+    #[validate(length(max = 16))]
+    pub mfa_code: Option<String>,
+}
+
+#[derive(Debug, Validate, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct VerifyEmailRequest {
-    pub token: String,
+    #[validate(length(min = 1, max = 4096))]
+    pub token: Secret<String>,
 }
 
 #[derive(Debug, Validate, Deserialize)]
+#[cfg_attr(feature = "client", derive(Serialize))]
+#[serde(deny_unknown_fields)]
 pub struct EnableMfaRequest {
+    #[validate(length(max = 16))]
     pub mfa_code: String,
 }
 
 #[derive(Debug, Validate, Deserialize)]
+#[cfg_attr(feature = "client", derive(Serialize))]
+#[serde(deny_unknown_fields)]
 pub struct VerifyMfaRequest {
+    #[validate(length(max = 16))]
     pub mfa_code: String,
 }
 
 #[derive(Debug, Validate, Deserialize)]
+#[cfg_attr(feature = "client", derive(Serialize))]
+#[serde(deny_unknown_fields)]
 pub struct DisableMfaRequest {
+    #[validate(length(max = 16))]
     pub mfa_code: String,
-    pub password: String,
+    #[validate(length(max = 1024))]
+    pub password: Secret<String>,
 }
 
 #[derive(Debug, Serialize)]
+#[cfg_attr(feature = "client", derive(Deserialize))]
 pub struct UserResponse {
     pub id: Uuid,
     pub username: String,
@@ -113,16 +469,61 @@ pub struct UserResponse {
     pub updated_at: DateTime<Utc>,
     pub last_login_at: Option<DateTime<Utc>>,
     pub is_active: bool,
+    pub status: AccountStatus,
     pub is_admin: bool,
+    pub is_guest: bool,
+    pub activation_pending: bool,
+    pub version: i32,
+    pub display_name: Option<String>,
+    pub avatar_url: Option<String>,
+    pub locale: Option<String>,
+    pub timezone: Option<String>,
+    pub metadata: serde_json::Value,
+    pub password_expires_at: Option<DateTime<Utc>>,
+    pub archived_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UserSort {
+    #[default]
+    CreatedAt,
+    Username,
+}
+
+/// Filters for the admin user list, on top of the search term
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct UserFilter {
+    #[serde(default)]
+    pub sort: UserSort,
+    pub status: Option<AccountStatus>,
+    pub is_admin: Option<bool>,
+    pub is_guest: Option<bool>,
+    pub mfa_enabled: Option<bool>,
+    #[serde(default)]
+    pub archived: bool, // Archived accounts instead of live ones
+}
+
+impl UserFilter {
+    pub fn matches(&self, user: &User) -> bool {
+        self.status.map_or(true, |status| user.status == status.as_str())
+            && self.is_admin.map_or(true, |is_admin| user.is_admin == is_admin)
+            && self.is_guest.map_or(true, |is_guest| user.is_guest == is_guest)
+            && self.mfa_enabled.map_or(true, |mfa_enabled| user.mfa_enabled == mfa_enabled)
+            && user.is_archived() == self.archived
+    }
 }
 
 #[derive(Debug, Serialize)]
+#[cfg_attr(feature = "client", derive(Deserialize))]
 pub struct RegisterResponse {
-    pub user: UserResponse,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user: Option<UserResponse>, // Left out when `REGISTRATION_GENERIC_RESPONSE` is on
     pub message: String,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Serialize)]
+#[cfg_attr(feature = "client", derive(Deserialize))]
 pub struct LoginResponse {
     pub access_token: String,
     pub refresh_token: String,
@@ -130,14 +531,52 @@ pub struct LoginResponse {
     pub expires_in: u64,
     pub user: UserResponse,
     pub mfa_required: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub passkey_prompt: Option<PasskeyPrompt>,
+    /// Set when the login is held until the owner approves it by email
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub approval_id: Option<Uuid>,
+    /// The password has expired; `access_token` is only good for `/auth/change-password`
+    pub password_change_required: bool,
+    /// MFA was reset after suspicious activity; `access_token` is only good for
+    /// `/auth/mfa-setup` and `/auth/mfa-enable`, after which the user logs in again
+    pub mfa_enrollment_required: bool,
+    /// Set when this policy must be accepted first; `access_token` is only good for
+    /// `/auth/accept-policy`, which completes the login
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub policy_acceptance_required: Option<PolicyNotice>,
 }
 
-#[derive(Debug, Serialize)]
+redacted_debug!(LoginResponse {
+    token_type,
+    expires_in,
+    user,
+    mfa_required,
+    passkey_prompt,
+    approval_id,
+    password_change_required,
+    mfa_enrollment_required,
+    policy_acceptance_required,
+});
+
+#[derive(Serialize)]
+pub struct ReauthenticateResponse {
+    pub access_token: String,
+    pub token_type: String,
+    pub expires_in: u64,
+}
+
+redacted_debug!(ReauthenticateResponse { token_type, expires_in });
+
+#[derive(Serialize)]
+#[cfg_attr(feature = "client", derive(Deserialize))]
 pub struct MfaSetupResponse {
     pub secret: String,
-    pub qr_code_url: String,
+    pub qr_code_url: String, // Embeds the secret too
 }
 
+redacted_debug!(MfaSetupResponse {});
+
 #[derive(Debug, Serialize)]
 pub struct PasswordResetResponse {
     pub message: String,
@@ -152,15 +591,84 @@ impl From<User> for UserResponse {
     fn from(user: User) -> Self {
         UserResponse {
             id: user.id,
+            email: user.display_email().to_string(),
             username: user.username,
-            email: user.email,
             is_email_verified: user.is_email_verified,
             mfa_enabled: user.mfa_enabled,
             created_at: user.created_at,
             updated_at: user.updated_at,
             last_login_at: user.last_login_at,
-            is_active: user.is_active,
+            is_active: user.is_active(),
+            status: user.account_status(),
             is_admin: user.is_admin,
+            is_guest: user.is_guest,
+            activation_pending: user.activation_pending,
+            version: user.version,
+            display_name: user.display_name,
+            avatar_url: user.avatar_url,
+            locale: user.locale,
+            timezone: user.timezone,
+            metadata: user.metadata,
+            password_expires_at: user.password_expires_at,
+            archived_at: user.archived_at,
+        }
+    }
+}
+
+/// Everything an account-security page shows, in one response
+#[derive(Debug, Serialize)]
+pub struct AccountOverview {
+    pub profile: UserResponse,
+    pub mfa: MfaOverview,
+    pub passkeys: Vec<PasskeySummary>,
+    pub sessions: Page<SessionResponse>,
+    pub recent_logins: Vec<RecentLogin>,
+    pub proxy_aliases: Vec<ProxyAliasResponse>,
+    pub pending_actions: Vec<SecurityAction>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MfaOverview {
+    pub enabled: bool,
+    pub methods: Vec<&'static str>, // "totp", "passkey", in the user's preferred order
+    pub totp_devices: Vec<TotpDeviceResponse>,
+    pub recovery_codes: RecoveryCodeStatus,
+}
+
+/// Where and when a session was started
+#[derive(Debug, Serialize)]
+pub struct RecentLogin {
+    pub at: DateTime<Utc>,
+    pub device: String,
+    pub ip_address: Option<String>,
+}
+
+impl From<OutboxEvent> for RecentLogin {
+    /// From a `user.logged_in` event
+    fn from(event: OutboxEvent) -> Self {
+        let field = |name: &str| event.payload.get(name).and_then(|v| v.as_str()).map(str::to_string);
+        let device = DeviceInfo {
+            browser: field("browser"),
+            os: field("os"),
+            device_class: field("device_class"),
+        };
+
+        RecentLogin {
+            at: event.created_at,
+            device: device.describe(None),
+            ip_address: field("ip_address"),
         }
     }
 }
+
+/// Something the user should do to secure their account
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SecurityAction {
+    VerifyEmail,
+    EnableMfa,
+    ConfirmTotpDevice, // A TOTP device was added but never confirmed
+    AddPasskey,
+    ChangePassword,          // The password has expired or an admin forced a reset
+    RegenerateRecoveryCodes, // Few or no unused recovery codes are left
+}
@@ -0,0 +1,58 @@
+use crate::schema::user_directory;
+use crate::utils::validation::canonical_email;
+use chrono::{DateTime, Utc};
+use diesel::prelude::*;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+/// Which region's database a user lives in, kept in the home region's
+#[derive(Debug, Clone, Serialize, Queryable, Identifiable)]
+#[diesel(table_name = user_directory, primary_key(user_id))]
+pub struct DirectoryEntry {
+    pub user_id: Uuid,
+    pub region: String,
+    pub email_digest: String,    // `directory_digest(email)`
+    pub username_digest: String, // `directory_digest(username)`
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Insertable)]
+#[diesel(table_name = user_directory)]
+pub struct NewDirectoryEntry {
+    pub user_id: Uuid,
+    pub region: String,
+    pub email_digest: String,
+    pub username_digest: String,
+}
+
+impl NewDirectoryEntry {
+    pub fn new(user_id: Uuid, region: &str, username: &str, email: &str) -> Self {
+        NewDirectoryEntry {
+            user_id,
+            region: region.to_string(),
+            email_digest: directory_digest(email),
+            username_digest: directory_digest(username),
+        }
+    }
+}
+
+/// An email or username as the directory keeps it: canonicalized and lower
+/// cased like the lookups on `users`, then hashed, so the directory can find
+/// an account without holding its identifiers
+pub fn directory_digest(identifier: &str) -> String {
+    hex::encode(Sha256::digest(canonical_email(identifier).to_lowercase().as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_digests_match_like_lookups() {
+        assert_eq!(directory_digest(" Alice@Example.com "), directory_digest("alice@example.com"));
+        assert_eq!(directory_digest("Alice"), directory_digest("alice"));
+        assert_ne!(directory_digest("alice"), directory_digest("alice@example.com"));
+        assert_eq!(directory_digest("alice").len(), 64);
+    }
+}
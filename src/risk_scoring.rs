@@ -1,5 +1,7 @@
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Mutex, RwLock}; // Added RwLock for better concurrency
 use uuid::Uuid;
 use chrono::{DateTime, Utc, Duration, Timelike};
@@ -8,6 +10,7 @@ use chrono::TimeZone; // Required for hour() method
 // Risk factor thresholds
 const RISK_THRESHOLD_BLOCK: u32 = 80;  // Block login if risk score > 80%
 const RISK_THRESHOLD_MFA: u32 = 50;    // Require MFA if risk score > 50%
+const RISK_THRESHOLD_CAPTCHA: u32 = 30; // Require a CAPTCHA if risk score > 30%
 
 // Risk factors weights (out of 100)
 const RISK_WEIGHT_NEW_DEVICE: u32 = 20;
@@ -16,6 +19,7 @@ const RISK_WEIGHT_ODD_TIME: u32 = 15;
 const RISK_WEIGHT_IMPOSSIBLE_TRAVEL: u32 = 50;
 const RISK_WEIGHT_MULTIPLE_FAILED_ATTEMPTS: u32 = 30;
 const RISK_WEIGHT_COMPROMISED_PASSWORD: u32 = 100;
+pub const RISK_WEIGHT_BAD_IP_REPUTATION: u32 = 40; // Default; deployments set their own
 
 // Store for user login history
 #[derive(Debug, Clone, Default)]
@@ -36,7 +40,7 @@ pub struct LoginRecord {
 }
 
 // Geographic location data
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct GeoLocation {
     pub latitude: f64,
     pub longitude: f64,
@@ -72,13 +76,163 @@ pub struct RiskFactor {
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub enum RiskAction {
     Allow,
+    RequireCaptcha, // Moderate risk: prove there's a person behind the login
     RequireMfa,
     Block,
 }
 
+// What a reputation source says about an address
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IpReputationVerdict {
+    pub source: String,
+    pub confidence: u32, // 0-100, how sure the source is that the address is abusive
+}
+
+// A source of IP reputation: a third-party service, an internal denylist, ...
+// Lookups happen on the login path and must answer from memory, so providers
+// backed by a remote service fetch in the background and answer from a cache.
+pub trait IpReputationProvider: Send + Sync {
+    fn lookup(&self, ip: &IpAddr) -> Option<IpReputationVerdict>;
+}
+
+// Addresses and networks that are always treated as abusive, e.g.
+// "203.0.113.7", "198.51.100.0/24" or "2001:db8::/32"
+#[derive(Debug, Clone, Default)]
+pub struct IpDenylist {
+    networks: Vec<(IpAddr, u8)>,
+}
+
+impl IpDenylist {
+    pub fn parse<S: AsRef<str>>(entries: &[S]) -> Result<Self, String> {
+        let mut networks = Vec::new();
+
+        for entry in entries {
+            let entry = entry.as_ref().trim();
+            if entry.is_empty() {
+                continue;
+            }
+
+            let (address, prefix) = match entry.split_once('/') {
+                Some((address, prefix)) => (address, Some(prefix)),
+                None => (entry, None),
+            };
+            let address: IpAddr = address.parse().map_err(|_| format!("invalid address: {}", entry))?;
+            let max_prefix = if address.is_ipv4() { 32 } else { 128 };
+            let prefix = match prefix {
+                Some(prefix) => prefix
+                    .parse::<u8>()
+                    .ok()
+                    .filter(|p| *p <= max_prefix)
+                    .ok_or_else(|| format!("invalid prefix length: {}", entry))?,
+                None => max_prefix,
+            };
+
+            networks.push((address, prefix));
+        }
+
+        Ok(IpDenylist { networks })
+    }
+
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        self.networks.iter().any(|(network, prefix)| in_network(ip, network, *prefix))
+    }
+}
+
+impl IpReputationProvider for IpDenylist {
+    fn lookup(&self, ip: &IpAddr) -> Option<IpReputationVerdict> {
+        self.contains(ip).then(|| IpReputationVerdict {
+            source: "denylist".to_string(),
+            confidence: 100,
+        })
+    }
+}
+
+// Whether `ip` falls in `network`/`prefix`; addresses of different families never match
+fn in_network(ip: &IpAddr, network: &IpAddr, prefix: u8) -> bool {
+    let (ip, network, bits) = match (ip, network) {
+        (IpAddr::V4(ip), IpAddr::V4(network)) => (u32::from(*ip) as u128, u32::from(*network) as u128, 32),
+        (IpAddr::V6(ip), IpAddr::V6(network)) => (u128::from(*ip), u128::from(*network), 128),
+        _ => return false,
+    };
+
+    if prefix == 0 {
+        return true;
+    }
+    let shift = bits - prefix as u32;
+    (ip >> shift) == (network >> shift)
+}
+
+// Reputation-based scoring counters, e.g. for a metrics endpoint
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct IpReputationMetrics {
+    pub lookups: u64,
+    pub flagged: u64, // Lookups that added the reputation factor
+    pub blocked: u64, // Blocked logins the reputation factor contributed to
+}
+
+// Adds a `bad_ip_reputation` factor, worth `weight`, when any provider is at
+// least `min_confidence` sure the login's address is abusive
+pub struct IpReputation {
+    providers: Vec<Box<dyn IpReputationProvider>>,
+    weight: u32,
+    min_confidence: u32,
+    lookups: AtomicU64,
+    flagged: AtomicU64,
+    blocked: AtomicU64,
+}
+
+impl IpReputation {
+    pub fn new(weight: u32, min_confidence: u32) -> Self {
+        IpReputation {
+            providers: Vec::new(),
+            weight,
+            min_confidence,
+            lookups: AtomicU64::new(0),
+            flagged: AtomicU64::new(0),
+            blocked: AtomicU64::new(0),
+        }
+    }
+
+    pub fn with_provider(mut self, provider: Box<dyn IpReputationProvider>) -> Self {
+        self.providers.push(provider);
+        self
+    }
+
+    pub fn weight(&self) -> u32 {
+        self.weight
+    }
+
+    // The most confident verdict at or above the threshold, if any
+    pub fn check(&self, ip_address: &str) -> Option<IpReputationVerdict> {
+        let ip: IpAddr = ip_address.parse().ok()?;
+        self.lookups.fetch_add(1, Ordering::Relaxed);
+
+        let verdict = self
+            .providers
+            .iter()
+            .filter_map(|provider| provider.lookup(&ip))
+            .filter(|verdict| verdict.confidence >= self.min_confidence)
+            .max_by_key(|verdict| verdict.confidence);
+
+        if verdict.is_some() {
+            self.flagged.fetch_add(1, Ordering::Relaxed);
+        }
+        verdict
+    }
+
+    pub fn metrics(&self) -> IpReputationMetrics {
+        IpReputationMetrics {
+            lookups: self.lookups.load(Ordering::Relaxed),
+            flagged: self.flagged.load(Ordering::Relaxed),
+            blocked: self.blocked.load(Ordering::Relaxed),
+        }
+    }
+}
+
 // Risk scoring context
 pub struct RiskScoringContext {
     pub state: RwLock<RiskScoringState>, // Using RwLock for better concurrency
+    ip_reputation: Option<IpReputation>,
 }
 
 // Calculate the distance between two geographic points (Haversine formula)
@@ -139,9 +293,20 @@ impl RiskScoringContext {
     pub fn new() -> Self {
         RiskScoringContext {
             state: RwLock::new(RiskScoringState::default()),
+            ip_reputation: None,
         }
     }
     
+    // Score logins from addresses with a bad reputation
+    pub fn with_ip_reputation(mut self, ip_reputation: IpReputation) -> Self {
+        self.ip_reputation = Some(ip_reputation);
+        self
+    }
+    
+    pub fn ip_reputation_metrics(&self) -> Option<IpReputationMetrics> {
+        self.ip_reputation.as_ref().map(|r| r.metrics())
+    }
+    
     // Record a login attempt
     pub fn record_login(&self, user_id: &Uuid, record: LoginRecord) {
         // Using write lock for writing operations
@@ -286,6 +451,22 @@ impl RiskScoringContext {
             total_weight += RISK_WEIGHT_MULTIPLE_FAILED_ATTEMPTS;
         }
         
+        // Check the address against the configured reputation sources
+        let bad_reputation = self.ip_reputation.as_ref()
+            .and_then(|r| r.check(&login_info.ip_address).map(|verdict| (r, verdict)));
+            
+        if let Some((reputation, verdict)) = &bad_reputation {
+            risk_factors.push(RiskFactor {
+                name: "bad_ip_reputation".to_string(),
+                description: format!(
+                    "Login from an address flagged by {} ({}% confidence)",
+                    verdict.source, verdict.confidence
+                ),
+                weight: reputation.weight(),
+            });
+            total_weight += reputation.weight();
+        }
+        
         // Calculate final score (0-100)
         let score = if risk_factors.is_empty() { 
             0 
@@ -298,10 +479,16 @@ impl RiskScoringContext {
             RiskAction::Block
         } else if score >= RISK_THRESHOLD_MFA {
             RiskAction::RequireMfa
+        } else if score >= RISK_THRESHOLD_CAPTCHA {
+            RiskAction::RequireCaptcha
         } else {
             RiskAction::Allow
         };
         
+        if let Some((reputation, _)) = bad_reputation.filter(|_| action == RiskAction::Block) {
+            reputation.blocked.fetch_add(1, Ordering::Relaxed);
+        }
+        
         RiskAnalysisResult {
             score,
             factors: risk_factors,
@@ -322,4 +509,74 @@ impl RiskScoringContext {
         let result = self.analyze_login_risk(user_id, login_info);
         (result.action == RiskAction::RequireMfa || result.action == RiskAction::Block, result)
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn login_from(ip: &str) -> LoginRecord {
+        LoginRecord {
+            timestamp: Utc::now(),
+            ip_address: ip.to_string(),
+            location: None,
+            device_id: String::new(),
+            user_agent: String::new(),
+            success: true,
+        }
+    }
+
+    #[test]
+    fn test_denylist_matches_addresses_and_networks() {
+        let denylist = IpDenylist::parse(&["203.0.113.7", "198.51.100.0/24", "2001:db8::/32"]).unwrap();
+
+        assert!(denylist.contains(&"203.0.113.7".parse().unwrap()));
+        assert!(!denylist.contains(&"203.0.113.8".parse().unwrap()));
+        assert!(denylist.contains(&"198.51.100.200".parse().unwrap()));
+        assert!(!denylist.contains(&"198.51.101.1".parse().unwrap()));
+        assert!(denylist.contains(&"2001:db8::1".parse().unwrap()));
+        assert!(!denylist.contains(&"::ffff:198.51.100.1".parse().unwrap()));
+
+        assert!(IpDenylist::parse(&["10.0.0.0/33"]).is_err());
+        assert!(IpDenylist::parse(&["not-an-ip"]).is_err());
+    }
+
+    #[test]
+    fn test_bad_reputation_adds_configured_weight() {
+        let denylist = IpDenylist::parse(&["198.51.100.0/24"]).unwrap();
+        let context = RiskScoringContext::new()
+            .with_ip_reputation(IpReputation::new(85, 75).with_provider(Box::new(denylist)));
+        let user_id = Uuid::new_v4();
+
+        let result = context.analyze_login_risk(&user_id, &login_from("198.51.100.9"));
+        assert_eq!(result.score, 85);
+        assert_eq!(result.action, RiskAction::Block);
+        assert!(result.factors.iter().any(|f| f.name == "bad_ip_reputation"));
+
+        let result = context.analyze_login_risk(&user_id, &login_from("192.0.2.1"));
+        assert_eq!(result.score, 0);
+        assert_eq!(result.action, RiskAction::Allow);
+
+        let metrics = context.ip_reputation_metrics().unwrap();
+        assert_eq!((metrics.lookups, metrics.flagged, metrics.blocked), (2, 1, 1));
+    }
+
+    #[test]
+    fn test_moderate_risk_requires_captcha() {
+        let denylist = IpDenylist::parse(&["198.51.100.0/24"]).unwrap();
+        let user_id = Uuid::new_v4();
+
+        // A lightly weighted denylist only gets the login a CAPTCHA
+        let context = RiskScoringContext::new()
+            .with_ip_reputation(IpReputation::new(35, 75).with_provider(Box::new(denylist.clone())));
+        let result = context.analyze_login_risk(&user_id, &login_from("198.51.100.9"));
+        assert_eq!(result.action, RiskAction::RequireCaptcha);
+        assert!(!context.should_require_mfa(&user_id, &login_from("198.51.100.9")).0);
+
+        // MFA is kept for higher scores
+        let context = RiskScoringContext::new()
+            .with_ip_reputation(IpReputation::new(55, 75).with_provider(Box::new(denylist)));
+        let result = context.analyze_login_risk(&user_id, &login_from("198.51.100.9"));
+        assert_eq!(result.action, RiskAction::RequireMfa);
+    }
+}
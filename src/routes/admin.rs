@@ -0,0 +1,806 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use serde::Deserialize;
+use validator::Validate;
+
+use crate::errors::AuthError;
+use crate::middleware::admin_audit::AdminAudit;
+use crate::middleware::auth::{AdminMiddleware, AuthenticatedUser, ScopedAuthMiddleware};
+use crate::middleware::step_up::{StepUpMiddleware, StepUpPolicy};
+use crate::models::{
+    AuditEventFilter, BulkJobRequest, ClientApplicationRequest, CreateCanaryRequest, DelegatedTokenRequest, FeatureFlagRequest, ForcePasswordResetRequest, InviteUserRequest, LoginFreezeRequest, PageRequest, ResolveAppealRequest,
+    SessionFilter, UpdateAccountStatusRequest, UpdateApiKeyQuotaRequest, UserFilter,
+};
+use crate::routes::users::{etag, if_match};
+use crate::services::auth::AuthService;
+use crate::utils::i18n::Locale;
+use crate::utils::jwt::TokenScope;
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        // Middleware runs last-wrapped first: authenticate, record the call,
+        // then check the caller is an admin
+        web::scope("/admin")
+            .wrap(AdminMiddleware)
+            .wrap(AdminAudit)
+            .wrap(ScopedAuthMiddleware(&[TokenScope::Full, TokenScope::ApiKey]))
+            .service(accessibility_report)
+            .service(session_metrics)
+            .service(system_checks)
+            .service(force_password_reset)
+            .service(create_bulk_job)
+            .service(list_bulk_jobs)
+            .service(get_bulk_job)
+            .service(bulk_job_report)
+            .service(invite_user)
+            .service(search_users)
+            .service(get_user)
+            .service(update_account_status)
+            .service(account_status_history)
+            .service(user_sessions)
+            .service(revoke_user_session)
+            .service(revoke_user_sessions)
+            .service(issue_delegated_token)
+            .service(revoke_delegated_token)
+            .service(lockouts)
+            .service(unlock_account)
+            .service(unlock_ip)
+            .service(audit_events)
+            .service(pending_appeals)
+            .service(resolve_appeal)
+            .service(user_api_keys)
+            .service(api_key_usage)
+            .service(update_api_key_quota)
+            .service(list_canaries)
+            .service(create_canary)
+            .service(delete_canary)
+            .service(unblock_source)
+            .service(list_feature_flags)
+            .service(save_feature_flag)
+            .service(delete_feature_flag)
+            .service(list_client_applications)
+            .service(get_client_application)
+            .service(save_client_application)
+            .service(delete_client_application)
+            .service(list_login_freezes)
+            .service(freeze_logins)
+            .service(unfreeze_logins)
+            .service(freeze_organization_logins)
+            .service(unfreeze_organization_logins),
+    );
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum ReportFormat {
+    #[default]
+    Json,
+    Csv,
+}
+
+#[derive(Debug, Deserialize)]
+struct ReportQuery {
+    #[serde(default)]
+    format: ReportFormat,
+}
+
+// Trigrams need three characters, so shorter terms can't use the indexes
+#[derive(Debug, Validate, Deserialize)]
+struct SearchQuery {
+    #[validate(length(min = 3, max = 254))]
+    q: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct UsageQuery {
+    days: Option<i64>,
+}
+
+// Days of usage returned when the query doesn't say
+const DEFAULT_USAGE_DAYS: i64 = 30;
+const MAX_USAGE_DAYS: i64 = 366;
+
+/// WCAG compliance measured on the stylesheets and shortcuts users are served
+#[actix_web::get("/accessibility-report")]
+async fn accessibility_report(
+    auth_service: web::Data<AuthService>,
+    query: web::Query<ReportQuery>,
+) -> Result<HttpResponse, AuthError> {
+    let report = auth_service.accessibility_report();
+    
+    Ok(match query.format {
+        ReportFormat::Json => HttpResponse::Ok().json(report),
+        ReportFormat::Csv => HttpResponse::Ok()
+            .content_type("text/csv; charset=utf-8")
+            .insert_header((
+                "Content-Disposition",
+                "attachment; filename=\"accessibility-report.csv\"",
+            ))
+            .body(report.to_csv()),
+    })
+}
+
+/// Size of the sessions table, live and ended, and what the purge has removed
+#[actix_web::get("/metrics/sessions")]
+async fn session_metrics(auth_service: web::Data<AuthService>) -> Result<HttpResponse, AuthError> {
+    let metrics = auth_service.session_table_metrics().await?;
+    
+    Ok(HttpResponse::Ok().json(metrics))
+}
+
+/// JWT secret strength, development settings left on, SMTP reachability
+/// and pending migrations, as checked at startup
+#[actix_web::get("/system/checks")]
+async fn system_checks(auth_service: web::Data<AuthService>) -> Result<HttpResponse, AuthError> {
+    let report = auth_service.system_checks().await;
+    
+    Ok(HttpResponse::Ok().json(report))
+}
+
+/// Expire passwords for specific users or everyone, e.g. after an incident
+#[actix_web::post(
+    "/force-password-reset",
+    wrap = "StepUpMiddleware(StepUpPolicy::password_within(300))"
+)]
+async fn force_password_reset(
+    auth_service: web::Data<AuthService>,
+    user: web::ReqData<AuthenticatedUser>,
+    reset_data: web::Json<ForcePasswordResetRequest>,
+) -> Result<HttpResponse, AuthError> {
+    reset_data.validate()?;
+    
+    let response = auth_service
+        .force_password_reset(user.user_id, reset_data.into_inner())
+        .await?;
+    
+    Ok(HttpResponse::Ok().json(response))
+}
+
+/// Queue an operation over many accounts, or a dry run of one, for the
+/// background runner
+#[actix_web::post(
+    "/bulk-jobs",
+    wrap = "StepUpMiddleware(StepUpPolicy::password_within(300))"
+)]
+async fn create_bulk_job(
+    auth_service: web::Data<AuthService>,
+    user: web::ReqData<AuthenticatedUser>,
+    job_data: web::Json<BulkJobRequest>,
+) -> Result<HttpResponse, AuthError> {
+    let response = auth_service
+        .create_bulk_job(user.user_id, job_data.into_inner())
+        .await?;
+    
+    Ok(HttpResponse::Accepted().json(response))
+}
+
+#[actix_web::get("/bulk-jobs")]
+async fn list_bulk_jobs(auth_service: web::Data<AuthService>) -> Result<HttpResponse, AuthError> {
+    let response = auth_service.list_bulk_jobs().await?;
+    
+    Ok(HttpResponse::Ok().json(response))
+}
+
+#[actix_web::get("/bulk-jobs/{job_id}")]
+async fn get_bulk_job(
+    auth_service: web::Data<AuthService>,
+    job_id: web::Path<uuid::Uuid>,
+) -> Result<HttpResponse, AuthError> {
+    let response = auth_service.get_bulk_job(*job_id).await?;
+    
+    Ok(HttpResponse::Ok().json(response))
+}
+
+/// Per-account results of a finished job, as JSON or a CSV download
+#[actix_web::get("/bulk-jobs/{job_id}/report")]
+async fn bulk_job_report(
+    auth_service: web::Data<AuthService>,
+    job_id: web::Path<uuid::Uuid>,
+    query: web::Query<ReportQuery>,
+) -> Result<HttpResponse, AuthError> {
+    let report = auth_service.bulk_job_report(*job_id).await?;
+    
+    Ok(match query.format {
+        ReportFormat::Json => HttpResponse::Ok().json(report),
+        ReportFormat::Csv => HttpResponse::Ok()
+            .content_type("text/csv; charset=utf-8")
+            .insert_header((
+                "Content-Disposition",
+                format!("attachment; filename=\"bulk-job-{}.csv\"", job_id),
+            ))
+            .body(report.to_csv()),
+    })
+}
+
+/// Create an account from an email address; its owner sets the password
+#[actix_web::post("/users/invite")]
+async fn invite_user(
+    auth_service: web::Data<AuthService>,
+    user: web::ReqData<AuthenticatedUser>,
+    invite_data: web::Json<InviteUserRequest>,
+    locale: web::ReqData<Locale>,
+) -> Result<HttpResponse, AuthError> {
+    invite_data.validate()?;
+    
+    let response = auth_service
+        .invite_user(user.user_id, invite_data.into_inner(), &locale.0)
+        .await?;
+    
+    Ok(HttpResponse::Created().json(response))
+}
+
+/// Find users by any part of their username, email or display name, or by id
+#[actix_web::get("/users")]
+async fn search_users(
+    auth_service: web::Data<AuthService>,
+    search: web::Query<SearchQuery>,
+    filter: web::Query<UserFilter>,
+    page: web::Query<PageRequest>,
+) -> Result<HttpResponse, AuthError> {
+    search.validate()?;
+    page.validate()?;
+    
+    let response = auth_service
+        .search_users(search.into_inner().q, filter.into_inner(), page.into_inner())
+        .await?;
+    
+    Ok(HttpResponse::Ok().json(response))
+}
+
+/// An account with its rolling risk score and the protective actions taken
+#[actix_web::get("/users/{user_id}")]
+async fn get_user(
+    auth_service: web::Data<AuthService>,
+    user_id: web::Path<uuid::Uuid>,
+) -> Result<HttpResponse, AuthError> {
+    let response = auth_service.admin_get_user(*user_id).await?;
+    
+    Ok(HttpResponse::Ok().insert_header(etag(response.user.version)).json(response))
+}
+
+/// Suspend, ban, schedule for deletion or reactivate an account. With
+/// `If-Match`, only if the account hasn't changed since it was looked at.
+#[actix_web::put("/users/{user_id}/status")]
+async fn update_account_status(
+    req: HttpRequest,
+    auth_service: web::Data<AuthService>,
+    user: web::ReqData<AuthenticatedUser>,
+    user_id: web::Path<uuid::Uuid>,
+    status_data: web::Json<UpdateAccountStatusRequest>,
+) -> Result<HttpResponse, AuthError> {
+    status_data.validate()?;
+    
+    let response = auth_service
+        .update_account_status(user.user_id, *user_id, status_data.into_inner(), if_match(&req)?)
+        .await?;
+    
+    Ok(HttpResponse::Ok().insert_header(etag(response.version)).json(response))
+}
+
+#[actix_web::get("/users/{user_id}/status-history")]
+async fn account_status_history(
+    auth_service: web::Data<AuthService>,
+    user_id: web::Path<uuid::Uuid>,
+    page: web::Query<PageRequest>,
+) -> Result<HttpResponse, AuthError> {
+    page.validate()?;
+    
+    let response = auth_service.account_status_history(*user_id, page.into_inner()).await?;
+    
+    Ok(HttpResponse::Ok().json(response))
+}
+
+/// A user's sessions, for finding one to revoke
+#[actix_web::get("/users/{user_id}/sessions")]
+async fn user_sessions(
+    auth_service: web::Data<AuthService>,
+    user_id: web::Path<uuid::Uuid>,
+    page: web::Query<PageRequest>,
+    filter: web::Query<SessionFilter>,
+) -> Result<HttpResponse, AuthError> {
+    page.validate()?;
+    
+    let response = auth_service
+        .admin_user_sessions(*user_id, filter.into_inner(), page.into_inner())
+        .await?;
+    
+    Ok(HttpResponse::Ok().json(response))
+}
+
+#[actix_web::delete("/users/{user_id}/sessions/{session_id}")]
+async fn revoke_user_session(
+    auth_service: web::Data<AuthService>,
+    user: web::ReqData<AuthenticatedUser>,
+    path: web::Path<(uuid::Uuid, uuid::Uuid)>,
+) -> Result<HttpResponse, AuthError> {
+    let (user_id, session_id) = path.into_inner();
+    
+    let response = auth_service
+        .admin_revoke_session(user.user_id, user_id, session_id)
+        .await?;
+    
+    Ok(HttpResponse::Ok().json(response))
+}
+
+/// Sign a user out everywhere, e.g. when the account is thought compromised
+#[actix_web::delete("/users/{user_id}/sessions")]
+async fn revoke_user_sessions(
+    auth_service: web::Data<AuthService>,
+    user: web::ReqData<AuthenticatedUser>,
+    user_id: web::Path<uuid::Uuid>,
+) -> Result<HttpResponse, AuthError> {
+    let response = auth_service
+        .admin_revoke_all_sessions(user.user_id, *user_id)
+        .await?;
+    
+    Ok(HttpResponse::Ok().json(response))
+}
+
+/// A short-lived token acting as the user with only the scopes asked for, for
+/// support tooling; issuing it is recorded in the audit log
+#[actix_web::post(
+    "/users/{user_id}/delegated-tokens",
+    wrap = "StepUpMiddleware(StepUpPolicy::password_within(300))"
+)]
+async fn issue_delegated_token(
+    auth_service: web::Data<AuthService>,
+    user: web::ReqData<AuthenticatedUser>,
+    user_id: web::Path<uuid::Uuid>,
+    data: web::Json<DelegatedTokenRequest>,
+) -> Result<HttpResponse, AuthError> {
+    data.validate()?;
+
+    let response = auth_service
+        .issue_delegated_token(user.user_id, *user_id, data.into_inner())
+        .await?;
+
+    Ok(HttpResponse::Created()
+        .insert_header(("Cache-Control", "no-store"))
+        .json(response))
+}
+
+#[actix_web::delete("/users/{user_id}/delegated-tokens/{token_id}")]
+async fn revoke_delegated_token(
+    auth_service: web::Data<AuthService>,
+    user: web::ReqData<AuthenticatedUser>,
+    path: web::Path<(uuid::Uuid, uuid::Uuid)>,
+) -> Result<HttpResponse, AuthError> {
+    let (user_id, token_id) = path.into_inner();
+
+    let response = auth_service
+        .revoke_delegated_token(user.user_id, user_id, token_id)
+        .await?;
+
+    Ok(HttpResponse::Ok().json(response))
+}
+
+/// Accounts and IPs locked out after repeated failed logins
+#[actix_web::get("/lockouts")]
+async fn lockouts(auth_service: web::Data<AuthService>) -> Result<HttpResponse, AuthError> {
+    let response = auth_service.lockouts().await?;
+    
+    Ok(HttpResponse::Ok().json(response))
+}
+
+#[actix_web::delete("/users/{user_id}/lockout")]
+async fn unlock_account(
+    auth_service: web::Data<AuthService>,
+    user: web::ReqData<AuthenticatedUser>,
+    user_id: web::Path<uuid::Uuid>,
+) -> Result<HttpResponse, AuthError> {
+    let response = auth_service.unlock_account(user.user_id, *user_id).await?;
+    
+    Ok(HttpResponse::Ok().json(response))
+}
+
+#[actix_web::delete("/lockouts/ips/{ip}")]
+async fn unlock_ip(
+    auth_service: web::Data<AuthService>,
+    user: web::ReqData<AuthenticatedUser>,
+    ip: web::Path<String>,
+) -> Result<HttpResponse, AuthError> {
+    let response = auth_service.unlock_ip(user.user_id, &ip).await?;
+    
+    Ok(HttpResponse::Ok().json(response))
+}
+
+/// Auth events such as sign-ups and status changes, filtered by user or type
+#[actix_web::get("/audit-events")]
+async fn audit_events(
+    auth_service: web::Data<AuthService>,
+    filter: web::Query<AuditEventFilter>,
+    page: web::Query<PageRequest>,
+) -> Result<HttpResponse, AuthError> {
+    page.validate()?;
+    
+    let response = auth_service
+        .audit_events(filter.into_inner(), page.into_inner())
+        .await?;
+    
+    Ok(HttpResponse::Ok().json(response))
+}
+
+#[actix_web::get("/appeals")]
+async fn pending_appeals(auth_service: web::Data<AuthService>) -> Result<HttpResponse, AuthError> {
+    let response = auth_service.pending_appeals().await?;
+    
+    Ok(HttpResponse::Ok().json(response))
+}
+
+#[actix_web::post("/appeals/{appeal_id}/resolve")]
+async fn resolve_appeal(
+    auth_service: web::Data<AuthService>,
+    user: web::ReqData<AuthenticatedUser>,
+    appeal_id: web::Path<uuid::Uuid>,
+    resolve_data: web::Json<ResolveAppealRequest>,
+) -> Result<HttpResponse, AuthError> {
+    resolve_data.validate()?;
+    
+    let response = auth_service
+        .resolve_appeal(user.user_id, *appeal_id, resolve_data.into_inner())
+        .await?;
+    
+    Ok(HttpResponse::Ok().json(response))
+}
+
+#[actix_web::get("/users/{user_id}/api-keys")]
+async fn user_api_keys(
+    auth_service: web::Data<AuthService>,
+    user_id: web::Path<uuid::Uuid>,
+) -> Result<HttpResponse, AuthError> {
+    let response = auth_service.admin_list_api_keys(*user_id).await?;
+    
+    Ok(HttpResponse::Ok().json(response))
+}
+
+/// Daily request counts for a key against its quotas
+#[actix_web::get("/api-keys/{key_id}/usage")]
+async fn api_key_usage(
+    auth_service: web::Data<AuthService>,
+    key_id: web::Path<uuid::Uuid>,
+    query: web::Query<UsageQuery>,
+) -> Result<HttpResponse, AuthError> {
+    let days = query.days.unwrap_or(DEFAULT_USAGE_DAYS).clamp(1, MAX_USAGE_DAYS);
+    let response = auth_service.api_key_usage(*key_id, days).await?;
+    
+    Ok(HttpResponse::Ok().json(response))
+}
+
+/// Give one key its own daily and monthly quotas
+#[actix_web::put("/api-keys/{key_id}/quota")]
+async fn update_api_key_quota(
+    auth_service: web::Data<AuthService>,
+    key_id: web::Path<uuid::Uuid>,
+    quota_data: web::Json<UpdateApiKeyQuotaRequest>,
+) -> Result<HttpResponse, AuthError> {
+    quota_data.validate()?;
+    
+    let response = auth_service
+        .update_api_key_quota(*key_id, quota_data.into_inner())
+        .await?;
+    
+    Ok(HttpResponse::Ok().json(response))
+}
+
+/// Planted canaries with their trip counts, and the sources currently blocked
+#[actix_web::get("/canaries")]
+async fn list_canaries(auth_service: web::Data<AuthService>) -> Result<HttpResponse, AuthError> {
+    let response = auth_service.list_canaries().await?;
+    
+    Ok(HttpResponse::Ok().json(response))
+}
+
+/// Plant a canary account, optionally with an API key
+#[actix_web::post(
+    "/canaries",
+    wrap = "StepUpMiddleware(StepUpPolicy::password_within(300))"
+)]
+async fn create_canary(
+    auth_service: web::Data<AuthService>,
+    user: web::ReqData<AuthenticatedUser>,
+    canary_data: web::Json<CreateCanaryRequest>,
+) -> Result<HttpResponse, AuthError> {
+    canary_data.validate()?;
+    
+    let response = auth_service
+        .create_canary(user.user_id, canary_data.into_inner())
+        .await?;
+    
+    Ok(HttpResponse::Created().json(response))
+}
+
+#[actix_web::delete("/canaries/{canary_id}")]
+async fn delete_canary(
+    auth_service: web::Data<AuthService>,
+    user: web::ReqData<AuthenticatedUser>,
+    canary_id: web::Path<uuid::Uuid>,
+) -> Result<HttpResponse, AuthError> {
+    let response = auth_service.delete_canary(user.user_id, *canary_id).await?;
+    
+    Ok(HttpResponse::Ok().json(response))
+}
+
+/// Lift the block on a source that used a canary credential
+#[actix_web::delete("/canaries/blocked-sources/{ip}")]
+async fn unblock_source(
+    auth_service: web::Data<AuthService>,
+    user: web::ReqData<AuthenticatedUser>,
+    ip: web::Path<String>,
+) -> Result<HttpResponse, AuthError> {
+    let response = auth_service.unblock_source(user.user_id, &ip).await?;
+    
+    Ok(HttpResponse::Ok().json(response))
+}
+
+/// Every feature flag; flags not listed are at their default
+#[actix_web::get("/feature-flags")]
+async fn list_feature_flags(auth_service: web::Data<AuthService>) -> Result<HttpResponse, AuthError> {
+    let response = auth_service.list_feature_flags().await?;
+    
+    Ok(HttpResponse::Ok().json(response))
+}
+
+/// Turn a flag on or off, for everyone, a share of users, or some organizations
+#[actix_web::put("/feature-flags/{key}")]
+async fn save_feature_flag(
+    auth_service: web::Data<AuthService>,
+    user: web::ReqData<AuthenticatedUser>,
+    key: web::Path<String>,
+    flag_data: web::Json<FeatureFlagRequest>,
+) -> Result<HttpResponse, AuthError> {
+    flag_data.validate()?;
+    
+    let response = auth_service
+        .save_feature_flag(user.user_id, &key, flag_data.into_inner())
+        .await?;
+    
+    Ok(HttpResponse::Ok().json(response))
+}
+
+#[actix_web::delete("/feature-flags/{key}")]
+async fn delete_feature_flag(
+    auth_service: web::Data<AuthService>,
+    user: web::ReqData<AuthenticatedUser>,
+    key: web::Path<String>,
+) -> Result<HttpResponse, AuthError> {
+    let response = auth_service.delete_feature_flag(user.user_id, &key).await?;
+    
+    Ok(HttpResponse::Ok().json(response))
+}
+
+/// Every client registered to use the hosted sign-in pages
+#[actix_web::get("/clients")]
+async fn list_client_applications(auth_service: web::Data<AuthService>) -> Result<HttpResponse, AuthError> {
+    let response = auth_service.list_client_applications().await?;
+    
+    Ok(HttpResponse::Ok().json(response))
+}
+
+#[actix_web::get("/clients/{client_id}")]
+async fn get_client_application(
+    auth_service: web::Data<AuthService>,
+    client_id: web::Path<String>,
+) -> Result<HttpResponse, AuthError> {
+    let response = auth_service.get_client_application(&client_id).await?;
+    
+    Ok(HttpResponse::Ok().json(response))
+}
+
+/// Register a client, or replace its redirect URIs, grants and token
+/// lifetimes. A confidential client's secret is in the response only when new.
+#[actix_web::put("/clients/{client_id}")]
+async fn save_client_application(
+    auth_service: web::Data<AuthService>,
+    user: web::ReqData<AuthenticatedUser>,
+    client_id: web::Path<String>,
+    client_data: web::Json<ClientApplicationRequest>,
+) -> Result<HttpResponse, AuthError> {
+    client_data.validate()?;
+    
+    let response = auth_service
+        .save_client_application(user.user_id, &client_id, client_data.into_inner())
+        .await?;
+    
+    Ok(HttpResponse::Ok().json(response))
+}
+
+/// Remove a client and sign out every session started for it
+#[actix_web::delete("/clients/{client_id}")]
+async fn delete_client_application(
+    auth_service: web::Data<AuthService>,
+    user: web::ReqData<AuthenticatedUser>,
+    client_id: web::Path<String>,
+) -> Result<HttpResponse, AuthError> {
+    let response = auth_service.delete_client_application(user.user_id, &client_id).await?;
+    
+    Ok(HttpResponse::Ok().json(response))
+}
+
+/// Login freezes in place, and whether one is set in the configuration
+#[actix_web::get("/login-freezes")]
+async fn list_login_freezes(auth_service: web::Data<AuthService>) -> Result<HttpResponse, AuthError> {
+    let response = auth_service.list_login_freezes().await?;
+    
+    Ok(HttpResponse::Ok().json(response))
+}
+
+/// Refuse new logins and registrations everywhere; admins can still sign in
+#[actix_web::put(
+    "/login-freeze",
+    wrap = "StepUpMiddleware(StepUpPolicy::password_within(300))"
+)]
+async fn freeze_logins(
+    auth_service: web::Data<AuthService>,
+    user: web::ReqData<AuthenticatedUser>,
+    freeze_data: web::Json<LoginFreezeRequest>,
+) -> Result<HttpResponse, AuthError> {
+    freeze_data.validate()?;
+    
+    let response = auth_service
+        .freeze_logins(user.user_id, None, freeze_data.into_inner())
+        .await?;
+    
+    Ok(HttpResponse::Ok().json(response))
+}
+
+#[actix_web::delete("/login-freeze")]
+async fn unfreeze_logins(
+    auth_service: web::Data<AuthService>,
+    user: web::ReqData<AuthenticatedUser>,
+) -> Result<HttpResponse, AuthError> {
+    let response = auth_service.unfreeze_logins(user.user_id, None).await?;
+    
+    Ok(HttpResponse::Ok().json(response))
+}
+
+/// Refuse new logins and registrations for one organization's members
+#[actix_web::put(
+    "/organizations/{organization_id}/login-freeze",
+    wrap = "StepUpMiddleware(StepUpPolicy::password_within(300))"
+)]
+async fn freeze_organization_logins(
+    auth_service: web::Data<AuthService>,
+    user: web::ReqData<AuthenticatedUser>,
+    organization_id: web::Path<uuid::Uuid>,
+    freeze_data: web::Json<LoginFreezeRequest>,
+) -> Result<HttpResponse, AuthError> {
+    freeze_data.validate()?;
+    
+    let response = auth_service
+        .freeze_logins(user.user_id, Some(*organization_id), freeze_data.into_inner())
+        .await?;
+    
+    Ok(HttpResponse::Ok().json(response))
+}
+
+#[actix_web::delete("/organizations/{organization_id}/login-freeze")]
+async fn unfreeze_organization_logins(
+    auth_service: web::Data<AuthService>,
+    user: web::ReqData<AuthenticatedUser>,
+    organization_id: web::Path<uuid::Uuid>,
+) -> Result<HttpResponse, AuthError> {
+    let response = auth_service
+        .unfreeze_logins(user.user_id, Some(*organization_id))
+        .await?;
+    
+    Ok(HttpResponse::Ok().json(response))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::StatusCode;
+    use actix_web::test;
+    use serde_json::{json, Value};
+
+    use crate::models::{AccountStatus, EventType, NewOutboxEvent};
+    use crate::test_utils::TestContext;
+
+    #[actix_web::test]
+    async fn test_bulk_jobs_preview_before_changing_accounts() {
+        let ctx = TestContext::new();
+        let app = ctx.spawn_app().await;
+        let admin = ctx.user().admin().create().await.unwrap();
+        let staff = ctx.user().email("staff@breached.example").create().await.unwrap();
+        let other = ctx.user().create().await.unwrap();
+        let bearer = ("Authorization", format!("Bearer {}", ctx.session(&admin).create().await.unwrap().access_token));
+
+        let queue = |action: Value, dry_run: bool| {
+            test::TestRequest::post()
+                .uri("/admin/bulk-jobs")
+                .insert_header(bearer.clone())
+                .set_json(json!({ "action": action, "dry_run": dry_run }))
+                .to_request()
+        };
+        let report = |job: &Value, format: &str| {
+            test::TestRequest::get()
+                .uri(&format!("/admin/bulk-jobs/{}/report?format={}", job["id"].as_str().unwrap(), format))
+                .insert_header(bearer.clone())
+                .to_request()
+        };
+        let deactivate = json!({ "type": "deactivate_domain", "domain": "Breached.Example", "reason": "Vendor offboarded" });
+
+        let preview: Value = test::call_and_read_body_json(&app, queue(deactivate.clone(), true)).await;
+        assert_eq!(preview["status"], "queued");
+        // Nothing to download until the runner gets to it
+        assert_eq!(test::call_service(&app, report(&preview, "json")).await.status(), StatusCode::BAD_REQUEST);
+
+        assert_eq!(ctx.auth_service.bulk_job_runner().run_queued().await.unwrap(), 1);
+        let previewed: Value = test::call_and_read_body_json(&app, report(&preview, "json")).await;
+        assert_eq!(previewed["job"]["summary"]["would_change"], 1);
+        assert_eq!(previewed["results"][0]["target"], "staff@breached.example");
+        assert!(ctx.db.find_user_by_id(staff.id()).await.unwrap().is_active());
+
+        let job: Value = test::call_and_read_body_json(&app, queue(deactivate, false)).await;
+        ctx.auth_service.bulk_job_runner().run_queued().await.unwrap();
+        let csv = test::call_and_read_body(&app, report(&job, "csv")).await;
+        let csv = String::from_utf8(csv.to_vec()).unwrap();
+        assert!(csv.contains(&format!("staff@breached.example,{},changed,", staff.id())), "{}", csv);
+        assert_eq!(ctx.db.find_user_by_id(staff.id()).await.unwrap().status, "suspended");
+        assert!(ctx.db.find_user_by_id(other.id()).await.unwrap().is_active());
+
+        // Breach list entries that match no account are reported, not dropped
+        let breach = json!({ "type": "force_password_reset", "accounts": [other.user.username, "nobody@example.com"] });
+        let job: Value = test::call_and_read_body_json(&app, queue(breach, false)).await;
+        ctx.auth_service.bulk_job_runner().run_queued().await.unwrap();
+        let reset: Value = test::call_and_read_body_json(&app, report(&job, "json")).await;
+        assert_eq!(reset["job"]["summary"]["changed"], 1);
+        assert_eq!(reset["job"]["summary"]["not_found"], 1);
+        assert!(ctx.db.find_user_by_id(other.id()).await.unwrap().password_expires_at.is_some());
+    }
+
+    #[actix_web::test]
+    async fn test_admins_see_the_startup_checks() {
+        let mut config = crate::test_utils::test_config();
+        config.database.url = "postgres://auth@db.internal/auth".to_string();
+        let ctx = TestContext::with_config(config);
+        let app = ctx.spawn_app().await;
+        let admin = ctx.user().admin().create().await.unwrap();
+        let user = ctx.user().create().await.unwrap();
+        let get_checks = |token: &str| {
+            test::TestRequest::get()
+                .uri("/admin/system/checks")
+                .insert_header(("Authorization", format!("Bearer {}", token)))
+                .to_request()
+        };
+
+        let response = test::call_service(&app, get_checks(&ctx.session(&user).create().await.unwrap().access_token)).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let report: Value =
+            test::call_and_read_body_json(&app, get_checks(&ctx.session(&admin).create().await.unwrap().access_token)).await;
+        let check = |name: &str| {
+            report["checks"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .find(|check| check["name"] == name)
+                .cloned()
+                .unwrap_or_else(|| panic!("no {} check", name))
+        };
+        assert!(check("jwt_secret")["status"].is_string());
+        // Tests send email to the log, which is fine locally but not in production
+        assert_eq!(check("insecure_defaults")["status"], "warning");
+        assert_eq!(check("smtp")["status"], "pass");
+        assert_eq!(check("migrations")["status"], "pass");
+        assert_eq!(report["production"], false);
+    }
+
+    #[actix_web::test]
+    async fn test_status_history_is_paged() {
+        let ctx = TestContext::new();
+        let app = ctx.spawn_app().await;
+        let admin = ctx.user().admin().create().await.unwrap();
+        let user = ctx.user().create().await.unwrap();
+        let bearer = ("Authorization", format!("Bearer {}", ctx.session(&admin).create().await.unwrap().access_token));
+        for (status, reason) in [(AccountStatus::Suspended, "Chargeback"), (AccountStatus::Active, "Resolved")] {
+            let event = NewOutboxEvent::new(EventType::StatusChanged, user.id(), json!({}));
+            ctx.db.set_account_status(user.id(), None, status, reason, Some(admin.id()), event).await.unwrap();
+        }
+
+        let request = test::TestRequest::get()
+            .uri(&format!("/admin/users/{}/status-history?limit=1", user.id()))
+            .insert_header(bearer)
+            .to_request();
+        let history: Value = test::call_and_read_body_json(&app, request).await;
+        assert_eq!(history["total"], 2);
+        assert_eq!(history["items"].as_array().unwrap().len(), 1);
+}
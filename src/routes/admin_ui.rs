@@ -0,0 +1,93 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+
+use crate::build_info::build_info;
+use crate::services::auth::AuthService;
+use crate::utils::http_cache;
+
+// The admin pages, served when `ADMIN_UI_ENABLED` is set. They hold no data,
+// so they load without a token, which a browser can't attach to a page it
+// opens; everything they show comes from the admin API behind
+// `AdminMiddleware`, called with the token the admin signs in for.
+const INDEX_HTML: &str = include_str!("../../admin-ui/index.html");
+const ADMIN_JS: &str = include_str!("../../admin-ui/admin.js");
+const ADMIN_CSS: &str = include_str!("../../admin-ui/admin.css");
+
+// Only these files may run or style the pages, and nothing may frame them
+const CONTENT_SECURITY_POLICY: &str = "default-src 'none'; script-src 'self'; style-src 'self'; \
+     connect-src 'self'; base-uri 'none'; form-action 'none'; frame-ancestors 'none'";
+
+// Registered ahead of `admin::configure`, whose `/admin` scope would otherwise
+// take these paths and ask for a token
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(web::scope("/admin/ui").service(index).service(asset));
+}
+
+#[actix_web::routes]
+#[get("")]
+#[get("/")]
+async fn index(req: HttpRequest, auth_service: web::Data<AuthService>) -> HttpResponse {
+    serve(&req, &auth_service, "text/html; charset=utf-8", INDEX_HTML)
+}
+
+#[actix_web::get("/{file}")]
+async fn asset(req: HttpRequest, auth_service: web::Data<AuthService>, file: web::Path<String>) -> HttpResponse {
+    match file.as_str() {
+        "admin.js" => serve(&req, &auth_service, "text/javascript; charset=utf-8", ADMIN_JS),
+        "admin.css" => serve(&req, &auth_service, "text/css; charset=utf-8", ADMIN_CSS),
+        _ => HttpResponse::NotFound().finish(),
+    }
+}
+
+fn serve(req: &HttpRequest, auth_service: &AuthService, content_type: &str, body: &'static str) -> HttpResponse {
+    if !auth_service.admin_ui_enabled() {
+        return HttpResponse::NotFound().finish();
+    }
+
+    http_cache::respond(
+        req,
+        HttpResponse::Ok()
+            .content_type(content_type)
+            // Revalidated, so a deploy's new pages are picked up at once
+            .insert_header(("Cache-Control", "no-cache"))
+            .insert_header(("Content-Security-Policy", CONTENT_SECURITY_POLICY))
+            .insert_header(("X-Content-Type-Options", "nosniff"))
+            .insert_header(("Referrer-Policy", "no-referrer")),
+        body,
+        build_info().built_at, // Built into the binary
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::StatusCode;
+    use actix_web::test;
+
+    use crate::test_utils::TestContext;
+
+    #[actix_web::test]
+    async fn test_admin_pages_are_served_only_when_enabled() {
+        let get = |path: &str| test::TestRequest::get().uri(path).to_request();
+
+        let ctx = TestContext::new();
+        let app = ctx.spawn_app().await;
+        let response = test::call_service(&app, get("/admin/ui")).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let mut config = crate::test_utils::test_config();
+        config.admin_ui.enabled = true;
+        let ctx = TestContext::with_config(config);
+        let app = ctx.spawn_app().await;
+
+        for path in ["/admin/ui", "/admin/ui/", "/admin/ui/admin.js", "/admin/ui/admin.css"] {
+            let response = test::call_service(&app, get(path)).await;
+            assert_eq!(response.status(), StatusCode::OK, "{}", path);
+            assert!(response.headers().contains_key("Content-Security-Policy"));
+        }
+        let response = test::call_service(&app, get("/admin/ui/secrets.txt")).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        // The pages load for anyone; the API behind them doesn't
+        let response = test::call_service(&app, get("/admin/users")).await;
+        assert!(response.status().is_client_error());
+    }
+}
@@ -1,47 +1,151 @@
 use actix_web::{web, HttpRequest, HttpResponse, Scope};
+use serde::Deserialize;
 use validator::Validate;
 
 use crate::errors::AuthError;
-use crate::middleware::auth::AuthenticatedUser;
+use crate::middleware::auth::{AuthenticatedUser, ScopedAuthMiddleware};
+use crate::middleware::idempotency::IdempotencyMiddleware;
+use crate::middleware::step_up::{StepUpMiddleware, StepUpPolicy};
+use crate::middleware::verified_email::RequireVerifiedEmail;
 use crate::models::{
-    DisableMfaRequest, EnableMfaRequest, LoginRequest, LogoutRequest, MfaLoginRequest,
-    MfaRecoveryRequest, PasswordResetConfirmRequest, PasswordResetRequest, RefreshTokenRequest,
-    RegisterRequest, VerifyEmailRequest, VerifyMfaRequest,
-    PasswordlessRegisterStartRequest, PasswordlessRegisterCompleteRequest,
-    PasswordlessLoginStartRequest, PasswordlessLoginCompleteRequest,
+    AcceptPolicyRequest, AccountLockRequest, AddTotpDeviceRequest, AppealRequest, ApproveLoginRequest,
+    CaptchaChallengeRequest, ChangePasswordRequest, ConfirmTotpDeviceRequest, DisableMfaRequest,
+    ActivateAccountRequest, EmailCodeStartRequest, EmailCodeVerifyRequest, EmailRegisterRequest, EnableMfaRequest, GuestRequest, LoginRequest, LogoutRequest, MfaLoginRequest,
+    MfaMethodOrderRequest, MfaRecoveryCodesResponse, MfaRecoveryRequest, OidcCallbackQuery, PasskeyEnrollStartRequest, PasswordResetConfirmRequest,
+    PasswordResetRequest, LockAccountRequest, ReactivateAccountRequest, ReauthenticateRequest, RefreshTokenRequest, RegisterRequest,
+    LinkedPreferencesRequest, NotificationLinkRequest, SamlAcsForm, SecurityQuestionRecoveryRequest, SsoDiscoverRequest, UpgradeGuestRequest, VerifyBackupEmailRequest,
+    VerifyEmailRequest, VerifyMfaRequest, PasswordlessRegisterStartRequest,
+    PasswordlessRegisterCompleteRequest, PasswordlessLoginStartRequest,
+    PasswordlessLoginCompleteRequest, TrustedDeviceLoginRequest,
 };
 use crate::services::auth::AuthService;
+use crate::services::feature_flags::{Flags, PASSWORDLESS_LOGIN};
+use crate::services::mfa::QrFormat;
+use crate::utils::dpop;
+use crate::utils::i18n::Locale;
+use crate::utils::negotiate;
+use crate::utils::jwt::TokenScope;
 
 pub fn configure(cfg: &mut web::ServiceConfig) {
     cfg.service(
         web::scope("/auth")
+            .service(issue_captcha)
+            .service(captcha_audio)
+            .service(voice_command)
             .service(register)
+            .service(register_with_email)
+            .service(activate_account)
+            .service(reactivate_account)
+            .service(account_lock_request)
+            .service(lock_account)
+            .service(create_guest)
+            .service(upgrade_guest)
             .service(login)
             .service(mfa_login)
+            .service(approve_login)
+            .service(complete_login_approval)
+            .service(start_email_code_login)
+            .service(verify_email_code_login)
+            .service(trusted_device_login)
+            .service(accept_policy)
+            .service(sso_discover)
+            .service(sso_callback)
+            .service(sso_saml_acs)
             .service(refresh_token)
             .service(logout)
             .service(logout_all)
+            .service(reauthenticate)
             .service(verify_email)
+            .service(verify_backup_email)
+            .service(linked_notification_preferences)
+            .service(update_linked_notification_preferences)
+            .service(unsubscribe)
             .service(resend_verification_email)
             .service(password_reset)
             .service(password_reset_confirm)
+            .service(change_password)
+            .service(account_status)
+            .service(appeal_account_status)
             .service(mfa_setup)
+            .service(mfa_setup_qr_png)
+            .service(mfa_setup_qr_svg)
             .service(mfa_enable)
             .service(mfa_disable)
             .service(mfa_recovery_codes)
+            .service(recovery_code_status)
+            .service(list_totp_devices)
+            .service(add_totp_device)
+            .service(confirm_totp_device)
+            .service(remove_totp_device)
             .service(mfa_verify)
             .service(mfa_recovery)
+            .service(pending_security_questions)
+            .service(security_question_recovery)
+            .service(mfa_methods)
+            .service(set_mfa_method_order)
+            .service(mfa_passkey_start)
+            .service(mfa_passkey_complete)
             .service(passwordless_register_start)
             .service(passwordless_register_complete)
             .service(passwordless_login_start)
-            .service(passwordless_login_complete),
+            .service(passwordless_login_complete)
+            .service(passkey_enroll_start)
+            .service(passkey_enroll_complete)
+            .service(dismiss_passkey_prompt),
     );
 }
 
-#[actix_web::post("/register")]
+/// Issue a CAPTCHA, picked from the account's accessibility preferences when known
+#[actix_web::post("/captcha")]
+async fn issue_captcha(
+    auth_service: web::Data<AuthService>,
+    challenge_data: web::Json<CaptchaChallengeRequest>,
+) -> Result<HttpResponse, AuthError> {
+    challenge_data.validate()?;
+    
+    let challenge = auth_service.issue_captcha(challenge_data.into_inner()).await?;
+    
+    Ok(HttpResponse::Ok().json(challenge))
+}
+
+#[actix_web::get("/captcha/{captcha_id}/audio")]
+async fn captcha_audio(
+    auth_service: web::Data<AuthService>,
+    captcha_id: web::Path<uuid::Uuid>,
+) -> Result<HttpResponse, AuthError> {
+    let audio = auth_service.captcha_audio(*captcha_id)?;
+    
+    Ok(HttpResponse::Ok()
+        .content_type("audio/wav")
+        .insert_header(("Cache-Control", "no-store"))
+        .body(audio))
+}
+
+/// Resolve an uploaded voice recording (WAV, FLAC, ...) to an auth intent
+#[actix_web::post("/voice-command")]
+async fn voice_command(
+    auth_service: web::Data<AuthService>,
+    audio: web::Bytes,
+    req: HttpRequest,
+) -> Result<HttpResponse, AuthError> {
+    if audio.is_empty() {
+        return Err(AuthError::ValidationError("Audio is required".into()));
+    }
+    
+    let content_type = req.headers().get("Content-Type")
+        .and_then(|h| h.to_str().ok())
+        .unwrap_or("application/octet-stream");
+    
+    let command = auth_service.recognize_voice_command(&audio, content_type).await?;
+    
+    Ok(HttpResponse::Ok().json(command))
+}
+
+#[actix_web::post("/register", wrap = "IdempotencyMiddleware")]
 async fn register(
     auth_service: web::Data<AuthService>,
     register_data: web::Json<RegisterRequest>,
+    locale: web::ReqData<Locale>,
     req: HttpRequest,
 ) -> Result<HttpResponse, AuthError> {
     register_data.validate()?;
@@ -54,200 +158,267 @@ async fn register(
         .map(|s| s.to_string());
     
     let response = auth_service
-        .register(register_data.into_inner(), ip, user_agent)
+        .register(register_data.into_inner(), ip, user_agent, &locale.0)
         .await?;
     
     Ok(HttpResponse::Created().json(response))
 }
 
-#[actix_web::post("/login")]
-async fn login(
+/// Sign up with an email address; the password is chosen from the activation link
+#[actix_web::post("/register/email")]
+async fn register_with_email(
     auth_service: web::Data<AuthService>,
-    login_data: web::Json<LoginRequest>,
-    req: HttpRequest,
+    register_data: web::Json<EmailRegisterRequest>,
+    locale: web::ReqData<Locale>,
 ) -> Result<HttpResponse, AuthError> {
-    login_data.validate()?;
-    
-    let ip = req.connection_info().realip_remote_addr()
-        .map(|s| s.to_string());
-    
-    let user_agent = req.headers().get("User-Agent")
-        .and_then(|h| h.to_str().ok())
-        .map(|s| s.to_string());
+    register_data.validate()?;
     
     let response = auth_service
-        .login(login_data.into_inner(), ip, user_agent)
+        .register_with_email(register_data.into_inner(), &locale.0)
         .await?;
     
-    Ok(HttpResponse::Ok().json(response))
+    Ok(HttpResponse::Created().json(response))
 }
 
-#[actix_web::post("/mfa-login")]
-async fn mfa_login(
+/// Set the first password of an invited or email-only account
+#[actix_web::post("/activate", wrap = "IdempotencyMiddleware")]
+async fn activate_account(
     auth_service: web::Data<AuthService>,
-    login_data: web::Json<MfaLoginRequest>,
-    req: HttpRequest,
+    activate_data: web::Json<ActivateAccountRequest>,
+    locale: web::ReqData<Locale>,
 ) -> Result<HttpResponse, AuthError> {
-    let ip = req.connection_info().realip_remote_addr()
-        .map(|s| s.to_string());
-    
-    let user_agent = req.headers().get("User-Agent")
-        .and_then(|h| h.to_str().ok())
-        .map(|s| s.to_string());
+    activate_data.validate()?;
     
     let response = auth_service
-        .mfa_login(login_data.into_inner(), ip, user_agent)
+        .activate_account(activate_data.into_inner(), &locale.0)
         .await?;
     
     Ok(HttpResponse::Ok().json(response))
 }
 
-#[actix_web::post("/refresh-token")]
-async fn refresh_token(
+/// Bring back an archived account from the link emailed at its last login
+#[actix_web::post("/reactivate", wrap = "IdempotencyMiddleware")]
+async fn reactivate_account(
     auth_service: web::Data<AuthService>,
-    refresh_data: web::Json<RefreshTokenRequest>,
-    req: HttpRequest,
+    reactivate_data: web::Json<ReactivateAccountRequest>,
+    locale: web::ReqData<Locale>,
 ) -> Result<HttpResponse, AuthError> {
-    let ip = req.connection_info().realip_remote_addr()
-        .map(|s| s.to_string());
-    
-    let user_agent = req.headers().get("User-Agent")
-        .and_then(|h| h.to_str().ok())
-        .map(|s| s.to_string());
+    reactivate_data.validate()?;
     
     let response = auth_service
-        .refresh_token(refresh_data.into_inner(), ip, user_agent)
+        .reactivate_account(reactivate_data.into_inner(), &locale.0)
         .await?;
     
     Ok(HttpResponse::Ok().json(response))
 }
 
-#[actix_web::post("/logout")]
-async fn logout(
+/// Email a link that locks the account, for owners who can't sign in to it
+#[actix_web::post("/lock-account/request", wrap = "IdempotencyMiddleware")]
+async fn account_lock_request(
     auth_service: web::Data<AuthService>,
-    user: Option<web::ReqData<AuthenticatedUser>>,
-    logout_data: web::Json<LogoutRequest>,
+    request_data: web::Json<AccountLockRequest>,
+    locale: web::ReqData<Locale>,
 ) -> Result<HttpResponse, AuthError> {
-    let user_id = user.map(|u| u.user_id);
+    request_data.validate()?;
     
     let response = auth_service
-        .logout(logout_data.into_inner(), user_id)
+        .account_lock_request(request_data.into_inner(), &locale.0)
         .await?;
     
     Ok(HttpResponse::Ok().json(response))
 }
 
-#[actix_web::post("/logout-all")]
-async fn logout_all(
+/// Lock the account from the emailed link
+#[actix_web::post("/lock-account", wrap = "IdempotencyMiddleware")]
+async fn lock_account(
     auth_service: web::Data<AuthService>,
-    user: web::ReqData<AuthenticatedUser>,
+    lock_data: web::Json<LockAccountRequest>,
+    locale: web::ReqData<Locale>,
 ) -> Result<HttpResponse, AuthError> {
+    lock_data.validate()?;
+    
     let response = auth_service
-        .logout_all(user.user_id)
+        .lock_account_from_link(lock_data.into_inner(), &locale.0)
         .await?;
     
     Ok(HttpResponse::Ok().json(response))
 }
 
-#[actix_web::post("/verify-email")]
-async fn verify_email(
+/// Start an anonymous guest session, when enabled
+#[actix_web::post("/guest")]
+async fn create_guest(
     auth_service: web::Data<AuthService>,
-    verify_data: web::Json<VerifyEmailRequest>,
+    guest_data: web::Json<GuestRequest>,
+    req: HttpRequest,
 ) -> Result<HttpResponse, AuthError> {
+    guest_data.validate()?;
+    
+    let ip = req.connection_info().realip_remote_addr()
+        .map(|s| s.to_string());
+    
+    let user_agent = req.headers().get("User-Agent")
+        .and_then(|h| h.to_str().ok())
+        .map(|s| s.to_string());
+    
     let response = auth_service
-        .verify_email(verify_data.into_inner())
+        .create_guest(guest_data.into_inner(), ip, user_agent)
         .await?;
     
-    Ok(HttpResponse::Ok().json(response))
+    Ok(HttpResponse::Created().json(response))
 }
 
-#[actix_web::post("/resend-verification-email")]
-async fn resend_verification_email(
+/// Register the guest account the token belongs to, keeping its user ID
+#[actix_web::post("/guest/upgrade", wrap = "ScopedAuthMiddleware(&[TokenScope::Guest])")]
+async fn upgrade_guest(
     auth_service: web::Data<AuthService>,
     user: web::ReqData<AuthenticatedUser>,
+    upgrade_data: web::Json<UpgradeGuestRequest>,
+    locale: web::ReqData<Locale>,
 ) -> Result<HttpResponse, AuthError> {
+    upgrade_data.validate()?;
+    
     let response = auth_service
-        .resend_verification_email(user.user_id)
+        .upgrade_guest(user.user_id, upgrade_data.into_inner(), &locale.0)
         .await?;
     
     Ok(HttpResponse::Ok().json(response))
 }
 
-#[actix_web::post("/password-reset")]
-async fn password_reset(
+#[actix_web::post("/login")]
+async fn login(
     auth_service: web::Data<AuthService>,
-    reset_data: web::Json<PasswordResetRequest>,
+    login_data: web::Json<LoginRequest>,
+    locale: web::ReqData<Locale>,
+    req: HttpRequest,
 ) -> Result<HttpResponse, AuthError> {
-    reset_data.validate()?;
+    login_data.validate()?;
+    
+    let ip = req.connection_info().realip_remote_addr()
+        .map(|s| s.to_string());
+    
+    let user_agent = req.headers().get("User-Agent")
+        .and_then(|h| h.to_str().ok())
+        .map(|s| s.to_string());
+    
+    let dpop_key = dpop_key(&auth_service, &req)?;
+    let network = auth_service.network_fingerprint(req.headers());
     
     let response = auth_service
-        .password_reset_request(reset_data.into_inner())
+        .login(login_data.into_inner(), ip, user_agent, dpop_key, network, &locale.0)
         .await?;
     
     Ok(HttpResponse::Ok().json(response))
 }
 
-#[actix_web::post("/password-reset-confirm")]
-async fn password_reset_confirm(
+#[actix_web::post("/mfa-login")]
+async fn mfa_login(
     auth_service: web::Data<AuthService>,
-    confirm_data: web::Json<PasswordResetConfirmRequest>,
+    login_data: web::Json<MfaLoginRequest>,
+    locale: web::ReqData<Locale>,
+    req: HttpRequest,
 ) -> Result<HttpResponse, AuthError> {
-    confirm_data.validate()?;
+    login_data.validate()?;
+    
+    let ip = req.connection_info().realip_remote_addr()
+        .map(|s| s.to_string());
+    
+    let user_agent = req.headers().get("User-Agent")
+        .and_then(|h| h.to_str().ok())
+        .map(|s| s.to_string());
+    
+    let dpop_key = dpop_key(&auth_service, &req)?;
+    let network = auth_service.network_fingerprint(req.headers());
     
     let response = auth_service
-        .password_reset_confirm(confirm_data.into_inner())
+        .mfa_login(login_data.into_inner(), ip, user_agent, dpop_key, network, &locale.0)
         .await?;
     
     Ok(HttpResponse::Ok().json(response))
 }
 
-#[actix_web::get("/mfa-setup")]
-async fn mfa_setup(
+/// Approve a held high-risk login with the token from the approval email
+#[actix_web::post("/login-approval/approve")]
+async fn approve_login(
     auth_service: web::Data<AuthService>,
-    user: web::ReqData<AuthenticatedUser>,
+    approve_data: web::Json<ApproveLoginRequest>,
 ) -> Result<HttpResponse, AuthError> {
-    let response = auth_service.mfa_setup(user.user_id).await?;
+    approve_data.validate()?;
+    
+    let response = auth_service.approve_login(approve_data.into_inner()).await?;
     
     Ok(HttpResponse::Ok().json(response))
 }
 
-#[actix_web::post("/mfa-enable")]
-async fn mfa_enable(
+/// Polled by the held client; succeeds once the login has been approved
+#[actix_web::post("/login-approval/{approval_id}/complete")]
+async fn complete_login_approval(
     auth_service: web::Data<AuthService>,
-    user: web::ReqData<AuthenticatedUser>,
-    enable_data: web::Json<EnableMfaRequest>,
+    approval_id: web::Path<uuid::Uuid>,
+    locale: web::ReqData<Locale>,
 ) -> Result<HttpResponse, AuthError> {
-    enable_data.validate()?;
+    let response = auth_service.complete_login_approval(*approval_id, &locale.0).await?;
+    
+    Ok(HttpResponse::Ok().json(response))
+}
+
+/// Email a 6-digit sign-in code; answers the same for unknown addresses
+#[actix_web::post("/email-code")]
+async fn start_email_code_login(
+    auth_service: web::Data<AuthService>,
+    start_data: web::Json<EmailCodeStartRequest>,
+    locale: web::ReqData<Locale>,
+    req: HttpRequest,
+) -> Result<HttpResponse, AuthError> {
+    start_data.validate()?;
+    
+    let ip = req.connection_info().realip_remote_addr()
+        .map(|s| s.to_string());
+    
+    let user_agent = req.headers().get("User-Agent")
+        .and_then(|h| h.to_str().ok())
+        .map(|s| s.to_string());
     
     let response = auth_service
-        .mfa_enable(user.user_id, enable_data.into_inner())
+        .start_email_code_login(start_data.into_inner(), ip, user_agent, &locale.0)
         .await?;
     
     Ok(HttpResponse::Ok().json(response))
 }
 
-#[actix_web::post("/mfa-disable")]
-async fn mfa_disable(
+#[actix_web::post("/email-code/verify")]
+async fn verify_email_code_login(
     auth_service: web::Data<AuthService>,
-    user: web::ReqData<AuthenticatedUser>,
-    disable_data: web::Json<DisableMfaRequest>,
+    verify_data: web::Json<EmailCodeVerifyRequest>,
+    locale: web::ReqData<Locale>,
+    req: HttpRequest,
 ) -> Result<HttpResponse, AuthError> {
-    disable_data.validate()?;
+    verify_data.validate()?;
+    
+    let ip = req.connection_info().realip_remote_addr()
+        .map(|s| s.to_string());
+    
+    let user_agent = req.headers().get("User-Agent")
+        .and_then(|h| h.to_str().ok())
+        .map(|s| s.to_string());
+    
+    let network = auth_service.network_fingerprint(req.headers());
     
     let response = auth_service
-        .mfa_disable(user.user_id, disable_data.into_inner())
+        .verify_email_code_login(verify_data.into_inner(), ip, user_agent, network, &locale.0)
         .await?;
     
     Ok(HttpResponse::Ok().json(response))
 }
 
-#[actix_web::post("/mfa-verify")]
-async fn mfa_verify(
+/// Sign in without a code from a device trusted at an earlier email code login
+#[actix_web::post("/email-code/trusted-device")]
+async fn trusted_device_login(
     auth_service: web::Data<AuthService>,
-    verify_data: web::Json<VerifyMfaRequest>,
+    login_data: web::Json<TrustedDeviceLoginRequest>,
+    locale: web::ReqData<Locale>,
     req: HttpRequest,
 ) -> Result<HttpResponse, AuthError> {
-    verify_data.validate()?;
+    login_data.validate()?;
     
     let ip = req.connection_info().realip_remote_addr()
         .map(|s| s.to_string());
@@ -256,31 +427,64 @@ async fn mfa_verify(
         .and_then(|h| h.to_str().ok())
         .map(|s| s.to_string());
     
+    let network = auth_service.network_fingerprint(req.headers());
+    
     let response = auth_service
-        .mfa_verify(verify_data.into_inner(), ip, user_agent)
+        .trusted_device_login(login_data.into_inner(), ip, user_agent, network, &locale.0)
         .await?;
     
     Ok(HttpResponse::Ok().json(response))
 }
 
-#[actix_web::get("/mfa-recovery-codes")]
-async fn mfa_recovery_codes(
+/// Continues a login that came back with `policy_acceptance_required`
+#[actix_web::post(
+    "/accept-policy",
+    wrap = "ScopedAuthMiddleware(&[TokenScope::PolicyAcceptance])"
+)]
+async fn accept_policy(
     auth_service: web::Data<AuthService>,
     user: web::ReqData<AuthenticatedUser>,
+    accept_data: web::Json<AcceptPolicyRequest>,
+    req: HttpRequest,
 ) -> Result<HttpResponse, AuthError> {
-    let response = auth_service.mfa_recovery_codes(user.user_id).await?;
+    accept_data.validate()?;
+    
+    let ip = req.connection_info().realip_remote_addr()
+        .map(|s| s.to_string());
+    
+    let user_agent = req.headers().get("User-Agent")
+        .and_then(|h| h.to_str().ok())
+        .map(|s| s.to_string());
+    
+    let response = auth_service
+        .accept_policy(user.user_id, &user.amr, accept_data.into_inner(), ip, user_agent)
+        .await?;
     
     Ok(HttpResponse::Ok().json(response))
 }
 
-#[actix_web::post("/mfa-recovery")]
-async fn mfa_recovery(
+/// Check whether an email address signs in through its organization's IdP,
+/// returning the URL to start that login at
+#[actix_web::post("/sso/discover")]
+async fn sso_discover(
     auth_service: web::Data<AuthService>,
-    recovery_data: web::Json<MfaRecoveryRequest>,
-    req: HttpRequest,
+    discover_data: web::Json<SsoDiscoverRequest>,
 ) -> Result<HttpResponse, AuthError> {
-    recovery_data.validate()?;
+    discover_data.validate()?;
     
+    let response = auth_service.sso_discover(discover_data.into_inner()).await?;
+    
+    Ok(HttpResponse::Ok().json(response))
+}
+
+/// OIDC redirect back from the IdP
+#[actix_web::get("/sso/callback")]
+async fn sso_callback(
+    auth_service: web::Data<AuthService>,
+    query: web::Query<OidcCallbackQuery>,
+    locale: web::ReqData<Locale>,
+    req: HttpRequest,
+) -> Result<HttpResponse, AuthError> {
     let ip = req.connection_info().realip_remote_addr()
         .map(|s| s.to_string());
     
@@ -288,35 +492,49 @@ async fn mfa_recovery(
         .and_then(|h| h.to_str().ok())
         .map(|s| s.to_string());
     
+    let network = auth_service.network_fingerprint(req.headers());
+    
     let response = auth_service
-        .mfa_recovery(recovery_data.into_inner(), ip, user_agent)
+        .sso_oidc_callback(query.into_inner(), ip, user_agent, network, &locale.0)
         .await?;
     
     Ok(HttpResponse::Ok().json(response))
 }
 
-/// Start passwordless registration process
-#[actix_web::post("/passwordless-register-start")]
-async fn passwordless_register_start(
+/// SAML assertion consumer service (HTTP-POST binding)
+#[actix_web::post("/sso/saml/acs")]
+async fn sso_saml_acs(
     auth_service: web::Data<AuthService>,
-    register_data: web::Json<PasswordlessRegisterStartRequest>,
+    form: web::Form<SamlAcsForm>,
+    locale: web::ReqData<Locale>,
+    req: HttpRequest,
 ) -> Result<HttpResponse, AuthError> {
-    register_data.validate()?;
+    let ip = req.connection_info().realip_remote_addr()
+        .map(|s| s.to_string());
+    
+    let user_agent = req.headers().get("User-Agent")
+        .and_then(|h| h.to_str().ok())
+        .map(|s| s.to_string());
+    
+    let network = auth_service.network_fingerprint(req.headers());
     
     let response = auth_service
-        .passwordless_register_start(register_data.into_inner())
+        .sso_saml_acs(form.into_inner(), ip, user_agent, network, &locale.0)
         .await?;
     
     Ok(HttpResponse::Ok().json(response))
 }
 
-/// Complete passwordless registration
-#[actix_web::post("/passwordless-register-complete")]
-async fn passwordless_register_complete(
+/// Answers in MessagePack for `Accept: application/msgpack`, which mobile
+/// SDKs refreshing often use to save bandwidth
+#[actix_web::post("/refresh-token")]
+async fn refresh_token(
     auth_service: web::Data<AuthService>,
-    register_data: web::Json<PasswordlessRegisterCompleteRequest>,
+    refresh_data: web::Json<RefreshTokenRequest>,
     req: HttpRequest,
 ) -> Result<HttpResponse, AuthError> {
+    refresh_data.validate()?;
+    
     let ip = req.connection_info().realip_remote_addr()
         .map(|s| s.to_string());
     
@@ -324,45 +542,1768 @@ async fn passwordless_register_complete(
         .and_then(|h| h.to_str().ok())
         .map(|s| s.to_string());
     
+    let dpop_key = dpop_key(&auth_service, &req)?;
+    let network = auth_service.network_fingerprint(req.headers());
+    
     let response = auth_service
-        .passwordless_register_complete(register_data.into_inner(), ip, user_agent)
+        .refresh_token(refresh_data.into_inner(), ip, user_agent, dpop_key, network)
         .await?;
     
-    Ok(HttpResponse::Created().json(response))
+    negotiate::respond(&req, &mut HttpResponse::Ok(), &response)
 }
 
-/// Start passwordless login process
-#[actix_web::post("/passwordless-login-start")]
-async fn passwordless_login_start(
+#[actix_web::post("/logout")]
+async fn logout(
     auth_service: web::Data<AuthService>,
-    login_data: web::Json<PasswordlessLoginStartRequest>,
+    user: Option<web::ReqData<AuthenticatedUser>>,
+    logout_data: web::Json<LogoutRequest>,
 ) -> Result<HttpResponse, AuthError> {
-    login_data.validate()?;
+    logout_data.validate()?;
+    
+    let user_id = user.map(|u| u.user_id);
     
     let response = auth_service
-        .passwordless_login_start(login_data.into_inner())
+        .logout(logout_data.into_inner(), user_id)
         .await?;
     
     Ok(HttpResponse::Ok().json(response))
 }
 
-/// Complete passwordless login
-#[actix_web::post("/passwordless-login-complete")]
-async fn passwordless_login_complete(
+#[actix_web::post("/logout-all", wrap = "ScopedAuthMiddleware(&[TokenScope::Full])")]
+async fn logout_all(
     auth_service: web::Data<AuthService>,
-    login_data: web::Json<PasswordlessLoginCompleteRequest>,
+    user: web::ReqData<AuthenticatedUser>,
+) -> Result<HttpResponse, AuthError> {
+    let response = auth_service
+        .logout_all(user.user_id)
+        .await?;
+    
+    Ok(HttpResponse::Ok().json(response))
+}
+
+#[actix_web::post("/reauthenticate", wrap = "ScopedAuthMiddleware(&[TokenScope::Full])")]
+async fn reauthenticate(
+    auth_service: web::Data<AuthService>,
+    user: web::ReqData<AuthenticatedUser>,
+    reauth_data: web::Json<ReauthenticateRequest>,
     req: HttpRequest,
 ) -> Result<HttpResponse, AuthError> {
+    reauth_data.validate()?;
+    
     let ip = req.connection_info().realip_remote_addr()
         .map(|s| s.to_string());
     
-    let user_agent = req.headers().get("User-Agent")
-        .and_then(|h| h.to_str().ok())
-        .map(|s| s.to_string());
+    let response = auth_service
+        .reauthenticate(user.user_id, reauth_data.into_inner(), ip)
+        .await?;
+    
+    Ok(HttpResponse::Ok().json(response))
+}
+
+#[actix_web::post("/verify-email")]
+async fn verify_email(
+    auth_service: web::Data<AuthService>,
+    verify_data: web::Json<VerifyEmailRequest>,
+) -> Result<HttpResponse, AuthError> {
+    verify_data.validate()?;
     
     let response = auth_service
-        .passwordless_login_complete(login_data.into_inner(), ip, user_agent)
+        .verify_email(verify_data.into_inner())
         .await?;
     
     Ok(HttpResponse::Ok().json(response))
 }
+
+// Followed from the emailed link, so no login is needed
+#[actix_web::post("/verify-backup-email")]
+async fn verify_backup_email(
+    auth_service: web::Data<AuthService>,
+    verify_data: web::Json<VerifyBackupEmailRequest>,
+) -> Result<HttpResponse, AuthError> {
+    verify_data.validate()?;
+    
+    let response = auth_service
+        .verify_backup_email(verify_data.into_inner())
+        .await?;
+    
+    Ok(HttpResponse::Ok().json(response))
+}
+
+// The unsubscribe link in an email stands in for a login on the preference
+// page. The token goes in the body so it stays out of request logs.
+#[actix_web::post("/notification-preferences")]
+async fn linked_notification_preferences(
+    auth_service: web::Data<AuthService>,
+    link_data: web::Json<NotificationLinkRequest>,
+) -> Result<HttpResponse, AuthError> {
+    link_data.validate()?;
+    
+    let response = auth_service
+        .linked_notification_preferences(link_data.into_inner())
+        .await?;
+    
+    Ok(HttpResponse::Ok().json(response))
+}
+
+#[actix_web::put("/notification-preferences")]
+async fn update_linked_notification_preferences(
+    auth_service: web::Data<AuthService>,
+    preferences_data: web::Json<LinkedPreferencesRequest>,
+) -> Result<HttpResponse, AuthError> {
+    preferences_data.validate()?;
+    
+    let response = auth_service
+        .update_linked_notification_preferences(preferences_data.into_inner())
+        .await?;
+    
+    Ok(HttpResponse::Ok().json(response))
+}
+
+/// One-click unsubscribe from the category the link was sent for
+#[actix_web::post("/unsubscribe")]
+async fn unsubscribe(
+    auth_service: web::Data<AuthService>,
+    link_data: web::Json<NotificationLinkRequest>,
+) -> Result<HttpResponse, AuthError> {
+    link_data.validate()?;
+    
+    let response = auth_service.unsubscribe(link_data.into_inner()).await?;
+    
+    Ok(HttpResponse::Ok().json(response))
+}
+
+#[actix_web::post(
+    "/resend-verification-email",
+    wrap = "ScopedAuthMiddleware(&[TokenScope::Full, TokenScope::EmailUnverified])"
+)]
+async fn resend_verification_email(
+    auth_service: web::Data<AuthService>,
+    user: web::ReqData<AuthenticatedUser>,
+    locale: web::ReqData<Locale>,
+) -> Result<HttpResponse, AuthError> {
+    let response = auth_service
+        .resend_verification_email(user.user_id, &locale.0)
+        .await?;
+    
+    Ok(HttpResponse::Ok().json(response))
+}
+
+#[actix_web::post("/password-reset", wrap = "IdempotencyMiddleware")]
+async fn password_reset(
+    auth_service: web::Data<AuthService>,
+    reset_data: web::Json<PasswordResetRequest>,
+    locale: web::ReqData<Locale>,
+) -> Result<HttpResponse, AuthError> {
+    reset_data.validate()?;
+    
+    let response = auth_service
+        .password_reset_request(reset_data.into_inner(), &locale.0)
+        .await?;
+    
+    Ok(HttpResponse::Ok().json(response))
+}
+
+#[actix_web::post("/password-reset-confirm", wrap = "IdempotencyMiddleware")]
+async fn password_reset_confirm(
+    auth_service: web::Data<AuthService>,
+    confirm_data: web::Json<PasswordResetConfirmRequest>,
+) -> Result<HttpResponse, AuthError> {
+    confirm_data.validate()?;
+    
+    let response = auth_service
+        .password_reset_confirm(confirm_data.into_inner())
+        .await?;
+    
+    Ok(HttpResponse::Ok().json(response))
+}
+
+// Also accepts the limited token handed out when a login hits an expired password
+#[actix_web::post(
+    "/change-password",
+    wrap = "ScopedAuthMiddleware(&[TokenScope::Full, TokenScope::PasswordReset])"
+)]
+async fn change_password(
+    auth_service: web::Data<AuthService>,
+    user: web::ReqData<AuthenticatedUser>,
+    change_data: web::Json<ChangePasswordRequest>,
+) -> Result<HttpResponse, AuthError> {
+    change_data.validate()?;
+    
+    let response = auth_service
+        .change_password(user.user_id, change_data.into_inner())
+        .await?;
+    
+    Ok(HttpResponse::Ok().json(response))
+}
+
+// Suspended and banned users get an `account_status` token from their failed login
+#[actix_web::get(
+    "/account-status",
+    wrap = "ScopedAuthMiddleware(&[TokenScope::Full, TokenScope::AccountStatus])"
+)]
+async fn account_status(
+    auth_service: web::Data<AuthService>,
+    user: web::ReqData<AuthenticatedUser>,
+) -> Result<HttpResponse, AuthError> {
+    let response = auth_service.get_account_status(user.user_id).await?;
+    
+    Ok(HttpResponse::Ok().json(response))
+}
+
+#[actix_web::post(
+    "/account-status/appeal",
+    wrap = "ScopedAuthMiddleware(&[TokenScope::AccountStatus])"
+)]
+async fn appeal_account_status(
+    auth_service: web::Data<AuthService>,
+    user: web::ReqData<AuthenticatedUser>,
+    appeal_data: web::Json<AppealRequest>,
+) -> Result<HttpResponse, AuthError> {
+    appeal_data.validate()?;
+    
+    let response = auth_service
+        .submit_appeal(user.user_id, appeal_data.into_inner())
+        .await?;
+    
+    Ok(HttpResponse::Created().json(response))
+}
+
+// Also accepts the limited token handed out when the account risk score reset MFA
+#[actix_web::get(
+    "/mfa-setup",
+    wrap = "RequireVerifiedEmail",
+    wrap = "ScopedAuthMiddleware(&[TokenScope::Full, TokenScope::MfaEnrollment])"
+)]
+async fn mfa_setup(
+    auth_service: web::Data<AuthService>,
+    user: web::ReqData<AuthenticatedUser>,
+) -> Result<HttpResponse, AuthError> {
+    let response = auth_service.mfa_setup(user.user_id).await?;
+    
+    Ok(HttpResponse::Ok().json(response))
+}
+
+#[actix_web::get(
+    "/mfa-setup/qr.png",
+    wrap = "RequireVerifiedEmail",
+    wrap = "ScopedAuthMiddleware(&[TokenScope::Full, TokenScope::MfaEnrollment])"
+)]
+async fn mfa_setup_qr_png(
+    auth_service: web::Data<AuthService>,
+    user: web::ReqData<AuthenticatedUser>,
+) -> Result<HttpResponse, AuthError> {
+    mfa_setup_qr(&auth_service, user.user_id, QrFormat::Png).await
+}
+
+#[actix_web::get(
+    "/mfa-setup/qr.svg",
+    wrap = "RequireVerifiedEmail",
+    wrap = "ScopedAuthMiddleware(&[TokenScope::Full, TokenScope::MfaEnrollment])"
+)]
+async fn mfa_setup_qr_svg(
+    auth_service: web::Data<AuthService>,
+    user: web::ReqData<AuthenticatedUser>,
+) -> Result<HttpResponse, AuthError> {
+    mfa_setup_qr(&auth_service, user.user_id, QrFormat::Svg).await
+}
+
+// The image encodes the TOTP secret, so it must never be cached
+async fn mfa_setup_qr(
+    auth_service: &AuthService,
+    user_id: uuid::Uuid,
+    format: QrFormat,
+) -> Result<HttpResponse, AuthError> {
+    let image = auth_service.mfa_setup_qr_code(user_id, format).await?;
+    
+    Ok(HttpResponse::Ok()
+        .content_type(format.content_type())
+        .insert_header(("Cache-Control", "no-store"))
+        .body(image))
+}
+
+#[actix_web::post(
+    "/mfa-enable",
+    wrap = "RequireVerifiedEmail",
+    wrap = "ScopedAuthMiddleware(&[TokenScope::Full, TokenScope::MfaEnrollment])"
+)]
+async fn mfa_enable(
+    auth_service: web::Data<AuthService>,
+    user: web::ReqData<AuthenticatedUser>,
+    enable_data: web::Json<EnableMfaRequest>,
+) -> Result<HttpResponse, AuthError> {
+    enable_data.validate()?;
+    
+    let response = auth_service
+        .mfa_enable(user.user_id, enable_data.into_inner())
+        .await?;
+    
+    Ok(HttpResponse::Ok().json(response))
+}
+
+#[actix_web::post(
+    "/mfa-disable",
+    wrap = "StepUpMiddleware(StepUpPolicy::password_within(300))",
+    wrap = "ScopedAuthMiddleware(&[TokenScope::Full])"
+)]
+async fn mfa_disable(
+    auth_service: web::Data<AuthService>,
+    user: web::ReqData<AuthenticatedUser>,
+    disable_data: web::Json<DisableMfaRequest>,
+) -> Result<HttpResponse, AuthError> {
+    disable_data.validate()?;
+    
+    let response = auth_service
+        .mfa_disable(user.user_id, disable_data.into_inner())
+        .await?;
+    
+    Ok(HttpResponse::Ok().json(response))
+}
+
+#[actix_web::post("/mfa-verify", wrap = "ScopedAuthMiddleware(&[TokenScope::MfaPending])")]
+async fn mfa_verify(
+    auth_service: web::Data<AuthService>,
+    user: web::ReqData<AuthenticatedUser>,
+    verify_data: web::Json<VerifyMfaRequest>,
+    req: HttpRequest,
+) -> Result<HttpResponse, AuthError> {
+    verify_data.validate()?;
+    
+    let ip = req.connection_info().realip_remote_addr()
+        .map(|s| s.to_string());
+    
+    let user_agent = req.headers().get("User-Agent")
+        .and_then(|h| h.to_str().ok())
+        .map(|s| s.to_string());
+    
+    let response = auth_service
+        .mfa_verify(user.user_id, verify_data.into_inner(), ip, user_agent)
+        .await?;
+    
+    Ok(HttpResponse::Ok().json(response))
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum RecoveryCodeFormat {
+    #[default]
+    Json,
+    Text,
+    Pdf,
+}
+
+#[derive(Debug, Deserialize)]
+struct RecoveryCodeQuery {
+    #[serde(default)]
+    format: RecoveryCodeFormat,
+}
+
+// Regenerating invalidates every code the user holds, so it takes a fresh
+// sign-in with MFA, such as one from `/auth/reauthenticate`. The new codes are
+// shown only in this response; `?format=text` or `?format=pdf` returns them
+// as a file to save or print.
+#[actix_web::post(
+    "/mfa-recovery-codes",
+    wrap = "StepUpMiddleware(StepUpPolicy::mfa_within(300))",
+    wrap = "ScopedAuthMiddleware(&[TokenScope::Full])"
+)]
+async fn mfa_recovery_codes(
+    auth_service: web::Data<AuthService>,
+    user: web::ReqData<AuthenticatedUser>,
+    query: web::Query<RecoveryCodeQuery>,
+) -> Result<HttpResponse, AuthError> {
+    let sheet = auth_service.mfa_recovery_codes(user.user_id).await?;
+    
+    let mut response = HttpResponse::Ok();
+    response.insert_header(("Cache-Control", "no-store"));
+    Ok(match query.format {
+        RecoveryCodeFormat::Json => response.json(MfaRecoveryCodesResponse {
+            recovery_codes: sheet.codes,
+        }),
+        RecoveryCodeFormat::Text => response
+            .content_type("text/plain; charset=utf-8")
+            .insert_header(("Content-Disposition", "attachment; filename=\"recovery-codes.txt\""))
+            .body(sheet.to_text()),
+        RecoveryCodeFormat::Pdf => response
+            .content_type("application/pdf")
+            .insert_header(("Content-Disposition", "attachment; filename=\"recovery-codes.pdf\""))
+            .body(sheet.to_pdf()),
+    })
+}
+
+#[actix_web::get("/mfa-recovery-codes/status", wrap = "ScopedAuthMiddleware(&[TokenScope::Full])")]
+async fn recovery_code_status(
+    auth_service: web::Data<AuthService>,
+    user: web::ReqData<AuthenticatedUser>,
+) -> Result<HttpResponse, AuthError> {
+    let status = auth_service.recovery_code_status(user.user_id).await?;
+    
+    Ok(HttpResponse::Ok().json(status))
+}
+
+#[actix_web::get("/mfa-devices", wrap = "ScopedAuthMiddleware(&[TokenScope::Full])")]
+async fn list_totp_devices(
+    auth_service: web::Data<AuthService>,
+    user: web::ReqData<AuthenticatedUser>,
+) -> Result<HttpResponse, AuthError> {
+    let response = auth_service.list_totp_devices(user.user_id).await?;
+    
+    Ok(HttpResponse::Ok().json(response))
+}
+
+#[actix_web::post(
+    "/mfa-devices",
+    wrap = "StepUpMiddleware(StepUpPolicy::mfa_within(3600))",
+    wrap = "ScopedAuthMiddleware(&[TokenScope::Full])"
+)]
+async fn add_totp_device(
+    auth_service: web::Data<AuthService>,
+    user: web::ReqData<AuthenticatedUser>,
+    device_data: web::Json<AddTotpDeviceRequest>,
+) -> Result<HttpResponse, AuthError> {
+    device_data.validate()?;
+    
+    let response = auth_service
+        .add_totp_device(user.user_id, device_data.into_inner())
+        .await?;
+    
+    Ok(HttpResponse::Ok().json(response))
+}
+
+#[actix_web::post("/mfa-devices/{device_id}/confirm", wrap = "ScopedAuthMiddleware(&[TokenScope::Full])")]
+async fn confirm_totp_device(
+    auth_service: web::Data<AuthService>,
+    user: web::ReqData<AuthenticatedUser>,
+    device_id: web::Path<uuid::Uuid>,
+    confirm_data: web::Json<ConfirmTotpDeviceRequest>,
+) -> Result<HttpResponse, AuthError> {
+    confirm_data.validate()?;
+    
+    let response = auth_service
+        .confirm_totp_device(user.user_id, *device_id, confirm_data.into_inner())
+        .await?;
+    
+    Ok(HttpResponse::Ok().json(response))
+}
+
+#[actix_web::delete(
+    "/mfa-devices/{device_id}",
+    wrap = "StepUpMiddleware(StepUpPolicy::password_within(300))",
+    wrap = "ScopedAuthMiddleware(&[TokenScope::Full])"
+)]
+async fn remove_totp_device(
+    auth_service: web::Data<AuthService>,
+    user: web::ReqData<AuthenticatedUser>,
+    device_id: web::Path<uuid::Uuid>,
+) -> Result<HttpResponse, AuthError> {
+    let response = auth_service
+        .remove_totp_device(user.user_id, *device_id)
+        .await?;
+    
+    Ok(HttpResponse::Ok().json(response))
+}
+
+#[actix_web::post("/mfa-recovery", wrap = "ScopedAuthMiddleware(&[TokenScope::MfaPending])")]
+async fn mfa_recovery(
+    auth_service: web::Data<AuthService>,
+    user: web::ReqData<AuthenticatedUser>,
+    recovery_data: web::Json<MfaRecoveryRequest>,
+    req: HttpRequest,
+) -> Result<HttpResponse, AuthError> {
+    recovery_data.validate()?;
+    
+    let ip = req.connection_info().realip_remote_addr()
+        .map(|s| s.to_string());
+    
+    let user_agent = req.headers().get("User-Agent")
+        .and_then(|h| h.to_str().ok())
+        .map(|s| s.to_string());
+    
+    let response = auth_service
+        .mfa_recovery(user.user_id, recovery_data.into_inner(), ip, user_agent)
+        .await?;
+    
+    Ok(HttpResponse::Ok().json(response))
+}
+
+/// The security questions to answer when no second factor or recovery code
+/// is at hand
+#[actix_web::get(
+    "/security-questions",
+    wrap = "ScopedAuthMiddleware(&[TokenScope::MfaPending])"
+)]
+async fn pending_security_questions(
+    auth_service: web::Data<AuthService>,
+    user: web::ReqData<AuthenticatedUser>,
+) -> Result<HttpResponse, AuthError> {
+    let response = auth_service.pending_security_questions(user.user_id).await?;
+    
+    Ok(HttpResponse::Ok().json(response))
+}
+
+#[actix_web::post(
+    "/security-questions/recover",
+    wrap = "ScopedAuthMiddleware(&[TokenScope::MfaPending])"
+)]
+async fn security_question_recovery(
+    auth_service: web::Data<AuthService>,
+    user: web::ReqData<AuthenticatedUser>,
+    recovery_data: web::Json<SecurityQuestionRecoveryRequest>,
+    req: HttpRequest,
+) -> Result<HttpResponse, AuthError> {
+    recovery_data.validate()?;
+    
+    let ip = req.connection_info().realip_remote_addr()
+        .map(|s| s.to_string());
+    
+    let user_agent = req.headers().get("User-Agent")
+        .and_then(|h| h.to_str().ok())
+        .map(|s| s.to_string());
+    
+    let response = auth_service
+        .security_question_recovery(user.user_id, recovery_data.into_inner(), ip, user_agent)
+        .await?;
+    
+    Ok(HttpResponse::Ok().json(response))
+}
+
+/// Second factors the user can finish a login with, preferred first, so a
+/// client holding an `mfa_pending` token knows which challenge to show
+#[actix_web::get(
+    "/mfa-methods",
+    wrap = "ScopedAuthMiddleware(&[TokenScope::Full, TokenScope::MfaPending])"
+)]
+async fn mfa_methods(
+    auth_service: web::Data<AuthService>,
+    user: web::ReqData<AuthenticatedUser>,
+) -> Result<HttpResponse, AuthError> {
+    let response = auth_service.mfa_methods(user.user_id).await?;
+    
+    Ok(HttpResponse::Ok().json(response))
+}
+
+#[actix_web::put("/mfa-methods", wrap = "ScopedAuthMiddleware(&[TokenScope::Full])")]
+async fn set_mfa_method_order(
+    auth_service: web::Data<AuthService>,
+    user: web::ReqData<AuthenticatedUser>,
+    order_data: web::Json<MfaMethodOrderRequest>,
+) -> Result<HttpResponse, AuthError> {
+    order_data.validate()?;
+    
+    let response = auth_service
+        .set_mfa_method_order(user.user_id, order_data.into_inner())
+        .await?;
+    
+    Ok(HttpResponse::Ok().json(response))
+}
+
+/// Answer the MFA step with a passkey instead of a TOTP code
+#[actix_web::post("/mfa-passkey/start", wrap = "ScopedAuthMiddleware(&[TokenScope::MfaPending])")]
+async fn mfa_passkey_start(
+    auth_service: web::Data<AuthService>,
+    user: web::ReqData<AuthenticatedUser>,
+) -> Result<HttpResponse, AuthError> {
+    let response = auth_service.mfa_passkey_start(user.user_id).await?;
+    
+    Ok(HttpResponse::Ok().json(response))
+}
+
+#[actix_web::post("/mfa-passkey/complete", wrap = "ScopedAuthMiddleware(&[TokenScope::MfaPending])")]
+async fn mfa_passkey_complete(
+    auth_service: web::Data<AuthService>,
+    user: web::ReqData<AuthenticatedUser>,
+    assertion_data: web::Json<PasswordlessLoginCompleteRequest>,
+    req: HttpRequest,
+) -> Result<HttpResponse, AuthError> {
+    assertion_data.validate()?;
+    
+    let ip = req.connection_info().realip_remote_addr()
+        .map(|s| s.to_string());
+    
+    let user_agent = req.headers().get("User-Agent")
+        .and_then(|h| h.to_str().ok())
+        .map(|s| s.to_string());
+    
+    let response = auth_service
+        .mfa_passkey_complete(user.user_id, assertion_data.into_inner(), ip, user_agent)
+        .await?;
+    
+    Ok(HttpResponse::Ok().json(response))
+}
+
+/// Start passwordless registration process
+#[actix_web::post("/passwordless-register-start")]
+async fn passwordless_register_start(
+    auth_service: web::Data<AuthService>,
+    register_data: web::Json<PasswordlessRegisterStartRequest>,
+    flags: Flags,
+) -> Result<HttpResponse, AuthError> {
+    flags.require(PASSWORDLESS_LOGIN)?;
+    register_data.validate()?;
+    
+    let response = auth_service
+        .passwordless_register_start(register_data.into_inner())
+        .await?;
+    
+    Ok(HttpResponse::Ok().json(response))
+}
+
+/// Complete passwordless registration
+#[actix_web::post("/passwordless-register-complete", wrap = "IdempotencyMiddleware")]
+async fn passwordless_register_complete(
+    auth_service: web::Data<AuthService>,
+    register_data: web::Json<PasswordlessRegisterCompleteRequest>,
+    flags: Flags,
+    req: HttpRequest,
+) -> Result<HttpResponse, AuthError> {
+    flags.require(PASSWORDLESS_LOGIN)?;
+    register_data.validate()?;
+    
+    let ip = req.connection_info().realip_remote_addr()
+        .map(|s| s.to_string());
+    
+    let user_agent = req.headers().get("User-Agent")
+        .and_then(|h| h.to_str().ok())
+        .map(|s| s.to_string());
+    
+    let response = auth_service
+        .passwordless_register_complete(register_data.into_inner(), ip, user_agent)
+        .await?;
+    
+    Ok(HttpResponse::Created().json(response))
+}
+
+/// Start passwordless login process
+#[actix_web::post("/passwordless-login-start")]
+async fn passwordless_login_start(
+    auth_service: web::Data<AuthService>,
+    login_data: web::Json<PasswordlessLoginStartRequest>,
+    flags: Flags,
+) -> Result<HttpResponse, AuthError> {
+    flags.require(PASSWORDLESS_LOGIN)?;
+    login_data.validate()?;
+    
+    let response = auth_service
+        .passwordless_login_start(login_data.into_inner())
+        .await?;
+    
+    Ok(HttpResponse::Ok().json(response))
+}
+
+/// Complete passwordless login
+#[actix_web::post("/passwordless-login-complete")]
+async fn passwordless_login_complete(
+    auth_service: web::Data<AuthService>,
+    login_data: web::Json<PasswordlessLoginCompleteRequest>,
+    flags: Flags,
+    locale: web::ReqData<Locale>,
+    req: HttpRequest,
+) -> Result<HttpResponse, AuthError> {
+    flags.require(PASSWORDLESS_LOGIN)?;
+    login_data.validate()?;
+    
+    let ip = req.connection_info().realip_remote_addr()
+        .map(|s| s.to_string());
+    
+    let user_agent = req.headers().get("User-Agent")
+        .and_then(|h| h.to_str().ok())
+        .map(|s| s.to_string());
+    
+    let network = auth_service.network_fingerprint(req.headers());
+    
+    let response = auth_service
+        .passwordless_login_complete(login_data.into_inner(), ip, user_agent, network, &locale.0)
+        .await?;
+    
+    Ok(HttpResponse::Ok().json(response))
+}
+
+/// Start adding a passkey to the signed-in account
+#[actix_web::post(
+    "/passkeys/enroll-start",
+    wrap = "StepUpMiddleware(StepUpPolicy::password_within(300))",
+    wrap = "ScopedAuthMiddleware(&[TokenScope::Full])"
+)]
+async fn passkey_enroll_start(
+    auth_service: web::Data<AuthService>,
+    user: web::ReqData<AuthenticatedUser>,
+    enroll_data: web::Json<PasskeyEnrollStartRequest>,
+) -> Result<HttpResponse, AuthError> {
+    enroll_data.validate()?;
+    
+    let response = auth_service
+        .passkey_enroll_start(user.user_id, enroll_data.into_inner())
+        .await?;
+    
+    Ok(HttpResponse::Ok().json(response))
+}
+
+/// Complete adding a passkey
+#[actix_web::post(
+    "/passkeys/enroll-complete",
+    wrap = "IdempotencyMiddleware",
+    wrap = "ScopedAuthMiddleware(&[TokenScope::Full])"
+)]
+async fn passkey_enroll_complete(
+    auth_service: web::Data<AuthService>,
+    user: web::ReqData<AuthenticatedUser>,
+    enroll_data: web::Json<PasswordlessRegisterCompleteRequest>,
+) -> Result<HttpResponse, AuthError> {
+    enroll_data.validate()?;
+    
+    let response = auth_service
+        .passkey_enroll_complete(user.user_id, enroll_data.into_inner())
+        .await?;
+    
+    Ok(HttpResponse::Created().json(response))
+}
+
+/// Stop suggesting passkey enrollment after password logins
+#[actix_web::post("/passkeys/prompt/dismiss", wrap = "ScopedAuthMiddleware(&[TokenScope::Full])")]
+async fn dismiss_passkey_prompt(
+    auth_service: web::Data<AuthService>,
+    user: web::ReqData<AuthenticatedUser>,
+) -> Result<HttpResponse, AuthError> {
+    let response = auth_service.dismiss_passkey_prompt(user.user_id).await?;
+    
+    Ok(HttpResponse::Ok().json(response))
+}
+
+// Thumbprint of the client's key when a token request carries a DPoP proof
+fn dpop_key(auth_service: &AuthService, req: &HttpRequest) -> Result<Option<String>, AuthError> {
+    match dpop::proof_header(req.headers())? {
+        Some(proof) => auth_service
+            .dpop_verifier()
+            .verify(proof, req.method().as_str(), &dpop::request_url(req), None)
+            .map(Some),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::StatusCode;
+    use actix_web::test;
+    use base64::Engine;
+    use serde_json::{json, Value};
+
+    use crate::test_utils::{confirm_password_reset, login, mfa_login, post_json, register, request_password_reset, verify_email, TestContext};
+
+    #[actix_web::test]
+    async fn test_register_then_login() {
+        let ctx = TestContext::new();
+        let app = ctx.spawn_app().await;
+
+        register(&app, "harness_user", "harness_user@example.com", "TestPass123!")
+            .await
+            .assert_success();
+
+        let response = login(&app, "harness_user", "TestPass123!").await.assert_success();
+        assert!(response.field("access_token").is_some());
+    }
+
+    #[actix_web::test]
+    async fn test_mfa_login_with_factory_user() {
+        let ctx = TestContext::new();
+        let app = ctx.spawn_app().await;
+        let user = ctx.user().with_mfa().create().await.unwrap();
+
+        let code = user.totp_code(&ctx).unwrap();
+        let response = mfa_login(&app, &user.user.username, &user.password, &code)
+            .await
+            .assert_success();
+        assert!(response.field("access_token").is_some());
+    }
+
+    #[actix_web::test]
+    async fn test_tokens_carry_the_assurance_level_of_their_session() {
+        let ctx = TestContext::new();
+        let app = ctx.spawn_app().await;
+        let claims = |token: &str| -> Value {
+            let payload = token.split('.').nth(1).unwrap();
+            serde_json::from_slice(&base64::engine::general_purpose::URL_SAFE_NO_PAD.decode(payload).unwrap()).unwrap()
+        };
+
+        let user = ctx.user().create().await.unwrap();
+        let response = login(&app, &user.user.username, &user.password).await.assert_success();
+        let token = claims(response.field("access_token").unwrap());
+        assert_eq!(token["amr"], json!(["pwd"]));
+        assert_eq!(token["aal"], 1);
+
+        let user = ctx.user().with_mfa().create().await.unwrap();
+        let code = user.totp_code(&ctx).unwrap();
+        let response = mfa_login(&app, &user.user.username, &user.password, &code).await.assert_success();
+        let token = claims(response.field("access_token").unwrap());
+        assert_eq!(token["amr"], json!(["pwd", "otp", "mfa"]));
+        assert_eq!(token["aal"], 2);
+
+        // A refresh keeps the level but isn't a fresh sign-in
+        let refreshed = post_json(&app, "/auth/refresh-token", json!({ "refresh_token": response.field("refresh_token") }))
+            .await
+            .assert_success();
+        let access_token = refreshed.field("access_token").unwrap();
+        let token = claims(access_token);
+        assert_eq!(token["aal"], 2);
+        assert!(token.get("auth_time").is_none());
+
+        let request = test::TestRequest::get()
+            .uri("/users/sessions")
+            .insert_header(("Authorization", format!("Bearer {}", access_token)))
+            .to_request();
+        let sessions: Value = test::call_and_read_body_json(&app, request).await;
+        assert_eq!(sessions["items"][0]["amr"], json!(["pwd", "otp", "mfa"]));
+        assert_eq!(sessions["items"][0]["aal"], 2);
+    }
+
+    #[actix_web::test]
+    async fn test_logins_from_new_devices_get_shortened_sessions() {
+        let ctx = TestContext::new();
+        let app = ctx.spawn_app().await;
+        let user = ctx.user().create().await.unwrap();
+        ctx.session(&user)
+            .user_agent("Mozilla/5.0 (X11; Linux x86_64; rv:120.0) Gecko/20100101 Firefox/120.0")
+            .create()
+            .await
+            .unwrap();
+
+        let response = login(&app, &user.user.username, &user.password).await.assert_success();
+        let risky = ctx.config.session_lifetime.risky_access_token_expiry.min(ctx.config.jwt.access_token_expiry);
+        assert_eq!(response.body["expires_in"], risky);
+
+        let request = test::TestRequest::get()
+            .uri("/users/sessions")
+            .insert_header(("Authorization", format!("Bearer {}", response.field("access_token").unwrap())))
+            .to_request();
+        let sessions: Value = test::call_and_read_body_json(&app, request).await;
+        let current = sessions["items"].as_array().unwrap().iter().find(|s| s["is_current"] == true).unwrap();
+        assert_eq!(current["lifetime"], "shortened");
+
+        // Refreshes keep the session shortened, but the device is known from now on
+        let refreshed = post_json(&app, "/auth/refresh-token", json!({ "refresh_token": response.field("refresh_token") }))
+            .await
+            .assert_success();
+        assert_eq!(refreshed.body["expires_in"], risky);
+        let response = login(&app, &user.user.username, &user.password).await.assert_success();
+        assert_eq!(response.body["expires_in"], ctx.config.jwt.access_token_expiry);
+    }
+
+    #[actix_web::test]
+    async fn test_email_verification_via_mock_mailer() {
+        let ctx = TestContext::new();
+        let app = ctx.spawn_app().await;
+
+        register(&app, "verify_me", "verify_me@example.com", "TestPass123!")
+            .await
+            .assert_success();
+        ctx.mailer.assert_sent_to("verify_me@example.com", 1);
+
+        let token = ctx.mailer.token_for("verify_me@example.com");
+        verify_email(&app, &token).await.assert_success();
+
+        let user = ctx.db.find_user_by_username("verify_me").await.unwrap();
+        assert!(user.is_email_verified);
+    }
+
+    #[actix_web::test]
+    async fn test_password_reset_via_mock_mailer() {
+        let ctx = TestContext::new();
+        let app = ctx.spawn_app().await;
+        let user = ctx.user().create().await.unwrap();
+
+        request_password_reset(&app, &user.user.email).await.assert_success();
+        let token = ctx.mailer.token_for(&user.user.email);
+        confirm_password_reset(&app, &token, "NewPass456!").await.assert_success();
+
+        assert!(!login(&app, &user.user.username, &user.password).await.status.is_success());
+        login(&app, &user.user.username, "NewPass456!").await.assert_success();
+    }
+
+    #[actix_web::test]
+    async fn test_reset_emails_are_throttled_per_address() {
+        let ctx = TestContext::new();
+        let app = ctx.spawn_app().await;
+        let user = ctx.user().create().await.unwrap();
+
+        request_password_reset(&app, &user.user.email).await.assert_success();
+        let again = request_password_reset(&app, &user.user.email).await;
+        assert_eq!(again.status, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(again.field("code"), Some("EMAIL_RESEND_THROTTLED"));
+        ctx.mailer.assert_sent_to(&user.user.email, 1);
+
+        // Addresses without an account hit the same limit
+        request_password_reset(&app, "nobody@example.com").await.assert_success();
+        let unknown = request_password_reset(&app, "nobody@example.com").await;
+        assert_eq!(unknown.status, again.status);
+        assert_eq!(unknown.field("code"), again.field("code"));
+    }
+
+    #[actix_web::test]
+    async fn test_unknown_user_fails_like_wrong_password() {
+        let ctx = TestContext::new();
+        let app = ctx.spawn_app().await;
+        let user = ctx.user().create().await.unwrap();
+
+        let wrong_password = login(&app, &user.user.username, "WrongPass123!").await;
+        let unknown_user = login(&app, "nobody_here", "WrongPass123!").await;
+
+        assert_eq!(wrong_password.status, unknown_user.status);
+        assert_eq!(wrong_password.body, unknown_user.body);
+    }
+
+    #[actix_web::test]
+    async fn test_generic_registration_responses() {
+        let mut config = crate::test_utils::test_config();
+        config.registration.generic_response = true;
+        let ctx = TestContext::with_config(config);
+        let app = ctx.spawn_app().await;
+        let existing = ctx.user().create().await.unwrap();
+
+        let fresh = register(&app, "brand_new", "brand_new@example.com", "TestPass123!").await;
+        let taken = register(&app, "another_name", &existing.user.email, "TestPass123!").await;
+
+        assert_eq!(fresh.status, taken.status);
+        assert_eq!(fresh.body, taken.body);
+        assert!(fresh.body.get("user").is_none());
+
+        // The owner of the taken address is told instead
+        let notice = ctx.mailer.last_to(&existing.user.email);
+        assert!(!notice.text_body.contains("token="));
+    }
+
+    #[actix_web::test]
+    async fn test_email_only_registration_is_activated_by_link() {
+        let ctx = TestContext::new();
+        let app = ctx.spawn_app().await;
+
+        post_json(&app, "/auth/register/email", json!({ "email": "later@example.com" }))
+            .await
+            .assert_success();
+        let user = ctx.db.find_user_by_email("later@example.com").await.unwrap();
+        assert!(user.activation_pending);
+
+        // Asking for a reset resends the activation link instead
+        request_password_reset(&app, "later@example.com").await.assert_success();
+        ctx.mailer.assert_sent_to("later@example.com", 2);
+        let token = ctx.mailer.token_for("later@example.com");
+
+        let activate = json!({
+            "token": token,
+            "password": "TestPass123!",
+            "password_confirmation": "TestPass123!",
+        });
+        post_json(&app, "/auth/activate", activate.clone()).await.assert_success();
+        let response = post_json(&app, "/auth/activate", activate).await;
+        assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+
+        let user = ctx.db.find_user_by_id(user.id).await.unwrap();
+        assert!(!user.activation_pending && user.is_email_verified);
+        login(&app, "later@example.com", "TestPass123!").await.assert_success();
+    }
+
+    #[actix_web::test]
+    async fn test_idle_session_is_revoked_on_refresh() {
+        let mut config = crate::test_utils::test_config();
+        config.sessions.inactivity_timeout_days = 30;
+        let ctx = TestContext::with_config(config);
+        let app = ctx.spawn_app().await;
+        let user = ctx.user().create().await.unwrap();
+
+        let active = ctx.session(&user).create().await.unwrap();
+        let idle = ctx.session(&user).create().await.unwrap();
+        let last_seen = chrono::Utc::now() - chrono::Duration::days(31);
+        ctx.db.touch_session(idle.session.id, last_seen).await.unwrap();
+
+        let refresh = |token: String| post_json(&app, "/auth/refresh-token", json!({ "refresh_token": token }));
+        refresh(active.refresh_token.clone()).await.assert_success();
+
+        let response = refresh(idle.refresh_token.clone()).await;
+        assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+        assert!(ctx.db.find_session_by_id(idle.session.id).await.unwrap().is_revoked);
+    }
+
+    #[actix_web::test]
+    async fn test_failed_logins_raise_account_risk() {
+        let mut config = crate::test_utils::test_config();
+        config.account_risk.notify_threshold = 5;
+        config.account_risk.revoke_sessions_threshold = 10;
+        config.account_risk.mfa_reenrollment_threshold = 15;
+        let ctx = TestContext::with_config(config);
+        let app = ctx.spawn_app().await;
+        let user = ctx.user().with_mfa().create().await.unwrap();
+        let session = ctx.session(&user).create().await.unwrap();
+
+        for _ in 0..3 {
+            let response = login(&app, &user.user.username, "WrongPass123!").await;
+            assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+        }
+
+        // Each action alerted the owner once, and MFA was reset
+        ctx.mailer.assert_sent_to(&user.user.email, 3);
+        assert!(ctx.db.find_session_by_id(session.session.id).await.unwrap().is_revoked);
+        let stored = ctx.db.find_user_by_id(user.id()).await.unwrap();
+        assert!(!stored.mfa_enabled);
+        assert!(stored.mfa_reenrollment_required);
+
+        // The right password now only gets a token for setting MFA up again
+        let response = login(&app, &user.user.username, &user.password).await.assert_success();
+        assert_eq!(response.body["mfa_enrollment_required"], true);
+        assert_eq!(response.field("refresh_token"), Some(""));
+    }
+
+    #[actix_web::test]
+    async fn test_repeated_failures_require_captcha() {
+        let mut config = crate::test_utils::test_config();
+        config.brute_force.enabled = true;
+        config.brute_force.account_captcha_after = 2;
+        let ctx = TestContext::with_config(config);
+        let app = ctx.spawn_app().await;
+        let user = ctx.user().create().await.unwrap();
+
+        for _ in 0..2 {
+            let response = login(&app, &user.user.username, "WrongPass123!").await;
+            assert_eq!(response.field("code"), Some("INVALID_CREDENTIALS"));
+        }
+
+        // Even the right password needs a CAPTCHA now
+        let response = login(&app, &user.user.username, &user.password).await;
+        assert_eq!(response.field("code"), Some("CAPTCHA_REQUIRED"));
+    }
+
+    #[actix_web::test]
+    async fn test_repeated_failures_lock_the_account() {
+        let mut config = crate::test_utils::test_config();
+        config.brute_force.enabled = true;
+        config.brute_force.account_captcha_after = 0;
+        config.brute_force.account_lockout_after = 3;
+        let ctx = TestContext::with_config(config);
+        let app = ctx.spawn_app().await;
+        let user = ctx.user().create().await.unwrap();
+
+        for _ in 0..3 {
+            let response = login(&app, &user.user.username, "WrongPass123!").await;
+            assert_eq!(response.field("code"), Some("INVALID_CREDENTIALS"));
+        }
+
+        let response = login(&app, &user.user.username, &user.password).await;
+        assert_eq!(response.status, StatusCode::LOCKED);
+        assert_eq!(response.field("code"), Some("ACCOUNT_LOCKED"));
+        assert!(ctx.db.find_user_by_id(user.id()).await.unwrap().locked_until.is_some());
+    }
+
+    #[actix_web::test]
+    async fn test_email_code_login_and_trusted_device() {
+        let mut config = crate::test_utils::test_config();
+        config.email_code_login.enabled = true;
+        let ctx = TestContext::with_config(config);
+        let app = ctx.spawn_app().await;
+        let user = ctx.user().create().await.unwrap();
+
+        // Unknown addresses get a challenge too, but no email
+        post_json(&app, "/auth/email-code", json!({ "email": "nobody@example.com" }))
+            .await
+            .assert_success();
+        assert!(ctx.mailer.sent().is_empty());
+
+        let response = post_json(&app, "/auth/email-code", json!({ "email": user.user.email }))
+            .await
+            .assert_success();
+        let challenge_id = response.body["challenge_id"].clone();
+        let code = ctx.mailer.code_for(&user.user.email);
+
+        // Asking again right away doesn't send another code
+        let again = post_json(&app, "/auth/email-code", json!({ "email": user.user.email })).await;
+        assert_eq!(again.field("code"), Some("EMAIL_RESEND_THROTTLED"));
+
+        let verify = |code: String| {
+            post_json(
+                &app,
+                "/auth/email-code/verify",
+                json!({ "challenge_id": challenge_id, "code": code, "trust_device": true }),
+            )
+        };
+        let wrong = if code == "000000" { "000001" } else { "000000" };
+        let response = verify(wrong.to_string()).await;
+        assert_eq!(response.field("code"), Some("INVALID_VERIFICATION_CODE"));
+
+        let response = verify(code.clone()).await.assert_success();
+        assert!(!response.body["access_token"].as_str().unwrap().is_empty());
+        let device_token = response.body["device_token"].as_str().unwrap().to_string();
+
+        // Codes work once
+        let response = verify(code).await;
+        assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+
+        // The trusted device skips the code until the user signs out everywhere
+        let trusted = json!({ "email": user.user.email, "device_token": device_token });
+        post_json(&app, "/auth/email-code/trusted-device", trusted.clone())
+            .await
+            .assert_success();
+        ctx.auth_service.logout_all(user.id()).await.unwrap();
+        let response = post_json(&app, "/auth/email-code/trusted-device", trusted).await;
+        assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[actix_web::test]
+    async fn test_login_policies_apply_to_email_code_logins() {
+        let mut config = crate::test_utils::test_config();
+        config.email_code_login.enabled = true;
+        let ctx = TestContext::with_config(config);
+        let app = ctx.spawn_app().await;
+        let user = ctx.user().create().await.unwrap();
+        let policy = serde_json::from_value(json!({ "allowed_countries": ["de"] })).unwrap();
+        ctx.auth_service.save_login_policy(user.id(), policy).await.unwrap();
+
+        let response = post_json(&app, "/auth/email-code", json!({ "email": user.user.email }))
+            .await
+            .assert_success();
+        let code = ctx.mailer.code_for(&user.user.email);
+
+        // Test requests carry no country, which can't be shown to be Germany
+        let response = post_json(
+            &app,
+            "/auth/email-code/verify",
+            json!({ "challenge_id": response.body["challenge_id"], "code": code }),
+        )
+        .await;
+        assert_eq!(response.status, StatusCode::FORBIDDEN);
+        assert_eq!(response.field("code"), Some("LOGIN_NOT_ALLOWED"));
+    }
+
+    #[actix_web::test]
+    async fn test_canary_login_fails_and_is_recorded() {
+        let ctx = TestContext::new();
+        let app = ctx.spawn_app().await;
+        let admin = ctx.user().admin().create().await.unwrap();
+        let request = serde_json::from_value(json!({
+            "label": "wiki: deploy runbook",
+            "username": "backup-admin",
+            "email": "backup-admin@example.com",
+            "password": "Summer2023!",
+        }))
+        .unwrap();
+        let planted = ctx.auth_service.create_canary(admin.id(), request).await.unwrap();
+
+        // Even the planted password only gets an ordinary rejection
+        let response = login(&app, "backup-admin", "Summer2023!").await;
+        assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+        assert_eq!(response.field("code"), Some("INVALID_CREDENTIALS"));
+
+        let canaries = ctx.auth_service.list_canaries().await.unwrap().canaries;
+        assert_eq!(canaries[0].id, planted.canary.id);
+        assert_eq!(canaries[0].trip_count, 1);
+    }
+
+    #[actix_web::test]
+    async fn test_refresh_from_another_network_steps_up() {
+        let mut config = crate::test_utils::test_config();
+        config.refresh_binding.binding = crate::config::RefreshBinding::CountryAndAsn;
+        let ctx = TestContext::with_config(config);
+        let app = ctx.spawn_app().await;
+        let user = ctx.user().create().await.unwrap();
+        let session = ctx.session(&user).network("DE", "3320").create().await.unwrap();
+
+        // No network headers at all doesn't match the bound network
+        let response = post_json(&app, "/auth/refresh-token", json!({ "refresh_token": session.refresh_token })).await;
+        assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+        assert_eq!(response.field("code"), Some("NETWORK_CHANGED"));
+
+        let response = post_json(
+            &app,
+            "/auth/refresh-token",
+            json!({ "refresh_token": session.refresh_token, "password": "WrongPass123!" }),
+        )
+        .await;
+        assert_eq!(response.field("code"), Some("INVALID_CREDENTIALS"));
+
+        post_json(
+            &app,
+            "/auth/refresh-token",
+            json!({ "refresh_token": session.refresh_token, "password": user.password }),
+        )
+        .await
+        .assert_success();
+    }
+
+    #[actix_web::test]
+    async fn test_recovery_codes_download_and_count_down() {
+        let mut config = crate::test_utils::test_config();
+        config.recovery_codes.count = 4;
+        config.recovery_codes.warn_below = 4;
+        let ctx = TestContext::with_config(config);
+        let app = ctx.spawn_app().await;
+        let user = ctx.user().with_mfa().create().await.unwrap();
+
+        let code = user.totp_code(&ctx).unwrap();
+        let signed_in = mfa_login(&app, &user.user.username, &user.password, &code).await.assert_success();
+        let bearer = format!("Bearer {}", signed_in.field("access_token").unwrap());
+
+        let request = test::TestRequest::post()
+            .uri("/auth/mfa-recovery-codes?format=text")
+            .insert_header(("Authorization", bearer.as_str()))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers().get("Cache-Control").unwrap(), "no-store");
+        assert_eq!(
+            response.headers().get("Content-Disposition").unwrap(),
+            "attachment; filename=\"recovery-codes.txt\""
+        );
+        let sheet = String::from_utf8(test::read_body(response).await.to_vec()).unwrap();
+        let codes: Vec<&str> = sheet
+            .lines()
+            .filter_map(|line| line.trim_start().split_once(". "))
+            .filter(|(number, _)| number.parse::<usize>().is_ok())
+            .map(|(_, code)| code)
+            .collect();
+        assert_eq!(codes.len(), 4);
+
+        // A code from the sheet signs in once, and the response says how many are left
+        let pending = login(&app, &user.user.username, &user.password).await.assert_success();
+        let request = test::TestRequest::post()
+            .uri("/auth/mfa-recovery")
+            .insert_header(("Authorization", format!("Bearer {}", pending.field("access_token").unwrap())))
+            .set_json(json!({ "recovery_code": codes[0] }))
+            .to_request();
+        let response: Value = test::call_and_read_body_json(&app, request).await;
+        assert_eq!(response["recovery_codes"]["remaining"], 3);
+        assert_eq!(response["recovery_codes"]["low"], true);
+        assert!(response["recovery_codes"]["warning"].as_str().unwrap().contains("Only 3"));
+
+        let request = test::TestRequest::get()
+            .uri("/auth/mfa-recovery-codes/status")
+            .insert_header(("Authorization", bearer.as_str()))
+            .to_request();
+        let status: Value = test::call_and_read_body_json(&app, request).await;
+        assert_eq!(status["total"], 4);
+        assert_eq!(status["remaining"], 3);
+    }
+
+    #[actix_web::test]
+    async fn test_security_questions_stand_in_for_the_second_factor() {
+        let mut config = crate::test_utils::test_config();
+        config.security_questions.enabled = true;
+        config.security_questions.count = 2;
+        config.security_questions.max_failures = 2;
+        let ctx = TestContext::with_config(config);
+        let app = ctx.spawn_app().await;
+        let user = ctx.user().with_mfa().create().await.unwrap();
+
+        let code = user.totp_code(&ctx).unwrap();
+        let signed_in = mfa_login(&app, &user.user.username, &user.password, &code).await.assert_success();
+        let request = test::TestRequest::put()
+            .uri("/users/me/security-questions")
+            .insert_header(("Authorization", format!("Bearer {}", signed_in.field("access_token").unwrap())))
+            .set_json(json!({
+                "questions": [
+                    { "question": "First street you lived on?", "answer": "Baker Street" },
+                    { "question": "Name of your first pet?", "answer": "Rex" },
+                ]
+            }))
+            .to_request();
+        let saved: Value = test::call_and_read_body_json(&app, request).await;
+        assert_eq!(saved["questions"][1], "Name of your first pet?");
+
+        let attempts = [
+            (json!(["baker street", "Max"]), StatusCode::UNAUTHORIZED),
+            // Case, punctuation and spacing don't matter, and success clears the failure
+            (json!(["  BAKER street.", "rex!"]), StatusCode::OK),
+            (json!(["Abbey Road", "Rex"]), StatusCode::UNAUTHORIZED),
+            (json!(["Abbey Road", "Rex"]), StatusCode::UNAUTHORIZED),
+            // Out of attempts for the window, even with the right answers
+            (json!(["Baker Street", "Rex"]), StatusCode::TOO_MANY_REQUESTS),
+        ];
+        for (answers, expected) in attempts {
+            let pending = login(&app, &user.user.username, &user.password).await.assert_success();
+            let request = test::TestRequest::post()
+                .uri("/auth/security-questions/recover")
+                .insert_header(("Authorization", format!("Bearer {}", pending.field("access_token").unwrap())))
+                .set_json(json!({ "answers": answers }))
+                .to_request();
+            assert_eq!(test::call_service(&app, request).await.status(), expected, "{}", answers);
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_locked_account_needs_a_full_reset_to_unlock() {
+        let ctx = TestContext::new();
+        let app = ctx.spawn_app().await;
+        let user = ctx.user().with_mfa().create().await.unwrap();
+        let email = user.user.email.clone();
+
+        let code = user.totp_code(&ctx).unwrap();
+        let signed_in = mfa_login(&app, &user.user.username, &user.password, &code).await.assert_success();
+        let access_token = signed_in.field("access_token").unwrap();
+        let request = test::TestRequest::post()
+            .uri("/users/me/lock")
+            .insert_header(("Authorization", format!("Bearer {}", access_token)))
+            .to_request();
+        assert_eq!(test::call_service(&app, request).await.status(), StatusCode::OK);
+
+        // Signed out everywhere, and the password alone no longer gets in
+        let request = test::TestRequest::get()
+            .uri("/users/me")
+            .insert_header(("Authorization", format!("Bearer {}", access_token)))
+            .to_request();
+        assert_eq!(test::call_service(&app, request).await.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(login(&app, &user.user.username, &user.password).await.status, StatusCode::FORBIDDEN);
+
+        request_password_reset(&app, &email).await.assert_success();
+        let token = ctx.mailer.token_for(&email);
+        let reset = |mfa_code: Option<&str>| {
+            json!({
+                "token": token,
+                "password": "Unlocked-Passw0rd!",
+                "password_confirmation": "Unlocked-Passw0rd!",
+                "mfa_code": mfa_code,
+            })
+        };
+        // Without the second factor the link is refused, but not used up
+        let response = post_json(&app, "/auth/password-reset-confirm", reset(None)).await;
+        assert_eq!(response.status, StatusCode::FORBIDDEN);
+        let code = user.totp_code(&ctx).unwrap();
+        post_json(&app, "/auth/password-reset-confirm", reset(Some(&code))).await.assert_success();
+
+        login(&app, &user.user.username, "Unlocked-Passw0rd!").await.assert_success();
+
+        // The emailed variant locks it again without a session
+        post_json(&app, "/auth/lock-account/request", json!({ "email": email })).await.assert_success();
+        let token = ctx.mailer.token_for(&email);
+        post_json(&app, "/auth/lock-account", json!({ "token": token })).await.assert_success();
+        let response = login(&app, &user.user.username, "Unlocked-Passw0rd!").await;
+        assert_eq!(response.status, StatusCode::FORBIDDEN);
+    }
+
+    #[actix_web::test]
+    async fn test_reauthentication_failures_count_like_failed_logins() {
+        let mut config = crate::test_utils::test_config();
+        config.brute_force.enabled = true;
+        config.brute_force.account_lockout_after = 2;
+        let ctx = TestContext::with_config(config);
+        let app = ctx.spawn_app().await;
+        let user = ctx.user().create().await.unwrap();
+        let session = ctx.session(&user).create().await.unwrap();
+        let reauthenticate = |password: &str| {
+            test::TestRequest::post()
+                .uri("/auth/reauthenticate")
+                .insert_header(("Authorization", session.bearer()))
+                .set_json(json!({ "password": password }))
+                .to_request()
+        };
+
+        let response: Value = test::call_and_read_body_json(&app, reauthenticate(&user.password)).await;
+        assert!(response["access_token"].is_string());
+
+        for _ in 0..2 {
+            let response = test::call_service(&app, reauthenticate("WrongPass123!")).await;
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        }
+        // Locked like the login would be, even with the right password
+        let response = test::call_service(&app, reauthenticate(&user.password)).await;
+        assert_eq!(response.status(), StatusCode::LOCKED);
+    }
+
+    #[actix_web::test]
+    async fn test_totp_devices_are_listed_alongside_the_primary_authenticator() {
+        let ctx = TestContext::new();
+        let app = ctx.spawn_app().await;
+        let user = ctx.user().with_mfa().create().await.unwrap();
+
+        let code = user.totp_code(&ctx).unwrap();
+        let signed_in = mfa_login(&app, &user.user.username, &user.password, &code).await.assert_success();
+        let bearer = format!("Bearer {}", signed_in.field("access_token").unwrap());
+        let list = || {
+            test::TestRequest::get()
+                .uri("/auth/mfa-devices")
+                .insert_header(("Authorization", bearer.as_str()))
+                .to_request()
+        };
+
+        let devices: Value = test::call_and_read_body_json(&app, list()).await;
+        assert_eq!(devices.as_array().unwrap().len(), 1);
+        assert_eq!(devices[0]["primary"], true);
+        assert!(devices[0]["id"].is_null());
+
+        let request = test::TestRequest::post()
+            .uri("/auth/mfa-devices")
+            .insert_header(("Authorization", bearer.as_str()))
+            .set_json(json!({ "name": "Backup phone" }))
+            .to_request();
+        let setup: Value = test::call_and_read_body_json(&app, request).await;
+        let device_id = setup["device_id"].as_str().unwrap().to_string();
+        let device_code = ctx.mfa_service().current_code(setup["secret"].as_str().unwrap()).unwrap();
+
+        let request = test::TestRequest::post()
+            .uri(&format!("/auth/mfa-devices/{}/confirm", device_id))
+            .insert_header(("Authorization", bearer.as_str()))
+            .set_json(json!({ "mfa_code": device_code }))
+            .to_request();
+        let confirmed: Value = test::call_and_read_body_json(&app, request).await;
+        assert_eq!(confirmed["is_confirmed"], true);
+
+        let devices: Value = test::call_and_read_body_json(&app, list()).await;
+        assert_eq!(devices.as_array().unwrap().len(), 2);
+        assert_eq!(devices[1]["id"], device_id.as_str());
+        assert_eq!(devices[1]["primary"], false);
+
+        let request = test::TestRequest::delete()
+            .uri(&format!("/auth/mfa-devices/{}", device_id))
+            .insert_header(("Authorization", bearer.as_str()))
+            .to_request();
+        let removed: Value = test::call_and_read_body_json(&app, request).await;
+        assert_eq!(removed["device_id"], device_id.as_str());
+        assert_eq!(removed["name"], "Backup phone");
+        assert_eq!(removed["remaining_devices"], 1);
+    }
+
+    #[actix_web::test]
+    async fn test_dismissed_passkey_prompt_stays_dismissed() {
+        let mut config = crate::test_utils::test_config();
+        config.passkey_prompt.enabled = true;
+        config.passkey_prompt.interval = 0;
+        let ctx = TestContext::with_config(config);
+        let app = ctx.spawn_app().await;
+        let user = ctx.user().create().await.unwrap();
+        let login_body = json!({
+            "username_or_email": user.user.username,
+            "password": user.password,
+            "webauthn_supported": true,
+        });
+
+        let signed_in = post_json(&app, "/auth/login", login_body.clone()).await.assert_success();
+        assert_eq!(signed_in.body["passkey_prompt"]["dismiss_endpoint"], "/auth/passkeys/prompt/dismiss");
+
+        let request = test::TestRequest::post()
+            .uri("/auth/passkeys/prompt/dismiss")
+            .insert_header(("Authorization", format!("Bearer {}", signed_in.field("access_token").unwrap())))
+            .to_request();
+        let state: Value = test::call_and_read_body_json(&app, request).await;
+        assert!(state["dismissed_at"].is_string());
+        assert_eq!(state["prompt_count"], 1);
+
+        let signed_in = post_json(&app, "/auth/login", login_body).await.assert_success();
+        assert!(signed_in.body["passkey_prompt"].is_null());
+    }
+
+    #[actix_web::test]
+    async fn test_breach_and_risk_checks_run_on_login() {
+        let ctx = TestContext::new();
+        let app = ctx.spawn_app().await;
+        let flagged = ctx.user().create().await.unwrap();
+        let user = ctx.user().create().await.unwrap();
+
+        // Only the right password learns that a breached account needs a reset
+        ctx.auth_service.breach_detection().require_password_reset(&flagged.id());
+        let response = login(&app, &flagged.user.username, "WrongPass123!").await;
+        assert_eq!(response.field("code"), Some("INVALID_CREDENTIALS"));
+        let response = login(&app, &flagged.user.username, &flagged.password).await;
+        assert_eq!(response.status, StatusCode::FORBIDDEN);
+        assert_eq!(response.field("code"), Some("PASSWORD_RESET_REQUIRED"));
+
+        // Failures spread over many accounts raise the risk of every other login
+        for name in ["stuffed_a", "stuffed_b", "stuffed_c", "stuffed_d"] {
+            let response = login(&app, name, "WrongPass123!").await;
+            assert_eq!(response.field("code"), Some("INVALID_CREDENTIALS"));
+        }
+        let response = login(&app, &user.user.username, &user.password).await;
+        assert_eq!(response.field("code"), Some("CAPTCHA_REQUIRED"));
+        assert!(response.body["captcha_challenge"]["id"].is_string(), "{}", response.body);
+    }
+
+    #[actix_web::test]
+    async fn test_logins_from_denylisted_addresses_need_a_captcha() {
+        let mut config = crate::test_utils::test_config();
+        config.ip_reputation.denylist = vec!["203.0.113.0/24".to_string()];
+        config.ip_reputation.weight = 40;
+        let ctx = TestContext::with_config(config);
+        let app = ctx.spawn_app().await;
+        let user = ctx.user().create().await.unwrap();
+
+        let login_from = |ip: &str| {
+            test::TestRequest::post()
+                .uri("/auth/login")
+                .insert_header(("X-Forwarded-For", ip))
+                .set_json(json!({ "username_or_email": user.user.username, "password": user.password }))
+                .to_request()
+        };
+
+        let response = test::call_service(&app, login_from("198.51.100.7")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = test::call_service(&app, login_from("203.0.113.7")).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body: Value = test::read_body_json(response).await;
+        assert_eq!(body["code"], "CAPTCHA_REQUIRED");
+    }
+
+    #[actix_web::test]
+    async fn test_impossible_travel_is_challenged_and_reported() {
+        // Stands in for the security webhook's receiver
+        let receiver = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut config = crate::test_utils::test_config();
+        config.security_webhook.url = Some(format!("http://{}/events", receiver.local_addr().unwrap()));
+        let (delivered, events) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            use std::io::{Read, Write};
+            let (mut stream, _) = receiver.accept().unwrap();
+            let mut request = String::new();
+            let mut chunk = [0; 4096];
+            while !request.ends_with('}') {
+                let read = stream.read(&mut chunk).unwrap();
+                if read == 0 {
+                    break;
+                }
+                request.push_str(&String::from_utf8_lossy(&chunk[..read]));
+            }
+            stream.write_all(b"HTTP/1.1 204 No Content\r\n\r\n").unwrap();
+            delivered.send(request).unwrap();
+        });
+
+        let ctx = TestContext::with_config(config);
+        let app = ctx.spawn_app().await;
+        let user = ctx.user().create().await.unwrap();
+        let login_from = |latitude: &str, longitude: &str| {
+            test::TestRequest::post()
+                .uri("/auth/login")
+                .insert_header(("CF-IPLatitude", latitude))
+                .insert_header(("CF-IPLongitude", longitude))
+                .set_json(json!({ "username_or_email": user.user.username, "password": user.password }))
+                .to_request()
+        };
+
+        // Berlin, then Sydney a moment later
+        let response = test::call_service(&app, login_from("52.52", "13.40")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = test::call_service(&app, login_from("-33.87", "151.21")).await;
+        let body: Value = test::read_body_json(response).await;
+        assert_eq!(body["code"], "CAPTCHA_REQUIRED");
+
+        let mut request = None;
+        for _ in 0..50 {
+            if let Ok(received) = events.try_recv() {
+                request = Some(received);
+                break;
+            }
+            actix_web::rt::time::sleep(std::time::Duration::from_millis(100)).await;
+        }
+        let request = request.expect("no security event delivered");
+        let event: Value = serde_json::from_str(&request[request.find("\r\n\r\n").unwrap() + 4..]).unwrap();
+        assert_eq!(event["kind"], "impossible_travel");
+        assert_eq!(event["user_id"], user.id().to_string());
+        assert_eq!(event["location"]["latitude"], -33.87);
+    }
+
+    #[actix_web::test]
+    async fn test_high_risk_login_waits_for_email_approval() {
+        let mut config = crate::test_utils::test_config();
+        config.login_approval.enabled = true;
+        config.ip_reputation.denylist = vec!["203.0.113.7".to_string()];
+        config.ip_reputation.weight = 80;
+        let ctx = TestContext::with_config(config);
+        let app = ctx.spawn_app().await;
+        let user = ctx.user().create().await.unwrap();
+
+        let request = test::TestRequest::post()
+            .uri("/auth/login")
+            .insert_header(("X-Forwarded-For", "203.0.113.7"))
+            .set_json(json!({ "username_or_email": user.user.username, "password": user.password }))
+            .to_request();
+        let held: Value = test::call_and_read_body_json(&app, request).await;
+        assert_eq!(held["access_token"], "");
+        let approval_id = held["approval_id"].as_str().expect("login wasn't held for approval").to_string();
+        let complete_path = format!("/auth/login-approval/{}/complete", approval_id);
+
+        let response = post_json(&app, &complete_path, json!({})).await;
+        assert_eq!(response.field("code"), Some("LOGIN_APPROVAL_PENDING"));
+
+        // The owner approves from the link in their mailbox
+        let token = ctx.mailer.token_for(&user.user.email);
+        post_json(&app, "/auth/login-approval/approve", json!({ "token": token }))
+            .await
+            .assert_success();
+
+        let response = post_json(&app, &complete_path, json!({})).await.assert_success();
+        assert!(!response.field("access_token").unwrap().is_empty());
+        // Approval doesn't make the risky login's session any longer
+        let risky = ctx.config.session_lifetime.risky_access_token_expiry.min(ctx.config.jwt.access_token_expiry);
+        assert_eq!(response.body["expires_in"], risky);
+
+        // Each approval signs in once
+        let response = post_json(&app, &complete_path, json!({})).await;
+        assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[actix_web::test]
+    async fn test_approved_logins_are_refused_while_logins_are_frozen() {
+        let mut config = crate::test_utils::test_config();
+        config.login_approval.enabled = true;
+        config.ip_reputation.denylist = vec!["203.0.113.7".to_string()];
+        config.ip_reputation.weight = 80;
+        let ctx = TestContext::with_config(config);
+        let app = ctx.spawn_app().await;
+        let admin = ctx.user().admin().create().await.unwrap();
+        let user = ctx.user().create().await.unwrap();
+
+        let request = test::TestRequest::post()
+            .uri("/auth/login")
+            .insert_header(("X-Forwarded-For", "203.0.113.7"))
+            .set_json(json!({ "username_or_email": user.user.username, "password": user.password }))
+            .to_request();
+        let held: Value = test::call_and_read_body_json(&app, request).await;
+        let approval_id = held["approval_id"].as_str().expect("login wasn't held for approval").to_string();
+        let token = ctx.mailer.token_for(&user.user.email);
+        post_json(&app, "/auth/login-approval/approve", json!({ "token": token }))
+            .await
+            .assert_success();
+
+        // Logins were frozen while this one was held
+        let freeze = serde_json::from_value(json!({})).unwrap();
+        ctx.auth_service.freeze_logins(admin.id(), None, freeze).await.unwrap();
+
+        let complete_path = format!("/auth/login-approval/{}/complete", approval_id);
+        let response = post_json(&app, &complete_path, json!({})).await;
+        assert_eq!(response.status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.field("code"), Some("LOGINS_FROZEN"));
+    }
+
+    #[actix_web::test]
+    async fn test_moderate_risk_login_needs_a_solved_captcha() {
+        let mut config = crate::test_utils::test_config();
+        config.ip_reputation.denylist = vec!["203.0.113.7".to_string()];
+        config.ip_reputation.weight = 40;
+        let ctx = TestContext::with_config(config);
+        let app = ctx.spawn_app().await;
+        let user = ctx.user().create().await.unwrap();
+
+        let login_with = |captcha: Value| {
+            let mut body = json!({ "username_or_email": user.user.username, "password": user.password });
+            body.as_object_mut().unwrap().extend(captcha.as_object().unwrap().clone());
+            test::TestRequest::post()
+                .uri("/auth/login")
+                .insert_header(("X-Forwarded-For", "203.0.113.7"))
+                .set_json(body)
+                .to_request()
+        };
+        // Without a CAPTCHA preference the challenge is "What is a plus b?"
+        let challenge = |body: &Value| {
+            let challenge = &body["captcha_challenge"];
+            let prompt = challenge["prompt"].as_str().expect("no challenge offered");
+            let sum: u32 = prompt
+                .trim_start_matches("What is ")
+                .trim_end_matches('?')
+                .split(" plus ")
+                .map(|n| n.parse::<u32>().unwrap())
+                .sum();
+            (challenge["id"].clone(), sum)
+        };
+
+        // The right password alone isn't enough from a flagged address
+        let body: Value = test::call_and_read_body_json(&app, login_with(json!({}))).await;
+        assert_eq!(body["code"], "CAPTCHA_REQUIRED");
+        let (captcha_id, sum) = challenge(&body);
+
+        let wrong = json!({ "captcha_id": captcha_id, "captcha_answer": (sum + 1).to_string() });
+        let body: Value = test::call_and_read_body_json(&app, login_with(wrong)).await;
+        assert_eq!(body["code"], "INVALID_CAPTCHA");
+
+        let body: Value = test::call_and_read_body_json(&app, login_with(json!({}))).await;
+        let (captcha_id, sum) = challenge(&body);
+        let solved = json!({ "captcha_id": captcha_id, "captcha_answer": sum.to_string() });
+        let response = test::call_service(&app, login_with(solved)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: Value = test::read_body_json(response).await;
+        assert!(!body["access_token"].as_str().unwrap().is_empty());
+    }
+
+    #[actix_web::test]
+    async fn test_risky_logins_get_shortened_sessions() {
+        let mut config = crate::test_utils::test_config();
+        config.ip_reputation.denylist = vec!["203.0.113.7".to_string()];
+        config.ip_reputation.weight = 60;
+        config.jwt.access_token_expiry = 900;
+        config.session_lifetime.risky_access_token_expiry = 300;
+        let ctx = TestContext::with_config(config);
+        let app = ctx.spawn_app().await;
+        let user = ctx.user().with_mfa().create().await.unwrap();
+
+        let mfa_login_from = |ip: &str, code: String| {
+            test::TestRequest::post()
+                .uri("/auth/mfa-login")
+                .insert_header(("X-Forwarded-For", ip))
+                .set_json(json!({
+                    "username_or_email": user.user.username,
+                    "password": user.password,
+                    "mfa_code": code,
+                }))
+                .to_request()
+        };
+
+        let body: Value =
+            test::call_and_read_body_json(&app, mfa_login_from("198.51.100.7", user.totp_code(&ctx).unwrap())).await;
+        assert_eq!(body["expires_in"], 900);
+
+        // The second factor gets an elevated-risk login through, for less time
+        let body: Value =
+            test::call_and_read_body_json(&app, mfa_login_from("203.0.113.7", user.totp_code(&ctx).unwrap())).await;
+        assert_eq!(body["expires_in"], 300, "{}", body);
+    }
+
+    #[actix_web::test]
+    async fn test_failures_reset_only_after_the_second_factor() {
+        let mut config = crate::test_utils::test_config();
+        config.brute_force.enabled = true;
+        config.brute_force.account_captcha_after = 0;
+        config.brute_force.account_lockout_after = 0;
+        config.mfa_attempts.max_failures = 3;
+        let ctx = TestContext::with_config(config);
+        let app = ctx.spawn_app().await;
+        let user = ctx.user().with_mfa().create().await.unwrap();
+
+        for _ in 0..2 {
+            let response = login(&app, &user.user.username, "WrongPass123!").await;
+            assert_eq!(response.field("code"), Some("INVALID_CREDENTIALS"));
+        }
+
+        // The password alone doesn't wipe the slate
+        let response = login(&app, &user.user.username, &user.password).await.assert_success();
+        assert_eq!(response.body["mfa_required"], true);
+        let pending = response.field("access_token").unwrap().to_string();
+        assert_eq!(ctx.db.find_user_by_id(user.id()).await.unwrap().failed_login_count, 2);
+
+        let verify = |code: String| {
+            test::TestRequest::post()
+                .uri("/auth/mfa-verify")
+                .insert_header(("Authorization", format!("Bearer {}", pending)))
+                .set_json(json!({ "mfa_code": code }))
+                .to_request()
+        };
+        let code = user.totp_code(&ctx).unwrap();
+        let wrong = if code == "000000" { "000001" } else { "000000" };
+        for _ in 0..3 {
+            let response = test::call_service(&app, verify(wrong.to_string())).await;
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        }
+
+        // Out of attempts: even the right code waits for the window to pass
+        let response = test::call_service(&app, verify(code.clone())).await;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert!(response.headers().contains_key("Retry-After"));
+
+        ctx.db.clear_mfa_failures(user.id()).await.unwrap();
+        let response = test::call_service(&app, verify(code)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(ctx.db.find_user_by_id(user.id()).await.unwrap().failed_login_count, 0);
+    }
+
+    #[actix_web::test]
+    async fn test_clients_can_only_ask_for_the_audio_captcha() {
+        let ctx = TestContext::new();
+        let app = ctx.spawn_app().await;
+
+        let response = post_json(&app, "/auth/captcha", json!({ "kind": "LogicPuzzle" })).await;
+        assert_eq!(response.status, StatusCode::BAD_REQUEST);
+        assert_eq!(response.field("code"), Some("VALIDATION_ERROR"));
+
+        // Without recordings loaded the audio challenge falls back to a written one
+        let response = post_json(&app, "/auth/captcha", json!({ "kind": "Audio" })).await.assert_success();
+        assert_eq!(response.field("kind"), Some("SimpleMath"));
+    }
+}
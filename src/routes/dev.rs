@@ -0,0 +1,17 @@
+use actix_web::{web, HttpResponse};
+
+use crate::errors::AuthError;
+use crate::services::auth::AuthService;
+
+// Development helpers; every handler refuses unless `DEV_SEED_ENABLED` is set
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(web::scope("/dev").service(seed));
+}
+
+/// Create demo accounts in every state the sign-in flows handle
+#[actix_web::post("/seed")]
+async fn seed(auth_service: web::Data<AuthService>) -> Result<HttpResponse, AuthError> {
+    let report = auth_service.seed_demo_data().await?;
+    
+    Ok(HttpResponse::Ok().json(report))
+}
@@ -0,0 +1,25 @@
+use actix_web::{web, HttpResponse};
+
+use crate::errors::AuthError;
+use crate::services::auth::AuthService;
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(web::scope("/media").service(get_media));
+}
+
+/// Public avatar images, when they're kept on local disk
+#[actix_web::get("/{key:.*}")]
+async fn get_media(
+    auth_service: web::Data<AuthService>,
+    key: web::Path<String>,
+) -> Result<HttpResponse, AuthError> {
+    Ok(match auth_service.avatar_file(&key).await? {
+        // Every key is unique to one upload, so the content never changes
+        Some(data) => HttpResponse::Ok()
+            .content_type("image/png")
+            .insert_header(("Cache-Control", "public, max-age=31536000, immutable"))
+            .insert_header(("X-Content-Type-Options", "nosniff"))
+            .body(data),
+        None => HttpResponse::NotFound().finish(),
+    })
+}
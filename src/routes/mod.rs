@@ -1,2 +1,9 @@
+pub mod admin;
+pub mod admin_ui;
 pub mod auth;
+pub mod dev;
+pub mod media;
+pub mod oauth;
+pub mod organizations;
+pub mod pages;
 pub mod users;
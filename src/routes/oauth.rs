@@ -0,0 +1,245 @@
+use actix_web::http::{header, StatusCode};
+use actix_web::{web, HttpRequest, HttpResponse};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde::Deserialize;
+
+use crate::errors::AuthError;
+use crate::models::TokenTypeHint;
+use crate::services::auth::AuthService;
+use crate::utils::secret::Secret;
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(web::scope("/oauth").service(revoke));
+}
+
+#[derive(Deserialize)]
+struct RevokeForm {
+    token: Option<Secret<String>>,
+    token_type_hint: Option<String>,
+    // Only for clients that don't authenticate with HTTP Basic
+    client_id: Option<String>,
+    client_secret: Option<Secret<String>>,
+}
+
+/// Token revocation for OAuth clients (RFC 7009). Answers 200 whether or not
+/// the token was valid, so only client errors are reported.
+#[actix_web::post("/revoke")]
+async fn revoke(
+    req: HttpRequest,
+    auth_service: web::Data<AuthService>,
+    form: web::Form<RevokeForm>,
+) -> Result<HttpResponse, AuthError> {
+    let form = form.into_inner();
+
+    let basic = basic_credentials(&req);
+    let (client_id, client_secret) = match (&basic, &form.client_id) {
+        (Some((id, secret)), None) => (id.as_str(), Some(secret.as_str())),
+        (None, Some(id)) => (id.as_str(), form.client_secret.as_ref().map(|s| s.expose().as_str())),
+        // Exactly one way of authenticating may be used
+        _ => return Ok(oauth_error(StatusCode::UNAUTHORIZED, "invalid_client")),
+    };
+    if !auth_service.authenticate_oauth_client(client_id, client_secret).await? {
+        return Ok(oauth_error(StatusCode::UNAUTHORIZED, "invalid_client"));
+    }
+
+    let token = match form.token.as_ref().map(|t| t.expose().trim()) {
+        Some(token) if !token.is_empty() => token,
+        _ => return Ok(oauth_error(StatusCode::BAD_REQUEST, "invalid_request")),
+    };
+    let hint = match form.token_type_hint.as_deref() {
+        None => None,
+        Some("access_token") => Some(TokenTypeHint::AccessToken),
+        Some("refresh_token") => Some(TokenTypeHint::RefreshToken),
+        Some(_) => return Ok(oauth_error(StatusCode::BAD_REQUEST, "unsupported_token_type")),
+    };
+
+    auth_service.revoke_oauth_token(client_id, token, hint).await?;
+
+    Ok(HttpResponse::Ok().insert_header(("Cache-Control", "no-store")).finish())
+}
+
+// Errors in the RFC 6749 format OAuth clients expect, rather than problem details
+fn oauth_error(status: StatusCode, error: &str) -> HttpResponse {
+    let mut response = HttpResponse::build(status);
+    if status == StatusCode::UNAUTHORIZED {
+        response.insert_header((header::WWW_AUTHENTICATE, "Basic realm=\"oauth\""));
+    }
+    response
+        .insert_header(("Cache-Control", "no-store"))
+        .json(serde_json::json!({ "error": error }))
+}
+
+// Client id and secret from `Authorization: Basic`, each form-encoded before
+// being joined (RFC 6749 section 2.3.1)
+fn basic_credentials(req: &HttpRequest) -> Option<(String, String)> {
+    let value = req.headers().get(header::AUTHORIZATION)?.to_str().ok()?;
+    let (scheme, encoded) = value.split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("basic") {
+        return None;
+    }
+
+    let decoded = String::from_utf8(STANDARD.decode(encoded.trim()).ok()?).ok()?;
+    let (id, secret) = decoded.split_once(':')?;
+    Some((form_decode(id), form_decode(secret)))
+}
+
+fn form_decode(value: &str) -> String {
+    url::form_urlencoded::parse(format!("v={}", value).as_bytes())
+        .next()
+        .map(|(_, decoded)| decoded.into_owned())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::StatusCode;
+    use actix_web::test;
+    use base64::Engine;
+    use serde_json::Value;
+
+    use crate::models::{client_secret_hash, ClientApplicationRequest, NewClientApplication};
+    use crate::test_utils::TestContext;
+
+    fn client(client_id: &str, secret: Option<&str>) -> NewClientApplication {
+        NewClientApplication {
+            client_id: client_id.to_string(),
+            name: client_id.to_string(),
+            redirect_uris: vec!["https://app.example/callback".to_string()],
+            allowed_grants: vec!["password".to_string()],
+            access_token_ttl: None,
+            refresh_token_ttl: None,
+            logo_url: None,
+            updated_by: None,
+            first_party: true,
+            client_secret_hash: secret.map(client_secret_hash),
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_oauth_revocation_ends_sessions_and_tokens() {
+        let ctx = TestContext::new();
+        let client_id = ctx.config.jwt.audience.clone();
+        ctx.db.save_client_application(client(&client_id, Some("s3cret"))).await.unwrap();
+        let app = ctx.spawn_app().await;
+        let user = ctx.user().create().await.unwrap();
+        let by_refresh = ctx.session(&user).create().await.unwrap();
+        let by_access = ctx.session(&user).create().await.unwrap();
+
+        let basic = |secret: &str| {
+            let credentials = format!("{}:{}", client_id, secret);
+            format!("Basic {}", base64::engine::general_purpose::STANDARD.encode(credentials))
+        };
+        let revoke = |secret: &str, token: &str, hint: &str| {
+            test::TestRequest::post()
+                .uri("/oauth/revoke")
+                .insert_header(("Authorization", basic(secret)))
+                .set_form([("token", token), ("token_type_hint", hint)])
+                .to_request()
+        };
+        let get_me = |token: &str| {
+            test::TestRequest::get()
+                .uri("/users/me")
+                .insert_header(("Authorization", format!("Bearer {}", token)))
+                .to_request()
+        };
+
+        let response = test::call_service(&app, revoke("wrong", &by_refresh.refresh_token, "refresh_token")).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let body: Value = test::read_body_json(response).await;
+        assert_eq!(body["error"], "invalid_client");
+
+        // A confidential client can't leave its secret out
+        let without_secret = test::TestRequest::post()
+            .uri("/oauth/revoke")
+            .set_form([("token", by_refresh.refresh_token.as_str()), ("client_id", client_id.as_str())])
+            .to_request();
+        let response = test::call_service(&app, without_secret).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert!(!ctx.db.find_session_by_id(by_refresh.session.id).await.unwrap().is_revoked);
+
+        // A refresh token takes its session, and the session's access tokens, with it
+        let response = test::call_service(&app, revoke("s3cret", &by_refresh.refresh_token, "refresh_token")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(ctx.db.find_session_by_id(by_refresh.session.id).await.unwrap().is_revoked);
+        let response = test::call_service(&app, get_me(&by_refresh.access_token)).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        // An access token goes on its own
+        let response = test::call_service(&app, revoke("s3cret", &by_access.access_token, "access_token")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = test::call_service(&app, get_me(&by_access.access_token)).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert!(!ctx.db.find_session_by_id(by_access.session.id).await.unwrap().is_revoked);
+
+        // Unknown tokens get the same answer
+        let response = test::call_service(&app, revoke("s3cret", "not-a-token", "refresh_token")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = test::call_service(&app, revoke("s3cret", "not-a-token", "id_token")).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_web::test]
+    async fn test_oauth_clients_must_be_registered() {
+        let ctx = TestContext::new();
+        ctx.db.save_client_application(client("cli", None)).await.unwrap();
+        let app = ctx.spawn_app().await;
+
+        let revoke = |client_id: &str, client_secret: Option<&str>| {
+            let mut form = vec![("token", "not-a-token"), ("client_id", client_id)];
+            form.extend(client_secret.map(|secret| ("client_secret", secret)));
+            test::TestRequest::post().uri("/oauth/revoke").set_form(form).to_request()
+        };
+
+        // A public client is known by its id alone
+        let response = test::call_service(&app, revoke("cli", None)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = test::call_service(&app, revoke("cli", Some("guess"))).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        // Accepted audiences that were never registered aren't clients
+        let audience = ctx.config.jwt.audience.clone();
+        let response = test::call_service(&app, revoke(&audience, None)).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let response = test::call_service(&app, revoke("unknown", None)).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[actix_web::test]
+    async fn test_confidential_clients_get_a_secret_once() {
+        let ctx = TestContext::new();
+        let admin = ctx.user().admin().create().await.unwrap();
+        let registration = |confidential: bool, rotate_secret: bool| ClientApplicationRequest {
+            name: "Backend".to_string(),
+            redirect_uris: vec!["https://backend.example/callback".to_string()],
+            allowed_grants: vec!["refresh_token".to_string()],
+            access_token_ttl: None,
+            refresh_token_ttl: None,
+            logo_url: None,
+            first_party: true,
+            confidential,
+            rotate_secret,
+        };
+        let save = |confidential: bool, rotate_secret: bool| {
+            ctx.auth_service
+                .save_client_application(admin.id(), "backend", registration(confidential, rotate_secret))
+        };
+
+        let saved = save(true, false).await.unwrap();
+        let secret = saved.client_secret.expect("a new confidential client gets a secret");
+        assert!(ctx.auth_service.authenticate_oauth_client("backend", Some(&secret)).await.unwrap());
+        assert!(!ctx.auth_service.authenticate_oauth_client("backend", None).await.unwrap());
+
+        // Saving again keeps the secret, without showing it
+        assert!(save(true, false).await.unwrap().client_secret.is_none());
+        assert!(ctx.auth_service.authenticate_oauth_client("backend", Some(&secret)).await.unwrap());
+
+        let rotated = save(true, true).await.unwrap().client_secret.unwrap();
+        assert!(!ctx.auth_service.authenticate_oauth_client("backend", Some(&secret)).await.unwrap());
+        assert!(ctx.auth_service.authenticate_oauth_client("backend", Some(&rotated)).await.unwrap());
+
+        // Made public, it no longer has one
+        assert!(save(false, false).await.unwrap().client_secret.is_none());
+        assert!(ctx.auth_service.authenticate_oauth_client("backend", None).await.unwrap());
+    }
+}
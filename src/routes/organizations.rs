@@ -0,0 +1,320 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use validator::Validate;
+
+use crate::errors::AuthError;
+use crate::middleware::auth::{AuthenticatedUser, RequireScope};
+use crate::middleware::step_up::{StepUpMiddleware, StepUpPolicy};
+use crate::models::{
+    AddOrganizationDomainRequest, CreateOrganizationRequest, InviteMemberRequest,
+    LoginPolicyRequest, OrganizationBrandingRequest, SsoConnectionRequest, UpdateOrganizationDomainRequest,
+};
+use crate::services::auth::AuthService;
+use crate::utils::http_cache;
+use crate::utils::i18n::Locale;
+use crate::utils::scopes::{ORGANIZATIONS_READ, ORGANIZATIONS_WRITE};
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("/organizations")
+            .service(create_organization)
+            .service(list_organizations)
+            .service(invite_member)
+            .service(list_domains)
+            .service(add_domain)
+            .service(verify_domain)
+            .service(update_domain)
+            .service(remove_domain)
+            .service(get_sso_connection)
+            .service(save_sso_connection)
+            .service(delete_sso_connection)
+            .service(get_branding)
+            .service(save_branding)
+            .service(get_login_policy)
+            .service(save_login_policy)
+            .service(delete_login_policy),
+    );
+}
+
+/// Create an organization owned by the caller
+#[actix_web::post("", wrap = "RequireScope(ORGANIZATIONS_WRITE)")]
+async fn create_organization(
+    auth_service: web::Data<AuthService>,
+    user: web::ReqData<AuthenticatedUser>,
+    organization_data: web::Json<CreateOrganizationRequest>,
+) -> Result<HttpResponse, AuthError> {
+    organization_data.validate()?;
+    
+    let response = auth_service
+        .create_organization(user.user_id, organization_data.into_inner())
+        .await?;
+    
+    Ok(HttpResponse::Created().json(response))
+}
+
+/// Organizations the caller belongs to, with their role in each
+#[actix_web::get("", wrap = "RequireScope(ORGANIZATIONS_READ)")]
+async fn list_organizations(
+    auth_service: web::Data<AuthService>,
+    user: web::ReqData<AuthenticatedUser>,
+) -> Result<HttpResponse, AuthError> {
+    let response = auth_service.list_organizations(user.user_id).await?;
+    
+    Ok(HttpResponse::Ok().json(response))
+}
+
+/// Create an account for someone joining the organization; they set its password by email
+#[actix_web::post("/{organization_id}/invitations", wrap = "RequireScope(ORGANIZATIONS_WRITE)")]
+async fn invite_member(
+    auth_service: web::Data<AuthService>,
+    user: web::ReqData<AuthenticatedUser>,
+    organization_id: web::Path<uuid::Uuid>,
+    invite_data: web::Json<InviteMemberRequest>,
+    locale: web::ReqData<Locale>,
+) -> Result<HttpResponse, AuthError> {
+    invite_data.validate()?;
+    
+    let response = auth_service
+        .invite_organization_member(
+            user.user_id,
+            organization_id.into_inner(),
+            invite_data.into_inner(),
+            &locale.0,
+        )
+        .await?;
+    
+    Ok(HttpResponse::Created().json(response))
+}
+
+/// Domains claimed by the organization, with the TXT record each must publish
+#[actix_web::get("/{organization_id}/domains", wrap = "RequireScope(ORGANIZATIONS_READ)")]
+async fn list_domains(
+    auth_service: web::Data<AuthService>,
+    user: web::ReqData<AuthenticatedUser>,
+    organization_id: web::Path<uuid::Uuid>,
+) -> Result<HttpResponse, AuthError> {
+    let response = auth_service
+        .list_organization_domains(user.user_id, *organization_id)
+        .await?;
+    
+    Ok(HttpResponse::Ok().json(response))
+}
+
+#[actix_web::post(
+    "/{organization_id}/domains",
+    wrap = "StepUpMiddleware(StepUpPolicy::signed_in_within(300))",
+    wrap = "RequireScope(ORGANIZATIONS_WRITE)"
+)]
+async fn add_domain(
+    auth_service: web::Data<AuthService>,
+    user: web::ReqData<AuthenticatedUser>,
+    organization_id: web::Path<uuid::Uuid>,
+    domain_data: web::Json<AddOrganizationDomainRequest>,
+) -> Result<HttpResponse, AuthError> {
+    domain_data.validate()?;
+    
+    let response = auth_service
+        .add_organization_domain(user.user_id, *organization_id, domain_data.into_inner())
+        .await?;
+    
+    Ok(HttpResponse::Created().json(response))
+}
+
+/// Check the domain's TXT record now
+#[actix_web::post(
+    "/{organization_id}/domains/{domain_id}/verify",
+    wrap = "StepUpMiddleware(StepUpPolicy::signed_in_within(300))",
+    wrap = "RequireScope(ORGANIZATIONS_WRITE)"
+)]
+async fn verify_domain(
+    auth_service: web::Data<AuthService>,
+    user: web::ReqData<AuthenticatedUser>,
+    path: web::Path<(uuid::Uuid, uuid::Uuid)>,
+) -> Result<HttpResponse, AuthError> {
+    let (organization_id, domain_id) = path.into_inner();
+    let response = auth_service
+        .verify_organization_domain(user.user_id, organization_id, domain_id)
+        .await?;
+    
+    Ok(HttpResponse::Ok().json(response))
+}
+
+#[actix_web::patch(
+    "/{organization_id}/domains/{domain_id}",
+    wrap = "StepUpMiddleware(StepUpPolicy::signed_in_within(300))",
+    wrap = "RequireScope(ORGANIZATIONS_WRITE)"
+)]
+async fn update_domain(
+    auth_service: web::Data<AuthService>,
+    user: web::ReqData<AuthenticatedUser>,
+    path: web::Path<(uuid::Uuid, uuid::Uuid)>,
+    domain_data: web::Json<UpdateOrganizationDomainRequest>,
+) -> Result<HttpResponse, AuthError> {
+    let (organization_id, domain_id) = path.into_inner();
+    let response = auth_service
+        .update_organization_domain(user.user_id, organization_id, domain_id, domain_data.into_inner())
+        .await?;
+    
+    Ok(HttpResponse::Ok().json(response))
+}
+
+#[actix_web::delete(
+    "/{organization_id}/domains/{domain_id}",
+    wrap = "StepUpMiddleware(StepUpPolicy::signed_in_within(300))",
+    wrap = "RequireScope(ORGANIZATIONS_WRITE)"
+)]
+async fn remove_domain(
+    auth_service: web::Data<AuthService>,
+    user: web::ReqData<AuthenticatedUser>,
+    path: web::Path<(uuid::Uuid, uuid::Uuid)>,
+) -> Result<HttpResponse, AuthError> {
+    let (organization_id, domain_id) = path.into_inner();
+    let response = auth_service
+        .remove_organization_domain(user.user_id, organization_id, domain_id)
+        .await?;
+    
+    Ok(HttpResponse::Ok().json(response))
+}
+
+#[actix_web::get("/{organization_id}/sso", wrap = "RequireScope(ORGANIZATIONS_READ)")]
+async fn get_sso_connection(
+    auth_service: web::Data<AuthService>,
+    user: web::ReqData<AuthenticatedUser>,
+    organization_id: web::Path<uuid::Uuid>,
+) -> Result<HttpResponse, AuthError> {
+    let response = auth_service
+        .get_sso_connection(user.user_id, *organization_id)
+        .await?;
+    
+    Ok(HttpResponse::Ok().json(response))
+}
+
+/// Configure the organization's OIDC or SAML identity provider (org admins only)
+#[actix_web::put(
+    "/{organization_id}/sso",
+    wrap = "StepUpMiddleware(StepUpPolicy::signed_in_within(300))",
+    wrap = "RequireScope(ORGANIZATIONS_WRITE)"
+)]
+async fn save_sso_connection(
+    auth_service: web::Data<AuthService>,
+    user: web::ReqData<AuthenticatedUser>,
+    organization_id: web::Path<uuid::Uuid>,
+    connection_data: web::Json<SsoConnectionRequest>,
+) -> Result<HttpResponse, AuthError> {
+    connection_data.validate()?;
+    
+    let response = auth_service
+        .save_sso_connection(user.user_id, *organization_id, connection_data.into_inner())
+        .await?;
+    
+    Ok(HttpResponse::Ok().json(response))
+}
+
+#[actix_web::delete(
+    "/{organization_id}/sso",
+    wrap = "StepUpMiddleware(StepUpPolicy::signed_in_within(300))",
+    wrap = "RequireScope(ORGANIZATIONS_WRITE)"
+)]
+async fn delete_sso_connection(
+    auth_service: web::Data<AuthService>,
+    user: web::ReqData<AuthenticatedUser>,
+    organization_id: web::Path<uuid::Uuid>,
+) -> Result<HttpResponse, AuthError> {
+    let response = auth_service
+        .delete_sso_connection(user.user_id, *organization_id)
+        .await?;
+    
+    Ok(HttpResponse::Ok().json(response))
+}
+
+/// Answers `If-None-Match` and `If-Modified-Since` with 304, for SDKs that poll it
+#[actix_web::get("/{organization_id}/branding", wrap = "RequireScope(ORGANIZATIONS_READ)")]
+async fn get_branding(
+    req: HttpRequest,
+    auth_service: web::Data<AuthService>,
+    user: web::ReqData<AuthenticatedUser>,
+    organization_id: web::Path<uuid::Uuid>,
+) -> Result<HttpResponse, AuthError> {
+    let response = auth_service
+        .get_organization_branding(user.user_id, *organization_id)
+        .await?;
+    let body = serde_json::to_vec(&response)
+        .map_err(|e| AuthError::InternalServerError(format!("Failed to encode response: {}", e)))?;
+    
+    Ok(http_cache::respond(
+        &req,
+        HttpResponse::Ok()
+            .content_type("application/json")
+            // Only for members, and revalidated so a change shows at once
+            .insert_header(("Cache-Control", "private, no-cache")),
+        body,
+        response.updated_at,
+    ))
+}
+
+/// Set the name, logo and color of the organization's hosted sign-in pages (org admins only)
+#[actix_web::put("/{organization_id}/branding", wrap = "RequireScope(ORGANIZATIONS_WRITE)")]
+async fn save_branding(
+    auth_service: web::Data<AuthService>,
+    user: web::ReqData<AuthenticatedUser>,
+    organization_id: web::Path<uuid::Uuid>,
+    branding_data: web::Json<OrganizationBrandingRequest>,
+) -> Result<HttpResponse, AuthError> {
+    branding_data.validate()?;
+    
+    let response = auth_service
+        .save_organization_branding(user.user_id, *organization_id, branding_data.into_inner())
+        .await?;
+    
+    Ok(HttpResponse::Ok().json(response))
+}
+
+#[actix_web::get("/{organization_id}/login-policy", wrap = "RequireScope(ORGANIZATIONS_READ)")]
+async fn get_login_policy(
+    auth_service: web::Data<AuthService>,
+    user: web::ReqData<AuthenticatedUser>,
+    organization_id: web::Path<uuid::Uuid>,
+) -> Result<HttpResponse, AuthError> {
+    let response = auth_service
+        .get_organization_login_policy(user.user_id, *organization_id)
+        .await?;
+    
+    Ok(HttpResponse::Ok().json(response))
+}
+
+/// Restrict where and when members may sign in (org admins only)
+#[actix_web::put(
+    "/{organization_id}/login-policy",
+    wrap = "StepUpMiddleware(StepUpPolicy::signed_in_within(300))",
+    wrap = "RequireScope(ORGANIZATIONS_WRITE)"
+)]
+async fn save_login_policy(
+    auth_service: web::Data<AuthService>,
+    user: web::ReqData<AuthenticatedUser>,
+    organization_id: web::Path<uuid::Uuid>,
+    policy_data: web::Json<LoginPolicyRequest>,
+) -> Result<HttpResponse, AuthError> {
+    policy_data.validate()?;
+    
+    let response = auth_service
+        .save_organization_login_policy(user.user_id, *organization_id, policy_data.into_inner())
+        .await?;
+    
+    Ok(HttpResponse::Ok().json(response))
+}
+
+#[actix_web::delete(
+    "/{organization_id}/login-policy",
+    wrap = "StepUpMiddleware(StepUpPolicy::signed_in_within(300))",
+    wrap = "RequireScope(ORGANIZATIONS_WRITE)"
+)]
+async fn delete_login_policy(
+    auth_service: web::Data<AuthService>,
+    user: web::ReqData<AuthenticatedUser>,
+    organization_id: web::Path<uuid::Uuid>,
+) -> Result<HttpResponse, AuthError> {
+    let response = auth_service
+        .delete_organization_login_policy(user.user_id, *organization_id)
+        .await?;
+    
+    Ok(HttpResponse::Ok().json(response))
+}
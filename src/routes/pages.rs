@@ -0,0 +1,1351 @@
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpRequest, HttpResponse};
+use askama::Template;
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use serde::Deserialize;
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::accessibility::CaptchaChallenge;
+use crate::build_info::build_info;
+use crate::errors::AuthError;
+use crate::models::{
+    CaptchaChallengeRequest, ClientApplication, LoginRequest, LoginResponse, MfaVerifyResponse, PasswordResetConfirmRequest,
+    PasswordResetRequest, RegisterRequest, VerifyMfaRequest, GRANT_REFRESH_TOKEN,
+};
+use crate::services::auth::AuthService;
+use crate::utils::http_cache;
+use crate::utils::i18n::{Locale, Translator};
+use crate::utils::secret::Secret;
+
+// Sign-in pages for deployments without a frontend of their own, served when
+// `HOSTED_PAGES_ENABLED` is set. Each page is also served under
+// `/pages/o/{org}/`, which shows that organization's branding. They call
+// `AuthService` directly, and once the user is signed in, post the tokens to
+// `HOSTED_PAGES_RETURN_URL`, or for a sign-in opened with `client_id` and
+// `redirect_uri`, to that registered client's redirect URI. Nothing is kept
+// between requests but what the forms carry.
+const PAGES_CSS: &str = include_str!("../../templates/pages/pages.css");
+const PAGES_JS: &str = include_str!("../../templates/pages/pages.js");
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("/pages")
+            .service(asset)
+            .service(login_page)
+            .service(login)
+            .service(register_page)
+            .service(register)
+            .service(mfa_page)
+            .service(mfa)
+            .service(consent)
+            .service(forgot_password_page)
+            .service(forgot_password)
+            .service(reset_password_page)
+            .service(reset_password),
+    );
+}
+
+/// What every page shows around its form
+struct Layout {
+    title: &'static str,
+    brand_name: String,
+    logo_url: Option<String>,
+    primary_color: Option<String>, // `#rrggbb`, checked when the branding was saved
+    base: String, // Where the page's links and forms point, e.g. `/pages/o/acme`
+    css: String,
+    nonce: String, // Lets the page's own `<style>` through the CSP, and nothing else inline
+    error: Option<String>,
+    return_url: String, // Where the tokens are posted once the user is signed in
+    client: Option<ClientRedirect>,
+}
+
+/// The registered client a sign-in was opened for. Carried in the query
+/// string of every form and link, so it lasts through each step.
+struct ClientRedirect {
+    application: ClientApplication,
+    redirect_uri: String,        // One of the client's own, checked when the page was opened
+    scopes: Option<Vec<String>>, // Asked for in `scope`; the user's own when left out
+}
+
+#[derive(Default, Deserialize)]
+struct ClientQuery {
+    client_id: Option<String>,
+    #[serde(default)]
+    redirect_uri: String,
+    #[serde(default)]
+    scope: String, // Space-separated, as in OAuth
+}
+
+impl Layout {
+    /// `None` when the pages are off, or opened for an organization that
+    /// doesn't exist. Opened for a client, an error unless it's registered
+    /// with the redirect URI given; the tokens never go anywhere else.
+    async fn new(auth_service: &AuthService, req: &HttpRequest, title: &'static str) -> Result<Option<Self>, AuthError> {
+        let config = auth_service.hosted_pages();
+        if !config.enabled {
+            return Ok(None);
+        }
+
+        let mut layout = Layout {
+            title,
+            brand_name: config.site_name.clone(),
+            logo_url: config.logo_url.clone(),
+            primary_color: None,
+            base: "/pages".to_string(),
+            css: String::new(),
+            nonce: rand::thread_rng()
+                .sample_iter(&Alphanumeric)
+                .take(24)
+                .map(char::from)
+                .collect(),
+            error: None,
+            return_url: config.return_url.clone(),
+            client: None,
+        };
+
+        if let Some(slug) = req.match_info().get("org") {
+            let (organization, branding) = match auth_service.organization_for_pages(slug).await? {
+                Some(found) => found,
+                None => return Ok(None),
+            };
+            layout.base = format!("/pages/o/{}", organization.slug);
+            layout.brand_name = organization.name;
+            if let Some(branding) = branding {
+                layout.brand_name = branding.display_name.unwrap_or(layout.brand_name);
+                layout.logo_url = branding.logo_url.or(layout.logo_url);
+                layout.primary_color = branding.primary_color;
+            }
+        }
+
+        let query = web::Query::<ClientQuery>::from_query(req.query_string())
+            .map(|query| query.into_inner())
+            .unwrap_or_default();
+        if let Some(client_id) = query.client_id {
+            let redirect_uri = query.redirect_uri;
+            let application = auth_service.client_for_redirect(&client_id, &redirect_uri).await?;
+            let scopes = auth_service.requested_client_scopes(&application, &query.scope)?;
+            layout.return_url = redirect_uri.clone();
+            layout.client = Some(ClientRedirect {
+                application,
+                redirect_uri,
+                scopes,
+            });
+        }
+
+        Ok(Some(layout.styled_for(auth_service, None)))
+    }
+
+    /// Query string for the page's forms and links, keeping the client the
+    /// sign-in was opened for
+    fn client_query(&self) -> String {
+        let client = match &self.client {
+            Some(client) => client,
+            None => return String::new(),
+        };
+
+        let mut query = url::form_urlencoded::Serializer::new(String::new());
+        query
+            .append_pair("client_id", &client.application.client_id)
+            .append_pair("redirect_uri", &client.redirect_uri);
+        if let Some(scopes) = &client.scopes {
+            query.append_pair("scope", &scopes.join(" "));
+        }
+        format!("?{}", query.finish())
+    }
+
+    /// Style the page from the user's accessibility preferences, or the
+    /// defaults before we know who they are. The brand color gives way to a
+    /// high contrast theme.
+    fn styled_for(mut self, auth_service: &AuthService, user_id: Option<Uuid>) -> Self {
+        let accessibility = auth_service.accessibility();
+        let (mut css, high_contrast) = match user_id {
+            Some(user_id) => (
+                accessibility.generate_css_variables(&user_id),
+                accessibility.get_preferences(&user_id).high_contrast,
+            ),
+            None => (accessibility.generate_default_css_variables(), false),
+        };
+
+        if let (Some(color), false) = (&self.primary_color, high_contrast) {
+            css.push_str(&format!(
+                ":root {{\n  --primary-color: {};\n  --focus-outline: 2px solid {};\n}}\n",
+                color, color
+            ));
+        }
+        self.css = css;
+        self
+    }
+
+    fn with_error(mut self, error: String) -> Self {
+        self.error = Some(error);
+        self
+    }
+}
+
+#[derive(Template)]
+#[template(path = "pages/login.html")]
+struct LoginPage {
+    layout: Layout,
+    username_or_email: String,
+    captcha: Option<CaptchaChallenge>,
+}
+
+#[derive(Template)]
+#[template(path = "pages/register.html")]
+struct RegisterPage {
+    layout: Layout,
+    username: String,
+    email: String,
+    captcha: Option<CaptchaChallenge>,
+}
+
+#[derive(Template)]
+#[template(path = "pages/mfa.html")]
+struct MfaPage {
+    layout: Layout,
+    mfa_token: String, // The `mfa_pending` token from the password step
+}
+
+#[derive(Template)]
+#[template(path = "pages/forgot_password.html")]
+struct ForgotPasswordPage {
+    layout: Layout,
+    email: String,
+}
+
+#[derive(Template)]
+#[template(path = "pages/reset_password.html")]
+struct ResetPasswordPage {
+    layout: Layout,
+    token: String,
+}
+
+#[derive(Template)]
+#[template(path = "pages/message.html")]
+struct MessagePage {
+    layout: Layout,
+    message: String,
+}
+
+/// Posts the tokens to the layout's return URL
+#[derive(Template)]
+#[template(path = "pages/signed_in.html")]
+struct SignedInPage {
+    layout: Layout,
+    access_token: String,
+    refresh_token: Option<String>, // Left out for clients not allowed to refresh
+    token_type: String,
+    expires_in: u64,
+    scope: Option<String>, // What a client was granted, when it asked
+    next: Option<&'static str>, // What the app must have the user do before the token is a full one
+}
+
+/// Asks whether a third-party client may have the scopes it asked for,
+/// carrying the tokens it gets if so
+#[derive(Template)]
+#[template(path = "pages/consent.html")]
+struct ConsentPage {
+    layout: Layout,
+    permissions: Vec<String>, // The scopes, described
+    access_token: String,
+    refresh_token: Option<String>,
+    token_type: String,
+    expires_in: u64,
+    next: Option<&'static str>,
+}
+
+/// Tells the client the user said no (`error=access_denied`)
+#[derive(Template)]
+#[template(path = "pages/consent_declined.html")]
+struct ConsentDeclinedPage {
+    layout: Layout,
+}
+
+impl SignedInPage {
+    fn from_login(layout: Layout, login: LoginResponse) -> Self {
+        let next = if login.password_change_required {
+            Some("change_password")
+        } else if login.mfa_enrollment_required {
+            Some("enroll_mfa")
+        } else if login.policy_acceptance_required.is_some() {
+            Some("accept_policy")
+        } else {
+            None
+        };
+
+        SignedInPage {
+            layout,
+            access_token: login.access_token,
+            refresh_token: Some(login.refresh_token),
+            token_type: login.token_type,
+            expires_in: login.expires_in,
+            scope: None,
+            next,
+        }
+    }
+
+    fn from_mfa(layout: Layout, verified: MfaVerifyResponse) -> Self {
+        let next = if verified.password_change_required {
+            Some("change_password")
+        } else if verified.policy_acceptance_required.is_some() {
+            Some("accept_policy")
+        } else {
+            None
+        };
+
+        SignedInPage {
+            layout,
+            access_token: verified.access_token,
+            refresh_token: Some(verified.refresh_token),
+            token_type: verified.token_type,
+            expires_in: verified.expires_in,
+            scope: None,
+            next,
+        }
+    }
+
+    /// Hold the new session to the policy of the client the sign-in was
+    /// opened for
+    async fn for_client(mut self, auth_service: &AuthService) -> Result<Self, AuthError> {
+        let (client, refresh_token) = match (&self.layout.client, &self.refresh_token) {
+            (Some(client), Some(refresh_token)) => (client, refresh_token),
+            _ => return Ok(self),
+        };
+
+        let (access_token, expires_in) = auth_service
+            .apply_client_policy(&client.application, client.scopes.as_deref(), &self.access_token, refresh_token)
+            .await?;
+        if !client.application.allows_grant(GRANT_REFRESH_TOKEN) {
+            self.refresh_token = None;
+        }
+        self.scope = client.scopes.as_ref().map(|scopes| scopes.join(" "));
+        self.access_token = access_token;
+        self.expires_in = expires_in;
+        Ok(self)
+    }
+
+    /// Hand the tokens over, or first ask the user about scopes a
+    /// third-party client hasn't been granted yet
+    async fn hand_over(self, auth_service: &AuthService, user_id: Uuid) -> Result<HttpResponse, AuthError> {
+        let client = match &self.layout.client {
+            Some(client) => client,
+            None => return render(StatusCode::OK, &self.layout, &self),
+        };
+        let scopes = client.scopes.as_deref();
+        if !auth_service.needs_client_consent(user_id, &client.application, scopes).await? {
+            return render(StatusCode::OK, &self.layout, &self);
+        }
+
+        let page = ConsentPage {
+            permissions: scopes.unwrap_or_default().iter().map(|scope| describe_scope(scope)).collect(),
+            layout: Layout { title: "Allow access?", ..self.layout },
+            access_token: self.access_token,
+            refresh_token: self.refresh_token,
+            token_type: self.token_type,
+            expires_in: self.expires_in,
+            next: self.next,
+        };
+        render(StatusCode::OK, &page.layout, &page)
+    }
+}
+
+// Forms post strings; an empty CAPTCHA field is the same as none
+#[derive(Deserialize)]
+struct LoginForm {
+    username_or_email: String,
+    password: Secret<String>,
+    #[serde(default)]
+    captcha_id: String,
+    #[serde(default)]
+    captcha_answer: String,
+}
+
+#[derive(Deserialize)]
+struct RegisterForm {
+    username: String,
+    email: String,
+    password: Secret<String>,
+    password_confirmation: Secret<String>,
+    #[serde(default)]
+    captcha_id: String,
+    #[serde(default)]
+    captcha_answer: String,
+}
+
+#[derive(Deserialize)]
+struct MfaForm {
+    mfa_token: String,
+    mfa_code: String,
+}
+
+// The consent page's tokens, posted back with the user's answer
+#[derive(Deserialize)]
+struct ConsentForm {
+    access_token: String,
+    #[serde(default)]
+    refresh_token: String,
+    token_type: String,
+    expires_in: u64,
+    #[serde(default)]
+    next: String,
+    decision: String, // "allow" or "deny"
+}
+
+#[derive(Deserialize)]
+struct ForgotPasswordForm {
+    email: String,
+}
+
+#[derive(Deserialize)]
+struct ResetPasswordQuery {
+    #[serde(default)]
+    token: String,
+}
+
+#[derive(Deserialize)]
+struct ResetPasswordForm {
+    token: Secret<String>,
+    password: Secret<String>,
+    password_confirmation: Secret<String>,
+    // Only asked for when unlocking a locked account
+    #[serde(default)]
+    mfa_code: String,
+}
+
+#[actix_web::get("/assets/{file}")]
+async fn asset(req: HttpRequest, auth_service: web::Data<AuthService>, file: web::Path<String>) -> HttpResponse {
+    if !auth_service.hosted_pages().enabled {
+        return HttpResponse::NotFound().finish();
+    }
+
+    let (content_type, body) = match file.as_str() {
+        "pages.css" => ("text/css; charset=utf-8", PAGES_CSS),
+        "pages.js" => ("text/javascript; charset=utf-8", PAGES_JS),
+        _ => return HttpResponse::NotFound().finish(),
+    };
+    http_cache::respond(
+        &req,
+        HttpResponse::Ok()
+            .content_type(content_type)
+            // Revalidated, so a deploy's new styles are picked up at once
+            .insert_header(("Cache-Control", "no-cache"))
+            .insert_header(("X-Content-Type-Options", "nosniff")),
+        body,
+        build_info().built_at, // Built into the binary
+    )
+}
+
+#[actix_web::routes]
+#[get("/login")]
+#[get("/o/{org}/login")]
+async fn login_page(auth_service: web::Data<AuthService>, req: HttpRequest) -> Result<HttpResponse, AuthError> {
+    let layout = match Layout::new(&auth_service, &req, "Sign in").await? {
+        Some(layout) => layout,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+
+    let page = LoginPage {
+        layout,
+        username_or_email: String::new(),
+        captcha: captcha_for(&auth_service, None).await?,
+    };
+    render(StatusCode::OK, &page.layout, &page)
+}
+
+#[actix_web::routes]
+#[post("/login")]
+#[post("/o/{org}/login")]
+async fn login(
+    auth_service: web::Data<AuthService>,
+    translator: web::Data<Translator>,
+    locale: web::ReqData<Locale>,
+    form: web::Form<LoginForm>,
+    req: HttpRequest,
+) -> Result<HttpResponse, AuthError> {
+    let layout = match Layout::new(&auth_service, &req, "Sign in").await? {
+        Some(layout) => layout,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+    ensure_same_origin(&req)?;
+
+    let form = form.into_inner();
+    let data = LoginRequest {
+        username_or_email: form.username_or_email.trim().to_string(),
+        password: form.password,
+        webauthn_supported: false,
+        device_token: None,
+        captcha_id: form.captcha_id.parse().ok(),
+        captcha_answer: Some(form.captcha_answer).filter(|answer| !answer.is_empty()),
+    };
+    let username_or_email = data.username_or_email.clone();
+
+    let ip = req.connection_info().realip_remote_addr()
+        .map(|s| s.to_string());
+
+    let user_agent = req.headers().get("User-Agent")
+        .and_then(|h| h.to_str().ok())
+        .map(|s| s.to_string());
+
+    let network = auth_service.network_fingerprint(req.headers());
+
+    let result = match data.validate() {
+        Ok(()) => auth_service.login(data, ip, user_agent, None, network, &locale.0).await,
+        Err(errors) => Err(errors.into()),
+    };
+
+    match result {
+        // Held until the owner follows the link in the approval email
+        Ok(response) if response.approval_id.is_some() => {
+            let page = MessagePage {
+                layout,
+                message: translator.text(&locale.0, "error-login-approval-pending", None),
+            };
+            render(StatusCode::OK, &page.layout, &page)
+        }
+        Ok(response) if response.mfa_required => {
+            let layout = Layout {
+                title: "Two-factor authentication",
+                ..layout
+            };
+            let page = MfaPage {
+                layout: layout.styled_for(&auth_service, Some(response.user.id)),
+                mfa_token: response.access_token,
+            };
+            render(StatusCode::OK, &page.layout, &page)
+        }
+        Ok(response) => {
+            let user_id = response.user.id;
+            let layout = Layout { title: "Signed in", ..layout }.styled_for(&auth_service, Some(user_id));
+            let page = SignedInPage::from_login(layout, response).for_client(&auth_service).await?;
+            page.hand_over(&auth_service, user_id).await
+        }
+        Err(err) => {
+            let page = LoginPage {
+                layout: layout.with_error(error_message(&err, &translator, &locale.0)),
+                username_or_email,
+                captcha: captcha_for(&auth_service, Some(&err)).await?,
+            };
+            render(err.status_code(), &page.layout, &page)
+        }
+    }
+}
+
+#[actix_web::routes]
+#[get("/register")]
+#[get("/o/{org}/register")]
+async fn register_page(auth_service: web::Data<AuthService>, req: HttpRequest) -> Result<HttpResponse, AuthError> {
+    let layout = match Layout::new(&auth_service, &req, "Create an account").await? {
+        Some(layout) => layout,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+
+    let page = RegisterPage {
+        layout,
+        username: String::new(),
+        email: String::new(),
+        captcha: captcha_for(&auth_service, None).await?,
+    };
+    render(StatusCode::OK, &page.layout, &page)
+}
+
+#[actix_web::routes]
+#[post("/register")]
+#[post("/o/{org}/register")]
+async fn register(
+    auth_service: web::Data<AuthService>,
+    translator: web::Data<Translator>,
+    locale: web::ReqData<Locale>,
+    form: web::Form<RegisterForm>,
+    req: HttpRequest,
+) -> Result<HttpResponse, AuthError> {
+    let layout = match Layout::new(&auth_service, &req, "Create an account").await? {
+        Some(layout) => layout,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+    ensure_same_origin(&req)?;
+
+    let form = form.into_inner();
+    let data = RegisterRequest {
+        username: form.username.trim().to_string(),
+        email: form.email.trim().to_string(),
+        password: form.password,
+        password_confirmation: form.password_confirmation,
+        captcha_id: form.captcha_id.parse().ok(),
+        captcha_answer: Some(form.captcha_answer).filter(|answer| !answer.is_empty()),
+        region: None,
+    };
+    let (username, email) = (data.username.clone(), data.email.clone());
+
+    let ip = req.connection_info().realip_remote_addr()
+        .map(|s| s.to_string());
+
+    let user_agent = req.headers().get("User-Agent")
+        .and_then(|h| h.to_str().ok())
+        .map(|s| s.to_string());
+
+    let result = match data.validate() {
+        Ok(()) => auth_service.register(data, ip, user_agent, &locale.0).await,
+        Err(errors) => Err(errors.into()),
+    };
+
+    match result {
+        Ok(response) => {
+            let layout = Layout { title: "Check your email", ..layout };
+            let page = MessagePage {
+                layout,
+                message: response.message,
+            };
+            render(StatusCode::CREATED, &page.layout, &page)
+        }
+        Err(err) => {
+            let page = RegisterPage {
+                layout: layout.with_error(error_message(&err, &translator, &locale.0)),
+                username,
+                email,
+                captcha: captcha_for(&auth_service, Some(&err)).await?,
+            };
+            render(err.status_code(), &page.layout, &page)
+        }
+    }
+}
+
+// The code is asked for after the password, so there's nothing to show
+// without one
+#[actix_web::routes]
+#[get("/mfa")]
+#[get("/o/{org}/mfa")]
+async fn mfa_page(auth_service: web::Data<AuthService>, req: HttpRequest) -> Result<HttpResponse, AuthError> {
+    match Layout::new(&auth_service, &req, "Sign in").await? {
+        Some(layout) => Ok(HttpResponse::SeeOther()
+            .insert_header(("Location", format!("{}/login{}", layout.base, layout.client_query())))
+            .finish()),
+        None => Ok(HttpResponse::NotFound().finish()),
+    }
+}
+
+#[actix_web::routes]
+#[post("/mfa")]
+#[post("/o/{org}/mfa")]
+async fn mfa(
+    auth_service: web::Data<AuthService>,
+    translator: web::Data<Translator>,
+    locale: web::ReqData<Locale>,
+    form: web::Form<MfaForm>,
+    req: HttpRequest,
+) -> Result<HttpResponse, AuthError> {
+    let layout = match Layout::new(&auth_service, &req, "Two-factor authentication").await? {
+        Some(layout) => layout,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+    ensure_same_origin(&req)?;
+
+    let form = form.into_inner();
+
+    // An expired or altered token means starting again from the password
+    let user_id = match auth_service.mfa_pending_user(&form.mfa_token) {
+        Ok(user_id) => user_id,
+        Err(err) => {
+            let page = LoginPage {
+                layout: Layout { title: "Sign in", ..layout }.with_error(error_message(&err, &translator, &locale.0)),
+                username_or_email: String::new(),
+                captcha: captcha_for(&auth_service, None).await?,
+            };
+            return render(err.status_code(), &page.layout, &page);
+        }
+    };
+    let layout = layout.styled_for(&auth_service, Some(user_id));
+
+    let data = VerifyMfaRequest {
+        mfa_code: form.mfa_code.trim().to_string(),
+    };
+
+    let ip = req.connection_info().realip_remote_addr()
+        .map(|s| s.to_string());
+
+    let user_agent = req.headers().get("User-Agent")
+        .and_then(|h| h.to_str().ok())
+        .map(|s| s.to_string());
+
+    let result = match data.validate() {
+        Ok(()) => auth_service.mfa_verify(user_id, data, ip, user_agent).await,
+        Err(errors) => Err(errors.into()),
+    };
+
+    match result {
+        Ok(response) => {
+            let page = SignedInPage::from_mfa(Layout { title: "Signed in", ..layout }, response)
+                .for_client(&auth_service)
+                .await?;
+            page.hand_over(&auth_service, user_id).await
+        }
+        Err(err) => {
+            let page = MfaPage {
+                layout: layout.with_error(error_message(&err, &translator, &locale.0)),
+                mfa_token: form.mfa_token,
+            };
+            render(err.status_code(), &page.layout, &page)
+        }
+    }
+}
+
+/// The user's answer on the consent screen. Only reached for a client, with
+/// the tokens of the session just started for it.
+#[actix_web::routes]
+#[post("/consent")]
+#[post("/o/{org}/consent")]
+async fn consent(
+    auth_service: web::Data<AuthService>,
+    form: web::Form<ConsentForm>,
+    req: HttpRequest,
+) -> Result<HttpResponse, AuthError> {
+    let layout = match Layout::new(&auth_service, &req, "Signed in").await? {
+        Some(layout) => layout,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+    ensure_same_origin(&req)?;
+
+    let form = form.into_inner();
+    let (application, scopes) = match &layout.client {
+        Some(ClientRedirect { application, scopes: Some(scopes), .. }) => (application, scopes),
+        _ => return Err(AuthError::ValidationError("Nothing is waiting for consent".into())),
+    };
+
+    let allow = form.decision == "allow";
+    auth_service
+        .decide_client_consent(application, scopes, &form.access_token, allow)
+        .await?;
+    if !allow {
+        let page = ConsentDeclinedPage {
+            layout: Layout { title: "Access declined", ..layout },
+        };
+        return render(StatusCode::OK, &page.layout, &page);
+    }
+
+    let page = SignedInPage {
+        scope: Some(scopes.join(" ")),
+        layout,
+        access_token: form.access_token,
+        refresh_token: Some(form.refresh_token).filter(|token| !token.is_empty()),
+        token_type: form.token_type,
+        expires_in: form.expires_in,
+        next: ["change_password", "enroll_mfa", "accept_policy"]
+            .into_iter()
+            .find(|next| *next == form.next),
+    };
+    render(StatusCode::OK, &page.layout, &page)
+}
+
+#[actix_web::routes]
+#[get("/forgot-password")]
+#[get("/o/{org}/forgot-password")]
+async fn forgot_password_page(auth_service: web::Data<AuthService>, req: HttpRequest) -> Result<HttpResponse, AuthError> {
+    let layout = match Layout::new(&auth_service, &req, "Reset your password").await? {
+        Some(layout) => layout,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+
+    let page = ForgotPasswordPage {
+        layout,
+        email: String::new(),
+    };
+    render(StatusCode::OK, &page.layout, &page)
+}
+
+#[actix_web::routes]
+#[post("/forgot-password")]
+#[post("/o/{org}/forgot-password")]
+async fn forgot_password(
+    auth_service: web::Data<AuthService>,
+    translator: web::Data<Translator>,
+    locale: web::ReqData<Locale>,
+    form: web::Form<ForgotPasswordForm>,
+    req: HttpRequest,
+) -> Result<HttpResponse, AuthError> {
+    let layout = match Layout::new(&auth_service, &req, "Reset your password").await? {
+        Some(layout) => layout,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+    ensure_same_origin(&req)?;
+
+    let data = PasswordResetRequest {
+        email: form.into_inner().email.trim().to_string(),
+    };
+    let email = data.email.clone();
+
+    let result = match data.validate() {
+        Ok(()) => auth_service.password_reset_request(data, &locale.0).await,
+        Err(errors) => Err(errors.into()),
+    };
+
+    match result {
+        Ok(response) => {
+            let layout = Layout { title: "Check your email", ..layout };
+            let page = MessagePage {
+                layout,
+                message: response.message,
+            };
+            render(StatusCode::OK, &page.layout, &page)
+        }
+        Err(err) => {
+            let page = ForgotPasswordPage {
+                layout: layout.with_error(error_message(&err, &translator, &locale.0)),
+                email,
+            };
+            render(err.status_code(), &page.layout, &page)
+        }
+    }
+}
+
+/// Opened from the emailed link when `FRONTEND_URL` points at these pages
+#[actix_web::routes]
+#[get("/reset-password")]
+#[get("/o/{org}/reset-password")]
+async fn reset_password_page(
+    auth_service: web::Data<AuthService>,
+    query: web::Query<ResetPasswordQuery>,
+    req: HttpRequest,
+) -> Result<HttpResponse, AuthError> {
+    let layout = match Layout::new(&auth_service, &req, "Choose a new password").await? {
+        Some(layout) => layout,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+
+    let page = ResetPasswordPage {
+        layout,
+        token: query.into_inner().token,
+    };
+    render(StatusCode::OK, &page.layout, &page)
+}
+
+#[actix_web::routes]
+#[post("/reset-password")]
+#[post("/o/{org}/reset-password")]
+async fn reset_password(
+    auth_service: web::Data<AuthService>,
+    translator: web::Data<Translator>,
+    locale: web::ReqData<Locale>,
+    form: web::Form<ResetPasswordForm>,
+    req: HttpRequest,
+) -> Result<HttpResponse, AuthError> {
+    let layout = match Layout::new(&auth_service, &req, "Choose a new password").await? {
+        Some(layout) => layout,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+    ensure_same_origin(&req)?;
+
+    let form = form.into_inner();
+    let token = form.token.expose().clone();
+    let data = PasswordResetConfirmRequest {
+        token: form.token,
+        password: form.password,
+        password_confirmation: form.password_confirmation,
+        mfa_code: Some(form.mfa_code).filter(|code| !code.is_empty()).map(Secret::new),
+    };
+
+    let result = match data.validate() {
+        Ok(()) => auth_service.password_reset_confirm(data).await,
+        Err(errors) => Err(errors.into()),
+    };
+
+    match result {
+        Ok(response) => {
+            let layout = Layout { title: "Password changed", ..layout };
+            let page = MessagePage {
+                layout,
+                message: response.message,
+            };
+            render(StatusCode::OK, &page.layout, &page)
+        }
+        Err(err) => {
+            let page = ResetPasswordPage {
+                layout: layout.with_error(error_message(&err, &translator, &locale.0)),
+                token,
+            };
+            render(err.status_code(), &page.layout, &page)
+        }
+    }
+}
+
+// Form posts must come from these pages, so another site can't sign a
+// visitor in to an account of its choosing. Browsers too old to send either
+// header are let through.
+fn ensure_same_origin(req: &HttpRequest) -> Result<(), AuthError> {
+    let header = |name: &str| req.headers().get(name).and_then(|h| h.to_str().ok());
+
+    let same_origin = match (header("Sec-Fetch-Site"), header("Origin")) {
+        (Some(site), _) => site == "same-origin",
+        (None, Some(origin)) => {
+            origin.split_once("://").map(|(_, host)| host) == Some(req.connection_info().host())
+        }
+        (None, None) => true,
+    };
+    if same_origin {
+        Ok(())
+    } else {
+        Err(AuthError::PermissionDenied)
+    }
+}
+
+/// A CAPTCHA for the form when every attempt needs one, or when the failed
+/// attempt was asked for one
+async fn captcha_for(
+    auth_service: &AuthService,
+    error: Option<&AuthError>,
+) -> Result<Option<CaptchaChallenge>, AuthError> {
+    if let Some(AuthError::CaptchaRequired { challenge: Some(challenge) }) = error {
+        return Ok(Some(challenge.clone()));
+    }
+
+    let needed = auth_service.captcha_required()
+        || matches!(error, Some(AuthError::CaptchaRequired { .. } | AuthError::InvalidCaptcha));
+    if !needed {
+        return Ok(None);
+    }
+    auth_service
+        .issue_captcha(CaptchaChallengeRequest {
+            username_or_email: None,
+            kind: None,
+        })
+        .await
+        .map(Some)
+}
+
+// What a scope lets a client do, in the user's terms
+fn describe_scope(scope: &str) -> String {
+    let (resource, action) = scope.split_once(':').unwrap_or((scope, ""));
+    let what = match resource {
+        "users" => "your profile",
+        "sessions" => "your signed-in devices and apps",
+        "organizations" => "your organizations",
+        other => other,
+    };
+    match action {
+        "read" => format!("See {}", what),
+        "write" => format!("See and change {}", what),
+        _ => format!("Fully manage {}", what),
+    }
+}
+
+// Server-side failures are logged rather than shown
+fn error_message(err: &AuthError, translator: &Translator, locale: &str) -> String {
+    if err.status_code().is_server_error() {
+        log::error!("Hosted page request failed: {}", err);
+        return "Something went wrong on our side. Please try again.".to_string();
+    }
+    err.localized_message(translator, locale)
+}
+
+fn render(status: StatusCode, layout: &Layout, page: &impl Template) -> Result<HttpResponse, AuthError> {
+    let body = page
+        .render()
+        .map_err(|e| AuthError::InternalServerError(format!("Failed to render page: {}", e)))?;
+
+    // Forms may only post back here, or the tokens to the app
+    let return_origin = url::Url::parse(&layout.return_url)
+        .map(|url| url.origin().ascii_serialization())
+        .unwrap_or_default();
+    let content_security_policy = format!(
+        "default-src 'none'; script-src 'self'; style-src 'self' 'nonce-{}'; img-src 'self' https:; \
+         media-src 'self'; form-action 'self' {}; base-uri 'none'; frame-ancestors 'none'",
+        layout.nonce, return_origin
+    );
+
+    Ok(HttpResponse::build(status)
+        .content_type("text/html; charset=utf-8")
+        // Pages can carry tokens, so no copy is kept anywhere
+        .insert_header(("Cache-Control", "no-store"))
+        .insert_header(("Content-Security-Policy", content_security_policy))
+        .insert_header(("X-Content-Type-Options", "nosniff"))
+        .insert_header(("Referrer-Policy", "no-referrer"))
+        .body(body))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::StatusCode;
+    use actix_web::test;
+    use serde_json::{json, Value};
+
+    use crate::errors::AuthError;
+    use crate::models::{AuditEventFilter, ClientApplicationRequest, EventType, PageRequest};
+    use crate::test_utils::{post_json, TestContext};
+
+    #[actix_web::test]
+    async fn test_assets_and_branding_answer_conditional_requests() {
+        let mut config = crate::test_utils::test_config();
+        config.hosted_pages.enabled = true;
+        let ctx = TestContext::with_config(config);
+        let app = ctx.spawn_app().await;
+        let get = |path: &str, etag: Option<&str>, token: Option<&str>| {
+            let mut request = test::TestRequest::get().uri(path);
+            if let Some(etag) = etag {
+                request = request.insert_header(("If-None-Match", etag.to_string()));
+            }
+            if let Some(token) = token {
+                request = request.insert_header(("Authorization", format!("Bearer {}", token)));
+            }
+            request.to_request()
+        };
+        let etag = |response: &actix_web::dev::ServiceResponse| {
+            response.headers().get("ETag").unwrap().to_str().unwrap().to_string()
+        };
+
+        let response = test::call_service(&app, get("/pages/assets/pages.css", None, None)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers().contains_key("Last-Modified"));
+        let css_etag = etag(&response);
+        let response = test::call_service(&app, get("/pages/assets/pages.css", Some(&css_etag), None)).await;
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(etag(&response), css_etag);
+        assert!(test::read_body(response).await.is_empty());
+
+        let user = ctx.user().create().await.unwrap();
+        let token = ctx.session(&user).create().await.unwrap().access_token;
+        let organization = ctx
+            .db
+            .create_organization(
+                crate::models::NewOrganization {
+                    id: uuid::Uuid::new_v4(),
+                    name: "Acme".to_string(),
+                    slug: "acme".to_string(),
+                },
+                user.id(),
+            )
+            .await
+            .unwrap();
+        let path = format!("/organizations/{}/branding", organization.id);
+
+        let response = test::call_service(&app, get(&path, None, Some(&token))).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers().get("Cache-Control").unwrap(), "private, no-cache");
+        let unbranded = etag(&response);
+        let response = test::call_service(&app, get(&path, Some(&unbranded), Some(&token))).await;
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+
+        // A change is served at once, whatever the client has
+        ctx.db
+            .save_organization_branding(crate::models::NewOrganizationBranding {
+                organization_id: organization.id,
+                display_name: Some("Acme Corp".to_string()),
+                logo_url: None,
+                primary_color: None,
+            })
+            .await
+            .unwrap();
+        let response = test::call_service(&app, get(&path, Some(&unbranded), Some(&token))).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_ne!(etag(&response), unbranded);
+        let branding: Value = test::read_body_json(response).await;
+        assert_eq!(branding["display_name"], "Acme Corp");
+    }
+
+    #[actix_web::test]
+    async fn test_hosted_pages_sign_in_with_organization_branding() {
+        let get = |path: &str| test::TestRequest::get().uri(path).to_request();
+        let body = |bytes: actix_web::web::Bytes| String::from_utf8(bytes.to_vec()).unwrap();
+
+        let ctx = TestContext::new();
+        let app = ctx.spawn_app().await;
+        let response = test::call_service(&app, get("/pages/login")).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let mut config = crate::test_utils::test_config();
+        config.hosted_pages.enabled = true;
+        config.hosted_pages.return_url = "https://app.example/callback".to_string();
+        let ctx = TestContext::with_config(config);
+        let app = ctx.spawn_app().await;
+
+        let user = ctx.user().with_mfa().create().await.unwrap();
+        let organization = ctx
+            .db
+            .create_organization(
+                crate::models::NewOrganization {
+                    id: uuid::Uuid::new_v4(),
+                    name: "Acme".to_string(),
+                    slug: "acme".to_string(),
+                },
+                user.id(),
+            )
+            .await
+            .unwrap();
+        ctx.db
+            .save_organization_branding(crate::models::NewOrganizationBranding {
+                organization_id: organization.id,
+                display_name: Some("Acme Corp".to_string()),
+                logo_url: None,
+                primary_color: Some("#aa0000".to_string()),
+            })
+            .await
+            .unwrap();
+
+        let response = test::call_service(&app, get("/pages/o/acme/login")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let csp = response.headers().get("Content-Security-Policy").unwrap().to_str().unwrap().to_string();
+        assert!(csp.contains("'nonce-") && csp.contains("form-action 'self' https://app.example"));
+        let page = body(test::read_body(response).await);
+        assert!(page.contains("Acme Corp"));
+        assert!(page.contains("--primary-color: #aa0000"));
+        assert!(page.contains("--base-font-size"));
+
+        let response = test::call_service(&app, get("/pages/o/globex/login")).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let login = |origin: &'static str| {
+            test::TestRequest::post()
+                .uri("/pages/o/acme/login")
+                .insert_header(("Sec-Fetch-Site", origin))
+                .set_form([
+                    ("username_or_email", user.user.username.as_str()),
+                    ("password", user.password.as_str()),
+                ])
+                .to_request()
+        };
+
+        // Another site can't post the form
+        let response = test::call_service(&app, login("cross-site")).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let response = test::call_service(&app, login("same-origin")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let page = body(test::read_body(response).await);
+        let mfa_token = page
+            .split("name=\"mfa_token\" value=\"")
+            .nth(1)
+            .and_then(|rest| rest.split('"').next())
+            .unwrap()
+            .to_string();
+
+        let code = user.totp_code(&ctx).unwrap();
+        let request = test::TestRequest::post()
+            .uri("/pages/o/acme/mfa")
+            .insert_header(("Sec-Fetch-Site", "same-origin"))
+            .set_form([("mfa_token", mfa_token.as_str()), ("mfa_code", code.as_str())])
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers().get("Cache-Control").unwrap(), "no-store");
+        let page = body(test::read_body(response).await);
+        assert!(page.contains("id=\"handoff\""));
+        assert!(page.contains("app.example"));
+        assert!(page.contains("name=\"refresh_token\""));
+    }
+
+    #[actix_web::test]
+    async fn test_hosted_pages_hand_tokens_only_to_registered_clients() {
+        let get = |path: &str| test::TestRequest::get().uri(path).to_request();
+        let body = |bytes: actix_web::web::Bytes| String::from_utf8(bytes.to_vec()).unwrap();
+        let field = |page: &str, name: &str| {
+            page.split(&format!("name=\"{}\" value=\"", name))
+                .nth(1)
+                .and_then(|rest| rest.split('"').next())
+                .map(str::to_string)
+        };
+
+        let mut config = crate::test_utils::test_config();
+        config.hosted_pages.enabled = true;
+        config.hosted_pages.return_url = "https://default.example/callback".to_string();
+        let ctx = TestContext::with_config(config);
+        let app = ctx.spawn_app().await;
+        let admin = ctx.user().admin().create().await.unwrap();
+        let user = ctx.user().create().await.unwrap();
+
+        let registration = |redirect_uri: &str| ClientApplicationRequest {
+            name: "Web".to_string(),
+            redirect_uris: vec![redirect_uri.to_string()],
+            allowed_grants: vec!["password".to_string()],
+            access_token_ttl: Some(120),
+            refresh_token_ttl: None,
+            logo_url: None,
+            first_party: true,
+            confidential: false,
+            rotate_secret: false,
+        };
+        let refused = ctx
+            .auth_service
+            .save_client_application(admin.id(), "web", registration("http://app.example/callback"))
+            .await;
+        assert!(matches!(refused, Err(AuthError::ValidationError(_))));
+        ctx.auth_service
+            .save_client_application(admin.id(), "web", registration("https://app.example/callback"))
+            .await
+            .unwrap();
+
+        let query = "?client_id=web&redirect_uri=https%3A%2F%2Fapp.example%2Fcallback";
+        let response = test::call_service(&app, get("/pages/login?client_id=web&redirect_uri=https%3A%2F%2Fevil.example%2F")).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let response = test::call_service(&app, get("/pages/login?client_id=mobile")).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let response = test::call_service(&app, get(&format!("/pages/login{}", query))).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let csp = response.headers().get("Content-Security-Policy").unwrap().to_str().unwrap().to_string();
+        assert!(csp.contains("form-action 'self' https://app.example;"));
+        let page = body(test::read_body(response).await);
+        assert!(page.contains("Continue to Web"));
+        assert!(page.contains("action=\"/pages/login?client_id=web&amp;redirect_uri=https%3A%2F%2Fapp.example%2Fcallback\""));
+
+        let request = test::TestRequest::post()
+            .uri(&format!("/pages/login{}", query))
+            .insert_header(("Sec-Fetch-Site", "same-origin"))
+            .set_form([
+                ("username_or_email", user.user.username.as_str()),
+                ("password", user.password.as_str()),
+            ])
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let page = body(test::read_body(response).await);
+        assert!(page.contains("action=\"https://app.example/callback\""));
+        // Held to the client's lifetime, and without a refresh token it may not use
+        assert_eq!(field(&page, "expires_in").as_deref(), Some("120"));
+        assert!(field(&page, "refresh_token").is_none());
+        let access_token = field(&page, "access_token").unwrap();
+
+        let get_me = || {
+            test::TestRequest::get()
+                .uri("/users/me")
+                .insert_header(("Authorization", format!("Bearer {}", access_token)))
+                .to_request()
+        };
+        let response = test::call_service(&app, get_me()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // Removing the client signs its sessions out
+        ctx.auth_service.delete_client_application(admin.id(), "web").await.unwrap();
+        let response = test::call_service(&app, get_me()).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let response = test::call_service(&app, get(&format!("/pages/login{}", query))).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_web::test]
+    async fn test_third_party_clients_get_only_consented_scopes() {
+        let get = |path: &str| test::TestRequest::get().uri(path).to_request();
+        let body = |bytes: actix_web::web::Bytes| String::from_utf8(bytes.to_vec()).unwrap();
+        let field = |page: &str, name: &str| {
+            page.split(&format!("name=\"{}\" value=\"", name))
+                .nth(1)
+                .and_then(|rest| rest.split('"').next())
+                .map(str::to_string)
+        };
+
+        let mut config = crate::test_utils::test_config();
+        config.hosted_pages.enabled = true;
+        let ctx = TestContext::with_config(config);
+        let app = ctx.spawn_app().await;
+        let admin = ctx.user().admin().create().await.unwrap();
+        let user = ctx.user().create().await.unwrap();
+        ctx.auth_service
+            .save_client_application(
+                admin.id(),
+                "photos",
+                ClientApplicationRequest {
+                    name: "Photos".to_string(),
+                    redirect_uris: vec!["https://photos.example/callback".to_string()],
+                    allowed_grants: vec!["password".to_string(), "refresh_token".to_string()],
+                    access_token_ttl: None,
+                    refresh_token_ttl: None,
+                    logo_url: None,
+                    first_party: false,
+                    confidential: false,
+                    rotate_secret: false,
+                },
+            )
+            .await
+            .unwrap();
+
+        // Third-party clients have to say what they want
+        let client = "?client_id=photos&redirect_uri=https%3A%2F%2Fphotos.example%2Fcallback";
+        let response = test::call_service(&app, get(&format!("/pages/login{}", client))).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let query = format!("{}&scope=users%3Aread", client);
+        let sign_in = || {
+            test::TestRequest::post()
+                .uri(&format!("/pages/login{}", query))
+                .insert_header(("Sec-Fetch-Site", "same-origin"))
+                .set_form([
+                    ("username_or_email", user.user.username.as_str()),
+                    ("password", user.password.as_str()),
+                ])
+                .to_request()
+        };
+        let decide = |page: &str, decision: &str| {
+            test::TestRequest::post()
+                .uri(&format!("/pages/consent{}", query))
+                .insert_header(("Sec-Fetch-Site", "same-origin"))
+                .set_form([
+                    ("access_token", field(page, "access_token").unwrap()),
+                    ("refresh_token", field(page, "refresh_token").unwrap()),
+                    ("token_type", field(page, "token_type").unwrap()),
+                    ("expires_in", field(page, "expires_in").unwrap()),
+                    ("decision", decision.to_string()),
+                ])
+                .to_request()
+        };
+        let get_as = |path: &str, token: &str| {
+            test::TestRequest::get()
+                .uri(path)
+                .insert_header(("Authorization", format!("Bearer {}", token)))
+                .to_request()
+        };
+
+        // Declining tells the client and signs the new session out
+        let page = body(test::read_body(test::call_service(&app, sign_in()).await).await);
+        assert!(page.contains("Allow access?") && page.contains("See your profile"));
+        assert!(!page.contains("id=\"handoff\""));
+        let response = test::call_service(&app, decide(&page, "deny")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let declined = body(test::read_body(response).await);
+        assert!(declined.contains("name=\"error\" value=\"access_denied\""));
+        let response = test::call_service(&app, get_as("/users/me", &field(&page, "access_token").unwrap())).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        // Allowing hands over a token limited to what was granted
+        let page = body(test::read_body(test::call_service(&app, sign_in()).await).await);
+        let response = test::call_service(&app, decide(&page, "allow")).await;
+        let page = body(test::read_body(response).await);
+        assert!(page.contains("action=\"https://photos.example/callback\""));
+        assert_eq!(field(&page, "scope").as_deref(), Some("users:read"));
+        let access_token = field(&page, "access_token").unwrap();
+        let response = test::call_service(&app, get_as("/users/me", &access_token)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = test::call_service(&app, get_as("/users/sessions", &access_token)).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        // Refreshed tokens keep to the grant
+        let refreshed = post_json(&app, "/auth/refresh-token", json!({ "refresh_token": field(&page, "refresh_token") }))
+            .await
+            .assert_success();
+        let response = test::call_service(&app, get_as("/users/sessions", refreshed.field("access_token").unwrap())).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        // The same scopes again don't need asking
+        let page = body(test::read_body(test::call_service(&app, sign_in()).await).await);
+        assert!(page.contains("id=\"handoff\""));
+
+        let own = ctx.session(&user).create().await.unwrap();
+        let apps: Value = test::call_and_read_body_json(&app, get_as("/users/me/authorized-apps", &own.access_token)).await;
+        assert_eq!(apps[0]["client_id"], "photos");
+        assert_eq!(apps[0]["scopes"], json!(["users:read"]));
+        let filter = AuditEventFilter {
+            user_id: Some(user.id()),
+            event_type: Some(EventType::ClientAuthorized.as_str().to_string()),
+        };
+        let (events, _) = ctx.db.find_outbox_events(&filter, &PageRequest::default()).await.unwrap();
+        assert_eq!(events.len(), 1);
+
+        // Revoking the app signs it out, and its next sign-in asks again
+        let request = test::TestRequest::delete()
+            .uri("/users/me/authorized-apps/photos")
+            .insert_header(("Authorization", format!("Bearer {}", own.access_token)))
+            .to_request();
+        assert_eq!(test::call_service(&app, request).await.status(), StatusCode::OK);
+        let response = test::call_service(&app, get_as("/users/me", &access_token)).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let page = body(test::read_body(test::call_service(&app, sign_in()).await).await);
+        assert!(page.contains("Allow access?"));
+    }
+}
@@ -1,40 +1,376 @@
-use actix_web::{web, HttpResponse, Scope};
+use actix_web::http::header;
+use actix_web::{web, HttpRequest, HttpResponse, Scope};
+use futures::StreamExt;
 use validator::Validate;
 
 use crate::errors::AuthError;
-use crate::middleware::auth::AuthenticatedUser;
+use crate::middleware::auth::{AuthenticatedUser, RequireScope, ScopedAuthMiddleware};
+use crate::middleware::step_up::{StepUpMiddleware, StepUpPolicy};
+use crate::models::{
+    AddBackupEmailRequest, CreateApiKeyRequest, LoginPolicyRequest, PageRequest, SecurityQuestionsRequest, SessionFilter,
+    UpdateNotificationPreferencesRequest, UpdateProfileRequest, UpdateSessionRequest,
+};
 use crate::services::auth::AuthService;
+use crate::services::feature_flags::Flags;
+use crate::utils::i18n::Locale;
+use crate::utils::jwt::TokenScope;
+use crate::utils::negotiate;
+use crate::utils::scopes::{SESSIONS_READ, SESSIONS_WRITE, USERS_READ, USERS_WRITE};
 
 pub fn configure(cfg: &mut web::ServiceConfig) {
     cfg.service(
         web::scope("/users")
             .service(get_me)
+            .service(update_me)
+            .service(upload_avatar)
+            .service(remove_avatar)
+            .service(get_overview)
+            .service(list_backup_emails)
+            .service(add_backup_email)
+            .service(remove_backup_email)
+            .service(get_notification_preferences)
+            .service(update_notification_preferences)
+            .service(get_login_policy)
+            .service(save_login_policy)
+            .service(delete_login_policy)
+            .service(get_security_questions)
+            .service(save_security_questions)
+            .service(delete_security_questions)
+            .service(lock_account)
             .service(get_sessions)
-            .service(revoke_session),
+            .service(login_history)
+            .service(update_session)
+            .service(revoke_session)
+            .service(list_authorized_apps)
+            .service(revoke_authorized_app)
+            .service(list_api_keys)
+            .service(create_api_key)
+            .service(delete_api_key),
     );
 }
 
-#[actix_web::get("/me")]
+/// The user's `version` as an `ETag`, for `If-Match` on later writes
+pub(crate) fn etag(version: i32) -> (header::HeaderName, String) {
+    (header::ETAG, format!("\"{}\"", version))
+}
+
+/// The version named by `If-Match`; `None` when it's missing or `*`
+pub(crate) fn if_match(req: &HttpRequest) -> Result<Option<i32>, AuthError> {
+    let value = match req.headers().get(header::IF_MATCH) {
+        Some(value) => value.to_str().unwrap_or_default().trim(),
+        None => return Ok(None),
+    };
+    if value == "*" {
+        return Ok(None);
+    }
+
+    value
+        .trim_matches('"')
+        .parse()
+        .map(Some)
+        .map_err(|_| AuthError::ValidationError("If-Match must be an ETag returned by this API".into()))
+}
+
+/// What SDKs call to check a token is still good; answers in MessagePack
+/// for `Accept: application/msgpack`
+#[actix_web::get("/me", wrap = "RequireScope(USERS_READ)")]
 async fn get_me(
+    req: HttpRequest,
     auth_service: web::Data<AuthService>,
     user: web::ReqData<AuthenticatedUser>,
 ) -> Result<HttpResponse, AuthError> {
     let response = auth_service.get_user(user.user_id).await?;
     
+    negotiate::respond(&req, HttpResponse::Ok().insert_header(etag(response.version)), &response)
+}
+
+/// Send the `ETag` from `GET /users/me` as `If-Match` to refuse the update
+/// if the account has changed since (409 `VERSION_CONFLICT`)
+#[actix_web::patch("/me", wrap = "RequireScope(USERS_WRITE)")]
+async fn update_me(
+    req: HttpRequest,
+    auth_service: web::Data<AuthService>,
+    user: web::ReqData<AuthenticatedUser>,
+    profile_data: web::Json<UpdateProfileRequest>,
+) -> Result<HttpResponse, AuthError> {
+    profile_data.validate()?;
+    
+    let response = auth_service
+        .update_profile(user.user_id, profile_data.into_inner(), if_match(&req)?)
+        .await?;
+    
+    Ok(HttpResponse::Ok().insert_header(etag(response.version)).json(response))
+}
+
+/// Upload a new avatar as the raw request body (PNG, JPEG, GIF or WebP)
+#[actix_web::put("/me/avatar", wrap = "RequireScope(USERS_WRITE)")]
+async fn upload_avatar(
+    auth_service: web::Data<AuthService>,
+    user: web::ReqData<AuthenticatedUser>,
+    mut payload: web::Payload,
+) -> Result<HttpResponse, AuthError> {
+    // Stop reading as soon as the upload goes over the limit
+    let limit = auth_service.avatar_upload_limit();
+    let mut data = web::BytesMut::new();
+    while let Some(chunk) = payload.next().await {
+        let chunk = chunk.map_err(|e| AuthError::ValidationError(e.to_string()))?;
+        if data.len() + chunk.len() > limit {
+            return Err(AuthError::PayloadTooLarge { limit });
+        }
+        data.extend_from_slice(&chunk);
+    }
+    
+    let response = auth_service.upload_avatar(user.user_id, data.to_vec()).await?;
+    
+    Ok(HttpResponse::Ok().json(response))
+}
+
+#[actix_web::delete("/me/avatar", wrap = "RequireScope(USERS_WRITE)")]
+async fn remove_avatar(
+    auth_service: web::Data<AuthService>,
+    user: web::ReqData<AuthenticatedUser>,
+) -> Result<HttpResponse, AuthError> {
+    let response = auth_service.remove_avatar(user.user_id).await?;
+    
     Ok(HttpResponse::Ok().json(response))
 }
 
-#[actix_web::get("/sessions")]
+/// Everything the account security page needs in one request
+#[actix_web::get("/me/overview", wrap = "RequireScope(USERS_READ)")]
+async fn get_overview(
+    auth_service: web::Data<AuthService>,
+    user: web::ReqData<AuthenticatedUser>,
+    flags: Flags,
+) -> Result<HttpResponse, AuthError> {
+    let response = auth_service.get_account_overview(user.user_id, &flags).await?;
+    
+    Ok(HttpResponse::Ok().json(response))
+}
+
+#[actix_web::get("/me/emails", wrap = "RequireScope(USERS_READ)")]
+async fn list_backup_emails(
+    auth_service: web::Data<AuthService>,
+    user: web::ReqData<AuthenticatedUser>,
+) -> Result<HttpResponse, AuthError> {
+    let response = auth_service.list_backup_emails(user.user_id).await?;
+    
+    Ok(HttpResponse::Ok().json(response))
+}
+
+// Backup addresses can receive password reset links, so adding or removing
+// one needs a recent password check
+#[actix_web::post(
+    "/me/emails",
+    wrap = "StepUpMiddleware(StepUpPolicy::password_within(300))",
+    wrap = "RequireScope(USERS_WRITE)"
+)]
+async fn add_backup_email(
+    auth_service: web::Data<AuthService>,
+    user: web::ReqData<AuthenticatedUser>,
+    locale: web::ReqData<Locale>,
+    email_data: web::Json<AddBackupEmailRequest>,
+) -> Result<HttpResponse, AuthError> {
+    email_data.validate()?;
+    
+    let response = auth_service
+        .add_backup_email(user.user_id, email_data.into_inner(), &locale.0)
+        .await?;
+    
+    Ok(HttpResponse::Created().json(response))
+}
+
+#[actix_web::delete(
+    "/me/emails/{email_id}",
+    wrap = "StepUpMiddleware(StepUpPolicy::password_within(300))",
+    wrap = "RequireScope(USERS_WRITE)"
+)]
+async fn remove_backup_email(
+    auth_service: web::Data<AuthService>,
+    user: web::ReqData<AuthenticatedUser>,
+    email_id: web::Path<uuid::Uuid>,
+) -> Result<HttpResponse, AuthError> {
+    let response = auth_service
+        .remove_backup_email(user.user_id, *email_id)
+        .await?;
+    
+    Ok(HttpResponse::Ok().json(response))
+}
+
+#[actix_web::get("/me/notification-preferences", wrap = "RequireScope(USERS_READ)")]
+async fn get_notification_preferences(
+    auth_service: web::Data<AuthService>,
+    user: web::ReqData<AuthenticatedUser>,
+) -> Result<HttpResponse, AuthError> {
+    let response = auth_service.get_notification_preferences(user.user_id).await?;
+    
+    Ok(HttpResponse::Ok().json(response))
+}
+
+#[actix_web::put("/me/notification-preferences", wrap = "RequireScope(USERS_WRITE)")]
+async fn update_notification_preferences(
+    auth_service: web::Data<AuthService>,
+    user: web::ReqData<AuthenticatedUser>,
+    preferences_data: web::Json<UpdateNotificationPreferencesRequest>,
+) -> Result<HttpResponse, AuthError> {
+    preferences_data.validate()?;
+    
+    let response = auth_service
+        .update_notification_preferences(user.user_id, preferences_data.into_inner())
+        .await?;
+    
+    Ok(HttpResponse::Ok().json(response))
+}
+
+#[actix_web::get("/me/login-policy", wrap = "RequireScope(USERS_READ)")]
+async fn get_login_policy(
+    auth_service: web::Data<AuthService>,
+    user: web::ReqData<AuthenticatedUser>,
+) -> Result<HttpResponse, AuthError> {
+    let response = auth_service.login_policies(user.user_id).await?;
+
+    Ok(HttpResponse::Ok().json(response))
+}
+
+// A policy can lock the account out of logins, so changing it needs a recent
+// password check
+#[actix_web::put(
+    "/me/login-policy",
+    wrap = "StepUpMiddleware(StepUpPolicy::password_within(300))",
+    wrap = "RequireScope(USERS_WRITE)"
+)]
+async fn save_login_policy(
+    auth_service: web::Data<AuthService>,
+    user: web::ReqData<AuthenticatedUser>,
+    policy_data: web::Json<LoginPolicyRequest>,
+) -> Result<HttpResponse, AuthError> {
+    policy_data.validate()?;
+
+    let response = auth_service
+        .save_login_policy(user.user_id, policy_data.into_inner())
+        .await?;
+
+    Ok(HttpResponse::Ok().json(response))
+}
+
+#[actix_web::delete(
+    "/me/login-policy",
+    wrap = "StepUpMiddleware(StepUpPolicy::password_within(300))",
+    wrap = "RequireScope(USERS_WRITE)"
+)]
+async fn delete_login_policy(
+    auth_service: web::Data<AuthService>,
+    user: web::ReqData<AuthenticatedUser>,
+) -> Result<HttpResponse, AuthError> {
+    let response = auth_service.delete_login_policy(user.user_id).await?;
+
+    Ok(HttpResponse::Ok().json(response))
+}
+
+#[actix_web::get("/me/security-questions", wrap = "RequireScope(USERS_READ)")]
+async fn get_security_questions(
+    auth_service: web::Data<AuthService>,
+    user: web::ReqData<AuthenticatedUser>,
+) -> Result<HttpResponse, AuthError> {
+    let response = auth_service.security_questions(user.user_id).await?;
+
+    Ok(HttpResponse::Ok().json(response))
+}
+
+// The answers stand in for the second factor, so setting them takes the
+// second factor
+#[actix_web::put(
+    "/me/security-questions",
+    wrap = "StepUpMiddleware(StepUpPolicy::mfa_within(300))",
+    wrap = "RequireScope(USERS_WRITE)"
+)]
+async fn save_security_questions(
+    auth_service: web::Data<AuthService>,
+    user: web::ReqData<AuthenticatedUser>,
+    questions_data: web::Json<SecurityQuestionsRequest>,
+) -> Result<HttpResponse, AuthError> {
+    questions_data.validate()?;
+
+    let response = auth_service
+        .save_security_questions(user.user_id, questions_data.into_inner())
+        .await?;
+
+    Ok(HttpResponse::Ok().json(response))
+}
+
+#[actix_web::delete(
+    "/me/security-questions",
+    wrap = "StepUpMiddleware(StepUpPolicy::password_within(300))",
+    wrap = "RequireScope(USERS_WRITE)"
+)]
+async fn delete_security_questions(
+    auth_service: web::Data<AuthService>,
+    user: web::ReqData<AuthenticatedUser>,
+) -> Result<HttpResponse, AuthError> {
+    let response = auth_service.delete_security_questions(user.user_id).await?;
+
+    Ok(HttpResponse::Ok().json(response))
+}
+
+/// Lock the account and sign it out everywhere; only a password reset from
+/// the primary address unlocks it
+#[actix_web::post("/me/lock", wrap = "RequireScope(USERS_WRITE)")]
+async fn lock_account(
+    auth_service: web::Data<AuthService>,
+    user: web::ReqData<AuthenticatedUser>,
+    locale: web::ReqData<Locale>,
+) -> Result<HttpResponse, AuthError> {
+    let response = auth_service.lock_own_account(user.user_id, &locale.0).await?;
+
+    Ok(HttpResponse::Ok().json(response))
+}
+
+/// Past sign-ins to the account, newest first
+#[actix_web::get("/login-history", wrap = "RequireScope(SESSIONS_READ)")]
+async fn login_history(
+    auth_service: web::Data<AuthService>,
+    user: web::ReqData<AuthenticatedUser>,
+    page: web::Query<PageRequest>,
+) -> Result<HttpResponse, AuthError> {
+    page.validate()?;
+    
+    let response = auth_service.login_history(user.user_id, page.into_inner()).await?;
+    
+    Ok(HttpResponse::Ok().json(response))
+}
+
+#[actix_web::get("/sessions", wrap = "RequireScope(SESSIONS_READ)")]
 async fn get_sessions(
     auth_service: web::Data<AuthService>,
     user: web::ReqData<AuthenticatedUser>,
+    page: web::Query<PageRequest>,
+    filter: web::Query<SessionFilter>,
 ) -> Result<HttpResponse, AuthError> {
-    let response = auth_service.get_sessions(user.user_id).await?;
+    page.validate()?;
+    
+    let response = auth_service
+        .get_sessions(user.user_id, filter.into_inner(), page.into_inner())
+        .await?;
     
     Ok(HttpResponse::Ok().json(response))
 }
 
-#[actix_web::delete("/sessions/{session_id}")]
+#[actix_web::patch("/sessions/{session_id}", wrap = "RequireScope(SESSIONS_WRITE)")]
+async fn update_session(
+    auth_service: web::Data<AuthService>,
+    user: web::ReqData<AuthenticatedUser>,
+    session_id: web::Path<uuid::Uuid>,
+    session_data: web::Json<UpdateSessionRequest>,
+) -> Result<HttpResponse, AuthError> {
+    session_data.validate()?;
+    
+    let response = auth_service
+        .update_session(user.user_id, *session_id, session_data.into_inner())
+        .await?;
+    
+    Ok(HttpResponse::Ok().json(response))
+}
+
+#[actix_web::delete("/sessions/{session_id}", wrap = "RequireScope(SESSIONS_WRITE)")]
 async fn revoke_session(
     auth_service: web::Data<AuthService>,
     user: web::ReqData<AuthenticatedUser>,
@@ -46,3 +382,290 @@ async fn revoke_session(
     
     Ok(HttpResponse::Ok().json(response))
 }
+
+/// Third-party apps the user has let act for them, and the scopes each was granted
+#[actix_web::get("/me/authorized-apps", wrap = "RequireScope(SESSIONS_READ)")]
+async fn list_authorized_apps(
+    auth_service: web::Data<AuthService>,
+    user: web::ReqData<AuthenticatedUser>,
+) -> Result<HttpResponse, AuthError> {
+    let response = auth_service.list_authorized_apps(user.user_id).await?;
+    
+    Ok(HttpResponse::Ok().json(response))
+}
+
+/// Take back an app's access and sign out its sessions; it has to ask again
+#[actix_web::delete("/me/authorized-apps/{client_id}", wrap = "RequireScope(SESSIONS_WRITE)")]
+async fn revoke_authorized_app(
+    auth_service: web::Data<AuthService>,
+    user: web::ReqData<AuthenticatedUser>,
+    client_id: web::Path<String>,
+) -> Result<HttpResponse, AuthError> {
+    let response = auth_service
+        .revoke_authorized_app(user.user_id, &client_id)
+        .await?;
+    
+    Ok(HttpResponse::Ok().json(response))
+}
+
+/// Personal access tokens for integrations; managed only from a signed-in session
+#[actix_web::get("/me/api-keys", wrap = "ScopedAuthMiddleware(&[TokenScope::Full])")]
+async fn list_api_keys(
+    auth_service: web::Data<AuthService>,
+    user: web::ReqData<AuthenticatedUser>,
+) -> Result<HttpResponse, AuthError> {
+    let response = auth_service.list_api_keys(user.user_id).await?;
+    
+    Ok(HttpResponse::Ok().json(response))
+}
+
+/// Create an API key; the key itself is only returned in this response
+#[actix_web::post(
+    "/me/api-keys",
+    wrap = "StepUpMiddleware(StepUpPolicy::password_within(300))",
+    wrap = "ScopedAuthMiddleware(&[TokenScope::Full])"
+)]
+async fn create_api_key(
+    auth_service: web::Data<AuthService>,
+    user: web::ReqData<AuthenticatedUser>,
+    key_data: web::Json<CreateApiKeyRequest>,
+) -> Result<HttpResponse, AuthError> {
+    key_data.validate()?;
+    
+    let response = auth_service
+        .create_api_key(user.user_id, key_data.into_inner())
+        .await?;
+    
+    Ok(HttpResponse::Created().json(response))
+}
+
+#[actix_web::delete("/me/api-keys/{key_id}", wrap = "ScopedAuthMiddleware(&[TokenScope::Full])")]
+async fn delete_api_key(
+    auth_service: web::Data<AuthService>,
+    user: web::ReqData<AuthenticatedUser>,
+    key_id: web::Path<uuid::Uuid>,
+) -> Result<HttpResponse, AuthError> {
+    let response = auth_service
+        .delete_api_key(user.user_id, *key_id)
+        .await?;
+    
+    Ok(HttpResponse::Ok().json(response))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::dev::ServiceResponse;
+    use actix_web::http::StatusCode;
+    use actix_web::test;
+    use serde_json::{json, Value};
+
+    use crate::errors::AuthError;
+    use crate::models::{AuditEventFilter, DelegatedTokenRequest, EventType, PageRequest};
+    use crate::test_utils::{login, TestContext};
+
+    #[actix_web::test]
+    async fn test_login_policies_block_logins_from_other_countries() {
+        let ctx = TestContext::new();
+        let app = ctx.spawn_app().await;
+        let user = ctx.user().create().await.unwrap();
+        let access_token = login(&app, &user.user.username, &user.password)
+            .await
+            .assert_success()
+            .field("access_token")
+            .unwrap()
+            .to_string();
+
+        let request = test::TestRequest::put()
+            .uri("/users/me/login-policy")
+            .insert_header(("Authorization", format!("Bearer {}", access_token)))
+            .set_json(json!({ "allowed_countries": ["de"] }))
+            .to_request();
+        let policy: Value = test::call_and_read_body_json(&app, request).await;
+        assert_eq!(policy["allowed_countries"], json!(["DE"]));
+
+        // Test requests carry no country, which can't be shown to be Germany
+        let response = login(&app, &user.user.username, &user.password).await;
+        assert_eq!(response.status, StatusCode::FORBIDDEN);
+    }
+
+    #[actix_web::test]
+    async fn test_revoked_session_access_tokens_stop_working() {
+        let ctx = TestContext::new();
+        let app = ctx.spawn_app().await;
+        let user = ctx.user().create().await.unwrap();
+        let kept = ctx.session(&user).create().await.unwrap();
+        let revoked = ctx.session(&user).create().await.unwrap();
+
+        let get_me = |token: &str| {
+            test::TestRequest::get()
+                .uri("/users/me")
+                .insert_header(("Authorization", format!("Bearer {}", token)))
+                .to_request()
+        };
+        let response = test::call_service(&app, get_me(&revoked.access_token)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let request = test::TestRequest::delete()
+            .uri(&format!("/users/sessions/{}", revoked.session.id))
+            .insert_header(("Authorization", format!("Bearer {}", kept.access_token)))
+            .to_request();
+        assert_eq!(test::call_service(&app, request).await.status(), StatusCode::OK);
+
+        // Only the revoked session's token is cut off, without waiting for it to expire
+        let response = test::call_service(&app, get_me(&revoked.access_token)).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let response = test::call_service(&app, get_me(&kept.access_token)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[actix_web::test]
+    async fn test_delegated_token_acts_as_user_until_revoked() {
+        let ctx = TestContext::new();
+        let app = ctx.spawn_app().await;
+        let admin = ctx.user().admin().create().await.unwrap();
+        let user = ctx.user().create().await.unwrap();
+
+        let request = |scopes: &[&str]| DelegatedTokenRequest {
+            scopes: scopes.iter().map(|s| s.to_string()).collect(),
+            expires_in: Some(600),
+            reason: "Ticket 1234".to_string(),
+        };
+        let refused = ctx.auth_service.issue_delegated_token(admin.id(), user.id(), request(&["admin:read"])).await;
+        assert!(matches!(refused, Err(AuthError::InsufficientScope { .. })));
+
+        let delegated = ctx
+            .auth_service
+            .issue_delegated_token(admin.id(), user.id(), request(&["users:read"]))
+            .await
+            .unwrap();
+        let get_me = || {
+            test::TestRequest::get()
+                .uri("/users/me")
+                .insert_header(("Authorization", format!("Bearer {}", delegated.access_token)))
+                .to_request()
+        };
+        let me: Value = test::call_and_read_body_json(&app, get_me()).await;
+        assert_eq!(me["id"], user.id().to_string());
+
+        // The audit log names the admin behind the token
+        let filter = AuditEventFilter {
+            user_id: Some(user.id()),
+            event_type: Some(EventType::DelegatedTokenIssued.as_str().to_string()),
+        };
+        let (events, _) = ctx.db.find_outbox_events(&filter, &PageRequest::default()).await.unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].payload["actor_id"], admin.id().to_string());
+        assert_eq!(events[0].payload["reason"], "Ticket 1234");
+
+        ctx.auth_service
+            .revoke_delegated_token(admin.id(), user.id(), delegated.token_id)
+            .await
+            .unwrap();
+        let response = test::call_service(&app, get_me()).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[actix_web::test]
+    async fn test_delegated_tokens_cannot_change_credentials() {
+        let ctx = TestContext::new();
+        let app = ctx.spawn_app().await;
+        let admin = ctx.user().admin().create().await.unwrap();
+        let user = ctx.user().create().await.unwrap();
+        let request = DelegatedTokenRequest {
+            scopes: vec!["users:*".to_string(), "sessions:*".to_string(), "organizations:*".to_string()],
+            expires_in: Some(600),
+            reason: "Ticket 1234".to_string(),
+        };
+        let delegated = ctx.auth_service.issue_delegated_token(admin.id(), user.id(), request).await.unwrap();
+
+        // Middleware refusals come back as errors rather than responses
+        let status_of = |result: Result<ServiceResponse<_>, actix_web::Error>| match result {
+            Ok(res) => res.status(),
+            Err(e) => e.as_response_error().status_code(),
+        };
+        let password = json!({
+            "current_password": user.password,
+            "password": "Another1Pass!",
+            "password_confirmation": "Another1Pass!",
+        });
+        let requests = [
+            test::TestRequest::post().uri("/auth/change-password").set_json(password),
+            test::TestRequest::get().uri("/auth/mfa-setup"),
+            test::TestRequest::delete().uri(&format!("/users/me/api-keys/{}", uuid::Uuid::new_v4())),
+        ];
+        for request in requests {
+            let request = request
+                .insert_header(("Authorization", format!("Bearer {}", delegated.access_token)))
+                .to_request();
+            let path = request.path().to_string();
+            let status = status_of(test::try_call_service(&app, request).await);
+            assert_eq!(status, StatusCode::UNAUTHORIZED, "{}", path);
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_overview_lists_stored_passkeys_and_proxy_aliases() {
+        let ctx = TestContext::new();
+        let app = ctx.spawn_app().await;
+        let user = ctx.user().create().await.unwrap();
+        ctx.db
+            .create_passkey(crate::models::NewPasskey {
+                user_id: user.user.id,
+                credential_id: "cred-1".to_string(),
+                public_key: "key".to_string(),
+                counter: 0,
+                created_at: chrono::Utc::now(),
+                last_used_at: None,
+                device_name: Some("Laptop".to_string()),
+                aaguid: None,
+            })
+            .await
+            .unwrap();
+        ctx.db
+            .create_proxy_alias(crate::models::NewProxyAlias {
+                id: uuid::Uuid::new_v4(),
+                user_id: user.user.id,
+                proxy_address: "k3j9@relay.example.com".to_string(),
+                label: "Shopping".to_string(),
+            })
+            .await
+            .unwrap();
+        let session = ctx.session(&user).create().await.unwrap();
+
+        let request = test::TestRequest::get()
+            .uri("/users/me/overview")
+            .insert_header(("Authorization", session.bearer()))
+            .to_request();
+        let overview: Value = test::call_and_read_body_json(&app, request).await;
+        assert_eq!(overview["passkeys"][0]["device_name"], "Laptop");
+        assert_eq!(overview["proxy_aliases"][0]["proxy_address"], "k3j9@relay.example.com");
+        assert_eq!(overview["proxy_aliases"][0]["status"], "active");
+    }
+
+    #[actix_web::test]
+    async fn test_login_history_is_paged() {
+        let ctx = TestContext::new();
+        let app = ctx.spawn_app().await;
+        let user = ctx.user().create().await.unwrap();
+        let mut access_token = String::new();
+        for _ in 0..3 {
+            let response = login(&app, &user.user.username, &user.password).await.assert_success();
+            access_token = response.field("access_token").unwrap().to_string();
+        }
+
+        let history = |query: &str| {
+            test::TestRequest::get()
+                .uri(&format!("/users/login-history?{}", query))
+                .insert_header(("Authorization", format!("Bearer {}", access_token)))
+                .to_request()
+        };
+        let first: Value = test::call_and_read_body_json(&app, history("limit=2")).await;
+        assert_eq!(first["total"], 3);
+        assert_eq!(first["items"].as_array().unwrap().len(), 2);
+        let rest: Value = test::call_and_read_body_json(&app, history("limit=2&offset=2")).await;
+        assert_eq!(rest["items"].as_array().unwrap().len(), 1);
+
+        let response = test::call_service(&app, history("limit=0")).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+}
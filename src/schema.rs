@@ -1,5 +1,217 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    account_appeals (id) {
+        id -> Uuid,
+        user_id -> Uuid,
+        message -> Text,
+        status -> Text,
+        resolution_note -> Nullable<Text>,
+        resolved_by -> Nullable<Uuid>,
+        resolved_at -> Nullable<Timestamptz>,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    account_risk_signals (id) {
+        id -> Uuid,
+        user_id -> Uuid,
+        signal -> Text,
+        weight -> Int4,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    account_status_events (id) {
+        id -> Uuid,
+        user_id -> Uuid,
+        status -> Text,
+        reason -> Text,
+        actor_id -> Nullable<Uuid>,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    action_token_redemptions (jti) {
+        jti -> Uuid,
+        purpose -> Text,
+        user_id -> Uuid,
+        redeemed_at -> Timestamptz,
+        expires_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    api_key_usage (api_key_id, day) {
+        api_key_id -> Uuid,
+        day -> Date,
+        request_count -> Int8,
+    }
+}
+
+diesel::table! {
+    api_keys (id) {
+        id -> Uuid,
+        user_id -> Uuid,
+        name -> Text,
+        key_prefix -> Text,
+        key_hash -> Text,
+        scopes -> Array<Text>,
+        expires_at -> Nullable<Timestamptz>,
+        last_used_at -> Nullable<Timestamptz>,
+        created_at -> Timestamptz,
+        daily_quota -> Nullable<Int8>,
+        monthly_quota -> Nullable<Int8>,
+    }
+}
+
+diesel::table! {
+    authenticator_metadata (aaguid) {
+        aaguid -> Uuid,
+        description -> Text,
+        certification_level -> Nullable<Text>,
+        compromised -> Bool,
+        blob_number -> Int4,
+        updated_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    client_applications (client_id) {
+        client_id -> Text,
+        name -> Text,
+        redirect_uris -> Array<Text>,
+        allowed_grants -> Array<Text>,
+        access_token_ttl -> Nullable<Int4>,
+        refresh_token_ttl -> Nullable<Int4>,
+        logo_url -> Nullable<Text>,
+        updated_by -> Nullable<Uuid>,
+        created_at -> Timestamptz,
+        updated_at -> Timestamptz,
+        first_party -> Bool,
+        client_secret_hash -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    client_consents (user_id, client_id) {
+        user_id -> Uuid,
+        client_id -> Text,
+        scopes -> Array<Text>,
+        granted_at -> Timestamptz,
+        updated_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    bulk_jobs (id) {
+        id -> Uuid,
+        action -> Text,
+        params -> Jsonb,
+        dry_run -> Bool,
+        status -> Text,
+        created_by -> Nullable<Uuid>,
+        created_at -> Timestamptz,
+        started_at -> Nullable<Timestamptz>,
+        finished_at -> Nullable<Timestamptz>,
+        results -> Jsonb,
+        error -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    canary_credentials (id) {
+        id -> Uuid,
+        user_id -> Uuid,
+        api_key_id -> Nullable<Uuid>,
+        label -> Text,
+        trip_count -> Int4,
+        last_tripped_at -> Nullable<Timestamptz>,
+        last_tripped_ip -> Nullable<Text>,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    email_sends (id) {
+        id -> Uuid,
+        address -> Text,
+        kind -> Text,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    events_outbox (id) {
+        id -> Uuid,
+        event_type -> Text,
+        aggregate_id -> Uuid,
+        payload -> Jsonb,
+        attempts -> Int4,
+        last_error -> Nullable<Text>,
+        next_attempt_at -> Timestamptz,
+        delivered_at -> Nullable<Timestamptz>,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    feature_flags (key) {
+        key -> Text,
+        description -> Nullable<Text>,
+        enabled -> Bool,
+        rollout_percent -> Int4,
+        organization_ids -> Array<Uuid>,
+        updated_by -> Nullable<Uuid>,
+        created_at -> Timestamptz,
+        updated_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    login_freezes (id) {
+        id -> Uuid,
+        organization_id -> Nullable<Uuid>,
+        message -> Text,
+        frozen_by -> Nullable<Uuid>,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    login_policies (id) {
+        id -> Uuid,
+        user_id -> Nullable<Uuid>,
+        organization_id -> Nullable<Uuid>,
+        allowed_countries -> Array<Text>,
+        window_start -> Nullable<Time>,
+        window_end -> Nullable<Time>,
+        utc_offset_minutes -> Int4,
+        on_violation -> Text,
+        updated_by -> Nullable<Uuid>,
+        updated_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    mfa_failures (user_id) {
+        user_id -> Uuid,
+        failure_count -> Int4,
+        last_failed_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    mfa_method_preferences (user_id) {
+        user_id -> Uuid,
+        methods -> Array<Text>,
+        updated_at -> Timestamptz,
+    }
+}
+
 diesel::table! {
     mfa_recovery_codes (id) {
         id -> Uuid,
@@ -11,6 +223,123 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    mfa_totp_devices (id) {
+        id -> Uuid,
+        user_id -> Uuid,
+        name -> Text,
+        secret -> Text,
+        is_confirmed -> Bool,
+        created_at -> Timestamptz,
+        confirmed_at -> Nullable<Timestamptz>,
+        last_used_at -> Nullable<Timestamptz>,
+    }
+}
+
+diesel::table! {
+    notification_preferences (user_id) {
+        user_id -> Uuid,
+        security_alerts -> Bool,
+        product_emails -> Bool,
+        updated_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    organization_branding (organization_id) {
+        organization_id -> Uuid,
+        display_name -> Nullable<Text>,
+        logo_url -> Nullable<Text>,
+        primary_color -> Nullable<Text>,
+        updated_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    organization_domains (id) {
+        id -> Uuid,
+        organization_id -> Uuid,
+        domain -> Text,
+        created_at -> Timestamptz,
+        verification_token -> Text,
+        verified_at -> Nullable<Timestamptz>,
+        last_checked_at -> Nullable<Timestamptz>,
+        auto_join -> Bool,
+    }
+}
+
+diesel::table! {
+    organization_members (id) {
+        id -> Uuid,
+        organization_id -> Uuid,
+        user_id -> Uuid,
+        role -> Text,
+        created_at -> Timestamptz,
+        updated_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    organizations (id) {
+        id -> Uuid,
+        name -> Text,
+        slug -> Text,
+        created_at -> Timestamptz,
+        updated_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    passkey_prompts (user_id) {
+        user_id -> Uuid,
+        prompt_count -> Int4,
+        last_prompted_at -> Nullable<Timestamptz>,
+        dismissed_at -> Nullable<Timestamptz>,
+    }
+}
+
+diesel::table! {
+    policy_acceptances (id) {
+        id -> Uuid,
+        user_id -> Uuid,
+        version -> Text,
+        ip_address -> Nullable<Text>,
+        user_agent -> Nullable<Text>,
+        accepted_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    proxy_aliases (id) {
+        id -> Uuid,
+        user_id -> Uuid,
+        proxy_address -> Text,
+        label -> Text,
+        status -> Text,
+        forwarding_enabled -> Bool,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    security_question_failures (user_id) {
+        user_id -> Uuid,
+        failure_count -> Int4,
+        last_failed_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    security_questions (id) {
+        id -> Uuid,
+        user_id -> Uuid,
+        position -> Int4,
+        question -> Text,
+        answer_hash -> Text,
+        created_at -> Timestamptz,
+    }
+}
+
 diesel::table! {
     sessions (id) {
         id -> Uuid,
@@ -22,6 +351,92 @@ diesel::table! {
         created_at -> Timestamptz,
         updated_at -> Timestamptz,
         is_revoked -> Bool,
+        browser -> Nullable<Text>,
+        os -> Nullable<Text>,
+        device_class -> Nullable<Text>,
+        name -> Nullable<Text>,
+        is_pinned -> Bool,
+        dpop_jkt -> Nullable<Text>,
+        last_seen_at -> Timestamptz,
+        network_country -> Nullable<Text>,
+        network_asn -> Nullable<Text>,
+        client_id -> Nullable<Text>,
+        scopes -> Nullable<Array<Text>>,
+        amr -> Array<Text>,
+        lifetime -> Text,
+    }
+}
+
+diesel::table! {
+    sso_connections (id) {
+        id -> Uuid,
+        organization_id -> Uuid,
+        protocol -> Text,
+        enabled -> Bool,
+        oidc_issuer -> Nullable<Text>,
+        oidc_client_id -> Nullable<Text>,
+        oidc_client_secret -> Nullable<Text>,
+        saml_idp_metadata -> Nullable<Text>,
+        groups_attribute -> Text,
+        role_mappings -> Jsonb,
+        default_role -> Text,
+        created_at -> Timestamptz,
+        updated_at -> Timestamptz,
+        provisioning_rules -> Jsonb,
+    }
+}
+
+diesel::table! {
+    sso_identities (id) {
+        id -> Uuid,
+        connection_id -> Uuid,
+        user_id -> Uuid,
+        subject -> Text,
+        created_at -> Timestamptz,
+        last_login_at -> Nullable<Timestamptz>,
+    }
+}
+
+diesel::table! {
+    token_revocations (id) {
+        id -> Uuid,
+        expires_at -> Timestamptz,
+        revoked_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    trusted_devices (id) {
+        id -> Uuid,
+        user_id -> Uuid,
+        token_hash -> Text,
+        name -> Nullable<Text>,
+        expires_at -> Timestamptz,
+        last_used_at -> Nullable<Timestamptz>,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    user_directory (user_id) {
+        user_id -> Uuid,
+        region -> Text,
+        email_digest -> Text,
+        username_digest -> Text,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    user_emails (id) {
+        id -> Uuid,
+        user_id -> Uuid,
+        email -> Text,
+        is_verified -> Bool,
+        verification_token -> Nullable<Text>,
+        verification_sent_at -> Nullable<Timestamptz>,
+        verified_at -> Nullable<Timestamptz>,
+        created_at -> Timestamptz,
     }
 }
 
@@ -41,16 +456,123 @@ diesel::table! {
         created_at -> Timestamptz,
         updated_at -> Timestamptz,
         last_login_at -> Nullable<Timestamptz>,
-        is_active -> Bool,
         is_admin -> Bool,
+        token_version -> Int4,
+        display_name -> Nullable<Text>,
+        avatar_url -> Nullable<Text>,
+        locale -> Nullable<Text>,
+        timezone -> Nullable<Text>,
+        metadata -> Jsonb,
+        password_reset_email -> Nullable<Text>,
+        password_expires_at -> Nullable<Timestamptz>,
+        status -> Text,
+        status_reason -> Nullable<Text>,
+        status_changed_by -> Nullable<Uuid>,
+        status_changed_at -> Nullable<Timestamptz>,
+        is_guest -> Bool,
+        mfa_reenrollment_required -> Bool,
+        failed_login_count -> Int4,
+        last_failed_login_at -> Nullable<Timestamptz>,
+        locked_until -> Nullable<Timestamptz>,
+        activation_pending -> Bool,
+        version -> Int4,
+        email_display -> Nullable<Text>,
+        archived_at -> Nullable<Timestamptz>,
+    }
+}
+
+diesel::table! {
+    webauthn_credentials (id) {
+        id -> Int4,
+        user_id -> Uuid,
+        credential_id -> Text,
+        public_key -> Text,
+        counter -> Int4,
+        created_at -> Timestamptz,
+        last_used_at -> Nullable<Timestamptz>,
+        device_name -> Nullable<Text>,
+        aaguid -> Nullable<Uuid>,
     }
 }
 
+diesel::joinable!(account_appeals -> users (user_id));
+diesel::joinable!(account_risk_signals -> users (user_id));
+diesel::joinable!(account_status_events -> users (user_id));
+diesel::joinable!(action_token_redemptions -> users (user_id));
+diesel::joinable!(api_key_usage -> api_keys (api_key_id));
+diesel::joinable!(api_keys -> users (user_id));
+diesel::joinable!(bulk_jobs -> users (created_by));
+diesel::joinable!(canary_credentials -> api_keys (api_key_id));
+diesel::joinable!(canary_credentials -> users (user_id));
+diesel::joinable!(client_applications -> users (updated_by));
+diesel::joinable!(client_consents -> client_applications (client_id));
+diesel::joinable!(client_consents -> users (user_id));
+diesel::joinable!(feature_flags -> users (updated_by));
+diesel::joinable!(login_freezes -> organizations (organization_id));
+diesel::joinable!(login_freezes -> users (frozen_by));
+diesel::joinable!(login_policies -> organizations (organization_id));
+diesel::joinable!(login_policies -> users (user_id));
+diesel::joinable!(mfa_failures -> users (user_id));
+diesel::joinable!(mfa_method_preferences -> users (user_id));
 diesel::joinable!(mfa_recovery_codes -> users (user_id));
+diesel::joinable!(mfa_totp_devices -> users (user_id));
+diesel::joinable!(notification_preferences -> users (user_id));
+diesel::joinable!(organization_branding -> organizations (organization_id));
+diesel::joinable!(organization_domains -> organizations (organization_id));
+diesel::joinable!(organization_members -> organizations (organization_id));
+diesel::joinable!(organization_members -> users (user_id));
+diesel::joinable!(passkey_prompts -> users (user_id));
+diesel::joinable!(policy_acceptances -> users (user_id));
+diesel::joinable!(proxy_aliases -> users (user_id));
+diesel::joinable!(security_question_failures -> users (user_id));
+diesel::joinable!(security_questions -> users (user_id));
+diesel::joinable!(sessions -> client_applications (client_id));
 diesel::joinable!(sessions -> users (user_id));
+diesel::joinable!(sso_connections -> organizations (organization_id));
+diesel::joinable!(sso_identities -> sso_connections (connection_id));
+diesel::joinable!(sso_identities -> users (user_id));
+diesel::joinable!(trusted_devices -> users (user_id));
+diesel::joinable!(user_emails -> users (user_id));
+diesel::joinable!(webauthn_credentials -> users (user_id));
 
 diesel::allow_tables_to_appear_in_same_query!(
+    account_appeals,
+    account_risk_signals,
+    account_status_events,
+    action_token_redemptions,
+    api_key_usage,
+    api_keys,
+    authenticator_metadata,
+    bulk_jobs,
+    canary_credentials,
+    client_applications,
+    client_consents,
+    email_sends,
+    events_outbox,
+    feature_flags,
+    login_freezes,
+    login_policies,
+    mfa_failures,
+    mfa_method_preferences,
     mfa_recovery_codes,
+    mfa_totp_devices,
+    notification_preferences,
+    organization_branding,
+    organization_domains,
+    organization_members,
+    organizations,
+    passkey_prompts,
+    policy_acceptances,
+    proxy_aliases,
+    security_question_failures,
+    security_questions,
     sessions,
+    sso_connections,
+    sso_identities,
+    token_revocations,
+    trusted_devices,
+    user_directory,
+    user_emails,
     users,
+    webauthn_credentials,
 );
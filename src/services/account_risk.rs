@@ -0,0 +1,101 @@
+use chrono::{DateTime, Utc};
+
+use crate::config::AccountRiskConfig;
+use crate::models::{AccountRiskAction, AccountRiskSignal};
+
+// Scores an account by the suspicious activity recorded against it over a
+// rolling window. Each action fires once, when a new signal takes the score
+// from below its threshold to at or above it; the account stays at that level
+// until signals age out of the window.
+pub struct AccountRisk {
+    config: AccountRiskConfig,
+}
+
+impl AccountRisk {
+    pub fn new(config: &AccountRiskConfig) -> Self {
+        AccountRisk { config: config.clone() }
+    }
+
+    pub fn window_days(&self) -> u32 {
+        self.config.window_days
+    }
+
+    /// Signals recorded before this no longer count
+    pub fn window_start(&self, now: DateTime<Utc>) -> DateTime<Utc> {
+        now - chrono::Duration::days(self.config.window_days as i64)
+    }
+
+    pub fn score(signals: &[AccountRiskSignal]) -> i32 {
+        signals.iter().map(|signal| signal.weight).sum()
+    }
+
+    /// Every action whose threshold `score` has reached
+    pub fn actions_at(&self, score: i32) -> Vec<AccountRiskAction> {
+        self.thresholds()
+            .filter(|(threshold, _)| score >= *threshold)
+            .map(|(_, action)| action)
+            .collect()
+    }
+
+    /// Actions whose threshold lies between the score before a signal and after it
+    pub fn crossed(&self, before: i32, after: i32) -> Vec<AccountRiskAction> {
+        self.thresholds()
+            .filter(|(threshold, _)| before < *threshold && after >= *threshold)
+            .map(|(_, action)| action)
+            .collect()
+    }
+
+    // Enabled thresholds, least drastic action first
+    fn thresholds(&self) -> impl Iterator<Item = (i32, AccountRiskAction)> {
+        [
+            (self.config.notify_threshold, AccountRiskAction::Notify),
+            (self.config.revoke_sessions_threshold, AccountRiskAction::RevokeSessions),
+            (self.config.mfa_reenrollment_threshold, AccountRiskAction::RequireMfaReenrollment),
+        ]
+        .into_iter()
+        .filter(|(threshold, _)| *threshold > 0)
+        .map(|(threshold, action)| (threshold as i32, action))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn risk(notify: u32, revoke: u32, reenroll: u32) -> AccountRisk {
+        AccountRisk::new(&AccountRiskConfig {
+            window_days: 7,
+            notify_threshold: notify,
+            revoke_sessions_threshold: revoke,
+            mfa_reenrollment_threshold: reenroll,
+        })
+    }
+
+    #[test]
+    fn test_crossed_fires_each_action_once() {
+        let risk = risk(30, 60, 90);
+
+        assert!(risk.crossed(0, 25).is_empty());
+        assert_eq!(risk.crossed(25, 30), vec![AccountRiskAction::Notify]);
+        // Already past the notify threshold, so only the new one fires
+        assert_eq!(risk.crossed(30, 65), vec![AccountRiskAction::RevokeSessions]);
+        assert!(risk.crossed(65, 70).is_empty());
+        // A big jump crosses several at once
+        assert_eq!(
+            risk.crossed(0, 100),
+            vec![
+                AccountRiskAction::Notify,
+                AccountRiskAction::RevokeSessions,
+                AccountRiskAction::RequireMfaReenrollment,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_zero_threshold_is_disabled() {
+        let risk = risk(30, 0, 90);
+
+        assert_eq!(risk.actions_at(1000), vec![AccountRiskAction::Notify, AccountRiskAction::RequireMfaReenrollment]);
+        assert!(risk.crossed(-10, 0).is_empty());
+    }
+}
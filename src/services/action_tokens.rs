@@ -0,0 +1,87 @@
+use std::sync::Arc;
+
+use chrono::Duration;
+use uuid::Uuid;
+
+use crate::db::DatabaseConnection;
+use crate::errors::AuthError;
+use crate::models::NewActionTokenRedemption;
+use crate::utils::action_token::{ActionClaims, ActionPurpose, ActionTokenSigner};
+
+// One-time action links (email verification, password reset, magic links,
+// email change confirmation, unsubscribe, ...). Tokens are self-contained and
+// signed, so nothing is stored when one is issued; redeeming records the
+// token's id so it can't be used twice.
+pub struct ActionTokens {
+    db: Arc<DatabaseConnection>,
+    signer: ActionTokenSigner,
+}
+
+impl ActionTokens {
+    pub fn new(db: Arc<DatabaseConnection>, secret: &str) -> Self {
+        ActionTokens {
+            db,
+            signer: ActionTokenSigner::new(secret),
+        }
+    }
+
+    pub fn issue(&self, claims: &ActionClaims) -> Result<String, AuthError> {
+        self.signer.sign(claims)
+    }
+
+    /// Check a token and mark it used. Callers still compare the claims'
+    /// binding against the current state of whatever the link acts on.
+    pub async fn redeem(&self, token: &str, purpose: ActionPurpose) -> Result<ActionClaims, AuthError> {
+        let claims = self.signer.verify(token, purpose)?;
+        self.record_redemption(claims).await
+    }
+
+    /// `redeem`, also refusing tokens older than `max_age`, so shortening a
+    /// configured lifetime applies to links already sent
+    pub async fn redeem_within(
+        &self,
+        token: &str,
+        purpose: ActionPurpose,
+        max_age: Duration,
+    ) -> Result<ActionClaims, AuthError> {
+        let claims = self.signer.verify(token, purpose)?;
+        if !claims.issued_within(max_age) {
+            return Err(AuthError::TokenExpired);
+        }
+        self.record_redemption(claims).await
+    }
+
+    /// The user a token for `purpose` was issued to, without using it up,
+    /// e.g. to find which region's database to redeem it in
+    pub fn subject(&self, token: &str, purpose: ActionPurpose) -> Option<Uuid> {
+        self.signer.verify(token, purpose).ok().map(|claims| claims.sub)
+    }
+
+    /// Check a token without using it up, for links that may be followed
+    /// more than once, such as unsubscribing
+    pub fn verify_within(
+        &self,
+        token: &str,
+        purpose: ActionPurpose,
+        max_age: Duration,
+    ) -> Result<ActionClaims, AuthError> {
+        let claims = self.signer.verify(token, purpose)?;
+        if !claims.issued_within(max_age) {
+            return Err(AuthError::TokenExpired);
+        }
+        Ok(claims)
+    }
+
+    async fn record_redemption(&self, claims: ActionClaims) -> Result<ActionClaims, AuthError> {
+        self.db
+            .redeem_action_token(NewActionTokenRedemption {
+                jti: claims.jti,
+                purpose: claims.purpose.as_str().to_string(),
+                user_id: claims.sub,
+                expires_at: claims.expires_at(),
+            })
+            .await?;
+
+        Ok(claims)
+    }
+}
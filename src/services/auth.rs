@@ -1,43 +1,247 @@
+use std::collections::HashSet;
+use std::path::Path;
 use std::sync::Arc;
 
-use chrono::{Duration, Utc};
+use actix_web::http::header::HeaderMap;
+use chrono::{DateTime, Duration, Utc};
+use rand::{distributions::Alphanumeric, Rng};
 use uuid::Uuid;
 
-use crate::db::DatabaseConnection;
+use crate::accessibility::{
+    AccessibilityContext, AccessibilityReport, CaptchaAlternative, CaptchaChallenge, SpeechError,
+    VoiceCommand,
+};
+use crate::db::{DatabaseConnection, UnitOfWork};
 use crate::errors::AuthError;
+use crate::middleware::auth::{AuthenticatedUser, UserCache};
 use crate::models::{
-    DisableMfaRequest, EnableMfaRequest, LoginRequest, LogoutRequest, LoginResponse,
-    MfaLoginRequest, MfaRecoveryCodesResponse, MfaRecoveryRequest, MfaSetupResponse,
-    MfaVerifyRequest, MfaVerifyResponse, NewMfaRecoveryCode, NewSession, NewUser,
-    PasswordResetConfirmRequest, PasswordResetRequest, PasswordResetResponse, RefreshTokenRequest,
-    RefreshTokenResponse, RegisterRequest, RegisterResponse, Session, SessionResponse,
-    User, UserResponse, VerifyEmailRequest,
+    AcceptPolicyRequest, AccountAppeal, AccountOverview, AccountRiskAction, AccountRiskResponse,
+    AccountSignal, AccountLockRequest, AccountStatus, AccountStatusEvent, AccountStatusResponse, ActivateAccountRequest, AddBackupEmailRequest, AddOrganizationDomainRequest,
+    AddTotpDeviceRequest, AdminUserResponse, ApiKeyResponse, AuditEventFilter, ApiKeyUsageResponse, AppealRequest, ApproveLoginRequest,
+    BackupEmailResponse, BulkAction, BulkJob, BulkJobReport, BulkJobRequest, BulkJobResponse, CanaryCredential, CanaryListResponse, CaptchaChallengeRequest, CaptchaSolution,
+    AuthorizedApp, ChangePasswordRequest, ClientApplication, ClientApplicationRequest, client_secret_hash, ConfirmTotpDeviceRequest, CreateApiKeyRequest, CreateCanaryRequest,
+    CreateOrganizationRequest, CreatedApiKeyResponse, CreatedCanaryResponse,
+    DelegatedTokenRequest, DelegatedTokenResponse, DisableMfaRequest, EmailCodeChallenge, EmailCodeLoginResponse, EmailCodeStartRequest,
+    EmailCodeVerifyRequest, EmailRegisterRequest, EnableMfaRequest, EventType, ForcePasswordResetRequest,
+    ForcePasswordResetResponse, GuestRequest, GuestUpgrade, FeatureFlag, FeatureFlagRequest, InviteMemberRequest, InviteUserRequest, LoginFreeze, LoginFreezeList,
+    LockedAccount, LockedIp, LockoutsResponse, LoginFreezeRequest, LoginPolicy, LoginPolicyOverview, LoginPolicyRequest,
+    LoginPolicyScope, LoginRequest, LoginResponse,
+    LinkedPreferencesRequest, LogoutRequest, LogoutResponse, MfaLoginRequest, MfaMethod, MfaOverview, MfaRecoveryCodesResponse,
+    MfaRecoveryRequest, MfaSetupResponse, MfaVerifyRequest, MfaVerifyResponse, NewAccountAppeal,
+    NewAccountRiskSignal, NewApiKey, NewBackupEmail, NewBulkJob, NewCanaryCredential, NewClientApplication, NewClientConsent, NewFeatureFlag, NewLoginFreeze, NewLoginPolicy, NewMfaRecoveryCode, NewOrganization,
+    NotificationCategory, NotificationLinkRequest, NotificationPreferences, NotificationPreferencesResponse,
+    NewOrganizationDomain, NewOrganizationMember, NewPolicyAcceptance, NewSession,
+    NewOrganizationBranding, NewSsoConnection, NewSsoIdentity, NewTotpDevice, NewUser, OidcCallbackQuery, Organization, OutboxEvent,
+    OrganizationBranding, OrganizationBrandingRequest, OrganizationBrandingResponse, OrganizationDomain,
+    OrganizationDomainResponse, OrganizationResponse, OrganizationRole, Page, PageRequest,
+    PasskeyPrompt, PasskeyPromptState, ProxyAliasResponse, PasswordResetConfirmRequest, PasswordResetRequest, PasswordResetResponse,
+    PolicyNotice, ProfileChanges, TokenTypeHint, ProvisioningRules, LockAccountRequest, SavedClientApplication, ReactivateAccountRequest, ReauthenticateRequest, ReauthenticateResponse, RecoveryCodeStatus,
+    RecentLogin, RefreshTokenRequest, RefreshTokenResponse, RegisterRequest, RegisterResponse,
+    ResolveAppealRequest, SamlAcsForm, NewSecurityQuestion, SecurityQuestionRecoveryRequest, SecurityQuestionsRequest,
+    SecurityQuestionsResponse, normalize_answer, GRANT_PASSWORD, GRANT_REFRESH_TOKEN, GRANT_TYPES, SecurityAction, Session, SessionChanges, SessionFilter, SessionLifetime,
+    SessionResponse, SessionTableMetrics, SsoConnection, SsoConnectionRequest, SsoConnectionResponse, SsoDiscoverRequest,
+    SsoDiscoverResponse, SsoProtocol, TotpDevice, TotpDeviceRemovedResponse, TotpDeviceResponse, TotpDeviceSetupResponse,
+    NewTrustedDevice, TrustedDeviceLoginRequest,
+    UpdateAccountStatusRequest, UpdateApiKeyQuotaRequest, UpdateOrganizationDomainRequest,
+    UpdateNotificationPreferencesRequest, UpdateProfileRequest, UpdateSessionRequest, UpgradeGuestRequest, User, UserFilter, UserResponse,
+    VerifyBackupEmailRequest, VerifyEmailRequest,
 };
-use crate::services::email::EmailService;
-use crate::services::mfa::MfaService;
+use crate::breach_detection::BreachDetectionContext;
+use crate::risk_scoring::{RiskFactor, RiskScoringContext};
+use crate::services::account_risk::AccountRisk;
+use crate::services::action_tokens::ActionTokens;
+use crate::services::domain_verification::{normalize_domain, DomainVerifier};
+use crate::services::email::{EmailService, EmailTransport, RegistrationNotice, SecurityAlert};
+use crate::services::email_code::{device_token, hash_device_token, EmailCodes};
+use crate::services::email_throttle::{EmailThrottle, ThrottledEmail};
+use crate::services::ip_reputation::ip_reputation;
+use crate::services::mfa::{MfaService, QrFormat};
+use crate::services::provisioning::{self, ProvisioningPlan};
+use crate::services::quotas::{self, QuotaStatus};
+use crate::services::brute_force::BruteForceGuard;
+use crate::services::bulk_jobs::BulkJobRunner;
+use crate::services::canary::SourceBlocklist;
+use crate::services::security_events::{SecurityEvent, SecurityEventKind, SecurityWebhook};
+use crate::services::seed::{self, DemoState, SeedReport, SeededAccount};
+use crate::services::session_activity::SessionActivity;
+use crate::services::event_export::EventExporter;
+use crate::services::feature_flags::{self, FeatureFlags, Flags};
+use crate::services::fido_metadata::FidoMetadata;
+use crate::services::session_purge::SessionPurge;
+use crate::services::token_revocation::TokenRevocations;
+use crate::services::login_approval::{LoginApprovals, PendingLogin};
+use crate::services::login_checks::{CheckOutcome, LoginAttempt, LoginPipeline, LoginVerdict};
+use crate::services::speech::speech_to_text;
+use crate::services::sso::{email_domain, FederatedIdentity, SsoService};
+use crate::services::storage::{blob_storage, BlobStorage};
+use crate::services::system_checks::{self, SystemCheckReport};
+use crate::services::tarpit::{LoginTarpit, TarpitMetrics};
+use crate::services::timing::ResponseFloor;
+use crate::services::user_archive::UserArchiver;
 use crate::utils::{
-    jwt::{create_jwt, JwtClaims},
-    password::hash_password, password::verify_password,
-    validation::validate_email, validation::validate_password, validation::validate_username,
+    action_token::{fingerprint, ActionClaims, ActionPurpose},
+    api_key,
+    dpop::{Confirmation, DpopVerifier},
+    jwt::{assurance_level, create_jwt, decode_jwt, decode_jwt_with_secret, Actor, JwtClaims, TokenAudience, TokenScope, AMR_EMAIL, AMR_FEDERATED, AMR_KBA, AMR_MFA, AMR_OTP, AMR_PASSWORD},
+    network::NetworkFingerprint,
+    password::{hash_password, verify_dummy_password, verify_password},
+    recovery_sheet::RecoverySheet,
+    scopes::{self, default_scopes},
+    secret::Secret,
+    avatar::process_avatar,
+    user_agent::DeviceInfo,
+    validation::{
+        normalize_email, validate_http_url, validate_locale, validate_metadata, validate_password,
+        validate_redirect_uri, validate_timezone, validate_username,
+    },
 };
-use crate::config::Config;
+use crate::utils::i18n::Translator;
+use crate::config::{Config, EmailVerificationPolicy, HostedPagesConfig, NetworkMismatchPolicy, RefreshBinding};
+
+// Backup addresses a user can register besides their primary email
+const MAX_BACKUP_EMAILS: usize = 5;
+
+// Personal access tokens a user can hold at once
+const MAX_API_KEYS: usize = 25;
+
+// Most recent bulk jobs listed for admins
+const MAX_LISTED_BULK_JOBS: i64 = 100;
+
+// Between a refresh token's region and the rest; region names don't use it
+const REFRESH_TOKEN_REGION_SEPARATOR: char = '_';
+
+// Keeps the rules evaluated on each SSO login to a manageable number
+const MAX_PROVISIONING_RULES: usize = 50;
+
+// Reserved TLD (RFC 2606), so guest addresses can never receive mail
+const GUEST_EMAIL_DOMAIN: &str = "guest.invalid";
 
 pub struct AuthService {
     db: Arc<DatabaseConnection>,
     email_service: EmailService,
     mfa_service: MfaService,
+    tarpit: LoginTarpit,
+    brute_force: Arc<BruteForceGuard>,
+    login_checks: LoginPipeline,
+    login_approvals: LoginApprovals,
+    email_codes: EmailCodes,
+    session_activity: SessionActivity,
+    session_purge: Arc<SessionPurge>,
+    event_exporter: Arc<EventExporter>,
+    user_archiver: Arc<UserArchiver>,
+    bulk_jobs: Arc<BulkJobRunner>,
+    account_risk: AccountRisk,
+    security_webhook: Arc<SecurityWebhook>,
+    breach_detection: Arc<BreachDetectionContext>,
+    risk_scoring: Arc<RiskScoringContext>,
+    source_blocklist: SourceBlocklist,
+    sso: SsoService,
+    domain_verifier: DomainVerifier,
+    action_tokens: ActionTokens,
+    email_throttle: EmailThrottle,
+    accessibility: Arc<AccessibilityContext>,
+    storage: Arc<dyn BlobStorage>,
+    user_cache: Arc<UserCache>,
+    token_revocations: Arc<TokenRevocations>,
+    feature_flags: Arc<FeatureFlags>,
+    dpop: Arc<DpopVerifier>,
+    fido_metadata: Arc<FidoMetadata>,
+    translator: Arc<Translator>,
     config: Config,
 }
 
 impl AuthService {
-    pub fn new(db: Arc<DatabaseConnection>, config: Config) -> Self {
-        let email_service = EmailService::new(config.clone());
-        let mfa_service = MfaService::new();
+    pub fn new(db: Arc<DatabaseConnection>, config: Config, translator: Arc<Translator>) -> Self {
+        let email_service = EmailService::new(config.clone(), translator.clone());
+        let mfa_service = MfaService::new(config.totp.clone());
+        let brute_force = Arc::new(BruteForceGuard::new(&config.brute_force));
+        let tarpit = LoginTarpit::new(config.tarpit.clone(), brute_force.clone());
+        let login_approvals = LoginApprovals::new(&config.login_approval);
+        let email_codes = EmailCodes::new(&config.email_code_login);
+        let session_activity = SessionActivity::new(&config.sessions);
+        let session_purge = Arc::new(SessionPurge::new(db.clone(), &config.session_purge));
+        let event_exporter = Arc::new(EventExporter::from_config(
+            db.clone(),
+            &config.event_export,
+            config.account_risk.window_days,
+        ));
+        let account_risk = AccountRisk::new(&config.account_risk);
+        let security_webhook = Arc::new(SecurityWebhook::new(config.security_webhook.clone()));
+        let breach_detection = Arc::new(BreachDetectionContext::new());
+        let risk_scoring = match ip_reputation(&config.ip_reputation) {
+            Some(reputation) => RiskScoringContext::new().with_ip_reputation(reputation),
+            None => RiskScoringContext::new(),
+        };
+        let risk_scoring = Arc::new(risk_scoring);
+        let login_checks = LoginPipeline::new(
+            &config,
+            breach_detection.clone(),
+            risk_scoring.clone(),
+            security_webhook.clone(),
+        );
+        let source_blocklist = SourceBlocklist::new(&config.canary);
+        let sso = SsoService::new(&config.sso);
+        let domain_verifier = DomainVerifier::new(&config.domain_verification);
+        let action_tokens = ActionTokens::new(db.clone(), &config.jwt.secret);
+        let email_throttle = EmailThrottle::new(db.clone(), &config.email_resend);
+        let mut accessibility = match &config.captcha.audio_dir {
+            Some(dir) => AccessibilityContext::new()
+                .with_audio_clips(Path::new(dir))
+                .expect("CAPTCHA_AUDIO_DIR must contain 0.wav to 9.wav"),
+            None => AccessibilityContext::new(),
+        };
+        if let Some(speech) = speech_to_text(&config.speech) {
+            accessibility = accessibility.with_speech_provider(speech, config.speech.min_confidence);
+        }
+        let storage = Arc::from(blob_storage(&config.storage));
+        let user_cache = Arc::new(UserCache::new(db.clone(), &config.user_cache));
+        // Revocations must outlast the longest-lived token they could match
+        let token_revocations = Arc::new(TokenRevocations::new(
+            db.clone(),
+            &config.token_revocation,
+            config
+                .jwt
+                .access_token_expiry
+                .max(config.session_lifetime.trusted_access_token_expiry)
+                .max(config.delegation.max_ttl),
+        ));
+        let feature_flags = Arc::new(FeatureFlags::new(db.clone(), &config.feature_flags));
+        let user_archiver = Arc::new(UserArchiver::new(db.clone(), user_cache.clone(), &config.user_archive));
+        let bulk_jobs = Arc::new(BulkJobRunner::new(db.clone(), user_cache.clone(), &config.bulk_jobs));
+        let dpop = Arc::new(DpopVerifier::new(&config.dpop));
+        let fido_metadata = Arc::new(FidoMetadata::new(db.clone(), &config.fido_metadata));
         
         AuthService {
             db,
             email_service,
             mfa_service,
+            tarpit,
+            brute_force,
+            login_checks,
+            login_approvals,
+            email_codes,
+            session_activity,
+            session_purge,
+            event_exporter,
+            user_archiver,
+            bulk_jobs,
+            account_risk,
+            security_webhook,
+            breach_detection,
+            risk_scoring,
+            source_blocklist,
+            sso,
+            domain_verifier,
+            action_tokens,
+            email_throttle,
+            accessibility: Arc::new(accessibility),
+            storage,
+            user_cache,
+            token_revocations,
+            feature_flags,
+            dpop,
+            fido_metadata,
+            translator,
             config,
         }
     }
@@ -47,10 +251,29 @@ impl AuthService {
         data: RegisterRequest,
         ip: Option<String>,
         user_agent: Option<String>,
+        locale: &str,
+    ) -> Result<RegisterResponse, AuthError> {
+        let region = match data.region.as_deref() {
+            None => None,
+            Some(region) if self.db.has_region(region) => Some(region.to_string()),
+            Some(_) => return Err(AuthError::ValidationError("Unknown region".into())),
+        };
+        DatabaseConnection::in_region(region, self.register_in_region(data, ip, user_agent, locale)).await
+    }
+
+    async fn register_in_region(
+        &self,
+        data: RegisterRequest,
+        ip: Option<String>,
+        user_agent: Option<String>,
+        locale: &str,
     ) -> Result<RegisterResponse, AuthError> {
+        self.check_captcha(&data.captcha())?;
+
         // Validate input
         validate_username(&data.username)?;
-        validate_email(&data.email)?;
+        let address = normalize_email(&data.email)?;
+        self.ensure_registration_open(Some(&address.canonical)).await?;
         validate_password(&data.password)?;
 
         if data.password != data.password_confirmation {
@@ -58,43 +281,348 @@ impl AuthService {
         }
 
         // Check if user already exists
-        if self.db.user_exists_by_username(&data.username).await? {
-            return Err(AuthError::UsernameExists);
-        }
+        let username_taken = self.db.user_exists_by_username(&data.username).await?;
+        let email_taken = self.db.user_exists_by_email(&address.canonical).await?;
+        let generic_response = self.config.registration.generic_response;
 
-        if self.db.user_exists_by_email(&data.email).await? {
-            return Err(AuthError::EmailExists);
+        if !generic_response {
+            if username_taken {
+                return Err(AuthError::UsernameExists);
+            }
+            if email_taken {
+                return Err(AuthError::EmailExists);
+            }
         }
 
         // Hash password
         let password_hash = hash_password(&data.password)?;
 
-        // Generate verification token
-        let verification_token = Uuid::new_v4().to_string();
+        // Generic responses: do the same work as a new account and answer the
+        // same way; the address owner learns by email why nothing was created
+        if username_taken || email_taken {
+            let notice = if email_taken {
+                RegistrationNotice::EmailTaken
+            } else {
+                RegistrationNotice::UsernameTaken(&data.username)
+            };
+            self.email_service
+                .send_registration_notice(&address.canonical, &notice, locale)
+                .await?;
+
+            return Ok(RegisterResponse {
+                user: None,
+                message: self.translator.text(locale, "register-pending", None),
+            });
+        }
 
         // Create user
         let new_user = NewUser {
             id: Uuid::new_v4(),
             username: data.username,
-            email: data.email.clone(),
+            email: address.canonical.clone(),
             password_hash,
             is_email_verified: false,
-            email_verification_token: Some(verification_token.clone()),
+            email_verification_token: None,
             email_verification_sent_at: Some(Utc::now()),
-            is_active: true,
             is_admin: false,
+            password_expires_at: self.password_expiry(false),
+            is_guest: false,
+            activation_pending: false,
+            email_display: address.display_if_different(),
+        };
+
+        let event = user_created_event(&new_user, "register");
+        let user = self.db.create_user(new_user, event).await?;
+
+        // Send verification email; it counts against the resend limits
+        self.email_throttle.record(&user.email, ThrottledEmail::Verification).await?;
+        let verification_token = self.email_verification_token(&user)?;
+        self.email_service
+            .send_verification_email(&user.email, &verification_token, locale)
+            .await?;
+
+        if generic_response {
+            return Ok(RegisterResponse {
+                user: None,
+                message: self.translator.text(locale, "register-pending", None),
+            });
+        }
+
+        Ok(RegisterResponse {
+            user: Some(user.into()),
+            message: self.translator.text(locale, "register-success", None),
+        })
+    }
+
+    /// Sign up with an address alone. The account can't be used until its
+    /// owner sets a password from the emailed activation link.
+    pub async fn register_with_email(
+        &self,
+        data: EmailRegisterRequest,
+        locale: &str,
+    ) -> Result<RegisterResponse, AuthError> {
+        self.check_captcha(&data.captcha())?;
+
+        let address = normalize_email(&data.email)?;
+        self.ensure_registration_open(Some(&address.canonical)).await?;
+        if let Some(username) = &data.username {
+            validate_username(username)?;
+        }
+
+        let username_taken = match &data.username {
+            Some(username) => self.db.user_exists_by_username(username).await?,
+            None => false,
         };
+        let email_taken = self.db.user_exists_by_email(&address.canonical).await?;
+        let generic_response = self.config.registration.generic_response;
+
+        if !generic_response {
+            if username_taken {
+                return Err(AuthError::UsernameExists);
+            }
+            if email_taken {
+                return Err(AuthError::EmailExists);
+            }
+        }
+
+        // Same generic answer as `register`
+        if username_taken || email_taken {
+            let notice = match (email_taken, &data.username) {
+                (false, Some(username)) => RegistrationNotice::UsernameTaken(username),
+                _ => RegistrationNotice::EmailTaken,
+            };
+            self.email_service
+                .send_registration_notice(&address.canonical, &notice, locale)
+                .await?;
 
-        let user = self.db.create_user(new_user).await?;
+            return Ok(RegisterResponse {
+                user: None,
+                message: self.translator.text(locale, "register-pending", None),
+            });
+        }
 
-        // Send verification email
+        let user = self
+            .create_pending_user(&data.email, data.username.as_deref(), "register_email")
+            .await?;
+        self.email_throttle.record(&user.email, ThrottledEmail::Activation).await?;
+        let activation_token = self.activation_token(&user)?;
         self.email_service
-            .send_verification_email(&user.email, &verification_token)
+            .send_activation_email(&user.email, &activation_token, locale)
             .await?;
 
         Ok(RegisterResponse {
+            user: (!generic_response).then(|| user.into()),
+            message: self.translator.text(locale, "register-pending", None),
+        })
+    }
+
+    /// Bring an archived account back from the link its last login emailed
+    pub async fn reactivate_account(
+        &self,
+        data: ReactivateAccountRequest,
+        locale: &str,
+    ) -> Result<PasswordResetResponse, AuthError> {
+        let region = self.region_of_link(&data.token, ActionPurpose::Reactivation).await?;
+        DatabaseConnection::in_region(region, self.reactivate_account_in_region(data, locale)).await
+    }
+
+    async fn reactivate_account_in_region(
+        &self,
+        data: ReactivateAccountRequest,
+        locale: &str,
+    ) -> Result<PasswordResetResponse, AuthError> {
+        let claims = self
+            .action_tokens
+            .redeem_within(
+                &data.token,
+                ActionPurpose::Reactivation,
+                Duration::seconds(self.config.action_tokens.reactivation_ttl as i64),
+            )
+            .await?;
+        let user = self.db.find_user_by_id(claims.sub).await?;
+
+        // Links from an earlier archiving don't work
+        if !user.is_archived() || !claims.is_bound_to(&archive_binding(&user)) {
+            return Err(AuthError::InvalidToken);
+        }
+
+        self.reactivate_if_archived(user, "link").await?;
+
+        Ok(PasswordResetResponse {
+            message: self.translator.text(locale, "account-reactivated", None),
+        })
+    }
+
+    /// Set the first password of an account created from an address alone,
+    /// which also verifies the address
+    pub async fn activate_account(
+        &self,
+        data: ActivateAccountRequest,
+        locale: &str,
+    ) -> Result<PasswordResetResponse, AuthError> {
+        let region = self.region_of_link(&data.token, ActionPurpose::Activation).await?;
+        DatabaseConnection::in_region(region, self.activate_account_in_region(data, locale)).await
+    }
+
+    async fn activate_account_in_region(
+        &self,
+        data: ActivateAccountRequest,
+        locale: &str,
+    ) -> Result<PasswordResetResponse, AuthError> {
+        validate_password(&data.password)?;
+
+        if data.password != data.password_confirmation {
+            return Err(AuthError::ValidationError("Passwords do not match".into()));
+        }
+
+        let claims = self
+            .action_tokens
+            .redeem_within(
+                &data.token,
+                ActionPurpose::Activation,
+                Duration::seconds(self.config.action_tokens.activation_ttl as i64),
+            )
+            .await?;
+        let user = self.db.find_user_by_id(claims.sub).await?;
+
+        if !user.activation_pending || !claims.is_bound_to(&user.email) {
+            return Err(AuthError::InvalidToken);
+        }
+
+        let password_hash = hash_password(&data.password)?;
+        self.db
+            .activate_user(
+                user.id,
+                &password_hash,
+                self.password_expiry(user.is_admin),
+                password_changed_event(user.id, "activation"),
+            )
+            .await?;
+        self.user_cache.invalidate(user.id);
+
+        log::info!("User {} activated their account", user.id);
+
+        Ok(PasswordResetResponse {
+            message: self.translator.text(locale, "account-activated", None),
+        })
+    }
+
+    /// Start an anonymous session backed by a synthetic guest account, which
+    /// `upgrade_guest` can later turn into a registered one
+    pub async fn create_guest(
+        &self,
+        data: GuestRequest,
+        ip: Option<String>,
+        user_agent: Option<String>,
+    ) -> Result<LoginResponse, AuthError> {
+        if !self.config.guest.enabled {
+            return Err(AuthError::PermissionDenied);
+        }
+        self.check_captcha(&data.captcha())?;
+        self.ensure_registration_open(None).await?;
+
+        // Nobody knows the password and the address can't receive mail, so
+        // the session's tokens are the only way into the account
+        let id = Uuid::new_v4();
+        let new_user = NewUser {
+            id,
+            username: format!("guest_{}", id.simple()),
+            email: format!("{}@{}", id.simple(), GUEST_EMAIL_DOMAIN),
+            password_hash: hash_password(&Uuid::new_v4().to_string())?,
+            is_email_verified: false,
+            email_verification_token: None,
+            email_verification_sent_at: None,
+            is_admin: false,
+            password_expires_at: None,
+            is_guest: true,
+            activation_pending: false,
+            email_display: None,
+        };
+        let event = user_created_event(&new_user, "guest");
+        let user = self.db.create_user(new_user, event).await?;
+
+        let refresh_token = new_refresh_token();
+
+        let expires_at = Utc::now() + Duration::seconds(self.config.guest.session_ttl as i64);
+        let session = NewSession::new(user.id, refresh_token.clone(), user_agent, ip, expires_at);
+        // Guest tokens carry the guest scope; see `create_access_token`
+        let access_token = self.create_access_token(&user, &[], Some(session.id))?;
+        self.db.create_session(session).await?;
+
+        Ok(LoginResponse {
+            access_token,
+            refresh_token,
+            token_type: "Bearer".into(),
+            expires_in: self.config.jwt.access_token_expiry,
             user: user.into(),
-            message: "User registered successfully. Please verify your email.".into(),
+            mfa_required: false,
+            passkey_prompt: None,
+            approval_id: None,
+            password_change_required: false,
+            mfa_enrollment_required: false,
+            policy_acceptance_required: None,
+        })
+    }
+
+    /// Turn the caller's guest account into a registered one. The user id, and
+    /// so everything linked to it, is kept; the guest's sessions end and the
+    /// user signs in with their new credentials.
+    pub async fn upgrade_guest(
+        &self,
+        user_id: Uuid,
+        data: UpgradeGuestRequest,
+        locale: &str,
+    ) -> Result<RegisterResponse, AuthError> {
+        validate_username(&data.username)?;
+        let address = normalize_email(&data.email)?;
+        validate_password(&data.password)?;
+
+        if data.password != data.password_confirmation {
+            return Err(AuthError::ValidationError("Passwords do not match".into()));
+        }
+
+        if self.db.user_exists_by_username(&data.username).await? {
+            return Err(AuthError::UsernameExists);
+        }
+
+        if self.db.user_exists_by_email(&address.canonical).await? {
+            return Err(AuthError::EmailExists);
+        }
+
+        let user = self
+            .db
+            .upgrade_guest_user(
+                user_id,
+                GuestUpgrade {
+                    username: data.username.clone(),
+                    email: address.canonical.clone(),
+                    email_display: address.display_if_different(),
+                    password_hash: hash_password(&data.password)?,
+                    email_verification_sent_at: Some(Utc::now()),
+                    password_expires_at: self.password_expiry(false),
+                },
+                NewOutboxEvent::new(
+                    EventType::GuestUpgraded,
+                    user_id,
+                    serde_json::json!({ "username": data.username, "email": address.canonical }),
+                ),
+            )
+            .await?;
+
+        self.db.revoke_all_sessions(user.id, true).await?;
+        self.user_cache.invalidate(user.id);
+
+        log::info!("Guest {} registered as {}", user.id, user.username);
+
+        self.email_throttle.record(&user.email, ThrottledEmail::Verification).await?;
+        let verification_token = self.email_verification_token(&user)?;
+        self.email_service
+            .send_verification_email(&user.email, &verification_token, locale)
+            .await?;
+
+        Ok(RegisterResponse {
+            user: Some(user.into()),
+            message: self.translator.text(locale, "register-success", None),
         })
     }
 
@@ -103,63 +631,142 @@ impl AuthService {
         data: LoginRequest,
         ip: Option<String>,
         user_agent: Option<String>,
+        dpop_jkt: Option<String>,
+        network: NetworkFingerprint,
+        locale: &str,
     ) -> Result<LoginResponse, AuthError> {
-        // Find user by username or email
-        let user = self
+        let region = self.db.region_of_identifier(&data.username_or_email).await?;
+        DatabaseConnection::in_region(region, self.login_in_region(data, ip, user_agent, dpop_jkt, network, locale)).await
+    }
+
+    async fn login_in_region(
+        &self,
+        data: LoginRequest,
+        ip: Option<String>,
+        user_agent: Option<String>,
+        dpop_jkt: Option<String>,
+        network: NetworkFingerprint,
+        locale: &str,
+    ) -> Result<LoginResponse, AuthError> {
+        self.ensure_source_allowed(ip.as_deref())?;
+
+        // Slow down repeated failures before touching the account
+        let tarpit_keys = LoginTarpit::keys(&data.username_or_email, ip.as_deref());
+        self.tarpit.wait(&tarpit_keys).await;
+
+        self.check_captcha(&data.captcha())?;
+
+        // Find user by username or email. An unknown account fails like a
+        // wrong password, after as long, so logins can't be used to find accounts.
+        let user = match self
             .db
             .find_user_by_username_or_email(&data.username_or_email)
+            .await
+        {
+            Ok(user) => user,
+            Err(AuthError::UserNotFound) => {
+                self.check_brute_force(None, ip.as_deref(), &data.captcha(), self.config.captcha.required)?;
+                verify_dummy_password(&data.password);
+                self.tarpit.record_failure(&tarpit_keys);
+                self.risk_scoring.record_failed_attempt(&data.username_or_email);
+                self.record_login_failure(None, &ip).await;
+                return Err(AuthError::InvalidCredentials);
+            }
+            Err(err) => return Err(err),
+        };
+        self.ensure_not_canary(&user, &data.password, &ip, &user_agent, &tarpit_keys).await?;
+        let captcha_checked =
+            self.check_brute_force(Some(&user), ip.as_deref(), &data.captcha(), self.config.captcha.required)?;
+
+        // Credentials, account status, verification, and any extension checks
+        let client = LoginClient {
+            ip: &ip,
+            user_agent: &user_agent,
+            network: &network,
+            device_token: data.device_token.as_ref(),
+        };
+        let LoginVerdict { outcome, lifetime } = self
+            .run_login_checks(&user, Some(&data.password), client, &tarpit_keys, locale)
             .await?;
+        self.ensure_logins_open(Some(&user), None).await?;
+        self.ensure_password_login_allowed(&user).await?;
 
-        // Verify password
-        if !verify_password(&data.password, &user.password_hash)? {
-            return Err(AuthError::InvalidCredentials);
+        // Moderately risky login: a CAPTCHA is enough, MFA is kept for riskier ones
+        if outcome == CheckOutcome::RequireCaptcha && !captcha_checked {
+            self.check_risk_captcha(&user, &data.captcha())?;
         }
 
-        // Check if user is active
-        if !user.is_active {
-            return Err(AuthError::PermissionDenied);
+        // High-risk login: the owner has to approve it from their mailbox first
+        if outcome == CheckOutcome::RequireApproval {
+            self.tarpit.record_success(&tarpit_keys);
+            let login = PendingLogin {
+                user_id: user.id,
+                ip,
+                user_agent,
+                amr: amr_values(&[AMR_PASSWORD]),
+                lifetime,
+                dpop_jkt,
+                network,
+            };
+            return self.start_login_approval(user, login, locale).await;
+        }
+
+        if user.mfa_reenrollment_required {
+            self.tarpit.record_success(&tarpit_keys);
+            return self.mfa_enrollment_response(user);
         }
 
         // Check if MFA is required
-        if user.mfa_enabled {
-            // Return partial response with MFA required flag
-            return Ok(LoginResponse {
-                access_token: String::new(),
-                refresh_token: String::new(),
-                token_type: "Bearer".into(),
-                expires_in: 0,
-                user: user.clone().into(),
-                mfa_required: true,
-            });
+        if outcome == CheckOutcome::RequireMfa {
+            // Password is verified; hand out a token only good for the MFA step.
+            // Failures only reset once that step succeeds too.
+            return self.mfa_pending_response(user);
+        }
+
+        if user.password_expired() {
+            self.tarpit.record_success(&tarpit_keys);
+            return self.password_change_response(user);
+        }
+
+        if let Some(policy) = self.pending_policy(&user).await? {
+            self.tarpit.record_success(&tarpit_keys);
+            return self.policy_acceptance_response(user, &[AMR_PASSWORD], policy);
         }
 
         // Generate tokens
-        let access_token = self.create_access_token(&user)?;
-        let refresh_token = Uuid::new_v4().to_string();
+        let refresh_token = new_refresh_token();
+        let token_type = token_type(&dpop_jkt);
 
-        // Save refresh token
-        let expires_at = Utc::now() + Duration::seconds(self.config.jwt.refresh_token_expiry as i64);
-        let session = NewSession {
-            id: Uuid::new_v4(),
-            user_id: user.id,
-            refresh_token: refresh_token.clone(),
-            user_agent,
-            ip_address: ip,
-            expires_at,
-        };
+        // Save refresh token, lasting as long as the checks allowed
+        let expires_at = Utc::now() + self.refresh_token_lifetime(false, lifetime);
+        let mut session = NewSession::new(user.id, refresh_token.clone(), user_agent, ip, expires_at);
+        session.dpop_jkt = dpop_jkt;
+        session.amr = amr_values(&[AMR_PASSWORD]);
+        session.lifetime = lifetime.as_str().to_string();
+        self.bind_session_network(&mut session, network);
+        // Bound to the client's key if it sent a DPoP proof
+        let access_token = self.create_session_access_token(&user, &[AMR_PASSWORD], &session)?;
 
-        self.db.create_session(session).await?;
+        self.start_login_session(session).await?;
 
         // Update last login
         self.db.update_last_login(user.id).await?;
+        self.tarpit.record_success(&tarpit_keys);
+
+        let passkey_prompt = self.passkey_prompt(user.id, data.webauthn_supported).await?;
 
         Ok(LoginResponse {
             access_token,
             refresh_token,
-            token_type: "Bearer".into(),
-            expires_in: self.config.jwt.access_token_expiry,
+            token_type,
+            expires_in: self.access_token_expiry(lifetime),
             user: user.into(),
             mfa_required: false,
+            passkey_prompt,
+            approval_id: None,
+            password_change_required: false,
+            mfa_enrollment_required: false,
+            policy_acceptance_required: None,
         })
     }
 
@@ -168,21 +775,83 @@ impl AuthService {
         data: MfaLoginRequest,
         ip: Option<String>,
         user_agent: Option<String>,
+        dpop_jkt: Option<String>,
+        network: NetworkFingerprint,
+        locale: &str,
     ) -> Result<LoginResponse, AuthError> {
-        // Find user by username or email
-        let user = self
+        let region = self.db.region_of_identifier(&data.username_or_email).await?;
+        DatabaseConnection::in_region(region, self.mfa_login_in_region(data, ip, user_agent, dpop_jkt, network, locale))
+            .await
+    }
+
+    async fn mfa_login_in_region(
+        &self,
+        data: MfaLoginRequest,
+        ip: Option<String>,
+        user_agent: Option<String>,
+        dpop_jkt: Option<String>,
+        network: NetworkFingerprint,
+        locale: &str,
+    ) -> Result<LoginResponse, AuthError> {
+        self.ensure_source_allowed(ip.as_deref())?;
+
+        // Slow down repeated failures before touching the account
+        let tarpit_keys = LoginTarpit::keys(&data.username_or_email, ip.as_deref());
+        self.tarpit.wait(&tarpit_keys).await;
+
+        // Find user by username or email. An unknown account fails like a
+        // wrong password, after as long, so logins can't be used to find accounts.
+        let user = match self
             .db
             .find_user_by_username_or_email(&data.username_or_email)
+            .await
+        {
+            Ok(user) => user,
+            Err(AuthError::UserNotFound) => {
+                self.check_brute_force(None, ip.as_deref(), &data.captcha(), false)?;
+                verify_dummy_password(&data.password);
+                self.tarpit.record_failure(&tarpit_keys);
+                self.risk_scoring.record_failed_attempt(&data.username_or_email);
+                self.record_login_failure(None, &ip).await;
+                return Err(AuthError::InvalidCredentials);
+            }
+            Err(err) => return Err(err),
+        };
+        self.ensure_not_canary(&user, &data.password, &ip, &user_agent, &tarpit_keys).await?;
+        self.check_brute_force(Some(&user), ip.as_deref(), &data.captcha(), false)?;
+
+        // Credentials, account status, verification, and any extension checks
+        let client = LoginClient {
+            ip: &ip,
+            user_agent: &user_agent,
+            network: &network,
+            device_token: None,
+        };
+        let LoginVerdict { outcome, lifetime } = self
+            .run_login_checks(&user, Some(&data.password), client, &tarpit_keys, locale)
             .await?;
+        self.ensure_logins_open(Some(&user), None).await?;
+        self.ensure_password_login_allowed(&user).await?;
 
-        // Verify password
-        if !verify_password(&data.password, &user.password_hash)? {
-            return Err(AuthError::InvalidCredentials);
+        // High-risk login: MFA happens after the owner approves it
+        if outcome == CheckOutcome::RequireApproval {
+            self.tarpit.record_success(&tarpit_keys);
+            let login = PendingLogin {
+                user_id: user.id,
+                ip,
+                user_agent,
+                amr: amr_values(&[AMR_PASSWORD]),
+                lifetime,
+                dpop_jkt,
+                network,
+            };
+            return self.start_login_approval(user, login, locale).await;
         }
 
-        // Check if user is active
-        if !user.is_active {
-            return Err(AuthError::PermissionDenied);
+        // The old second factor was reset; set up a new one first
+        if user.mfa_reenrollment_required {
+            self.tarpit.record_success(&tarpit_keys);
+            return self.mfa_enrollment_response(user);
         }
 
         // Check if MFA is enabled
@@ -195,40 +864,50 @@ impl AuthService {
             return Err(AuthError::MfaRequired);
         }
 
+        // Recovery codes count as MFA but not as a one-time password
+        let amr: &[&str] = if data.mfa_code.is_some() {
+            &[AMR_PASSWORD, AMR_OTP, AMR_MFA]
+        } else {
+            &[AMR_PASSWORD, AMR_MFA]
+        };
+
         // Verify MFA
-        if let Some(mfa_code) = data.mfa_code {
-            // Verify MFA code
-            if let Some(secret) = &user.mfa_secret {
-                if !self.mfa_service.verify_totp(secret, &mfa_code) {
-                    return Err(AuthError::InvalidMfaCode);
-                }
-            } else {
-                return Err(AuthError::InternalServerError("MFA secret not found".into()));
-            }
-        } else if let Some(recovery_code) = data.recovery_code {
-            // Verify recovery code
-            let used = self.db.use_recovery_code(user.id, &recovery_code).await?;
-            if !used {
-                return Err(AuthError::InvalidMfaCode);
-            }
+        self.ensure_mfa_attempts_left(user.id).await?;
+        let verified = match (data.mfa_code, data.recovery_code) {
+            (Some(mfa_code), _) => self.verify_any_totp(&user, &mfa_code).await?,
+            (None, Some(recovery_code)) => self.db.use_recovery_code(user.id, &recovery_code).await?,
+            (None, None) => false,
+        };
+        if !verified {
+            self.tarpit.record_failure(&tarpit_keys);
+            self.record_mfa_failure(user.id).await?;
+            return Err(AuthError::InvalidMfaCode);
+        }
+        self.record_login_success(&user, &tarpit_keys).await?;
+
+        if user.password_expired() {
+            return self.password_change_response(user);
+        }
+
+        if let Some(policy) = self.pending_policy(&user).await? {
+            return self.policy_acceptance_response(user, amr, policy);
         }
 
         // Generate tokens
-        let access_token = self.create_access_token(&user)?;
-        let refresh_token = Uuid::new_v4().to_string();
+        let refresh_token = new_refresh_token();
+        let token_type = token_type(&dpop_jkt);
 
-        // Save refresh token
-        let expires_at = Utc::now() + Duration::seconds(self.config.jwt.refresh_token_expiry as i64);
-        let session = NewSession {
-            id: Uuid::new_v4(),
-            user_id: user.id,
-            refresh_token: refresh_token.clone(),
-            user_agent,
-            ip_address: ip,
-            expires_at,
-        };
+        // Save refresh token, lasting as long as the checks allowed
+        let expires_at = Utc::now() + self.refresh_token_lifetime(false, lifetime);
+        let mut session = NewSession::new(user.id, refresh_token.clone(), user_agent, ip, expires_at);
+        session.dpop_jkt = dpop_jkt;
+        session.amr = amr_values(amr);
+        session.lifetime = lifetime.as_str().to_string();
+        self.bind_session_network(&mut session, network);
+        // Bound to the client's key if it sent a DPoP proof
+        let access_token = self.create_session_access_token(&user, amr, &session)?;
 
-        self.db.create_session(session).await?;
+        self.start_login_session(session).await?;
 
         // Update last login
         self.db.update_last_login(user.id).await?;
@@ -236,63 +915,526 @@ impl AuthService {
         Ok(LoginResponse {
             access_token,
             refresh_token,
-            token_type: "Bearer".into(),
-            expires_in: self.config.jwt.access_token_expiry,
+            token_type,
+            expires_in: self.access_token_expiry(lifetime),
             user: user.into(),
             mfa_required: false,
+            passkey_prompt: None,
+            approval_id: None,
+            password_change_required: false,
+            mfa_enrollment_required: false,
+            policy_acceptance_required: None,
         })
     }
 
-    pub async fn refresh_token(
+    /// Approve a held login from the emailed link
+    pub async fn approve_login(&self, data: ApproveLoginRequest) -> Result<LogoutResponse, AuthError> {
+        self.login_approvals.approve(&data.token)?;
+
+        Ok(LogoutResponse {
+            message: "Sign-in approved".into(),
+        })
+    }
+
+    /// Called by the client that was held for approval; fails with
+    /// `LoginApprovalPending` until the owner has approved the login
+    pub async fn complete_login_approval(
         &self,
-        data: RefreshTokenRequest,
-        ip: Option<String>,
-        user_agent: Option<String>,
-    ) -> Result<RefreshTokenResponse, AuthError> {
-        // Find session by refresh token
-        let session = self.db.find_session_by_token(&data.refresh_token).await?;
+        approval_id: Uuid,
+        locale: &str,
+    ) -> Result<LoginResponse, AuthError> {
+        let pending = self.login_approvals.complete(approval_id)?;
+        let region = self.db.region_of_user(pending.user_id).await?;
+        DatabaseConnection::in_region(region, self.complete_approved_login(pending, locale)).await
+    }
+
+    async fn complete_approved_login(&self, pending: PendingLogin, locale: &str) -> Result<LoginResponse, AuthError> {
+        let amr: Vec<&str> = pending.amr.iter().map(String::as_str).collect();
+
+        // The account may have changed while the login was held
+        let user = self.db.find_user_by_id(pending.user_id).await?;
+        if !user.is_active() {
+            return Err(AuthError::AccountDisabled {
+                status_token: Some(self.create_scoped_token(&user, TokenScope::AccountStatus)?),
+            });
+        }
+
+        // The approval settles the risk that held the login, not the login policies
+        let client = LoginClient {
+            ip: &pending.ip,
+            user_agent: &pending.user_agent,
+            network: &pending.network,
+            device_token: None,
+        };
+        let LoginVerdict { outcome, lifetime } = self
+            .run_verified_login_checks(&user, "login approval", client, locale)
+            .await?;
+        // As short as the held login was given, or shorter
+        let lifetime = lifetime.max(pending.lifetime);
+
+        if outcome == CheckOutcome::RequireMfa {
+            return self.mfa_pending_response(user);
+        }
+
+        if user.password_expired() {
+            return self.password_change_response(user);
+        }
+
+        if let Some(policy) = self.pending_policy(&user).await? {
+            return self.policy_acceptance_response(user, &amr, policy);
+        }
+
+        // Generate tokens
+        let refresh_token = new_refresh_token();
+        let token_type = token_type(&pending.dpop_jkt);
+
+        // Save refresh token, bound like the held login's would have been
+        let expires_at = Utc::now() + self.refresh_token_lifetime(false, lifetime);
+        let mut session = NewSession::new(
+            user.id,
+            refresh_token.clone(),
+            pending.user_agent,
+            pending.ip,
+            expires_at,
+        );
+        session.dpop_jkt = pending.dpop_jkt;
+        session.amr = amr_values(&amr);
+        session.lifetime = lifetime.as_str().to_string();
+        self.bind_session_network(&mut session, pending.network);
+        let access_token = self.create_session_access_token(&user, &amr, &session)?;
+
+        self.start_login_session(session).await?;
+
+        // Update last login
+        self.db.update_last_login(user.id).await?;
+
+        Ok(LoginResponse {
+            access_token,
+            refresh_token,
+            token_type,
+            expires_in: self.access_token_expiry(lifetime),
+            user: user.into(),
+            mfa_required: false,
+            passkey_prompt: None,
+            approval_id: None,
+            password_change_required: false,
+            mfa_enrollment_required: false,
+            policy_acceptance_required: None,
+        })
+    }
+
+    /// Email a sign-in code. Unknown addresses get a challenge too, after
+    /// about as long, that no code will ever satisfy.
+    pub async fn start_email_code_login(
+        &self,
+        data: EmailCodeStartRequest,
+        ip: Option<String>,
+        user_agent: Option<String>,
+        locale: &str,
+    ) -> Result<EmailCodeChallenge, AuthError> {
+        if !self.config.email_code_login.enabled {
+            return Err(AuthError::PermissionDenied);
+        }
+        self.ensure_source_allowed(ip.as_deref())?;
+
+        let region = self.db.region_of_identifier(&data.email).await?;
+        let floor = ResponseFloor::start(&self.config.response_timing);
+        let result =
+            DatabaseConnection::in_region(region, self.send_login_code(&data.email, &ip, &user_agent, locale)).await;
+        floor.wait().await;
+        result
+    }
+
+    async fn send_login_code(
+        &self,
+        email: &str,
+        ip: &Option<String>,
+        user_agent: &Option<String>,
+        locale: &str,
+    ) -> Result<EmailCodeChallenge, AuthError> {
+        // Counted per address before the lookup, so unknown addresses are
+        // throttled like the rest
+        self.email_throttle.acquire(email, ThrottledEmail::LoginCode).await?;
+
+        let user = match self.db.find_user_by_email(email).await {
+            Ok(user) => Some(user),
+            Err(AuthError::UserNotFound) => None,
+            Err(err) => return Err(err),
+        };
+
+        // Canary accounts never get a code; asking for one is an intrusion
+        let user = match user {
+            Some(user) => match self.db.find_canary_by_user_id(user.id).await? {
+                Some(canary) => {
+                    self.trip_canary(canary, "email code", ip, user_agent).await;
+                    None
+                }
+                None => Some(user),
+            },
+            None => None,
+        };
+
+        let (challenge_id, code) = self.email_codes.create(user.as_ref().map(|user| user.id));
+        if let Some(user) = &user {
+            self.lookup_email_service()
+                .send_login_code_email(&user.email, &code, locale)
+                .await?;
+        }
+
+        Ok(EmailCodeChallenge {
+            challenge_id,
+            expires_in: self.config.email_code_login.ttl,
+        })
+    }
+
+    /// Sign in with an emailed code, optionally trusting the device so it
+    /// can skip the code next time
+    pub async fn verify_email_code_login(
+        &self,
+        data: EmailCodeVerifyRequest,
+        ip: Option<String>,
+        user_agent: Option<String>,
+        network: NetworkFingerprint,
+        locale: &str,
+    ) -> Result<EmailCodeLoginResponse, AuthError> {
+        if !self.config.email_code_login.enabled {
+            return Err(AuthError::PermissionDenied);
+        }
+        self.ensure_source_allowed(ip.as_deref())?;
+
+        let user_id = self.email_codes.verify(data.challenge_id, data.code.expose())?;
+
+        // The rest of the login, and the trusted device, go to the user's region
+        let region = self.db.region_of_user(user_id).await?;
+        DatabaseConnection::in_region(region, async move {
+            let user = self.db.find_user_by_id(user_id).await?;
+            let login = self
+                .complete_email_code_login(user, ip, user_agent.clone(), network, None, locale)
+                .await?;
+
+            let trust_days = self.config.email_code_login.trusted_device_days;
+            let device_token = if data.trust_device && trust_days > 0 {
+                let (token, token_hash) = device_token();
+                self.db
+                    .create_trusted_device(NewTrustedDevice {
+                        id: Uuid::new_v4(),
+                        user_id,
+                        token_hash,
+                        name: user_agent.as_deref().map(|ua| DeviceInfo::parse(ua).describe(None)),
+                        expires_at: Utc::now() + Duration::days(trust_days),
+                    })
+                    .await?;
+                Some(token)
+            } else {
+                None
+            };
+
+            Ok(EmailCodeLoginResponse { login, device_token })
+        })
+        .await
+    }
+
+    /// Sign in from a device trusted at an earlier email code login
+    pub async fn trusted_device_login(
+        &self,
+        data: TrustedDeviceLoginRequest,
+        ip: Option<String>,
+        user_agent: Option<String>,
+        network: NetworkFingerprint,
+        locale: &str,
+    ) -> Result<LoginResponse, AuthError> {
+        if !self.config.email_code_login.enabled || self.config.email_code_login.trusted_device_days <= 0 {
+            return Err(AuthError::PermissionDenied);
+        }
+        self.ensure_source_allowed(ip.as_deref())?;
+
+        let region = self.db.region_of_identifier(&data.email).await?;
+        DatabaseConnection::in_region(region, async move {
+            let user = match self.db.find_user_by_email(&data.email).await {
+                Ok(user) => user,
+                Err(AuthError::UserNotFound) => return Err(AuthError::InvalidToken),
+                Err(err) => return Err(err),
+            };
+            let token_hash = hash_device_token(data.device_token.expose());
+            if self.db.use_trusted_device(user.id, &token_hash).await?.is_none() {
+                return Err(AuthError::InvalidToken);
+            }
+
+            self.complete_email_code_login(user, ip, user_agent, network, Some(&data.device_token), locale)
+                .await
+        })
+        .await
+    }
+
+    // The emailed code (or a trusted device) stands in for the password; the
+    // rest of the login goes as usual, MFA and login policies included.
+    // Trusted devices get extended sessions.
+    async fn complete_email_code_login(
+        &self,
+        user: User,
+        ip: Option<String>,
+        user_agent: Option<String>,
+        network: NetworkFingerprint,
+        device_token: Option<&Secret<String>>,
+        locale: &str,
+    ) -> Result<LoginResponse, AuthError> {
+        if !user.is_active() {
+            return Err(AuthError::AccountDisabled {
+                status_token: Some(self.create_scoped_token(&user, TokenScope::AccountStatus)?),
+            });
+        }
+        let user = self.reactivate_if_archived(user, "email_code").await?;
+        self.ensure_password_login_allowed(&user).await?;
+
+        let client = LoginClient {
+            ip: &ip,
+            user_agent: &user_agent,
+            network: &network,
+            device_token,
+        };
+        let LoginVerdict { outcome, lifetime } = self
+            .run_verified_login_checks(&user, "email code", client, locale)
+            .await?;
+
+        if outcome == CheckOutcome::RequireApproval {
+            let login = PendingLogin {
+                user_id: user.id,
+                ip,
+                user_agent,
+                amr: amr_values(&[AMR_EMAIL]),
+                lifetime,
+                dpop_jkt: None,
+                network,
+            };
+            return self.start_login_approval(user, login, locale).await;
+        }
+
+        if user.mfa_reenrollment_required {
+            return self.mfa_enrollment_response(user);
+        }
+
+        if outcome == CheckOutcome::RequireMfa {
+            return self.mfa_pending_response(user);
+        }
+
+        if let Some(policy) = self.pending_policy(&user).await? {
+            return self.policy_acceptance_response(user, &[AMR_EMAIL], policy);
+        }
+
+        let refresh_token = new_refresh_token();
+        let expires_at = Utc::now() + self.refresh_token_lifetime(false, lifetime);
+        let mut session = NewSession::new(user.id, refresh_token.clone(), user_agent, ip, expires_at);
+        session.amr = amr_values(&[AMR_EMAIL]);
+        session.lifetime = lifetime.as_str().to_string();
+        let access_token = self.create_session_access_token(&user, &[AMR_EMAIL], &session)?;
+
+        self.start_login_session(session).await?;
+        self.db.update_last_login(user.id).await?;
+
+        Ok(LoginResponse {
+            access_token,
+            refresh_token,
+            token_type: "Bearer".into(),
+            expires_in: self.access_token_expiry(lifetime),
+            user: user.into(),
+            mfa_required: false,
+            passkey_prompt: None,
+            approval_id: None,
+            password_change_required: false,
+            mfa_enrollment_required: false,
+            policy_acceptance_required: None,
+        })
+    }
+
+    /// Record acceptance of the current policy and finish the login it held up
+    pub async fn accept_policy(
+        &self,
+        user_id: Uuid,
+        amr: &[String],
+        data: AcceptPolicyRequest,
+        ip: Option<String>,
+        user_agent: Option<String>,
+    ) -> Result<LoginResponse, AuthError> {
+        if self.config.policy.version.as_deref() != Some(data.version.as_str()) {
+            return Err(AuthError::ValidationError(
+                "This policy version is no longer current; reload and review it again".into(),
+            ));
+        }
+
+        let user = self.db.find_user_by_id(user_id).await?;
+        if !user.is_active() {
+            return Err(AuthError::AccountDisabled {
+                status_token: Some(self.create_scoped_token(&user, TokenScope::AccountStatus)?),
+            });
+        }
+
+        self.db
+            .record_policy_acceptance(NewPolicyAcceptance {
+                id: Uuid::new_v4(),
+                user_id,
+                version: data.version,
+                ip_address: ip.clone(),
+                user_agent: user_agent.clone(),
+            })
+            .await?;
+
+        // Generate tokens with the methods used before the login was held
+        let amr: Vec<&str> = amr.iter().map(String::as_str).collect();
+        let refresh_token = new_refresh_token();
+
+        // Save refresh token
+        let expires_at = Utc::now() + Duration::seconds(self.config.jwt.refresh_token_expiry as i64);
+        let mut session = NewSession::new(user.id, refresh_token.clone(), user_agent, ip, expires_at);
+        session.amr = amr_values(&amr);
+        let access_token = self.create_access_token(&user, &amr, Some(session.id))?;
+
+        self.start_login_session(session).await?;
+
+        // Update last login
+        self.db.update_last_login(user.id).await?;
+
+        Ok(LoginResponse {
+            access_token,
+            refresh_token,
+            token_type: "Bearer".into(),
+            expires_in: self.config.jwt.access_token_expiry,
+            user: user.into(),
+            mfa_required: false,
+            passkey_prompt: None,
+            approval_id: None,
+            password_change_required: false,
+            mfa_enrollment_required: false,
+            policy_acceptance_required: None,
+        })
+    }
+
+    pub async fn refresh_token(
+        &self,
+        data: RefreshTokenRequest,
+        ip: Option<String>,
+        user_agent: Option<String>,
+        dpop_jkt: Option<String>,
+        network: NetworkFingerprint,
+    ) -> Result<RefreshTokenResponse, AuthError> {
+        let region = self.region_of_token(&data.refresh_token).await?;
+        DatabaseConnection::in_region(region, self.refresh_token_in_region(data, ip, user_agent, dpop_jkt, network)).await
+    }
+
+    async fn refresh_token_in_region(
+        &self,
+        data: RefreshTokenRequest,
+        ip: Option<String>,
+        user_agent: Option<String>,
+        dpop_jkt: Option<String>,
+        network: NetworkFingerprint,
+    ) -> Result<RefreshTokenResponse, AuthError> {
+        self.ensure_source_allowed(ip.as_deref())?;
+
+        // Find session by refresh token
+        let session = self.db.find_session_by_token(&data.refresh_token).await?;
 
         // Check if session is valid
         if session.is_revoked || session.expires_at < Utc::now() {
             return Err(AuthError::InvalidToken);
         }
+        self.ensure_session_not_idle(&session).await?;
+
+        // A bound session's refresh token is only good with a proof from the same key
+        if session.dpop_jkt.is_some() && session.dpop_jkt != dpop_jkt {
+            return Err(AuthError::InvalidToken);
+        }
 
         // Find user
         let user = self.db.find_user_by_id(session.user_id).await?;
 
         // Check if user is active
-        if !user.is_active {
+        if !user.is_active() {
             return Err(AuthError::PermissionDenied);
         }
 
-        // Generate new tokens
-        let access_token = self.create_access_token(&user)?;
-        let refresh_token = Uuid::new_v4().to_string();
+        // An expired password has to be changed through a fresh login
+        if user.password_expired() {
+            return Err(AuthError::PasswordResetRequired);
+        }
 
-        // Revoke old session
-        self.db.revoke_session(session.id).await?;
+        self.ensure_refresh_network(&session, &user, &data, &network, &ip, &user_agent).await?;
 
-        // Save new refresh token
-        let expires_at = Utc::now() + Duration::seconds(self.config.jwt.refresh_token_expiry as i64);
-        let new_session = NewSession {
-            id: Uuid::new_v4(),
-            user_id: user.id,
-            refresh_token: refresh_token.clone(),
-            user_agent,
-            ip_address: ip,
-            expires_at,
+        // A session started for a registered client keeps to that client's policy
+        let client = match &session.client_id {
+            Some(client_id) => self.db.find_client_application(client_id).await?,
+            None => None,
+        };
+        if client.as_ref().map_or(false, |client| !client.allows_grant(GRANT_REFRESH_TOKEN)) {
+            return Err(AuthError::InvalidToken);
+        }
+
+        // Generate new tokens
+        let refresh_token = new_refresh_token();
+        let token_type = token_type(&dpop_jkt);
+
+        // Save new refresh token, keeping the session's name, pin, and the
+        // lifetime the login pipeline gave it
+        let lifetime = if user.is_guest {
+            Duration::seconds(self.config.guest.session_ttl as i64)
+        } else {
+            self.refresh_token_lifetime(session.is_pinned, session.lifetime())
+        };
+        let lifetime = match client.as_ref().and_then(|client| client.refresh_token_ttl) {
+            Some(ttl) => lifetime.min(Duration::seconds(ttl as i64)),
+            None => lifetime,
+        };
+        let expires_at = Utc::now() + lifetime;
+        let mut new_session = NewSession::new(user.id, refresh_token.clone(), user_agent, ip, expires_at);
+        new_session.name = session.name;
+        new_session.is_pinned = session.is_pinned;
+        new_session.dpop_jkt = dpop_jkt;
+        new_session.client_id = session.client_id;
+        new_session.scopes = session.scopes;
+        new_session.amr = session.amr;
+        new_session.lifetime = session.lifetime;
+        // Follow the client onto a network it has just verified from
+        new_session.network_country = session.network_country;
+        new_session.network_asn = session.network_asn;
+        self.bind_session_network(&mut new_session, network);
+        // A refresh isn't a fresh authentication, so no `auth_time`
+        let access_token = self.create_refreshed_access_token(&user, &new_session)?;
+        let (access_token, expires_in) = match (&client, &new_session.scopes) {
+            (None, None) => (access_token, self.access_token_expiry(new_session.lifetime())),
+            (client, scopes) => self.client_access_token(
+                client.as_ref().and_then(|client| client.access_token_ttl),
+                scopes.as_deref(),
+                &access_token,
+            )?,
         };
 
-        self.db.create_session(new_session).await?;
+        // The old session is revoked and the new one saved together: a failed
+        // save can't sign the client out, and of two refreshes racing with the
+        // same token only one gets through
+        self.db
+            .commit(UnitOfWork::new().revoke_session(session.id).create_session(new_session))
+            .await?;
 
         Ok(RefreshTokenResponse {
             access_token,
             refresh_token,
-            token_type: "Bearer".into(),
-            expires_in: self.config.jwt.access_token_expiry,
+            token_type,
+            expires_in,
         })
     }
 
+    /// Note a request authenticated with a token from `session_id`, at most once
+    /// per `SESSION_ACTIVITY_UPDATE_INTERVAL`. A session that had already gone
+    /// idle is revoked instead, and the request rejected.
+    pub async fn record_session_activity(&self, session_id: Uuid) -> Result<(), AuthError> {
+        if !self.session_activity.due(session_id) {
+            return Ok(());
+        }
+
+        let session = self.db.find_session_by_id(session_id).await?;
+        self.ensure_session_not_idle(&session).await?;
+        self.db.touch_session(session.id, Utc::now()).await
+    }
+
     pub async fn logout(
         &self,
         data: LogoutRequest,
@@ -300,16 +1442,21 @@ impl AuthService {
     ) -> Result<LogoutResponse, AuthError> {
         // If refresh token is provided, revoke the specific session
         if let Some(refresh_token) = data.refresh_token {
-            let session = self.db.find_session_by_token(&refresh_token).await?;
+            let region = self.region_of_token(&refresh_token).await?;
+            DatabaseConnection::in_region(region, async {
+                let session = self.db.find_session_by_token(&refresh_token).await?;
 
-            // Check if user_id matches (if authenticated)
-            if let Some(uid) = user_id {
-                if session.user_id != uid {
-                    return Err(AuthError::PermissionDenied);
+                // Check if user_id matches (if authenticated)
+                if let Some(uid) = user_id {
+                    if session.user_id != uid {
+                        return Err(AuthError::PermissionDenied);
+                    }
                 }
-            }
 
-            self.db.revoke_session(session.id).await?;
+                self.db.revoke_session(session.id).await?;
+                self.token_revocations.revoke(&[session.id]).await
+            })
+            .await?;
         }
 
         Ok(LogoutResponse {
@@ -321,7 +1468,11 @@ impl AuthService {
         &self,
         user_id: Uuid,
     ) -> Result<LogoutResponse, AuthError> {
-        self.db.revoke_all_sessions(user_id).await?;
+        // Pinned sessions stay signed in; they can still be revoked one by one
+        self.db.revoke_all_sessions(user_id, false).await?;
+        self.revoke_access_tokens(user_id).await?;
+        // Trusted devices would otherwise sign straight back in
+        self.db.delete_trusted_devices_by_user_id(user_id).await?;
 
         Ok(LogoutResponse {
             message: "All sessions logged out successfully".into(),
@@ -332,14 +1483,42 @@ impl AuthService {
         &self,
         data: VerifyEmailRequest,
     ) -> Result<UserResponse, AuthError> {
-        // Find user by verification token
-        let user = self
-            .db
-            .find_user_by_verification_token(&data.token)
+        // Bad links fail as slowly as good ones succeed
+        let floor = ResponseFloor::start(&self.config.response_timing);
+        let result = match self.region_of_link(&data.token, ActionPurpose::EmailVerification).await {
+            Ok(region) => DatabaseConnection::in_region(region, self.redeem_email_verification(data)).await,
+            Err(e) => Err(e),
+        };
+        floor.wait().await;
+        result
+    }
+
+    async fn redeem_email_verification(&self, data: VerifyEmailRequest) -> Result<UserResponse, AuthError> {
+        let claims = self
+            .action_tokens
+            .redeem_within(
+                &data.token,
+                ActionPurpose::EmailVerification,
+                Duration::seconds(self.config.action_tokens.email_verification_ttl as i64),
+            )
             .await?;
+        let user = self.db.find_user_by_id(claims.sub).await?;
+
+        // A link sent before the address changed doesn't verify the new one
+        if !claims.is_bound_to(&user.email) {
+            return Err(AuthError::InvalidToken);
+        }
 
         // Verify email
-        let user = self.db.verify_email(user.id).await?;
+        let event = NewOutboxEvent::new(
+            EventType::EmailVerified,
+            user.id,
+            serde_json::json!({ "email": user.email }),
+        );
+        let user = self.db.verify_email(user.id, event).await?;
+        self.user_cache.invalidate(user.id);
+
+        self.auto_join_organization(&user).await?;
 
         Ok(user.into())
     }
@@ -347,6 +1526,18 @@ impl AuthService {
     pub async fn resend_verification_email(
         &self,
         user_id: Uuid,
+        locale: &str,
+    ) -> Result<PasswordResetResponse, AuthError> {
+        let floor = ResponseFloor::start(&self.config.response_timing);
+        let result = self.send_fresh_verification_email(user_id, locale).await;
+        floor.wait().await;
+        result
+    }
+
+    async fn send_fresh_verification_email(
+        &self,
+        user_id: Uuid,
+        locale: &str,
     ) -> Result<PasswordResetResponse, AuthError> {
         // Find user
         let user = self.db.find_user_by_id(user_id).await?;
@@ -356,60 +1547,104 @@ impl AuthService {
             return Err(AuthError::ValidationError("Email is already verified".into()));
         }
 
-        // Generate new verification token
-        let verification_token = Uuid::new_v4().to_string();
-
-        // Update user
-        self.db
-            .update_verification_token(user.id, &verification_token)
-            .await?;
-
-        // Send verification email
-        self.email_service
-            .send_verification_email(&user.email, &verification_token)
+        // Send a fresh link; earlier ones stay valid until they expire
+        self.email_throttle.acquire(&user.email, ThrottledEmail::Verification).await?;
+        let verification_token = self.email_verification_token(&user)?;
+        self.lookup_email_service()
+            .send_verification_email(&user.email, &verification_token, locale)
             .await?;
 
         Ok(PasswordResetResponse {
-            message: "Verification email sent successfully".into(),
+            message: self.translator.text(locale, "verification-email-sent", None),
         })
     }
 
     pub async fn password_reset_request(
         &self,
         data: PasswordResetRequest,
+        locale: &str,
     ) -> Result<PasswordResetResponse, AuthError> {
-        // Find user by email
-        let user = match self.db.find_user_by_email(&data.email).await {
-            Ok(user) => user,
-            Err(_) => {
+        // Unknown addresses get the same answer, after about as long
+        let floor = ResponseFloor::start(&self.config.response_timing);
+        let result = match self.db.region_of_identifier(&data.email).await {
+            Ok(region) => DatabaseConnection::in_region(region, self.send_password_reset(data, locale)).await,
+            Err(e) => Err(e),
+        };
+        floor.wait().await;
+        result
+    }
+
+    async fn send_password_reset(
+        &self,
+        data: PasswordResetRequest,
+        locale: &str,
+    ) -> Result<PasswordResetResponse, AuthError> {
+        // Throttled by the address asked for, before looking it up, so the
+        // limit applies the same whether or not it has an account
+        self.email_throttle.acquire(&data.email, ThrottledEmail::PasswordReset).await?;
+
+        let user = match self.find_user_by_recovery_email(&data.email).await? {
+            Some(user) => user,
+            None => {
                 // Return success even if user doesn't exist for security reasons
                 return Ok(PasswordResetResponse {
-                    message: "If the email is registered, a password reset link has been sent".into(),
+                    message: self.translator.text(locale, "password-reset-requested", None),
                 });
             }
         };
 
-        // Generate reset token
-        let reset_token = Uuid::new_v4().to_string();
+        // There's no password to reset yet; resend the lost invitation instead
+        if user.activation_pending {
+            let activation_token = self.activation_token(&user)?;
+            self.lookup_email_service()
+                .send_activation_email(&user.email, &activation_token, locale)
+                .await?;
 
-        // Update user
-        self.db
-            .update_password_reset_token(user.id, &reset_token)
-            .await?;
+            return Ok(PasswordResetResponse {
+                message: self.translator.text(locale, "password-reset-requested", None),
+            });
+        }
 
-        // Send password reset email
-        self.email_service
-            .send_password_reset_email(&user.email, &reset_token)
+        // The link stops working once the password changes, and remembers
+        // which address it went to
+        let ttl = Duration::seconds(self.config.action_tokens.password_reset_ttl as i64);
+        let claims = ActionClaims::new(ActionPurpose::PasswordReset, user.id, ttl)
+            .with_binding(fingerprint(&user.password_hash))
+            .with_data(serde_json::json!({ "sent_to": data.email }));
+        let reset_token = self.action_tokens.issue(&claims)?;
+
+        // Send password reset email to the address that asked for it
+        self.lookup_email_service()
+            .send_password_reset_email(&data.email, &reset_token, locale)
             .await?;
 
         Ok(PasswordResetResponse {
-            message: "If the email is registered, a password reset link has been sent".into(),
+            message: self.translator.text(locale, "password-reset-requested", None),
         })
     }
 
+    // The user with this primary email, or with it as a verified backup address
+    async fn find_user_by_recovery_email(&self, email: &str) -> Result<Option<User>, AuthError> {
+        match self.db.find_user_by_email(email).await {
+            Ok(user) => Ok(Some(user)),
+            Err(_) => match self.db.find_backup_email_by_address(email).await? {
+                Some(backup) if backup.is_verified => Ok(Some(self.db.find_user_by_id(backup.user_id).await?)),
+                _ => Ok(None),
+            },
+        }
+    }
+
     pub async fn password_reset_confirm(
         &self,
         data: PasswordResetConfirmRequest,
+    ) -> Result<PasswordResetResponse, AuthError> {
+        let region = self.region_of_link(&data.token, ActionPurpose::PasswordReset).await?;
+        DatabaseConnection::in_region(region, self.reset_password_in_region(data)).await
+    }
+
+    async fn reset_password_in_region(
+        &self,
+        data: PasswordResetConfirmRequest,
     ) -> Result<PasswordResetResponse, AuthError> {
         // Validate password
         validate_password(&data.password)?;
@@ -418,53 +1653,345 @@ impl AuthService {
             return Err(AuthError::ValidationError("Passwords do not match".into()));
         }
 
-        // Find user by reset token
-        let user = self
-            .db
-            .find_user_by_reset_token(&data.token)
-            .await?;
+        // Checked before the link is used up, so a refused unlock can be retried
+        let max_age = Duration::seconds(self.config.action_tokens.password_reset_ttl as i64);
+        let claims = self
+            .action_tokens
+            .verify_within(&data.token, ActionPurpose::PasswordReset, max_age)?;
+        let user = self.db.find_user_by_id(claims.sub).await?;
 
-        // Check if token is expired (24 hours)
-        if let Some(sent_at) = user.password_reset_sent_at {
-            if (Utc::now() - sent_at).num_seconds() > 86400 {
-                return Err(AuthError::TokenExpired);
-            }
-        } else {
+        if !claims.is_bound_to(&fingerprint(&user.password_hash)) {
             return Err(AuthError::InvalidToken);
         }
 
+        let via = claims
+            .data
+            .as_ref()
+            .and_then(|data| data["sent_to"].as_str())
+            .map(str::to_string)
+            .unwrap_or_else(|| user.email.clone());
+        let unlocking = user.account_status() == AccountStatus::Locked;
+        if unlocking {
+            self.check_unlock(&user, &via, data.mfa_code.as_ref()).await?;
+        }
+
+        self.action_tokens
+            .redeem_within(&data.token, ActionPurpose::PasswordReset, max_age)
+            .await?;
+
         // Hash new password
         let password_hash = hash_password(&data.password)?;
 
-        // Update password and clear reset token
+        // Update password, clear reset token and restart the expiry clock, and
+        // revoke all sessions along with it
         self.db
-            .update_password(user.id, &password_hash)
+            .commit(
+                UnitOfWork::new()
+                    .update_password(user.id, &password_hash, self.password_expiry(user.is_admin))
+                    .event(password_changed_event(user.id, "reset"))
+                    .revoke_all_sessions(user.id, true),
+            )
             .await?;
 
-        // Revoke all sessions
-        self.db.revoke_all_sessions(user.id).await?;
+        // And outstanding access tokens
+        self.revoke_access_tokens(user.id).await?;
+
+        if unlocking {
+            self.db
+                .set_account_status(
+                    user.id,
+                    None,
+                    AccountStatus::Active,
+                    "Unlocked by the account owner with a password reset",
+                    Some(user.id),
+                    status_changed_event(user.id, AccountStatus::Active, Some(user.id)),
+                )
+                .await?;
+            self.user_cache.invalidate(user.id);
+            log::info!("User {} unlocked their account", user.id);
+        }
+
+        log::info!("Password for user {} reset via {}", user.id, via);
+        self.notify_security_event(&user, SecurityAlert::PasswordReset { via: &via })
+            .await;
 
         Ok(PasswordResetResponse {
             message: "Password updated successfully".into(),
         })
     }
 
-    pub async fn mfa_setup(&self, user_id: Uuid) -> Result<MfaSetupResponse, AuthError> {
-        // Find user
-        let user = self.db.find_user_by_id(user_id).await?;
+    // A self-locked account is only unlocked by a reset link sent to its
+    // primary address, along with the second factor if it has one, so a
+    // backup address or a stolen password alone isn't enough
+    async fn check_unlock(
+        &self,
+        user: &User,
+        sent_to: &str,
+        mfa_code: Option<&Secret<String>>,
+    ) -> Result<(), AuthError> {
+        if !sent_to.eq_ignore_ascii_case(&user.email) {
+            return Err(AuthError::ValidationError(
+                "A locked account can only be unlocked with a reset link sent to its primary email address".into(),
+            ));
+        }
 
-        // Check if MFA is already enabled
         if user.mfa_enabled {
-            return Err(AuthError::ValidationError("MFA is already enabled".into()));
+            let code = match mfa_code.map(|c| c.expose().trim()) {
+                Some(code) if !code.is_empty() => code,
+                _ => return Err(AuthError::MfaRequired),
+            };
+            if !self.verify_any_totp(user, code).await? && !self.db.use_recovery_code(user.id, code).await? {
+                return Err(AuthError::InvalidMfaCode);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Change a known password; also the way out of an expired-password login
+    pub async fn change_password(
+        &self,
+        user_id: Uuid,
+        data: ChangePasswordRequest,
+    ) -> Result<PasswordResetResponse, AuthError> {
+        let user = self.db.find_user_by_id(user_id).await?;
+
+        if !verify_password(&data.current_password, &user.password_hash)? {
+            return Err(AuthError::InvalidCredentials);
+        }
+
+        validate_password(&data.password)?;
+
+        if data.password != data.password_confirmation {
+            return Err(AuthError::ValidationError("Passwords do not match".into()));
+        }
+
+        if verify_password(&data.password, &user.password_hash)? {
+            return Err(AuthError::ValidationError(
+                "New password must be different from the current one".into(),
+            ));
+        }
+
+        let password_hash = hash_password(&data.password)?;
+        // Sign out everywhere; the caller logs in again with the new password
+        self.db
+            .commit(
+                UnitOfWork::new()
+                    .update_password(user.id, &password_hash, self.password_expiry(user.is_admin))
+                    .event(password_changed_event(user.id, "change"))
+                    .revoke_all_sessions(user.id, true),
+            )
+            .await?;
+        self.revoke_access_tokens(user.id).await?;
+
+        self.notify_security_event(&user, SecurityAlert::PasswordChanged).await;
+
+        Ok(PasswordResetResponse {
+            message: "Password updated successfully".into(),
+        })
+    }
+
+    /// Make the given users (or everyone) change their password at the next login,
+    /// e.g. after an incident
+    pub async fn force_password_reset(
+        &self,
+        admin_id: Uuid,
+        data: ForcePasswordResetRequest,
+    ) -> Result<ForcePasswordResetResponse, AuthError> {
+        let user_ids = match (data.all, data.user_ids.is_empty()) {
+            (true, true) => None,
+            (false, false) => Some(data.user_ids),
+            _ => {
+                return Err(AuthError::ValidationError(
+                    "Provide either user_ids or all, but not both".into(),
+                ))
+            }
+        };
+
+        let affected = self.db.expire_passwords(user_ids).await?;
+
+        if data.revoke_sessions {
+            for user_id in &affected {
+                self.db.revoke_all_sessions(*user_id, true).await?;
+                self.revoke_access_tokens(*user_id).await?;
+            }
+        } else {
+            for user_id in &affected {
+                self.user_cache.invalidate(*user_id);
+            }
+        }
+
+        log::info!(
+            "Admin {} expired the passwords of {} users (sessions revoked: {})",
+            admin_id,
+            affected.len(),
+            data.revoke_sessions
+        );
+
+        Ok(ForcePasswordResetResponse {
+            affected: affected.len(),
+        })
+    }
+
+    /// Queue an operation over many accounts for the background runner. A
+    /// dry run goes through the same accounts and only reports on them.
+    pub async fn create_bulk_job(&self, admin_id: Uuid, data: BulkJobRequest) -> Result<BulkJobResponse, AuthError> {
+        let max_targets = self.config.bulk_jobs.max_targets;
+        let too_many = |count: usize| {
+            AuthError::ValidationError(format!("A bulk job takes at most {} accounts, not {}", max_targets, count))
+        };
+
+        let action = match data.action {
+            BulkAction::RevokeSessions { mut user_ids } => {
+                let mut seen = HashSet::new();
+                user_ids.retain(|id| seen.insert(*id));
+                if user_ids.is_empty() {
+                    return Err(AuthError::ValidationError("List at least one user".into()));
+                }
+                if user_ids.len() > max_targets {
+                    return Err(too_many(user_ids.len()));
+                }
+                BulkAction::RevokeSessions { user_ids }
+            }
+            BulkAction::ForcePasswordReset { accounts, revoke_sessions } => {
+                // Breach dumps repeat themselves and come with stray whitespace
+                let mut seen = HashSet::new();
+                let accounts: Vec<String> = accounts
+                    .iter()
+                    .map(|account| account.trim())
+                    .filter(|account| !account.is_empty() && seen.insert(account.to_lowercase()))
+                    .map(str::to_string)
+                    .collect();
+                if accounts.is_empty() {
+                    return Err(AuthError::ValidationError("List at least one email or username".into()));
+                }
+                if accounts.len() > max_targets {
+                    return Err(too_many(accounts.len()));
+                }
+                BulkAction::ForcePasswordReset { accounts, revoke_sessions }
+            }
+            BulkAction::DeactivateDomain { domain, reason } => {
+                let domain = normalize_domain(&domain)
+                    .ok_or_else(|| AuthError::ValidationError("Invalid domain".into()))?;
+                let reason = reason.trim().to_string();
+                if reason.is_empty() || reason.len() > 500 {
+                    return Err(AuthError::ValidationError("A reason of up to 500 characters is required".into()));
+                }
+                BulkAction::DeactivateDomain { domain, reason }
+            }
+        };
+
+        let job = self
+            .db
+            .create_bulk_job(NewBulkJob {
+                id: Uuid::new_v4(),
+                action: action.as_str().to_string(),
+                params: serde_json::to_value(&action).map_err(|e| AuthError::InternalServerError(e.to_string()))?,
+                dry_run: data.dry_run,
+                created_by: Some(admin_id),
+            })
+            .await?;
+
+        log::info!(
+            "Admin {} queued bulk job {} ({}{})",
+            admin_id,
+            job.id,
+            job.action,
+            if job.dry_run { ", dry run" } else { "" }
+        );
+
+        Ok(job.into())
+    }
+
+    pub async fn list_bulk_jobs(&self) -> Result<Vec<BulkJobResponse>, AuthError> {
+        let jobs = self.db.list_bulk_jobs(MAX_LISTED_BULK_JOBS).await?;
+        Ok(jobs.into_iter().map(BulkJobResponse::from).collect())
+    }
+
+    pub async fn get_bulk_job(&self, job_id: Uuid) -> Result<BulkJobResponse, AuthError> {
+        Ok(self.find_bulk_job(job_id).await?.into())
+    }
+
+    /// What happened to each account, once the job has finished
+    pub async fn bulk_job_report(&self, job_id: Uuid) -> Result<BulkJobReport, AuthError> {
+        let job = self.find_bulk_job(job_id).await?;
+        if !job.status().is_finished() {
+            return Err(AuthError::ValidationError("The job hasn't finished yet".into()));
+        }
+
+        let results = job.results();
+        Ok(BulkJobReport {
+            job: job.into(),
+            results,
+        })
+    }
+
+    async fn find_bulk_job(&self, job_id: Uuid) -> Result<BulkJob, AuthError> {
+        self.db
+            .find_bulk_job(job_id)
+            .await?
+            .ok_or_else(|| AuthError::ValidationError("Bulk job not found".into()))
+    }
+
+    /// Re-enter credentials to satisfy a step-up policy, returning a fresh access token
+    pub async fn reauthenticate(
+        &self,
+        user_id: Uuid,
+        data: ReauthenticateRequest,
+        ip: Option<String>,
+    ) -> Result<ReauthenticateResponse, AuthError> {
+        let user = self.db.find_user_by_id(user_id).await?;
+
+        // Wrong guesses count like failed logins, so a session can't be used
+        // to try passwords without limit. There's no CAPTCHA to answer here,
+        // but lockouts still apply.
+        let tarpit_keys = LoginTarpit::keys(&user.username, ip.as_deref());
+        self.tarpit.wait(&tarpit_keys).await;
+        self.check_brute_force(Some(&user), ip.as_deref(), &CaptchaSolution::default(), true)?;
+
+        if !verify_password(&data.password, &user.password_hash)? {
+            self.tarpit.record_failure(&tarpit_keys);
+            self.record_login_failure(Some(&user), &ip).await;
+            return Err(AuthError::InvalidCredentials);
+        }
+
+        let mut amr = vec![AMR_PASSWORD];
+
+        if let Some(mfa_code) = &data.mfa_code {
+            if !user.mfa_enabled {
+                return Err(AuthError::ValidationError("MFA is not enabled".into()));
+            }
+            if !self.verify_any_totp(&user, mfa_code).await? {
+                self.tarpit.record_failure(&tarpit_keys);
+                self.record_login_failure(Some(&user), &ip).await;
+                return Err(AuthError::InvalidMfaCode);
+            }
+            amr.extend([AMR_OTP, AMR_MFA]);
+        }
+
+        Ok(ReauthenticateResponse {
+            access_token: self.create_access_token(&user, &amr, None)?,
+            token_type: "Bearer".into(),
+            expires_in: self.config.jwt.access_token_expiry,
+        })
+    }
+
+    pub async fn mfa_setup(&self, user_id: Uuid) -> Result<MfaSetupResponse, AuthError> {
+        // Find user
+        let user = self.db.find_user_by_id(user_id).await?;
+
+        // Check if MFA is already enabled
+        if user.mfa_enabled {
+            return Err(AuthError::ValidationError("MFA is already enabled".into()));
         }
 
         // Generate TOTP secret
         let (secret, qr_code_url) = self
             .mfa_service
-            .generate_totp_secret(&user.username);
+            .generate_totp_secret(&user.username)?;
 
         // Save secret temporarily
-        self.db.update_mfa_secret(user.id, &secret).await?;
+        self.db.update_mfa_secret(user.id, Some(user.version), &secret).await?;
 
         Ok(MfaSetupResponse {
             secret,
@@ -472,6 +1999,28 @@ impl AuthService {
         })
     }
 
+    /// QR code for the pending TOTP secret created by `mfa_setup`
+    pub async fn mfa_setup_qr_code(&self, user_id: Uuid, format: QrFormat) -> Result<Vec<u8>, AuthError> {
+        let user = self.db.find_user_by_id(user_id).await?;
+
+        // Only while enrollment is in progress; an active secret is never shown again
+        if user.mfa_enabled {
+            return Err(AuthError::ValidationError("MFA is already enabled".into()));
+        }
+
+        let secret = user
+            .mfa_secret
+            .as_ref()
+            .ok_or_else(|| AuthError::ValidationError("MFA setup has not been started".into()))?;
+
+        let url = self
+            .mfa_service
+            .provisioning_url(secret, &user.username)
+            .ok_or_else(|| AuthError::InternalServerError("Invalid MFA secret".into()))?;
+
+        self.mfa_service.render_qr_code(&url, format)
+    }
+
     pub async fn mfa_enable(
         &self,
         user_id: Uuid,
@@ -496,8 +2045,11 @@ impl AuthService {
             return Err(AuthError::InvalidMfaCode);
         }
 
-        // Enable MFA
-        self.db.enable_mfa(user.id).await?;
+        // Enable MFA, unless the enrollment changed while the code was checked
+        self.db.enable_mfa(user.id, Some(user.version)).await?;
+        if user.mfa_reenrollment_required {
+            self.db.set_mfa_reenrollment_required(user.id, false).await?;
+        }
 
         // Generate recovery codes
         let recovery_codes = self.generate_recovery_codes(user.id).await?;
@@ -523,188 +2075,4615 @@ impl AuthService {
             return Err(AuthError::InvalidCredentials);
         }
 
-        // Verify TOTP code
-        if let Some(secret) = &user.mfa_secret {
-            if !self.mfa_service.verify_totp(secret, &data.mfa_code) {
-                return Err(AuthError::InvalidMfaCode);
-            }
-        } else {
-            return Err(AuthError::InternalServerError("MFA secret not found".into()));
+        // Verify TOTP code from any enrolled device
+        if !self.verify_any_totp(&user, &data.mfa_code).await? {
+            return Err(AuthError::InvalidMfaCode);
         }
 
         // Disable MFA
-        let user = self.db.disable_mfa(user.id).await?;
+        let user = self.db.disable_mfa(user.id, Some(user.version)).await?;
 
-        // Delete recovery codes
+        // Delete recovery codes and additional devices
         self.db.delete_recovery_codes(user.id).await?;
+        self.db.delete_totp_devices(user.id).await?;
+
+        self.notify_security_event(&user, SecurityAlert::MfaDisabled).await;
 
         Ok(user.into())
     }
 
     pub async fn mfa_verify(
         &self,
+        user_id: Uuid,
         data: VerifyMfaRequest,
         ip: Option<String>,
         user_agent: Option<String>,
     ) -> Result<MfaVerifyResponse, AuthError> {
-        // MFA verification happens after login, so we should have a temporary session
-        // This implementation is simplified - you'd need a temporary token system
-        
-        // This is a placeholder for demonstration
-        // In a real system, you'd verify the temporary token, get the user ID
-        // For now, we'll just use a hardcoded example
-        
-        return Err(AuthError::InternalServerError("MFA verification not fully implemented".into()));
-        
-        // The rest of the function would look something like:
-        /*
-        // Verify the temporary token to get the user
-        let user = verify_temp_token(...)?;
-        
-        // Verify TOTP code
-        if let Some(secret) = &user.mfa_secret {
-            if !self.mfa_service.verify_totp(secret, &data.mfa_code) {
-                return Err(AuthError::InvalidMfaCode);
-            }
-        } else {
-            return Err(AuthError::InternalServerError("MFA secret not found".into()));
-        }
-
-        // Generate tokens
-        let access_token = self.create_access_token(&user)?;
-        let refresh_token = Uuid::new_v4().to_string();
-
-        // Save refresh token
-        let expires_at = Utc::now() + Duration::seconds(self.config.jwt.refresh_token_expiry as i64);
-        let session = NewSession {
-            id: Uuid::new_v4(),
-            user_id: user.id,
-            refresh_token: refresh_token.clone(),
-            user_agent,
-            ip_address: ip,
-            expires_at,
-        };
-
-        self.db.create_session(session).await?;
+        // The caller holds an `mfa_pending` token from `login`
+        self.ensure_mfa_attempts_left(user_id).await?;
+        let user = self.db.find_user_by_id(user_id).await?;
 
-        // Update last login
-        self.db.update_last_login(user.id).await?;
+        // Verify TOTP code from any enrolled device
+        if !self.verify_any_totp(&user, &data.mfa_code).await? {
+            self.record_mfa_failure(user.id).await?;
+            return Err(AuthError::InvalidMfaCode);
+        }
 
-        Ok(MfaVerifyResponse {
-            access_token,
-            refresh_token,
-            token_type: "Bearer".into(),
-            expires_in: self.config.jwt.access_token_expiry,
-            user: user.into(),
-        })
-        */
+        self.complete_mfa_login(user, &[AMR_PASSWORD, AMR_OTP, AMR_MFA], ip, user_agent).await
     }
 
     pub async fn mfa_recovery(
         &self,
+        user_id: Uuid,
         data: MfaRecoveryRequest,
         ip: Option<String>,
         user_agent: Option<String>,
     ) -> Result<MfaVerifyResponse, AuthError> {
-        // Similar to mfa_verify, this requires a temporary token system
-        // This is a placeholder
-        
-        return Err(AuthError::InternalServerError("MFA recovery not fully implemented".into()));
+        // The caller holds an `mfa_pending` token from `login`
+        self.ensure_mfa_attempts_left(user_id).await?;
+        let user = self.db.find_user_by_id(user_id).await?;
+
+        // Verify recovery code
+        if !self.db.use_recovery_code(user.id, &data.recovery_code).await? {
+            self.record_mfa_failure(user.id).await?;
+            return Err(AuthError::InvalidMfaCode);
+        }
+
+        let mut response = self.complete_mfa_login(user, &[AMR_PASSWORD, AMR_MFA], ip, user_agent).await?;
+        response.recovery_codes = Some(self.recovery_code_status(user_id).await?);
+        Ok(response)
     }
 
-    pub async fn mfa_recovery_codes(
+    /// Start enrolling an additional TOTP device; MFA must already be enabled
+    pub async fn add_totp_device(
         &self,
         user_id: Uuid,
-    ) -> Result<MfaRecoveryCodesResponse, AuthError> {
-        // Find user
+        data: AddTotpDeviceRequest,
+    ) -> Result<TotpDeviceSetupResponse, AuthError> {
         let user = self.db.find_user_by_id(user_id).await?;
 
-        // Check if MFA is enabled
         if !user.mfa_enabled {
             return Err(AuthError::ValidationError("MFA is not enabled".into()));
         }
 
-        // Generate new recovery codes
-        self.db.delete_recovery_codes(user.id).await?;
-        let recovery_codes = self.generate_recovery_codes(user.id).await?;
+        let (secret, qr_code_url) = self.mfa_service.generate_totp_secret(&user.username)?;
 
-        Ok(MfaRecoveryCodesResponse { recovery_codes })
+        let device = self
+            .db
+            .create_totp_device(NewTotpDevice {
+                id: Uuid::new_v4(),
+                user_id: user.id,
+                name: data.name.trim().to_string(),
+                secret: secret.clone(),
+            })
+            .await?;
+
+        Ok(TotpDeviceSetupResponse {
+            device_id: device.id,
+            secret,
+            qr_code_url,
+        })
     }
 
-    pub async fn get_user(&self, user_id: Uuid) -> Result<UserResponse, AuthError> {
-        let user = self.db.find_user_by_id(user_id).await?;
-        Ok(user.into())
+    /// Confirm a new device with a code from it; unconfirmed devices never satisfy MFA
+    pub async fn confirm_totp_device(
+        &self,
+        user_id: Uuid,
+        device_id: Uuid,
+        data: ConfirmTotpDeviceRequest,
+    ) -> Result<TotpDeviceResponse, AuthError> {
+        let device = self.find_totp_device(user_id, device_id).await?;
+
+        if device.is_confirmed {
+            return Err(AuthError::ValidationError("Device is already confirmed".into()));
+        }
+
+        if !self.mfa_service.verify_totp(&device.secret, &data.mfa_code) {
+            return Err(AuthError::InvalidMfaCode);
+        }
+
+        self.db.confirm_totp_device(device.id).await?;
+
+        let device = self.find_totp_device(user_id, device_id).await?;
+        Ok(device.into())
     }
 
-    pub async fn get_sessions(&self, user_id: Uuid) -> Result<Vec<SessionResponse>, AuthError> {
-        let sessions = self.db.find_sessions_by_user_id(user_id).await?;
-        
-        let response: Vec<SessionResponse> = sessions
-            .into_iter()
-            .map(|s| {
-                let mut sr: SessionResponse = s.into();
-                // Mark current session - this requires comparing with the actual session token
-                // which we don't have here without a context
-                sr.is_current = false;
-                sr
-            })
-            .collect();
-        
-        Ok(response)
+    /// Every TOTP authenticator, the one enrolled through `mfa_setup` first
+    pub async fn list_totp_devices(&self, user_id: Uuid) -> Result<Vec<TotpDeviceResponse>, AuthError> {
+        let (user, devices) = futures::try_join!(
+            self.db.find_user_by_id(user_id),
+            self.db.find_totp_devices_by_user_id(user_id),
+        )?;
+
+        let mut listed = Vec::with_capacity(devices.len() + 1);
+        if user.mfa_enabled && user.mfa_secret.is_some() {
+            listed.push(TotpDeviceResponse::primary());
+        }
+        listed.extend(devices.into_iter().map(TotpDeviceResponse::from));
+        Ok(listed)
     }
 
-    pub async fn revoke_session(
+    pub async fn remove_totp_device(
         &self,
         user_id: Uuid,
-        session_id: Uuid,
-    ) -> Result<LogoutResponse, AuthError> {
-        // Find session
-        let session = self.db.find_session_by_id(session_id).await?;
+        device_id: Uuid,
+    ) -> Result<TotpDeviceRemovedResponse, AuthError> {
+        let device = self.find_totp_device(user_id, device_id).await?;
+        self.db.delete_totp_device(device.id).await?;
 
-        // Check if user owns the session
-        if session.user_id != user_id {
-            return Err(AuthError::PermissionDenied);
+        let remaining_devices = self
+            .list_totp_devices(user_id)
+            .await?
+            .iter()
+            .filter(|d| d.is_confirmed)
+            .count();
+
+        Ok(TotpDeviceRemovedResponse {
+            device_id: device.id,
+            name: device.name,
+            remaining_devices,
+        })
+    }
+
+    /// Replace the user's recovery codes with a new set; the old codes stop
+    /// working at once
+    pub async fn mfa_recovery_codes(&self, user_id: Uuid) -> Result<RecoverySheet, AuthError> {
+        // Find user
+        let user = self.db.find_user_by_id(user_id).await?;
+
+        // Check if MFA is enabled
+        if !user.mfa_enabled {
+            return Err(AuthError::ValidationError("MFA is not enabled".into()));
         }
 
-        // Revoke session
-        self.db.revoke_session(session.id).await?;
+        // Generate new recovery codes
+        self.db.delete_recovery_codes(user.id).await?;
+        let codes = self.generate_recovery_codes(user.id).await?;
 
-        Ok(LogoutResponse {
-            message: "Session revoked successfully".into(),
+        Ok(RecoverySheet {
+            issuer: self.config.totp.issuer.clone(),
+            account: user.username,
+            generated_at: Utc::now(),
+            codes,
         })
     }
 
-    // Helper functions
+    /// How many recovery codes the user has left, with a warning once it's
+    /// time to generate a new set
+    pub async fn recovery_code_status(&self, user_id: Uuid) -> Result<RecoveryCodeStatus, AuthError> {
+        let codes = self.db.find_recovery_codes(user_id).await?;
+        Ok(RecoveryCodeStatus::new(&codes, self.config.recovery_codes.warn_below))
+    }
 
-    fn create_access_token(&self, user: &User) -> Result<String, AuthError> {
-        let claims = JwtClaims {
-            sub: user.id,
-            exp: (Utc::now() + Duration::seconds(self.config.jwt.access_token_expiry as i64)).timestamp() as usize,
-            iat: Utc::now().timestamp() as usize,
-            is_admin: user.is_admin,
-        };
+    /// The user's security questions, without the answers
+    pub async fn security_questions(&self, user_id: Uuid) -> Result<SecurityQuestionsResponse, AuthError> {
+        if !self.config.security_questions.enabled {
+            return Err(AuthError::PermissionDenied);
+        }
 
-        create_jwt(&claims, &self.config.jwt.secret)
+        Ok(self.db.find_security_questions(user_id).await?.into())
     }
 
-    async fn generate_recovery_codes(&self, user_id: Uuid) -> Result<Vec<String>, AuthError> {
-        let mut codes = Vec::new();
+    /// Replace the user's security questions. Answers are normalized before
+    /// they're hashed, so case, punctuation and spacing don't matter later.
+    pub async fn save_security_questions(
+        &self,
+        user_id: Uuid,
+        data: SecurityQuestionsRequest,
+    ) -> Result<SecurityQuestionsResponse, AuthError> {
+        if !self.config.security_questions.enabled {
+            return Err(AuthError::PermissionDenied);
+        }
+        let count = self.config.security_questions.count;
+        if data.questions.len() != count {
+            return Err(AuthError::ValidationError(format!("Exactly {} questions are required", count)));
+        }
 
-        // Generate 10 recovery codes
-        for _ in 0..10 {
-            let code = self.mfa_service.generate_recovery_code();
-            codes.push(code.clone());
+        let mut questions: Vec<NewSecurityQuestion> = Vec::with_capacity(count);
+        let mut answers: Vec<String> = Vec::with_capacity(count);
+        for (position, entry) in data.questions.iter().enumerate() {
+            let question = entry.question.trim();
+            if question.is_empty() || question.chars().count() > 200 {
+                return Err(AuthError::ValidationError("Questions must be 1 to 200 characters".into()));
+            }
+            if questions.iter().any(|q| q.question.to_lowercase() == question.to_lowercase()) {
+                return Err(AuthError::ValidationError("Each question must be different".into()));
+            }
+
+            // An answer that's short or repeats another adds little
+            let answer = normalize_answer(entry.answer.expose());
+            if answer.chars().count() < 3 || answer.chars().count() > 200 {
+                return Err(AuthError::ValidationError(
+                    "Answers must have 3 to 200 letters or digits".into(),
+                ));
+            }
+            if answers.contains(&answer) {
+                return Err(AuthError::ValidationError("Each answer must be different".into()));
+            }
 
-            // Save recovery code
-            let recovery_code = NewMfaRecoveryCode {
+            questions.push(NewSecurityQuestion {
                 id: Uuid::new_v4(),
                 user_id,
-                code,
-            };
+                position: position as i32,
+                question: question.to_string(),
+                answer_hash: hash_password(&answer)?,
+            });
+            answers.push(answer);
+        }
 
-            self.db.create_recovery_code(recovery_code).await?;
+        let saved = self.db.save_security_questions(user_id, questions).await?;
+        self.db.clear_security_question_failures(user_id).await?;
+
+        log::info!("User {} set their security questions", user_id);
+
+        Ok(saved.into())
+    }
+
+    pub async fn delete_security_questions(&self, user_id: Uuid) -> Result<LogoutResponse, AuthError> {
+        if !self.db.delete_security_questions(user_id).await? {
+            return Err(AuthError::ValidationError("No security questions are set".into()));
         }
 
-        Ok(codes)
+        log::info!("User {} removed their security questions", user_id);
+
+        Ok(LogoutResponse {
+            message: "Security questions removed".to_string(),
+        })
     }
+
+    /// The questions a caller holding an `mfa_pending` token can answer
+    /// instead of giving a second factor
+    pub async fn pending_security_questions(&self, user_id: Uuid) -> Result<SecurityQuestionsResponse, AuthError> {
+        let response = self.security_questions(user_id).await?;
+        if response.questions.is_empty() {
+            return Err(AuthError::ValidationError("No security questions are set".into()));
+        }
+        Ok(response)
+    }
+
+    /// Finish a login by answering every security question, the last resort
+    /// when neither a second factor nor a recovery code is at hand. The
+    /// session only reaches AAL1, and the owner is told it happened.
+    pub async fn security_question_recovery(
+        &self,
+        user_id: Uuid,
+        data: SecurityQuestionRecoveryRequest,
+        ip: Option<String>,
+        user_agent: Option<String>,
+    ) -> Result<MfaVerifyResponse, AuthError> {
+        let config = &self.config.security_questions;
+        if !config.enabled {
+            return Err(AuthError::PermissionDenied);
+        }
+
+        // Too many wrong sets lock recovery for the rest of the window, on
+        // every instance, whatever address the guesses come from
+        let window = Duration::seconds(config.window as i64);
+        if let Some(failures) = self.db.find_security_question_failures(user_id).await? {
+            let retry_at = failures.last_failed_at + window;
+            if failures.failure_count >= config.max_failures as i32 && retry_at > Utc::now() {
+                return Err(AuthError::RateLimitExceeded {
+                    limit: config.max_failures,
+                    retry_after: (retry_at - Utc::now()).num_seconds().max(1) as u64,
+                });
+            }
+        }
+
+        let user = self.db.find_user_by_id(user_id).await?;
+        let questions = self.db.find_security_questions(user.id).await?;
+        if questions.is_empty() {
+            return Err(AuthError::ValidationError("No security questions are set".into()));
+        }
+
+        // Every answer is checked, so the time taken doesn't tell which was wrong
+        let mut correct = data.answers.len() == questions.len();
+        for (question, answer) in questions.iter().zip(&data.answers) {
+            correct &= verify_password(&normalize_answer(answer.expose()), &question.answer_hash)?;
+        }
+        if !correct {
+            let failures = self
+                .db
+                .record_security_question_failure(user.id, Utc::now() - window)
+                .await?;
+            log::warn!(
+                "Wrong security question answers for user {} ({} of {} allowed)",
+                user.id,
+                failures,
+                config.max_failures
+            );
+            return Err(AuthError::InvalidCredentials);
+        }
+
+        self.db.clear_security_question_failures(user.id).await?;
+        self.notify_security_event(&user, SecurityAlert::SecurityQuestionsUsed).await;
+        log::info!("User {} answered their security questions in place of a second factor", user.id);
+
+        self.complete_mfa_login(user, &[AMR_PASSWORD, AMR_KBA], ip, user_agent).await
+    }
+
+    /// Enforce the email verification policy for a route marked sensitive
+    pub async fn ensure_email_verified(&self, user_id: Uuid) -> Result<(), AuthError> {
+        if self.config.email.require_verified_email == EmailVerificationPolicy::Never {
+            return Ok(());
+        }
+
+        let user = self.user_cache.load(user_id).await?;
+        if !user.is_email_verified {
+            return Err(AuthError::EmailNotVerified { resend_token: None });
+        }
+
+        Ok(())
+    }
+
+    /// Invalidate every access token issued to the user so far
+    pub async fn revoke_access_tokens(&self, user_id: Uuid) -> Result<(), AuthError> {
+        self.db.bump_token_version(user_id).await?;
+        self.user_cache.invalidate(user_id);
+        Ok(())
+    }
+
+    /// Swap the email transport before the service is shared, e.g. for a test double
+    pub fn set_email_transport(&mut self, transport: Arc<dyn EmailTransport>) {
+        self.email_service.set_transport(transport);
+    }
+
+    /// Cache used by `AuthMiddleware`, to be registered as app data
+    pub fn user_cache(&self) -> Arc<UserCache> {
+        self.user_cache.clone()
+    }
+
+    /// Accounts flagged here must reset their password before they can log in
+    pub fn breach_detection(&self) -> Arc<BreachDetectionContext> {
+        self.breach_detection.clone()
+    }
+
+    /// Revoked sessions checked by `AuthMiddleware`, to be registered as app
+    /// data; also the poll for other instances' revocations, for spawning at startup
+    pub fn token_revocations(&self) -> Arc<TokenRevocations> {
+        self.token_revocations.clone()
+    }
+
+    /// Flags read by the `Flags` extractor, to be registered as app data
+    pub fn feature_flags(&self) -> Arc<FeatureFlags> {
+        self.feature_flags.clone()
+    }
+
+    /// DPoP proof checker shared by token requests and `AuthMiddleware`, to be
+    /// registered as app data
+    pub fn dpop_verifier(&self) -> Arc<DpopVerifier> {
+        self.dpop.clone()
+    }
+
+    pub fn tarpit_metrics(&self) -> TarpitMetrics {
+        self.tarpit.metrics()
+    }
+
+    /// The purge of ended sessions, for spawning at startup
+    pub fn session_purge(&self) -> Arc<SessionPurge> {
+        self.session_purge.clone()
+    }
+
+    /// The FIDO metadata sync, for spawning at startup
+    pub fn fido_metadata(&self) -> Arc<FidoMetadata> {
+        self.fido_metadata.clone()
+    }
+
+    /// The export of old audit and login events, for spawning at startup
+    pub fn event_exporter(&self) -> Arc<EventExporter> {
+        self.event_exporter.clone()
+    }
+
+    /// The archiving of inactive accounts, for spawning at startup
+    pub fn user_archiver(&self) -> Arc<UserArchiver> {
+        self.user_archiver.clone()
+    }
+
+    /// The runner for admins' bulk jobs, for spawning at startup
+    pub fn bulk_job_runner(&self) -> Arc<BulkJobRunner> {
+        self.bulk_jobs.clone()
+    }
+
+    pub async fn session_table_metrics(&self) -> Result<SessionTableMetrics, AuthError> {
+        self.session_purge.metrics().await
+    }
+
+    /// Accessibility preferences, which also decide the CAPTCHA each user is served
+    pub fn accessibility(&self) -> Arc<AccessibilityContext> {
+        self.accessibility.clone()
+    }
+
+    /// Whether the bundled admin pages are served
+    pub fn admin_ui_enabled(&self) -> bool {
+        self.config.admin_ui.enabled
+    }
+
+    /// Settings for the server-rendered sign-in pages
+    pub fn hosted_pages(&self) -> &HostedPagesConfig {
+        &self.config.hosted_pages
+    }
+
+    /// Whether logins and registrations must carry a solved CAPTCHA
+    pub fn captcha_required(&self) -> bool {
+        self.config.captcha.required
+    }
+
+    /// The user an `mfa_pending` token from `login` was issued to, for the
+    /// hosted pages, which carry the token in a form rather than a header
+    pub fn mfa_pending_user(&self, token: &str) -> Result<Uuid, AuthError> {
+        let claims = decode_jwt::<JwtClaims>(token)?;
+        if claims.scope != TokenScope::MfaPending || claims.cnf.is_some() {
+            return Err(AuthError::InvalidToken);
+        }
+        Ok(claims.sub)
+    }
+
+    /// The region whose database has the session a refresh token is for, or
+    /// the user an access token or API key was issued to, so requests
+    /// carrying one run there. `None` without regions, and for tokens that
+    /// don't check out.
+    pub async fn region_of_token(&self, token: &str) -> Result<Option<String>, AuthError> {
+        if !self.db.is_regional() {
+            return Ok(None);
+        }
+
+        // Refresh tokens say where they're from; see `new_refresh_token`
+        if let Some((region, rest)) = token.split_once(REFRESH_TOKEN_REGION_SEPARATOR) {
+            if Uuid::parse_str(rest).is_ok() && self.db.has_region(region) {
+                return Ok(Some(region.to_string()));
+            }
+        }
+
+        // So do API keys; see `api_key::generate`. Keys made before there
+        // were regions are for users of the home region.
+        if api_key::is_api_key(token) {
+            return Ok(api_key::region(token).filter(|region| self.db.has_region(region)).map(str::to_string));
+        }
+
+        let expected = TokenAudience::new(self.config.jwt.issuer.clone(), &self.config.jwt.accepted_audiences);
+        match decode_jwt_with_secret::<JwtClaims>(token, &self.config.jwt.secret, &expected) {
+            Ok(claims) => self.db.region_of_user(claims.sub).await,
+            Err(_) => Ok(None),
+        }
+    }
+
+    // The region of the user an emailed link is for, so following it runs
+    // there; `None` for links that don't check out, which fail when redeemed
+    async fn region_of_link(&self, token: &str, purpose: ActionPurpose) -> Result<Option<String>, AuthError> {
+        match self.action_tokens.subject(token, purpose) {
+            Some(user_id) => self.db.region_of_user(user_id).await,
+            None => Ok(None),
+        }
+    }
+
+    pub fn accessibility_report(&self) -> AccessibilityReport {
+        self.accessibility.generate_accessibility_report()
+    }
+
+    /// Record a call to the admin API, described by `middleware::admin_audit`
+    pub async fn record_admin_request(&self, admin_id: Uuid, request: serde_json::Value) -> Result<(), AuthError> {
+        let event = NewOutboxEvent::new(EventType::AdminRequest, admin_id, request);
+        self.db.commit(UnitOfWork::new().event(event)).await
+    }
+
+    /// The startup checks, run again against the live configuration
+    pub async fn system_checks(&self) -> SystemCheckReport {
+        system_checks::run(&self.config, &self.db).await
+    }
+
+    /// Issue a CAPTCHA suited to the account's accessibility preferences, or
+    /// the audio one when the client asks for it. Other kinds can't be picked,
+    /// so a bot can't choose the easiest.
+    pub async fn issue_captcha(
+        &self,
+        data: CaptchaChallengeRequest,
+    ) -> Result<CaptchaChallenge, AuthError> {
+        match data.kind {
+            Some(CaptchaAlternative::Audio) => return Ok(self.accessibility.issue_captcha(CaptchaAlternative::Audio)),
+            Some(_) => return Err(AuthError::ValidationError("Only the audio CAPTCHA can be asked for".into())),
+            None => {}
+        }
+
+        let user = match &data.username_or_email {
+            Some(username_or_email) => {
+                match self.db.find_user_by_username_or_email(username_or_email).await {
+                    Ok(user) => Some(user),
+                    // Unknown accounts get the default challenge, like any other
+                    Err(AuthError::UserNotFound) => None,
+                    Err(err) => return Err(err),
+                }
+            }
+            None => None,
+        };
+
+        Ok(match user {
+            Some(user) => self.accessibility.issue_captcha_for_user(&user.id),
+            None => self.accessibility.issue_captcha(CaptchaAlternative::Standard),
+        })
+    }
+
+    /// WAV recording of an audio CAPTCHA
+    pub fn captcha_audio(&self, captcha_id: Uuid) -> Result<Vec<u8>, AuthError> {
+        self.accessibility
+            .captcha_audio(&captcha_id)
+            .ok_or(AuthError::InvalidCaptcha)
+    }
+
+    /// Resolve a spoken command ("sign in", "I forgot my password") to an auth intent
+    pub async fn recognize_voice_command(
+        &self,
+        audio_data: &[u8],
+        content_type: &str,
+    ) -> Result<VoiceCommand, AuthError> {
+        match self.accessibility.parse_voice_command(audio_data, content_type).await {
+            Ok(Some(command)) => Ok(command),
+            Ok(None) => Err(AuthError::VoiceCommandNotRecognized),
+            Err(SpeechError::NotConfigured) => Err(AuthError::SpeechRecognitionUnavailable),
+            Err(SpeechError::Provider(e)) => {
+                log::warn!("Speech recognition failed: {}", e);
+                Err(AuthError::SpeechRecognitionUnavailable)
+            }
+        }
+    }
+
+    fn check_captcha(&self, solution: &CaptchaSolution) -> Result<(), AuthError> {
+        if !self.config.captcha.required {
+            return Ok(());
+        }
+
+        self.verify_captcha(solution)
+    }
+
+    fn verify_captcha(&self, solution: &CaptchaSolution) -> Result<(), AuthError> {
+        match (&solution.captcha_id, &solution.captcha_answer) {
+            (Some(id), Some(answer)) if self.accessibility.verify_captcha(id, answer) => Ok(()),
+            (Some(_), Some(_)) => Err(AuthError::InvalidCaptcha),
+            _ => Err(AuthError::CaptchaRequired { challenge: None }),
+        }
+    }
+
+    // Risk scoring asked for a CAPTCHA. Without an answer, the error carries a
+    // challenge in the user's preferred CAPTCHA format to retry with.
+    fn check_risk_captcha(&self, user: &User, solution: &CaptchaSolution) -> Result<(), AuthError> {
+        match self.verify_captcha(solution) {
+            Err(AuthError::CaptchaRequired { .. }) => Err(AuthError::CaptchaRequired {
+                challenge: Some(self.accessibility.issue_captcha_for_user(&user.id)),
+            }),
+            result => result,
+        }
+    }
+
+    pub async fn get_user(&self, user_id: Uuid) -> Result<UserResponse, AuthError> {
+        let user = self.db.find_user_by_id(user_id).await?;
+        Ok(user.into())
+    }
+
+    /// `expected_version` comes from `If-Match`; without it the changes apply
+    /// on top of whatever is stored
+    pub async fn update_profile(
+        &self,
+        user_id: Uuid,
+        data: UpdateProfileRequest,
+        expected_version: Option<i32>,
+    ) -> Result<UserResponse, AuthError> {
+        // Empty strings clear a field
+        let clearable = |value: Option<String>| {
+            value.map(|v| Some(v.trim().to_string()).filter(|v| !v.is_empty()))
+        };
+
+        let changes = ProfileChanges {
+            display_name: clearable(data.display_name),
+            avatar_url: clearable(data.avatar_url),
+            locale: clearable(data.locale),
+            timezone: clearable(data.timezone),
+            metadata: data.metadata,
+        };
+
+        if let Some(Some(avatar_url)) = &changes.avatar_url {
+            validate_http_url(avatar_url)?;
+        }
+        if let Some(Some(locale)) = &changes.locale {
+            validate_locale(locale)?;
+        }
+        if let Some(Some(timezone)) = &changes.timezone {
+            validate_timezone(timezone)?;
+        }
+        if let Some(metadata) = &changes.metadata {
+            validate_metadata(metadata)?;
+        }
+
+        let user = self.db.update_profile(user_id, expected_version, changes).await?;
+        self.user_cache.invalidate(user_id);
+
+        Ok(user.into())
+    }
+
+    /// Replace the user's avatar with an uploaded image
+    pub async fn upload_avatar(&self, user_id: Uuid, data: Vec<u8>) -> Result<UserResponse, AuthError> {
+        let limit = self.config.avatar.max_upload_size;
+        if data.len() > limit {
+            return Err(AuthError::PayloadTooLarge { limit });
+        }
+
+        // Decoding and resizing are CPU-bound
+        let size = self.config.avatar.size;
+        let png = tokio::task::spawn_blocking(move || process_avatar(&data, size))
+            .await
+            .map_err(|e| AuthError::InternalServerError(format!("Task join error: {}", e)))??;
+
+        // A fresh key per upload, so CDNs and browsers never serve the old image
+        let key = format!("avatars/{}/{}.png", user_id, Uuid::new_v4().simple());
+        let url = self.storage.put(&key, "image/png", png).await?;
+
+        self.set_avatar_url(user_id, Some(url)).await
+    }
+
+    pub async fn remove_avatar(&self, user_id: Uuid) -> Result<UserResponse, AuthError> {
+        self.set_avatar_url(user_id, None).await
+    }
+
+    /// A stored avatar, for serving from local storage
+    pub async fn avatar_file(&self, key: &str) -> Result<Option<Vec<u8>>, AuthError> {
+        if !key.starts_with("avatars/") {
+            return Ok(None);
+        }
+        self.storage.get(key).await
+    }
+
+    pub fn avatar_upload_limit(&self) -> usize {
+        self.config.avatar.max_upload_size
+    }
+
+    async fn set_avatar_url(&self, user_id: Uuid, url: Option<String>) -> Result<UserResponse, AuthError> {
+        let previous = self.db.find_user_by_id(user_id).await?;
+
+        // The old image is only deleted if it's still the one being replaced
+        let changes = ProfileChanges {
+            avatar_url: Some(url),
+            ..Default::default()
+        };
+        let user = self.db.update_profile(user_id, Some(previous.version), changes).await?;
+        self.user_cache.invalidate(user_id);
+
+        // Only clean up images we stored; external avatar URLs are left alone
+        if let Some(key) = previous.avatar_url.as_deref().and_then(|url| self.storage.key_for_url(url)) {
+            if let Err(e) = self.storage.delete(&key).await {
+                log::warn!("Failed to delete old avatar {}: {}", key, e);
+            }
+        }
+
+        Ok(user.into())
+    }
+
+    pub async fn list_backup_emails(&self, user_id: Uuid) -> Result<Vec<BackupEmailResponse>, AuthError> {
+        let emails = self.db.find_backup_emails_by_user_id(user_id).await?;
+        Ok(emails.into_iter().map(Into::into).collect())
+    }
+
+    /// Register a backup address; it is only used once the emailed link is followed
+    pub async fn add_backup_email(
+        &self,
+        user_id: Uuid,
+        data: AddBackupEmailRequest,
+        locale: &str,
+    ) -> Result<BackupEmailResponse, AuthError> {
+        let address = normalize_email(&data.email)?;
+
+        let user = self.db.find_user_by_id(user_id).await?;
+        if address.canonical.eq_ignore_ascii_case(&user.email) {
+            return Err(AuthError::ValidationError(
+                "This is already your primary email address".into(),
+            ));
+        }
+
+        let existing = self.db.find_backup_emails_by_user_id(user_id).await?;
+        if existing.len() >= MAX_BACKUP_EMAILS {
+            return Err(AuthError::ValidationError(format!(
+                "You can register at most {} backup email addresses",
+                MAX_BACKUP_EMAILS
+            )));
+        }
+
+        // Another account's primary address can't double as a backup
+        if self.db.user_exists_by_email(&address.canonical).await? {
+            return Err(AuthError::EmailExists);
+        }
+
+        let verification_token = Uuid::new_v4().to_string();
+        let email = self
+            .db
+            .create_backup_email(NewBackupEmail {
+                id: Uuid::new_v4(),
+                user_id,
+                email: address.canonical,
+                verification_token: Some(verification_token.clone()),
+                verification_sent_at: Some(Utc::now()),
+            })
+            .await?;
+
+        self.email_service
+            .send_backup_email_verification(&email.email, &verification_token, locale)
+            .await?;
+
+        Ok(email.into())
+    }
+
+    pub async fn verify_backup_email(
+        &self,
+        data: VerifyBackupEmailRequest,
+    ) -> Result<BackupEmailResponse, AuthError> {
+        let email = self.db.find_backup_email_by_token(&data.token).await?;
+
+        // Same 24 hour window as the primary verification link
+        match email.verification_sent_at {
+            Some(sent_at) if (Utc::now() - sent_at).num_seconds() > 86400 => {
+                return Err(AuthError::TokenExpired);
+            }
+            Some(_) => {}
+            None => return Err(AuthError::InvalidToken),
+        }
+
+        let email = self.db.verify_backup_email(email.id).await?;
+
+        // Tell every address, including the new one, now that it counts
+        let user = self.db.find_user_by_id(email.user_id).await?;
+        self.notify_security_event(&user, SecurityAlert::BackupEmailAdded(&email.email))
+            .await;
+
+        Ok(email.into())
+    }
+
+    pub async fn remove_backup_email(
+        &self,
+        user_id: Uuid,
+        email_id: Uuid,
+    ) -> Result<LogoutResponse, AuthError> {
+        let email = self
+            .db
+            .find_backup_emails_by_user_id(user_id)
+            .await?
+            .into_iter()
+            .find(|e| e.id == email_id)
+            .ok_or(AuthError::PermissionDenied)?;
+
+        self.db.delete_backup_email(email.id).await?;
+
+        if email.is_verified {
+            let user = self.db.find_user_by_id(user_id).await?;
+            let alert = SecurityAlert::BackupEmailRemoved(&email.email);
+            self.notify_security_event(&user, alert.clone()).await;
+            // The removed address should hear about it too
+            self.send_security_alert(&user, &email.email, &alert).await;
+        }
+
+        Ok(LogoutResponse {
+            message: "Backup email removed successfully".into(),
+        })
+    }
+
+    pub async fn get_notification_preferences(
+        &self,
+        user_id: Uuid,
+    ) -> Result<NotificationPreferencesResponse, AuthError> {
+        Ok(self.notification_preferences(user_id).await?.into())
+    }
+
+    pub async fn update_notification_preferences(
+        &self,
+        user_id: Uuid,
+        data: UpdateNotificationPreferencesRequest,
+    ) -> Result<NotificationPreferencesResponse, AuthError> {
+        let current = self.notification_preferences(user_id).await?;
+        let saved = self.db.save_notification_preferences(data.apply_to(&current)).await?;
+        Ok(saved.into())
+    }
+
+    /// The preferences an unsubscribe link is for, without signing in
+    pub async fn linked_notification_preferences(
+        &self,
+        data: NotificationLinkRequest,
+    ) -> Result<NotificationPreferencesResponse, AuthError> {
+        let (user_id, category) = self.verify_unsubscribe_link(&data.token).await?;
+        let region = self.db.region_of_user(user_id).await?;
+        let mut response: NotificationPreferencesResponse =
+            DatabaseConnection::in_region(region, self.notification_preferences(user_id)).await?.into();
+        response.unsubscribe_from = Some(category);
+        Ok(response)
+    }
+
+    /// Change preferences from an unsubscribe link, without signing in
+    pub async fn update_linked_notification_preferences(
+        &self,
+        data: LinkedPreferencesRequest,
+    ) -> Result<NotificationPreferencesResponse, AuthError> {
+        let (user_id, category) = self.verify_unsubscribe_link(&data.token).await?;
+        let region = self.db.region_of_user(user_id).await?;
+        let mut response =
+            DatabaseConnection::in_region(region, self.update_notification_preferences(user_id, data.changes())).await?;
+        response.unsubscribe_from = Some(category);
+        Ok(response)
+    }
+
+    /// Turn off the category an unsubscribe link was sent for. Links work
+    /// until they expire, so following one twice does no harm.
+    pub async fn unsubscribe(&self, data: NotificationLinkRequest) -> Result<NotificationPreferencesResponse, AuthError> {
+        let (user_id, category) = self.verify_unsubscribe_link(&data.token).await?;
+        let changes = match category {
+            NotificationCategory::SecurityAlerts => UpdateNotificationPreferencesRequest {
+                security_alerts: Some(false),
+                ..Default::default()
+            },
+            NotificationCategory::ProductEmails => UpdateNotificationPreferencesRequest {
+                product_emails: Some(false),
+                ..Default::default()
+            },
+        };
+
+        let region = self.db.region_of_user(user_id).await?;
+        let mut response = DatabaseConnection::in_region(region, self.update_notification_preferences(user_id, changes)).await?;
+        response.unsubscribe_from = Some(category);
+        Ok(response)
+    }
+
+    /// The user's own login policy and those of their organizations
+    pub async fn login_policies(&self, user_id: Uuid) -> Result<LoginPolicyOverview, AuthError> {
+        let organization_ids = self
+            .db
+            .find_user_organizations(user_id)
+            .await?
+            .into_iter()
+            .map(|organization| organization.id)
+            .collect();
+        let mut policies = self.db.find_login_policies(user_id, organization_ids).await?;
+
+        let policy = match policies.first() {
+            Some(first) if first.user_id == Some(user_id) => Some(policies.remove(0)),
+            _ => None,
+        };
+        Ok(LoginPolicyOverview {
+            policy,
+            organization_policies: policies,
+        })
+    }
+
+    /// Replace the user's own login policy
+    pub async fn save_login_policy(&self, user_id: Uuid, data: LoginPolicyRequest) -> Result<LoginPolicy, AuthError> {
+        let policy = self
+            .db
+            .save_login_policy(new_login_policy(LoginPolicyScope::User(user_id), user_id, data)?)
+            .await?;
+
+        log::info!("User {} updated their login policy", user_id);
+
+        Ok(policy)
+    }
+
+    pub async fn delete_login_policy(&self, user_id: Uuid) -> Result<LogoutResponse, AuthError> {
+        if !self.db.delete_login_policy(LoginPolicyScope::User(user_id)).await? {
+            return Err(AuthError::ValidationError("No login policy is set".into()));
+        }
+
+        log::info!("User {} removed their login policy", user_id);
+
+        Ok(LogoutResponse {
+            message: "Login policy removed".to_string(),
+        })
+    }
+
+    pub async fn list_api_keys(&self, user_id: Uuid) -> Result<Vec<ApiKeyResponse>, AuthError> {
+        let keys = self.db.find_api_keys_by_user_id(user_id).await?;
+        Ok(keys.into_iter().map(ApiKeyResponse::from).collect())
+    }
+
+    /// Create a personal access token limited to the requested scopes
+    pub async fn create_api_key(
+        &self,
+        user_id: Uuid,
+        data: CreateApiKeyRequest,
+    ) -> Result<CreatedApiKeyResponse, AuthError> {
+        let user = self.db.find_user_by_id(user_id).await?;
+        if user.is_guest {
+            return Err(AuthError::PermissionDenied);
+        }
+
+        let scopes = scopes::validate_requested(&data.scopes, user.is_admin)?;
+
+        if self.db.find_api_keys_by_user_id(user_id).await?.len() >= MAX_API_KEYS {
+            return Err(AuthError::ValidationError(format!(
+                "You can have at most {} API keys",
+                MAX_API_KEYS
+            )));
+        }
+
+        // Marked with the user's region, so requests carrying it run there
+        let generated = api_key::generate(DatabaseConnection::current_region().as_deref());
+        let key = self
+            .db
+            .create_api_key(NewApiKey {
+                id: Uuid::new_v4(),
+                user_id,
+                name: data.name.trim().to_string(),
+                key_prefix: generated.display_prefix,
+                key_hash: generated.hash,
+                scopes,
+                expires_at: data
+                    .expires_in_days
+                    .map(|days| Utc::now() + Duration::days(days as i64)),
+            })
+            .await?;
+
+        Ok(CreatedApiKeyResponse {
+            key: generated.key,
+            api_key: key.into(),
+        })
+    }
+
+    pub async fn delete_api_key(&self, user_id: Uuid, key_id: Uuid) -> Result<LogoutResponse, AuthError> {
+        let key = self
+            .db
+            .find_api_keys_by_user_id(user_id)
+            .await?
+            .into_iter()
+            .find(|k| k.id == key_id)
+            .ok_or(AuthError::PermissionDenied)?;
+
+        self.db.delete_api_key(key.id).await?;
+
+        Ok(LogoutResponse {
+            message: "API key deleted successfully".into(),
+        })
+    }
+
+    /// The caller behind an API key, as `AuthMiddleware` sees it. Counts the
+    /// request against the key's quotas and returns where it stands.
+    pub async fn authenticate_api_key(
+        &self,
+        key: &str,
+        ip: Option<String>,
+    ) -> Result<(AuthenticatedUser, Option<QuotaStatus>), AuthError> {
+        self.ensure_source_allowed(ip.as_deref())?;
+
+        let stored = self.db.find_api_key_by_hash(&api_key::hash(key)).await?;
+        if let Some(canary) = self.db.find_canary_by_api_key_id(stored.id).await? {
+            self.trip_canary(canary, "api_key", &ip, &None).await;
+            return Err(AuthError::InvalidToken);
+        }
+        if stored.is_expired() {
+            return Err(AuthError::TokenExpired);
+        }
+
+        let user = match self.db.find_user_by_id(stored.user_id).await {
+            Ok(user) => user,
+            Err(AuthError::UserNotFound) => return Err(AuthError::InvalidToken),
+            Err(err) => return Err(err),
+        };
+        if !user.is_active() {
+            return Err(AuthError::AccountDisabled { status_token: None });
+        }
+
+        let now = Utc::now();
+        let today = now.date_naive();
+        let (daily_count, monthly_count) = self
+            .db
+            .record_api_key_request(stored.id, today, quotas::month_start(today))
+            .await?;
+        let quota = quotas::check(
+            quotas::effective_quotas(&stored, &self.config.api_keys),
+            daily_count,
+            monthly_count,
+            now,
+        )?;
+
+        // Admin scopes lapse with the admin role
+        let scopes = stored
+            .scopes
+            .into_iter()
+            .filter(|scope| user.is_admin || !scope.starts_with("admin:"))
+            .collect();
+
+        let caller = AuthenticatedUser {
+            user_id: user.id,
+            is_admin: user.is_admin,
+            scope: TokenScope::ApiKey,
+            auth_time: None,
+            amr: Vec::new(),
+            scopes,
+            actor_id: None,
+        };
+
+        Ok((caller, quota))
+    }
+
+    /// A user's API keys with their quota overrides, for admins
+    pub async fn admin_list_api_keys(&self, user_id: Uuid) -> Result<Vec<ApiKeyResponse>, AuthError> {
+        self.db.find_user_by_id(user_id).await?;
+        self.list_api_keys(user_id).await
+    }
+
+    /// Requests made with a key over the last `days` days, with its effective quotas
+    pub async fn api_key_usage(&self, key_id: Uuid, days: i64) -> Result<ApiKeyUsageResponse, AuthError> {
+        let key = self.db.find_api_key_by_id(key_id).await?;
+
+        let today = Utc::now().date_naive();
+        let month_start = quotas::month_start(today);
+        let since = month_start.min(today - Duration::days(days - 1));
+        let usage = self.db.find_api_key_usage(key.id, since).await?;
+
+        let today_count = usage.iter().filter(|u| u.day == today).map(|u| u.request_count).sum();
+        let month_count = usage.iter().filter(|u| u.day >= month_start).map(|u| u.request_count).sum();
+        let (daily_quota, monthly_quota) = quotas::effective_quotas(&key, &self.config.api_keys);
+
+        Ok(ApiKeyUsageResponse {
+            api_key_id: key.id,
+            user_id: key.user_id,
+            daily_quota,
+            monthly_quota,
+            today: today_count,
+            this_month: month_count,
+            days: usage
+                .into_iter()
+                .filter(|u| u.day > today - Duration::days(days))
+                .collect(),
+        })
+    }
+
+    /// Override the default quotas for one key; unset fields fall back to the defaults
+    pub async fn update_api_key_quota(
+        &self,
+        key_id: Uuid,
+        data: UpdateApiKeyQuotaRequest,
+    ) -> Result<ApiKeyResponse, AuthError> {
+        let key = self
+            .db
+            .update_api_key_quota(key_id, data.daily_quota, data.monthly_quota)
+            .await?;
+
+        Ok(key.into())
+    }
+
+    /// Profile, second factors, sessions and to-dos for the account security page
+    pub async fn get_account_overview(&self, user_id: Uuid, flags: &Flags) -> Result<AccountOverview, AuthError> {
+        let recent = PageRequest { limit: 5, ..PageRequest::default() };
+        let (user, totp_devices, passkeys, sessions, recent_logins, recovery_codes, method_order) = futures::try_join!(
+            self.db.find_user_by_id(user_id),
+            self.list_totp_devices(user_id),
+            self.list_passkeys(user_id),
+            self.get_sessions(user_id, SessionFilter::default(), PageRequest::default()),
+            self.login_history(user_id, recent),
+            self.recovery_code_status(user_id),
+            self.db.find_mfa_method_order(user_id),
+        )?;
+        let recent_logins = recent_logins.items;
+
+        let proxy_aliases = if flags.is_enabled(feature_flags::PROXY_EMAILS) {
+            self.db
+                .find_proxy_aliases_by_user_id(user.id)
+                .await?
+                .into_iter()
+                .map(ProxyAliasResponse::from)
+                .collect()
+        } else {
+            Vec::new()
+        };
+
+        let mut methods = Vec::new();
+        if totp_devices.iter().any(|d| d.is_confirmed) {
+            methods.push(MfaMethod::Totp);
+        }
+        if !passkeys.is_empty() {
+            methods.push(MfaMethod::Passkey);
+        }
+        let methods = MfaMethod::in_order(&methods, &method_order).iter().map(|m| m.as_str()).collect();
+
+        let mut pending_actions = Vec::new();
+        if !user.is_email_verified {
+            pending_actions.push(SecurityAction::VerifyEmail);
+        }
+        if !user.mfa_enabled {
+            pending_actions.push(SecurityAction::EnableMfa);
+        }
+        if totp_devices.iter().any(|d| !d.is_confirmed) {
+            pending_actions.push(SecurityAction::ConfirmTotpDevice);
+        }
+        if passkeys.is_empty() {
+            pending_actions.push(SecurityAction::AddPasskey);
+        }
+        if user.password_expired() {
+            pending_actions.push(SecurityAction::ChangePassword);
+        }
+        if user.mfa_enabled && recovery_codes.low {
+            pending_actions.push(SecurityAction::RegenerateRecoveryCodes);
+        }
+
+        Ok(AccountOverview {
+            mfa: MfaOverview {
+                enabled: user.mfa_enabled,
+                methods,
+                totp_devices,
+                recovery_codes,
+            },
+            profile: user.into(),
+            passkeys,
+            sessions,
+            recent_logins,
+            proxy_aliases,
+            pending_actions,
+        })
+    }
+
+    pub async fn get_sessions(
+        &self,
+        user_id: Uuid,
+        filter: SessionFilter,
+        page: PageRequest,
+    ) -> Result<Page<SessionResponse>, AuthError> {
+        // Idle sessions are over even if nothing has tried to use them yet
+        if let Some(cutoff) = self.session_activity.idle_cutoff(Utc::now()) {
+            self.db.revoke_idle_sessions(user_id, cutoff).await?;
+        }
+
+        let (sessions, total) = self
+            .db
+            .find_sessions_by_user_id(user_id, &filter, &page)
+            .await?;
+        
+        let response = Page::new(sessions, total, &page).map(|s| {
+            let mut sr: SessionResponse = s.into();
+            // Mark current session - this requires comparing with the actual session token
+            // which we don't have here without a context
+            sr.is_current = false;
+            sr
+        });
+        
+        Ok(response)
+    }
+
+    pub async fn revoke_session(
+        &self,
+        user_id: Uuid,
+        session_id: Uuid,
+    ) -> Result<LogoutResponse, AuthError> {
+        // Find session
+        let session = self.db.find_session_by_id(session_id).await?;
+
+        // Check if user owns the session
+        if session.user_id != user_id {
+            return Err(AuthError::PermissionDenied);
+        }
+
+        // Revoke session, and the access tokens already issued with it
+        self.db.revoke_session(session.id).await?;
+        self.token_revocations.revoke(&[session.id]).await?;
+
+        Ok(LogoutResponse {
+            message: "Session revoked successfully".into(),
+        })
+    }
+
+    /// Whether OAuth client credentials are good: a registered client, with
+    /// its secret if it's confidential. Public clients have no secret to give.
+    pub async fn authenticate_oauth_client(&self, client_id: &str, client_secret: Option<&str>) -> Result<bool, AuthError> {
+        let Some(client) = self.db.find_client_application(client_id).await? else {
+            return Ok(false);
+        };
+
+        Ok(match (&client.client_secret_hash, client_secret) {
+            // Digests, so how long the comparison takes says nothing about the secret
+            (Some(hash), Some(given)) => client_secret_hash(given) == *hash,
+            (Some(_), None) => false,
+            (None, given) => given.is_none(),
+        })
+    }
+
+    /// Revoke a refresh or access token for an OAuth client (RFC 7009). Tokens
+    /// that are unknown, expired or were issued to another client are ignored,
+    /// so the answer says nothing about them; `hint` only decides which kind
+    /// is tried first.
+    pub async fn revoke_oauth_token(
+        &self,
+        client_id: &str,
+        token: &str,
+        hint: Option<TokenTypeHint>,
+    ) -> Result<(), AuthError> {
+        let region = self.region_of_token(token).await?;
+        let revoked = DatabaseConnection::in_region(region, async {
+            Ok::<_, AuthError>(if hint == Some(TokenTypeHint::AccessToken) {
+                self.revoke_oauth_access_token(client_id, token).await?
+                    || self.revoke_oauth_refresh_token(client_id, token).await?
+            } else {
+                self.revoke_oauth_refresh_token(client_id, token).await?
+                    || self.revoke_oauth_access_token(client_id, token).await?
+            })
+        })
+        .await?;
+
+        if revoked {
+            log::info!("Client {} revoked a token", client_id);
+        }
+        Ok(())
+    }
+
+    // Refresh tokens are all issued to the deployment's own audience. Revoking
+    // one ends its session, and with it the access tokens issued alongside.
+    async fn revoke_oauth_refresh_token(&self, client_id: &str, token: &str) -> Result<bool, AuthError> {
+        if client_id != self.config.jwt.audience {
+            return Ok(false);
+        }
+
+        let session = match self.db.find_session_by_token(token).await {
+            Ok(session) => session,
+            Err(AuthError::InvalidToken) => return Ok(false),
+            Err(e) => return Err(e),
+        };
+        self.db.revoke_session(session.id).await?;
+        self.token_revocations.revoke(&[session.id]).await?;
+        Ok(true)
+    }
+
+    // An access token is revoked on its own by `jti`; tokens issued before
+    // they carried one can only go with their whole session
+    async fn revoke_oauth_access_token(&self, client_id: &str, token: &str) -> Result<bool, AuthError> {
+        let expected = TokenAudience::new(self.config.jwt.issuer.clone(), &self.config.jwt.accepted_audiences);
+        let claims = match decode_jwt_with_secret::<JwtClaims>(token, &self.config.jwt.secret, &expected) {
+            Ok(claims) if claims.aud == client_id => claims,
+            _ => return Ok(false),
+        };
+
+        match claims.jti.or(claims.sid) {
+            Some(id) => {
+                self.token_revocations.revoke(&[id]).await?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Rename and/or pin one of the user's sessions
+    pub async fn update_session(
+        &self,
+        user_id: Uuid,
+        session_id: Uuid,
+        data: UpdateSessionRequest,
+    ) -> Result<SessionResponse, AuthError> {
+        let session = self.db.find_session_by_id(session_id).await?;
+
+        if session.user_id != user_id {
+            return Err(AuthError::PermissionDenied);
+        }
+        if session.is_revoked {
+            return Err(AuthError::InvalidToken);
+        }
+
+        let mut changes = SessionChanges {
+            name: data
+                .name
+                .map(|name| Some(name.trim().to_string()).filter(|name| !name.is_empty())),
+            ..Default::default()
+        };
+
+        // Pinning extends the current refresh token; unpinning takes effect at the next refresh
+        if let Some(pinned) = data.pinned {
+            changes.is_pinned = Some(pinned);
+            if pinned && !session.is_pinned {
+                changes.expires_at = Some(Utc::now() + self.refresh_token_lifetime(true, session.lifetime()));
+            }
+        }
+
+        let session = self.db.update_session(session.id, changes).await?;
+
+        Ok(session.into())
+    }
+
+    /// Stop suggesting passkey enrollment to this user; returns the prompt's
+    /// state with `dismissed_at` set
+    pub async fn dismiss_passkey_prompt(&self, user_id: Uuid) -> Result<PasskeyPromptState, AuthError> {
+        self.db.dismiss_passkey_prompt(user_id).await?;
+
+        self.db
+            .find_passkey_prompt(user_id)
+            .await?
+            .ok_or_else(|| AuthError::InternalServerError("Passkey prompt state missing after dismissal".into()))
+    }
+
+    /// Lock the caller's own account at once, e.g. when they think someone
+    /// else has signed in to it
+    pub async fn lock_own_account(&self, user_id: Uuid, locale: &str) -> Result<LogoutResponse, AuthError> {
+        let user = self.db.find_user_by_id(user_id).await?;
+        self.lock_account_now(user, "session").await?;
+
+        Ok(LogoutResponse {
+            message: self.translator.text(locale, "account-locked", None),
+        })
+    }
+
+    /// Email a link that locks the account, for owners who can't sign in
+    pub async fn account_lock_request(
+        &self,
+        data: AccountLockRequest,
+        locale: &str,
+    ) -> Result<PasswordResetResponse, AuthError> {
+        // Unknown addresses get the same answer, after about as long
+        let floor = ResponseFloor::start(&self.config.response_timing);
+        let result = self.send_account_lock_link(data, locale).await;
+        floor.wait().await;
+        result
+    }
+
+    async fn send_account_lock_link(
+        &self,
+        data: AccountLockRequest,
+        locale: &str,
+    ) -> Result<PasswordResetResponse, AuthError> {
+        self.email_throttle.acquire(&data.email, ThrottledEmail::AccountLock).await?;
+
+        let response = PasswordResetResponse {
+            message: self.translator.text(locale, "account-lock-requested", None),
+        };
+        let user = match self.find_user_by_recovery_email(&data.email).await? {
+            Some(user) if user.is_active() => user,
+            _ => return Ok(response),
+        };
+
+        let ttl = Duration::seconds(self.config.action_tokens.account_lock_ttl as i64);
+        let claims = ActionClaims::new(ActionPurpose::AccountLock, user.id, ttl)
+            .with_data(serde_json::json!({ "sent_to": data.email }));
+        let token = self.action_tokens.issue(&claims)?;
+
+        self.lookup_email_service()
+            .send_account_lock_email(&data.email, &token, locale)
+            .await?;
+
+        Ok(response)
+    }
+
+    /// Lock the account from an emailed link; needs no session
+    pub async fn lock_account_from_link(
+        &self,
+        data: LockAccountRequest,
+        locale: &str,
+    ) -> Result<PasswordResetResponse, AuthError> {
+        let region = self.region_of_link(&data.token, ActionPurpose::AccountLock).await?;
+        DatabaseConnection::in_region(region, self.lock_account_from_link_in_region(data, locale)).await
+    }
+
+    async fn lock_account_from_link_in_region(
+        &self,
+        data: LockAccountRequest,
+        locale: &str,
+    ) -> Result<PasswordResetResponse, AuthError> {
+        let claims = self
+            .action_tokens
+            .redeem_within(
+                &data.token,
+                ActionPurpose::AccountLock,
+                Duration::seconds(self.config.action_tokens.account_lock_ttl as i64),
+            )
+            .await?;
+        let user = self.db.find_user_by_id(claims.sub).await?;
+        self.lock_account_now(user, "link").await?;
+
+        Ok(PasswordResetResponse {
+            message: self.translator.text(locale, "account-locked", None),
+        })
+    }
+
+    // Accounts that are already locked, suspended or banned are left as they are
+    async fn lock_account_now(&self, user: User, via: &str) -> Result<(), AuthError> {
+        if !user.is_active() {
+            return Ok(());
+        }
+
+        self.db
+            .set_account_status(
+                user.id,
+                None,
+                AccountStatus::Locked,
+                "Locked by the account owner",
+                Some(user.id),
+                status_changed_event(user.id, AccountStatus::Locked, Some(user.id)),
+            )
+            .await?;
+
+        // Pinned sessions and remembered devices go too
+        self.db.revoke_all_sessions(user.id, true).await?;
+        self.revoke_access_tokens(user.id).await?;
+        self.db.delete_trusted_devices_by_user_id(user.id).await?;
+        self.user_cache.invalidate(user.id);
+
+        log::info!("User {} locked their account via {}", user.id, via);
+        self.notify_security_event(&user, SecurityAlert::AccountLocked).await;
+        Ok(())
+    }
+
+    /// Why the account is locked, and where its appeal stands
+    pub async fn get_account_status(&self, user_id: Uuid) -> Result<AccountStatusResponse, AuthError> {
+        let user = self.db.find_user_by_id(user_id).await?;
+        let appeal = self.db.find_latest_account_appeal(user_id).await?;
+        let status = user.account_status();
+        let appeal_pending = appeal.as_ref().map_or(false, |a| a.status == "pending");
+
+        Ok(AccountStatusResponse {
+            status,
+            reason: user.status_reason,
+            changed_at: user.status_changed_at,
+            can_appeal: status.can_appeal() && !appeal_pending,
+            appeal,
+        })
+    }
+
+    pub async fn submit_appeal(
+        &self,
+        user_id: Uuid,
+        data: AppealRequest,
+    ) -> Result<AccountAppeal, AuthError> {
+        let user = self.db.find_user_by_id(user_id).await?;
+        if !user.account_status().can_appeal() {
+            return Err(AuthError::ValidationError(
+                "Only suspended or banned accounts can be appealed".into(),
+            ));
+        }
+
+        let appeal = self
+            .db
+            .create_account_appeal(NewAccountAppeal {
+                id: Uuid::new_v4(),
+                user_id,
+                message: data.message.trim().to_string(),
+            })
+            .await?;
+
+        log::info!("User {} appealed their {} account status", user_id, user.status);
+
+        Ok(appeal)
+    }
+
+    /// Suspend, ban, schedule for deletion or reactivate an account. The reason is
+    /// kept in the account's status history and shown to the user.
+    pub async fn update_account_status(
+        &self,
+        admin_id: Uuid,
+        user_id: Uuid,
+        data: UpdateAccountStatusRequest,
+        expected_version: Option<i32>,
+    ) -> Result<UserResponse, AuthError> {
+        if admin_id == user_id {
+            return Err(AuthError::ValidationError(
+                "Admins can't change the status of their own account".into(),
+            ));
+        }
+
+        let reason = data.reason.trim();
+        if reason.is_empty() {
+            return Err(AuthError::ValidationError("A reason is required".into()));
+        }
+
+        let user = self
+            .db
+            .set_account_status(
+                user_id,
+                expected_version,
+                data.status,
+                reason,
+                Some(admin_id),
+                status_changed_event(user_id, data.status, Some(admin_id)),
+            )
+            .await?;
+
+        // Locking an account signs it out everywhere
+        if data.status == AccountStatus::Active {
+            self.user_cache.invalidate(user_id);
+        } else {
+            self.db.revoke_all_sessions(user_id, true).await?;
+            self.revoke_access_tokens(user_id).await?;
+        }
+
+        log::info!(
+            "Admin {} set the status of user {} to {}: {}",
+            admin_id,
+            user_id,
+            data.status.as_str(),
+            reason
+        );
+
+        Ok(user.into())
+    }
+
+    pub async fn account_status_history(
+        &self,
+        user_id: Uuid,
+        page: PageRequest,
+    ) -> Result<Page<AccountStatusEvent>, AuthError> {
+        let (events, total) = self.db.find_account_status_events(user_id, &page).await?;
+
+        Ok(Page::new(events, total, &page))
+    }
+
+    /// The account's logins, from the `user.logged_in` events kept for the
+    /// audit retention period
+    pub async fn login_history(&self, user_id: Uuid, page: PageRequest) -> Result<Page<RecentLogin>, AuthError> {
+        let filter = AuditEventFilter {
+            user_id: Some(user_id),
+            event_type: Some(EventType::LoggedIn.as_str().to_string()),
+        };
+        let (events, total) = self.db.find_outbox_events(&filter, &page).await?;
+
+        Ok(Page::new(events, total, &page).map(RecentLogin::from))
+    }
+
+    /// An account as admins see it, with its current risk score
+    /// Users whose username, email or display name contains `query`, or whose
+    /// id it is, for the admin user list
+    pub async fn search_users(
+        &self,
+        query: Option<String>,
+        filter: UserFilter,
+        page: PageRequest,
+    ) -> Result<Page<UserResponse>, AuthError> {
+        let (users, total) = self.db.search_users(query.as_deref(), &filter, &page).await?;
+
+        Ok(Page::new(users, total, &page).map(UserResponse::from))
+    }
+
+    pub async fn admin_get_user(&self, user_id: Uuid) -> Result<AdminUserResponse, AuthError> {
+        let user = self.db.find_user_by_id(user_id).await?;
+        let signals = self
+            .db
+            .find_account_risk_signals(user.id, self.account_risk.window_start(Utc::now()))
+            .await?;
+        let score = AccountRisk::score(&signals);
+
+        Ok(AdminUserResponse {
+            risk: AccountRiskResponse {
+                score,
+                window_days: self.account_risk.window_days(),
+                actions: self.account_risk.actions_at(score),
+                mfa_reenrollment_required: user.mfa_reenrollment_required,
+                signals,
+            },
+            user: user.into(),
+        })
+    }
+
+    /// Create an account for someone else, who chooses its password from the
+    /// emailed activation link
+    pub async fn invite_user(
+        &self,
+        admin_id: Uuid,
+        data: InviteUserRequest,
+        locale: &str,
+    ) -> Result<UserResponse, AuthError> {
+        let user = self
+            .create_pending_user(&data.email, data.username.as_deref(), "admin_invite")
+            .await?;
+        self.email_throttle.record(&user.email, ThrottledEmail::Activation).await?;
+        let activation_token = self.activation_token(&user)?;
+        self.email_service
+            .send_activation_email(&user.email, &activation_token, locale)
+            .await?;
+
+        log::info!("Admin {} invited user {}", admin_id, user.id);
+
+        Ok(user.into())
+    }
+
+    /// Plant a canary account, and an API key on it if asked. Both look like
+    /// any other credential; using either only raises the alarm.
+    pub async fn create_canary(
+        &self,
+        admin_id: Uuid,
+        data: CreateCanaryRequest,
+    ) -> Result<CreatedCanaryResponse, AuthError> {
+        validate_username(&data.username)?;
+        let address = normalize_email(&data.email)?;
+
+        if self.db.user_exists_by_username(&data.username).await? {
+            return Err(AuthError::UsernameExists);
+        }
+        if self.db.user_exists_by_email(&address.canonical).await? {
+            return Err(AuthError::EmailExists);
+        }
+
+        let label = data.label.trim().to_string();
+        let new_user = NewUser {
+            id: Uuid::new_v4(),
+            username: data.username,
+            email: address.canonical.clone(),
+            password_hash: hash_password(&data.password)?,
+            is_email_verified: true,
+            email_verification_token: None,
+            email_verification_sent_at: None,
+            is_admin: false,
+            password_expires_at: None,
+            is_guest: false,
+            activation_pending: false,
+            email_display: address.display_if_different(),
+        };
+        let event = user_created_event(&new_user, "canary");
+        let user = self.db.create_user(new_user, event).await?;
+
+        let mut api_key = None;
+        let mut api_key_id = None;
+        if data.with_api_key {
+            let generated = api_key::generate(DatabaseConnection::current_region().as_deref());
+            let key = self
+                .db
+                .create_api_key(NewApiKey {
+                    id: Uuid::new_v4(),
+                    user_id: user.id,
+                    name: label.clone(),
+                    key_prefix: generated.display_prefix,
+                    key_hash: generated.hash,
+                    scopes: Vec::new(),
+                    expires_at: None,
+                })
+                .await?;
+            api_key_id = Some(key.id);
+            api_key = Some(generated.key);
+        }
+
+        let canary = self
+            .db
+            .create_canary_credential(NewCanaryCredential {
+                id: Uuid::new_v4(),
+                user_id: user.id,
+                api_key_id,
+                label,
+            })
+            .await?;
+
+        log::info!("Admin {} planted canary {} ({})", admin_id, canary.id, canary.label);
+
+        Ok(CreatedCanaryResponse { canary, api_key })
+    }
+
+    pub async fn list_canaries(&self) -> Result<CanaryListResponse, AuthError> {
+        Ok(CanaryListResponse {
+            canaries: self.db.find_canary_credentials().await?,
+            blocked_sources: self.source_blocklist.blocked(Utc::now()),
+        })
+    }
+
+    /// Retire a canary. Its API key is deleted and its account banned, so the
+    /// planted credentials stop working without freeing the username.
+    pub async fn delete_canary(&self, admin_id: Uuid, canary_id: Uuid) -> Result<LogoutResponse, AuthError> {
+        let canary = self
+            .db
+            .find_canary_credentials()
+            .await?
+            .into_iter()
+            .find(|c| c.id == canary_id)
+            .ok_or_else(|| AuthError::ValidationError("Canary not found".into()))?;
+
+        if let Some(key_id) = canary.api_key_id {
+            self.db.delete_api_key(key_id).await?;
+        }
+        self.db
+            .set_account_status(
+                canary.user_id,
+                None,
+                AccountStatus::Banned,
+                "Canary retired",
+                Some(admin_id),
+                status_changed_event(canary.user_id, AccountStatus::Banned, Some(admin_id)),
+            )
+            .await?;
+        self.db.delete_canary_credential(canary.id).await?;
+
+        log::info!("Admin {} retired canary {} ({})", admin_id, canary.id, canary.label);
+
+        Ok(LogoutResponse {
+            message: "Canary retired".into(),
+        })
+    }
+
+    /// Lift a canary block on a source before it runs out
+    pub async fn unblock_source(&self, admin_id: Uuid, ip: &str) -> Result<LogoutResponse, AuthError> {
+        if !self.source_blocklist.unblock(ip) {
+            return Err(AuthError::ValidationError("Source is not blocked".into()));
+        }
+
+        log::info!("Admin {} unblocked {}", admin_id, ip);
+
+        Ok(LogoutResponse {
+            message: "Source unblocked".into(),
+        })
+    }
+
+    /// Any user's sessions, as they'd see them
+    pub async fn admin_user_sessions(
+        &self,
+        user_id: Uuid,
+        filter: SessionFilter,
+        page: PageRequest,
+    ) -> Result<Page<SessionResponse>, AuthError> {
+        self.db.find_user_by_id(user_id).await?;
+        self.get_sessions(user_id, filter, page).await
+    }
+
+    pub async fn admin_revoke_session(
+        &self,
+        admin_id: Uuid,
+        user_id: Uuid,
+        session_id: Uuid,
+    ) -> Result<LogoutResponse, AuthError> {
+        let response = self.revoke_session(user_id, session_id).await?;
+        log::info!("Admin {} revoked session {} of user {}", admin_id, session_id, user_id);
+
+        Ok(response)
+    }
+
+    /// Sign a user out everywhere, pinned sessions and trusted devices included
+    pub async fn admin_revoke_all_sessions(&self, admin_id: Uuid, user_id: Uuid) -> Result<LogoutResponse, AuthError> {
+        self.db.find_user_by_id(user_id).await?;
+        self.db.revoke_all_sessions(user_id, true).await?;
+        self.revoke_access_tokens(user_id).await?;
+        self.db.delete_trusted_devices_by_user_id(user_id).await?;
+
+        log::info!("Admin {} revoked every session of user {}", admin_id, user_id);
+
+        Ok(LogoutResponse {
+            message: "All sessions revoked".into(),
+        })
+    }
+
+    /// A token letting an admin act as a user, limited to the scopes asked for
+    /// and recorded in the audit log. It has no authentication time, so routes
+    /// needing a recent sign-in refuse it, and it can't be refreshed.
+    pub async fn issue_delegated_token(
+        &self,
+        admin_id: Uuid,
+        user_id: Uuid,
+        data: DelegatedTokenRequest,
+    ) -> Result<DelegatedTokenResponse, AuthError> {
+        if admin_id == user_id {
+            return Err(AuthError::ValidationError("Delegated tokens are for acting as another user".into()));
+        }
+
+        let user = self.db.find_user_by_id(user_id).await?;
+        if !user.is_active() {
+            return Err(AuthError::AccountDisabled { status_token: None });
+        }
+
+        // Never admin scopes, even for an admin's account
+        let scopes = scopes::validate_requested(&data.scopes, false)?;
+        let expires_in = data.expires_in.unwrap_or(self.config.delegation.default_ttl);
+        if expires_in > self.config.delegation.max_ttl {
+            return Err(AuthError::ValidationError(format!(
+                "Delegated tokens last at most {} seconds",
+                self.config.delegation.max_ttl
+            )));
+        }
+
+        let token_id = Uuid::new_v4();
+        let now = Utc::now();
+        let expires_at = now + Duration::seconds(expires_in as i64);
+        let claims = JwtClaims {
+            sub: user.id,
+            iss: self.config.jwt.issuer.clone(),
+            aud: self.config.jwt.audience.clone(),
+            exp: expires_at.timestamp() as usize,
+            iat: now.timestamp() as usize,
+            is_admin: false,
+            token_version: user.token_version,
+            // Only routes that name a permission scope take it, so the admin
+            // can't change the password, second factors or credentials
+            scope: TokenScope::Delegated,
+            auth_time: None,
+            amr: Vec::new(),
+            aal: None,
+            scopes: scopes.clone(),
+            cnf: None,
+            sid: None,
+            jti: Some(token_id),
+            act: Some(Actor { sub: admin_id }),
+        };
+        let access_token = create_jwt(&claims, &self.config.jwt.secret)?;
+
+        let event = NewOutboxEvent::new(
+            EventType::DelegatedTokenIssued,
+            user.id,
+            serde_json::json!({
+                "actor_id": admin_id,
+                "token_id": token_id,
+                "scopes": scopes,
+                "expires_at": expires_at,
+                "reason": data.reason.trim(),
+            }),
+        );
+        self.db.commit(UnitOfWork::new().event(event)).await?;
+
+        log::info!("Admin {} took a token acting as user {} until {}", admin_id, user.id, expires_at);
+
+        Ok(DelegatedTokenResponse {
+            access_token,
+            token_type: "Bearer".into(),
+            expires_in,
+            expires_at,
+            token_id,
+            scopes,
+        })
+    }
+
+    /// Cut a delegated token off before it expires
+    pub async fn revoke_delegated_token(
+        &self,
+        admin_id: Uuid,
+        user_id: Uuid,
+        token_id: Uuid,
+    ) -> Result<LogoutResponse, AuthError> {
+        self.db.find_user_by_id(user_id).await?;
+        self.token_revocations.revoke(&[token_id]).await?;
+
+        let event = NewOutboxEvent::new(
+            EventType::DelegatedTokenRevoked,
+            user_id,
+            serde_json::json!({ "actor_id": admin_id, "token_id": token_id }),
+        );
+        self.db.commit(UnitOfWork::new().event(event)).await?;
+
+        Ok(LogoutResponse {
+            message: "Delegated token revoked".into(),
+        })
+    }
+
+    /// Accounts and IPs refusing password logins after repeated failures
+    pub async fn lockouts(&self) -> Result<LockoutsResponse, AuthError> {
+        let accounts = self.db.find_locked_users(Utc::now()).await?;
+
+        Ok(LockoutsResponse {
+            accounts: accounts.into_iter().map(LockedAccount::from).collect(),
+            ips: self
+                .brute_force
+                .locked_ips()
+                .into_iter()
+                .map(|(ip, seconds_left)| LockedIp { ip, seconds_left })
+                .collect(),
+        })
+    }
+
+    /// Lift an account's lockout before it runs out; its failures start over
+    pub async fn unlock_account(&self, admin_id: Uuid, user_id: Uuid) -> Result<LogoutResponse, AuthError> {
+        let user = self.db.find_user_by_id(user_id).await?;
+        if !user.locked_until.map_or(false, |until| until > Utc::now()) {
+            return Err(AuthError::ValidationError("Account is not locked".into()));
+        }
+
+        self.db.clear_failed_logins(user.id).await?;
+        self.user_cache.invalidate(user.id);
+
+        log::info!("Admin {} unlocked user {}", admin_id, user.id);
+
+        Ok(LogoutResponse {
+            message: "Account unlocked".into(),
+        })
+    }
+
+    pub async fn unlock_ip(&self, admin_id: Uuid, ip: &str) -> Result<LogoutResponse, AuthError> {
+        if !self.brute_force.unlock_ip(ip) {
+            return Err(AuthError::ValidationError("IP is not locked".into()));
+        }
+
+        log::info!("Admin {} unlocked {}", admin_id, ip);
+
+        Ok(LogoutResponse {
+            message: "IP unlocked".into(),
+        })
+    }
+
+    /// Auth events as published to subscribers, newest first by default
+    pub async fn audit_events(
+        &self,
+        filter: AuditEventFilter,
+        page: PageRequest,
+    ) -> Result<Page<OutboxEvent>, AuthError> {
+        let (events, total) = self.db.find_outbox_events(&filter, &page).await?;
+
+        Ok(Page::new(events, total, &page))
+    }
+
+    pub async fn list_feature_flags(&self) -> Result<Vec<FeatureFlag>, AuthError> {
+        self.db.find_feature_flags().await
+    }
+
+    /// Create or replace a flag. Other instances pick it up once their cached
+    /// flags expire.
+    pub async fn save_feature_flag(
+        &self,
+        admin_id: Uuid,
+        key: &str,
+        data: FeatureFlagRequest,
+    ) -> Result<FeatureFlag, AuthError> {
+        let valid_key = (1..=64).contains(&key.len())
+            && key.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_');
+        if !valid_key {
+            return Err(AuthError::ValidationError(
+                "Flag keys are 1-64 lowercase letters, digits and underscores".into(),
+            ));
+        }
+        for organization_id in &data.organization_ids {
+            if self.db.find_organization_by_id(*organization_id).await?.is_none() {
+                return Err(AuthError::ValidationError(format!("Organization {} not found", organization_id)));
+            }
+        }
+
+        let flag = self
+            .db
+            .save_feature_flag(NewFeatureFlag {
+                key: key.to_string(),
+                description: data.description.filter(|d| !d.trim().is_empty()),
+                enabled: data.enabled,
+                rollout_percent: data.rollout_percent.unwrap_or(100),
+                organization_ids: data.organization_ids,
+                updated_by: Some(admin_id),
+            })
+            .await?;
+        self.feature_flags.invalidate();
+
+        log::info!(
+            "Admin {} set feature flag {}: enabled={} rollout={}% organizations={}",
+            admin_id,
+            flag.key,
+            flag.enabled,
+            flag.rollout_percent,
+            flag.organization_ids.len()
+        );
+        Ok(flag)
+    }
+
+    /// Remove a flag, so the code's default applies again
+    pub async fn delete_feature_flag(&self, admin_id: Uuid, key: &str) -> Result<LogoutResponse, AuthError> {
+        if !self.db.delete_feature_flag(key).await? {
+            return Err(AuthError::ValidationError("Feature flag not found".into()));
+        }
+        self.feature_flags.invalidate();
+
+        log::info!("Admin {} deleted feature flag {}", admin_id, key);
+
+        Ok(LogoutResponse {
+            message: "Feature flag deleted".into(),
+        })
+    }
+
+    pub async fn list_client_applications(&self) -> Result<Vec<ClientApplication>, AuthError> {
+        self.db.find_client_applications().await
+    }
+
+    pub async fn get_client_application(&self, client_id: &str) -> Result<ClientApplication, AuthError> {
+        self.db
+            .find_client_application(client_id)
+            .await?
+            .ok_or_else(|| AuthError::ValidationError("Client not found".into()))
+    }
+
+    /// Register a client, or replace its registration. Sessions already
+    /// started for it keep to the new policy from their next refresh.
+    pub async fn save_client_application(
+        &self,
+        admin_id: Uuid,
+        client_id: &str,
+        data: ClientApplicationRequest,
+    ) -> Result<SavedClientApplication, AuthError> {
+        let valid_id = (1..=64).contains(&client_id.len())
+            && client_id
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || matches!(c, '-' | '_' | '.'));
+        if !valid_id {
+            return Err(AuthError::ValidationError(
+                "Client ids are 1-64 lowercase letters, digits, dots, dashes and underscores".into(),
+            ));
+        }
+
+        let mut redirect_uris: Vec<String> = Vec::new();
+        for redirect_uri in data.redirect_uris {
+            validate_redirect_uri(&redirect_uri)?;
+            if !redirect_uris.contains(&redirect_uri) {
+                redirect_uris.push(redirect_uri);
+            }
+        }
+        let mut allowed_grants: Vec<String> = Vec::new();
+        for grant in data.allowed_grants {
+            if !GRANT_TYPES.contains(&grant.as_str()) {
+                return Err(AuthError::ValidationError(format!(
+                    "Unknown grant type {}; expected one of {}",
+                    grant,
+                    GRANT_TYPES.join(", ")
+                )));
+            }
+            if !allowed_grants.contains(&grant) {
+                allowed_grants.push(grant);
+            }
+        }
+        // Shown on pages served over https, where an http logo would be blocked
+        if let Some(logo_url) = &data.logo_url {
+            validate_http_url(logo_url)?;
+            if !logo_url.starts_with("https://") {
+                return Err(AuthError::ValidationError("The logo must be served over https".into()));
+            }
+        }
+
+        // A confidential client keeps its secret until it's rotated
+        let existing_hash = match self.db.find_client_application(client_id).await? {
+            Some(existing) if !data.rotate_secret => existing.client_secret_hash,
+            _ => None,
+        };
+        let (client_secret, client_secret_hash) = match existing_hash {
+            Some(hash) if data.confidential => (None, Some(hash)),
+            _ if data.confidential => {
+                let secret: String = rand::thread_rng()
+                    .sample_iter(&Alphanumeric)
+                    .take(40)
+                    .map(char::from)
+                    .collect();
+                let hash = client_secret_hash(&secret);
+                (Some(secret), Some(hash))
+            }
+            _ => (None, None),
+        };
+
+        let client = self
+            .db
+            .save_client_application(NewClientApplication {
+                client_id: client_id.to_string(),
+                name: data.name.trim().to_string(),
+                redirect_uris,
+                allowed_grants,
+                access_token_ttl: data.access_token_ttl,
+                refresh_token_ttl: data.refresh_token_ttl,
+                logo_url: data.logo_url,
+                updated_by: Some(admin_id),
+                first_party: data.first_party,
+                client_secret_hash,
+            })
+            .await?;
+
+        log::info!(
+            "Admin {} registered client {}: redirect_uris={} grants={} first_party={} confidential={} new_secret={}",
+            admin_id,
+            client.client_id,
+            client.redirect_uris.len(),
+            client.allowed_grants.join(","),
+            client.first_party,
+            client.is_confidential(),
+            client_secret.is_some()
+        );
+        Ok(SavedClientApplication { client, client_secret })
+    }
+
+    /// Remove a client, signing out every session started for it
+    pub async fn delete_client_application(&self, admin_id: Uuid, client_id: &str) -> Result<LogoutResponse, AuthError> {
+        self.get_client_application(client_id).await?;
+
+        let revoked = self.db.revoke_client_sessions(client_id, None).await?;
+        self.token_revocations.revoke(&revoked).await?;
+        self.db.delete_client_application(client_id).await?;
+
+        log::info!("Admin {} deleted client {}, revoking {} sessions", admin_id, client_id, revoked.len());
+
+        Ok(LogoutResponse {
+            message: "Client deleted".into(),
+        })
+    }
+
+    /// The client a hosted sign-in was started for, if it may sign users in
+    /// there and `redirect_uri` is one it registered
+    pub async fn client_for_redirect(&self, client_id: &str, redirect_uri: &str) -> Result<ClientApplication, AuthError> {
+        match self.db.find_client_application(client_id).await? {
+            Some(client) if client.allows_redirect_uri(redirect_uri) && client.allows_grant(GRANT_PASSWORD) => Ok(client),
+            Some(client) if client.allows_redirect_uri(redirect_uri) => Err(AuthError::ValidationError(
+                "This client may not sign users in with a password".into(),
+            )),
+            _ => Err(AuthError::ValidationError("Unknown client or redirect URI".into())),
+        }
+    }
+
+    /// Scopes a client asked for in a hosted sign-in's `scope`, checked
+    /// against those users can grant. `None` leaves a first-party client with
+    /// the user's own scopes; third-party clients must always ask.
+    pub fn requested_client_scopes(&self, client: &ClientApplication, scope: &str) -> Result<Option<Vec<String>>, AuthError> {
+        let requested: Vec<String> = scope.split_whitespace().map(str::to_string).collect();
+        if requested.is_empty() && client.first_party {
+            return Ok(None);
+        }
+        // Never admin scopes, even for an admin's account
+        scopes::validate_requested(&requested, false).map(Some)
+    }
+
+    /// Hold a session just started on the hosted pages to the client's
+    /// policy, and to `scopes` when given. Returns the access token cut down
+    /// to match, and how long it lasts.
+    pub async fn apply_client_policy(
+        &self,
+        client: &ClientApplication,
+        scopes: Option<&[String]>,
+        access_token: &str,
+        refresh_token: &str,
+    ) -> Result<(String, u64), AuthError> {
+        let region = self.region_of_token(refresh_token).await?;
+        DatabaseConnection::in_region(region, async {
+            let session = self.db.find_session_by_token(refresh_token).await?;
+
+            let mut changes = SessionChanges {
+                client_id: Some(Some(client.client_id.clone())),
+                scopes: Some(scopes.map(<[String]>::to_vec)),
+                ..Default::default()
+            };
+            if let Some(ttl) = client.refresh_token_ttl {
+                changes.expires_at = Some(session.expires_at.min(session.created_at + Duration::seconds(ttl as i64)));
+            }
+            self.db.update_session(session.id, changes).await
+        })
+        .await?;
+
+        self.client_access_token(client.access_token_ttl, scopes, access_token)
+    }
+
+    /// Whether the user has to be asked before the client gets `scopes`
+    pub async fn needs_client_consent(
+        &self,
+        user_id: Uuid,
+        client: &ClientApplication,
+        scopes: Option<&[String]>,
+    ) -> Result<bool, AuthError> {
+        let scopes = match scopes {
+            Some(scopes) if !client.first_party => scopes,
+            _ => return Ok(false),
+        };
+
+        let region = self.db.region_of_user(user_id).await?;
+        let consent =
+            DatabaseConnection::in_region(region, self.db.find_client_consent(user_id, &client.client_id)).await?;
+        Ok(!consent.map_or(false, |consent| consent.covers(scopes)))
+    }
+
+    /// Record the user's answer on the consent screen for the session
+    /// `access_token` was issued with. Allowing adds `scopes` to those the
+    /// client already had; declining signs the new session out.
+    pub async fn decide_client_consent(
+        &self,
+        client: &ClientApplication,
+        scopes: &[String],
+        access_token: &str,
+        allow: bool,
+    ) -> Result<(), AuthError> {
+        let expected = TokenAudience::new(self.config.jwt.issuer.clone(), &self.config.jwt.accepted_audiences);
+        let claims = decode_jwt_with_secret::<JwtClaims>(access_token, &self.config.jwt.secret, &expected)?;
+        let region = self.db.region_of_user(claims.sub).await?;
+        DatabaseConnection::in_region(region, self.record_client_consent(client, scopes, claims, allow)).await
+    }
+
+    async fn record_client_consent(
+        &self,
+        client: &ClientApplication,
+        scopes: &[String],
+        claims: JwtClaims,
+        allow: bool,
+    ) -> Result<(), AuthError> {
+        let session = self.db.find_session_by_id(claims.sid.ok_or(AuthError::InvalidToken)?).await?;
+        if session.is_revoked || session.user_id != claims.sub || session.client_id.as_deref() != Some(&client.client_id) {
+            return Err(AuthError::InvalidToken);
+        }
+
+        if !allow {
+            self.db.revoke_session(session.id).await?;
+            return self.token_revocations.revoke(&[session.id]).await;
+        }
+
+        let mut granted = self
+            .db
+            .find_client_consent(session.user_id, &client.client_id)
+            .await?
+            .map(|consent| consent.scopes)
+            .unwrap_or_default();
+        granted.extend(scopes.iter().cloned());
+        granted.sort();
+        granted.dedup();
+
+        self.db
+            .save_client_consent(NewClientConsent {
+                user_id: session.user_id,
+                client_id: client.client_id.clone(),
+                scopes: granted.clone(),
+            })
+            .await?;
+
+        let event = NewOutboxEvent::new(
+            EventType::ClientAuthorized,
+            session.user_id,
+            serde_json::json!({ "client_id": client.client_id, "scopes": granted }),
+        );
+        self.db.commit(UnitOfWork::new().event(event)).await
+    }
+
+    /// Third-party clients the user has let act for them
+    pub async fn list_authorized_apps(&self, user_id: Uuid) -> Result<Vec<AuthorizedApp>, AuthError> {
+        let consents = self.db.find_client_consents(user_id).await?;
+
+        let mut apps = Vec::with_capacity(consents.len());
+        for consent in consents {
+            // Consents go with their client, so one is only missing mid-delete
+            let Some(client) = self.db.find_client_application(&consent.client_id).await? else {
+                continue;
+            };
+            apps.push(AuthorizedApp {
+                client_id: consent.client_id,
+                name: client.name,
+                logo_url: client.logo_url,
+                scopes: consent.scopes,
+                granted_at: consent.granted_at,
+                updated_at: consent.updated_at,
+            });
+        }
+        Ok(apps)
+    }
+
+    /// Take back everything the user let the client have, signing out its
+    /// sessions. Its next sign-in asks again.
+    pub async fn revoke_authorized_app(&self, user_id: Uuid, client_id: &str) -> Result<LogoutResponse, AuthError> {
+        if !self.db.delete_client_consent(user_id, client_id).await? {
+            return Err(AuthError::ValidationError("App not found".into()));
+        }
+
+        let revoked = self.db.revoke_client_sessions(client_id, Some(user_id)).await?;
+        self.token_revocations.revoke(&revoked).await?;
+
+        let event = NewOutboxEvent::new(
+            EventType::ClientAuthorizationRevoked,
+            user_id,
+            serde_json::json!({ "client_id": client_id, "sessions_revoked": revoked.len() }),
+        );
+        self.db.commit(UnitOfWork::new().event(event)).await?;
+
+        Ok(LogoutResponse {
+            message: "App access revoked".into(),
+        })
+    }
+
+    // An access token re-signed to expire within `ttl` seconds and to carry
+    // only `scopes` when given, keeping its `jti`, and how long it lasts
+    fn client_access_token(
+        &self,
+        ttl: Option<i32>,
+        scopes: Option<&[String]>,
+        access_token: &str,
+    ) -> Result<(String, u64), AuthError> {
+        let expected = TokenAudience::new(self.config.jwt.issuer.clone(), &self.config.jwt.accepted_audiences);
+        let mut claims = decode_jwt_with_secret::<JwtClaims>(access_token, &self.config.jwt.secret, &expected)?;
+
+        if let Some(ttl) = ttl {
+            claims.exp = claims.exp.min(claims.iat + ttl as usize);
+        }
+        if let Some(scopes) = scopes {
+            claims.scopes = scopes.to_vec();
+        }
+        let expires_in = claims.exp.saturating_sub(claims.iat) as u64;
+
+        Ok((create_jwt(&claims, &self.config.jwt.secret)?, expires_in))
+    }
+
+    pub async fn list_login_freezes(&self) -> Result<LoginFreezeList, AuthError> {
+        Ok(LoginFreezeList {
+            configured: self.config.login_freeze.enabled,
+            freezes: self.db.find_login_freezes().await?,
+        })
+    }
+
+    /// Refuse new logins and registrations everywhere (`None`) or for one
+    /// organization, until lifted. Setting it again replaces the message.
+    pub async fn freeze_logins(
+        &self,
+        admin_id: Uuid,
+        organization_id: Option<Uuid>,
+        data: LoginFreezeRequest,
+    ) -> Result<LoginFreeze, AuthError> {
+        if let Some(organization_id) = organization_id {
+            if self.db.find_organization_by_id(organization_id).await?.is_none() {
+                return Err(AuthError::ValidationError("Organization not found".into()));
+            }
+        }
+
+        let message = data
+            .message
+            .map(|message| message.trim().to_string())
+            .filter(|message| !message.is_empty())
+            .unwrap_or_else(|| self.config.login_freeze.message.clone());
+        let freeze = self
+            .db
+            .set_login_freeze(NewLoginFreeze {
+                id: Uuid::new_v4(),
+                organization_id,
+                message,
+                frozen_by: Some(admin_id),
+            })
+            .await?;
+
+        match organization_id {
+            Some(organization_id) => {
+                log::warn!("Admin {} froze logins for organization {}", admin_id, organization_id)
+            }
+            None => log::warn!("Admin {} froze all logins", admin_id),
+        }
+        Ok(freeze)
+    }
+
+    pub async fn unfreeze_logins(&self, admin_id: Uuid, organization_id: Option<Uuid>) -> Result<LogoutResponse, AuthError> {
+        if !self.db.clear_login_freeze(organization_id).await? {
+            return Err(AuthError::ValidationError("Logins are not frozen".into()));
+        }
+
+        match organization_id {
+            Some(organization_id) => {
+                log::warn!("Admin {} lifted the login freeze for organization {}", admin_id, organization_id)
+            }
+            None => log::warn!("Admin {} lifted the login freeze", admin_id),
+        }
+        Ok(LogoutResponse {
+            message: "Login freeze lifted".into(),
+        })
+    }
+
+    pub async fn pending_appeals(&self) -> Result<Vec<AccountAppeal>, AuthError> {
+        self.db.find_pending_account_appeals().await
+    }
+
+    /// Accept (reactivating the account) or reject an appeal
+    pub async fn resolve_appeal(
+        &self,
+        admin_id: Uuid,
+        appeal_id: Uuid,
+        data: ResolveAppealRequest,
+    ) -> Result<AccountAppeal, AuthError> {
+        let note = data.note.trim();
+        let appeal = self
+            .db
+            .resolve_account_appeal(appeal_id, data.accept, note, admin_id)
+            .await?
+            .ok_or_else(|| {
+                AuthError::ValidationError("Appeal not found or already resolved".into())
+            })?;
+
+        if data.accept {
+            let reason = format!("Appeal accepted: {}", note);
+            self.db
+                .set_account_status(
+                    appeal.user_id,
+                    None,
+                    AccountStatus::Active,
+                    &reason,
+                    Some(admin_id),
+                    status_changed_event(appeal.user_id, AccountStatus::Active, Some(admin_id)),
+                )
+                .await?;
+            self.user_cache.invalidate(appeal.user_id);
+        }
+
+        log::info!(
+            "Admin {} {} appeal {} from user {}",
+            admin_id,
+            if data.accept { "accepted" } else { "rejected" },
+            appeal.id,
+            appeal.user_id
+        );
+
+        Ok(appeal)
+    }
+
+    pub async fn create_organization(
+        &self,
+        user_id: Uuid,
+        data: CreateOrganizationRequest,
+    ) -> Result<OrganizationResponse, AuthError> {
+        let slug = data.slug.trim().to_lowercase();
+        if !slug.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+            return Err(AuthError::ValidationError(
+                "Slug may only contain letters, digits and hyphens".into(),
+            ));
+        }
+
+        let organization = self
+            .db
+            .create_organization(
+                NewOrganization {
+                    id: Uuid::new_v4(),
+                    name: data.name.trim().to_string(),
+                    slug,
+                },
+                user_id,
+            )
+            .await?;
+
+        Ok(OrganizationResponse::new(organization, OrganizationRole::Owner))
+    }
+
+    pub async fn list_organizations(&self, user_id: Uuid) -> Result<Vec<OrganizationResponse>, AuthError> {
+        let organizations = self.db.find_user_organizations(user_id).await?;
+
+        Ok(organizations
+            .into_iter()
+            .map(|(organization, member)| OrganizationResponse::new(organization, member.role()))
+            .collect())
+    }
+
+    /// Create an account for someone joining the organization, who activates
+    /// it by email. Organization admins may invite members and admins.
+    pub async fn invite_organization_member(
+        &self,
+        user_id: Uuid,
+        organization_id: Uuid,
+        data: InviteMemberRequest,
+        locale: &str,
+    ) -> Result<UserResponse, AuthError> {
+        self.organization_admin(user_id, organization_id).await?;
+
+        let role = data.role.unwrap_or(OrganizationRole::Member);
+        if role == OrganizationRole::Owner {
+            return Err(AuthError::PermissionDenied);
+        }
+
+        let invited = self
+            .create_pending_user(&data.email, data.username.as_deref(), "organization_invite")
+            .await?;
+        self.db
+            .add_organization_member(NewOrganizationMember {
+                id: Uuid::new_v4(),
+                organization_id,
+                user_id: invited.id,
+                role: role.as_str().to_string(),
+            })
+            .await?;
+
+        self.email_throttle.record(&invited.email, ThrottledEmail::Activation).await?;
+        let activation_token = self.activation_token(&invited)?;
+        self.email_service
+            .send_activation_email(&invited.email, &activation_token, locale)
+            .await?;
+
+        log::info!(
+            "User {} invited user {} to organization {} as {}",
+            user_id,
+            invited.id,
+            organization_id,
+            role.as_str()
+        );
+
+        Ok(invited.into())
+    }
+
+    pub async fn list_organization_domains(
+        &self,
+        user_id: Uuid,
+        organization_id: Uuid,
+    ) -> Result<Vec<OrganizationDomainResponse>, AuthError> {
+        self.organization_admin(user_id, organization_id).await?;
+
+        let domains = self.db.find_organization_domains(organization_id).await?;
+        Ok(domains.into_iter().map(|d| self.domain_response(d)).collect())
+    }
+
+    /// Claims a domain for the organization. It stays unverified, and so has no
+    /// effect, until the returned TXT record is published and checked.
+    pub async fn add_organization_domain(
+        &self,
+        user_id: Uuid,
+        organization_id: Uuid,
+        data: AddOrganizationDomainRequest,
+    ) -> Result<OrganizationDomainResponse, AuthError> {
+        self.organization_admin(user_id, organization_id).await?;
+
+        let domain = normalize_domain(&data.domain)
+            .ok_or_else(|| AuthError::ValidationError(format!("Invalid domain: {}", data.domain)))?;
+
+        let domain = self
+            .db
+            .create_organization_domain(NewOrganizationDomain {
+                id: Uuid::new_v4(),
+                organization_id,
+                domain,
+                verification_token: self.domain_verifier.new_token(),
+                auto_join: data.auto_join,
+            })
+            .await?;
+
+        log::info!(
+            "User {} claimed domain {} for organization {}",
+            user_id,
+            domain.domain,
+            organization_id
+        );
+
+        Ok(self.domain_response(domain))
+    }
+
+    /// Looks up the domain's TXT record and marks it verified once the token is found
+    pub async fn verify_organization_domain(
+        &self,
+        user_id: Uuid,
+        organization_id: Uuid,
+        domain_id: Uuid,
+    ) -> Result<OrganizationDomainResponse, AuthError> {
+        let domain = self.organization_domain(user_id, organization_id, domain_id).await?;
+
+        let found = self
+            .domain_verifier
+            .check(&domain.domain, &domain.verification_token)
+            .await?;
+        let verified_at = domain.verified_at.or_else(|| found.then(Utc::now));
+        let domain = self.db.record_organization_domain_check(domain.id, verified_at).await?;
+
+        if !found && !domain.is_verified() {
+            return Err(AuthError::ValidationError(format!(
+                "TXT record not found at {}",
+                self.domain_verifier.record_name(&domain.domain)
+            )));
+        }
+
+        log::info!("Organization {} verified domain {}", organization_id, domain.domain);
+
+        Ok(self.domain_response(domain))
+    }
+
+    pub async fn update_organization_domain(
+        &self,
+        user_id: Uuid,
+        organization_id: Uuid,
+        domain_id: Uuid,
+        data: UpdateOrganizationDomainRequest,
+    ) -> Result<OrganizationDomainResponse, AuthError> {
+        let domain = self.organization_domain(user_id, organization_id, domain_id).await?;
+        let domain = self
+            .db
+            .set_organization_domain_auto_join(domain.id, data.auto_join)
+            .await?;
+
+        Ok(self.domain_response(domain))
+    }
+
+    pub async fn remove_organization_domain(
+        &self,
+        user_id: Uuid,
+        organization_id: Uuid,
+        domain_id: Uuid,
+    ) -> Result<LogoutResponse, AuthError> {
+        let domain = self.organization_domain(user_id, organization_id, domain_id).await?;
+        self.db.delete_organization_domain(domain.id).await?;
+
+        log::info!(
+            "User {} removed domain {} from organization {}",
+            user_id,
+            domain.domain,
+            organization_id
+        );
+
+        Ok(LogoutResponse {
+            message: "Domain removed".into(),
+        })
+    }
+
+    pub async fn get_sso_connection(
+        &self,
+        user_id: Uuid,
+        organization_id: Uuid,
+    ) -> Result<SsoConnectionResponse, AuthError> {
+        self.organization_admin(user_id, organization_id).await?;
+
+        let connection = self
+            .db
+            .find_sso_connection_by_organization(organization_id)
+            .await?
+            .ok_or_else(|| AuthError::ValidationError("SSO is not configured".into()))?;
+        self.sso_connection_response(connection).await
+    }
+
+    /// Create or replace the organization's IdP connection and the email domains routed to it
+    pub async fn save_sso_connection(
+        &self,
+        user_id: Uuid,
+        organization_id: Uuid,
+        data: SsoConnectionRequest,
+    ) -> Result<SsoConnectionResponse, AuthError> {
+        self.organization_admin(user_id, organization_id).await?;
+
+        let existing = self.db.find_sso_connection_by_organization(organization_id).await?;
+        let oidc_client_secret = data
+            .oidc_client_secret
+            .map(Secret::into_inner)
+            .or_else(|| existing.as_ref().and_then(|c| c.oidc_client_secret.clone()));
+
+        match data.protocol {
+            SsoProtocol::Oidc => {
+                let issuer = data.oidc_issuer.as_deref().unwrap_or_default();
+                validate_http_url(issuer)?;
+                if data.oidc_client_id.is_none() || oidc_client_secret.is_none() {
+                    return Err(AuthError::ValidationError(
+                        "OIDC connections need a client ID and secret".into(),
+                    ));
+                }
+            }
+            SsoProtocol::Saml => {
+                if data.saml_idp_metadata.is_none() {
+                    return Err(AuthError::ValidationError(
+                        "SAML connections need the IdP metadata".into(),
+                    ));
+                }
+            }
+        }
+
+        // Only ownership is granted through the API, never through IdP groups
+        if data.default_role == OrganizationRole::Owner
+            || data.role_mappings.values().any(|role| *role == OrganizationRole::Owner)
+        {
+            return Err(AuthError::ValidationError("IdP groups cannot map to owner".into()));
+        }
+        self.check_provisioning_rules(user_id, organization_id, &data.provisioning_rules)
+            .await?;
+
+        let connection = self
+            .db
+            .save_sso_connection(NewSsoConnection {
+                id: existing.map(|c| c.id).unwrap_or_else(Uuid::new_v4),
+                organization_id,
+                protocol: data.protocol.as_str().to_string(),
+                enabled: data.enabled,
+                oidc_issuer: data.oidc_issuer,
+                oidc_client_id: data.oidc_client_id,
+                oidc_client_secret,
+                saml_idp_metadata: data.saml_idp_metadata,
+                groups_attribute: data.groups_attribute.unwrap_or_else(|| "groups".to_string()),
+                role_mappings: serde_json::to_value(&data.role_mappings)
+                    .map_err(|e| AuthError::InternalServerError(e.to_string()))?,
+                default_role: data.default_role.as_str().to_string(),
+                provisioning_rules: serde_json::to_value(&data.provisioning_rules)
+                    .map_err(|e| AuthError::InternalServerError(e.to_string()))?,
+            })
+            .await?;
+
+        log::info!(
+            "User {} configured {} SSO for organization {} (enabled: {})",
+            user_id,
+            data.protocol.as_str(),
+            organization_id,
+            connection.enabled
+        );
+
+        self.sso_connection_response(connection).await
+    }
+
+    pub async fn delete_sso_connection(
+        &self,
+        user_id: Uuid,
+        organization_id: Uuid,
+    ) -> Result<LogoutResponse, AuthError> {
+        self.organization_admin(user_id, organization_id).await?;
+        self.db.delete_sso_connection(organization_id).await?;
+
+        log::info!("User {} removed SSO for organization {}", user_id, organization_id);
+
+        Ok(LogoutResponse {
+            message: "SSO connection removed".into(),
+        })
+    }
+
+    pub async fn get_organization_branding(
+        &self,
+        user_id: Uuid,
+        organization_id: Uuid,
+    ) -> Result<OrganizationBrandingResponse, AuthError> {
+        self.organization_admin(user_id, organization_id).await?;
+
+        let branding = self.db.find_organization_branding(organization_id).await?;
+        Ok(branding.into())
+    }
+
+    /// Replace how the organization's hosted sign-in pages look (org admins only)
+    pub async fn save_organization_branding(
+        &self,
+        user_id: Uuid,
+        organization_id: Uuid,
+        data: OrganizationBrandingRequest,
+    ) -> Result<OrganizationBrandingResponse, AuthError> {
+        self.organization_admin(user_id, organization_id).await?;
+
+        // Pages are served over https, where an http logo would be blocked
+        if let Some(logo_url) = &data.logo_url {
+            validate_http_url(logo_url)?;
+            if !logo_url.starts_with("https://") {
+                return Err(AuthError::ValidationError("The logo must be served over https".into()));
+            }
+        }
+        // Written into the pages' stylesheet, so nothing but a hex color gets through
+        let primary_color = match data.primary_color {
+            Some(color) if color.starts_with('#') && color[1..].chars().all(|c| c.is_ascii_hexdigit()) => {
+                Some(color.to_ascii_lowercase())
+            }
+            Some(_) => return Err(AuthError::ValidationError("Colors must be given as #rrggbb".into())),
+            None => None,
+        };
+
+        let branding = self
+            .db
+            .save_organization_branding(NewOrganizationBranding {
+                organization_id,
+                display_name: data.display_name,
+                logo_url: data.logo_url,
+                primary_color,
+            })
+            .await?;
+
+        log::info!("User {} updated the branding of organization {}", user_id, organization_id);
+
+        Ok(Some(branding).into())
+    }
+
+    pub async fn get_organization_login_policy(
+        &self,
+        user_id: Uuid,
+        organization_id: Uuid,
+    ) -> Result<Option<LoginPolicy>, AuthError> {
+        self.organization_admin(user_id, organization_id).await?;
+        self.db.find_login_policy(LoginPolicyScope::Organization(organization_id)).await
+    }
+
+    /// Replace the login policy every member of the organization is held to
+    pub async fn save_organization_login_policy(
+        &self,
+        user_id: Uuid,
+        organization_id: Uuid,
+        data: LoginPolicyRequest,
+    ) -> Result<LoginPolicy, AuthError> {
+        self.organization_admin(user_id, organization_id).await?;
+
+        let policy = self
+            .db
+            .save_login_policy(new_login_policy(LoginPolicyScope::Organization(organization_id), user_id, data)?)
+            .await?;
+
+        log::info!("User {} updated the login policy of organization {}", user_id, organization_id);
+
+        Ok(policy)
+    }
+
+    pub async fn delete_organization_login_policy(
+        &self,
+        user_id: Uuid,
+        organization_id: Uuid,
+    ) -> Result<LogoutResponse, AuthError> {
+        self.organization_admin(user_id, organization_id).await?;
+
+        if !self
+            .db
+            .delete_login_policy(LoginPolicyScope::Organization(organization_id))
+            .await?
+        {
+            return Err(AuthError::ValidationError("No login policy is set".into()));
+        }
+
+        log::info!("User {} removed the login policy of organization {}", user_id, organization_id);
+
+        Ok(LogoutResponse {
+            message: "Login policy removed".to_string(),
+        })
+    }
+
+    /// The organization a hosted page was opened for, by the slug in its
+    /// path, with its branding if any was saved
+    pub async fn organization_for_pages(
+        &self,
+        slug: &str,
+    ) -> Result<Option<(Organization, Option<OrganizationBranding>)>, AuthError> {
+        let organization = match self.db.find_organization_by_slug(slug).await? {
+            Some(organization) => organization,
+            None => return Ok(None),
+        };
+        let branding = self.db.find_organization_branding(organization.id).await?;
+        Ok(Some((organization, branding)))
+    }
+
+    /// Called with the email typed at login: if its domain belongs to an organization
+    /// with SSO enabled, start the login at that organization's IdP
+    pub async fn sso_discover(&self, data: SsoDiscoverRequest) -> Result<SsoDiscoverResponse, AuthError> {
+        let (connection, organization) = match self.sso_connection_for_email(&data.email).await? {
+            Some(found) => found,
+            None => {
+                return Ok(SsoDiscoverResponse {
+                    sso: false,
+                    organization: None,
+                    redirect_url: None,
+                })
+            }
+        };
+
+        Ok(SsoDiscoverResponse {
+            sso: true,
+            organization: Some(organization.name),
+            redirect_url: Some(self.sso.start(&connection).await?),
+        })
+    }
+
+    /// Redirect back from an OIDC IdP
+    pub async fn sso_oidc_callback(
+        &self,
+        query: OidcCallbackQuery,
+        ip: Option<String>,
+        user_agent: Option<String>,
+        network: NetworkFingerprint,
+        locale: &str,
+    ) -> Result<LoginResponse, AuthError> {
+        let login = self.sso.claim(&query.state)?;
+        if let Some(error) = query.error {
+            return Err(AuthError::SsoError(query.error_description.unwrap_or(error)));
+        }
+        let code = query
+            .code
+            .ok_or_else(|| AuthError::SsoError("No authorization code returned".into()))?;
+
+        let connection = self.enabled_sso_connection(login.connection_id).await?;
+        let identity = self.sso.oidc_identity(&connection, &login, &code).await?;
+
+        self.complete_sso_login(&connection, identity, ip, user_agent, network, locale).await
+    }
+
+    /// SAML response POSTed to the assertion consumer service
+    pub async fn sso_saml_acs(
+        &self,
+        form: SamlAcsForm,
+        ip: Option<String>,
+        user_agent: Option<String>,
+        network: NetworkFingerprint,
+        locale: &str,
+    ) -> Result<LoginResponse, AuthError> {
+        let login = self.sso.claim(&form.relay_state)?;
+        let connection = self.enabled_sso_connection(login.connection_id).await?;
+        let identity = self.sso.saml_identity(&connection, &login, &form.saml_response)?;
+
+        self.complete_sso_login(&connection, identity, ip, user_agent, network, locale).await
+    }
+
+    /// Create one demo account per account state, for frontend and QA work.
+    /// Accounts that already exist are left as they are, so this can be rerun.
+    pub async fn seed_demo_data(&self) -> Result<SeedReport, AuthError> {
+        if !self.config.dev.seed_enabled {
+            return Err(AuthError::PermissionDenied);
+        }
+
+        let password = self.config.dev.seed_password.clone();
+        validate_password(&password)?;
+        let password_hash = hash_password(&password)?;
+
+        let mut accounts = Vec::new();
+        for &(username, state) in seed::DEMO_ACCOUNTS {
+            let email = seed::demo_email(username);
+
+            if self.db.user_exists_by_username(username).await? {
+                accounts.push(SeededAccount {
+                    username: username.to_string(),
+                    email,
+                    state,
+                    created: false,
+                    totp_url: None,
+                });
+                continue;
+            }
+
+            let is_admin = state == DemoState::Admin;
+            let new_user = NewUser {
+                id: Uuid::new_v4(),
+                username: username.to_string(),
+                email: email.clone(),
+                password_hash: password_hash.clone(),
+                is_email_verified: state.is_email_verified(),
+                email_verification_token: None,
+                email_verification_sent_at: None,
+                is_admin,
+                password_expires_at: if state == DemoState::PasswordExpired {
+                    Some(Utc::now() - Duration::days(1))
+                } else {
+                    self.password_expiry(is_admin)
+                },
+                is_guest: false,
+                activation_pending: false,
+                email_display: None,
+            };
+            let event = user_created_event(&new_user, "seed");
+            let user = self.db.create_user(new_user, event).await?;
+
+            let mut totp_url = None;
+            if state == DemoState::MfaEnabled {
+                let (secret, url) = self.mfa_service.generate_totp_secret(&user.username)?;
+                self.db.update_mfa_secret(user.id, None, &secret).await?;
+                self.db.enable_mfa(user.id, None).await?;
+                totp_url = Some(url);
+            }
+
+            if let Some(status) = state.account_status() {
+                self.db
+                    .set_account_status(
+                        user.id,
+                        None,
+                        status,
+                        "Demo account",
+                        None,
+                        status_changed_event(user.id, status, None),
+                    )
+                    .await?;
+            }
+
+            accounts.push(SeededAccount {
+                username: user.username,
+                email: user.email,
+                state,
+                created: true,
+                totp_url,
+            });
+        }
+
+        log::warn!("Seeded {} demo accounts", accounts.iter().filter(|a| a.created).count());
+
+        Ok(SeedReport { password, accounts })
+    }
+
+    /// An access token for `user` as if they had just signed in with a password,
+    /// for test fixtures that skip the login flow
+    #[cfg(any(test, feature = "test-utils"))]
+    pub fn issue_access_token(&self, user: &User, session_id: Option<Uuid>) -> Result<String, AuthError> {
+        self.create_access_token(user, &[AMR_PASSWORD], session_id)
+    }
+
+    // Helper functions
+
+    // Email for flows whose timing mustn't reveal whether an account exists,
+    // sent in the background so mail server latency doesn't show
+    fn lookup_email_service(&self) -> EmailService {
+        if self.config.response_timing.background_email {
+            self.email_service.in_background()
+        } else {
+            self.email_service.clone()
+        }
+    }
+
+    // Revoke a session unused for longer than `SESSION_INACTIVITY_TIMEOUT_DAYS`
+    async fn ensure_session_not_idle(&self, session: &Session) -> Result<(), AuthError> {
+        if session.is_revoked || !self.session_activity.is_idle(session.last_seen_at, Utc::now()) {
+            return Ok(());
+        }
+
+        self.db.revoke_session(session.id).await?;
+        Err(AuthError::TokenExpired)
+    }
+
+    // Lockouts and CAPTCHA escalation for repeated failed logins, checked
+    // before the password so a locked account can't be probed.
+    // `captcha_checked` says the request already passed a CAPTCHA; returns
+    // whether it has passed one now.
+    fn check_brute_force(
+        &self,
+        user: Option<&User>,
+        ip: Option<&str>,
+        captcha: &CaptchaSolution,
+        captcha_checked: bool,
+    ) -> Result<bool, AuthError> {
+        if !self.config.brute_force.enabled {
+            return Ok(captcha_checked);
+        }
+
+        if let Some(retry_after) = self.brute_force.ip_locked_for(ip) {
+            return Err(AuthError::RateLimitExceeded {
+                limit: self.config.brute_force.ip_lockout_after,
+                retry_after,
+            });
+        }
+
+        let now = Utc::now();
+        if let Some(until) = user.and_then(|user| user.locked_until).filter(|until| *until > now) {
+            return Err(AuthError::AccountLocked {
+                retry_after: (until - now).num_seconds().max(1) as u64,
+            });
+        }
+
+        let account_failures = user.map_or(0, |user| self.brute_force.account_failures(user, now));
+        let verdict = self.brute_force.verdict(account_failures, self.brute_force.ip_failures(ip));
+        if verdict.captcha && !captcha_checked {
+            self.verify_captcha(captcha)?;
+            return Ok(true);
+        }
+
+        Ok(captcha_checked)
+    }
+
+    // Count a failed password login against the account and the IP, locking
+    // either out once its count calls for it
+    // The login's last factor checked out, so earlier failures no longer
+    // count. Until then a right password alone doesn't reset anything.
+    // Save the session a login starts, recording the login in the account's
+    // history alongside it
+    async fn start_login_session(&self, session: NewSession) -> Result<(), AuthError> {
+        let event = logged_in_event(&session);
+        self.db.commit(UnitOfWork::new().create_session(session).event(event)).await
+    }
+
+    async fn record_login_success(&self, user: &User, tarpit_keys: &[String]) -> Result<(), AuthError> {
+        self.tarpit.record_success(tarpit_keys);
+        if user.failed_login_count > 0 || user.locked_until.is_some() {
+            self.db.clear_failed_logins(user.id).await?;
+        }
+        if user.mfa_enabled {
+            self.db.clear_mfa_failures(user.id).await?;
+        }
+        Ok(())
+    }
+
+    // Too many wrong codes lock the second step for the rest of the window, on
+    // every instance, however many `mfa_pending` tokens the guesses are spread over
+    async fn ensure_mfa_attempts_left(&self, user_id: Uuid) -> Result<(), AuthError> {
+        let config = &self.config.mfa_attempts;
+        let window = Duration::seconds(config.window as i64);
+        if let Some(failures) = self.db.find_mfa_failures(user_id).await? {
+            let retry_at = failures.last_failed_at + window;
+            if failures.failure_count >= config.max_failures as i32 && retry_at > Utc::now() {
+                return Err(AuthError::RateLimitExceeded {
+                    limit: config.max_failures,
+                    retry_after: (retry_at - Utc::now()).num_seconds().max(1) as u64,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    async fn record_mfa_failure(&self, user_id: Uuid) -> Result<(), AuthError> {
+        let config = &self.config.mfa_attempts;
+        let window = Duration::seconds(config.window as i64);
+        let failures = self.db.record_mfa_failure(user_id, Utc::now() - window).await?;
+        log::warn!(
+            "Wrong second factor for user {} ({} of {} allowed)",
+            user_id,
+            failures,
+            config.max_failures
+        );
+        Ok(())
+    }
+
+    async fn record_login_failure(&self, user: Option<&User>, ip: &Option<String>) {
+        if !self.config.brute_force.enabled {
+            return;
+        }
+
+        let now = Utc::now();
+        // The tarpit has already counted this failure against the IP
+        let ip_failures = self.brute_force.ip_failures(ip.as_deref());
+        let account_failures = match user {
+            Some(user) => match self.db.record_failed_login(user.id, self.brute_force.window_start(now)).await {
+                Ok(count) => count.max(0) as u32,
+                Err(e) => {
+                    log::error!("Failed to count failed login for user {}: {}", user.id, e);
+                    0
+                }
+            },
+            None => 0,
+        };
+
+        let verdict = self.brute_force.verdict(account_failures, ip_failures);
+        if let (true, Some(user)) = (verdict.lock_account, user) {
+            log::warn!("Locking user {} after {} failed logins", user.id, account_failures);
+            if let Err(e) = self.db.lock_account(user.id, self.brute_force.lockout_until(now)).await {
+                log::error!("Failed to lock user {}: {}", user.id, e);
+            }
+        }
+        if let (true, Some(ip)) = (verdict.lock_ip, ip.as_deref()) {
+            log::warn!("Locking out {} after {} failed logins", ip, ip_failures);
+            self.brute_force.lock_ip(ip);
+        }
+    }
+
+    // Sources caught using a canary credential are refused everywhere
+    fn ensure_source_allowed(&self, ip: Option<&str>) -> Result<(), AuthError> {
+        match ip {
+            Some(ip) if self.source_blocklist.is_blocked(ip, Utc::now()) => Err(AuthError::PermissionDenied),
+            _ => Ok(()),
+        }
+    }
+
+    // Refuse a new login while logins are frozen everywhere, or for the
+    // organization it's into or one the user belongs to. Admins are let
+    // through so a freeze can always be lifted.
+    async fn ensure_logins_open(&self, user: Option<&User>, organization_id: Option<Uuid>) -> Result<(), AuthError> {
+        if user.map_or(false, |user| user.is_admin) {
+            return Ok(());
+        }
+        if self.config.login_freeze.enabled {
+            return Err(AuthError::LoginsFrozen {
+                message: self.config.login_freeze.message.clone(),
+            });
+        }
+
+        let freezes = self.db.find_login_freezes().await?;
+        if freezes.is_empty() {
+            return Ok(());
+        }
+
+        let mut organization_ids: Vec<Uuid> = organization_id.into_iter().collect();
+        if let Some(user) = user {
+            if freezes.iter().any(|freeze| !freeze.is_global()) {
+                let memberships = self.db.find_user_organizations(user.id).await?;
+                organization_ids.extend(memberships.into_iter().map(|(organization, _)| organization.id));
+            }
+        }
+
+        let frozen = freezes.into_iter().find(|freeze| {
+            freeze
+                .organization_id
+                .map_or(true, |id| organization_ids.contains(&id))
+        });
+        match frozen {
+            Some(freeze) => Err(AuthError::LoginsFrozen { message: freeze.message }),
+            None => Ok(()),
+        }
+    }
+
+    // Registrations are frozen with logins; an address at an organization's
+    // verified domain counts as joining it
+    async fn ensure_registration_open(&self, email: Option<&str>) -> Result<(), AuthError> {
+        let organization_id = match email.and_then(email_domain) {
+            Some(domain) => self
+                .db
+                .find_verified_organization_domain(&domain)
+                .await?
+                .map(|domain| domain.organization_id),
+            None => None,
+        };
+        self.ensure_logins_open(None, organization_id).await
+    }
+
+    // A login to a canary account fails like a wrong password, after as long,
+    // whatever password was tried
+    async fn ensure_not_canary(
+        &self,
+        user: &User,
+        password: &str,
+        ip: &Option<String>,
+        user_agent: &Option<String>,
+        tarpit_keys: &[String],
+    ) -> Result<(), AuthError> {
+        let canary = match self.db.find_canary_by_user_id(user.id).await? {
+            Some(canary) => canary,
+            None => return Ok(()),
+        };
+
+        let _ = verify_password(password, &user.password_hash);
+        self.tarpit.record_failure(tarpit_keys);
+        self.trip_canary(canary, "login", ip, user_agent).await;
+        Err(AuthError::InvalidCredentials)
+    }
+
+    // Someone used a canary credential: block the source and alert the
+    // security webhook. Failures are logged, never returned, so the caller
+    // still sees an ordinary rejection.
+    async fn trip_canary(
+        &self,
+        canary: CanaryCredential,
+        via: &str,
+        ip: &Option<String>,
+        user_agent: &Option<String>,
+    ) {
+        let now = Utc::now();
+        log::error!(
+            "Canary {} ({}) used via {} from {}",
+            canary.id,
+            canary.label,
+            via,
+            ip.as_deref().unwrap_or("unknown")
+        );
+
+        if let Err(e) = self.db.record_canary_trip(canary.id, ip.clone()).await {
+            log::error!("Failed to record trip of canary {}: {}", canary.id, e);
+        }
+        if let Some(until) = ip.as_deref().and_then(|ip| self.source_blocklist.block(ip, now)) {
+            log::warn!("Blocked {} until {}", ip.as_deref().unwrap_or_default(), until);
+        }
+
+        self.security_webhook.notify(SecurityEvent {
+            id: Uuid::new_v4(),
+            kind: SecurityEventKind::CanaryTriggered,
+            priority: "critical",
+            user_id: canary.user_id,
+            score: 100,
+            factors: vec![RiskFactor {
+                name: format!("canary_{}", via),
+                description: format!("Canary credential \"{}\" was used", canary.label),
+                weight: 100,
+            }],
+            ip_address: ip.clone(),
+            user_agent: user_agent.clone(),
+            location: None,
+            occurred_at: now,
+        });
+    }
+
+    /// The client's coarse network, from the headers the proxy adds
+    pub fn network_fingerprint(&self, headers: &HeaderMap) -> NetworkFingerprint {
+        NetworkFingerprint::from_headers(headers, &self.config.refresh_binding)
+    }
+
+    // Record the network a session's refresh token is bound to. Parts the
+    // proxy didn't report are left as they were.
+    fn bind_session_network(&self, session: &mut NewSession, network: NetworkFingerprint) {
+        let binding = self.config.refresh_binding.binding;
+        if matches!(binding, RefreshBinding::Country | RefreshBinding::CountryAndAsn) && network.country.is_some() {
+            session.network_country = network.country;
+        }
+        if matches!(binding, RefreshBinding::Asn | RefreshBinding::CountryAndAsn) && network.asn.is_some() {
+            session.network_asn = network.asn;
+        }
+    }
+
+    // A refresh from a network other than the one the session is bound to is
+    // either refused outright or has to re-verify the user's credentials
+    async fn ensure_refresh_network(
+        &self,
+        session: &Session,
+        user: &User,
+        data: &RefreshTokenRequest,
+        network: &NetworkFingerprint,
+        ip: &Option<String>,
+        user_agent: &Option<String>,
+    ) -> Result<(), AuthError> {
+        let binding = &self.config.refresh_binding;
+        if !network.differs_from(session.network_country.as_deref(), session.network_asn.as_deref(), binding.binding) {
+            return Ok(());
+        }
+
+        if binding.on_mismatch == NetworkMismatchPolicy::Reject {
+            log::warn!("Refresh for session {} from a different network, revoking it", session.id);
+            self.db.revoke_session(session.id).await?;
+            self.token_revocations.revoke(&[session.id]).await?;
+            return Err(AuthError::InvalidToken);
+        }
+
+        let password = data.password.as_ref().ok_or(AuthError::NetworkChanged)?;
+        if user.mfa_enabled && data.mfa_code.is_none() {
+            return Err(AuthError::NetworkChanged);
+        }
+
+        if !verify_password(password, &user.password_hash)? {
+            self.record_account_signal(user, AccountSignal::FailedLogin, ip, user_agent).await;
+            return Err(AuthError::InvalidCredentials);
+        }
+        if let Some(mfa_code) = data.mfa_code.as_ref().filter(|_| user.mfa_enabled) {
+            if !self.verify_any_totp(user, mfa_code).await? {
+                return Err(AuthError::InvalidMfaCode);
+            }
+        }
+
+        Ok(())
+    }
+
+    // Run the login pipeline, recording credential failures against the tarpit
+    // and anything suspicious against the account's risk score
+    async fn run_login_checks(
+        &self,
+        user: &User,
+        password: Option<&str>,
+        client: LoginClient<'_>,
+        tarpit_keys: &[String],
+        locale: &str,
+    ) -> Result<LoginVerdict, AuthError> {
+        let LoginClient { ip, user_agent, network, device_token } = client;
+
+        // A user's first device isn't new; there's nothing to compare it with
+        let device = user_agent.as_deref().map(DeviceInfo::parse).unwrap_or_default();
+        let seen = self.db.find_session_devices(user.id).await?;
+        let trusted_device = match device_token {
+            Some(token) if self.config.email_code_login.trusted_device_days > 0 => self
+                .db
+                .use_trusted_device(user.id, &hash_device_token(token.expose()))
+                .await?
+                .is_some(),
+            _ => false,
+        };
+
+        let organization_ids = self
+            .db
+            .find_user_organizations(user.id)
+            .await?
+            .into_iter()
+            .map(|(organization, _)| organization.id)
+            .collect();
+        let policies = self.db.find_login_policies(user.id, organization_ids).await?;
+
+        let attempt = LoginAttempt {
+            user,
+            password,
+            ip: ip.as_deref(),
+            user_agent: user_agent.as_deref(),
+            country: network.country.as_deref(),
+            location: network.location.as_ref(),
+            policies: &policies,
+            new_device: !seen.is_empty() && !seen.contains(&device),
+            trusted_device,
+        };
+
+        let result = self.login_checks.run(&attempt);
+        let signal = match &result {
+            Ok(verdict) if verdict.outcome == CheckOutcome::RequireApproval => Some(AccountSignal::RiskySession),
+            Err(AuthError::InvalidCredentials) => Some(AccountSignal::FailedLogin),
+            Err(AuthError::PasswordResetRequired) => Some(AccountSignal::BreachHit),
+            // Risk scoring blocked the login outright
+            Err(AuthError::PermissionDenied) => Some(AccountSignal::RiskySession),
+            _ => None,
+        };
+        if let Some(signal) = signal {
+            self.record_account_signal(user, signal, ip, user_agent).await;
+        }
+
+        match result {
+            Ok(verdict) => {
+                // The password (or what stood in for it) was right, so earlier
+                // failures no longer count, unless a second factor is still to come
+                self.risk_scoring.reset_failed_attempts(&user.username);
+                if verdict.outcome != CheckOutcome::RequireMfa
+                    && (user.failed_login_count > 0 || user.locked_until.is_some())
+                {
+                    self.db.clear_failed_logins(user.id).await?;
+                }
+                Ok(verdict)
+            }
+            Err(AuthError::InvalidCredentials) => {
+                self.tarpit.record_failure(tarpit_keys);
+                // Failures across many accounts at once look like credential stuffing
+                self.risk_scoring.record_failed_attempt(&user.username);
+                self.record_login_failure(Some(user), ip).await;
+                Err(AuthError::InvalidCredentials)
+            }
+            // Unverified users only get a token good for requesting a new verification email
+            Err(AuthError::EmailNotVerified { .. }) => Err(AuthError::EmailNotVerified {
+                resend_token: Some(self.create_scoped_token(user, TokenScope::EmailUnverified)?),
+            }),
+            // Locked-out users only get a token good for seeing why and appealing
+            Err(AuthError::AccountDisabled { .. }) => Err(AuthError::AccountDisabled {
+                status_token: Some(self.create_scoped_token(user, TokenScope::AccountStatus)?),
+            }),
+            // The right password alone doesn't bring an archived account back
+            Err(AuthError::AccountArchived) => {
+                self.send_reactivation_link(user, locale).await?;
+                Err(AuthError::AccountArchived)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    // The login pipeline for a login where an emailed code, a trusted device,
+    // a passkey, an approval link or an IdP stood in for the password. Canary
+    // accounts, login freezes, login policies, risk and account status apply
+    // to these like to any other.
+    pub(crate) async fn run_verified_login_checks(
+        &self,
+        user: &User,
+        via: &str,
+        client: LoginClient<'_>,
+        locale: &str,
+    ) -> Result<LoginVerdict, AuthError> {
+        if let Some(canary) = self.db.find_canary_by_user_id(user.id).await? {
+            self.trip_canary(canary, via, client.ip, client.user_agent).await;
+            return Err(AuthError::InvalidCredentials);
+        }
+        self.ensure_logins_open(Some(user), None).await?;
+
+        self.run_login_checks(user, None, client, &[], locale).await
+    }
+
+    // The owner proves they still have the mailbox before an archived account
+    // is used again. Logins inside the resend cooldown don't send another link.
+    async fn send_reactivation_link(&self, user: &User, locale: &str) -> Result<(), AuthError> {
+        match self.email_throttle.acquire(&user.email, ThrottledEmail::Reactivation).await {
+            Err(AuthError::EmailResendThrottled { .. }) => return Ok(()),
+            result => result?,
+        }
+
+        let ttl = Duration::seconds(self.config.action_tokens.reactivation_ttl as i64);
+        let claims = ActionClaims::new(ActionPurpose::Reactivation, user.id, ttl)
+            .with_binding(archive_binding(user));
+        let token = self.action_tokens.issue(&claims)?;
+        self.lookup_email_service()
+            .send_reactivation_email(&user.email, &token, locale)
+            .await
+    }
+
+    // Signing in through the mailbox or the organization's IdP proves as much
+    // as the reactivation link would
+    async fn reactivate_if_archived(&self, user: User, via: &str) -> Result<User, AuthError> {
+        if !user.is_archived() {
+            return Ok(user);
+        }
+
+        let user = self.db.reactivate_user(user.id, reactivated_event(user.id, via)).await?;
+        self.user_cache.invalidate(user.id);
+        log::info!("User {} reactivated their archived account via {}", user.id, via);
+        Ok(user)
+    }
+
+    // Signed link confirming the user's current address
+    fn email_verification_token(&self, user: &User) -> Result<String, AuthError> {
+        let ttl = Duration::seconds(self.config.action_tokens.email_verification_ttl as i64);
+        let claims = ActionClaims::new(ActionPurpose::EmailVerification, user.id, ttl)
+            .with_binding(user.email.clone());
+        self.action_tokens.issue(&claims)
+    }
+
+    // Signed link setting the first password of a pending account, for as
+    // long as the account keeps the address it went to
+    fn activation_token(&self, user: &User) -> Result<String, AuthError> {
+        let ttl = Duration::seconds(self.config.action_tokens.activation_ttl as i64);
+        let claims = ActionClaims::new(ActionPurpose::Activation, user.id, ttl)
+            .with_binding(user.email.clone());
+        self.action_tokens.issue(&claims)
+    }
+
+    // Risky sessions are held to at most the configured bound and trusted
+    // ones given at least theirs, pinned or not
+    pub(crate) fn refresh_token_lifetime(&self, pinned: bool, lifetime: SessionLifetime) -> Duration {
+        let seconds = if pinned {
+            self.config.jwt.pinned_refresh_token_expiry
+        } else {
+            self.config.jwt.refresh_token_expiry
+        };
+        let bounds = &self.config.session_lifetime;
+        let seconds = match lifetime {
+            SessionLifetime::Extended => seconds.max(bounds.trusted_refresh_token_expiry),
+            SessionLifetime::Standard => seconds,
+            SessionLifetime::Shortened => seconds.min(bounds.risky_refresh_token_expiry),
+        };
+
+        Duration::seconds(seconds as i64)
+    }
+
+    // In seconds, bounded like `refresh_token_lifetime`
+    pub(crate) fn access_token_expiry(&self, lifetime: SessionLifetime) -> u64 {
+        let seconds = self.config.jwt.access_token_expiry;
+        let bounds = &self.config.session_lifetime;
+        match lifetime {
+            SessionLifetime::Extended => seconds.max(bounds.trusted_access_token_expiry),
+            SessionLifetime::Standard => seconds,
+            SessionLifetime::Shortened => seconds.min(bounds.risky_access_token_expiry),
+        }
+    }
+
+    // Suggest a passkey when the client can create one, the user has none yet
+    // and hasn't opted out, and the last suggestion is older than the
+    // configured interval
+    async fn passkey_prompt(
+        &self,
+        user_id: Uuid,
+        webauthn_supported: bool,
+    ) -> Result<Option<PasskeyPrompt>, AuthError> {
+        if !self.config.passkey_prompt.enabled || !webauthn_supported {
+            return Ok(None);
+        }
+
+        if !self.list_passkeys(user_id).await?.is_empty() {
+            return Ok(None);
+        }
+
+        if let Some(state) = self.db.find_passkey_prompt(user_id).await? {
+            let interval = Duration::seconds(self.config.passkey_prompt.interval as i64);
+            let recently_prompted = state
+                .last_prompted_at
+                .map_or(false, |at| Utc::now() - at < interval);
+
+            if state.dismissed_at.is_some() || recently_prompted {
+                return Ok(None);
+            }
+        }
+
+        self.db.record_passkey_prompt(user_id).await?;
+
+        Ok(Some(PasskeyPrompt {
+            enroll_endpoint: "/auth/passkeys/enroll-start".to_string(),
+            dismiss_endpoint: "/auth/passkeys/prompt/dismiss".to_string(),
+        }))
+    }
+
+    // Password is verified; hand out a token only good for the MFA step
+    pub(crate) fn mfa_pending_response(&self, user: User) -> Result<LoginResponse, AuthError> {
+        Ok(LoginResponse {
+            access_token: self.create_scoped_token(&user, TokenScope::MfaPending)?,
+            refresh_token: String::new(),
+            token_type: "Bearer".into(),
+            expires_in: self.config.jwt.scoped_token_expiry,
+            user: user.into(),
+            mfa_required: true,
+            passkey_prompt: None,
+            approval_id: None,
+            password_change_required: false,
+            mfa_enrollment_required: false,
+            policy_acceptance_required: None,
+        })
+    }
+
+    // Password is verified but expired; hand out a token only good for changing it
+    fn password_change_response(&self, user: User) -> Result<LoginResponse, AuthError> {
+        Ok(LoginResponse {
+            access_token: self.create_scoped_token(&user, TokenScope::PasswordReset)?,
+            refresh_token: String::new(),
+            token_type: "Bearer".into(),
+            expires_in: self.config.jwt.scoped_token_expiry,
+            user: user.into(),
+            mfa_required: false,
+            passkey_prompt: None,
+            approval_id: None,
+            password_change_required: true,
+            mfa_enrollment_required: false,
+            policy_acceptance_required: None,
+        })
+    }
+
+    // MFA was reset by the account risk score; hand out a token only good for enrolling again
+    fn mfa_enrollment_response(&self, user: User) -> Result<LoginResponse, AuthError> {
+        Ok(LoginResponse {
+            access_token: self.create_scoped_token(&user, TokenScope::MfaEnrollment)?,
+            refresh_token: String::new(),
+            token_type: "Bearer".into(),
+            expires_in: self.config.jwt.scoped_token_expiry,
+            user: user.into(),
+            mfa_required: false,
+            passkey_prompt: None,
+            approval_id: None,
+            password_change_required: false,
+            mfa_enrollment_required: true,
+            policy_acceptance_required: None,
+        })
+    }
+
+    // Add a signal to the account's risk score and take the protective actions
+    // whose thresholds it crosses. Best effort: the request that raised the
+    // signal gets the same answer whether or not this succeeds.
+    async fn record_account_signal(
+        &self,
+        user: &User,
+        signal: AccountSignal,
+        ip: &Option<String>,
+        user_agent: &Option<String>,
+    ) {
+        if let Err(e) = self.apply_account_signal(user, signal, ip, user_agent).await {
+            log::error!("Failed to record {} signal for user {}: {}", signal.as_str(), user.id, e);
+        }
+    }
+
+    async fn apply_account_signal(
+        &self,
+        user: &User,
+        signal: AccountSignal,
+        ip: &Option<String>,
+        user_agent: &Option<String>,
+    ) -> Result<(), AuthError> {
+        let signals = self
+            .db
+            .find_account_risk_signals(user.id, self.account_risk.window_start(Utc::now()))
+            .await?;
+
+        // A breach is one event, however many logins run into it
+        if signal == AccountSignal::BreachHit && signals.iter().any(|s| s.signal == signal.as_str()) {
+            return Ok(());
+        }
+
+        let before = AccountRisk::score(&signals);
+        let recorded = self.db.record_account_risk_signal(NewAccountRiskSignal::new(user.id, signal)).await?;
+        let score = before + recorded.weight;
+
+        for action in self.account_risk.crossed(before, score) {
+            log::warn!(
+                "Account risk score for user {} reached {}, taking action {:?}",
+                user.id,
+                score,
+                action
+            );
+
+            match action {
+                AccountRiskAction::Notify => {
+                    self.security_webhook.notify(SecurityEvent {
+                        id: Uuid::new_v4(),
+                        kind: SecurityEventKind::AccountRiskElevated,
+                        priority: "high",
+                        user_id: user.id,
+                        score: score.max(0) as u32,
+                        factors: Vec::new(),
+                        ip_address: ip.clone(),
+                        user_agent: user_agent.clone(),
+                        location: None,
+                        occurred_at: recorded.created_at,
+                    });
+                    self.notify_security_event(user, SecurityAlert::SuspiciousActivity).await;
+                }
+                AccountRiskAction::RevokeSessions => {
+                    self.db.revoke_all_sessions(user.id, true).await?;
+                    self.revoke_access_tokens(user.id).await?;
+                    self.notify_security_event(user, SecurityAlert::SessionsRevoked).await;
+                }
+                AccountRiskAction::RequireMfaReenrollment => {
+                    // Whoever is behind the activity may hold the old factor too
+                    self.db.disable_mfa(user.id, None).await?;
+                    self.db.delete_recovery_codes(user.id).await?;
+                    self.db.delete_totp_devices(user.id).await?;
+                    self.db.set_mfa_reenrollment_required(user.id, true).await?;
+                    self.db.revoke_all_sessions(user.id, true).await?;
+                    self.revoke_access_tokens(user.id).await?;
+                    self.notify_security_event(user, SecurityAlert::MfaReenrollmentRequired).await;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // The current policy, if the user has yet to accept it
+    async fn pending_policy(&self, user: &User) -> Result<Option<PolicyNotice>, AuthError> {
+        let version = match &self.config.policy.version {
+            Some(version) => version,
+            None => return Ok(None),
+        };
+
+        if self.db.has_accepted_policy(user.id, version).await? {
+            return Ok(None);
+        }
+
+        Ok(Some(PolicyNotice {
+            version: version.clone(),
+            url: self.config.policy.url.clone(),
+        }))
+    }
+
+    // Credentials are verified but the policy isn't accepted; hand out a token only
+    // good for accepting it, remembering how the user authenticated
+    fn policy_acceptance_response(
+        &self,
+        user: User,
+        amr: &[&str],
+        policy: PolicyNotice,
+    ) -> Result<LoginResponse, AuthError> {
+        let claims = JwtClaims {
+            sub: user.id,
+            iss: self.config.jwt.issuer.clone(),
+            aud: self.config.jwt.audience.clone(),
+            exp: (Utc::now() + Duration::seconds(self.config.jwt.scoped_token_expiry as i64)).timestamp() as usize,
+            iat: Utc::now().timestamp() as usize,
+            is_admin: user.is_admin,
+            token_version: user.token_version,
+            scope: TokenScope::PolicyAcceptance,
+            auth_time: None,
+            amr: amr_values(amr),
+            aal: None,
+            scopes: Vec::new(),
+            cnf: None,
+            sid: None,
+            jti: None,
+            act: None,
+        };
+
+        Ok(LoginResponse {
+            access_token: create_jwt(&claims, &self.config.jwt.secret)?,
+            refresh_token: String::new(),
+            token_type: "Bearer".into(),
+            expires_in: self.config.jwt.scoped_token_expiry,
+            user: user.into(),
+            mfa_required: false,
+            passkey_prompt: None,
+            approval_id: None,
+            password_change_required: false,
+            mfa_enrollment_required: false,
+            policy_acceptance_required: Some(policy),
+        })
+    }
+
+    // When a password set now stops being accepted, per `PASSWORD_MAX_AGE_DAYS`
+    fn password_expiry(&self, is_admin: bool) -> Option<DateTime<Utc>> {
+        match self.config.password_policy.max_age_days(is_admin) {
+            0 => None,
+            days => Some(Utc::now() + Duration::days(days as i64)),
+        }
+    }
+
+    // Users at a domain with SSO enabled sign in through their organization's IdP;
+    // platform admins keep password access so a broken IdP can still be fixed
+    async fn ensure_password_login_allowed(&self, user: &User) -> Result<(), AuthError> {
+        if !user.is_admin && self.sso_connection_for_email(&user.email).await?.is_some() {
+            return Err(AuthError::SsoRequired);
+        }
+        Ok(())
+    }
+
+    // The organization, if `user_id` may manage it
+    async fn organization_admin(&self, user_id: Uuid, organization_id: Uuid) -> Result<Organization, AuthError> {
+        let member = self.db.find_organization_member(organization_id, user_id).await?;
+        if member.map_or(true, |m| m.role() < OrganizationRole::Admin) {
+            return Err(AuthError::PermissionDenied);
+        }
+
+        self.db
+            .find_organization_by_id(organization_id)
+            .await?
+            .ok_or(AuthError::PermissionDenied)
+    }
+
+    // A domain of an organization `user_id` may manage
+    async fn organization_domain(
+        &self,
+        user_id: Uuid,
+        organization_id: Uuid,
+        domain_id: Uuid,
+    ) -> Result<OrganizationDomain, AuthError> {
+        self.organization_admin(user_id, organization_id).await?;
+
+        match self.db.find_organization_domain_by_id(domain_id).await? {
+            Some(domain) if domain.organization_id == organization_id => Ok(domain),
+            _ => Err(AuthError::PermissionDenied),
+        }
+    }
+
+    fn domain_response(&self, domain: OrganizationDomain) -> OrganizationDomainResponse {
+        OrganizationDomainResponse {
+            id: domain.id,
+            verified: domain.is_verified(),
+            verified_at: domain.verified_at,
+            last_checked_at: domain.last_checked_at,
+            auto_join: domain.auto_join,
+            txt_record_name: self.domain_verifier.record_name(&domain.domain),
+            txt_record_value: self.domain_verifier.record_value(&domain.verification_token),
+            domain: domain.domain,
+        }
+    }
+
+    // Adds a user who just verified their email to the organization that verified
+    // its domain, if that organization opted into auto-join
+    async fn auto_join_organization(&self, user: &User) -> Result<(), AuthError> {
+        let domain = match email_domain(&user.email) {
+            Some(domain) => domain,
+            None => return Ok(()),
+        };
+        let domain = match self.db.find_verified_organization_domain(&domain).await? {
+            Some(domain) if domain.auto_join => domain,
+            _ => return Ok(()),
+        };
+
+        if self
+            .join_organization(domain.organization_id, user.id, OrganizationRole::Member)
+            .await?
+        {
+            log::info!("User {} auto-joined organization {}", user.id, domain.organization_id);
+        }
+        Ok(())
+    }
+
+    async fn sso_connection_response(&self, connection: SsoConnection) -> Result<SsoConnectionResponse, AuthError> {
+        let domains = self.db.find_organization_domains(connection.organization_id).await?;
+        let config = self.sso.config();
+        let domains = domains.into_iter().filter(|d| d.is_verified()).map(|d| d.domain);
+
+        Ok(SsoConnectionResponse {
+            id: connection.id,
+            organization_id: connection.organization_id,
+            protocol: connection.protocol(),
+            enabled: connection.enabled,
+            domains: domains.collect(),
+            oidc_issuer: connection.oidc_issuer,
+            oidc_client_id: connection.oidc_client_id,
+            oidc_redirect_url: config.redirect_url.clone(),
+            saml_entity_id: config.saml_entity_id.clone(),
+            saml_acs_url: config.saml_acs_url.clone(),
+            groups_attribute: connection.groups_attribute,
+            role_mappings: connection.role_mappings,
+            default_role: connection.default_role,
+            provisioning_rules: connection.provisioning_rules,
+            updated_at: connection.updated_at,
+        })
+    }
+
+    // The enabled connection, and its organization, that users at this email's domain sign in through
+    async fn sso_connection_for_email(
+        &self,
+        email: &str,
+    ) -> Result<Option<(SsoConnection, Organization)>, AuthError> {
+        let domain = match email_domain(email) {
+            Some(domain) => domain,
+            None => return Ok(None),
+        };
+        let domain = match self.db.find_verified_organization_domain(&domain).await? {
+            Some(domain) => domain,
+            None => return Ok(None),
+        };
+
+        let connection = match self
+            .db
+            .find_sso_connection_by_organization(domain.organization_id)
+            .await?
+        {
+            Some(connection) if connection.enabled => connection,
+            _ => return Ok(None),
+        };
+
+        Ok(self
+            .db
+            .find_organization_by_id(domain.organization_id)
+            .await?
+            .map(|organization| (connection, organization)))
+    }
+
+    // A connection may be disabled between starting a login and finishing it
+    async fn enabled_sso_connection(&self, connection_id: Uuid) -> Result<SsoConnection, AuthError> {
+        self.db
+            .find_sso_connection(connection_id)
+            .await?
+            .filter(|connection| connection.enabled)
+            .ok_or_else(|| AuthError::SsoError("SSO is no longer enabled for this organization".into()))
+    }
+
+    // Find or provision the user the IdP vouched for, sync their organization
+    // role from their groups, then issue a session
+    async fn complete_sso_login(
+        &self,
+        connection: &SsoConnection,
+        identity: FederatedIdentity,
+        ip: Option<String>,
+        user_agent: Option<String>,
+        network: NetworkFingerprint,
+        locale: &str,
+    ) -> Result<LoginResponse, AuthError> {
+        // An IdP may only vouch for addresses at its organization's verified domains
+        let domain = email_domain(&identity.email).unwrap_or_default();
+        let owned = self
+            .db
+            .find_verified_organization_domain(&domain)
+            .await?
+            .map_or(false, |d| d.organization_id == connection.organization_id);
+        if !owned {
+            return Err(AuthError::SsoError(format!(
+                "{} is not a verified domain of this organization",
+                domain
+            )));
+        }
+        self.ensure_logins_open(None, Some(connection.organization_id)).await?;
+
+        // Blocked attributes are checked on every sign-in, not just the first
+        let plan = provisioning::evaluate(
+            &connection.provisioning_rules(),
+            &identity,
+            connection.role_for_groups(&identity.groups),
+        )?;
+
+        let user = match self.db.find_sso_identity(connection.id, &identity.subject).await? {
+            Some(link) => {
+                self.db.record_sso_login(link.id).await?;
+                self.db.find_user_by_id(link.user_id).await?
+            }
+            None => {
+                let user = match self.db.find_user_by_email(&identity.email).await {
+                    Ok(user) => user,
+                    Err(AuthError::UserNotFound) => self.provision_sso_user(&identity, &plan).await?,
+                    Err(e) => return Err(e),
+                };
+                let link = self
+                    .db
+                    .create_sso_identity(NewSsoIdentity {
+                        id: Uuid::new_v4(),
+                        connection_id: connection.id,
+                        user_id: user.id,
+                        subject: identity.subject.clone(),
+                    })
+                    .await?;
+                self.db.record_sso_login(link.id).await?;
+
+                // Further organizations are only joined on first sign-in, so
+                // admins there can remove the user for good
+                for (organization_id, role) in &plan.organizations {
+                    self.join_organization(*organization_id, user.id, *role)
+                        .await?;
+                }
+                user
+            }
+        };
+
+        if !user.is_active() {
+            return Err(AuthError::AccountDisabled {
+                status_token: Some(self.create_scoped_token(&user, TokenScope::AccountStatus)?),
+            });
+        }
+        let user = self.reactivate_if_archived(user, "sso").await?;
+
+        // The IdP's groups and the rules decide the role on every login; owners are left alone
+        match self.db.find_organization_member(connection.organization_id, user.id).await? {
+            Some(member) if member.role() != OrganizationRole::Owner && member.role() != plan.role => {
+                self.db.update_organization_member_role(member.id, plan.role).await?;
+            }
+            Some(_) => {}
+            None => {
+                self.join_organization(connection.organization_id, user.id, plan.role)
+                    .await?;
+            }
+        }
+
+        // Only now, so the policies of an organization just joined apply too
+        let client = LoginClient {
+            ip: &ip,
+            user_agent: &user_agent,
+            network: &network,
+            device_token: None,
+        };
+        let LoginVerdict { outcome, lifetime } = self
+            .run_verified_login_checks(&user, "sso", client, locale)
+            .await?;
+
+        if outcome == CheckOutcome::RequireApproval {
+            let login = PendingLogin {
+                user_id: user.id,
+                ip,
+                user_agent,
+                amr: amr_values(&[AMR_FEDERATED]),
+                lifetime,
+                dpop_jkt: None,
+                network,
+            };
+            return self.start_login_approval(user, login, locale).await;
+        }
+
+        if outcome == CheckOutcome::RequireMfa {
+            return self.mfa_pending_response(user);
+        }
+
+        if let Some(policy) = self.pending_policy(&user).await? {
+            return self.policy_acceptance_response(user, &[AMR_FEDERATED], policy);
+        }
+
+        // Generate tokens
+        let refresh_token = new_refresh_token();
+
+        // Save refresh token, lasting as long as the checks allowed
+        let expires_at = Utc::now() + self.refresh_token_lifetime(false, lifetime);
+        let mut session = NewSession::new(user.id, refresh_token.clone(), user_agent, ip, expires_at);
+        session.amr = amr_values(&[AMR_FEDERATED]);
+        session.lifetime = lifetime.as_str().to_string();
+        let access_token = self.create_session_access_token(&user, &[AMR_FEDERATED], &session)?;
+
+        self.start_login_session(session).await?;
+
+        // Update last login
+        self.db.update_last_login(user.id).await?;
+
+        Ok(LoginResponse {
+            access_token,
+            refresh_token,
+            token_type: "Bearer".into(),
+            expires_in: self.access_token_expiry(lifetime),
+            user: user.into(),
+            mfa_required: false,
+            passkey_prompt: None,
+            approval_id: None,
+            password_change_required: false,
+            mfa_enrollment_required: false,
+            policy_acceptance_required: None,
+        })
+    }
+
+    // An account waiting for its owner to set a password. Until then it has
+    // an unguessable one, so nobody can sign in to it.
+    async fn create_pending_user(
+        &self,
+        email: &str,
+        username: Option<&str>,
+        source: &str,
+    ) -> Result<User, AuthError> {
+        let address = normalize_email(email)?;
+        if self.db.user_exists_by_email(&address.canonical).await? {
+            return Err(AuthError::EmailExists);
+        }
+
+        let username = match username {
+            Some(username) => {
+                validate_username(username)?;
+                if self.db.user_exists_by_username(username).await? {
+                    return Err(AuthError::UsernameExists);
+                }
+                username.to_string()
+            }
+            None => self.unique_username(None, &address.canonical).await?,
+        };
+
+        let new_user = NewUser {
+            id: Uuid::new_v4(),
+            username,
+            email: address.canonical.clone(),
+            password_hash: hash_password(&Uuid::new_v4().to_string())?,
+            is_email_verified: false,
+            email_verification_token: None,
+            email_verification_sent_at: Some(Utc::now()),
+            is_admin: false,
+            password_expires_at: None,
+            is_guest: false,
+            activation_pending: true,
+            email_display: address.display_if_different(),
+        };
+        let event = user_created_event(&new_user, source);
+        self.db.create_user(new_user, event).await
+    }
+
+    // A free username based on `preferred`, or on the address's local part
+    async fn unique_username(&self, preferred: Option<&str>, email: &str) -> Result<String, AuthError> {
+        let local_part = email.split('@').next().unwrap_or_default();
+        let base: String = preferred
+            .unwrap_or(local_part)
+            .chars()
+            .filter(|c| c.is_ascii_alphanumeric() || *c == '_' || *c == '.')
+            .take(40)
+            .collect();
+        let mut username = if base.len() >= 3 { base.clone() } else { format!("user_{}", base) };
+        while self.db.user_exists_by_username(&username).await? {
+            username = format!("{}_{}", base, &Uuid::new_v4().simple().to_string()[..6]);
+        }
+        Ok(username)
+    }
+
+    // Just-in-time account for a first SSO login, shaped by the connection's
+    // provisioning rules. It gets an unguessable password, so the IdP stays the
+    // only way in.
+    async fn provision_sso_user(
+        &self,
+        identity: &FederatedIdentity,
+        plan: &ProvisioningPlan,
+    ) -> Result<User, AuthError> {
+        let address = normalize_email(&identity.email)?;
+        let username = self
+            .unique_username(plan.username.as_deref(), &address.canonical)
+            .await?;
+
+        let new_user = NewUser {
+            id: Uuid::new_v4(),
+            username,
+            email: address.canonical.clone(),
+            password_hash: hash_password(&Uuid::new_v4().to_string())?,
+            is_email_verified: true,
+            email_verification_token: None,
+            email_verification_sent_at: None,
+            is_admin: false,
+            password_expires_at: None,
+            is_guest: false,
+            activation_pending: false,
+            email_display: address.display_if_different(),
+        };
+        let event = user_created_event(&new_user, "sso");
+        let user = self.db.create_user(new_user, event).await?;
+
+        // Mapped values the profile would reject are dropped rather than failing the login
+        let changes = ProfileChanges {
+            display_name: plan
+                .display_name
+                .as_ref()
+                .map(|name| Some(name.chars().take(100).collect())),
+            locale: plan
+                .locale
+                .clone()
+                .filter(|locale| validate_locale(locale).is_ok())
+                .map(Some),
+            timezone: plan
+                .timezone
+                .clone()
+                .filter(|timezone| validate_timezone(timezone).is_ok())
+                .map(Some),
+            ..Default::default()
+        };
+        if changes.display_name.is_none() && changes.locale.is_none() && changes.timezone.is_none() {
+            return Ok(user);
+        }
+        self.db.update_profile(user.id, None, changes).await
+    }
+
+    // Adds the user unless they already belong to the organization; false if they weren't added
+    async fn join_organization(
+        &self,
+        organization_id: Uuid,
+        user_id: Uuid,
+        role: OrganizationRole,
+    ) -> Result<bool, AuthError> {
+        if self.db.find_organization_member(organization_id, user_id).await?.is_some() {
+            return Ok(false);
+        }
+        if self.db.find_organization_by_id(organization_id).await?.is_none() {
+            log::warn!("Cannot add user {} to missing organization {}", user_id, organization_id);
+            return Ok(false);
+        }
+
+        self.db
+            .add_organization_member(NewOrganizationMember {
+                id: Uuid::new_v4(),
+                organization_id,
+                user_id,
+                role: role.as_str().to_string(),
+            })
+            .await?;
+        Ok(true)
+    }
+
+    // Rules may not grant ownership, nor place users in organizations the
+    // admin saving them doesn't manage
+    async fn check_provisioning_rules(
+        &self,
+        user_id: Uuid,
+        organization_id: Uuid,
+        rules: &ProvisioningRules,
+    ) -> Result<(), AuthError> {
+        if rules.rules.len() > MAX_PROVISIONING_RULES {
+            return Err(AuthError::ValidationError(format!(
+                "At most {} provisioning rules are allowed",
+                MAX_PROVISIONING_RULES
+            )));
+        }
+
+        let mut conditions = rules.blocked.iter().chain(rules.rules.iter().flat_map(|r| r.when.iter()));
+        if conditions.any(|c| c.attribute.is_empty() || c.attribute.len() > 100) {
+            return Err(AuthError::ValidationError(
+                "Attribute names must be 1 to 100 characters".into(),
+            ));
+        }
+
+        for rule in &rules.rules {
+            if rule.role == Some(OrganizationRole::Owner)
+                || rule.organizations.iter().any(|a| a.role == OrganizationRole::Owner)
+            {
+                return Err(AuthError::ValidationError("Provisioning rules cannot grant owner".into()));
+            }
+            for assignment in &rule.organizations {
+                if assignment.organization_id != organization_id {
+                    self.organization_admin(user_id, assignment.organization_id).await?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Hold the login and email the owner a one-time approval link; the client
+    // gets only the approval id to poll `complete_login_approval` with
+    pub(crate) async fn start_login_approval(
+        &self,
+        user: User,
+        login: PendingLogin,
+        locale: &str,
+    ) -> Result<LoginResponse, AuthError> {
+        let device = login
+            .user_agent
+            .as_deref()
+            .map(DeviceInfo::parse)
+            .unwrap_or_default()
+            .describe(None);
+        let ip_display = login.ip.clone().unwrap_or_else(|| "unknown IP".to_string());
+
+        let (approval_id, approve_token) = self.login_approvals.create(login);
+        self.email_service
+            .send_login_approval_email(&user.email, &approve_token, &device, &ip_display, locale)
+            .await?;
+
+        Ok(LoginResponse {
+            access_token: String::new(),
+            refresh_token: String::new(),
+            token_type: "Bearer".into(),
+            expires_in: 0,
+            user: user.into(),
+            mfa_required: false,
+            passkey_prompt: None,
+            approval_id: Some(approval_id),
+            password_change_required: false,
+            mfa_enrollment_required: false,
+            policy_acceptance_required: None,
+        })
+    }
+
+    // `amr` lists the methods the user just authenticated with; empty when the
+    // token is reissued without the user proving anything (e.g. a refresh)
+    // Email the primary and every verified backup address. Alerts are best
+    // effort: a mail failure must not undo the change being reported.
+    async fn notify_security_event(&self, user: &User, alert: SecurityAlert<'_>) {
+        if alert.is_optional() {
+            match self.notification_preferences(user.id).await {
+                Ok(preferences) if !preferences.allows(NotificationCategory::SecurityAlerts) => return,
+                Ok(_) => {}
+                Err(e) => log::warn!("Failed to load notification preferences for user {}: {}", user.id, e),
+            }
+        }
+
+        let mut addresses = vec![user.email.clone()];
+        match self.db.find_backup_emails_by_user_id(user.id).await {
+            Ok(emails) => addresses.extend(
+                emails.into_iter().filter(|e| e.is_verified).map(|e| e.email),
+            ),
+            Err(e) => log::warn!("Failed to load backup emails for user {}: {}", user.id, e),
+        }
+
+        for address in &addresses {
+            self.send_security_alert(user, address, &alert).await;
+        }
+    }
+
+    async fn send_security_alert(&self, user: &User, address: &str, alert: &SecurityAlert<'_>) {
+        let locale = user.locale.as_deref().unwrap_or(&self.config.i18n.default_locale);
+        let unsubscribe_token = alert
+            .is_optional()
+            .then(|| self.unsubscribe_token(user.id, address, NotificationCategory::SecurityAlerts).ok())
+            .flatten();
+        if let Err(e) = self
+            .email_service
+            .send_security_alert(address, alert, unsubscribe_token.as_deref(), locale)
+            .await
+        {
+            log::warn!("Failed to send security alert to user {}: {}", user.id, e);
+        }
+    }
+
+    async fn notification_preferences(&self, user_id: Uuid) -> Result<NotificationPreferences, AuthError> {
+        Ok(self
+            .db
+            .find_notification_preferences(user_id)
+            .await?
+            .unwrap_or_else(|| NotificationPreferences::defaults(user_id)))
+    }
+
+    // Unsubscribe links are bound to the address they're sent to, so one stops
+    // working once that address is no longer on the account
+    fn unsubscribe_token(&self, user_id: Uuid, address: &str, category: NotificationCategory) -> Result<String, AuthError> {
+        let ttl = Duration::seconds(self.config.action_tokens.unsubscribe_ttl as i64);
+        let claims = ActionClaims::new(ActionPurpose::Unsubscribe, user_id, ttl)
+            .with_binding(address.to_lowercase())
+            .with_data(serde_json::json!({ "category": category }));
+        self.action_tokens.issue(&claims)
+    }
+
+    // The user and category of an unsubscribe link. Nothing is redeemed: the
+    // link only changes preferences, and mail clients often follow it twice.
+    async fn verify_unsubscribe_link(&self, token: &str) -> Result<(Uuid, NotificationCategory), AuthError> {
+        let claims = self.action_tokens.verify_within(
+            token,
+            ActionPurpose::Unsubscribe,
+            Duration::seconds(self.config.action_tokens.unsubscribe_ttl as i64),
+        )?;
+        let category = claims
+            .data
+            .as_ref()
+            .and_then(|data| data.get("category"))
+            .and_then(|category| serde_json::from_value(category.clone()).ok())
+            .ok_or(AuthError::InvalidToken)?;
+        let address = claims.binding.as_deref().ok_or(AuthError::InvalidToken)?;
+
+        let user = match self.db.find_user_by_id(claims.sub).await {
+            Err(AuthError::UserNotFound) => return Err(AuthError::InvalidToken),
+            result => result?,
+        };
+        let still_theirs = user.email.eq_ignore_ascii_case(address)
+            || self
+                .db
+                .find_backup_emails_by_user_id(user.id)
+                .await?
+                .iter()
+                .any(|e| e.is_verified && e.email.eq_ignore_ascii_case(address));
+        if !still_theirs {
+            return Err(AuthError::InvalidToken);
+        }
+
+        Ok((user.id, category))
+    }
+
+    // `session_id` names the session the token was issued with, so requests
+    // made with it keep that session from going idle
+    pub(crate) fn create_access_token(&self, user: &User, amr: &[&str], session_id: Option<Uuid>) -> Result<String, AuthError> {
+        self.create_bound_access_token(user, amr, session_id, None)
+    }
+
+    // An access token that, given a DPoP key thumbprint, is only accepted
+    // alongside a proof signed with that key
+    fn create_bound_access_token(
+        &self,
+        user: &User,
+        amr: &[&str],
+        session_id: Option<Uuid>,
+        dpop_jkt: Option<&str>,
+    ) -> Result<String, AuthError> {
+        let claims = self.access_token_claims(user, amr, session_id, dpop_jkt);
+        create_jwt(&claims, &self.config.jwt.secret)
+    }
+
+    // The first access token of a session a login starts, lasting as long as
+    // the session's lifetime allows
+    pub(crate) fn create_session_access_token(&self, user: &User, amr: &[&str], session: &NewSession) -> Result<String, AuthError> {
+        let mut claims = self.access_token_claims(user, amr, Some(session.id), session.dpop_jkt.as_deref());
+        claims.exp = claims.iat + self.access_token_expiry(session.lifetime()) as usize;
+        create_jwt(&claims, &self.config.jwt.secret)
+    }
+
+    // A refreshed session's token keeps the methods the session was started
+    // with, and so its assurance level, but the user proved nothing just now:
+    // without an `auth_time` it can't pass a step-up check
+    fn create_refreshed_access_token(&self, user: &User, session: &NewSession) -> Result<String, AuthError> {
+        let amr: Vec<&str> = session.amr.iter().map(String::as_str).collect();
+        let mut claims = self.access_token_claims(user, &amr, Some(session.id), session.dpop_jkt.as_deref());
+        claims.exp = claims.iat + self.access_token_expiry(session.lifetime()) as usize;
+        claims.auth_time = None;
+        create_jwt(&claims, &self.config.jwt.secret)
+    }
+
+    fn access_token_claims(
+        &self,
+        user: &User,
+        amr: &[&str],
+        session_id: Option<Uuid>,
+        dpop_jkt: Option<&str>,
+    ) -> JwtClaims {
+        let auth_time = if amr.is_empty() {
+            None
+        } else {
+            Some(Utc::now().timestamp() as usize)
+        };
+
+        JwtClaims {
+            sub: user.id,
+            iss: self.config.jwt.issuer.clone(),
+            aud: self.config.jwt.audience.clone(),
+            exp: (Utc::now() + Duration::seconds(self.config.jwt.access_token_expiry as i64)).timestamp() as usize,
+            iat: Utc::now().timestamp() as usize,
+            is_admin: user.is_admin,
+            token_version: user.token_version,
+            scope: if user.is_guest { TokenScope::Guest } else { TokenScope::Full },
+            auth_time,
+            amr: amr_values(amr),
+            aal: assurance_level(amr),
+            scopes: default_scopes(user.is_admin),
+            cnf: dpop_jkt.map(|jkt| Confirmation { jkt: jkt.to_string() }),
+            sid: session_id,
+            jti: Some(Uuid::new_v4()),
+            act: None,
+        }
+    }
+
+    // Intermediate token that only routes accepting `scope` will take
+    fn create_scoped_token(&self, user: &User, scope: TokenScope) -> Result<String, AuthError> {
+        let claims = JwtClaims {
+            sub: user.id,
+            iss: self.config.jwt.issuer.clone(),
+            aud: self.config.jwt.audience.clone(),
+            exp: (Utc::now() + Duration::seconds(self.config.jwt.scoped_token_expiry as i64)).timestamp() as usize,
+            iat: Utc::now().timestamp() as usize,
+            is_admin: user.is_admin,
+            token_version: user.token_version,
+            scope,
+            auth_time: None,
+            amr: Vec::new(),
+            aal: None,
+            scopes: Vec::new(),
+            cnf: None,
+            sid: None,
+            jti: None,
+            act: None,
+        };
+
+        create_jwt(&claims, &self.config.jwt.secret)
+    }
+
+    // A code from the primary authenticator or any confirmed additional device
+    async fn verify_any_totp(&self, user: &User, code: &str) -> Result<bool, AuthError> {
+        if let Some(secret) = &user.mfa_secret {
+            if self.mfa_service.verify_totp(secret, code) {
+                return Ok(true);
+            }
+        }
+
+        let devices = self.db.find_totp_devices_by_user_id(user.id).await?;
+        for device in devices.iter().filter(|d| d.is_confirmed) {
+            if self.mfa_service.verify_totp(&device.secret, code) {
+                self.db.touch_totp_device(device.id).await?;
+                return Ok(true);
+            }
+        }
+
+        Ok(false)
+    }
+
+    async fn find_totp_device(&self, user_id: Uuid, device_id: Uuid) -> Result<TotpDevice, AuthError> {
+        self.db
+            .find_totp_devices_by_user_id(user_id)
+            .await?
+            .into_iter()
+            .find(|d| d.id == device_id)
+            .ok_or(AuthError::PermissionDenied)
+    }
+
+    // Exchange a verified second factor for a full session
+    pub(crate) async fn complete_mfa_login(
+        &self,
+        user: User,
+        amr: &[&str],
+        ip: Option<String>,
+        user_agent: Option<String>,
+    ) -> Result<MfaVerifyResponse, AuthError> {
+        // The password step left these alone; the user may have typed either identifier
+        let tarpit_keys = [LoginTarpit::keys(&user.username, None), LoginTarpit::keys(&user.email, None)].concat();
+        self.record_login_success(&user, &tarpit_keys).await?;
+
+        if user.password_expired() {
+            return Ok(MfaVerifyResponse {
+                access_token: self.create_scoped_token(&user, TokenScope::PasswordReset)?,
+                refresh_token: String::new(),
+                token_type: "Bearer".into(),
+                expires_in: self.config.jwt.scoped_token_expiry,
+                user: user.into(),
+                password_change_required: true,
+                policy_acceptance_required: None,
+                recovery_codes: None,
+            });
+        }
+
+        if let Some(policy) = self.pending_policy(&user).await? {
+            let response = self.policy_acceptance_response(user, amr, policy)?;
+            return Ok(MfaVerifyResponse {
+                access_token: response.access_token,
+                refresh_token: response.refresh_token,
+                token_type: response.token_type,
+                expires_in: response.expires_in,
+                user: response.user,
+                password_change_required: false,
+                policy_acceptance_required: response.policy_acceptance_required,
+                recovery_codes: None,
+            });
+        }
+
+        // Generate tokens
+        let refresh_token = new_refresh_token();
+
+        // Save refresh token
+        let expires_at = Utc::now() + Duration::seconds(self.config.jwt.refresh_token_expiry as i64);
+        let mut session = NewSession::new(user.id, refresh_token.clone(), user_agent, ip, expires_at);
+        session.amr = amr_values(amr);
+        let access_token = self.create_access_token(&user, amr, Some(session.id))?;
+
+        self.start_login_session(session).await?;
+
+        // Update last login
+        self.db.update_last_login(user.id).await?;
+
+        Ok(MfaVerifyResponse {
+            access_token,
+            refresh_token,
+            token_type: "Bearer".into(),
+            expires_in: self.config.jwt.access_token_expiry,
+            user: user.into(),
+            password_change_required: false,
+            policy_acceptance_required: None,
+            recovery_codes: None,
+        })
+    }
+
+    async fn generate_recovery_codes(&self, user_id: Uuid) -> Result<Vec<String>, AuthError> {
+        let codes: Vec<String> = (0..self.config.recovery_codes.count)
+            .map(|_| self.mfa_service.generate_recovery_code())
+            .collect();
+
+        // Save them in one go
+        let recovery_codes = codes
+            .iter()
+            .map(|code| NewMfaRecoveryCode {
+                id: Uuid::new_v4(),
+                user_id,
+                code: code.clone(),
+            })
+            .collect();
+        self.db.create_recovery_codes(recovery_codes).await?;
+
+        Ok(codes)
+    }
+}
+
+// `token_type` of a token response, per RFC 9449
+// What a client sent along with its credentials, for the login pipeline
+pub(crate) struct LoginClient<'a> {
+    pub(crate) ip: &'a Option<String>,
+    pub(crate) user_agent: &'a Option<String>,
+    pub(crate) network: &'a NetworkFingerprint,
+    pub(crate) device_token: Option<&'a Secret<String>>, // Of a device trusted at an email code login
+}
+
+// A new session's refresh token. In a region, it starts with the region's
+// name, so it can be redeemed without asking the directory.
+fn new_refresh_token() -> String {
+    match DatabaseConnection::current_region() {
+        Some(region) => format!("{}{}{}", region, REFRESH_TOKEN_REGION_SEPARATOR, Uuid::new_v4()),
+        None => Uuid::new_v4().to_string(),
+    }
+}
+
+fn token_type(dpop_jkt: &Option<String>) -> String {
+    if dpop_jkt.is_some() { "DPoP" } else { "Bearer" }.to_string()
+}
+
+// `amr` as stored on a session
+fn amr_values(amr: &[&str]) -> Vec<String> {
+    amr.iter().map(|m| m.to_string()).collect()
+}
+
+// Outbox events, written in the same transaction as the change they describe
+pub(crate) fn user_created_event(user: &NewUser, source: &str) -> NewOutboxEvent {
+    NewOutboxEvent::new(
+        EventType::UserCreated,
+        user.id,
+        serde_json::json!({
+            "username": user.username,
+            "email": user.email,
+            "is_guest": user.is_guest,
+            "activation_pending": user.activation_pending,
+            "source": source,
+        }),
+    )
+}
+
+/// What a reactivation link is bound to, so it only works for the archiving it
+/// was sent for
+fn archive_binding(user: &User) -> String {
+    user.archived_at.map(|at| at.to_rfc3339()).unwrap_or_default()
+}
+
+/// A policy for `scope` from what was submitted, with country codes upper cased
+fn new_login_policy(scope: LoginPolicyScope, updated_by: Uuid, data: LoginPolicyRequest) -> Result<NewLoginPolicy, AuthError> {
+    let mut allowed_countries = Vec::with_capacity(data.allowed_countries.len());
+    for country in &data.allowed_countries {
+        let country = country.trim().to_ascii_uppercase();
+        if country.len() != 2 || !country.chars().all(|c| c.is_ascii_alphabetic()) {
+            return Err(AuthError::ValidationError(format!("Invalid country code: {}", country)));
+        }
+        if !allowed_countries.contains(&country) {
+            allowed_countries.push(country);
+        }
+    }
+
+    match (data.window_start, data.window_end) {
+        (Some(start), Some(end)) if start == end => {
+            return Err(AuthError::ValidationError("The window must not start and end at the same time".into()))
+        }
+        (Some(_), None) | (None, Some(_)) => {
+            return Err(AuthError::ValidationError("The window needs both a start and an end".into()))
+        }
+        _ => {}
+    }
+
+    let (user_id, organization_id) = match scope {
+        LoginPolicyScope::User(user_id) => (Some(user_id), None),
+        LoginPolicyScope::Organization(organization_id) => (None, Some(organization_id)),
+    };
+    Ok(NewLoginPolicy {
+        id: Uuid::new_v4(),
+        user_id,
+        organization_id,
+        allowed_countries,
+        window_start: data.window_start,
+        window_end: data.window_end,
+        utc_offset_minutes: data.utc_offset_minutes,
+        on_violation: data.on_violation.as_str().to_string(),
+        updated_by: Some(updated_by),
+    })
+}
+
+fn logged_in_event(session: &NewSession) -> NewOutboxEvent {
+    NewOutboxEvent::new(
+        EventType::LoggedIn,
+        session.user_id,
+        serde_json::json!({
+            "session_id": session.id,
+            "ip_address": session.ip_address,
+            "browser": session.browser,
+            "os": session.os,
+            "device_class": session.device_class,
+            "amr": session.amr,
+        }),
+    )
+}
+
+fn reactivated_event(user_id: Uuid, via: &str) -> NewOutboxEvent {
+    NewOutboxEvent::new(EventType::Reactivated, user_id, serde_json::json!({ "via": via }))
+}
+
+fn password_changed_event(user_id: Uuid, via: &str) -> NewOutboxEvent {
+    NewOutboxEvent::new(EventType::PasswordChanged, user_id, serde_json::json!({ "via": via }))
+}
+
+pub(crate) fn status_changed_event(user_id: Uuid, status: AccountStatus, actor_id: Option<Uuid>) -> NewOutboxEvent {
+    NewOutboxEvent::new(
+        EventType::StatusChanged,
+        user_id,
+        serde_json::json!({ "status": status, "actor_id": actor_id }),
+    )
 }
@@ -0,0 +1,252 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+
+use crate::config::{BruteForceCombine, BruteForceConfig};
+use crate::models::User;
+
+/// How far repeated failures have escalated, least severe first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Escalation {
+    None,
+    Captcha,
+    Lockout,
+}
+
+/// What to do about a login, given the failures behind it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Verdict {
+    pub captcha: bool,      // Require a solved CAPTCHA
+    pub lock_account: bool, // Refuse password logins to the account for a while
+    pub lock_ip: bool,      // Refuse password logins from the IP for a while
+}
+
+// Failed password logins counted per IP, in memory, alongside the per-account
+// counts stored on `users`. The login tarpit records and reads its per-IP
+// delays through here too. Rotating IPs doesn't reset an account's count, and
+// spraying many accounts from one IP doesn't reset the IP's.
+pub struct BruteForceGuard {
+    config: BruteForceConfig,
+    ips: Mutex<HashMap<String, IpFailures>>,
+}
+
+struct IpFailures {
+    count: u32,
+    last: Instant,
+    locked_until: Option<Instant>,
+}
+
+impl BruteForceGuard {
+    pub fn new(config: &BruteForceConfig) -> Self {
+        BruteForceGuard {
+            config: config.clone(),
+            ips: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Failures older than this don't count against an account
+    pub fn window_start(&self, now: DateTime<Utc>) -> DateTime<Utc> {
+        now - chrono::Duration::seconds(self.config.window as i64)
+    }
+
+    pub fn lockout_until(&self, now: DateTime<Utc>) -> DateTime<Utc> {
+        now + chrono::Duration::seconds(self.config.lockout_duration as i64)
+    }
+
+    /// The account's failures that still count
+    pub fn account_failures(&self, user: &User, now: DateTime<Utc>) -> u32 {
+        match user.last_failed_login_at {
+            Some(at) if at >= self.window_start(now) => user.failed_login_count.max(0) as u32,
+            _ => 0,
+        }
+    }
+
+    pub fn ip_failures(&self, ip: Option<&str>) -> u32 {
+        let window = Duration::from_secs(self.config.window);
+        ip.and_then(|ip| self.ips.lock().unwrap().get(ip).map(|f| (f.count, f.last)))
+            .filter(|(_, last)| last.elapsed() < window)
+            .map_or(0, |(count, _)| count)
+    }
+
+    /// Seconds left on the IP's lockout, if it has one
+    pub fn ip_locked_for(&self, ip: Option<&str>) -> Option<u64> {
+        let ip = ip?;
+        let ips = self.ips.lock().unwrap();
+        let until = ips.get(ip)?.locked_until?;
+        let left = until.checked_duration_since(Instant::now())?;
+        Some(left.as_secs().max(1))
+    }
+
+    /// Count a failure from `ip`; returns its failures inside the window
+    pub fn record_ip_failure(&self, ip: &str) -> u32 {
+        let window = Duration::from_secs(self.config.window);
+        let mut ips = self.ips.lock().unwrap();
+        ips.retain(|_, f| f.last.elapsed() < window || f.locked_until.is_some_and(|until| until > Instant::now()));
+
+        let failures = ips.entry(ip.to_string()).or_insert(IpFailures {
+            count: 0,
+            last: Instant::now(),
+            locked_until: None,
+        });
+        if failures.last.elapsed() >= window {
+            failures.count = 0;
+        }
+        failures.count += 1;
+        failures.last = Instant::now();
+        failures.count
+    }
+
+    /// Refuse logins from `ip` for the lockout duration; its count starts over
+    pub fn lock_ip(&self, ip: &str) {
+        if let Some(failures) = self.ips.lock().unwrap().get_mut(ip) {
+            failures.count = 0;
+            failures.locked_until = Some(Instant::now() + Duration::from_secs(self.config.lockout_duration));
+        }
+    }
+
+    /// Locked IPs with the seconds left on each, longest first
+    pub fn locked_ips(&self) -> Vec<(String, u64)> {
+        let now = Instant::now();
+        let mut locked: Vec<(String, u64)> = self
+            .ips
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|(ip, f)| {
+                let left = f.locked_until?.checked_duration_since(now)?;
+                Some((ip.clone(), left.as_secs().max(1)))
+            })
+            .collect();
+        locked.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        locked
+    }
+
+    /// Lift an IP's lockout and forget its failures; false if it wasn't locked
+    pub fn unlock_ip(&self, ip: &str) -> bool {
+        let mut ips = self.ips.lock().unwrap();
+        let locked = ips
+            .get(ip)
+            .and_then(|f| f.locked_until)
+            .is_some_and(|until| until > Instant::now());
+        if locked {
+            ips.remove(ip);
+        }
+        locked
+    }
+
+    /// Combine the two counts according to `BRUTE_FORCE_COMBINE`. An IP is
+    /// only locked on its own count, and only when either count may escalate.
+    pub fn verdict(&self, account_failures: u32, ip_failures: u32) -> Verdict {
+        if !self.config.enabled {
+            return Verdict {
+                captcha: false,
+                lock_account: false,
+                lock_ip: false,
+            };
+        }
+
+        let account = escalation(account_failures, self.config.account_captcha_after, self.config.account_lockout_after);
+        let ip = escalation(ip_failures, self.config.ip_captcha_after, self.config.ip_lockout_after);
+        let combined = match self.config.combine {
+            BruteForceCombine::Or => account.max(ip),
+            BruteForceCombine::And => account.min(ip),
+        };
+
+        Verdict {
+            captcha: combined >= Escalation::Captcha,
+            lock_account: match self.config.combine {
+                BruteForceCombine::Or => account == Escalation::Lockout,
+                BruteForceCombine::And => combined == Escalation::Lockout,
+            },
+            lock_ip: self.config.combine == BruteForceCombine::Or && ip == Escalation::Lockout,
+        }
+    }
+}
+
+fn escalation(failures: u32, captcha_after: u32, lockout_after: u32) -> Escalation {
+    if lockout_after > 0 && failures >= lockout_after {
+        Escalation::Lockout
+    } else if captcha_after > 0 && failures >= captcha_after {
+        Escalation::Captcha
+    } else {
+        Escalation::None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn guard(combine: BruteForceCombine) -> BruteForceGuard {
+        BruteForceGuard::new(&BruteForceConfig {
+            enabled: true,
+            combine,
+            window: 900,
+            account_captcha_after: 3,
+            account_lockout_after: 10,
+            ip_captcha_after: 10,
+            ip_lockout_after: 50,
+            lockout_duration: 900,
+        })
+    }
+
+    #[test]
+    fn test_or_escalates_on_either_count() {
+        let guard = guard(BruteForceCombine::Or);
+
+        assert!(!guard.verdict(2, 9).captcha);
+        // Rotating IPs: the account alone gets there
+        assert!(guard.verdict(3, 0).captcha);
+        assert!(guard.verdict(10, 0).lock_account);
+        // Spraying accounts: the IP alone gets there, without locking the account
+        let verdict = guard.verdict(0, 50);
+        assert!(verdict.captcha && verdict.lock_ip && !verdict.lock_account);
+    }
+
+    #[test]
+    fn test_and_needs_both_counts() {
+        let guard = guard(BruteForceCombine::And);
+
+        assert!(!guard.verdict(10, 0).captcha);
+        assert!(!guard.verdict(0, 50).captcha);
+        assert!(guard.verdict(3, 10).captcha);
+
+        let verdict = guard.verdict(10, 50);
+        assert!(verdict.lock_account && !verdict.lock_ip);
+    }
+
+    #[test]
+    fn test_ip_failures_and_lockout() {
+        let guard = guard(BruteForceCombine::Or);
+
+        assert_eq!(guard.record_ip_failure("203.0.113.7"), 1);
+        assert_eq!(guard.record_ip_failure("203.0.113.7"), 2);
+        assert_eq!(guard.ip_failures(Some("203.0.113.7")), 2);
+        assert_eq!(guard.ip_failures(Some("198.51.100.1")), 0);
+        assert!(guard.ip_locked_for(Some("203.0.113.7")).is_none());
+
+        guard.lock_ip("203.0.113.7");
+        assert!(guard.ip_locked_for(Some("203.0.113.7")).is_some());
+        assert_eq!(guard.ip_failures(Some("203.0.113.7")), 0);
+    }
+
+    #[test]
+    fn test_locked_ips_are_listed_and_unlocked() {
+        let guard = guard(BruteForceCombine::Or);
+        guard.record_ip_failure("203.0.113.7");
+        guard.record_ip_failure("198.51.100.1");
+        guard.lock_ip("203.0.113.7");
+
+        let locked = guard.locked_ips();
+        assert_eq!(locked.len(), 1);
+        assert_eq!(locked[0].0, "203.0.113.7");
+        assert!(locked[0].1 > 0);
+
+        assert!(!guard.unlock_ip("198.51.100.1"));
+        assert!(guard.unlock_ip("203.0.113.7"));
+        assert!(guard.ip_locked_for(Some("203.0.113.7")).is_none());
+        assert!(guard.locked_ips().is_empty());
+    }
+}
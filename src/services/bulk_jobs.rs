@@ -0,0 +1,226 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::Utc;
+use uuid::Uuid;
+
+use crate::config::BulkJobConfig;
+use crate::db::DatabaseConnection;
+use crate::errors::AuthError;
+use crate::middleware::auth::UserCache;
+use crate::models::{AccountStatus, BulkAction, BulkJob, BulkJobResult, BulkJobStatus, BulkOutcome, User};
+use crate::services::auth::status_changed_event;
+
+// A job still running after this long is taken to have lost its runner, e.g.
+// to a restart, and is started over. Every action is safe to repeat.
+const RUNNING_LEASE_SECS: i64 = 3600;
+
+// Runs the bulk jobs admins queue, one at a time and oldest first. Each list
+// entry or matched account gets a line in the job's results, so a dry run
+// shows exactly what the real run would do. One account failing doesn't stop
+// the rest; the job only fails if its accounts can't be looked up at all.
+pub struct BulkJobRunner {
+    db: Arc<DatabaseConnection>,
+    user_cache: Arc<UserCache>,
+    config: BulkJobConfig,
+}
+
+impl BulkJobRunner {
+    pub fn new(db: Arc<DatabaseConnection>, user_cache: Arc<UserCache>, config: &BulkJobConfig) -> Self {
+        BulkJobRunner {
+            db,
+            user_cache,
+            config: config.clone(),
+        }
+    }
+
+    /// Run queued jobs on the configured interval until the process exits;
+    /// spawn at startup. Returns at once when polling is off.
+    pub async fn run(self: Arc<Self>) {
+        if self.config.poll_interval == 0 {
+            return;
+        }
+        let interval = Duration::from_secs(self.config.poll_interval);
+
+        loop {
+            match self.run_queued().await {
+                Ok(0) => {}
+                Ok(ran) => log::info!("Ran {} bulk jobs", ran),
+                Err(e) => log::error!("Running bulk jobs failed: {}", e),
+            }
+            tokio::time::sleep(interval).await;
+        }
+    }
+
+    /// Run every queued job, returning how many
+    pub async fn run_queued(&self) -> Result<usize, AuthError> {
+        let mut ran = 0;
+        loop {
+            let stale_before = Utc::now() - chrono::Duration::seconds(RUNNING_LEASE_SECS);
+            let job = match self.db.claim_bulk_job(stale_before).await? {
+                Some(job) => job,
+                None => return Ok(ran),
+            };
+            self.run_job(&job).await?;
+            ran += 1;
+        }
+    }
+
+    async fn run_job(&self, job: &BulkJob) -> Result<(), AuthError> {
+        let (status, results, error) = match self.results(job).await {
+            Ok(results) => (BulkJobStatus::Completed, results, None),
+            Err(e) => (BulkJobStatus::Failed, Vec::new(), Some(e.to_string())),
+        };
+        let results = serde_json::to_value(&results).map_err(|e| AuthError::InternalServerError(e.to_string()))?;
+        self.db.finish_bulk_job(job.id, status, results, error.clone()).await?;
+
+        match error {
+            Some(error) => log::error!("Bulk job {} ({}) failed: {}", job.id, job.action, error),
+            None => log::info!(
+                "Bulk job {} ({}{}) completed",
+                job.id,
+                job.action,
+                if job.dry_run { ", dry run" } else { "" }
+            ),
+        }
+        Ok(())
+    }
+
+    async fn results(&self, job: &BulkJob) -> Result<Vec<BulkJobResult>, AuthError> {
+        let action = job.bulk_action().map_err(AuthError::ValidationError)?;
+        let (targets, mut results) = self.targets(&action).await?;
+
+        // An account listed twice, e.g. by email and by username, is only changed once
+        let mut seen = HashSet::new();
+        for (target, user) in targets {
+            let skipped = if seen.insert(user.id) {
+                skip_reason(job, &action, &user)
+            } else {
+                Some("Already matched by an earlier entry".to_string())
+            };
+
+            let (outcome, detail) = match skipped {
+                Some(reason) => (BulkOutcome::Skipped, Some(reason)),
+                None if job.dry_run => (BulkOutcome::WouldChange, None),
+                None => match self.change(job, &action, &user).await {
+                    Ok(()) => (BulkOutcome::Changed, None),
+                    Err(e) => (BulkOutcome::Failed, Some(e.to_string())),
+                },
+            };
+            results.push(BulkJobResult {
+                target,
+                user_id: Some(user.id),
+                outcome,
+                detail,
+            });
+        }
+
+        Ok(results)
+    }
+
+    // The accounts the action applies to, each with the list entry that
+    // matched it, and a result for every entry that matched none
+    async fn targets(&self, action: &BulkAction) -> Result<(Vec<(String, User)>, Vec<BulkJobResult>), AuthError> {
+        let mut targets = Vec::new();
+        let mut not_found = Vec::new();
+
+        match action {
+            BulkAction::RevokeSessions { user_ids } => {
+                for user_id in user_ids {
+                    match self.db.find_user_by_id(*user_id).await {
+                        Ok(user) => targets.push((user_id.to_string(), user)),
+                        Err(AuthError::UserNotFound) => not_found.push(not_found_result(user_id.to_string())),
+                        Err(e) => return Err(e),
+                    }
+                }
+            }
+            BulkAction::ForcePasswordReset { accounts, .. } => {
+                for account in accounts {
+                    match self.db.find_user_by_username_or_email(account).await {
+                        Ok(user) => targets.push((account.clone(), user)),
+                        Err(AuthError::UserNotFound) => not_found.push(not_found_result(account.clone())),
+                        Err(e) => return Err(e),
+                    }
+                }
+            }
+            BulkAction::DeactivateDomain { domain, .. } => {
+                for user in self.db.find_users_by_email_domain(domain).await? {
+                    targets.push((user.email.clone(), user));
+                }
+            }
+        }
+
+        Ok((targets, not_found))
+    }
+
+    async fn change(&self, job: &BulkJob, action: &BulkAction, user: &User) -> Result<(), AuthError> {
+        match action {
+            BulkAction::RevokeSessions { .. } => {
+                self.sign_out_everywhere(user.id).await?;
+            }
+            BulkAction::ForcePasswordReset { revoke_sessions, .. } => {
+                self.db.expire_passwords(Some(vec![user.id])).await?;
+                if *revoke_sessions {
+                    self.sign_out_everywhere(user.id).await?;
+                }
+            }
+            BulkAction::DeactivateDomain { reason, .. } => {
+                self.db
+                    .set_account_status(
+                        user.id,
+                        None,
+                        AccountStatus::Suspended,
+                        reason,
+                        job.created_by,
+                        status_changed_event(user.id, AccountStatus::Suspended, job.created_by),
+                    )
+                    .await?;
+                self.sign_out_everywhere(user.id).await?;
+            }
+        }
+
+        self.user_cache.invalidate(user.id);
+        Ok(())
+    }
+
+    async fn sign_out_everywhere(&self, user_id: Uuid) -> Result<(), AuthError> {
+        self.db.revoke_all_sessions(user_id, true).await?;
+        self.db.bump_token_version(user_id).await?;
+        self.db.delete_trusted_devices_by_user_id(user_id).await
+    }
+}
+
+// Why an account the action matched is left alone
+fn skip_reason(job: &BulkJob, action: &BulkAction, user: &User) -> Option<String> {
+    match action {
+        BulkAction::RevokeSessions { .. } => None,
+        BulkAction::ForcePasswordReset { .. } => {
+            if user.is_guest || user.activation_pending {
+                Some("The account has no password yet".to_string())
+            } else {
+                None
+            }
+        }
+        BulkAction::DeactivateDomain { .. } => {
+            if job.created_by == Some(user.id) {
+                Some("The admin who started the job".to_string())
+            } else if user.is_admin {
+                Some("Admin accounts are deactivated one at a time".to_string())
+            } else if !user.is_active() {
+                Some(format!("Already {}", user.status))
+            } else {
+                None
+            }
+        }
+    }
+}
+
+fn not_found_result(target: String) -> BulkJobResult {
+    BulkJobResult {
+        target,
+        user_id: None,
+        outcome: BulkOutcome::NotFound,
+        detail: None,
+    }
+}
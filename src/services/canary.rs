@@ -0,0 +1,94 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use chrono::{DateTime, Duration, Utc};
+
+use crate::config::CanaryConfig;
+use crate::models::BlockedSource;
+
+// Addresses that tried a canary credential, refused until their block runs
+// out or an admin lifts it. Kept in memory like the login tarpit, so each
+// instance blocks the sources it caught.
+pub struct SourceBlocklist {
+    duration: Duration,
+    blocked: Mutex<HashMap<String, DateTime<Utc>>>,
+}
+
+impl SourceBlocklist {
+    pub fn new(config: &CanaryConfig) -> Self {
+        SourceBlocklist {
+            duration: Duration::seconds(config.block_duration as i64),
+            blocked: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Block `ip` for the configured duration; `None` when blocking is off
+    pub fn block(&self, ip: &str, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        if self.duration <= Duration::zero() {
+            return None;
+        }
+
+        let until = now + self.duration;
+        self.blocked.lock().unwrap().insert(ip.to_string(), until);
+        Some(until)
+    }
+
+    pub fn is_blocked(&self, ip: &str, now: DateTime<Utc>) -> bool {
+        self.blocked
+            .lock()
+            .unwrap()
+            .get(ip)
+            .map_or(false, |until| *until > now)
+    }
+
+    /// Sources still blocked, the longest-running block first
+    pub fn blocked(&self, now: DateTime<Utc>) -> Vec<BlockedSource> {
+        let mut blocked = self.blocked.lock().unwrap();
+        blocked.retain(|_, until| *until > now);
+
+        let mut sources: Vec<BlockedSource> = blocked
+            .iter()
+            .map(|(ip, until)| BlockedSource {
+                ip: ip.clone(),
+                blocked_until: *until,
+            })
+            .collect();
+        sources.sort_by(|a, b| b.blocked_until.cmp(&a.blocked_until));
+        sources
+    }
+
+    /// Lift a block early; false if `ip` wasn't blocked
+    pub fn unblock(&self, ip: &str) -> bool {
+        self.blocked.lock().unwrap().remove(ip).is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_blocks_expire_and_can_be_lifted() {
+        let blocklist = SourceBlocklist::new(&CanaryConfig { block_duration: 60 });
+        let now = Utc::now();
+
+        blocklist.block("203.0.113.7", now);
+        assert!(blocklist.is_blocked("203.0.113.7", now));
+        assert!(!blocklist.is_blocked("198.51.100.1", now));
+        assert!(!blocklist.is_blocked("203.0.113.7", now + Duration::seconds(61)));
+        assert_eq!(blocklist.blocked(now).len(), 1);
+
+        assert!(blocklist.unblock("203.0.113.7"));
+        assert!(!blocklist.is_blocked("203.0.113.7", now));
+        assert!(!blocklist.unblock("203.0.113.7"));
+    }
+
+    #[test]
+    fn test_zero_duration_only_alerts() {
+        let blocklist = SourceBlocklist::new(&CanaryConfig { block_duration: 0 });
+        let now = Utc::now();
+
+        assert!(blocklist.block("203.0.113.7", now).is_none());
+        assert!(!blocklist.is_blocked("203.0.113.7", now));
+    }
+}
@@ -0,0 +1,142 @@
+use futures::future::BoxFuture;
+use hickory_resolver::error::ResolveErrorKind;
+use hickory_resolver::TokioAsyncResolver;
+use rand::{distributions::Alphanumeric, Rng};
+
+use crate::config::DomainVerificationConfig;
+use crate::errors::AuthError;
+
+// Prefix of the TXT record value, so it can't be mistaken for other verification records
+const RECORD_VALUE_PREFIX: &str = "better-auth-domain-verification=";
+
+/// Looks up TXT records; the system resolver in production
+pub trait TxtResolver: Send + Sync {
+    fn txt_records<'a>(&'a self, name: &'a str) -> BoxFuture<'a, Result<Vec<String>, AuthError>>;
+}
+
+pub struct SystemTxtResolver {
+    resolver: TokioAsyncResolver,
+}
+
+impl SystemTxtResolver {
+    pub fn new() -> Self {
+        SystemTxtResolver {
+            resolver: TokioAsyncResolver::tokio_from_system_conf()
+                .expect("Failed to read the system DNS configuration"),
+        }
+    }
+}
+
+impl TxtResolver for SystemTxtResolver {
+    fn txt_records<'a>(&'a self, name: &'a str) -> BoxFuture<'a, Result<Vec<String>, AuthError>> {
+        Box::pin(async move {
+            match self.resolver.txt_lookup(name).await {
+                Ok(lookup) => Ok(lookup.iter().map(|txt| txt.to_string()).collect()),
+                Err(e) if matches!(e.kind(), ResolveErrorKind::NoRecordsFound { .. }) => Ok(Vec::new()),
+                Err(e) => Err(AuthError::InternalServerError(format!("DNS lookup failed: {}", e))),
+            }
+        })
+    }
+}
+
+// Checks that an organization controls a domain by asking it to publish a
+// token in a TXT record at `<record_name>.<domain>`
+pub struct DomainVerifier {
+    resolver: Box<dyn TxtResolver>,
+    record_name: String,
+}
+
+impl DomainVerifier {
+    pub fn new(config: &DomainVerificationConfig) -> Self {
+        DomainVerifier {
+            resolver: Box::new(SystemTxtResolver::new()),
+            record_name: config.record_name.clone(),
+        }
+    }
+
+    pub fn with_resolver(mut self, resolver: Box<dyn TxtResolver>) -> Self {
+        self.resolver = resolver;
+        self
+    }
+
+    /// A fresh token for a newly claimed domain
+    pub fn new_token(&self) -> String {
+        rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(32)
+            .map(char::from)
+            .collect()
+    }
+
+    /// Where the TXT record must be published
+    pub fn record_name(&self, domain: &str) -> String {
+        format!("{}.{}", self.record_name, domain)
+    }
+
+    /// What the TXT record must contain
+    pub fn record_value(&self, token: &str) -> String {
+        format!("{}{}", RECORD_VALUE_PREFIX, token)
+    }
+
+    /// Whether the domain currently publishes the token
+    pub async fn check(&self, domain: &str, token: &str) -> Result<bool, AuthError> {
+        let records = self.resolver.txt_records(&self.record_name(domain)).await?;
+        let expected = self.record_value(token);
+
+        Ok(records.iter().any(|record| record.trim() == expected))
+    }
+}
+
+/// A lowercased domain name, or `None` if it isn't one
+pub fn normalize_domain(domain: &str) -> Option<String> {
+    let domain = domain.trim().trim_start_matches('@').trim_end_matches('.').to_lowercase();
+
+    let valid = domain.len() <= 253
+        && domain.contains('.')
+        && domain.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        });
+
+    valid.then_some(domain)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct FixedResolver(Vec<String>);
+
+    impl TxtResolver for FixedResolver {
+        fn txt_records<'a>(&'a self, _name: &'a str) -> BoxFuture<'a, Result<Vec<String>, AuthError>> {
+            Box::pin(async move { Ok(self.0.clone()) })
+        }
+    }
+
+    #[test]
+    fn test_normalize_domain() {
+        assert_eq!(normalize_domain(" @Example.COM. "), Some("example.com".to_string()));
+        assert_eq!(normalize_domain("mail.example.co.uk"), Some("mail.example.co.uk".to_string()));
+        assert_eq!(normalize_domain("localhost"), None);
+        assert_eq!(normalize_domain("-bad.example.com"), None);
+        assert_eq!(normalize_domain("exa mple.com"), None);
+    }
+
+    #[actix_web::test]
+    async fn test_check_finds_token_among_records() {
+        let verifier = DomainVerifier {
+            resolver: Box::new(FixedResolver(vec![
+                "v=spf1 -all".to_string(),
+                "better-auth-domain-verification=abc123".to_string(),
+            ])),
+            record_name: "_better-auth".to_string(),
+        };
+
+        assert_eq!(verifier.record_name("example.com"), "_better-auth.example.com");
+        assert!(verifier.check("example.com", "abc123").await.unwrap());
+        assert!(!verifier.check("example.com", "other").await.unwrap());
+    }
+}
@@ -1,100 +1,814 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use fluent_bundle::FluentArgs;
+use futures::future::BoxFuture;
 use lettre::{
     message::{header, MultiPart, SinglePart},
-    transport::smtp::authentication::Credentials,
+    transport::smtp::{authentication::Credentials, SmtpTransportBuilder},
     Message, SmtpTransport, Transport,
 };
 
-use crate::config::Config;
+use crate::config::{Config, EmailConfig, EmailDelivery, LinkPurpose};
 use crate::errors::AuthError;
+use crate::utils::i18n::Translator;
+use crate::utils::links;
+
+/// An account change worth telling the owner about at every address they have
+#[derive(Debug, Clone)]
+pub enum SecurityAlert<'a> {
+    PasswordChanged,
+    PasswordReset { via: &'a str }, // The address the reset link was sent to
+    MfaDisabled,
+    BackupEmailAdded(&'a str),
+    BackupEmailRemoved(&'a str),
+    SuspiciousActivity, // The account risk score crossed a threshold
+    SessionsRevoked,    // ... high enough to sign the account out everywhere
+    MfaReenrollmentRequired,
+    SecurityQuestionsUsed, // Answered in place of the second factor
+    AccountLocked,         // By the owner, from a session or an emailed link
+}
+
+impl SecurityAlert<'_> {
+    /// Whether the owner can turn it off. Alerts about changes to the
+    /// account always go out: they're how an owner notices a takeover.
+    pub fn is_optional(&self) -> bool {
+        matches!(self, SecurityAlert::SuspiciousActivity | SecurityAlert::SessionsRevoked)
+    }
+}
+
+/// A rendered message, ready for a transport
+#[derive(Debug, Clone)]
+pub struct OutgoingEmail {
+    pub from: String,
+    pub to: String,
+    pub subject: String,
+    pub html_body: String,
+    pub text_body: String,
+}
+
+/// Where outgoing email goes: SMTP, the log, or a test double
+pub trait EmailTransport: Send + Sync {
+    fn send<'a>(&'a self, email: &'a OutgoingEmail) -> BoxFuture<'a, Result<(), AuthError>>;
+}
+
+/// Sends through the configured SMTP relay
+pub struct SmtpMailer {
+    config: EmailConfig,
+}
+
+impl SmtpMailer {
+    pub fn new(config: EmailConfig) -> Self {
+        SmtpMailer { config }
+    }
+}
+
+impl EmailTransport for SmtpMailer {
+    fn send<'a>(&'a self, email: &'a OutgoingEmail) -> BoxFuture<'a, Result<(), AuthError>> {
+        Box::pin(async move {
+            let message = Message::builder()
+                .from(email.from.parse().unwrap())
+                .to(email.to.parse().unwrap())
+                .subject(&email.subject)
+                .multipart(
+                    MultiPart::alternative()
+                        .singlepart(
+                            SinglePart::builder()
+                                .header(header::ContentType::TEXT_PLAIN)
+                                .body(email.text_body.clone()),
+                        )
+                        .singlepart(
+                            SinglePart::builder()
+                                .header(header::ContentType::TEXT_HTML)
+                                .body(email.html_body.clone()),
+                        ),
+                )?;
+
+            let mailer = smtp_relay(&self.config)?.build();
+
+            match mailer.send(&message) {
+                Ok(_) => Ok(()),
+                Err(e) => Err(AuthError::EmailError(e.to_string())),
+            }
+        })
+    }
+}
+
+// How long `check_smtp` waits on an unreachable relay
+const SMTP_CHECK_TIMEOUT_SECS: u64 = 10;
+
+fn smtp_relay(config: &EmailConfig) -> Result<SmtpTransportBuilder, AuthError> {
+    let creds = Credentials::new(config.smtp_username.clone(), config.smtp_password.clone());
+
+    Ok(SmtpTransport::relay(&config.smtp_host)
+        .map_err(|e| AuthError::EmailError(e.to_string()))?
+        .credentials(creds)
+        .port(config.smtp_port))
+}
+
+/// Connect to the configured SMTP relay and log in, without sending anything
+pub async fn check_smtp(config: &EmailConfig) -> Result<(), AuthError> {
+    let mailer = smtp_relay(config)?
+        .timeout(Some(Duration::from_secs(SMTP_CHECK_TIMEOUT_SECS)))
+        .build();
+
+    let connected = tokio::task::spawn_blocking(move || mailer.test_connection())
+        .await
+        .map_err(|e| AuthError::InternalServerError(format!("Task join error: {}", e)))?
+        .map_err(|e| AuthError::EmailError(e.to_string()))?;
+    if !connected {
+        return Err(AuthError::EmailError("The SMTP relay refused the connection".to_string()));
+    }
+    Ok(())
+}
+
+/// Writes messages to the log instead of sending them
+pub struct LogMailer;
+
+impl EmailTransport for LogMailer {
+    fn send<'a>(&'a self, email: &'a OutgoingEmail) -> BoxFuture<'a, Result<(), AuthError>> {
+        Box::pin(async move {
+            log::info!("Email to {} ({}):\n{}", email.to, email.subject, email.text_body);
+            Ok(())
+        })
+    }
+}
+
+/// Hands each message to another transport on a background task and returns
+/// at once, so the caller's response time doesn't depend on the mail server
+pub struct BackgroundMailer {
+    inner: Arc<dyn EmailTransport>,
+}
+
+impl BackgroundMailer {
+    pub fn new(inner: Arc<dyn EmailTransport>) -> Self {
+        BackgroundMailer { inner }
+    }
+}
+
+impl EmailTransport for BackgroundMailer {
+    fn send<'a>(&'a self, email: &'a OutgoingEmail) -> BoxFuture<'a, Result<(), AuthError>> {
+        let inner = self.inner.clone();
+        let email = email.clone();
+
+        tokio::spawn(async move {
+            if let Err(e) = inner.send(&email).await {
+                log::warn!("Failed to send email to {} in the background: {}", email.to, e);
+            }
+        });
 
+        Box::pin(async { Ok(()) })
+    }
+}
+
+/// The transport `EMAIL_DELIVERY` asks for
+pub fn transport_for(config: &EmailConfig) -> Arc<dyn EmailTransport> {
+    match config.delivery {
+        EmailDelivery::Smtp => Arc::new(SmtpMailer::new(config.clone())),
+        EmailDelivery::Log => Arc::new(LogMailer),
+    }
+}
+
+/// Why a registration didn't create an account, told to the address it named
+#[derive(Debug, Clone)]
+pub enum RegistrationNotice<'a> {
+    EmailTaken,
+    UsernameTaken(&'a str),
+}
+
+#[derive(Clone)]
 pub struct EmailService {
     config: Config,
+    translator: Arc<Translator>,
+    transport: Arc<dyn EmailTransport>,
 }
 
 impl EmailService {
-    pub fn new(config: Config) -> Self {
-        EmailService { config }
+    pub fn new(config: Config, translator: Arc<Translator>) -> Self {
+        let transport = transport_for(&config.email);
+        EmailService { config, translator, transport }
+    }
+
+    /// Replace the transport picked from config, e.g. with one that captures
+    /// messages in tests
+    pub fn set_transport(&mut self, transport: Arc<dyn EmailTransport>) {
+        self.transport = transport;
+    }
+
+    /// A copy that sends through `BackgroundMailer`; send errors are logged
+    /// rather than returned
+    pub fn in_background(&self) -> EmailService {
+        EmailService {
+            transport: Arc::new(BackgroundMailer::new(self.transport.clone())),
+            ..self.clone()
+        }
     }
 
     pub async fn send_verification_email(
         &self,
         email: &str,
         token: &str,
+        locale: &str,
     ) -> Result<(), AuthError> {
-        let subject = "Verify your email address";
-        let verification_url = format!("https://example.com/verify-email?token={}", token);
+        let t = |key: &str| self.translator.text(locale, key, None);
+        let subject = t("email-verify-subject");
+        let verification_url = links::link(&self.config.frontend, LinkPurpose::VerifyEmail, email, token);
+
+        let mut args = FluentArgs::new();
+        args.set("url", verification_url.clone());
+        let link_fallback = self.translator.text(locale, "email-link-fallback", Some(&args));
         
         let html_body = format!(
             r#"
             <html>
                 <body>
-                    <h1>Verify your email address</h1>
-                    <p>Thank you for registering! Please click the link below to verify your email address:</p>
-                    <p><a href="{}">Verify Email</a></p>
-                    <p>Or copy and paste this link: {}</p>
-                    <p>This link will expire in 24 hours.</p>
+                    <h1>{}</h1>
+                    <p>{}</p>
+                    <p><a href="{}">{}</a></p>
+                    <p>{}</p>
+                    <p>{}</p>
                 </body>
             </html>
             "#,
-            verification_url, verification_url
+            t("email-verify-heading"),
+            t("email-verify-body"),
+            verification_url,
+            t("email-verify-action"),
+            link_fallback,
+            t("email-link-expiry")
         );
 
         let text_body = format!(
             r#"
-            Verify your email address
+            {}
             
-            Thank you for registering! Please visit the link below to verify your email address:
+            {}
             
             {}
             
-            This link will expire in 24 hours.
+            {}
             "#,
-            verification_url
+            t("email-verify-heading"),
+            t("email-verify-body"),
+            verification_url,
+            t("email-link-expiry")
         );
 
-        self.send_email(email, subject, &html_body, &text_body).await
+        self.send_email(email, &subject, &html_body, &text_body).await
     }
 
     pub async fn send_password_reset_email(
         &self,
         email: &str,
         token: &str,
+        locale: &str,
     ) -> Result<(), AuthError> {
-        let subject = "Reset your password";
-        let reset_url = format!("https://example.com/reset-password?token={}", token);
+        let t = |key: &str| self.translator.text(locale, key, None);
+        let subject = t("email-reset-subject");
+        let reset_url = links::link(&self.config.frontend, LinkPurpose::ResetPassword, email, token);
+
+        let mut args = FluentArgs::new();
+        args.set("url", reset_url.clone());
+        let link_fallback = self.translator.text(locale, "email-link-fallback", Some(&args));
         
         let html_body = format!(
             r#"
             <html>
                 <body>
-                    <h1>Reset your password</h1>
-                    <p>You requested a password reset. Please click the link below to reset your password:</p>
-                    <p><a href="{}">Reset Password</a></p>
-                    <p>Or copy and paste this link: {}</p>
-                    <p>This link will expire in 24 hours.</p>
-                    <p>If you didn't request a password reset, please ignore this email.</p>
+                    <h1>{}</h1>
+                    <p>{}</p>
+                    <p><a href="{}">{}</a></p>
+                    <p>{}</p>
+                    <p>{}</p>
+                    <p>{}</p>
                 </body>
             </html>
             "#,
-            reset_url, reset_url
+            t("email-reset-heading"),
+            t("email-reset-body"),
+            reset_url,
+            t("email-reset-action"),
+            link_fallback,
+            t("email-link-expiry"),
+            t("email-reset-ignore")
         );
 
         let text_body = format!(
             r#"
-            Reset your password
+            {}
             
-            You requested a password reset. Please visit the link below to reset your password:
+            {}
             
             {}
             
-            This link will expire in 24 hours.
+            {}
             
-            If you didn't request a password reset, please ignore this email.
+            {}
             "#,
-            reset_url
+            t("email-reset-heading"),
+            t("email-reset-body"),
+            reset_url,
+            t("email-link-expiry"),
+            t("email-reset-ignore")
         );
 
-        self.send_email(email, subject, &html_body, &text_body).await
+        self.send_email(email, &subject, &html_body, &text_body).await
+    }
+
+    pub async fn send_activation_email(
+        &self,
+        email: &str,
+        token: &str,
+        locale: &str,
+    ) -> Result<(), AuthError> {
+        let t = |key: &str| self.translator.text(locale, key, None);
+        let subject = t("email-activate-subject");
+        let activation_url = links::link(&self.config.frontend, LinkPurpose::Activate, email, token);
+
+        let mut args = FluentArgs::new();
+        args.set("url", activation_url.clone());
+        let link_fallback = self.translator.text(locale, "email-link-fallback", Some(&args));
+
+        let mut args = FluentArgs::new();
+        args.set("days", self.config.action_tokens.activation_ttl / 86400);
+        let expiry = self.translator.text(locale, "email-activate-expiry", Some(&args));
+
+        let html_body = format!(
+            r#"
+            <html>
+                <body>
+                    <h1>{}</h1>
+                    <p>{}</p>
+                    <p><a href="{}">{}</a></p>
+                    <p>{}</p>
+                    <p>{}</p>
+                    <p>{}</p>
+                </body>
+            </html>
+            "#,
+            t("email-activate-heading"),
+            t("email-activate-body"),
+            activation_url,
+            t("email-activate-action"),
+            link_fallback,
+            expiry,
+            t("email-activate-ignore")
+        );
+
+        let text_body = format!(
+            r#"
+            {}
+            
+            {}
+            
+            {}
+            
+            {}
+            
+            {}
+            "#,
+            t("email-activate-heading"),
+            t("email-activate-body"),
+            activation_url,
+            expiry,
+            t("email-activate-ignore")
+        );
+
+        self.send_email(email, &subject, &html_body, &text_body).await
+    }
+
+    pub async fn send_reactivation_email(
+        &self,
+        email: &str,
+        token: &str,
+        locale: &str,
+    ) -> Result<(), AuthError> {
+        let t = |key: &str| self.translator.text(locale, key, None);
+        let subject = t("email-reactivate-subject");
+        let reactivation_url = links::link(&self.config.frontend, LinkPurpose::Reactivate, email, token);
+
+        let mut args = FluentArgs::new();
+        args.set("url", reactivation_url.clone());
+        let link_fallback = self.translator.text(locale, "email-link-fallback", Some(&args));
+
+        let mut args = FluentArgs::new();
+        args.set("hours", (self.config.action_tokens.reactivation_ttl / 3600).max(1));
+        let expiry = self.translator.text(locale, "email-reactivate-expiry", Some(&args));
+
+        let html_body = format!(
+            r#"
+            <html>
+                <body>
+                    <h1>{}</h1>
+                    <p>{}</p>
+                    <p><a href="{}">{}</a></p>
+                    <p>{}</p>
+                    <p>{}</p>
+                    <p>{}</p>
+                </body>
+            </html>
+            "#,
+            t("email-reactivate-heading"),
+            t("email-reactivate-body"),
+            reactivation_url,
+            t("email-reactivate-action"),
+            link_fallback,
+            expiry,
+            t("email-reactivate-ignore")
+        );
+
+        let text_body = format!(
+            r#"
+            {}
+            
+            {}
+            
+            {}
+            
+            {}
+            
+            {}
+            "#,
+            t("email-reactivate-heading"),
+            t("email-reactivate-body"),
+            reactivation_url,
+            expiry,
+            t("email-reactivate-ignore")
+        );
+
+        self.send_email(email, &subject, &html_body, &text_body).await
+    }
+
+    pub async fn send_account_lock_email(
+        &self,
+        email: &str,
+        token: &str,
+        locale: &str,
+    ) -> Result<(), AuthError> {
+        let t = |key: &str| self.translator.text(locale, key, None);
+        let subject = t("email-lock-subject");
+        let lock_url = links::link(&self.config.frontend, LinkPurpose::LockAccount, email, token);
+
+        let mut args = FluentArgs::new();
+        args.set("url", lock_url.clone());
+        let link_fallback = self.translator.text(locale, "email-link-fallback", Some(&args));
+
+        let mut args = FluentArgs::new();
+        args.set("hours", (self.config.action_tokens.account_lock_ttl / 3600).max(1));
+        let expiry = self.translator.text(locale, "email-lock-expiry", Some(&args));
+
+        let html_body = format!(
+            r#"
+            <html>
+                <body>
+                    <h1>{}</h1>
+                    <p>{}</p>
+                    <p><a href="{}">{}</a></p>
+                    <p>{}</p>
+                    <p>{}</p>
+                    <p>{}</p>
+                </body>
+            </html>
+            "#,
+            t("email-lock-heading"),
+            t("email-lock-body"),
+            lock_url,
+            t("email-lock-action"),
+            link_fallback,
+            expiry,
+            t("email-lock-ignore")
+        );
+
+        let text_body = format!(
+            r#"
+            {}
+            
+            {}
+            
+            {}
+            
+            {}
+            
+            {}
+            "#,
+            t("email-lock-heading"),
+            t("email-lock-body"),
+            lock_url,
+            expiry,
+            t("email-lock-ignore")
+        );
+
+        self.send_email(email, &subject, &html_body, &text_body).await
+    }
+
+    pub async fn send_login_approval_email(
+        &self,
+        email: &str,
+        token: &str,
+        device: &str,
+        ip: &str,
+        locale: &str,
+    ) -> Result<(), AuthError> {
+        let t = |key: &str| self.translator.text(locale, key, None);
+        let subject = t("email-approval-subject");
+        let approval_url = links::link(&self.config.frontend, LinkPurpose::ApproveLogin, email, token);
+
+        let mut args = FluentArgs::new();
+        args.set("device", device.to_string());
+        args.set("ip", ip.to_string());
+        let body = self.translator.text(locale, "email-approval-body", Some(&args));
+
+        let mut args = FluentArgs::new();
+        args.set("url", approval_url.clone());
+        let link_fallback = self.translator.text(locale, "email-link-fallback", Some(&args));
+
+        let mut args = FluentArgs::new();
+        args.set("minutes", self.config.login_approval.ttl / 60);
+        let link_expiry = self.translator.text(locale, "email-approval-expiry", Some(&args));
+        
+        let html_body = format!(
+            r#"
+            <html>
+                <body>
+                    <h1>{}</h1>
+                    <p>{}</p>
+                    <p><a href="{}">{}</a></p>
+                    <p>{}</p>
+                    <p>{}</p>
+                    <p>{}</p>
+                </body>
+            </html>
+            "#,
+            t("email-approval-heading"),
+            body,
+            approval_url,
+            t("email-approval-action"),
+            link_fallback,
+            link_expiry,
+            t("email-approval-ignore")
+        );
+
+        let text_body = format!(
+            r#"
+            {}
+            
+            {}
+            
+            {}
+            
+            {}
+            
+            {}
+            "#,
+            t("email-approval-heading"),
+            body,
+            approval_url,
+            link_expiry,
+            t("email-approval-ignore")
+        );
+
+        self.send_email(email, &subject, &html_body, &text_body).await
+    }
+
+    pub async fn send_login_code_email(
+        &self,
+        email: &str,
+        code: &str,
+        locale: &str,
+    ) -> Result<(), AuthError> {
+        let t = |key: &str| self.translator.text(locale, key, None);
+        let subject = t("email-code-subject");
+
+        let mut args = FluentArgs::new();
+        args.set("minutes", self.config.email_code_login.ttl / 60);
+        let code_expiry = self.translator.text(locale, "email-code-expiry", Some(&args));
+        
+        let html_body = format!(
+            r#"
+            <html>
+                <body>
+                    <h1>{}</h1>
+                    <p>{}</p>
+                    <p><strong>{}</strong></p>
+                    <p>{}</p>
+                    <p>{}</p>
+                </body>
+            </html>
+            "#,
+            t("email-code-heading"),
+            t("email-code-body"),
+            code,
+            code_expiry,
+            t("email-code-ignore")
+        );
+
+        let text_body = format!(
+            r#"
+            {}
+            
+            {}
+            
+            {}
+            
+            {}
+            
+            {}
+            "#,
+            t("email-code-heading"),
+            t("email-code-body"),
+            code,
+            code_expiry,
+            t("email-code-ignore")
+        );
+
+        self.send_email(email, &subject, &html_body, &text_body).await
+    }
+
+    pub async fn send_backup_email_verification(
+        &self,
+        email: &str,
+        token: &str,
+        locale: &str,
+    ) -> Result<(), AuthError> {
+        let t = |key: &str| self.translator.text(locale, key, None);
+        let subject = t("email-backup-subject");
+        let verification_url = links::link(&self.config.frontend, LinkPurpose::VerifyBackupEmail, email, token);
+
+        let mut args = FluentArgs::new();
+        args.set("url", verification_url.clone());
+        let link_fallback = self.translator.text(locale, "email-link-fallback", Some(&args));
+        
+        let html_body = format!(
+            r#"
+            <html>
+                <body>
+                    <h1>{}</h1>
+                    <p>{}</p>
+                    <p><a href="{}">{}</a></p>
+                    <p>{}</p>
+                    <p>{}</p>
+                    <p>{}</p>
+                </body>
+            </html>
+            "#,
+            t("email-backup-heading"),
+            t("email-backup-body"),
+            verification_url,
+            t("email-verify-action"),
+            link_fallback,
+            t("email-link-expiry"),
+            t("email-backup-ignore")
+        );
+
+        let text_body = format!(
+            r#"
+            {}
+            
+            {}
+            
+            {}
+            
+            {}
+            
+            {}
+            "#,
+            t("email-backup-heading"),
+            t("email-backup-body"),
+            verification_url,
+            t("email-link-expiry"),
+            t("email-backup-ignore")
+        );
+
+        self.send_email(email, &subject, &html_body, &text_body).await
+    }
+
+    pub async fn send_security_alert(
+        &self,
+        email: &str,
+        alert: &SecurityAlert<'_>,
+        unsubscribe_token: Option<&str>,
+        locale: &str,
+    ) -> Result<(), AuthError> {
+        let t = |key: &str| self.translator.text(locale, key, None);
+        let subject = t("email-security-subject");
+
+        let (key, address) = match alert {
+            SecurityAlert::PasswordChanged => ("security-event-password-changed", None),
+            SecurityAlert::PasswordReset { via } => ("security-event-password-reset", Some(via)),
+            SecurityAlert::MfaDisabled => ("security-event-mfa-disabled", None),
+            SecurityAlert::BackupEmailAdded(address) => {
+                ("security-event-backup-email-added", Some(address))
+            }
+            SecurityAlert::BackupEmailRemoved(address) => {
+                ("security-event-backup-email-removed", Some(address))
+            }
+            SecurityAlert::SuspiciousActivity => ("security-event-suspicious-activity", None),
+            SecurityAlert::SessionsRevoked => ("security-event-sessions-revoked", None),
+            SecurityAlert::MfaReenrollmentRequired => ("security-event-mfa-reenrollment", None),
+            SecurityAlert::SecurityQuestionsUsed => ("security-event-security-questions-used", None),
+            SecurityAlert::AccountLocked => ("security-event-account-locked", None),
+        };
+        let mut args = FluentArgs::new();
+        if let Some(address) = address {
+            args.set("email", address.to_string());
+        }
+        let event = self.translator.text(locale, key, Some(&args));
+
+        let (html_footer, text_footer) = match unsubscribe_token {
+            Some(token) => {
+                let url = links::link(&self.config.frontend, LinkPurpose::NotificationPreferences, email, token);
+                (
+                    format!(r#"<p><small>{} <a href="{}">{}</a></small></p>"#, t("email-security-unsubscribe"), url, url),
+                    format!("{} {}", t("email-security-unsubscribe"), url),
+                )
+            }
+            None => (String::new(), String::new()),
+        };
+        
+        let html_body = format!(
+            r#"
+            <html>
+                <body>
+                    <h1>{}</h1>
+                    <p>{}</p>
+                    <p>{}</p>
+                    {}
+                </body>
+            </html>
+            "#,
+            t("email-security-heading"),
+            event,
+            t("email-security-ignore"),
+            html_footer
+        );
+
+        let text_body = format!(
+            r#"
+            {}
+            
+            {}
+            
+            {}
+            
+            {}
+            "#,
+            t("email-security-heading"),
+            event,
+            t("email-security-ignore"),
+            text_footer
+        );
+
+        self.send_email(email, &subject, &html_body, &text_body).await
+    }
+
+    pub async fn send_registration_notice(
+        &self,
+        email: &str,
+        notice: &RegistrationNotice<'_>,
+        locale: &str,
+    ) -> Result<(), AuthError> {
+        let t = |key: &str| self.translator.text(locale, key, None);
+        let subject = t("email-registration-subject");
+
+        let reason = match notice {
+            RegistrationNotice::EmailTaken => t("email-registration-email-taken"),
+            RegistrationNotice::UsernameTaken(username) => {
+                let mut args = FluentArgs::new();
+                args.set("username", username.to_string());
+                self.translator
+                    .text(locale, "email-registration-username-taken", Some(&args))
+            }
+        };
+
+        let html_body = format!(
+            r#"
+            <html>
+                <body>
+                    <h1>{}</h1>
+                    <p>{}</p>
+                    <p>{}</p>
+                </body>
+            </html>
+            "#,
+            t("email-registration-heading"),
+            reason,
+            t("email-registration-ignore")
+        );
+
+        let text_body = format!(
+            r#"
+            {}
+            
+            {}
+            
+            {}
+            "#,
+            t("email-registration-heading"),
+            reason,
+            t("email-registration-ignore")
+        );
+
+        self.send_email(email, &subject, &html_body, &text_body).await
     }
 
     async fn send_email(
@@ -104,38 +818,14 @@ impl EmailService {
         html_body: &str,
         text_body: &str,
     ) -> Result<(), AuthError> {
-        let email = Message::builder()
-            .from(self.config.email.from_email.parse().unwrap())
-            .to(to.parse().unwrap())
-            .subject(subject)
-            .multipart(
-                MultiPart::alternative()
-                    .singlepart(
-                        SinglePart::builder()
-                            .header(header::ContentType::TEXT_PLAIN)
-                            .body(text_body.to_string()),
-                    )
-                    .singlepart(
-                        SinglePart::builder()
-                            .header(header::ContentType::TEXT_HTML)
-                            .body(html_body.to_string()),
-                    ),
-            )?;
-
-        let creds = Credentials::new(
-            self.config.email.smtp_username.clone(),
-            self.config.email.smtp_password.clone(),
-        );
-
-        let mailer = SmtpTransport::relay(&self.config.email.smtp_host)
-            .unwrap()
-            .credentials(creds)
-            .port(self.config.email.smtp_port)
-            .build();
-
-        match mailer.send(&email) {
-            Ok(_) => Ok(()),
-            Err(e) => Err(AuthError::EmailError(e.to_string())),
-        }
+        let email = OutgoingEmail {
+            from: self.config.email.from_email.clone(),
+            to: to.to_string(),
+            subject: subject.to_string(),
+            html_body: html_body.to_string(),
+            text_body: text_body.to_string(),
+        };
+
+        self.transport.send(&email).await
     }
 }
@@ -0,0 +1,208 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use rand::{distributions::Alphanumeric, Rng};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::config::EmailCodeLoginConfig;
+use crate::errors::AuthError;
+
+struct PendingCode {
+    user_id: Option<Uuid>, // `None` for addresses without an account; no code matches
+    code: String,
+    attempts: u32, // Wrong guesses so far, counting those at the user's earlier codes
+    created_at: Instant,
+}
+
+// Wrong guesses at a user's codes since the first one, so asking for a new
+// code doesn't buy more guesses
+struct Failures {
+    count: u32,
+    since: Instant,
+}
+
+#[derive(Default)]
+struct Codes {
+    pending: HashMap<Uuid, PendingCode>,
+    failures: HashMap<Uuid, Failures>,
+}
+
+// Sign-in codes waiting to be entered. The client holds the challenge id,
+// only the mailbox holds the code. Kept in memory like login approvals.
+pub struct EmailCodes {
+    ttl: Duration,
+    max_attempts: u32,
+    codes: Mutex<Codes>,
+}
+
+impl EmailCodes {
+    pub fn new(config: &EmailCodeLoginConfig) -> Self {
+        EmailCodes {
+            ttl: Duration::from_secs(config.ttl),
+            max_attempts: config.max_attempts,
+            codes: Mutex::new(Codes::default()),
+        }
+    }
+
+    /// Issue a 6-digit code, returning `(challenge_id, code)`. Any earlier code
+    /// for the same user stops working, and the new one only gets the wrong
+    /// guesses the user has left, so attempts can't be multiplied.
+    pub fn create(&self, user_id: Option<Uuid>) -> (Uuid, String) {
+        let challenge_id = Uuid::new_v4();
+        let code = format!("{:06}", rand::thread_rng().gen_range(0..1_000_000));
+
+        let mut codes = self.codes.lock().unwrap();
+        codes
+            .pending
+            .retain(|_, p| p.created_at.elapsed() < self.ttl && (user_id.is_none() || p.user_id != user_id));
+        codes.failures.retain(|_, f| f.since.elapsed() < self.ttl);
+        let attempts = user_id
+            .and_then(|user_id| codes.failures.get(&user_id))
+            .map_or(0, |failures| failures.count);
+        codes.pending.insert(
+            challenge_id,
+            PendingCode {
+                user_id,
+                code: code.clone(),
+                attempts,
+                created_at: Instant::now(),
+            },
+        );
+
+        (challenge_id, code)
+    }
+
+    /// The user the code was sent to. Each code works once, and is thrown
+    /// away once the user has guessed wrong too often, across all their codes.
+    pub fn verify(&self, challenge_id: Uuid, code: &str) -> Result<Uuid, AuthError> {
+        let mut codes = self.codes.lock().unwrap();
+        let Codes { pending, failures } = &mut *codes;
+        let entry = pending.get_mut(&challenge_id).ok_or(AuthError::InvalidToken)?;
+
+        if entry.created_at.elapsed() >= self.ttl {
+            pending.remove(&challenge_id);
+            return Err(AuthError::TokenExpired);
+        }
+        if entry.attempts >= self.max_attempts {
+            pending.remove(&challenge_id);
+            return Err(AuthError::InvalidToken);
+        }
+
+        if let (true, Some(user_id)) = (constant_time_eq(&entry.code, code), entry.user_id) {
+            pending.remove(&challenge_id);
+            failures.remove(&user_id);
+            return Ok(user_id);
+        }
+
+        entry.attempts += 1;
+        if let Some(user_id) = entry.user_id {
+            let user_failures = failures.entry(user_id).or_insert(Failures {
+                count: 0,
+                since: Instant::now(),
+            });
+            user_failures.count += 1;
+        }
+        if entry.attempts >= self.max_attempts {
+            pending.remove(&challenge_id);
+        }
+        Err(AuthError::InvalidVerificationCode)
+    }
+}
+
+// Looks at every byte whatever the first mismatch, so how long a guess takes
+// to reject says nothing about how close it was
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len() && a.bytes().zip(b.bytes()).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// A new device token, and the hash stored for it
+pub fn device_token() -> (String, String) {
+    let token: String = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(48)
+        .map(char::from)
+        .collect();
+    let hash = hash_device_token(&token);
+    (token, hash)
+}
+
+/// Device tokens are long and random, so a fast hash is enough
+pub fn hash_device_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn codes() -> EmailCodes {
+        EmailCodes::new(&EmailCodeLoginConfig {
+            enabled: true,
+            ttl: 600,
+            max_attempts: 3,
+            trusted_device_days: 30,
+        })
+    }
+
+    #[test]
+    fn test_code_works_once() {
+        let codes = codes();
+        let user_id = Uuid::new_v4();
+        let (challenge_id, code) = codes.create(Some(user_id));
+
+        assert_eq!(code.len(), 6);
+        assert_eq!(codes.verify(challenge_id, &code).unwrap(), user_id);
+        assert!(matches!(codes.verify(challenge_id, &code), Err(AuthError::InvalidToken)));
+    }
+
+    #[test]
+    fn test_wrong_guesses_use_up_the_code() {
+        let codes = codes();
+        let (challenge_id, code) = codes.create(Some(Uuid::new_v4()));
+        let wrong = if code == "000000" { "000001" } else { "000000" };
+
+        for _ in 0..3 {
+            assert!(matches!(codes.verify(challenge_id, wrong), Err(AuthError::InvalidVerificationCode)));
+        }
+        assert!(matches!(codes.verify(challenge_id, &code), Err(AuthError::InvalidToken)));
+    }
+
+    #[test]
+    fn test_new_code_replaces_the_old_one() {
+        let codes = codes();
+        let user_id = Uuid::new_v4();
+        let (first, first_code) = codes.create(Some(user_id));
+        let (second, second_code) = codes.create(Some(user_id));
+
+        assert!(codes.verify(first, &first_code).is_err());
+        assert_eq!(codes.verify(second, &second_code).unwrap(), user_id);
+    }
+
+    #[test]
+    fn test_new_code_keeps_the_wrong_guesses() {
+        let codes = codes();
+        let user_id = Uuid::new_v4();
+        let (first, code) = codes.create(Some(user_id));
+        let wrong = if code == "000000" { "000001" } else { "000000" };
+        for _ in 0..2 {
+            assert!(codes.verify(first, wrong).is_err());
+        }
+
+        // One guess left, however many codes are asked for
+        let (second, code) = codes.create(Some(user_id));
+        let wrong = if code == "000000" { "000001" } else { "000000" };
+        assert!(matches!(codes.verify(second, wrong), Err(AuthError::InvalidVerificationCode)));
+        let (third, code) = codes.create(Some(user_id));
+        assert!(matches!(codes.verify(third, &code), Err(AuthError::InvalidToken)));
+    }
+
+    #[test]
+    fn test_unknown_address_never_verifies() {
+        let codes = codes();
+        let (challenge_id, code) = codes.create(None);
+
+        assert!(matches!(codes.verify(challenge_id, &code), Err(AuthError::InvalidVerificationCode)));
+    }
+}
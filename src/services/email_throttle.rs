@@ -0,0 +1,147 @@
+use std::sync::Arc;
+
+use chrono::{DateTime, Duration, Utc};
+use uuid::Uuid;
+
+use crate::config::EmailResendConfig;
+use crate::db::DatabaseConnection;
+use crate::errors::AuthError;
+use crate::models::NewEmailSend;
+use crate::utils::validation::canonical_email;
+
+/// Emails with resend limits of their own
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThrottledEmail {
+    Verification,
+    PasswordReset,
+    Activation,
+    Reactivation,
+    AccountLock,
+    LoginCode,
+}
+
+impl ThrottledEmail {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ThrottledEmail::Verification => "verification",
+            ThrottledEmail::PasswordReset => "password_reset",
+            ThrottledEmail::Activation => "activation",
+            ThrottledEmail::Reactivation => "reactivation",
+            ThrottledEmail::AccountLock => "account_lock",
+            ThrottledEmail::LoginCode => "login_code",
+        }
+    }
+}
+
+// Cooldown and daily limit on action emails, counted in the database so every
+// instance sees the same sends. Counted per address rather than per account,
+// so addresses without an account are throttled exactly like the rest.
+pub struct EmailThrottle {
+    db: Arc<DatabaseConnection>,
+    config: EmailResendConfig,
+}
+
+impl EmailThrottle {
+    pub fn new(db: Arc<DatabaseConnection>, config: &EmailResendConfig) -> Self {
+        EmailThrottle {
+            db,
+            config: config.clone(),
+        }
+    }
+
+    /// Count an email about to be sent, or refuse it with how long to wait
+    pub async fn acquire(&self, address: &str, kind: ThrottledEmail) -> Result<(), AuthError> {
+        let now = Utc::now();
+        let sends = self
+            .db
+            .find_email_sends_since(&normalize(address), kind.as_str(), now - Duration::days(1))
+            .await?;
+
+        if let Some(retry_after) = retry_after(&self.config, &sends, now) {
+            return Err(AuthError::EmailResendThrottled { retry_after });
+        }
+
+        self.record(address, kind).await
+    }
+
+    /// Count an email nobody asked to have resent, such as the one sent at registration
+    pub async fn record(&self, address: &str, kind: ThrottledEmail) -> Result<(), AuthError> {
+        self.db
+            .record_email_send(
+                NewEmailSend {
+                    id: Uuid::new_v4(),
+                    address: normalize(address),
+                    kind: kind.as_str().to_string(),
+                },
+                Utc::now() - Duration::days(1),
+            )
+            .await
+    }
+}
+
+// Every spelling of an address shares one count
+fn normalize(address: &str) -> String {
+    canonical_email(address).to_lowercase()
+}
+
+/// Seconds until another email may go out, given the last day's sends, newest first
+fn retry_after(config: &EmailResendConfig, sends: &[DateTime<Utc>], now: DateTime<Utc>) -> Option<u64> {
+    let mut wait = Duration::zero();
+
+    if let Some(last) = sends.first() {
+        wait = wait.max(*last + Duration::seconds(config.cooldown as i64) - now);
+    }
+
+    // Wait for enough of the oldest sends to leave the window
+    let limit = config.daily_limit as usize;
+    if limit > 0 && sends.len() >= limit {
+        wait = wait.max(sends[limit - 1] + Duration::days(1) - now);
+    }
+
+    (wait > Duration::zero()).then(|| wait.num_seconds().max(1) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> EmailResendConfig {
+        EmailResendConfig {
+            cooldown: 60,
+            daily_limit: 3,
+        }
+    }
+
+    #[test]
+    fn test_cooldown_after_the_last_send() {
+        let now = Utc::now();
+
+        assert_eq!(retry_after(&config(), &[], now), None);
+        assert_eq!(retry_after(&config(), &[now - Duration::seconds(20)], now), Some(40));
+        assert_eq!(retry_after(&config(), &[now - Duration::seconds(61)], now), None);
+    }
+
+    #[test]
+    fn test_daily_limit_waits_for_the_oldest_send() {
+        let now = Utc::now();
+        let sends = [
+            now - Duration::hours(1),
+            now - Duration::hours(2),
+            now - Duration::hours(23),
+        ];
+
+        assert_eq!(retry_after(&config(), &sends, now), Some(3600));
+        assert_eq!(retry_after(&config(), &sends[..2], now), None);
+    }
+
+    #[test]
+    fn test_zero_turns_limits_off() {
+        let now = Utc::now();
+        let off = EmailResendConfig {
+            cooldown: 0,
+            daily_limit: 0,
+        };
+
+        assert_eq!(retry_after(&off, &[now; 10], now), None);
+    }
+}
@@ -0,0 +1,258 @@
+use std::io::Write;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use flate2::{write::GzEncoder, Compression};
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::config::EventExportConfig;
+use crate::db::DatabaseConnection;
+use crate::errors::AuthError;
+use crate::models::{AccountRiskSignal, EventEnvelope};
+use crate::services::storage::{blob_storage, BlobStorage};
+
+const AUDIT_STREAM: &str = "audit"; // Delivered outbox events, as subscribers received them
+const LOGIN_STREAM: &str = "login"; // Account risk signals: failed and risky logins, breach hits
+
+/// A row of an export file
+trait Exported: Serialize {
+    fn id(&self) -> Uuid;
+    fn occurred_at(&self) -> DateTime<Utc>;
+}
+
+impl Exported for EventEnvelope<'_> {
+    fn id(&self) -> Uuid {
+        self.id
+    }
+
+    fn occurred_at(&self) -> DateTime<Utc> {
+        self.occurred_at
+    }
+}
+
+impl Exported for AccountRiskSignal {
+    fn id(&self) -> Uuid {
+        self.id
+    }
+
+    fn occurred_at(&self) -> DateTime<Utc> {
+        self.created_at
+    }
+}
+
+// Moves audit and login events older than the retention period into gzipped
+// NDJSON files in object storage, one directory per stream and day, and then
+// deletes them from Postgres. A batch is only deleted once its files are
+// written; if the delete fails, the next run writes the same files again.
+pub struct EventExporter {
+    db: Arc<DatabaseConnection>,
+    storage: Box<dyn BlobStorage>,
+    config: EventExportConfig,
+    risk_window_days: u32, // Signals this recent still count towards the account risk score
+}
+
+impl EventExporter {
+    pub fn new(
+        db: Arc<DatabaseConnection>,
+        storage: Box<dyn BlobStorage>,
+        config: &EventExportConfig,
+        risk_window_days: u32,
+    ) -> Self {
+        EventExporter {
+            db,
+            storage,
+            config: config.clone(),
+            risk_window_days,
+        }
+    }
+
+    /// An exporter writing to the storage configured for exports
+    pub fn from_config(db: Arc<DatabaseConnection>, config: &EventExportConfig, risk_window_days: u32) -> Self {
+        EventExporter::new(db, blob_storage(&config.storage), config, risk_window_days)
+    }
+
+    /// Export on the configured interval until the process exits; spawn at
+    /// startup. Returns at once when exporting is off.
+    pub async fn run(self: Arc<Self>) {
+        if self.config.interval == 0 {
+            return;
+        }
+        let interval = Duration::from_secs(self.config.interval);
+
+        loop {
+            match self.export().await {
+                Ok(0) => {}
+                Ok(exported) => log::info!("Exported {} events to storage", exported),
+                Err(e) => log::error!("Event export failed: {}", e),
+            }
+            tokio::time::sleep(interval).await;
+        }
+    }
+
+    /// Export and delete everything older than the retention period, in
+    /// every region, a batch at a time, returning how many events
+    pub async fn export(&self) -> Result<usize, AuthError> {
+        let mut exported = 0;
+        for region in self.db.regions() {
+            exported += DatabaseConnection::in_region(region, self.export_region()).await?;
+        }
+        Ok(exported)
+    }
+
+    async fn export_region(&self) -> Result<usize, AuthError> {
+        let now = Utc::now();
+        let days = |days: u32| now - chrono::Duration::days(days as i64);
+
+        let audit = self.export_audit(days(self.config.retention_days)).await?;
+        let login = self
+            .export_login(days(self.config.retention_days.max(self.risk_window_days)))
+            .await?;
+        Ok(audit + login)
+    }
+
+    async fn export_audit(&self, before: DateTime<Utc>) -> Result<usize, AuthError> {
+        let batch_size = self.config.batch_size.max(1);
+
+        let mut exported = 0;
+        loop {
+            let events = self.db.find_delivered_outbox_events(before, batch_size).await?;
+            if events.is_empty() {
+                break;
+            }
+
+            let envelopes: Vec<EventEnvelope> = events.iter().map(EventEnvelope::from).collect();
+            self.write(AUDIT_STREAM, &envelopes).await?;
+            self.db
+                .delete_outbox_events(events.iter().map(|e| e.id).collect())
+                .await?;
+
+            exported += events.len();
+            if (events.len() as i64) < batch_size {
+                break;
+            }
+        }
+        Ok(exported)
+    }
+
+    async fn export_login(&self, before: DateTime<Utc>) -> Result<usize, AuthError> {
+        let batch_size = self.config.batch_size.max(1);
+
+        let mut exported = 0;
+        loop {
+            let signals = self.db.find_account_risk_signals_before(before, batch_size).await?;
+            if signals.is_empty() {
+                break;
+            }
+
+            self.write(LOGIN_STREAM, &signals).await?;
+            self.db
+                .delete_account_risk_signals(signals.iter().map(|s| s.id).collect())
+                .await?;
+
+            exported += signals.len();
+            if (signals.len() as i64) < batch_size {
+                break;
+            }
+        }
+        Ok(exported)
+    }
+
+    async fn write<T: Exported>(&self, stream: &str, rows: &[T]) -> Result<(), AuthError> {
+        for (key, rows) in partition(&self.config.prefix, stream, rows) {
+            self.storage.put(&key, "application/gzip", ndjson_gz(rows)?).await?;
+        }
+        Ok(())
+    }
+}
+
+fn export_error(e: impl std::fmt::Display) -> AuthError {
+    AuthError::InternalServerError(format!("Event export error: {}", e))
+}
+
+/// Split oldest-first rows into one file per day, keyed
+/// `<prefix>/<stream>/dt=<YYYY-MM-DD>/<first row id>.ndjson.gz`
+fn partition<'a, T: Exported>(prefix: &str, stream: &str, rows: &'a [T]) -> Vec<(String, &'a [T])> {
+    let prefix = prefix.trim_matches('/');
+    let mut files = Vec::new();
+
+    let mut start = 0;
+    for end in 1..=rows.len() {
+        let day = rows[start].occurred_at().date_naive();
+        if end < rows.len() && rows[end].occurred_at().date_naive() == day {
+            continue;
+        }
+
+        let file = format!("{}/dt={}/{}.ndjson.gz", stream, day.format("%Y-%m-%d"), rows[start].id());
+        let key = if prefix.is_empty() { file } else { format!("{}/{}", prefix, file) };
+        files.push((key, &rows[start..end]));
+        start = end;
+    }
+    files
+}
+
+/// One JSON object per line, gzipped
+fn ndjson_gz<T: Serialize>(rows: &[T]) -> Result<Vec<u8>, AuthError> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    for row in rows {
+        serde_json::to_writer(&mut encoder, row).map_err(export_error)?;
+        encoder.write_all(b"\n").map_err(export_error)?;
+    }
+    encoder.finish().map_err(export_error)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use chrono::TimeZone;
+    use flate2::read::GzDecoder;
+
+    use super::*;
+
+    fn signal(created_at: DateTime<Utc>) -> AccountRiskSignal {
+        AccountRiskSignal {
+            id: Uuid::new_v4(),
+            user_id: Uuid::new_v4(),
+            signal: "failed_login".to_string(),
+            weight: 5,
+            created_at,
+        }
+    }
+
+    #[test]
+    fn test_partition_by_day() {
+        let rows = vec![
+            signal(Utc.with_ymd_and_hms(2026, 3, 1, 9, 0, 0).unwrap()),
+            signal(Utc.with_ymd_and_hms(2026, 3, 1, 23, 59, 59).unwrap()),
+            signal(Utc.with_ymd_and_hms(2026, 3, 2, 0, 0, 0).unwrap()),
+        ];
+
+        let files = partition("/archive/", LOGIN_STREAM, &rows);
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[0].0, format!("archive/login/dt=2026-03-01/{}.ndjson.gz", rows[0].id));
+        assert_eq!(files[0].1.len(), 2);
+        assert_eq!(files[1].0, format!("archive/login/dt=2026-03-02/{}.ndjson.gz", rows[2].id));
+
+        assert!(partition::<AccountRiskSignal>("", LOGIN_STREAM, &[]).is_empty());
+        assert!(partition("", LOGIN_STREAM, &rows[..1])[0].0.starts_with("login/dt="));
+    }
+
+    #[test]
+    fn test_ndjson_gz_round_trip() {
+        let rows = vec![signal(Utc::now()), signal(Utc::now())];
+
+        let mut ndjson = String::new();
+        GzDecoder::new(&ndjson_gz(&rows).unwrap()[..])
+            .read_to_string(&mut ndjson)
+            .unwrap();
+
+        let lines: Vec<serde_json::Value> = ndjson
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[1]["id"], rows[1].id.to_string());
+    }
+}
@@ -0,0 +1,214 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use actix_web::{dev::Payload, web, FromRequest, HttpMessage, HttpRequest};
+use futures::future::LocalBoxFuture;
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::config::FeatureFlagConfig;
+use crate::db::DatabaseConnection;
+use crate::errors::AuthError;
+use crate::middleware::auth::AuthenticatedUser;
+use crate::models::FeatureFlag;
+
+/// A flag the code checks, and whether it's on until an operator sets it
+#[derive(Debug, Clone, Copy)]
+pub struct Flag {
+    pub key: &'static str,
+    pub default: bool,
+}
+
+pub const PASSWORDLESS_LOGIN: Flag = Flag {
+    key: "passwordless_login",
+    default: true,
+};
+pub const PROXY_EMAILS: Flag = Flag {
+    key: "proxy_emails",
+    default: true,
+};
+
+type Snapshot = Arc<HashMap<String, FeatureFlag>>;
+
+// Every flag, reread from the database once the cached copy is older than
+// `FEATURE_FLAG_CACHE_TTL`. Registered as app data for the `Flags` extractor.
+pub struct FeatureFlags {
+    db: Arc<DatabaseConnection>,
+    ttl: Duration,
+    cached: Mutex<Option<(Snapshot, Instant)>>,
+}
+
+impl FeatureFlags {
+    pub fn new(db: Arc<DatabaseConnection>, config: &FeatureFlagConfig) -> Self {
+        FeatureFlags {
+            db,
+            ttl: Duration::from_secs(config.cache_ttl),
+            cached: Mutex::new(None),
+        }
+    }
+
+    async fn snapshot(&self) -> Result<Snapshot, AuthError> {
+        if let Some((flags, loaded)) = self.cached.lock().unwrap().as_ref() {
+            if loaded.elapsed() < self.ttl {
+                return Ok(flags.clone());
+            }
+        }
+
+        let flags: Snapshot = Arc::new(
+            self.db
+                .find_feature_flags()
+                .await?
+                .into_iter()
+                .map(|flag| (flag.key.clone(), flag))
+                .collect(),
+        );
+        *self.cached.lock().unwrap() = Some((flags.clone(), Instant::now()));
+        Ok(flags)
+    }
+
+    /// Drop the cached flags so this instance sees a change at once
+    pub fn invalidate(&self) {
+        *self.cached.lock().unwrap() = None;
+    }
+
+    /// The flags as they apply to a user, or to an anonymous caller
+    pub async fn for_user(&self, user_id: Option<Uuid>) -> Result<Flags, AuthError> {
+        let flags = self.snapshot().await?;
+
+        // Memberships are only looked up while some flag targets organizations
+        let organization_ids = match user_id {
+            Some(user_id) if flags.values().any(|f| f.enabled && !f.organization_ids.is_empty()) => self
+                .db
+                .find_user_organizations(user_id)
+                .await?
+                .into_iter()
+                .map(|(organization, _)| organization.id)
+                .collect(),
+            _ => Vec::new(),
+        };
+
+        Ok(Flags {
+            flags,
+            user_id,
+            organization_ids,
+        })
+    }
+}
+
+/// The flags for the caller of a request, as an extractor. Routes behind
+/// `AuthMiddleware` get the signed-in user's flags; others get what applies
+/// to everyone.
+pub struct Flags {
+    flags: Snapshot,
+    user_id: Option<Uuid>,
+    organization_ids: Vec<Uuid>,
+}
+
+impl Flags {
+    pub fn is_enabled(&self, flag: Flag) -> bool {
+        match self.flags.get(flag.key) {
+            Some(stored) => is_on(stored, self.user_id, &self.organization_ids),
+            None => flag.default,
+        }
+    }
+
+    /// `PermissionDenied` unless the flag is on, for turning off a whole endpoint
+    pub fn require(&self, flag: Flag) -> Result<(), AuthError> {
+        if self.is_enabled(flag) {
+            Ok(())
+        } else {
+            Err(AuthError::PermissionDenied)
+        }
+    }
+}
+
+impl FromRequest for Flags {
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let feature_flags = req.app_data::<web::Data<FeatureFlags>>().cloned();
+        let user_id = req.extensions().get::<AuthenticatedUser>().map(|user| user.user_id);
+
+        Box::pin(async move {
+            let feature_flags = feature_flags
+                .ok_or_else(|| AuthError::InternalServerError("FeatureFlags is not registered".into()))?;
+            Ok(feature_flags.for_user(user_id).await?)
+        })
+    }
+}
+
+fn is_on(flag: &FeatureFlag, user_id: Option<Uuid>, organization_ids: &[Uuid]) -> bool {
+    if !flag.enabled {
+        return false;
+    }
+    if flag.organization_ids.iter().any(|id| organization_ids.contains(id)) {
+        return true;
+    }
+    match user_id {
+        Some(user_id) => (bucket(&flag.key, user_id) as i32) < flag.rollout_percent,
+        None => flag.rollout_percent >= 100,
+    }
+}
+
+// 0-99, the same for a user every time; hashing the key in too means each
+// flag's rollout reaches a different slice of users
+fn bucket(key: &str, user_id: Uuid) -> u32 {
+    let digest = Sha256::digest(format!("{}:{}", key, user_id).as_bytes());
+    u32::from_be_bytes([digest[0], digest[1], digest[2], digest[3]]) % 100
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+
+    use super::*;
+
+    fn flag(enabled: bool, rollout_percent: i32, organization_ids: Vec<Uuid>) -> FeatureFlag {
+        FeatureFlag {
+            key: "new_risk_rules".to_string(),
+            description: None,
+            enabled,
+            rollout_percent,
+            organization_ids,
+            updated_by: None,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn test_disabled_flag_is_off_for_everyone() {
+        let organization_id = Uuid::new_v4();
+        let disabled = flag(false, 100, vec![organization_id]);
+
+        assert!(!is_on(&disabled, Some(Uuid::new_v4()), &[organization_id]));
+        assert!(!is_on(&disabled, None, &[]));
+    }
+
+    #[test]
+    fn test_listed_organizations_skip_the_rollout() {
+        let organization_id = Uuid::new_v4();
+        let targeted = flag(true, 0, vec![organization_id]);
+
+        assert!(is_on(&targeted, Some(Uuid::new_v4()), &[organization_id]));
+        assert!(!is_on(&targeted, Some(Uuid::new_v4()), &[Uuid::new_v4()]));
+        assert!(!is_on(&targeted, None, &[]));
+    }
+
+    #[test]
+    fn test_rollout_is_stable_and_proportional() {
+        let half = flag(true, 50, Vec::new());
+        let users: Vec<Uuid> = (0..1000).map(|_| Uuid::new_v4()).collect();
+
+        let on = users.iter().filter(|user| is_on(&half, Some(**user), &[])).count();
+        assert!((400..600).contains(&on), "{} of 1000 users", on);
+        assert!(users.iter().all(|user| is_on(&half, Some(*user), &[]) == is_on(&half, Some(*user), &[])));
+
+        // Anonymous callers only see a flag that's on for everyone
+        assert!(!is_on(&half, None, &[]));
+        assert!(is_on(&flag(true, 100, Vec::new()), None, &[]));
+        assert!(!is_on(&flag(true, 0, Vec::new()), Some(users[0]), &[]));
+    }
+}
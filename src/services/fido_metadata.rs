@@ -0,0 +1,396 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use base64::Engine;
+use jsonwebtoken::{decode, decode_header, DecodingKey, Validation};
+use serde::Deserialize;
+use uuid::Uuid;
+use x509_parser::oid_registry::{OID_KEY_TYPE_EC_PUBLIC_KEY, OID_PKCS1_RSAENCRYPTION};
+use x509_parser::prelude::*;
+
+use crate::config::FidoMetadataConfig;
+use crate::db::DatabaseConnection;
+use crate::errors::AuthError;
+use crate::models::{AuthenticatorMetadata, CertificationLevel, NewAuthenticatorMetadata};
+
+// A model whose latest status report is one of these can't be trusted to
+// protect its keys, whatever it was certified to
+const COMPROMISED_STATUSES: [&str; 5] = [
+    "USER_VERIFICATION_BYPASS",
+    "ATTESTATION_KEY_COMPROMISE",
+    "USER_KEY_REMOTE_COMPROMISE",
+    "USER_KEY_PHYSICAL_COMPROMISE",
+    "REVOKED",
+];
+
+#[derive(Deserialize)]
+struct MetadataBlob {
+    no: i32, // Serial number, increased with every release
+    entries: Vec<MetadataEntry>,
+}
+
+// U2F and UAF entries have no AAGUID and are skipped
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct MetadataEntry {
+    aaguid: Option<Uuid>,
+    metadata_statement: Option<MetadataStatement>,
+    #[serde(default)]
+    status_reports: Vec<StatusReport>,
+}
+
+#[derive(Deserialize)]
+struct MetadataStatement {
+    description: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct StatusReport {
+    status: String,
+    effective_date: Option<String>, // ISO 8601 date, so it sorts as text
+}
+
+// Authenticator models from the FIDO Alliance metadata service. The signed
+// BLOB is synced into the database in the background, and kept in memory for
+// naming passkeys and checking them at registration.
+pub struct FidoMetadata {
+    db: Arc<DatabaseConnection>,
+    client: reqwest::Client,
+    config: FidoMetadataConfig,
+    root: Option<Vec<u8>>, // DER of the certificate the BLOB's chain must end at
+    models: RwLock<HashMap<Uuid, AuthenticatorMetadata>>,
+}
+
+impl FidoMetadata {
+    pub fn new(db: Arc<DatabaseConnection>, config: &FidoMetadataConfig) -> Self {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(config.timeout))
+            .build()
+            .expect("Failed to build FIDO metadata client");
+
+        let root = match (&config.root_certificate, config.enabled) {
+            (Some(path), true) => {
+                let pem = std::fs::read(path).expect("FIDO_MDS_ROOT_CERT must be readable");
+                let (_, pem) = parse_x509_pem(&pem).expect("FIDO_MDS_ROOT_CERT must be a PEM certificate");
+                Some(pem.contents)
+            }
+            (None, true) => panic!("FIDO_MDS_ROOT_CERT is required when FIDO_MDS_ENABLED is set"),
+            (_, false) => None,
+        };
+
+        FidoMetadata {
+            db,
+            client,
+            config: config.clone(),
+            root,
+            models: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Load what was synced before, then check for a newer BLOB on the
+    /// configured interval until the process exits; spawn at startup.
+    /// Returns at once when the metadata service is off.
+    pub async fn run(self: Arc<Self>) {
+        if !self.config.enabled {
+            return;
+        }
+        if let Err(e) = self.load().await {
+            log::error!("Loading cached FIDO metadata failed: {}", e);
+        }
+        let interval = Duration::from_secs(self.config.interval.max(60));
+
+        loop {
+            match self.sync().await {
+                Ok(Some(number)) => log::info!("Synced FIDO metadata BLOB {}", number),
+                Ok(None) => {}
+                Err(e) => log::error!("FIDO metadata sync failed: {}", e),
+            }
+            tokio::time::sleep(interval).await;
+        }
+    }
+
+    /// Replace the in-memory models with those last synced to the database
+    pub async fn load(&self) -> Result<(), AuthError> {
+        let models = self.db.find_authenticator_metadata().await?;
+        *self.models.write().unwrap() = models.into_iter().map(|m| (m.aaguid, m)).collect();
+        Ok(())
+    }
+
+    /// Download the BLOB and, when it's newer than the one cached, verify it
+    /// and replace the cache. Returns the serial number of a newly synced BLOB.
+    pub async fn sync(&self) -> Result<Option<i32>, AuthError> {
+        let response = self
+            .client
+            .get(&self.config.url)
+            .send()
+            .await
+            .map_err(|e| AuthError::InternalServerError(format!("Failed to fetch FIDO metadata: {}", e)))?;
+        if !response.status().is_success() {
+            return Err(AuthError::InternalServerError(format!(
+                "FIDO metadata service returned {}",
+                response.status()
+            )));
+        }
+        let jwt = response
+            .text()
+            .await
+            .map_err(|e| AuthError::InternalServerError(format!("Failed to read FIDO metadata: {}", e)))?;
+
+        let blob = self.verify(jwt.trim())?;
+        if blob.no <= self.blob_number() {
+            return Ok(None);
+        }
+
+        let models = blob.entries.iter().filter_map(|entry| summarize(entry, blob.no)).collect();
+        self.db.replace_authenticator_metadata(models).await?;
+        self.load().await?;
+
+        Ok(Some(blob.no))
+    }
+
+    /// The model a passkey was made on, when it's listed
+    pub fn model(&self, aaguid: Uuid) -> Option<AuthenticatorMetadata> {
+        self.models.read().unwrap().get(&aaguid).cloned()
+    }
+
+    /// Friendly name for a passkey's model, e.g. "YubiKey 5 Series"
+    pub fn name_for(&self, aaguid: Option<Uuid>) -> Option<String> {
+        aaguid.and_then(|aaguid| self.model(aaguid)).map(|m| m.description)
+    }
+
+    /// Whether a passkey made on the model `aaguid` may be registered. Always
+    /// allowed when the metadata service is off.
+    pub fn check_registration(&self, aaguid: Option<Uuid>) -> Result<(), AuthError> {
+        if !self.config.enabled {
+            return Ok(());
+        }
+        let model = aaguid.and_then(|aaguid| self.model(aaguid));
+        registration_policy(&self.config, model.as_ref())
+    }
+
+    // Serial number of the cached BLOB, 0 before the first sync
+    fn blob_number(&self) -> i32 {
+        self.models.read().unwrap().values().map(|m| m.blob_number).max().unwrap_or(0)
+    }
+
+    // The BLOB's payload, once its signature checks out against a certificate
+    // chain ending at the configured root
+    fn verify(&self, jwt: &str) -> Result<MetadataBlob, AuthError> {
+        let invalid = |reason: String| AuthError::InternalServerError(format!("Invalid FIDO metadata BLOB: {}", reason));
+        let root = self
+            .root
+            .as_deref()
+            .ok_or_else(|| invalid("no root certificate configured".into()))?;
+
+        let header = decode_header(jwt).map_err(|e| invalid(e.to_string()))?;
+        let chain = header
+            .x5c
+            .as_ref()
+            .filter(|chain| !chain.is_empty())
+            .ok_or_else(|| invalid("no certificate chain".into()))?
+            .iter()
+            .map(|cert| base64::engine::general_purpose::STANDARD.decode(cert))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| invalid(e.to_string()))?;
+        let key = signing_key(&chain, root).map_err(invalid)?;
+
+        // The BLOB has no registered claims; its freshness is `no` and `nextUpdate`
+        let mut validation = Validation::new(header.alg);
+        validation.required_spec_claims.clear();
+        validation.validate_exp = false;
+
+        let data = decode::<MetadataBlob>(jwt, &key, &validation).map_err(|e| invalid(e.to_string()))?;
+        Ok(data.claims)
+    }
+}
+
+// The leaf's public key, after checking each certificate in `chain` is
+// current and signed by the next, and the last by `root`
+fn signing_key(chain: &[Vec<u8>], root: &[u8]) -> Result<DecodingKey, String> {
+    let (_, root) = X509Certificate::from_der(root).map_err(|e| e.to_string())?;
+    let certificates = chain
+        .iter()
+        .map(|der| X509Certificate::from_der(der).map(|(_, cert)| cert))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+
+    for (i, certificate) in certificates.iter().enumerate() {
+        if !certificate.validity().is_valid() {
+            return Err(format!("certificate {} has expired or isn't valid yet", certificate.subject()));
+        }
+        // Only CAs may sign other certificates in the chain
+        if i > 0 && !matches!(certificate.basic_constraints(), Ok(Some(constraints)) if constraints.value.ca) {
+            return Err(format!("certificate {} isn't a CA", certificate.subject()));
+        }
+        let issuer = certificates.get(i + 1).unwrap_or(&root);
+        certificate
+            .verify_signature(Some(issuer.public_key()))
+            .map_err(|_| format!("certificate {} isn't signed by {}", certificate.subject(), issuer.subject()))?;
+    }
+
+    let leaf = certificates[0].public_key();
+    let key = &leaf.subject_public_key.data;
+    if leaf.algorithm.algorithm == OID_PKCS1_RSAENCRYPTION {
+        Ok(DecodingKey::from_rsa_der(key))
+    } else if leaf.algorithm.algorithm == OID_KEY_TYPE_EC_PUBLIC_KEY {
+        Ok(DecodingKey::from_ec_der(key))
+    } else {
+        Err("unsupported signing key type".into())
+    }
+}
+
+// What's kept of an entry: its name, latest certification and whether it's
+// compromised. Reports are ordered by date, since the BLOB doesn't promise to.
+fn summarize(entry: &MetadataEntry, blob_number: i32) -> Option<NewAuthenticatorMetadata> {
+    let aaguid = entry.aaguid?;
+    let description = entry.metadata_statement.as_ref()?.description.clone();
+
+    let mut reports: Vec<&StatusReport> = entry.status_reports.iter().collect();
+    reports.sort_by(|a, b| a.effective_date.cmp(&b.effective_date));
+
+    let certification_level = reports
+        .iter()
+        .rev()
+        .find_map(|report| report.status.parse::<CertificationLevel>().ok())
+        .map(|level| level.as_str().to_string());
+    let compromised = reports
+        .last()
+        .map_or(false, |report| COMPROMISED_STATUSES.contains(&report.status.as_str()));
+
+    Some(NewAuthenticatorMetadata {
+        aaguid,
+        description,
+        certification_level,
+        compromised,
+        blob_number,
+    })
+}
+
+// Compromised models are always refused. Otherwise listed models must meet
+// FIDO_MDS_MIN_CERTIFICATION, and unlisted ones, including those that hide
+// their AAGUID, are up to FIDO_MDS_ALLOW_UNLISTED.
+fn registration_policy(config: &FidoMetadataConfig, model: Option<&AuthenticatorMetadata>) -> Result<(), AuthError> {
+    let model = match model {
+        Some(model) => model,
+        None if config.allow_unlisted => return Ok(()),
+        None => {
+            return Err(AuthError::AuthenticatorNotAllowed(
+                "it isn't listed by the FIDO metadata service".into(),
+            ));
+        }
+    };
+
+    if model.compromised {
+        return Err(AuthError::AuthenticatorNotAllowed(format!("{} has a known security issue", model.description)));
+    }
+    if let Some(minimum) = config.min_certification {
+        if model.certification() < Some(minimum) {
+            return Err(AuthError::AuthenticatorNotAllowed(format!(
+                "{} isn't certified to {}",
+                model.description,
+                minimum.as_str()
+            )));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+
+    use super::*;
+
+    fn config(min_certification: Option<CertificationLevel>, allow_unlisted: bool) -> FidoMetadataConfig {
+        FidoMetadataConfig {
+            enabled: true,
+            url: "https://mds3.fidoalliance.org/".to_string(),
+            root_certificate: None,
+            interval: 86400,
+            timeout: 30,
+            min_certification,
+            allow_unlisted,
+        }
+    }
+
+    fn entry(reports: &[(&str, &str)]) -> MetadataEntry {
+        MetadataEntry {
+            aaguid: Some(Uuid::new_v4()),
+            metadata_statement: Some(MetadataStatement {
+                description: "Security Key".to_string(),
+            }),
+            status_reports: reports
+                .iter()
+                .map(|(status, date)| StatusReport {
+                    status: status.to_string(),
+                    effective_date: Some(date.to_string()),
+                })
+                .collect(),
+        }
+    }
+
+    fn model(entry: &MetadataEntry) -> AuthenticatorMetadata {
+        let new = summarize(entry, 1).unwrap();
+        AuthenticatorMetadata {
+            aaguid: new.aaguid,
+            description: new.description,
+            certification_level: new.certification_level,
+            compromised: new.compromised,
+            blob_number: new.blob_number,
+            updated_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn test_latest_reports_decide_certification_and_compromise() {
+        let upgraded = model(&entry(&[
+            ("FIDO_CERTIFIED_L2", "2023-06-01"),
+            ("FIDO_CERTIFIED_L1", "2021-01-01"),
+            ("UPDATE_AVAILABLE", "2024-01-01"),
+        ]));
+        assert_eq!(upgraded.certification(), Some(CertificationLevel::L2));
+        assert!(!upgraded.compromised);
+
+        let compromised = model(&entry(&[
+            ("FIDO_CERTIFIED", "2020-01-01"),
+            ("ATTESTATION_KEY_COMPROMISE", "2022-01-01"),
+        ]));
+        assert_eq!(compromised.certification(), Some(CertificationLevel::L1));
+        assert!(compromised.compromised);
+
+        // Fixed by a later update
+        let fixed = model(&entry(&[
+            ("USER_VERIFICATION_BYPASS", "2022-01-01"),
+            ("FIDO_CERTIFIED_L1", "2023-01-01"),
+        ]));
+        assert!(!fixed.compromised);
+
+        let uncertified = model(&entry(&[("SELF_ASSERTION_SUBMITTED", "2022-01-01")]));
+        assert_eq!(uncertified.certification(), Some(CertificationLevel::NotCertified));
+        assert!(summarize(&MetadataEntry { aaguid: None, ..entry(&[]) }, 1).is_none());
+    }
+
+    #[test]
+    fn test_registration_policy() {
+        let l1 = model(&entry(&[("FIDO_CERTIFIED_L1", "2023-01-01")]));
+        let l2 = model(&entry(&[("FIDO_CERTIFIED_L2", "2023-01-01")]));
+        let unreported = model(&entry(&[]));
+        let revoked = model(&entry(&[("FIDO_CERTIFIED_L3", "2020-01-01"), ("REVOKED", "2023-01-01")]));
+
+        let open = config(None, true);
+        assert!(registration_policy(&open, Some(&l1)).is_ok());
+        assert!(registration_policy(&open, None).is_ok());
+        assert!(matches!(
+            registration_policy(&open, Some(&revoked)),
+            Err(AuthError::AuthenticatorNotAllowed(_))
+        ));
+
+        let strict = config(Some(CertificationLevel::L2), false);
+        assert!(registration_policy(&strict, Some(&l2)).is_ok());
+        assert!(registration_policy(&strict, Some(&l1)).is_err());
+        assert!(registration_policy(&strict, Some(&unreported)).is_err());
+        assert!(registration_policy(&strict, None).is_err());
+    }
+}
@@ -0,0 +1,157 @@
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+
+use crate::config::IpReputationConfig;
+use crate::risk_scoring::{IpDenylist, IpReputation, IpReputationProvider, IpReputationVerdict};
+
+const ABUSEIPDB_CHECK_URL: &str = "https://api.abuseipdb.com/api/v2/check";
+
+// Reports older than this don't count towards an address's AbuseIPDB score
+const ABUSEIPDB_MAX_AGE_DAYS: u32 = 90;
+
+#[derive(Deserialize)]
+struct AbuseIpDbResponse {
+    data: AbuseIpDbCheck,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AbuseIpDbCheck {
+    abuse_confidence_score: u32,
+}
+
+// Confidence scores from AbuseIPDB. An address seen for the first time is
+// looked up in the background and scores nothing until the answer arrives,
+// so a slow or unreachable API never holds up a login.
+pub struct AbuseIpDb {
+    client: reqwest::Client,
+    api_key: String,
+    cache_ttl: Duration,
+    scores: Arc<Mutex<HashMap<IpAddr, (u32, Instant)>>>,
+    pending: Arc<Mutex<HashSet<IpAddr>>>,
+}
+
+impl AbuseIpDb {
+    pub fn new(api_key: String, cache_ttl: u64, timeout: u64) -> Self {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(timeout))
+            .build()
+            .expect("Failed to build AbuseIPDB client");
+
+        AbuseIpDb {
+            client,
+            api_key,
+            cache_ttl: Duration::from_secs(cache_ttl),
+            scores: Arc::new(Mutex::new(HashMap::new())),
+            pending: Arc::new(Mutex::new(HashSet::new())),
+        }
+    }
+
+    fn fetch_in_background(&self, ip: IpAddr) {
+        if !self.pending.lock().unwrap().insert(ip) {
+            return;
+        }
+
+        let request = self
+            .client
+            .get(ABUSEIPDB_CHECK_URL)
+            .query(&[
+                ("ipAddress", ip.to_string()),
+                ("maxAgeInDays", ABUSEIPDB_MAX_AGE_DAYS.to_string()),
+            ])
+            .header("Key", &self.api_key)
+            .header("Accept", "application/json");
+        let scores = self.scores.clone();
+        let pending = self.pending.clone();
+
+        tokio::spawn(async move {
+            let result = match request.send().await {
+                Ok(response) if response.status().is_success() => {
+                    response.json::<AbuseIpDbResponse>().await.map_err(|e| e.to_string())
+                }
+                Ok(response) => Err(format!("status {}", response.status())),
+                Err(e) => Err(e.to_string()),
+            };
+
+            match result {
+                Ok(body) => {
+                    let score = body.data.abuse_confidence_score.min(100);
+                    scores.lock().unwrap().insert(ip, (score, Instant::now()));
+                }
+                Err(e) => log::warn!("AbuseIPDB lookup for {} failed: {}", ip, e),
+            }
+            pending.lock().unwrap().remove(&ip);
+        });
+    }
+}
+
+impl IpReputationProvider for AbuseIpDb {
+    fn lookup(&self, ip: &IpAddr) -> Option<IpReputationVerdict> {
+        // Nothing to learn about internal addresses
+        if !is_public(ip) {
+            return None;
+        }
+
+        let cached = {
+            let mut scores = self.scores.lock().unwrap();
+            scores.retain(|_, (_, at)| at.elapsed() < self.cache_ttl);
+            scores.get(ip).map(|(score, _)| *score)
+        };
+
+        match cached {
+            Some(score) => Some(IpReputationVerdict {
+                source: "abuseipdb".to_string(),
+                confidence: score,
+            }),
+            None => {
+                self.fetch_in_background(*ip);
+                None
+            }
+        }
+    }
+}
+
+fn is_public(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => !(ip.is_private() || ip.is_loopback() || ip.is_link_local() || ip.is_unspecified()),
+        IpAddr::V6(ip) => !(ip.is_loopback() || ip.is_unspecified()),
+    }
+}
+
+/// Reputation sources for `RiskScoringContext::with_ip_reputation`, or `None`
+/// when neither a denylist nor an AbuseIPDB key is configured
+pub fn ip_reputation(config: &IpReputationConfig) -> Option<IpReputation> {
+    if config.denylist.is_empty() && config.abuseipdb_api_key.is_none() {
+        return None;
+    }
+
+    let mut reputation = IpReputation::new(config.weight, config.min_confidence);
+    if !config.denylist.is_empty() {
+        let denylist = IpDenylist::parse(&config.denylist)
+            .unwrap_or_else(|e| panic!("IP_DENYLIST must list addresses or CIDR networks: {}", e));
+        reputation = reputation.with_provider(Box::new(denylist));
+    }
+    if let Some(api_key) = &config.abuseipdb_api_key {
+        let abuseipdb = AbuseIpDb::new(api_key.clone(), config.cache_ttl, config.timeout);
+        reputation = reputation.with_provider(Box::new(abuseipdb));
+    }
+
+    Some(reputation)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_public() {
+        assert!(is_public(&"203.0.113.7".parse().unwrap()));
+        assert!(!is_public(&"10.1.2.3".parse().unwrap()));
+        assert!(!is_public(&"127.0.0.1".parse().unwrap()));
+        assert!(!is_public(&"::1".parse().unwrap()));
+    }
+}
@@ -0,0 +1,152 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use rand::{distributions::Alphanumeric, Rng};
+use uuid::Uuid;
+
+use crate::config::LoginApprovalConfig;
+use crate::errors::AuthError;
+use crate::models::SessionLifetime;
+use crate::utils::network::NetworkFingerprint;
+
+/// A login held back until the account owner approves it by email, with
+/// what the session it starts once approved is to carry
+#[derive(Debug, Clone)]
+pub struct PendingLogin {
+    pub user_id: Uuid,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub amr: Vec<String>,            // How the user signed in before the login was held
+    pub lifetime: SessionLifetime,   // As the login checks decided
+    pub dpop_jkt: Option<String>,    // Thumbprint of the client's DPoP key, if it sent a proof
+    pub network: NetworkFingerprint, // Checked against login policies and bound to the session
+}
+
+// A held login and where its approval stands
+struct HeldLogin {
+    login: PendingLogin,
+    approve_token: String,
+    approved: bool,
+    created_at: Instant,
+}
+
+// High-risk logins waiting on the emailed approval link. The requesting client
+// holds the approval id and polls with it; only the mailbox holds the token.
+pub struct LoginApprovals {
+    ttl: Duration,
+    pending: Mutex<HashMap<Uuid, HeldLogin>>,
+}
+
+impl LoginApprovals {
+    pub fn new(config: &LoginApprovalConfig) -> Self {
+        LoginApprovals {
+            ttl: Duration::from_secs(config.ttl),
+            pending: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Hold a login for approval, returning `(approval_id, approve_token)`
+    pub fn create(&self, login: PendingLogin) -> (Uuid, String) {
+        let approval_id = Uuid::new_v4();
+        let approve_token: String = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(48)
+            .map(char::from)
+            .collect();
+
+        let mut pending = self.pending.lock().unwrap();
+        pending.retain(|_, login| login.created_at.elapsed() < self.ttl);
+        pending.insert(
+            approval_id,
+            HeldLogin {
+                login,
+                approve_token: approve_token.clone(),
+                approved: false,
+                created_at: Instant::now(),
+            },
+        );
+
+        (approval_id, approve_token)
+    }
+
+    /// Mark the login behind an emailed token as approved
+    pub fn approve(&self, approve_token: &str) -> Result<(), AuthError> {
+        let mut pending = self.pending.lock().unwrap();
+        let login = pending
+            .values_mut()
+            .find(|login| login.approve_token == approve_token)
+            .filter(|login| login.created_at.elapsed() < self.ttl)
+            .ok_or(AuthError::InvalidToken)?;
+
+        login.approved = true;
+        Ok(())
+    }
+
+    /// Claim an approved login; each approval can be completed once
+    pub fn complete(&self, approval_id: Uuid) -> Result<PendingLogin, AuthError> {
+        let mut pending = self.pending.lock().unwrap();
+
+        match pending.get(&approval_id) {
+            Some(login) if login.created_at.elapsed() >= self.ttl => {
+                pending.remove(&approval_id);
+                Err(AuthError::TokenExpired)
+            }
+            Some(login) if !login.approved => Err(AuthError::LoginApprovalPending),
+            Some(_) => Ok(pending.remove(&approval_id).unwrap().login),
+            None => Err(AuthError::InvalidToken),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn approvals() -> LoginApprovals {
+        LoginApprovals::new(&LoginApprovalConfig {
+            enabled: true,
+            ttl: 900,
+        })
+    }
+
+    fn pending(user_id: Uuid) -> PendingLogin {
+        PendingLogin {
+            user_id,
+            ip: None,
+            user_agent: None,
+            amr: vec!["pwd".into()],
+            lifetime: SessionLifetime::Shortened,
+            dpop_jkt: Some("jkt".into()),
+            network: NetworkFingerprint::default(),
+        }
+    }
+
+    #[test]
+    fn test_login_completes_once_approved() {
+        let approvals = approvals();
+        let user_id = Uuid::new_v4();
+        let (approval_id, token) = approvals.create(pending(user_id));
+
+        assert!(matches!(
+            approvals.complete(approval_id),
+            Err(AuthError::LoginApprovalPending)
+        ));
+
+        approvals.approve(&token).unwrap();
+        let login = approvals.complete(approval_id).unwrap();
+        assert_eq!(login.user_id, user_id);
+        // The session is started as the held login would have been
+        assert_eq!(login.lifetime, SessionLifetime::Shortened);
+        assert_eq!(login.dpop_jkt.as_deref(), Some("jkt"));
+        assert!(matches!(approvals.complete(approval_id), Err(AuthError::InvalidToken)));
+    }
+
+    #[test]
+    fn test_unknown_token_is_rejected() {
+        let approvals = approvals();
+        approvals.create(pending(Uuid::new_v4()));
+
+        assert!(approvals.approve("not-a-token").is_err());
+    }
+}
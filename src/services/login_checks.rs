@@ -0,0 +1,405 @@
+use std::sync::Arc;
+
+use chrono::Utc;
+use uuid::Uuid;
+
+use crate::breach_detection::BreachDetectionContext;
+use crate::config::{Config, EmailVerificationPolicy};
+use crate::errors::AuthError;
+use crate::models::{LoginPolicy, LoginPolicyAction, SessionLifetime, User};
+use crate::risk_scoring::{GeoLocation, LoginRecord, RiskAction, RiskScoringContext};
+use crate::services::security_events::{SecurityEvent, SecurityEventKind, SecurityWebhook};
+use crate::utils::password::verify_password;
+
+/// Everything a check knows about a login attempt
+pub struct LoginAttempt<'a> {
+    pub user: &'a User,
+    pub password: Option<&'a str>,         // None when an emailed code, passkey or IdP stood in for it
+    pub ip: Option<&'a str>,
+    pub user_agent: Option<&'a str>,
+    pub country: Option<&'a str>,          // ISO 3166 alpha-2, as reported by the proxy
+    pub location: Option<&'a GeoLocation>, // Coordinates reported by the proxy, if any
+    pub policies: &'a [LoginPolicy],       // The user's own and their organizations'
+    pub new_device: bool,                  // The user has signed in before, never from this device
+    pub trusted_device: bool,              // Came with the token of a device trusted at an email code login
+}
+
+// Ordered from least to most restrictive; the pipeline keeps the strictest
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum CheckOutcome {
+    Continue,
+    RequireCaptcha,  // Let the attempt through once it comes with a solved CAPTCHA
+    RequireMfa,      // Let the attempt through, but only after a second factor
+    RequireApproval, // Hold the attempt until the owner approves it by email
+}
+
+/// A single step of the login pipeline. Returning an error stops the
+/// pipeline and fails the login with that error.
+pub trait LoginCheck: Send + Sync {
+    /// Stable name used to position other checks relative to this one
+    fn name(&self) -> &'static str;
+
+    fn check(&self, attempt: &LoginAttempt) -> Result<CheckOutcome, AuthError>;
+
+    /// How long the session should last if the login goes through, or `None`
+    /// to leave it to the other checks. `outcome` is what `check` returned.
+    fn session_lifetime(&self, _attempt: &LoginAttempt, _outcome: CheckOutcome) -> Option<SessionLifetime> {
+        None
+    }
+}
+
+/// What the pipeline made of an attempt that passed every check
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LoginVerdict {
+    pub outcome: CheckOutcome,
+    pub lifetime: SessionLifetime, // For the session started once `outcome` is satisfied
+}
+
+/// Ordered checks run by every login once the user has been found
+pub struct LoginPipeline {
+    checks: Vec<Box<dyn LoginCheck>>,
+}
+
+impl LoginPipeline {
+    /// The built-in checks: credentials, breach, active, archived, verified,
+    /// MFA policy, login policy, risk, device
+    pub fn new(
+        config: &Config,
+        breach: Arc<BreachDetectionContext>,
+        risk: Arc<RiskScoringContext>,
+        webhook: Arc<SecurityWebhook>,
+    ) -> Self {
+        let risk = RiskCheck::new(risk)
+            .with_webhook(webhook)
+            .with_email_approval(config.login_approval.enabled);
+
+        LoginPipeline {
+            checks: vec![
+                Box::new(CredentialsCheck),
+                // Only after the password, so flagged accounts can't be found by guessing
+                Box::new(BreachCheck(breach)),
+                Box::new(ActiveCheck),
+                Box::new(ArchivedCheck),
+                Box::new(EmailVerifiedCheck(config.email.require_verified_email)),
+                Box::new(MfaPolicyCheck),
+                Box::new(LoginPolicyCheck),
+                Box::new(risk),
+                Box::new(DeviceCheck),
+            ],
+        }
+    }
+
+    pub fn push(&mut self, check: Box<dyn LoginCheck>) {
+        self.checks.push(check);
+    }
+
+    /// Insert a check ahead of the named one, or at the end if it isn't present
+    pub fn insert_before(&mut self, name: &str, check: Box<dyn LoginCheck>) {
+        match self.checks.iter().position(|c| c.name() == name) {
+            Some(index) => self.checks.insert(index, check),
+            None => self.checks.push(check),
+        }
+    }
+
+    /// Insert a check after the named one, or at the end if it isn't present
+    pub fn insert_after(&mut self, name: &str, check: Box<dyn LoginCheck>) {
+        match self.checks.iter().position(|c| c.name() == name) {
+            Some(index) => self.checks.insert(index + 1, check),
+            None => self.checks.push(check),
+        }
+    }
+
+    pub fn remove(&mut self, name: &str) {
+        self.checks.retain(|c| c.name() != name);
+    }
+
+    /// Run every check in order, returning the strictest outcome and the
+    /// shortest session lifetime any check asked for. Attempts a check wants
+    /// a CAPTCHA for were risky enough to get a shortened session.
+    pub fn run(&self, attempt: &LoginAttempt) -> Result<LoginVerdict, AuthError> {
+        let mut outcome = CheckOutcome::Continue;
+        let mut lifetime = None;
+
+        for check in &self.checks {
+            let checked = check.check(attempt)?;
+            let asked = match checked {
+                CheckOutcome::RequireCaptcha => Some(SessionLifetime::Shortened),
+                _ => check.session_lifetime(attempt, checked),
+            };
+            outcome = outcome.max(checked);
+            lifetime = lifetime.max(asked);
+        }
+
+        Ok(LoginVerdict {
+            outcome,
+            lifetime: lifetime.unwrap_or_default(),
+        })
+    }
+}
+
+pub struct CredentialsCheck;
+
+impl LoginCheck for CredentialsCheck {
+    fn name(&self) -> &'static str {
+        "credentials"
+    }
+
+    fn check(&self, attempt: &LoginAttempt) -> Result<CheckOutcome, AuthError> {
+        let password = match attempt.password {
+            Some(password) => password,
+            None => return Ok(CheckOutcome::Continue),
+        };
+        if !verify_password(password, &attempt.user.password_hash)? {
+            return Err(AuthError::InvalidCredentials);
+        }
+        Ok(CheckOutcome::Continue)
+    }
+}
+
+pub struct ActiveCheck;
+
+impl LoginCheck for ActiveCheck {
+    fn name(&self) -> &'static str {
+        "active"
+    }
+
+    fn check(&self, attempt: &LoginAttempt) -> Result<CheckOutcome, AuthError> {
+        // `AuthService` attaches the status token to this error
+        if !attempt.user.is_active() {
+            return Err(AuthError::AccountDisabled { status_token: None });
+        }
+        Ok(CheckOutcome::Continue)
+    }
+}
+
+pub struct ArchivedCheck;
+
+impl LoginCheck for ArchivedCheck {
+    fn name(&self) -> &'static str {
+        "archived"
+    }
+
+    fn check(&self, attempt: &LoginAttempt) -> Result<CheckOutcome, AuthError> {
+        // `AuthService` emails the reactivation link
+        if attempt.user.is_archived() {
+            return Err(AuthError::AccountArchived);
+        }
+        Ok(CheckOutcome::Continue)
+    }
+}
+
+pub struct EmailVerifiedCheck(pub EmailVerificationPolicy);
+
+impl LoginCheck for EmailVerifiedCheck {
+    fn name(&self) -> &'static str {
+        "verified"
+    }
+
+    fn check(&self, attempt: &LoginAttempt) -> Result<CheckOutcome, AuthError> {
+        // `AuthService` attaches the resend token to this error
+        if self.0 == EmailVerificationPolicy::Login && !attempt.user.is_email_verified {
+            return Err(AuthError::EmailNotVerified { resend_token: None });
+        }
+        Ok(CheckOutcome::Continue)
+    }
+}
+
+pub struct MfaPolicyCheck;
+
+impl LoginCheck for MfaPolicyCheck {
+    fn name(&self) -> &'static str {
+        "mfa_policy"
+    }
+
+    fn check(&self, attempt: &LoginAttempt) -> Result<CheckOutcome, AuthError> {
+        if attempt.user.mfa_enabled {
+            return Ok(CheckOutcome::RequireMfa);
+        }
+        Ok(CheckOutcome::Continue)
+    }
+}
+
+/// Holds logins to the countries and times of day the user's and their
+/// organizations' login policies allow. A policy set to step up asks for the
+/// second factor instead of refusing, when the user has one.
+pub struct LoginPolicyCheck;
+
+impl LoginCheck for LoginPolicyCheck {
+    fn name(&self) -> &'static str {
+        "login_policy"
+    }
+
+    fn check(&self, attempt: &LoginAttempt) -> Result<CheckOutcome, AuthError> {
+        let now = Utc::now();
+        let mut outcome = CheckOutcome::Continue;
+
+        for policy in attempt.policies {
+            let violation = match policy.violation(now, attempt.country) {
+                Some(violation) => violation,
+                None => continue,
+            };
+            log::info!(
+                "Login for user {} outside login policy {}: {:?}",
+                attempt.user.id,
+                policy.id,
+                violation
+            );
+            match policy.action() {
+                LoginPolicyAction::StepUp if attempt.user.mfa_enabled => outcome = CheckOutcome::RequireMfa,
+                _ => return Err(AuthError::LoginNotAllowed),
+            }
+        }
+
+        Ok(outcome)
+    }
+}
+
+/// Extends sessions started on a device the user has trusted, and shortens
+/// those on any other device they haven't signed in from before
+pub struct DeviceCheck;
+
+impl LoginCheck for DeviceCheck {
+    fn name(&self) -> &'static str {
+        "device"
+    }
+
+    fn check(&self, _attempt: &LoginAttempt) -> Result<CheckOutcome, AuthError> {
+        Ok(CheckOutcome::Continue)
+    }
+
+    fn session_lifetime(&self, attempt: &LoginAttempt, _outcome: CheckOutcome) -> Option<SessionLifetime> {
+        if attempt.trusted_device {
+            Some(SessionLifetime::Extended)
+        } else if attempt.new_device {
+            Some(SessionLifetime::Shortened)
+        } else {
+            None
+        }
+    }
+}
+
+/// Refuses logins for accounts flagged by breach detection until the password is reset
+pub struct BreachCheck(pub Arc<BreachDetectionContext>);
+
+impl LoginCheck for BreachCheck {
+    fn name(&self) -> &'static str {
+        "breach"
+    }
+
+    fn check(&self, attempt: &LoginAttempt) -> Result<CheckOutcome, AuthError> {
+        if self.0.is_password_reset_required(&attempt.user.id) {
+            return Err(AuthError::PasswordResetRequired);
+        }
+        Ok(CheckOutcome::Continue)
+    }
+}
+
+/// Blocks high-risk logins (or holds them for email approval), asks for MFA on
+/// elevated ones when the user has it, and for a CAPTCHA on moderate ones or
+/// when there's no second factor to ask for. Blocked logins and impossible
+/// travel are also reported to the security webhook. Every attempt with the
+/// right password is kept as history for scoring the next one, and any login
+/// it asked more of gets a shortened session.
+pub struct RiskCheck {
+    risk: Arc<RiskScoringContext>,
+    webhook: Option<Arc<SecurityWebhook>>,
+    email_approval: bool,
+}
+
+impl RiskCheck {
+    pub fn new(risk: Arc<RiskScoringContext>) -> Self {
+        RiskCheck {
+            risk,
+            webhook: None,
+            email_approval: false,
+        }
+    }
+
+    pub fn with_webhook(mut self, webhook: Arc<SecurityWebhook>) -> Self {
+        self.webhook = Some(webhook);
+        self
+    }
+
+    /// Hold high-risk logins for approval instead of blocking them (`LOGIN_APPROVAL_ENABLED`)
+    pub fn with_email_approval(mut self, enabled: bool) -> Self {
+        self.email_approval = enabled;
+        self
+    }
+}
+
+impl LoginCheck for RiskCheck {
+    fn name(&self) -> &'static str {
+        "risk"
+    }
+
+    fn check(&self, attempt: &LoginAttempt) -> Result<CheckOutcome, AuthError> {
+        let record = LoginRecord {
+            timestamp: Utc::now(),
+            ip_address: attempt.ip.unwrap_or("unknown").to_string(),
+            location: attempt.location.cloned(),
+            device_id: String::new(),
+            user_agent: attempt.user_agent.unwrap_or_default().to_string(),
+            success: true,
+        };
+
+        let analysis = self.risk.analyze_login_risk(&attempt.user.id, &record);
+        let blocked = analysis.action == RiskAction::Block;
+        // Blocked attempts don't count as somewhere the user has been
+        self.risk.record_login(
+            &attempt.user.id,
+            LoginRecord {
+                success: !blocked,
+                ..record.clone()
+            },
+        );
+
+        let impossible_travel = analysis.factors.iter().any(|f| f.name == "impossible_travel");
+        if let Some(webhook) = self.webhook.as_ref().filter(|_| blocked || impossible_travel) {
+            webhook.notify(SecurityEvent {
+                id: Uuid::new_v4(),
+                kind: match (blocked, self.email_approval) {
+                    (true, true) => SecurityEventKind::LoginApprovalRequested,
+                    (true, false) => SecurityEventKind::LoginBlocked,
+                    _ => SecurityEventKind::ImpossibleTravel,
+                },
+                priority: "high",
+                user_id: attempt.user.id,
+                score: analysis.score,
+                factors: analysis.factors.clone(),
+                ip_address: attempt.ip.map(|ip| ip.to_string()),
+                user_agent: attempt.user_agent.map(|ua| ua.to_string()),
+                location: record.location.clone(),
+                occurred_at: record.timestamp,
+            });
+        }
+
+        if blocked && self.email_approval {
+            log::warn!(
+                "Holding login for user {} with risk score {} for email approval",
+                attempt.user.id,
+                analysis.score
+            );
+            return Ok(CheckOutcome::RequireApproval);
+        }
+
+        if blocked {
+            log::warn!(
+                "Blocked login for user {} with risk score {}",
+                attempt.user.id,
+                analysis.score
+            );
+            return Err(AuthError::PermissionDenied);
+        }
+
+        match analysis.action {
+            RiskAction::RequireMfa if attempt.user.mfa_enabled => Ok(CheckOutcome::RequireMfa),
+            RiskAction::RequireMfa | RiskAction::RequireCaptcha => Ok(CheckOutcome::RequireCaptcha),
+            _ => Ok(CheckOutcome::Continue),
+        }
+    }
+
+    fn session_lifetime(&self, _attempt: &LoginAttempt, outcome: CheckOutcome) -> Option<SessionLifetime> {
+        // Elevated risk satisfied by the second factor still ends sooner
+        (outcome != CheckOutcome::Continue).then_some(SessionLifetime::Shortened)
+    }
+}
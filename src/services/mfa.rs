@@ -1,33 +1,102 @@
+use image::{ImageOutputFormat, Luma};
+use qrcode::{render::svg, QrCode};
 use rand::{distributions::Alphanumeric, Rng};
 use totp_rs::{Algorithm, TOTP};
 
-pub struct MfaService {}
+use crate::config::{TotpAlgorithm, TotpConfig};
+use crate::errors::AuthError;
+
+/// Image formats the provisioning QR code can be rendered in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QrFormat {
+    Png,
+    Svg,
+}
+
+impl QrFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            QrFormat::Png => "image/png",
+            QrFormat::Svg => "image/svg+xml",
+        }
+    }
+}
+
+pub struct MfaService {
+    config: TotpConfig,
+}
 
 impl MfaService {
-    pub fn new() -> Self {
-        MfaService {}
+    pub fn new(config: TotpConfig) -> Self {
+        MfaService { config }
     }
 
-    pub fn generate_totp_secret(&self, username: &str) -> (String, String) {
+    fn totp(&self, secret: Vec<u8>, account_name: &str) -> Option<TOTP> {
+        let algorithm = match self.config.algorithm {
+            TotpAlgorithm::SHA1 => Algorithm::SHA1,
+            TotpAlgorithm::SHA256 => Algorithm::SHA256,
+            TotpAlgorithm::SHA512 => Algorithm::SHA512,
+        };
+
+        TOTP::new(
+            algorithm,
+            self.config.digits,
+            self.config.skew,
+            self.config.period,
+            secret,
+            Some(self.config.issuer.clone()),
+            account_name.to_string(),
+        )
+        .ok()
+    }
+
+    pub fn generate_totp_secret(&self, username: &str) -> Result<(String, String), AuthError> {
         // Generate a random secret
         let secret = self.generate_random_string(32);
         
         // Create TOTP
-        let totp = TOTP::new(
-            Algorithm::SHA1,
-            6,
-            1,
-            30,
-            secret.as_bytes().to_vec(),
-            Some("Better Auth".to_string()),
-            username.to_string(),
-        )
-        .unwrap();
+        let totp = self
+            .totp(secret.as_bytes().to_vec(), username)
+            .ok_or_else(|| AuthError::InternalServerError("Invalid TOTP configuration".into()))?;
 
-        // Generate QR code URL
+        // Generate QR code URL; it carries the algorithm, digits, and period
+        // so authenticator apps generate matching codes
         let qr_code_url = totp.get_url();
 
-        (base32::encode(base32::Alphabet::RFC4648 { padding: true }, secret.as_bytes()), qr_code_url)
+        Ok((base32::encode(base32::Alphabet::RFC4648 { padding: true }, secret.as_bytes()), qr_code_url))
+    }
+
+    /// Rebuild the `otpauth://` URL for a stored (base32) secret
+    pub fn provisioning_url(&self, secret: &str, username: &str) -> Option<String> {
+        let secret_bytes = base32::decode(base32::Alphabet::RFC4648 { padding: true }, secret)?;
+        self.totp(secret_bytes, username).map(|totp| totp.get_url())
+    }
+
+    /// Render a provisioning URL as a QR code image
+    pub fn render_qr_code(&self, url: &str, format: QrFormat) -> Result<Vec<u8>, AuthError> {
+        let code = QrCode::new(url.as_bytes()).map_err(|e| {
+            AuthError::InternalServerError(format!("Failed to encode QR code: {}", e))
+        })?;
+
+        match format {
+            QrFormat::Png => {
+                let image = code.render::<Luma<u8>>().min_dimensions(256, 256).build();
+                let mut png = std::io::Cursor::new(Vec::new());
+                image
+                    .write_to(&mut png, ImageOutputFormat::Png)
+                    .map_err(|e| {
+                        AuthError::InternalServerError(format!("Failed to render QR code: {}", e))
+                    })?;
+                Ok(png.into_inner())
+            }
+            QrFormat::Svg => {
+                let image = code
+                    .render::<svg::Color>()
+                    .min_dimensions(256, 256)
+                    .build();
+                Ok(image.into_bytes())
+            }
+        }
     }
 
     pub fn verify_totp(&self, secret: &str, code: &str) -> bool {
@@ -38,23 +107,21 @@ impl MfaService {
         };
 
         // Create TOTP
-        let totp = match TOTP::new(
-            Algorithm::SHA1,
-            6,
-            1,
-            30,
-            secret_bytes,
-            None,
-            "".to_string(),
-        ) {
-            Ok(totp) => totp,
-            Err(_) => return false,
+        let totp = match self.totp(secret_bytes, "") {
+            Some(totp) => totp,
+            None => return false,
         };
 
-        // Check if the code is valid
+        // Accepts codes from `skew` periods either side of now to allow for clock drift
         totp.check_current(code).unwrap_or(false)
     }
 
+    /// The code an authenticator app would show right now for a stored (base32) secret
+    pub fn current_code(&self, secret: &str) -> Option<String> {
+        let secret_bytes = base32::decode(base32::Alphabet::RFC4648 { padding: true }, secret)?;
+        self.totp(secret_bytes, "")?.generate_current().ok()
+    }
+
     pub fn generate_recovery_code(&self) -> String {
         // Generate a random alphanumeric string
         let code = self.generate_random_string(16);
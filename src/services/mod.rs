@@ -1,4 +1,34 @@
+pub mod account_risk;
+pub mod action_tokens;
 pub mod auth;
+pub mod brute_force;
+pub mod bulk_jobs;
+pub mod canary;
+pub mod domain_verification;
 pub mod email;
+pub mod email_code;
+pub mod email_throttle;
+pub mod event_export;
+pub mod feature_flags;
+pub mod fido_metadata;
+pub mod ip_reputation;
+pub mod login_approval;
+pub mod login_checks;
 pub mod mfa;
+pub mod outbox;
 pub mod passwordless;
+pub mod provisioning;
+pub mod quotas;
+pub mod security_events;
+pub mod seed;
+pub mod session_activity;
+pub mod session_purge;
+pub mod shutdown;
+pub mod speech;
+pub mod sso;
+pub mod storage;
+pub mod system_checks;
+pub mod tarpit;
+pub mod timing;
+pub mod token_revocation;
+pub mod user_archive;
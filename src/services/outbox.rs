@@ -0,0 +1,224 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::Utc;
+use futures::future::BoxFuture;
+
+use crate::config::OutboxConfig;
+use crate::db::DatabaseConnection;
+use crate::errors::AuthError;
+use crate::models::{EventEnvelope, OutboxEvent};
+use crate::services::security_events::sign;
+
+// How long a claimed event is hidden from other relays while it's published
+const CLAIM_LEASE_SECS: i64 = 300;
+
+// Longest wait between attempts at a failing event
+const MAX_BACKOFF_SECS: i64 = 3600;
+
+/// Where outbox events go: a webhook, a message bus, ...
+pub trait EventPublisher: Send + Sync {
+    fn publish<'a>(&'a self, event: &'a OutboxEvent) -> BoxFuture<'a, Result<(), String>>;
+}
+
+/// POSTs each event to `OUTBOX_WEBHOOK_URL`
+pub struct WebhookPublisher {
+    client: reqwest::Client,
+    url: String,
+    secret: Option<String>,
+}
+
+impl WebhookPublisher {
+    pub fn new(config: &OutboxConfig) -> Option<Self> {
+        let url = config.webhook_url.clone()?;
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(config.timeout))
+            .build()
+            .expect("Failed to build outbox webhook client");
+
+        Some(WebhookPublisher {
+            client,
+            url,
+            secret: config.webhook_secret.clone(),
+        })
+    }
+}
+
+impl EventPublisher for WebhookPublisher {
+    fn publish<'a>(&'a self, event: &'a OutboxEvent) -> BoxFuture<'a, Result<(), String>> {
+        Box::pin(async move {
+            let body = serde_json::to_vec(&EventEnvelope::from(event)).map_err(|e| e.to_string())?;
+
+            let mut request = self
+                .client
+                .post(&self.url)
+                .header("Content-Type", "application/json")
+                .header("X-Event-Id", event.id.to_string())
+                .header("X-Event-Type", event.event_type.as_str());
+            if let Some(secret) = &self.secret {
+                request = request.header("X-Signature", format!("sha256={}", sign(secret, &body)));
+            }
+
+            let response = request.body(body).send().await.map_err(|e| e.to_string())?;
+            if !response.status().is_success() {
+                return Err(format!("Webhook responded with status {}", response.status()));
+            }
+
+            Ok(())
+        })
+    }
+}
+
+// Publishes events written to `events_outbox` alongside the changes they
+// describe. An event is marked delivered only once the publisher accepted it,
+// so delivery is at least once: subscribers should drop repeats by event id.
+pub struct OutboxRelay {
+    db: Arc<DatabaseConnection>,
+    publisher: Arc<dyn EventPublisher>,
+    config: OutboxConfig,
+}
+
+impl OutboxRelay {
+    pub fn new(db: Arc<DatabaseConnection>, publisher: Arc<dyn EventPublisher>, config: OutboxConfig) -> Self {
+        OutboxRelay { db, publisher, config }
+    }
+
+    /// A relay for the configured webhook, or `None` when there's nowhere to publish
+    pub fn from_config(db: Arc<DatabaseConnection>, config: &OutboxConfig) -> Option<Self> {
+        let publisher = WebhookPublisher::new(config)?;
+        Some(OutboxRelay::new(db, Arc::new(publisher), config.clone()))
+    }
+
+    /// Publish due events until the process exits; spawn at startup
+    pub async fn run(self) {
+        let poll_interval = Duration::from_secs(self.config.poll_interval);
+
+        loop {
+            match self.relay_batch().await {
+                // A full batch means more are probably waiting
+                Ok(published) if published as i64 >= self.config.batch_size => continue,
+                Ok(_) => {}
+                Err(e) => log::error!("Outbox relay failed: {}", e),
+            }
+            tokio::time::sleep(poll_interval).await;
+        }
+    }
+
+    /// Publish everything that's due, batch by batch, before the process
+    /// exits. Events that fail wait for their retry on the next start.
+    pub async fn flush(&self) -> Result<usize, AuthError> {
+        let mut claimed = 0;
+        loop {
+            let batch = self.relay_batch().await?;
+            claimed += batch;
+            if (batch as i64) < self.config.batch_size {
+                return Ok(claimed);
+            }
+        }
+    }
+
+    /// Publish one batch of due events from each region, returning how many
+    /// were claimed
+    pub async fn relay_batch(&self) -> Result<usize, AuthError> {
+        let mut claimed = 0;
+        for region in self.db.regions() {
+            claimed += DatabaseConnection::in_region(region, self.relay_region_batch()).await?;
+        }
+        Ok(claimed)
+    }
+
+    async fn relay_region_batch(&self) -> Result<usize, AuthError> {
+        let lease_until = Utc::now() + chrono::Duration::seconds(CLAIM_LEASE_SECS);
+        let events = self
+            .db
+            .claim_outbox_events(self.config.batch_size, self.config.max_attempts, lease_until)
+            .await?;
+
+        for event in &events {
+            match self.publisher.publish(event).await {
+                Ok(()) => self.db.mark_outbox_event_delivered(event.id).await?,
+                Err(e) => {
+                    let attempts = event.attempts + 1;
+                    if attempts >= self.config.max_attempts {
+                        log::error!(
+                            "Giving up on outbox event {} ({}) after {} attempts: {}",
+                            event.id,
+                            event.event_type,
+                            attempts,
+                            e
+                        );
+                    } else {
+                        log::warn!("Failed to publish outbox event {}: {}", event.id, e);
+                    }
+
+                    let retry_at = Utc::now() + chrono::Duration::seconds(backoff_secs(attempts));
+                    self.db.record_outbox_event_failure(event.id, &e, retry_at).await?;
+                }
+            }
+        }
+
+        Ok(events.len())
+    }
+}
+
+/// Wait before the next attempt, doubling from 10 seconds up to an hour
+fn backoff_secs(attempts: i32) -> i64 {
+    let exponent = attempts.saturating_sub(1).clamp(0, 20) as u32;
+    (10i64 << exponent).min(MAX_BACKOFF_SECS)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use uuid::Uuid;
+
+    use crate::db::UnitOfWork;
+    use crate::models::{EventType, NewOutboxEvent};
+
+    use super::*;
+
+    #[derive(Default)]
+    struct RecordingPublisher {
+        published: Mutex<Vec<Uuid>>,
+    }
+
+    impl EventPublisher for RecordingPublisher {
+        fn publish<'a>(&'a self, event: &'a OutboxEvent) -> BoxFuture<'a, Result<(), String>> {
+            self.published.lock().unwrap().push(event.id);
+            Box::pin(async { Ok(()) })
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_flush_publishes_every_due_event() {
+        let db = Arc::new(DatabaseConnection::new_memory());
+        for _ in 0..5 {
+            let event = NewOutboxEvent::new(EventType::LoggedIn, Uuid::new_v4(), serde_json::json!({}));
+            db.commit(UnitOfWork::new().event(event)).await.unwrap();
+        }
+        let publisher = Arc::new(RecordingPublisher::default());
+        let config = OutboxConfig {
+            webhook_url: None,
+            webhook_secret: None,
+            timeout: 5,
+            poll_interval: 60,
+            batch_size: 2,
+            max_attempts: 3,
+        };
+        let relay = OutboxRelay::new(db.clone(), publisher.clone(), config);
+
+        assert_eq!(relay.flush().await.unwrap(), 5);
+        assert_eq!(publisher.published.lock().unwrap().len(), 5);
+        assert_eq!(relay.flush().await.unwrap(), 0);
+    }
+
+    #[test]
+    fn test_backoff_secs() {
+        assert_eq!(backoff_secs(1), 10);
+        assert_eq!(backoff_secs(2), 20);
+        assert_eq!(backoff_secs(5), 160);
+        assert_eq!(backoff_secs(12), MAX_BACKOFF_SECS);
+        assert_eq!(backoff_secs(i32::MAX), MAX_BACKOFF_SECS);
+    }
+}
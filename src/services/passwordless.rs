@@ -2,13 +2,20 @@ use uuid::Uuid;
 use chrono::Utc;
 
 use crate::models::{
-    LoginResponse, PasswordlessLoginCompleteRequest, PasswordlessLoginStartRequest,
-    PasswordlessLoginStartResponse, PasswordlessRegisterCompleteRequest,
-    PasswordlessRegisterStartRequest, PasswordlessRegisterStartResponse, RegisterResponse,
+    LoginResponse, LogoutResponse, MfaMethod, MfaMethodOrderRequest, MfaMethodsResponse,
+    MfaVerifyResponse, NewPasskey, NewSession, PasskeyEnrollStartRequest, PasskeySummary,
+    PasswordlessLoginCompleteRequest,
+    PasswordlessLoginStartRequest, PasswordlessLoginStartResponse,
+    PasswordlessRegisterCompleteRequest, PasswordlessRegisterStartRequest,
+    PasswordlessRegisterStartResponse, RegisterResponse,
 };
 use crate::errors::AuthError;
-use crate::webauthn_simplified::{WebAuthnContext, WebAuthnCredential};
-use crate::services::auth::AuthService;
+use crate::webauthn_simplified::{user_verified, WebAuthnContext, WebAuthnCredential};
+use crate::services::auth::{AuthService, LoginClient};
+use crate::services::login_approval::PendingLogin;
+use crate::services::login_checks::{CheckOutcome, LoginVerdict};
+use crate::utils::network::NetworkFingerprint;
+use crate::utils::jwt::{AMR_HWK, AMR_MFA, AMR_PASSWORD};
 
 impl AuthService {
     /// Start passwordless registration process
@@ -97,6 +104,7 @@ impl AuthService {
 
         // Complete WebAuthn registration
         let credential = webauthn_context.complete_registration(request)?;
+        self.fido_metadata.check_registration(credential.aaguid)?;
 
         // Create user with passwordless credential
         let user = self.create_passwordless_user(
@@ -173,6 +181,8 @@ impl AuthService {
         request: PasswordlessLoginCompleteRequest,
         ip: Option<String>,
         user_agent: Option<String>,
+        network: NetworkFingerprint,
+        locale: &str,
     ) -> Result<LoginResponse, AuthError> {
         // Get authentication data from cache
         let cache_key = format!("passwordless_login:{}", &request.authentication_id);
@@ -193,9 +203,18 @@ impl AuthService {
             .map_err(|_| AuthError::ServerError("Invalid user ID".to_string()))?;
 
         // Get user and credentials
-        let user = self.get_user_by_id(user_id).await?;
+        let user = self.db.find_user_by_id(user_id).await?;
         let user_credentials = self.get_user_webauthn_credentials(user_id).await?;
 
+        // A passkey that checked the user's PIN or biometric is two factors on its own
+        let verified = request
+            .credential
+            .response
+            .authenticator_data
+            .as_deref()
+            .map_or(false, user_verified);
+        let amr: &[&str] = if verified { &[AMR_HWK, AMR_MFA] } else { &[AMR_HWK] };
+
         // Create WebAuthn context
         let webauthn_context = WebAuthnContext::new(&self.config.domain, &self.config.origin)?;
 
@@ -208,32 +227,313 @@ impl AuthService {
         // Update the credential's counter and last used timestamp
         self.update_webauthn_credential(user_id, updated_credential).await?;
 
-        // Generate login tokens
-        let (access_token, refresh_token) = self.generate_tokens(user_id, None).await?;
-
-        // Create a new session
-        let session_id = self.create_session(
-            user_id, 
-            &access_token, 
-            ip, 
-            user_agent,
-            true, // Passwordless authentication is considered verified
-        ).await?;
-
         // Delete cache entry
         self.cache.del(&cache_key).await?;
 
+        // The passkey stands in for the password; freezes, canaries and the
+        // rest of the pipeline still apply
+        let client = LoginClient {
+            ip: &ip,
+            user_agent: &user_agent,
+            network: &network,
+            device_token: None,
+        };
+        let LoginVerdict { outcome, lifetime } = self
+            .run_verified_login_checks(&user, "passkey", client, locale)
+            .await?;
+
+        if outcome == CheckOutcome::RequireApproval {
+            let login = PendingLogin {
+                user_id,
+                ip,
+                user_agent,
+                amr: amr.iter().map(|m| m.to_string()).collect(),
+                lifetime,
+                dpop_jkt: None,
+                network,
+            };
+            return self.start_login_approval(user, login, locale).await;
+        }
+
+        // A verified passkey is the second factor already
+        if outcome == CheckOutcome::RequireMfa && !verified {
+            return self.mfa_pending_response(user);
+        }
+
+        // Create a new session, remembering how it was signed in
+        let refresh_token = Uuid::new_v4().to_string();
+        let expires_at = Utc::now() + self.refresh_token_lifetime(false, lifetime);
+        let mut session = NewSession::new(user_id, refresh_token.clone(), user_agent, ip, expires_at);
+        session.amr = amr.iter().map(|m| m.to_string()).collect();
+        session.lifetime = lifetime.as_str().to_string();
+        let access_token = self.create_session_access_token(&user, amr, &session)?;
+        self.db.create_session(session).await?;
+
         // Return login response
         Ok(LoginResponse {
-            user: user.into(),
             access_token,
             refresh_token,
             token_type: "Bearer".to_string(),
-            expires_in: self.config.access_token_expiry_seconds,
-            session_id,
+            expires_in: self.access_token_expiry(lifetime),
+            user: user.into(),
+            mfa_required: false,
+            passkey_prompt: None,
+            approval_id: None,
+            password_change_required: false,
+            mfa_enrollment_required: false,
+            policy_acceptance_required: None,
+        })
+    }
+
+    /// Start adding a passkey to an existing account
+    pub async fn passkey_enroll_start(
+        &self,
+        user_id: Uuid,
+        request: PasskeyEnrollStartRequest,
+    ) -> Result<PasswordlessRegisterStartResponse, AuthError> {
+        let user = self.get_user_by_id(user_id).await?;
+
+        // Exclude authenticators the user has already registered
+        let user_credentials = self.get_user_webauthn_credentials(user_id).await?;
+
+        // Create WebAuthn context
+        let webauthn_context = WebAuthnContext::new(&self.config.domain, &self.config.origin)?;
+
+        // Start WebAuthn registration
+        let webauthn_response = webauthn_context.start_registration(
+            &user_id,
+            &user.username,
+            &user_credentials,
+        )?;
+
+        // Store enrollment data in server-side cache for later verification
+        let cache_key = format!("passkey_enroll:{}", &webauthn_response.registration_id);
+        let cache_value = serde_json::json!({
+            "user_id": user_id.to_string(),
+            "device_name": request.device_name,
+            "registration_id": webauthn_response.registration_id,
+            "challenge": webauthn_response.options.challenge,
+            "timestamp": Utc::now().to_rfc3339(),
+        });
+
+        // Cache the enrollment data with expiration (15 minutes)
+        let cache_ttl = self.config.cache_ttl_seconds.unwrap_or(900);
+        self.cache.set_ex(&cache_key, &cache_value.to_string(), cache_ttl).await?;
+
+        Ok(PasswordlessRegisterStartResponse {
+            registration_id: webauthn_response.registration_id,
+            options: webauthn_response.options,
+        })
+    }
+
+    /// Finish adding a passkey; the user won't be prompted to enroll again
+    pub async fn passkey_enroll_complete(
+        &self,
+        user_id: Uuid,
+        request: PasswordlessRegisterCompleteRequest,
+    ) -> Result<LogoutResponse, AuthError> {
+        // Get enrollment data from cache
+        let cache_key = format!("passkey_enroll:{}", &request.registration_id);
+        let cached_data = match self.cache.get(&cache_key).await? {
+            Some(data) => data,
+            None => return Err(AuthError::TokenExpired),
+        };
+
+        let cached_json: serde_json::Value = serde_json::from_str(&cached_data)
+            .map_err(|_| AuthError::InternalServerError("Failed to parse cached data".to_string()))?;
+
+        // The enrollment must be finished by the user who started it
+        let cached_user_id = Uuid::parse_str(cached_json["user_id"].as_str().unwrap_or_default())
+            .map_err(|_| AuthError::InternalServerError("Invalid user ID".to_string()))?;
+        if cached_user_id != user_id {
+            return Err(AuthError::InvalidToken);
+        }
+        let device_name = cached_json["device_name"].as_str().map(|s| s.to_string());
+
+        // Create WebAuthn context
+        let webauthn_context = WebAuthnContext::new(&self.config.domain, &self.config.origin)?;
+
+        // Complete WebAuthn registration
+        let credential = webauthn_context.complete_registration(request)?;
+        self.fido_metadata.check_registration(credential.aaguid)?;
+
+        self.db
+            .create_passkey(NewPasskey {
+                user_id,
+                credential_id: credential.credential_id,
+                public_key: credential.public_key,
+                counter: credential.counter as i32,
+                created_at: credential.created_at,
+                last_used_at: credential.last_used_at,
+                device_name,
+                aaguid: credential.aaguid,
+            })
+            .await?;
+
+        self.db.dismiss_passkey_prompt(user_id).await?;
+
+        // Delete cache entry
+        self.cache.del(&cache_key).await?;
+
+        Ok(LogoutResponse {
+            message: "Passkey added successfully".into(),
+        })
+    }
+
+    /// Start finishing a password login with a passkey instead of a TOTP
+    /// code; the caller holds an `mfa_pending` token from `login`
+    pub async fn mfa_passkey_start(&self, user_id: Uuid) -> Result<PasswordlessLoginStartResponse, AuthError> {
+        let user = self.db.find_user_by_id(user_id).await?;
+        if !user.mfa_enabled {
+            return Err(AuthError::ValidationError("MFA is not enabled".into()));
+        }
+
+        let user_credentials = self.get_user_webauthn_credentials(user_id).await?;
+        if user_credentials.is_empty() {
+            return Err(AuthError::ValidationError("No passkeys are registered".into()));
+        }
+
+        // Create WebAuthn context
+        let webauthn_context = WebAuthnContext::new(&self.config.domain, &self.config.origin)?;
+
+        // Start WebAuthn authentication
+        let webauthn_response = webauthn_context.start_authentication(&user_credentials)?;
+
+        // Store the challenge in server-side cache for later verification
+        let cache_key = format!("mfa_passkey:{}", &webauthn_response.authentication_id);
+        let cache_value = serde_json::json!({
+            "user_id": user_id.to_string(),
+            "authentication_id": webauthn_response.authentication_id,
+            "challenge": webauthn_response.options.challenge,
+            "timestamp": Utc::now().to_rfc3339(),
+        });
+
+        // No longer than the `mfa_pending` token it continues
+        let cache_ttl = self.config.jwt.scoped_token_expiry;
+        self.cache.set_ex(&cache_key, &cache_value.to_string(), cache_ttl).await?;
+
+        Ok(PasswordlessLoginStartResponse {
+            authentication_id: webauthn_response.authentication_id,
+            options: webauthn_response.options,
         })
     }
 
+    /// Finish a password login with a passkey assertion
+    pub async fn mfa_passkey_complete(
+        &self,
+        user_id: Uuid,
+        request: PasswordlessLoginCompleteRequest,
+        ip: Option<String>,
+        user_agent: Option<String>,
+    ) -> Result<MfaVerifyResponse, AuthError> {
+        let cache_key = format!("mfa_passkey:{}", &request.authentication_id);
+        let cached_data = match self.cache.get(&cache_key).await? {
+            Some(data) => data,
+            None => return Err(AuthError::TokenExpired),
+        };
+
+        let cached_json: serde_json::Value = serde_json::from_str(&cached_data)
+            .map_err(|_| AuthError::InternalServerError("Failed to parse cached data".to_string()))?;
+
+        // The challenge must be answered for the login that asked for it
+        let cached_user_id = Uuid::parse_str(cached_json["user_id"].as_str().unwrap_or_default())
+            .map_err(|_| AuthError::InternalServerError("Invalid user ID".to_string()))?;
+        if cached_user_id != user_id {
+            return Err(AuthError::InvalidToken);
+        }
+
+        let user = self.db.find_user_by_id(user_id).await?;
+        let user_credentials = self.get_user_webauthn_credentials(user_id).await?;
+
+        // Create WebAuthn context
+        let webauthn_context = WebAuthnContext::new(&self.config.domain, &self.config.origin)?;
+
+        // A failed assertion fails like a wrong TOTP code
+        let updated_credential = webauthn_context
+            .complete_authentication(request, &user_credentials)
+            .map_err(|_| AuthError::InvalidMfaCode)?;
+
+        // Update the credential's counter and last used timestamp
+        self.update_webauthn_credential(user_id, updated_credential).await?;
+
+        // Each challenge is answered once
+        self.cache.del(&cache_key).await?;
+
+        self.complete_mfa_login(user, &[AMR_PASSWORD, AMR_HWK, AMR_MFA], ip, user_agent).await
+    }
+
+    /// The second factors the user can finish a password login with, in the
+    /// order they want them offered
+    pub async fn mfa_methods(&self, user_id: Uuid) -> Result<MfaMethodsResponse, AuthError> {
+        let enrolled = self.enrolled_mfa_methods(user_id).await?;
+        let preferred = self.db.find_mfa_method_order(user_id).await?;
+
+        Ok(MfaMethodsResponse {
+            methods: MfaMethod::in_order(&enrolled, &preferred),
+        })
+    }
+
+    /// Choose which second factor is offered first after a password login
+    pub async fn set_mfa_method_order(
+        &self,
+        user_id: Uuid,
+        request: MfaMethodOrderRequest,
+    ) -> Result<MfaMethodsResponse, AuthError> {
+        let enrolled = self.enrolled_mfa_methods(user_id).await?;
+        if enrolled.is_empty() {
+            return Err(AuthError::ValidationError("MFA is not enabled".into()));
+        }
+
+        for (i, method) in request.methods.iter().enumerate() {
+            if request.methods[..i].contains(method) {
+                return Err(AuthError::ValidationError(format!("{} is listed twice", method.as_str())));
+            }
+            if !enrolled.contains(method) {
+                return Err(AuthError::ValidationError(format!("{} is not set up", method.as_str())));
+            }
+        }
+
+        let methods = request.methods.iter().map(|m| m.as_str().to_string()).collect();
+        self.db.save_mfa_method_order(user_id, methods).await?;
+
+        self.mfa_methods(user_id).await
+    }
+
+    /// The user's passkeys, for account management pages, named after their
+    /// authenticator model when the FIDO metadata lists it
+    pub async fn list_passkeys(&self, user_id: Uuid) -> Result<Vec<PasskeySummary>, AuthError> {
+        let passkeys = self
+            .db
+            .find_passkeys_by_user_id(user_id)
+            .await?
+            .into_iter()
+            .map(|passkey| PasskeySummary {
+                authenticator: self.fido_metadata.name_for(passkey.aaguid),
+                credential_id: passkey.credential_id,
+                device_name: passkey.device_name,
+                created_at: passkey.created_at,
+                last_used_at: passkey.last_used_at,
+            })
+            .collect();
+
+        Ok(passkeys)
+    }
+
+    // Passkeys only count as a second factor once MFA has been turned on
+    // with a TOTP authenticator, which also issues the recovery codes
+    async fn enrolled_mfa_methods(&self, user_id: Uuid) -> Result<Vec<MfaMethod>, AuthError> {
+        let user = self.db.find_user_by_id(user_id).await?;
+        if !user.mfa_enabled {
+            return Ok(Vec::new());
+        }
+
+        let mut methods = vec![MfaMethod::Totp];
+        if !self.db.find_passkeys_by_user_id(user_id).await?.is_empty() {
+            methods.push(MfaMethod::Passkey);
+        }
+        Ok(methods)
+    }
+
     /// Create a user with passwordless credentials
     async fn create_passwordless_user(
         &self,
@@ -274,8 +574,8 @@ impl AuthService {
             sqlx::query!(
                 r#"
                 INSERT INTO webauthn_credentials
-                (user_id, credential_id, public_key, counter, created_at, last_used_at, device_name)
-                VALUES ($1, $2, $3, $4, $5, $6, $7)
+                (user_id, credential_id, public_key, counter, created_at, last_used_at, device_name, aaguid)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
                 "#,
                 user_id,
                 credential.credential_id,
@@ -284,6 +584,7 @@ impl AuthService {
                 credential.created_at,
                 credential.last_used_at,
                 None::<String>, // Device name not supported in this current implementation
+                credential.aaguid,
             )
             .execute(&mut *tx)
             .await?;
@@ -310,7 +611,8 @@ impl AuthService {
                 public_key,
                 counter,
                 created_at,
-                last_used_at
+                last_used_at,
+                aaguid
             FROM
                 webauthn_credentials
             WHERE
@@ -0,0 +1,167 @@
+use uuid::Uuid;
+
+use crate::errors::AuthError;
+use crate::models::{AttributeCondition, OrganizationRole, ProvisioningRules};
+use crate::services::sso::FederatedIdentity;
+
+/// What a federated sign-in gets under its connection's provisioning rules
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProvisioningPlan {
+    pub username: Option<String>,
+    pub display_name: Option<String>,
+    pub locale: Option<String>,
+    pub timezone: Option<String>,
+    /// Role in the connection's organization
+    pub role: OrganizationRole,
+    /// Further organizations to join, each at most once
+    pub organizations: Vec<(Uuid, OrganizationRole)>,
+}
+
+/// Applies `rules` to an identity. `group_role` is the role the connection's
+/// group mappings give; rules may raise it but never lower it.
+pub fn evaluate(
+    rules: &ProvisioningRules,
+    identity: &FederatedIdentity,
+    group_role: OrganizationRole,
+) -> Result<ProvisioningPlan, AuthError> {
+    if let Some(condition) = rules.blocked.iter().find(|c| matches(c, identity)) {
+        log::info!(
+            "Refused SSO sign-in for {}: blocked attribute {}",
+            identity.subject,
+            condition.attribute
+        );
+        return Err(AuthError::SsoError(
+            "Your account is not allowed to sign in to this organization".into(),
+        ));
+    }
+
+    let mapping = &rules.attribute_mapping;
+    let mut plan = ProvisioningPlan {
+        username: first_value(identity, mapping.username.as_deref()),
+        display_name: first_value(identity, mapping.display_name.as_deref()).or_else(|| identity.name.clone()),
+        locale: first_value(identity, mapping.locale.as_deref()),
+        timezone: first_value(identity, mapping.timezone.as_deref()),
+        role: group_role,
+        organizations: Vec::new(),
+    };
+
+    for rule in &rules.rules {
+        if !rule.when.iter().all(|c| matches(c, identity)) {
+            continue;
+        }
+
+        if let Some(role) = rule.role {
+            plan.role = plan.role.max(role);
+        }
+        for assignment in &rule.organizations {
+            match plan.organizations.iter_mut().find(|(id, _)| *id == assignment.organization_id) {
+                Some((_, role)) => *role = (*role).max(assignment.role),
+                None => plan.organizations.push((assignment.organization_id, assignment.role)),
+            }
+        }
+    }
+
+    Ok(plan)
+}
+
+fn matches(condition: &AttributeCondition, identity: &FederatedIdentity) -> bool {
+    match identity.attributes.get(&condition.attribute) {
+        Some(values) if condition.values.is_empty() => !values.is_empty(),
+        Some(values) => values
+            .iter()
+            .any(|value| condition.values.iter().any(|v| v.eq_ignore_ascii_case(value))),
+        None => false,
+    }
+}
+
+fn first_value(identity: &FederatedIdentity, attribute: Option<&str>) -> Option<String> {
+    identity
+        .attributes
+        .get(attribute?)
+        .and_then(|values| values.first())
+        .map(|value| value.trim().to_string())
+        .filter(|value| !value.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{AttributeMapping, OrganizationAssignment, ProvisioningRule};
+    use std::collections::HashMap;
+
+    fn identity(attributes: &[(&str, &[&str])]) -> FederatedIdentity {
+        FederatedIdentity {
+            subject: "sub-1".to_string(),
+            email: "alice@example.com".to_string(),
+            name: Some("Alice".to_string()),
+            groups: Vec::new(),
+            attributes: attributes
+                .iter()
+                .map(|(name, values)| (name.to_string(), values.iter().map(|v| v.to_string()).collect()))
+                .collect::<HashMap<_, _>>(),
+        }
+    }
+
+    fn condition(attribute: &str, values: &[&str]) -> AttributeCondition {
+        AttributeCondition {
+            attribute: attribute.to_string(),
+            values: values.iter().map(|v| v.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn test_blocked_attribute_refuses_sign_in() {
+        let rules = ProvisioningRules {
+            blocked: vec![condition("employee_type", &["contractor"])],
+            ..Default::default()
+        };
+
+        let contractor = identity(&[("employee_type", &["Contractor"])]);
+        assert!(evaluate(&rules, &contractor, OrganizationRole::Member).is_err());
+
+        let employee = identity(&[("employee_type", &["employee"])]);
+        assert!(evaluate(&rules, &employee, OrganizationRole::Member).is_ok());
+    }
+
+    #[test]
+    fn test_rules_map_attributes_and_raise_roles() {
+        let engineering = Uuid::new_v4();
+        let rules = ProvisioningRules {
+            attribute_mapping: AttributeMapping {
+                username: Some("preferred_username".to_string()),
+                ..Default::default()
+            },
+            rules: vec![
+                ProvisioningRule {
+                    when: vec![condition("department", &["engineering"])],
+                    role: Some(OrganizationRole::Admin),
+                    organizations: vec![OrganizationAssignment {
+                        organization_id: engineering,
+                        role: OrganizationRole::Member,
+                    }],
+                },
+                ProvisioningRule {
+                    when: vec![condition("department", &["sales"])],
+                    role: None,
+                    organizations: vec![OrganizationAssignment {
+                        organization_id: Uuid::new_v4(),
+                        role: OrganizationRole::Admin,
+                    }],
+                },
+            ],
+            ..Default::default()
+        };
+
+        let plan = evaluate(
+            &rules,
+            &identity(&[("preferred_username", &["alice"]), ("department", &["engineering"])]),
+            OrganizationRole::Member,
+        )
+        .unwrap();
+
+        assert_eq!(plan.username.as_deref(), Some("alice"));
+        assert_eq!(plan.display_name.as_deref(), Some("Alice"));
+        assert_eq!(plan.role, OrganizationRole::Admin);
+        assert_eq!(plan.organizations, vec![(engineering, OrganizationRole::Member)]);
+    }
+}
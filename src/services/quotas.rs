@@ -0,0 +1,112 @@
+use chrono::{DateTime, Datelike, Duration, NaiveDate, TimeZone, Utc};
+
+use crate::config::ApiKeyConfig;
+use crate::errors::AuthError;
+use crate::models::ApiKey;
+
+/// Where a key stands against its tightest quota, for the `X-Quota-*` headers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QuotaStatus {
+    pub limit: u64,
+    pub remaining: u64,
+    pub reset_at: i64, // Unix timestamp when the quota's window starts over
+}
+
+/// Daily and monthly quotas for a key: its own overrides, else the defaults.
+/// `None` means unlimited.
+pub fn effective_quotas(key: &ApiKey, config: &ApiKeyConfig) -> (Option<u64>, Option<u64>) {
+    (
+        key.daily_quota.map(|q| q.max(0) as u64).or(config.daily_quota),
+        key.monthly_quota.map(|q| q.max(0) as u64).or(config.monthly_quota),
+    )
+}
+
+/// First day of the month `day` falls in
+pub fn month_start(day: NaiveDate) -> NaiveDate {
+    day.with_day(1).unwrap_or(day)
+}
+
+/// Check request counts that already include the current request. Returns the
+/// status of whichever quota has fewer requests left, or `QuotaExceeded`.
+pub fn check(
+    quotas: (Option<u64>, Option<u64>),
+    daily_count: i64,
+    monthly_count: i64,
+    now: DateTime<Utc>,
+) -> Result<Option<QuotaStatus>, AuthError> {
+    let (daily_quota, monthly_quota) = quotas;
+    let today = now.date_naive();
+
+    let windows = [
+        (daily_quota, daily_count, next_day(today)),
+        (monthly_quota, monthly_count, next_month(today)),
+    ];
+
+    let mut tightest: Option<QuotaStatus> = None;
+    for (limit, count, reset_at) in windows {
+        let Some(limit) = limit else { continue };
+        let count = count.max(0) as u64;
+        if count > limit {
+            return Err(AuthError::QuotaExceeded { limit, reset_at });
+        }
+
+        let status = QuotaStatus { limit, remaining: limit - count, reset_at };
+        if tightest.map_or(true, |t| status.remaining < t.remaining) {
+            tightest = Some(status);
+        }
+    }
+
+    Ok(tightest)
+}
+
+fn next_day(day: NaiveDate) -> i64 {
+    midnight(day + Duration::days(1))
+}
+
+fn next_month(day: NaiveDate) -> i64 {
+    let (year, month) = if day.month() == 12 { (day.year() + 1, 1) } else { (day.year(), day.month() + 1) };
+    midnight(NaiveDate::from_ymd_opt(year, month, 1).unwrap_or(day))
+}
+
+fn midnight(day: NaiveDate) -> i64 {
+    Utc.from_utc_datetime(&day.and_hms_opt(0, 0, 0).unwrap_or_default()).timestamp()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(y: i32, m: u32, d: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(y, m, d, 15, 30, 0).unwrap()
+    }
+
+    #[test]
+    fn test_check_reports_tightest_quota() {
+        let now = at(2024, 12, 31);
+
+        let status = check((Some(100), Some(1000)), 10, 950, now).unwrap().unwrap();
+        assert_eq!(status.limit, 1000);
+        assert_eq!(status.remaining, 50);
+        assert_eq!(status.reset_at, Utc.with_ymd_and_hms(2025, 1, 1, 0, 0, 0).unwrap().timestamp());
+
+        let status = check((Some(100), None), 100, 100, now).unwrap().unwrap();
+        assert_eq!(status.remaining, 0);
+
+        assert_eq!(check((None, None), 5_000, 5_000, now).unwrap(), None);
+    }
+
+    #[test]
+    fn test_check_rejects_over_quota() {
+        let now = at(2024, 3, 10);
+
+        match check((Some(100), Some(1000)), 101, 500, now) {
+            Err(AuthError::QuotaExceeded { limit, reset_at }) => {
+                assert_eq!(limit, 100);
+                assert_eq!(reset_at, Utc.with_ymd_and_hms(2024, 3, 11, 0, 0, 0).unwrap().timestamp());
+            }
+            other => panic!("expected QuotaExceeded, got {:?}", other),
+        }
+
+        assert!(check((None, Some(1000)), 1, 1001, now).is_err());
+    }
+}
@@ -0,0 +1,116 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use sha2::Sha256;
+use uuid::Uuid;
+
+use crate::config::SecurityWebhookConfig;
+use crate::risk_scoring::{GeoLocation, RiskFactor};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SecurityEventKind {
+    LoginBlocked,
+    LoginApprovalRequested, // Would have been blocked; the owner was asked to approve it
+    ImpossibleTravel,
+    AccountRiskElevated, // An account's risk score crossed a threshold
+    CanaryTriggered,     // Someone used a planted canary account or API key
+}
+
+/// Everything a security team needs to react to a suspicious login
+#[derive(Debug, Clone, Serialize)]
+pub struct SecurityEvent {
+    pub id: Uuid,
+    pub kind: SecurityEventKind,
+    pub priority: &'static str,
+    pub user_id: Uuid,
+    pub score: u32,
+    pub factors: Vec<RiskFactor>,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    pub location: Option<GeoLocation>,
+    pub occurred_at: DateTime<Utc>,
+}
+
+// Delivers security events to `SECURITY_WEBHOOK_URL`. Delivery happens in
+// the background so a slow receiver never holds up the login that triggered it.
+pub struct SecurityWebhook {
+    client: reqwest::Client,
+    config: SecurityWebhookConfig,
+}
+
+impl SecurityWebhook {
+    pub fn new(config: SecurityWebhookConfig) -> Self {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(config.timeout))
+            .build()
+            .expect("Failed to build security webhook client");
+
+        SecurityWebhook { client, config }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.config.url.is_some()
+    }
+
+    pub fn notify(&self, event: SecurityEvent) {
+        let url = match &self.config.url {
+            Some(url) => url.clone(),
+            None => return,
+        };
+
+        let body = match serde_json::to_vec(&event) {
+            Ok(body) => body,
+            Err(e) => {
+                log::error!("Failed to serialize security event {}: {}", event.id, e);
+                return;
+            }
+        };
+
+        let mut request = self
+            .client
+            .post(url)
+            .header("Content-Type", "application/json")
+            .header("X-Event-Id", event.id.to_string())
+            .header("X-Event-Priority", event.priority);
+        if let Some(secret) = &self.config.secret {
+            request = request.header("X-Signature", format!("sha256={}", sign(secret, &body)));
+        }
+
+        tokio::spawn(async move {
+            match request.body(body).send().await {
+                Ok(response) if response.status().is_success() => {}
+                Ok(response) => log::warn!(
+                    "Security webhook rejected event {} with status {}",
+                    event.id,
+                    response.status()
+                ),
+                Err(e) => log::warn!("Failed to deliver security event {}: {}", event.id, e),
+            }
+        });
+    }
+}
+
+/// Hex-encoded HMAC-SHA256 of the payload, so receivers can check it came from us
+pub fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())
+        .expect("HMAC accepts keys of any length");
+    mac.update(body);
+    hex::encode(mac.finalize().into_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sign() {
+        // RFC 4231 test case 2
+        assert_eq!(
+            sign("Jefe", b"what do ya want for nothing?"),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+}
@@ -0,0 +1,80 @@
+use serde::Serialize;
+
+use crate::models::AccountStatus;
+
+// Demo accounts live under a reserved domain (RFC 2606), so seeded data can
+// never email a real person
+pub const DEMO_EMAIL_DOMAIN: &str = "example.com";
+
+/// What a demo account looks like once seeded
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DemoState {
+    Verified,
+    Unverified,
+    MfaEnabled,
+    Suspended,
+    Banned,
+    PasswordExpired,
+    Admin,
+}
+
+impl DemoState {
+    pub fn is_email_verified(&self) -> bool {
+        !matches!(self, DemoState::Unverified)
+    }
+
+    /// The locked status to apply after creation, if any
+    pub fn account_status(&self) -> Option<AccountStatus> {
+        match self {
+            DemoState::Suspended => Some(AccountStatus::Suspended),
+            DemoState::Banned => Some(AccountStatus::Banned),
+            _ => None,
+        }
+    }
+}
+
+/// One account per state the sign-in flows branch on
+pub const DEMO_ACCOUNTS: &[(&str, DemoState)] = &[
+    ("demo_verified", DemoState::Verified),
+    ("demo_unverified", DemoState::Unverified),
+    ("demo_mfa", DemoState::MfaEnabled),
+    ("demo_suspended", DemoState::Suspended),
+    ("demo_banned", DemoState::Banned),
+    ("demo_password_expired", DemoState::PasswordExpired),
+    ("demo_admin", DemoState::Admin),
+];
+
+pub fn demo_email(username: &str) -> String {
+    format!("{}@{}", username, DEMO_EMAIL_DOMAIN)
+}
+
+#[derive(Debug, Serialize)]
+pub struct SeededAccount {
+    pub username: String,
+    pub email: String,
+    pub state: DemoState,
+    pub created: bool,            // False when the account was already there and left alone
+    pub totp_url: Option<String>, // For MFA accounts, to add to an authenticator app
+}
+
+/// What `POST /dev/seed` reports back
+#[derive(Debug, Serialize)]
+pub struct SeedReport {
+    pub password: String, // Shared by every demo account
+    pub accounts: Vec<SeededAccount>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::validation::{validate_email, validate_username};
+
+    #[test]
+    fn test_demo_accounts_are_valid() {
+        for (username, _) in DEMO_ACCOUNTS {
+            assert!(validate_username(username).is_ok(), "{}", username);
+            assert!(validate_email(&demo_email(username)).is_ok(), "{}", username);
+        }
+    }
+}
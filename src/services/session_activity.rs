@@ -0,0 +1,90 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+use crate::config::SessionConfig;
+
+// Decides when a session's use is written to `last_seen_at`, and when it has
+// gone unused for too long. Writes are throttled per session so a busy client
+// costs one update every `SESSION_ACTIVITY_UPDATE_INTERVAL`, not one per request.
+pub struct SessionActivity {
+    update_interval: Duration,
+    inactivity_timeout: Option<chrono::Duration>,
+    recorded: Mutex<HashMap<Uuid, Instant>>,
+}
+
+impl SessionActivity {
+    pub fn new(config: &SessionConfig) -> Self {
+        SessionActivity {
+            update_interval: Duration::from_secs(config.activity_update_interval),
+            inactivity_timeout: match config.inactivity_timeout_days {
+                0 => None,
+                days => Some(chrono::Duration::days(days as i64)),
+            },
+            recorded: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Whether a request on `session_id` should be recorded now. Claims the
+    /// slot, so concurrent requests don't all write.
+    pub fn due(&self, session_id: Uuid) -> bool {
+        let mut recorded = self.recorded.lock().unwrap();
+        recorded.retain(|_, at| at.elapsed() < self.update_interval);
+
+        if recorded.contains_key(&session_id) {
+            return false;
+        }
+        recorded.insert(session_id, Instant::now());
+        true
+    }
+
+    /// Sessions last seen before this are idle, or `None` when they never are
+    pub fn idle_cutoff(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        self.inactivity_timeout.map(|timeout| now - timeout)
+    }
+
+    pub fn is_idle(&self, last_seen_at: DateTime<Utc>, now: DateTime<Utc>) -> bool {
+        self.idle_cutoff(now).map_or(false, |cutoff| last_seen_at < cutoff)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn activity(inactivity_timeout_days: u32, activity_update_interval: u64) -> SessionActivity {
+        SessionActivity::new(&SessionConfig { inactivity_timeout_days, activity_update_interval })
+    }
+
+    #[test]
+    fn test_due_once_per_interval() {
+        let activity = activity(30, 300);
+        let (first, second) = (Uuid::new_v4(), Uuid::new_v4());
+
+        assert!(activity.due(first));
+        assert!(!activity.due(first));
+        assert!(activity.due(second));
+
+        // Without an interval every request is recorded
+        let activity = self::activity(30, 0);
+        assert!(activity.due(first));
+        assert!(activity.due(first));
+    }
+
+    #[test]
+    fn test_is_idle() {
+        let now = Utc::now();
+
+        let activity = activity(30, 300);
+        assert!(!activity.is_idle(now - chrono::Duration::days(29), now));
+        assert!(activity.is_idle(now - chrono::Duration::days(31), now));
+
+        // A zero timeout never expires anything
+        let activity = self::activity(0, 300);
+        assert_eq!(activity.idle_cutoff(now), None);
+        assert!(!activity.is_idle(now - chrono::Duration::days(3650), now));
+    }
+}
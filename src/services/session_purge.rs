@@ -0,0 +1,88 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+
+use crate::config::SessionPurgeConfig;
+use crate::db::DatabaseConnection;
+use crate::errors::AuthError;
+use crate::models::SessionTableMetrics;
+
+// Deletes sessions that were revoked or expired longer ago than the retention
+// period. Revocation only flags a row, so without this the table, and the
+// indexes every refresh goes through, grow forever.
+pub struct SessionPurge {
+    db: Arc<DatabaseConnection>,
+    config: SessionPurgeConfig,
+    purged_total: AtomicU64,
+    last_purge_at: Mutex<Option<DateTime<Utc>>>,
+}
+
+impl SessionPurge {
+    pub fn new(db: Arc<DatabaseConnection>, config: &SessionPurgeConfig) -> Self {
+        SessionPurge {
+            db,
+            config: config.clone(),
+            purged_total: AtomicU64::new(0),
+            last_purge_at: Mutex::new(None),
+        }
+    }
+
+    /// Purge on the configured interval until the process exits; spawn at
+    /// startup. Returns at once when purging is off.
+    pub async fn run(self: Arc<Self>) {
+        if self.config.interval == 0 {
+            return;
+        }
+        let interval = Duration::from_secs(self.config.interval);
+
+        loop {
+            match self.purge().await {
+                Ok(0) => {}
+                Ok(purged) => log::info!("Purged {} ended sessions", purged),
+                Err(e) => log::error!("Session purge failed: {}", e),
+            }
+            tokio::time::sleep(interval).await;
+        }
+    }
+
+    /// Delete every session that ended before the retention period, in
+    /// every region, a batch at a time, returning how many
+    pub async fn purge(&self) -> Result<usize, AuthError> {
+        let now = Utc::now();
+        let ended_before = now - chrono::Duration::days(self.config.retention_days as i64);
+
+        let mut purged = 0;
+        for region in self.db.regions() {
+            purged += DatabaseConnection::in_region(region, self.purge_region(ended_before)).await?;
+        }
+
+        *self.last_purge_at.lock().unwrap() = Some(now);
+        Ok(purged)
+    }
+
+    async fn purge_region(&self, ended_before: DateTime<Utc>) -> Result<usize, AuthError> {
+        let batch_size = self.config.batch_size.max(1);
+
+        let mut purged = 0;
+        loop {
+            let deleted = self.db.purge_sessions(ended_before, batch_size).await?;
+            purged += deleted;
+            self.purged_total.fetch_add(deleted as u64, Ordering::Relaxed);
+            if (deleted as i64) < batch_size {
+                break;
+            }
+        }
+        Ok(purged)
+    }
+
+    /// Current row counts, with what this instance has purged
+    pub async fn metrics(&self) -> Result<SessionTableMetrics, AuthError> {
+        Ok(SessionTableMetrics {
+            stats: self.db.session_table_stats(Utc::now()).await?,
+            purged_total: self.purged_total.load(Ordering::Relaxed),
+            last_purge_at: *self.last_purge_at.lock().unwrap(),
+        })
+    }
+}
@@ -0,0 +1,37 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::config::Config;
+use crate::db::DatabaseConnection;
+use crate::services::outbox::OutboxRelay;
+
+/// What's left to do once `HttpServer::run` returns after SIGTERM: publish the
+/// outbox events written by the last requests, then close the database pools.
+/// The flush gets the same grace period the workers had to drain.
+pub async fn drain(config: &Config, db: Arc<DatabaseConnection>) {
+    let grace = Duration::from_secs(config.server.shutdown_grace_period);
+
+    // A relay of its own; the background one stopped with the runtime's tasks
+    if let Some(relay) = OutboxRelay::from_config(db.clone(), &config.outbox) {
+        match tokio::time::timeout(grace, relay.flush()).await {
+            Ok(Ok(claimed)) => log::info!("Flushed {} outbox events before exit", claimed),
+            Ok(Err(e)) => log::error!("Failed to flush the outbox: {}", e),
+            Err(_) => log::warn!(
+                "Outbox not flushed within {}s; the rest go out after the next start",
+                grace.as_secs()
+            ),
+        }
+    }
+
+    // r2d2 closes a pool's connections once the last handle to it is dropped
+    match Arc::try_unwrap(db) {
+        Ok(db) => {
+            drop(db);
+            log::info!("Database pools closed");
+        }
+        Err(db) => log::warn!(
+            "{} other handles to the database are still held; its connections close at exit",
+            Arc::strong_count(&db) - 1
+        ),
+    }
+}
@@ -0,0 +1,199 @@
+use std::time::Duration;
+
+use base64::Engine;
+use futures::future::BoxFuture;
+use serde::Deserialize;
+
+use crate::accessibility::{SpeechError, SpeechToText, Transcript};
+use crate::config::{SpeechConfig, SpeechProvider};
+
+const GOOGLE_RECOGNIZE_URL: &str = "https://speech.googleapis.com/v1/speech:recognize";
+
+/// The provider selected by `SPEECH_PROVIDER`, if any
+pub fn speech_to_text(config: &SpeechConfig) -> Option<Box<dyn SpeechToText>> {
+    // Only built when a provider is selected
+    let client = || {
+        reqwest::Client::builder()
+            .timeout(Duration::from_secs(config.timeout))
+            .build()
+            .expect("Failed to build speech-to-text client")
+    };
+
+    match config.provider {
+        SpeechProvider::None => None,
+        SpeechProvider::Whisper => Some(Box::new(WhisperSpeechToText {
+            client: client(),
+            url: config.whisper_url.clone(),
+            language: config.language.clone(),
+        })),
+        SpeechProvider::Google => Some(Box::new(GoogleSpeechToText {
+            client: client(),
+            api_key: config
+                .api_key
+                .clone()
+                .expect("SPEECH_API_KEY must be set for the google speech provider"),
+            language: config.language.clone(),
+        })),
+    }
+}
+
+// whisper.cpp server, e.g. `./server -m ggml-base.en.bin --port 8080`
+pub struct WhisperSpeechToText {
+    client: reqwest::Client,
+    url: String,
+    language: String,
+}
+
+#[derive(Deserialize)]
+struct WhisperResponse {
+    text: String,
+    #[serde(default)]
+    segments: Vec<WhisperSegment>,
+}
+
+#[derive(Deserialize)]
+struct WhisperSegment {
+    avg_logprob: f32,
+}
+
+impl SpeechToText for WhisperSpeechToText {
+    fn transcribe<'a>(
+        &'a self,
+        audio_data: &'a [u8],
+        content_type: &'a str,
+    ) -> BoxFuture<'a, Result<Transcript, SpeechError>> {
+        Box::pin(async move {
+            let file = reqwest::multipart::Part::bytes(audio_data.to_vec())
+                .file_name("command")
+                .mime_str(content_type)
+                .map_err(|e| SpeechError::Provider(e.to_string()))?;
+            // Whisper takes a bare language code ("en", not "en-US")
+            let language = self.language.split('-').next().unwrap_or("en").to_string();
+            let form = reqwest::multipart::Form::new()
+                .part("file", file)
+                .text("language", language)
+                .text("response_format", "verbose_json");
+
+            let response: WhisperResponse = self
+                .client
+                .post(&self.url)
+                .multipart(form)
+                .send()
+                .await
+                .and_then(|r| r.error_for_status())
+                .map_err(|e| SpeechError::Provider(e.to_string()))?
+                .json()
+                .await
+                .map_err(|e| SpeechError::Provider(e.to_string()))?;
+
+            Ok(Transcript {
+                text: response.text.trim().to_string(),
+                confidence: whisper_confidence(&response.segments),
+            })
+        })
+    }
+}
+
+/// Whisper reports log-probabilities per segment rather than a confidence,
+/// so use the probability of the average token
+fn whisper_confidence(segments: &[WhisperSegment]) -> f32 {
+    if segments.is_empty() {
+        return 0.0;
+    }
+
+    let avg_logprob =
+        segments.iter().map(|s| s.avg_logprob).sum::<f32>() / segments.len() as f32;
+    avg_logprob.exp().clamp(0.0, 1.0)
+}
+
+// Google Cloud Speech-to-Text, synchronous recognition (audio up to one minute)
+pub struct GoogleSpeechToText {
+    client: reqwest::Client,
+    api_key: String,
+    language: String,
+}
+
+#[derive(Deserialize)]
+struct GoogleResponse {
+    #[serde(default)]
+    results: Vec<GoogleResult>,
+}
+
+#[derive(Deserialize)]
+struct GoogleResult {
+    alternatives: Vec<GoogleAlternative>,
+}
+
+#[derive(Deserialize)]
+struct GoogleAlternative {
+    #[serde(default)]
+    transcript: String,
+    #[serde(default)]
+    confidence: f32,
+}
+
+impl SpeechToText for GoogleSpeechToText {
+    // Google detects the encoding from WAV and FLAC headers, so the content type is unused
+    fn transcribe<'a>(
+        &'a self,
+        audio_data: &'a [u8],
+        _content_type: &'a str,
+    ) -> BoxFuture<'a, Result<Transcript, SpeechError>> {
+        Box::pin(async move {
+            let body = serde_json::json!({
+                "config": { "languageCode": self.language },
+                "audio": {
+                    "content": base64::engine::general_purpose::STANDARD.encode(audio_data),
+                },
+            });
+
+            let response: GoogleResponse = self
+                .client
+                .post(GOOGLE_RECOGNIZE_URL)
+                .query(&[("key", &self.api_key)])
+                .json(&body)
+                .send()
+                .await
+                .and_then(|r| r.error_for_status())
+                .map_err(|e| SpeechError::Provider(e.to_string()))?
+                .json()
+                .await
+                .map_err(|e| SpeechError::Provider(e.to_string()))?;
+
+            // Each result covers a stretch of the audio; take the best guess for each
+            let best: Vec<&GoogleAlternative> = response
+                .results
+                .iter()
+                .filter_map(|r| r.alternatives.first())
+                .collect();
+
+            if best.is_empty() {
+                return Ok(Transcript {
+                    text: String::new(),
+                    confidence: 0.0,
+                });
+            }
+
+            Ok(Transcript {
+                text: best.iter().map(|a| a.transcript.trim()).collect::<Vec<_>>().join(" "),
+                confidence: best.iter().map(|a| a.confidence).fold(1.0, f32::min),
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_whisper_confidence() {
+        let segments = [
+            WhisperSegment { avg_logprob: -0.1 },
+            WhisperSegment { avg_logprob: -0.3 },
+        ];
+
+        assert!((whisper_confidence(&segments) - (-0.2f32).exp()).abs() < 1e-6);
+        assert_eq!(whisper_confidence(&[]), 0.0);
+    }
+}
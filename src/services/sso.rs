@@ -0,0 +1,406 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use base64::Engine;
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::{decode, decode_header, DecodingKey, Validation};
+use rand::{distributions::Alphanumeric, Rng};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::config::SsoConfig;
+use crate::errors::AuthError;
+use crate::models::{SsoConnection, SsoProtocol};
+
+/// Who the IdP says signed in
+#[derive(Debug, Clone)]
+pub struct FederatedIdentity {
+    pub subject: String,
+    pub email: String,
+    pub name: Option<String>,
+    pub groups: Vec<String>,
+    pub attributes: HashMap<String, Vec<String>>, // Every claim or attribute, for provisioning rules
+}
+
+/// An SSO login sent to the IdP and not yet back
+#[derive(Debug, Clone)]
+pub struct PendingSso {
+    pub connection_id: Uuid,
+    nonce: String,
+    code_verifier: String,           // PKCE, OIDC only
+    saml_request_id: Option<String>, // SAML only
+    created_at: Instant,
+}
+
+#[derive(Deserialize)]
+struct OidcDiscovery {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+}
+
+#[derive(Deserialize)]
+struct OidcTokenResponse {
+    id_token: String,
+}
+
+// Speaks OIDC and SAML to organizations' identity providers. Started logins are
+// keyed by the `state` (OIDC) or `RelayState` (SAML) round-tripped through the IdP.
+pub struct SsoService {
+    client: reqwest::Client,
+    config: SsoConfig,
+    pending: Mutex<HashMap<String, PendingSso>>,
+}
+
+impl SsoService {
+    pub fn new(config: &SsoConfig) -> Self {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(config.timeout))
+            .build()
+            .expect("Failed to build SSO client");
+
+        SsoService {
+            client,
+            config: config.clone(),
+            pending: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn config(&self) -> &SsoConfig {
+        &self.config
+    }
+
+    /// Start a login through `connection`, returning the IdP URL to send the user to
+    pub async fn start(&self, connection: &SsoConnection) -> Result<String, AuthError> {
+        let state = random_token(32);
+        let mut pending = PendingSso {
+            connection_id: connection.id,
+            nonce: random_token(32),
+            code_verifier: random_token(64),
+            saml_request_id: None,
+            created_at: Instant::now(),
+        };
+
+        let redirect_url = match connection.protocol() {
+            SsoProtocol::Oidc => self.oidc_authorization_url(connection, &state, &pending).await?,
+            SsoProtocol::Saml => {
+                let (request_id, url) = self.saml_redirect_url(connection, &state)?;
+                pending.saml_request_id = Some(request_id);
+                url
+            }
+        };
+
+        let ttl = Duration::from_secs(self.config.state_ttl);
+        let mut logins = self.pending.lock().unwrap();
+        logins.retain(|_, login| login.created_at.elapsed() < ttl);
+        logins.insert(state, pending);
+
+        Ok(redirect_url)
+    }
+
+    /// Claim the login behind a returned state; each can be completed once
+    pub fn claim(&self, state: &str) -> Result<PendingSso, AuthError> {
+        let login = self
+            .pending
+            .lock()
+            .unwrap()
+            .remove(state)
+            .ok_or(AuthError::InvalidToken)?;
+
+        if login.created_at.elapsed() >= Duration::from_secs(self.config.state_ttl) {
+            return Err(AuthError::TokenExpired);
+        }
+        Ok(login)
+    }
+
+    /// Exchange an OIDC authorization code and validate the ID token it returns
+    pub async fn oidc_identity(
+        &self,
+        connection: &SsoConnection,
+        login: &PendingSso,
+        code: &str,
+    ) -> Result<FederatedIdentity, AuthError> {
+        let discovery = self.oidc_discovery(connection).await?;
+        let client_id = connection.oidc_client_id.as_deref().unwrap_or_default();
+
+        let tokens: OidcTokenResponse = self
+            .client
+            .post(&discovery.token_endpoint)
+            .form(&[
+                ("grant_type", "authorization_code"),
+                ("code", code),
+                ("redirect_uri", self.config.redirect_url.as_str()),
+                ("client_id", client_id),
+                ("client_secret", connection.oidc_client_secret.as_deref().unwrap_or_default()),
+                ("code_verifier", login.code_verifier.as_str()),
+            ])
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(idp_error)?
+            .json()
+            .await
+            .map_err(idp_error)?;
+
+        let jwks: JwkSet = self
+            .client
+            .get(&discovery.jwks_uri)
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(idp_error)?
+            .json()
+            .await
+            .map_err(idp_error)?;
+
+        let header = decode_header(&tokens.id_token)?;
+        let jwk = header
+            .kid
+            .as_deref()
+            .and_then(|kid| jwks.find(kid))
+            .ok_or_else(|| AuthError::SsoError("ID token signed with an unknown key".into()))?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_audience(&[client_id]);
+        validation.set_issuer(&[discovery.issuer.as_str()]);
+        let claims = decode::<HashMap<String, serde_json::Value>>(
+            &tokens.id_token,
+            &DecodingKey::from_jwk(jwk)?,
+            &validation,
+        )?
+        .claims;
+
+        if claims.get("nonce").and_then(|n| n.as_str()) != Some(login.nonce.as_str()) {
+            return Err(AuthError::SsoError("ID token nonce does not match".into()));
+        }
+        if claims.get("email_verified").and_then(|v| v.as_bool()) == Some(false) {
+            return Err(AuthError::SsoError("The IdP has not verified this email address".into()));
+        }
+
+        let claim = |name: &str| claims.get(name).and_then(|v| v.as_str()).map(str::to_string);
+        Ok(FederatedIdentity {
+            subject: claim("sub").ok_or_else(|| AuthError::SsoError("ID token has no subject".into()))?,
+            email: claim("email").ok_or_else(|| AuthError::SsoError("ID token has no email".into()))?,
+            name: claim("name"),
+            groups: claims
+                .get(&connection.groups_attribute)
+                .map(string_list)
+                .unwrap_or_default(),
+            attributes: claims
+                .iter()
+                .map(|(name, value)| (name.clone(), attribute_values(value)))
+                .collect(),
+        })
+    }
+
+    /// Validate a SAML response POSTed to the assertion consumer service
+    pub fn saml_identity(
+        &self,
+        connection: &SsoConnection,
+        login: &PendingSso,
+        saml_response: &str,
+    ) -> Result<FederatedIdentity, AuthError> {
+        let sp = self.saml_service_provider(connection)?;
+        let request_ids: Vec<&str> = login.saml_request_id.iter().map(String::as_str).collect();
+
+        // Checks the signature against the IdP certificate, the audience,
+        // the validity window and that it answers our request
+        let response = sp
+            .parse_base64_response(saml_response, Some(&request_ids))
+            .map_err(|e| AuthError::SsoError(format!("Invalid SAML response: {}", e)))?;
+        let assertion = response
+            .assertion
+            .ok_or_else(|| AuthError::SsoError("SAML response has no assertion".into()))?;
+
+        let subject = assertion
+            .subject
+            .and_then(|s| s.name_id)
+            .map(|n| n.value)
+            .ok_or_else(|| AuthError::SsoError("SAML assertion has no subject".into()))?;
+
+        let mut attributes: HashMap<String, Vec<String>> = HashMap::new();
+        for statement in assertion.attribute_statements.unwrap_or_default() {
+            for attribute in statement.attributes {
+                let name = attribute.friendly_name.or(attribute.name).unwrap_or_default();
+                attributes
+                    .entry(name)
+                    .or_default()
+                    .extend(attribute.values.into_iter().filter_map(|v| v.value));
+            }
+        }
+        let first = |name: &str| attributes.get(name).and_then(|v| v.first()).cloned();
+
+        // Most IdPs send the email as the NameID; fall back to an `email` attribute
+        let email = first("email")
+            .or_else(|| subject.contains('@').then(|| subject.clone()))
+            .ok_or_else(|| AuthError::SsoError("SAML assertion has no email".into()))?;
+
+        Ok(FederatedIdentity {
+            email,
+            name: first("name").or_else(|| first("displayName")),
+            groups: attributes
+                .get(&connection.groups_attribute)
+                .cloned()
+                .unwrap_or_default(),
+            attributes,
+            subject,
+        })
+    }
+
+    // The IdP's endpoints, fetched per login so key rotations are picked up
+    async fn oidc_discovery(&self, connection: &SsoConnection) -> Result<OidcDiscovery, AuthError> {
+        let issuer = connection
+            .oidc_issuer
+            .as_deref()
+            .ok_or_else(|| AuthError::SsoError("Connection has no OIDC issuer".into()))?;
+
+        self.client
+            .get(format!(
+                "{}/.well-known/openid-configuration",
+                issuer.trim_end_matches('/')
+            ))
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(idp_error)?
+            .json()
+            .await
+            .map_err(idp_error)
+    }
+
+    async fn oidc_authorization_url(
+        &self,
+        connection: &SsoConnection,
+        state: &str,
+        login: &PendingSso,
+    ) -> Result<String, AuthError> {
+        let discovery = self.oidc_discovery(connection).await?;
+        let code_challenge = base64::engine::general_purpose::URL_SAFE_NO_PAD
+            .encode(Sha256::digest(login.code_verifier.as_bytes()));
+
+        let mut url = reqwest::Url::parse(&discovery.authorization_endpoint).map_err(idp_error)?;
+        url.query_pairs_mut()
+            .append_pair("response_type", "code")
+            .append_pair("client_id", connection.oidc_client_id.as_deref().unwrap_or_default())
+            .append_pair("redirect_uri", &self.config.redirect_url)
+            .append_pair("scope", "openid email profile")
+            .append_pair("state", state)
+            .append_pair("nonce", &login.nonce)
+            .append_pair("code_challenge", &code_challenge)
+            .append_pair("code_challenge_method", "S256");
+
+        Ok(url.to_string())
+    }
+
+    /// `(request_id, url)` of an AuthnRequest using the HTTP-Redirect binding
+    fn saml_redirect_url(
+        &self,
+        connection: &SsoConnection,
+        relay_state: &str,
+    ) -> Result<(String, String), AuthError> {
+        let sp = self.saml_service_provider(connection)?;
+        let sso_url = sp
+            .sso_binding_location(samael::metadata::HTTP_REDIRECT_BINDING)
+            .ok_or_else(|| AuthError::SsoError("IdP metadata has no redirect binding".into()))?;
+
+        let request = sp
+            .make_authentication_request(&sso_url)
+            .map_err(|e| AuthError::SsoError(e.to_string()))?;
+        let url = request
+            .redirect(relay_state)
+            .map_err(|e| AuthError::SsoError(e.to_string()))?
+            .ok_or_else(|| AuthError::SsoError("Failed to build SAML redirect".into()))?;
+
+        Ok((request.id, url.to_string()))
+    }
+
+    fn saml_service_provider(
+        &self,
+        connection: &SsoConnection,
+    ) -> Result<samael::service_provider::ServiceProvider, AuthError> {
+        let metadata = connection
+            .saml_idp_metadata
+            .as_deref()
+            .ok_or_else(|| AuthError::SsoError("Connection has no SAML metadata".into()))?;
+        let idp_metadata: samael::metadata::EntityDescriptor = samael::metadata::de::from_str(metadata)
+            .map_err(|e| AuthError::SsoError(format!("Invalid SAML metadata: {}", e)))?;
+
+        samael::service_provider::ServiceProviderBuilder::default()
+            .entity_id(self.config.saml_entity_id.clone())
+            .acs_url(self.config.saml_acs_url.clone())
+            .idp_metadata(idp_metadata)
+            .build()
+            .map_err(|e| AuthError::SsoError(e.to_string()))
+    }
+}
+
+fn idp_error(e: impl std::fmt::Display) -> AuthError {
+    AuthError::SsoError(format!("Identity provider request failed: {}", e))
+}
+
+fn random_token(len: usize) -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(len)
+        .map(char::from)
+        .collect()
+}
+
+/// IdPs send groups as a list or, with a single group, as a bare string
+fn string_list(value: &serde_json::Value) -> Vec<String> {
+    match value {
+        serde_json::Value::String(s) => vec![s.clone()],
+        serde_json::Value::Array(values) => values
+            .iter()
+            .filter_map(|v| v.as_str().map(str::to_string))
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+// A claim's values as text, so rules can match strings, numbers and flags alike
+fn attribute_values(value: &serde_json::Value) -> Vec<String> {
+    match value {
+        serde_json::Value::String(s) => vec![s.clone()],
+        serde_json::Value::Bool(b) => vec![b.to_string()],
+        serde_json::Value::Number(n) => vec![n.to_string()],
+        serde_json::Value::Array(values) => values.iter().flat_map(attribute_values).collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// The domain part of an email address, lowercased
+pub fn email_domain(email: &str) -> Option<String> {
+    email
+        .rsplit_once('@')
+        .map(|(_, domain)| domain.trim().to_lowercase())
+        .filter(|domain| !domain.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_email_domain() {
+        assert_eq!(email_domain("alice@Example.COM"), Some("example.com".to_string()));
+        assert_eq!(email_domain("alice@"), None);
+        assert_eq!(email_domain("alice"), None);
+    }
+
+    #[test]
+    fn test_string_list() {
+        assert_eq!(string_list(&serde_json::json!("eng")), vec!["eng"]);
+        assert_eq!(string_list(&serde_json::json!(["eng", 1, "ops"])), vec!["eng", "ops"]);
+        assert!(string_list(&serde_json::json!(null)).is_empty());
+    }
+
+    #[test]
+    fn test_attribute_values() {
+        assert_eq!(attribute_values(&serde_json::json!(["eng", 1, true])), vec!["eng", "1", "true"]);
+        assert!(attribute_values(&serde_json::json!({"nested": "object"})).is_empty());
+    }
+}
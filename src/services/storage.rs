@@ -0,0 +1,194 @@
+use std::path::{Component, Path, PathBuf};
+
+use futures::future::BoxFuture;
+
+use crate::config::{StorageConfig, StorageProvider};
+use crate::errors::AuthError;
+
+/// Where uploaded files (avatars, ...) live. Keys are relative paths such as
+/// `avatars/<user_id>/<name>.png`; `put` returns the URL the file is served from.
+pub trait BlobStorage: Send + Sync {
+    fn put<'a>(
+        &'a self,
+        key: &'a str,
+        content_type: &'a str,
+        data: Vec<u8>,
+    ) -> BoxFuture<'a, Result<String, AuthError>>;
+
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Option<Vec<u8>>, AuthError>>;
+
+    fn delete<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<(), AuthError>>;
+
+    /// The key behind a URL returned by `put`, if it points into this storage
+    fn key_for_url(&self, url: &str) -> Option<String>;
+}
+
+/// The backend selected by `STORAGE_PROVIDER`
+pub fn blob_storage(config: &StorageConfig) -> Box<dyn BlobStorage> {
+    match config.provider {
+        StorageProvider::Local => Box::new(LocalDiskStorage {
+            root: PathBuf::from(&config.local_path),
+            public_url: config.public_url.trim_end_matches('/').to_string(),
+        }),
+        StorageProvider::S3 => Box::new(S3Storage::new(config)),
+    }
+}
+
+fn storage_error(e: impl std::fmt::Display) -> AuthError {
+    AuthError::InternalServerError(format!("Storage error: {}", e))
+}
+
+/// Reject keys that could escape the storage root
+fn check_key(key: &str) -> Result<&Path, AuthError> {
+    let path = Path::new(key);
+    if key.is_empty() || !path.components().all(|c| matches!(c, Component::Normal(_))) {
+        return Err(AuthError::ValidationError("Invalid storage key".into()));
+    }
+    Ok(path)
+}
+
+// Files under a local directory, served by `GET /media/{key}`
+pub struct LocalDiskStorage {
+    root: PathBuf,
+    public_url: String,
+}
+
+impl BlobStorage for LocalDiskStorage {
+    fn put<'a>(
+        &'a self,
+        key: &'a str,
+        _content_type: &'a str,
+        data: Vec<u8>,
+    ) -> BoxFuture<'a, Result<String, AuthError>> {
+        Box::pin(async move {
+            let path = self.root.join(check_key(key)?);
+            if let Some(parent) = path.parent() {
+                tokio::fs::create_dir_all(parent).await.map_err(storage_error)?;
+            }
+            tokio::fs::write(&path, data).await.map_err(storage_error)?;
+
+            Ok(format!("{}/{}", self.public_url, key))
+        })
+    }
+
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Option<Vec<u8>>, AuthError>> {
+        Box::pin(async move {
+            match tokio::fs::read(self.root.join(check_key(key)?)).await {
+                Ok(data) => Ok(Some(data)),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+                Err(e) => Err(storage_error(e)),
+            }
+        })
+    }
+
+    fn delete<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<(), AuthError>> {
+        Box::pin(async move {
+            match tokio::fs::remove_file(self.root.join(check_key(key)?)).await {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(storage_error(e)),
+                _ => Ok(()),
+            }
+        })
+    }
+
+    fn key_for_url(&self, url: &str) -> Option<String> {
+        url.strip_prefix(&self.public_url)?
+            .strip_prefix('/')
+            .map(|key| key.to_string())
+    }
+}
+
+// An S3 (or S3-compatible, e.g. MinIO) bucket. Credentials come from the usual
+// AWS environment variables or instance profile.
+pub struct S3Storage {
+    bucket: s3::Bucket,
+    public_url: String,
+}
+
+impl S3Storage {
+    fn new(config: &StorageConfig) -> Self {
+        let bucket_name = config
+            .s3_bucket
+            .as_deref()
+            .expect("S3_BUCKET must be set for the s3 storage provider");
+
+        let region = match &config.s3_endpoint {
+            Some(endpoint) => s3::Region::Custom {
+                region: config.s3_region.clone(),
+                endpoint: endpoint.clone(),
+            },
+            None => config.s3_region.parse().expect("S3_REGION must be a valid AWS region"),
+        };
+        let credentials =
+            s3::creds::Credentials::default().expect("Failed to load S3 credentials");
+
+        let mut bucket = s3::Bucket::new(bucket_name, region, credentials)
+            .expect("Failed to configure S3 bucket");
+        if config.s3_endpoint.is_some() {
+            bucket = bucket.with_path_style();
+        }
+
+        S3Storage {
+            bucket,
+            public_url: config.public_url.trim_end_matches('/').to_string(),
+        }
+    }
+}
+
+impl BlobStorage for S3Storage {
+    fn put<'a>(
+        &'a self,
+        key: &'a str,
+        content_type: &'a str,
+        data: Vec<u8>,
+    ) -> BoxFuture<'a, Result<String, AuthError>> {
+        Box::pin(async move {
+            check_key(key)?;
+            self.bucket
+                .put_object_with_content_type(key, &data, content_type)
+                .await
+                .map_err(storage_error)?;
+
+            Ok(format!("{}/{}", self.public_url, key))
+        })
+    }
+
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Option<Vec<u8>>, AuthError>> {
+        Box::pin(async move {
+            check_key(key)?;
+            let response = self.bucket.get_object(key).await.map_err(storage_error)?;
+
+            match response.status_code() {
+                200 => Ok(Some(response.bytes().to_vec())),
+                404 => Ok(None),
+                status => Err(storage_error(format!("GET {} returned {}", key, status))),
+            }
+        })
+    }
+
+    fn delete<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<(), AuthError>> {
+        Box::pin(async move {
+            check_key(key)?;
+            self.bucket.delete_object(key).await.map_err(storage_error)?;
+            Ok(())
+        })
+    }
+
+    fn key_for_url(&self, url: &str) -> Option<String> {
+        url.strip_prefix(&self.public_url)?
+            .strip_prefix('/')
+            .map(|key| key.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_key() {
+        assert!(check_key("avatars/user/a.png").is_ok());
+        assert!(check_key("../etc/passwd").is_err());
+        assert!(check_key("/etc/passwd").is_err());
+        assert!(check_key("").is_err());
+    }
+}
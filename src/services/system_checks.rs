@@ -0,0 +1,239 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::config::{is_production, Config, EmailDelivery, DEVELOPMENT_DATABASE_URL, DEVELOPMENT_SECRET_KEY};
+use crate::db::{DatabaseConnection, SCHEMA_VERSION};
+use crate::services::email::check_smtp;
+
+// HS256 keys shorter than the hash output can be brute forced offline from
+// any token that was ever issued
+const MIN_SECRET_LEN: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckStatus {
+    Pass,
+    Warning,  // Worth fixing, but safe to run with
+    Critical, // Refuses startup in production
+}
+
+#[derive(Debug, Serialize)]
+pub struct SystemCheck {
+    pub name: &'static str,
+    pub status: CheckStatus,
+    pub detail: String,
+}
+
+impl SystemCheck {
+    fn new(name: &'static str, status: CheckStatus, detail: impl Into<String>) -> Self {
+        SystemCheck {
+            name,
+            status,
+            detail: detail.into(),
+        }
+    }
+}
+
+/// What `GET /admin/system/checks` reports, and what's checked at startup
+#[derive(Debug, Serialize)]
+pub struct SystemCheckReport {
+    pub status: CheckStatus, // The worst of the checks
+    pub production: bool,    // APP_ENV is production
+    pub checked_at: DateTime<Utc>,
+    pub checks: Vec<SystemCheck>,
+}
+
+impl SystemCheckReport {
+    pub fn critical(&self) -> impl Iterator<Item = &SystemCheck> {
+        self.checks.iter().filter(|check| check.status == CheckStatus::Critical)
+    }
+}
+
+/// Check the running configuration and what it connects to
+pub async fn run(config: &Config, db: &DatabaseConnection) -> SystemCheckReport {
+    let checks = vec![
+        jwt_secret(config),
+        insecure_defaults(config),
+        smtp(config).await,
+        migrations(config, db).await,
+    ];
+
+    SystemCheckReport {
+        status: checks.iter().map(|check| check.status).max().unwrap_or(CheckStatus::Pass),
+        production: is_production(),
+        checked_at: Utc::now(),
+        checks,
+    }
+}
+
+/// Run the checks before serving and log what they find. In production,
+/// any critical failure refuses startup.
+pub async fn check_at_startup(config: &Config, db: &DatabaseConnection) -> Result<SystemCheckReport, String> {
+    let report = run(config, db).await;
+    for check in &report.checks {
+        match check.status {
+            CheckStatus::Pass => log::info!("Startup check {} passed: {}", check.name, check.detail),
+            CheckStatus::Warning => log::warn!("Startup check {}: {}", check.name, check.detail),
+            CheckStatus::Critical => log::error!("Startup check {} failed: {}", check.name, check.detail),
+        }
+    }
+
+    if report.production && report.status == CheckStatus::Critical {
+        let failed: Vec<_> = report.critical().map(|check| check.name).collect();
+        return Err(format!("Refusing to start in production; failed checks: {}", failed.join(", ")));
+    }
+    Ok(report)
+}
+
+fn jwt_secret(config: &Config) -> SystemCheck {
+    let secret = &config.jwt.secret;
+    if secret == DEVELOPMENT_SECRET_KEY {
+        SystemCheck::new("jwt_secret", CheckStatus::Critical, "SECRET_KEY isn't set; tokens are signed with the development key")
+    } else if secret.len() < MIN_SECRET_LEN {
+        SystemCheck::new(
+            "jwt_secret",
+            CheckStatus::Critical,
+            format!("SECRET_KEY is {} bytes; use at least {}", secret.len(), MIN_SECRET_LEN),
+        )
+    } else {
+        SystemCheck::new("jwt_secret", CheckStatus::Pass, format!("{} bytes", secret.len()))
+    }
+}
+
+// Settings only meant for local development
+fn insecure_defaults(config: &Config) -> SystemCheck {
+    let mut critical = Vec::new();
+    if config.database.url == DEVELOPMENT_DATABASE_URL && !config.database.in_memory {
+        critical.push("DATABASE_URL isn't set");
+    }
+    if config.database.in_memory {
+        critical.push("data is kept in memory");
+    }
+    if config.dev.enabled {
+        critical.push("started with --dev");
+    }
+    if config.dev.seed_enabled {
+        critical.push("DEV_SEED_ENABLED is on");
+    }
+
+    let mut warnings = Vec::new();
+    if config.email.delivery == EmailDelivery::Log {
+        warnings.push("email goes to the log");
+    }
+    if config.frontend.base_url == "https://example.com" {
+        warnings.push("FRONTEND_URL isn't set");
+    }
+
+    let status = if !critical.is_empty() {
+        CheckStatus::Critical
+    } else if !warnings.is_empty() {
+        CheckStatus::Warning
+    } else {
+        CheckStatus::Pass
+    };
+    let found: Vec<_> = critical.into_iter().chain(warnings).collect();
+    let detail = if found.is_empty() { "None found".to_string() } else { found.join("; ") };
+    SystemCheck::new("insecure_defaults", status, detail)
+}
+
+async fn smtp(config: &Config) -> SystemCheck {
+    if config.email.delivery != EmailDelivery::Smtp {
+        return SystemCheck::new("smtp", CheckStatus::Pass, "Skipped; EMAIL_DELIVERY isn't smtp");
+    }
+
+    match check_smtp(&config.email).await {
+        Ok(()) => SystemCheck::new(
+            "smtp",
+            CheckStatus::Pass,
+            format!("Connected to {}:{}", config.email.smtp_host, config.email.smtp_port),
+        ),
+        Err(e) => SystemCheck::new(
+            "smtp",
+            CheckStatus::Critical,
+            format!("Can't reach {}:{}: {}", config.email.smtp_host, config.email.smtp_port, e),
+        ),
+    }
+}
+
+// Every region's database, each of which is migrated on its own
+async fn migrations(config: &Config, db: &DatabaseConnection) -> SystemCheck {
+    let mut regions = vec![None];
+    if db.is_regional() {
+        regions.extend(config.regions.database_urls.keys().cloned().map(Some));
+    }
+
+    let mut behind = Vec::new();
+    let mut ahead = Vec::new();
+    for region in regions {
+        let name = region.clone().unwrap_or_else(|| config.regions.home.clone());
+        match DatabaseConnection::in_region(region, db.latest_migration()).await {
+            Ok(Some(version)) if version.as_str() > SCHEMA_VERSION => ahead.push(format!("{} is at {}", name, version)),
+            Ok(Some(version)) if version.as_str() < SCHEMA_VERSION => behind.push(format!("{} is at {}", name, version)),
+            Ok(Some(_)) => {}
+            Ok(None) => behind.push(format!("{} has no migrations run", name)),
+            Err(e) => behind.push(format!("{}: {}", name, e)),
+        }
+    }
+
+    if !behind.is_empty() {
+        SystemCheck::new(
+            "migrations",
+            CheckStatus::Critical,
+            format!("Pending migrations, up to {}: {}", SCHEMA_VERSION, behind.join("; ")),
+        )
+    } else if !ahead.is_empty() {
+        // E.g. a deploy rolled back without its migrations
+        SystemCheck::new(
+            "migrations",
+            CheckStatus::Warning,
+            format!("Newer than this build's {}: {}", SCHEMA_VERSION, ahead.join("; ")),
+        )
+    } else {
+        SystemCheck::new("migrations", CheckStatus::Pass, format!("At {}", SCHEMA_VERSION))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::test_config;
+
+    #[test]
+    fn test_schema_version_is_the_newest_migration() {
+        let newest = std::fs::read_dir(concat!(env!("CARGO_MANIFEST_DIR"), "/migrations"))
+            .unwrap()
+            .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+            .filter_map(|name| Some(name.split_once('_')?.0.replace('-', "")))
+            .max()
+            .unwrap();
+        assert_eq!(newest, SCHEMA_VERSION);
+    }
+
+    #[test]
+    fn test_weak_secrets_are_critical() {
+        let mut config = test_config();
+        config.jwt.secret = DEVELOPMENT_SECRET_KEY.to_string();
+        assert_eq!(jwt_secret(&config).status, CheckStatus::Critical);
+        config.jwt.secret = "hunter2".to_string();
+        assert_eq!(jwt_secret(&config).status, CheckStatus::Critical);
+        config.jwt.secret = "k".repeat(MIN_SECRET_LEN);
+        assert_eq!(jwt_secret(&config).status, CheckStatus::Pass);
+    }
+
+    #[test]
+    fn test_development_settings_are_flagged() {
+        let mut config = test_config();
+        config.database.url = "postgres://auth@db.internal/auth".to_string();
+        config.email.delivery = EmailDelivery::Smtp;
+        config.frontend.base_url = "https://app.example.org".to_string();
+        assert_eq!(insecure_defaults(&config).status, CheckStatus::Pass);
+
+        config.email.delivery = EmailDelivery::Log;
+        assert_eq!(insecure_defaults(&config).status, CheckStatus::Warning);
+
+        config.dev.seed_enabled = true;
+        let check = insecure_defaults(&config);
+        assert_eq!(check.status, CheckStatus::Critical);
+        assert_eq!(check.detail, "DEV_SEED_ENABLED is on; email goes to the log");
+    }
+}
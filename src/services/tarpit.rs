@@ -0,0 +1,215 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use crate::config::TarpitConfig;
+use crate::services::brute_force::BruteForceGuard;
+
+/// Counters exposed for monitoring the tarpit
+#[derive(Debug, Serialize)]
+pub struct TarpitMetrics {
+    pub delayed_requests: u64,
+    pub total_delay_ms: u64,
+    pub tracked_keys: usize,
+}
+
+// Progressive delays for repeated failed logins, keyed by the submitted
+// identifier and the client IP. The delay is applied before credentials are
+// checked, so it is the same whether the account exists or the password is right.
+// Per-IP failures are kept by the `BruteForceGuard`, so delays and lockouts
+// share one count, window and reset rule; only identifiers are counted here.
+pub struct LoginTarpit {
+    config: TarpitConfig,
+    brute_force: Arc<BruteForceGuard>,
+    failures: Mutex<HashMap<String, (u32, Instant)>>,
+    delayed_requests: AtomicU64,
+    total_delay_ms: AtomicU64,
+}
+
+impl LoginTarpit {
+    pub fn new(config: TarpitConfig, brute_force: Arc<BruteForceGuard>) -> Self {
+        LoginTarpit {
+            config,
+            brute_force,
+            failures: Mutex::new(HashMap::new()),
+            delayed_requests: AtomicU64::new(0),
+            total_delay_ms: AtomicU64::new(0),
+        }
+    }
+
+    /// Tarpit keys for a login attempt
+    pub fn keys(username_or_email: &str, ip: Option<&str>) -> Vec<String> {
+        let mut keys = vec![format!("account:{}", username_or_email.trim().to_lowercase())];
+        if let Some(ip) = ip {
+            keys.push(format!("ip:{}", ip));
+        }
+        keys
+    }
+
+    /// Delay owed for the given keys, based on failures inside the window
+    pub fn delay_for(&self, keys: &[String]) -> Duration {
+        if !self.config.enabled || self.config.delays_ms.is_empty() {
+            return Duration::ZERO;
+        }
+
+        let window = Duration::from_secs(self.config.window);
+        let failures = self.failures.lock().unwrap();
+
+        let count = keys
+            .iter()
+            .map(|key| match key.strip_prefix("ip:") {
+                Some(ip) => self.brute_force.ip_failures(Some(ip)),
+                None => failures
+                    .get(key)
+                    .filter(|(_, last)| last.elapsed() < window)
+                    .map_or(0, |(count, _)| *count),
+            })
+            .max()
+            .unwrap_or(0);
+
+        if count == 0 {
+            return Duration::ZERO;
+        }
+
+        // Past the end of the schedule, keep using the longest delay
+        let index = (count as usize - 1).min(self.config.delays_ms.len() - 1);
+        Duration::from_millis(self.config.delays_ms[index])
+    }
+
+    /// Sleep for whatever delay the keys have accumulated
+    pub async fn wait(&self, keys: &[String]) {
+        let delay = self.delay_for(keys);
+        if delay.is_zero() {
+            return;
+        }
+
+        self.delayed_requests.fetch_add(1, Ordering::Relaxed);
+        self.total_delay_ms
+            .fetch_add(delay.as_millis() as u64, Ordering::Relaxed);
+        log::info!("Tarpitting login attempt for {}ms", delay.as_millis());
+
+        tokio::time::sleep(delay).await;
+    }
+
+    pub fn record_failure(&self, keys: &[String]) {
+        let window = Duration::from_secs(self.config.window);
+        let mut failures = self.failures.lock().unwrap();
+
+        // Clean up expired entries
+        failures.retain(|_, (_, last)| last.elapsed() < window);
+
+        for key in keys {
+            if let Some(ip) = key.strip_prefix("ip:") {
+                self.brute_force.record_ip_failure(ip);
+                continue;
+            }
+            let entry = failures.entry(key.clone()).or_insert((0, Instant::now()));
+            entry.0 += 1;
+            entry.1 = Instant::now();
+        }
+    }
+
+    /// Forget the account's failures once it signs in. The IP's are kept, so
+    /// one good password doesn't reset a client spraying many accounts.
+    pub fn record_success(&self, keys: &[String]) {
+        let mut failures = self.failures.lock().unwrap();
+        for key in keys {
+            failures.remove(key);
+        }
+    }
+
+    pub fn metrics(&self) -> TarpitMetrics {
+        TarpitMetrics {
+            delayed_requests: self.delayed_requests.load(Ordering::Relaxed),
+            total_delay_ms: self.total_delay_ms.load(Ordering::Relaxed),
+            tracked_keys: self.failures.lock().unwrap().len(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{BruteForceCombine, BruteForceConfig};
+
+    fn tarpit() -> LoginTarpit {
+        let brute_force = BruteForceGuard::new(&BruteForceConfig {
+            enabled: false,
+            combine: BruteForceCombine::Or,
+            window: 900,
+            account_captcha_after: 3,
+            account_lockout_after: 10,
+            ip_captcha_after: 10,
+            ip_lockout_after: 50,
+            lockout_duration: 900,
+        });
+        LoginTarpit::new(
+            TarpitConfig {
+                enabled: true,
+                delays_ms: vec![250, 1000, 3000],
+                window: 900,
+            },
+            Arc::new(brute_force),
+        )
+    }
+
+    #[test]
+    fn test_delay_grows_with_failures() {
+        let tarpit = tarpit();
+        let keys = LoginTarpit::keys("User@Example.com", Some("10.0.0.1"));
+
+        assert_eq!(tarpit.delay_for(&keys), Duration::ZERO);
+
+        tarpit.record_failure(&keys);
+        assert_eq!(tarpit.delay_for(&keys), Duration::from_millis(250));
+
+        tarpit.record_failure(&keys);
+        tarpit.record_failure(&keys);
+        tarpit.record_failure(&keys);
+        assert_eq!(tarpit.delay_for(&keys), Duration::from_millis(3000));
+
+        tarpit.record_success(&keys);
+        assert_eq!(tarpit.delay_for(&LoginTarpit::keys("user@example.com", None)), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_ip_failures_delay_other_accounts() {
+        let tarpit = tarpit();
+        tarpit.record_failure(&LoginTarpit::keys("alice", Some("10.0.0.1")));
+
+        let other_account = LoginTarpit::keys("bob", Some("10.0.0.1"));
+        assert_eq!(tarpit.delay_for(&other_account), Duration::from_millis(250));
+
+        let other_ip = LoginTarpit::keys("bob", Some("10.0.0.2"));
+        assert_eq!(tarpit.delay_for(&other_ip), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_success_keeps_ip_failures() {
+        let tarpit = tarpit();
+        for account in ["alice", "bob", "carol"] {
+            tarpit.record_failure(&LoginTarpit::keys(account, Some("10.0.0.1")));
+        }
+
+        // A sprayer that guesses one password right is still slowed down on the rest
+        tarpit.record_success(&LoginTarpit::keys("carol", Some("10.0.0.1")));
+        assert_eq!(tarpit.delay_for(&LoginTarpit::keys("carol", None)), Duration::ZERO);
+        assert_eq!(tarpit.delay_for(&LoginTarpit::keys("dave", Some("10.0.0.1"))), Duration::from_millis(3000));
+    }
+
+    #[test]
+    fn test_ip_failures_are_the_brute_force_count() {
+        let tarpit = tarpit();
+        tarpit.record_failure(&LoginTarpit::keys("alice", Some("10.0.0.1")));
+        tarpit.record_failure(&LoginTarpit::keys("bob", Some("10.0.0.1")));
+
+        // One count per IP, shared with lockouts
+        assert_eq!(tarpit.brute_force.ip_failures(Some("10.0.0.1")), 2);
+        tarpit.brute_force.record_ip_failure("10.0.0.1");
+        assert_eq!(tarpit.delay_for(&LoginTarpit::keys("carol", Some("10.0.0.1"))), Duration::from_millis(3000));
+        assert_eq!(tarpit.metrics().tracked_keys, 2);
+    }
+}
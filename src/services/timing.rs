@@ -0,0 +1,61 @@
+use std::time::Duration;
+
+use rand::Rng;
+use tokio::time::Instant;
+
+use crate::config::ResponseTimingConfig;
+
+/// Holds a response back until a minimum time plus random jitter has passed
+/// since it started, so every branch of a handler takes about as long.
+/// Start it before any lookup and `wait` on it after the last one.
+pub struct ResponseFloor {
+    deadline: Instant,
+}
+
+impl ResponseFloor {
+    pub fn start(config: &ResponseTimingConfig) -> Self {
+        let jitter = if config.jitter_ms > 0 {
+            rand::thread_rng().gen_range(0..=config.jitter_ms)
+        } else {
+            0
+        };
+
+        ResponseFloor {
+            deadline: Instant::now() + Duration::from_millis(config.min_response_ms + jitter),
+        }
+    }
+
+    pub async fn wait(self) {
+        tokio::time::sleep_until(self.deadline).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(min_response_ms: u64, jitter_ms: u64) -> ResponseTimingConfig {
+        ResponseTimingConfig {
+            min_response_ms,
+            jitter_ms,
+            background_email: false,
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_waits_for_floor() {
+        let started = Instant::now();
+        ResponseFloor::start(&config(50, 20)).wait().await;
+        assert!(started.elapsed() >= Duration::from_millis(50));
+    }
+
+    #[actix_web::test]
+    async fn test_slow_work_is_not_delayed_further() {
+        let floor = ResponseFloor::start(&config(20, 0));
+        tokio::time::sleep(Duration::from_millis(60)).await;
+
+        let before_wait = Instant::now();
+        floor.wait().await;
+        assert!(before_wait.elapsed() < Duration::from_millis(10));
+    }
+}
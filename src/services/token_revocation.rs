@@ -0,0 +1,139 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+use crate::config::TokenRevocationConfig;
+use crate::db::DatabaseConnection;
+use crate::errors::AuthError;
+use crate::models::NewTokenRevocation;
+
+// Sessions and single access tokens cut off before their access tokens
+// expire, checked by `AuthMiddleware` against a token's `sid` and `jti`.
+// Revocations made here apply at once; those made on other instances arrive
+// with the next poll. Entries are dropped once every token they could match
+// has expired, so the cache stays small without bumping the user's
+// `token_version`, which would sign out every other session too.
+pub struct TokenRevocations {
+    db: Arc<DatabaseConnection>,
+    poll_interval: u64,
+    token_lifetime: chrono::Duration,
+    revoked: Mutex<HashMap<Uuid, DateTime<Utc>>>, // Session id or `jti`, to when it stops mattering
+    polled_at: Mutex<DateTime<Utc>>,
+}
+
+impl TokenRevocations {
+    /// `token_lifetime` is in seconds, the longest any revocable token lasts
+    pub fn new(db: Arc<DatabaseConnection>, config: &TokenRevocationConfig, token_lifetime: u64) -> Self {
+        let token_lifetime = chrono::Duration::seconds(token_lifetime as i64);
+        TokenRevocations {
+            db,
+            poll_interval: config.poll_interval,
+            token_lifetime,
+            revoked: Mutex::new(HashMap::new()),
+            // A fresh instance picks up revocations whose tokens may still be live
+            polled_at: Mutex::new(Utc::now() - token_lifetime),
+        }
+    }
+
+    /// Pick up revocations from other instances on the configured interval
+    /// until the process exits; spawn at startup. Returns at once when polling
+    /// is off.
+    pub async fn run(self: Arc<Self>) {
+        if self.poll_interval == 0 {
+            return;
+        }
+        let interval = Duration::from_secs(self.poll_interval);
+
+        loop {
+            if let Err(e) = self.poll().await {
+                log::error!("Polling token revocations failed: {}", e);
+            }
+            tokio::time::sleep(interval).await;
+        }
+    }
+
+    /// Fetch revocations made since the last poll, returning how many
+    pub async fn poll(&self) -> Result<usize, AuthError> {
+        let now = Utc::now();
+        // Overlap the previous poll, so rows committed late or stamped by a
+        // slightly different clock aren't missed
+        let since = *self.polled_at.lock().unwrap() - chrono::Duration::seconds(self.poll_interval as i64);
+
+        // Revocations are stored in the region of the user they're for
+        let mut count = 0;
+        for region in self.db.regions() {
+            let revocations = DatabaseConnection::in_region(region, self.db.find_token_revocations(since)).await?;
+            count += revocations.len();
+            self.remember(revocations.into_iter().map(|r| (r.id, r.expires_at)));
+        }
+
+        *self.polled_at.lock().unwrap() = now;
+        Ok(count)
+    }
+
+    /// Reject access tokens issued with any of these sessions or `jti`s from
+    /// now on, here and, after their next poll, on every other instance
+    pub async fn revoke(&self, ids: &[Uuid]) -> Result<(), AuthError> {
+        if ids.is_empty() {
+            return Ok(());
+        }
+
+        let expires_at = Utc::now() + self.token_lifetime;
+        self.remember(ids.iter().map(|id| (*id, expires_at)));
+
+        let revocations = ids.iter().map(|id| NewTokenRevocation { id: *id, expires_at }).collect();
+        self.db.create_token_revocations(revocations).await
+    }
+
+    /// Whether a token's `jti` or `sid` has been revoked
+    pub fn is_revoked(&self, jti: Option<Uuid>, sid: Option<Uuid>) -> bool {
+        let revoked = self.revoked.lock().unwrap();
+        [jti, sid].iter().flatten().any(|id| revoked.contains_key(id))
+    }
+
+    fn remember(&self, entries: impl Iterator<Item = (Uuid, DateTime<Utc>)>) {
+        let now = Utc::now();
+        let mut revoked = self.revoked.lock().unwrap();
+        revoked.retain(|_, expires_at| *expires_at > now);
+        for (id, expires_at) in entries.filter(|(_, expires_at)| *expires_at > now) {
+            revoked.insert(id, expires_at);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[actix_web::test]
+    async fn test_revocations_reach_other_instances_on_poll() {
+        let db = Arc::new(DatabaseConnection::new_memory());
+        let config = TokenRevocationConfig { poll_interval: 5 };
+        let here = TokenRevocations::new(db.clone(), &config, 3600);
+        let elsewhere = TokenRevocations::new(db, &config, 3600);
+        let (session, token) = (Uuid::new_v4(), Uuid::new_v4());
+
+        here.revoke(&[session]).await.unwrap();
+        assert!(here.is_revoked(None, Some(session)));
+        assert!(here.is_revoked(Some(token), Some(session)));
+        assert!(!here.is_revoked(Some(token), None));
+        assert!(!elsewhere.is_revoked(None, Some(session)));
+
+        assert_eq!(elsewhere.poll().await.unwrap(), 1);
+        assert!(elsewhere.is_revoked(None, Some(session)));
+    }
+
+    #[actix_web::test]
+    async fn test_revocations_are_forgotten_once_tokens_expire() {
+        let db = Arc::new(DatabaseConnection::new_memory());
+        let revocations = TokenRevocations::new(db, &TokenRevocationConfig { poll_interval: 5 }, 0);
+        let token = Uuid::new_v4();
+
+        revocations.revoke(&[token]).await.unwrap();
+        assert!(!revocations.is_revoked(Some(token), None));
+        assert_eq!(revocations.poll().await.unwrap(), 0);
+    }
+}
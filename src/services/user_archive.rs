@@ -0,0 +1,75 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+
+use crate::config::UserArchiveConfig;
+use crate::db::DatabaseConnection;
+use crate::errors::AuthError;
+use crate::middleware::auth::UserCache;
+
+// Archives accounts nobody has signed in to for `USER_ARCHIVE_INACTIVE_DAYS`.
+// They're signed out everywhere and dropped from admin search; the next
+// password login emails a reactivation link instead of signing in.
+pub struct UserArchiver {
+    db: Arc<DatabaseConnection>,
+    user_cache: Arc<UserCache>,
+    config: UserArchiveConfig,
+}
+
+impl UserArchiver {
+    pub fn new(db: Arc<DatabaseConnection>, user_cache: Arc<UserCache>, config: &UserArchiveConfig) -> Self {
+        UserArchiver {
+            db,
+            user_cache,
+            config: config.clone(),
+        }
+    }
+
+    /// Archive on the configured interval until the process exits; spawn at
+    /// startup. Returns at once when archiving is off.
+    pub async fn run(self: Arc<Self>) {
+        if self.config.inactive_days == 0 || self.config.interval == 0 {
+            return;
+        }
+        let interval = Duration::from_secs(self.config.interval);
+
+        loop {
+            match self.archive().await {
+                Ok(0) => {}
+                Ok(archived) => log::info!("Archived {} inactive accounts", archived),
+                Err(e) => log::error!("Account archiving failed: {}", e),
+            }
+            tokio::time::sleep(interval).await;
+        }
+    }
+
+    /// Archive every account inactive for longer than the configured period,
+    /// in every region, a batch at a time, returning how many
+    pub async fn archive(&self) -> Result<usize, AuthError> {
+        let inactive_before = Utc::now() - chrono::Duration::days(self.config.inactive_days as i64);
+
+        let mut archived = 0;
+        for region in self.db.regions() {
+            archived += DatabaseConnection::in_region(region, self.archive_region(inactive_before)).await?;
+        }
+        Ok(archived)
+    }
+
+    async fn archive_region(&self, inactive_before: DateTime<Utc>) -> Result<usize, AuthError> {
+        let batch_size = self.config.batch_size.max(1);
+
+        let mut archived = 0;
+        loop {
+            let ids = self.db.archive_inactive_users(inactive_before, batch_size).await?;
+            for id in &ids {
+                self.user_cache.invalidate(*id);
+            }
+            archived += ids.len();
+            if (ids.len() as i64) < batch_size {
+                break;
+            }
+        }
+        Ok(archived)
+    }
+}
@@ -0,0 +1,131 @@
+use actix_http::Request;
+use actix_web::body::MessageBody;
+use actix_web::dev::{Service, ServiceResponse};
+use actix_web::http::StatusCode;
+use actix_web::test;
+use serde_json::{json, Value};
+
+/// Status and JSON body of a response
+#[derive(Debug)]
+pub struct TestResponse {
+    pub status: StatusCode,
+    pub body: Value, // `Value::Null` when the body isn't JSON
+}
+
+impl TestResponse {
+    /// Panics with the body when the request didn't succeed
+    pub fn assert_success(self) -> Self {
+        assert!(self.status.is_success(), "{}: {}", self.status, self.body);
+        self
+    }
+
+    /// A string field of the body, e.g. `access_token`
+    pub fn field(&self, name: &str) -> Option<&str> {
+        self.body.get(name).and_then(Value::as_str)
+    }
+}
+
+/// POST `body` as JSON to `path`
+pub async fn post_json<S, B>(app: &S, path: &str, body: Value) -> TestResponse
+where
+    S: Service<Request, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    B: MessageBody,
+{
+    let request = test::TestRequest::post()
+        .uri(path)
+        .insert_header(("User-Agent", "betterauth-test"))
+        .set_json(body)
+        .to_request();
+    let response = test::call_service(app, request).await;
+    let status = response.status();
+    let bytes = test::read_body(response).await;
+
+    TestResponse {
+        status,
+        body: serde_json::from_slice(&bytes).unwrap_or(Value::Null),
+    }
+}
+
+pub async fn register<S, B>(app: &S, username: &str, email: &str, password: &str) -> TestResponse
+where
+    S: Service<Request, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    B: MessageBody,
+{
+    post_json(
+        app,
+        "/auth/register",
+        json!({
+            "username": username,
+            "email": email,
+            "password": password,
+            "password_confirmation": password,
+        }),
+    )
+    .await
+}
+
+pub async fn login<S, B>(app: &S, username_or_email: &str, password: &str) -> TestResponse
+where
+    S: Service<Request, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    B: MessageBody,
+{
+    post_json(
+        app,
+        "/auth/login",
+        json!({
+            "username_or_email": username_or_email,
+            "password": password,
+        }),
+    )
+    .await
+}
+
+pub async fn mfa_login<S, B>(app: &S, username_or_email: &str, password: &str, mfa_code: &str) -> TestResponse
+where
+    S: Service<Request, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    B: MessageBody,
+{
+    post_json(
+        app,
+        "/auth/mfa-login",
+        json!({
+            "username_or_email": username_or_email,
+            "password": password,
+            "mfa_code": mfa_code,
+        }),
+    )
+    .await
+}
+
+pub async fn verify_email<S, B>(app: &S, token: &str) -> TestResponse
+where
+    S: Service<Request, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    B: MessageBody,
+{
+    post_json(app, "/auth/verify-email", json!({ "token": token })).await
+}
+
+pub async fn request_password_reset<S, B>(app: &S, email: &str) -> TestResponse
+where
+    S: Service<Request, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    B: MessageBody,
+{
+    post_json(app, "/auth/password-reset", json!({ "email": email })).await
+}
+
+pub async fn confirm_password_reset<S, B>(app: &S, token: &str, password: &str) -> TestResponse
+where
+    S: Service<Request, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    B: MessageBody,
+{
+    post_json(
+        app,
+        "/auth/password-reset-confirm",
+        json!({
+            "token": token,
+            "password": password,
+            "password_confirmation": password,
+        }),
+    )
+    .await
+}
@@ -0,0 +1,252 @@
+use chrono::{Duration, Utc};
+use uuid::Uuid;
+
+use crate::errors::AuthError;
+use crate::models::{AccountStatus, NewSession, NewUser, Session, User};
+use crate::services::auth::{status_changed_event, user_created_event};
+use crate::test_utils::TestContext;
+use crate::utils::jwt::{create_jwt, decode_jwt, JwtClaims, AMR_PASSWORD};
+use crate::utils::password::hash_password;
+
+pub const DEFAULT_PASSWORD: &str = "TestPass123!";
+
+/// A user created straight in the database, with the secrets a test needs to
+/// sign in as them
+#[derive(Debug, Clone)]
+pub struct TestUser {
+    pub user: User,
+    pub password: String,
+    pub mfa_secret: Option<String>, // Base32, as shown to authenticator apps
+}
+
+impl TestUser {
+    pub fn id(&self) -> Uuid {
+        self.user.id
+    }
+
+    /// Current TOTP code, for users built `with_mfa`
+    pub fn totp_code(&self, ctx: &TestContext) -> Option<String> {
+        let secret = self.mfa_secret.as_deref()?;
+        ctx.mfa_service().current_code(secret)
+    }
+}
+
+/// Builds users in any state without going through registration
+pub struct UserFactory<'a> {
+    ctx: &'a TestContext,
+    username: Option<String>,
+    email: Option<String>,
+    password: String,
+    verified: bool,
+    admin: bool,
+    guest: bool,
+    mfa: bool,
+    password_expired: bool,
+    status: Option<AccountStatus>,
+}
+
+impl<'a> UserFactory<'a> {
+    pub fn new(ctx: &'a TestContext) -> Self {
+        UserFactory {
+            ctx,
+            username: None,
+            email: None,
+            password: DEFAULT_PASSWORD.to_string(),
+            verified: true,
+            admin: false,
+            guest: false,
+            mfa: false,
+            password_expired: false,
+            status: None,
+        }
+    }
+
+    pub fn username(mut self, username: impl Into<String>) -> Self {
+        self.username = Some(username.into());
+        self
+    }
+
+    pub fn email(mut self, email: impl Into<String>) -> Self {
+        self.email = Some(email.into());
+        self
+    }
+
+    pub fn password(mut self, password: impl Into<String>) -> Self {
+        self.password = password.into();
+        self
+    }
+
+    pub fn unverified(mut self) -> Self {
+        self.verified = false;
+        self
+    }
+
+    pub fn admin(mut self) -> Self {
+        self.admin = true;
+        self
+    }
+
+    pub fn guest(mut self) -> Self {
+        self.guest = true;
+        self
+    }
+
+    pub fn with_mfa(mut self) -> Self {
+        self.mfa = true;
+        self
+    }
+
+    pub fn password_expired(mut self) -> Self {
+        self.password_expired = true;
+        self
+    }
+
+    pub fn status(mut self, status: AccountStatus) -> Self {
+        self.status = Some(status);
+        self
+    }
+
+    pub async fn create(self) -> Result<TestUser, AuthError> {
+        let db = &self.ctx.db;
+
+        // Unique by default so tests sharing a context don't collide
+        let username = self
+            .username
+            .unwrap_or_else(|| format!("user_{}", &Uuid::new_v4().simple().to_string()[..12]));
+        let email = self.email.unwrap_or_else(|| format!("{}@example.com", username));
+
+        let new_user = NewUser {
+            id: Uuid::new_v4(),
+            username,
+            email,
+            password_hash: hash_password(&self.password)?,
+            is_email_verified: self.verified,
+            email_verification_token: None,
+            email_verification_sent_at: None,
+            is_admin: self.admin,
+            password_expires_at: self.password_expired.then(|| Utc::now() - Duration::days(1)),
+            is_guest: self.guest,
+            activation_pending: false,
+            email_display: None,
+        };
+        let event = user_created_event(&new_user, "test");
+        let user = db.create_user(new_user, event).await?;
+
+        let mut mfa_secret = None;
+        if self.mfa {
+            let (secret, _) = self.ctx.mfa_service().generate_totp_secret(&user.username)?;
+            db.update_mfa_secret(user.id, None, &secret).await?;
+            db.enable_mfa(user.id, None).await?;
+            mfa_secret = Some(secret);
+        }
+
+        if let Some(status) = self.status {
+            let event = status_changed_event(user.id, status, None);
+            db.set_account_status(user.id, None, status, "Test fixture", None, event).await?;
+        }
+
+        Ok(TestUser {
+            user: db.find_user_by_id(user.id).await?,
+            password: self.password,
+            mfa_secret,
+        })
+    }
+}
+
+/// A signed-in session without a login round trip
+#[derive(Debug)]
+pub struct TestSession {
+    pub session: Session,
+    pub access_token: String,
+    pub refresh_token: String,
+}
+
+impl TestSession {
+    /// Value for the `Authorization` header
+    pub fn bearer(&self) -> String {
+        format!("Bearer {}", self.access_token)
+    }
+}
+
+pub struct SessionFactory<'a> {
+    ctx: &'a TestContext,
+    user: &'a TestUser,
+    user_agent: Option<String>,
+    ip: Option<String>,
+    expires_in: Duration,
+    network: Option<(String, String)>,
+    scopes: Option<Vec<String>>,
+}
+
+impl<'a> SessionFactory<'a> {
+    pub fn new(ctx: &'a TestContext, user: &'a TestUser) -> Self {
+        SessionFactory {
+            ctx,
+            user,
+            user_agent: Some("betterauth-test".to_string()),
+            ip: Some("127.0.0.1".to_string()),
+            expires_in: Duration::seconds(ctx.config.jwt.refresh_token_expiry as i64),
+            network: None,
+            scopes: None,
+        }
+    }
+
+    pub fn user_agent(mut self, user_agent: impl Into<String>) -> Self {
+        self.user_agent = Some(user_agent.into());
+        self
+    }
+
+    pub fn ip(mut self, ip: impl Into<String>) -> Self {
+        self.ip = Some(ip.into());
+        self
+    }
+
+    /// Negative durations give an already expired session
+    pub fn expires_in(mut self, expires_in: Duration) -> Self {
+        self.expires_in = expires_in;
+        self
+    }
+
+    /// Bind the refresh token to a country and ASN
+    pub fn network(mut self, country: impl Into<String>, asn: impl Into<String>) -> Self {
+        self.network = Some((country.into(), asn.into()));
+        self
+    }
+
+    /// Narrow the access token to these permission scopes, like a token for
+    /// a client the user consented to
+    pub fn scopes(mut self, scopes: &[&str]) -> Self {
+        self.scopes = Some(scopes.iter().map(|s| s.to_string()).collect());
+        self
+    }
+
+    pub async fn create(self) -> Result<TestSession, AuthError> {
+        let refresh_token = Uuid::new_v4().to_string();
+        let mut session = NewSession::new(
+            self.user.id(),
+            refresh_token.clone(),
+            self.user_agent,
+            self.ip,
+            Utc::now() + self.expires_in,
+        );
+        // Signed in with a password, like the token from `issue_access_token`
+        session.amr = vec![AMR_PASSWORD.to_string()];
+        if let Some((country, asn)) = self.network {
+            session.network_country = Some(country);
+            session.network_asn = Some(asn);
+        }
+        let session = self.ctx.db.create_session(session).await?;
+        let mut access_token = self.ctx.auth_service.issue_access_token(&self.user.user, Some(session.id))?;
+        if let Some(scopes) = self.scopes {
+            let mut claims = decode_jwt::<JwtClaims>(&access_token)?;
+            claims.scopes = scopes;
+            access_token = create_jwt(&claims, &self.ctx.config.jwt.secret)?;
+        }
+
+        Ok(TestSession {
+            session,
+            access_token,
+            refresh_token,
+        })
+    }
+}
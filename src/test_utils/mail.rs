@@ -0,0 +1,114 @@
+use std::sync::Mutex;
+
+use futures::future::BoxFuture;
+
+use crate::errors::AuthError;
+use crate::services::email::{EmailTransport, OutgoingEmail};
+
+/// Captures email instead of sending it, so tests can follow the links inside
+#[derive(Default)]
+pub struct MockMailer {
+    sent: Mutex<Vec<OutgoingEmail>>,
+    fail_with: Mutex<Option<String>>,
+}
+
+impl MockMailer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Everything sent so far, oldest first
+    pub fn sent(&self) -> Vec<OutgoingEmail> {
+        self.sent.lock().unwrap().clone()
+    }
+
+    pub fn sent_to(&self, address: &str) -> Vec<OutgoingEmail> {
+        self.sent
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|email| email.to.eq_ignore_ascii_case(address))
+            .cloned()
+            .collect()
+    }
+
+    /// Most recent message to `address`; panics if there isn't one
+    pub fn last_to(&self, address: &str) -> OutgoingEmail {
+        self.sent_to(address)
+            .pop()
+            .unwrap_or_else(|| panic!("No email was sent to {}", address))
+    }
+
+    /// The `token` from the link in the most recent message to `address`,
+    /// e.g. to complete email verification or a password reset
+    pub fn token_for(&self, address: &str) -> String {
+        let email = self.last_to(address);
+        extract_token(&email.text_body)
+            .unwrap_or_else(|| panic!("Email to {} ({}) has no token link", address, email.subject))
+    }
+
+    /// The 6-digit code in the most recent message to `address`, e.g. to
+    /// finish an email code login
+    pub fn code_for(&self, address: &str) -> String {
+        let email = self.last_to(address);
+        email
+            .text_body
+            .lines()
+            .map(str::trim)
+            .find(|line| line.len() == 6 && line.chars().all(|c| c.is_ascii_digit()))
+            .unwrap_or_else(|| panic!("Email to {} ({}) has no code", address, email.subject))
+            .to_string()
+    }
+
+    pub fn assert_sent_to(&self, address: &str, count: usize) {
+        let sent = self.sent_to(address).len();
+        assert_eq!(sent, count, "expected {} emails to {}, found {}", count, address, sent);
+    }
+
+    /// Make every send fail, to test how flows handle an outage
+    pub fn fail_with(&self, error: impl Into<String>) {
+        *self.fail_with.lock().unwrap() = Some(error.into());
+    }
+
+    pub fn clear(&self) {
+        self.sent.lock().unwrap().clear();
+        *self.fail_with.lock().unwrap() = None;
+    }
+}
+
+impl EmailTransport for MockMailer {
+    fn send<'a>(&'a self, email: &'a OutgoingEmail) -> BoxFuture<'a, Result<(), AuthError>> {
+        Box::pin(async move {
+            if let Some(error) = self.fail_with.lock().unwrap().clone() {
+                return Err(AuthError::EmailError(error));
+            }
+
+            self.sent.lock().unwrap().push(email.clone());
+            Ok(())
+        })
+    }
+}
+
+/// Value of the first `token=` query parameter in `body`
+pub fn extract_token(body: &str) -> Option<String> {
+    let start = body.find("token=")? + "token=".len();
+    let token: String = body[start..]
+        .chars()
+        .take_while(|c| !c.is_whitespace() && !matches!(c, '&' | '"' | '<'))
+        .collect();
+
+    (!token.is_empty()).then_some(token)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extract_token() {
+        let body = "Verify your email\n\nhttps://example.com/verify-email?token=abc.def-123\n\nThanks";
+        assert_eq!(extract_token(body).as_deref(), Some("abc.def-123"));
+        assert_eq!(extract_token("https://example.com/?token=x&next=/").as_deref(), Some("x"));
+        assert_eq!(extract_token("no link here"), None);
+    }
+}
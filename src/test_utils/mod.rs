@@ -0,0 +1,156 @@
+//! Shared setup for integration tests here and in apps built on this crate.
+//! Compiled for this crate's own tests and behind the `test-utils` feature.
+
+pub mod client;
+pub mod factories;
+pub mod mail;
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use actix_http::Request;
+use actix_web::body::MessageBody;
+use actix_web::dev::{Service, ServiceResponse};
+use actix_web::middleware::Compress;
+use actix_web::{test, web, App};
+
+use crate::config::{Config, EmailDelivery};
+use crate::db::DatabaseConnection;
+use crate::errors::init_error_format;
+use crate::middleware::compression::CompressionPolicy;
+use crate::middleware::idempotency::IdempotencyStore;
+use crate::middleware::locale::LocaleMiddleware;
+use crate::middleware::rate_limiter::RateLimiter;
+use crate::middleware::region::RegionRouting;
+use crate::middleware::request_limits::RequestLimits;
+use crate::routes;
+use crate::services::auth::AuthService;
+use crate::services::mfa::MfaService;
+use crate::utils::i18n::Translator;
+
+pub use client::{
+    confirm_password_reset, login, mfa_login, post_json, register, request_password_reset, verify_email,
+    TestResponse,
+};
+pub use factories::{SessionFactory, TestSession, TestUser, UserFactory};
+pub use mail::MockMailer;
+
+/// Configuration for tests: defaults from the environment, with the checks
+/// that get in the way of scripted clients switched off
+pub fn test_config() -> Config {
+    let mut config = Config::from_env().expect("The test environment must be a valid configuration");
+    config.email.delivery = EmailDelivery::Log;
+    config.captcha.required = false;
+    config.tarpit.enabled = false;
+    config.brute_force.enabled = false;
+    config.rate_limit.requests = u32::MAX;
+    config.rate_limit.policies.clear();
+    config.dev.seed_enabled = false;
+    // No padding, and email stays synchronous so tests can read it as soon as a request returns
+    config.response_timing.min_response_ms = 0;
+    config.response_timing.jitter_ms = 0;
+    config.response_timing.background_email = false;
+    config
+}
+
+/// An in-memory database and the services on top of it. Email is captured
+/// in `mailer` rather than sent.
+pub struct TestContext {
+    pub config: Config,
+    pub db: Arc<DatabaseConnection>,
+    pub auth_service: web::Data<AuthService>,
+    pub translator: Arc<Translator>,
+    pub mailer: Arc<MockMailer>,
+}
+
+impl TestContext {
+    pub fn new() -> Self {
+        Self::with_config(test_config())
+    }
+
+    pub fn with_config(config: Config) -> Self {
+        // An in-memory database for each configured region, besides the home region's
+        let mut db = DatabaseConnection::new_memory();
+        if config.regions.is_enabled() {
+            let regions: HashMap<_, _> = config
+                .regions
+                .database_urls
+                .keys()
+                .map(|region| (region.clone(), DatabaseConnection::new_memory()))
+                .collect();
+            db = db.with_regions(&config.regions.home, regions);
+        }
+        let db = Arc::new(db);
+        let translator = Arc::new(Translator::new(&config.i18n).expect("Failed to load translations"));
+        let mailer = Arc::new(MockMailer::new());
+
+        let mut auth_service = AuthService::new(db.clone(), config.clone(), translator.clone());
+        auth_service.set_email_transport(mailer.clone());
+
+        TestContext {
+            config,
+            db,
+            auth_service: web::Data::new(auth_service),
+            translator,
+            mailer,
+        }
+    }
+
+    /// Same TOTP settings as the service, for generating codes in tests
+    pub fn mfa_service(&self) -> MfaService {
+        MfaService::new(self.config.totp.clone())
+    }
+
+    pub fn user(&self) -> UserFactory<'_> {
+        UserFactory::new(self)
+    }
+
+    pub fn session<'a>(&'a self, user: &'a TestUser) -> SessionFactory<'a> {
+        SessionFactory::new(self, user)
+    }
+
+    /// Every route, served in-process; drive it with `actix_web::test` or the
+    /// helpers in `client`
+    pub async fn spawn_app(
+        &self,
+    ) -> impl Service<Request, Response = ServiceResponse<impl MessageBody>, Error = actix_web::Error> {
+        init_error_format(&self.config.errors);
+        let request_limits = RequestLimits::new(&self.config.request_limits);
+        test::init_service(
+            App::new()
+                .app_data(self.auth_service.clone())
+                .app_data(web::Data::from(self.auth_service.user_cache()))
+                .app_data(web::Data::from(self.auth_service.token_revocations()))
+                .app_data(web::Data::from(self.auth_service.feature_flags()))
+                .app_data(web::Data::from(self.auth_service.dpop_verifier()))
+                .app_data(web::Data::new(IdempotencyStore::new(
+                    self.config.idempotency.ttl,
+                    self.config.idempotency.max_entries,
+                )))
+                .app_data(web::Data::from(self.translator.clone()))
+                .app_data(request_limits.json_config())
+                .wrap(RateLimiter::from_config(&self.config.rate_limit))
+                .wrap(request_limits)
+                .wrap(RegionRouting)
+                .wrap(LocaleMiddleware)
+                .wrap(CompressionPolicy::new(&self.config.compression))
+                .wrap(Compress::default())
+                .configure(routes::auth::configure)
+                .configure(routes::users::configure)
+                .configure(routes::organizations::configure)
+                .configure(routes::admin_ui::configure)
+                .configure(routes::admin::configure)
+                .configure(routes::media::configure)
+                .configure(routes::oauth::configure)
+                .configure(routes::pages::configure)
+                .configure(routes::dev::configure),
+        )
+        .await
+    }
+}
+
+impl Default for TestContext {
+    fn default() -> Self {
+        Self::new()
+    }
+}
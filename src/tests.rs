@@ -84,5 +84,6 @@ mod tests {
         let json_body: serde_json::Value = serde_json::from_slice(&body).unwrap();
         
         assert_eq!(json_body["status"], "ok");
+        assert_eq!(json_body["version"], env!("CARGO_PKG_VERSION"));
     }
 }
\ No newline at end of file
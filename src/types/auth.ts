@@ -15,10 +15,11 @@ export interface RegisterRequest {
   email: string;
   password: string;
   password_confirmation: string;
+  region?: string; // One of the deployment's regions; the home region when left out
 }
 
 export interface RegisterResponse {
-  user: User;
+  user?: User; // Omitted when the server gives generic registration responses
   message: string;
 }
 
@@ -26,6 +26,7 @@ export interface RiskFactor {
 
 export enum RiskAction {
   Allow = 'Allow',
+  RequireCaptcha = 'RequireCaptcha',
   RequireMfa = 'RequireMfa',
   Block = 'Block',
 }
@@ -0,0 +1,225 @@
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::errors::AuthError;
+
+/// What a one-time action link is for. A token only redeems for its own purpose,
+/// so a reset link can't be replayed as, say, an unsubscribe link.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ActionPurpose {
+    EmailVerification,
+    PasswordReset,
+    MagicLink,
+    EmailChange,
+    SessionApproval,
+    Unsubscribe,
+    Activation,
+    Reactivation,
+    AccountLock,
+}
+
+impl ActionPurpose {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ActionPurpose::EmailVerification => "email_verification",
+            ActionPurpose::PasswordReset => "password_reset",
+            ActionPurpose::MagicLink => "magic_link",
+            ActionPurpose::EmailChange => "email_change",
+            ActionPurpose::SessionApproval => "session_approval",
+            ActionPurpose::Unsubscribe => "unsubscribe",
+            ActionPurpose::Activation => "activation",
+            ActionPurpose::Reactivation => "reactivation",
+            ActionPurpose::AccountLock => "account_lock",
+        }
+    }
+}
+
+/// The signed payload of an action link
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ActionClaims {
+    pub purpose: ActionPurpose,
+    pub sub: Uuid, // The user the action is for
+    pub jti: Uuid, // Recorded when redeemed, so the link works once
+    pub exp: i64,  // Expiration time (as UTC timestamp)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub iat: Option<i64>, // Issue time; missing from tokens issued before it was recorded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub binding: Option<String>, // State the link stays valid for, e.g. the address being verified
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<serde_json::Value>, // Purpose-specific details, readable by the token holder
+}
+
+impl ActionClaims {
+    pub fn new(purpose: ActionPurpose, sub: Uuid, ttl: Duration) -> Self {
+        let now = Utc::now();
+        ActionClaims {
+            purpose,
+            sub,
+            jti: Uuid::new_v4(),
+            exp: (now + ttl).timestamp(),
+            iat: Some(now.timestamp()),
+            binding: None,
+            data: None,
+        }
+    }
+
+    pub fn with_binding(mut self, binding: impl Into<String>) -> Self {
+        self.binding = Some(binding.into());
+        self
+    }
+
+    pub fn with_data(mut self, data: serde_json::Value) -> Self {
+        self.data = Some(data);
+        self
+    }
+
+    pub fn expires_at(&self) -> DateTime<Utc> {
+        Utc.timestamp_opt(self.exp, 0).single().unwrap_or_else(Utc::now)
+    }
+
+    /// Whether the token is at most `max_age` old, whatever its own expiry
+    /// says. Without an issue time it mustn't outlive `max_age` from now.
+    pub fn issued_within(&self, max_age: Duration) -> bool {
+        let now = Utc::now().timestamp();
+        match self.iat {
+            Some(iat) => iat + max_age.num_seconds() >= now,
+            None => self.exp - max_age.num_seconds() <= now,
+        }
+    }
+
+    /// Whether the link was issued for `value`; unbound links match anything
+    pub fn is_bound_to(&self, value: &str) -> bool {
+        self.binding.as_deref().map_or(true, |binding| binding == value)
+    }
+}
+
+/// Signs and checks action tokens: `<base64url payload>.<base64url HMAC-SHA256>`.
+/// Verification alone doesn't make a token single-use; see `ActionTokens::redeem`.
+pub struct ActionTokenSigner {
+    key: Vec<u8>,
+}
+
+impl ActionTokenSigner {
+    pub fn new(secret: &str) -> Self {
+        // Derive a separate key so these tokens can't be confused with JWTs
+        // signed with the same secret
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())
+            .expect("HMAC accepts keys of any length");
+        mac.update(b"better-auth action token");
+
+        ActionTokenSigner {
+            key: mac.finalize().into_bytes().to_vec(),
+        }
+    }
+
+    pub fn sign(&self, claims: &ActionClaims) -> Result<String, AuthError> {
+        let payload = serde_json::to_vec(claims)
+            .map_err(|e| AuthError::InternalServerError(format!("Failed to encode action token: {}", e)))?;
+        let payload = URL_SAFE_NO_PAD.encode(payload);
+
+        let mut mac = self.mac();
+        mac.update(payload.as_bytes());
+        let signature = URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes());
+
+        Ok(format!("{}.{}", payload, signature))
+    }
+
+    /// The token's claims, if it is authentic, unexpired and for `purpose`
+    pub fn verify(&self, token: &str, purpose: ActionPurpose) -> Result<ActionClaims, AuthError> {
+        let (payload, signature) = token.trim().split_once('.').ok_or(AuthError::InvalidToken)?;
+        let signature = URL_SAFE_NO_PAD
+            .decode(signature)
+            .map_err(|_| AuthError::InvalidToken)?;
+
+        let mut mac = self.mac();
+        mac.update(payload.as_bytes());
+        mac.verify_slice(&signature).map_err(|_| AuthError::InvalidToken)?;
+
+        let claims: ActionClaims = URL_SAFE_NO_PAD
+            .decode(payload)
+            .ok()
+            .and_then(|payload| serde_json::from_slice(&payload).ok())
+            .ok_or(AuthError::InvalidToken)?;
+
+        if claims.purpose != purpose {
+            return Err(AuthError::InvalidToken);
+        }
+        if claims.exp < Utc::now().timestamp() {
+            return Err(AuthError::TokenExpired);
+        }
+
+        Ok(claims)
+    }
+
+    fn mac(&self) -> Hmac<Sha256> {
+        Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC accepts keys of any length")
+    }
+}
+
+/// A binding for state that shouldn't be readable from the token, such as a
+/// password hash: the link stops working once the state changes
+pub fn fingerprint(state: &str) -> String {
+    hex::encode(&Sha256::digest(state.as_bytes())[..16])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sign_and_verify() {
+        let signer = ActionTokenSigner::new("secret");
+        let claims = ActionClaims::new(ActionPurpose::PasswordReset, Uuid::new_v4(), Duration::hours(1))
+            .with_binding("alice@example.com");
+
+        let token = signer.sign(&claims).unwrap();
+        let verified = signer.verify(&token, ActionPurpose::PasswordReset).unwrap();
+
+        assert_eq!(verified, claims);
+        assert!(verified.is_bound_to("alice@example.com"));
+        assert!(!verified.is_bound_to("mallory@example.com"));
+    }
+
+    #[test]
+    fn test_verify_rejects_other_purpose_forgery_and_expiry() {
+        let signer = ActionTokenSigner::new("secret");
+        let claims = ActionClaims::new(ActionPurpose::Unsubscribe, Uuid::new_v4(), Duration::hours(1));
+        let token = signer.sign(&claims).unwrap();
+
+        assert!(matches!(
+            signer.verify(&token, ActionPurpose::MagicLink),
+            Err(AuthError::InvalidToken)
+        ));
+        assert!(matches!(
+            ActionTokenSigner::new("other").verify(&token, ActionPurpose::Unsubscribe),
+            Err(AuthError::InvalidToken)
+        ));
+
+        let expired = ActionClaims::new(ActionPurpose::Unsubscribe, Uuid::new_v4(), Duration::hours(-1));
+        assert!(matches!(
+            signer.verify(&signer.sign(&expired).unwrap(), ActionPurpose::Unsubscribe),
+            Err(AuthError::TokenExpired)
+        ));
+    }
+
+    #[test]
+    fn test_issued_within_caps_the_lifetime() {
+        let mut claims = ActionClaims::new(ActionPurpose::EmailVerification, Uuid::new_v4(), Duration::days(30));
+        assert!(claims.issued_within(Duration::days(7)));
+
+        claims.iat = Some((Utc::now() - Duration::days(8)).timestamp());
+        assert!(!claims.issued_within(Duration::days(7)));
+
+        // Older tokens without an issue time are judged by how long they have left
+        claims.iat = None;
+        assert!(!claims.issued_within(Duration::days(7)));
+        claims.exp = (Utc::now() + Duration::days(1)).timestamp();
+        assert!(claims.issued_within(Duration::days(7)));
+    }
+}
@@ -0,0 +1,79 @@
+use rand::{distributions::Alphanumeric, Rng};
+use sha2::{Digest, Sha256};
+
+// Marks a credential as an API key rather than a JWT, and makes leaked keys
+// easy to spot in logs and secret scanners
+pub const API_KEY_PREFIX: &str = "bak_";
+
+// Characters of the key kept in the clear, so users can tell their keys apart
+const DISPLAY_PREFIX_LEN: usize = 12;
+
+// Between a key's region and its secret, which never contains it
+const REGION_SEPARATOR: char = '_';
+
+/// A freshly generated key
+pub struct GeneratedApiKey {
+    pub key: String,
+    pub display_prefix: String,
+    pub hash: String,
+}
+
+/// A key for a user in `region`, if users are kept apart by region. The key
+/// says which, so requests carrying it can run there.
+pub fn generate(region: Option<&str>) -> GeneratedApiKey {
+    let secret: String = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(40)
+        .map(char::from)
+        .collect();
+    let key = match region {
+        Some(region) => format!("{}{}{}{}", API_KEY_PREFIX, region, REGION_SEPARATOR, secret),
+        None => format!("{}{}", API_KEY_PREFIX, secret),
+    };
+
+    GeneratedApiKey {
+        display_prefix: key[..DISPLAY_PREFIX_LEN].to_string(),
+        hash: hash(&key),
+        key,
+    }
+}
+
+/// What is stored and looked up. Keys are long and random, so a fast hash is enough.
+pub fn hash(key: &str) -> String {
+    hex::encode(Sha256::digest(key.as_bytes()))
+}
+
+pub fn is_api_key(token: &str) -> bool {
+    token.starts_with(API_KEY_PREFIX)
+}
+
+/// The region a key was made in, for keys made with one
+pub fn region(key: &str) -> Option<&str> {
+    key.strip_prefix(API_KEY_PREFIX)?
+        .rsplit_once(REGION_SEPARATOR)
+        .map(|(region, _)| region)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generate() {
+        let generated = generate(None);
+
+        assert!(is_api_key(&generated.key));
+        assert!(generated.key.starts_with(&generated.display_prefix));
+        assert_eq!(generated.hash, hash(&generated.key));
+        assert_ne!(generated.key, generate(None).key);
+        assert_eq!(region(&generated.key), None);
+    }
+
+    #[test]
+    fn test_keys_carry_their_region() {
+        let generated = generate(Some("eu"));
+
+        assert!(is_api_key(&generated.key));
+        assert_eq!(region(&generated.key), Some("eu"));
+    }
+}
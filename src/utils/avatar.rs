@@ -0,0 +1,66 @@
+use std::io::Cursor;
+
+use image::imageops::FilterType;
+use image::{ImageFormat, ImageReader};
+
+use crate::errors::AuthError;
+
+/// Uploads larger than this in either dimension are rejected before decoding
+pub const MAX_SOURCE_DIMENSION: u32 = 8192;
+
+/// Decode an uploaded image, crop it to a centred square of `size` pixels and
+/// re-encode it as PNG. Re-encoding also drops EXIF and other embedded metadata.
+pub fn process_avatar(data: &[u8], size: u32) -> Result<Vec<u8>, AuthError> {
+    let invalid = || {
+        AuthError::ValidationError("Avatar must be a PNG, JPEG, GIF or WebP image".into())
+    };
+
+    let reader = ImageReader::new(Cursor::new(data))
+        .with_guessed_format()
+        .map_err(|_| invalid())?;
+    match reader.format() {
+        Some(ImageFormat::Png | ImageFormat::Jpeg | ImageFormat::Gif | ImageFormat::WebP) => {}
+        _ => return Err(invalid()),
+    }
+
+    // Check the header before decoding so a tiny file can't expand into gigabytes
+    let (width, height) = reader.into_dimensions().map_err(|_| invalid())?;
+    if width > MAX_SOURCE_DIMENSION || height > MAX_SOURCE_DIMENSION {
+        return Err(AuthError::ValidationError(format!(
+            "Avatar must be at most {0}x{0} pixels",
+            MAX_SOURCE_DIMENSION
+        )));
+    }
+
+    let image = image::load_from_memory(data).map_err(|_| invalid())?;
+    let avatar = image.resize_to_fill(size, size, FilterType::Lanczos3);
+
+    let mut png = Vec::new();
+    avatar
+        .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+        .map_err(|e| AuthError::InternalServerError(format!("Failed to encode avatar: {}", e)))?;
+
+    Ok(png)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_process_avatar_crops_to_square() {
+        let mut source = Vec::new();
+        image::DynamicImage::new_rgb8(300, 200)
+            .write_to(&mut Cursor::new(&mut source), ImageFormat::Jpeg)
+            .unwrap();
+
+        let avatar = image::load_from_memory(&process_avatar(&source, 128).unwrap()).unwrap();
+
+        assert_eq!((avatar.width(), avatar.height()), (128, 128));
+    }
+
+    #[test]
+    fn test_process_avatar_rejects_non_images() {
+        assert!(process_avatar(b"<svg xmlns='http://www.w3.org/2000/svg'/>", 128).is_err());
+    }
+}
@@ -0,0 +1,93 @@
+use std::time::SystemTime;
+
+use actix_web::http::header::{self, HttpDate};
+use actix_web::http::StatusCode;
+use actix_web::web::Bytes;
+use actix_web::{HttpRequest, HttpResponse, HttpResponseBuilder};
+use chrono::{DateTime, Utc};
+use sha2::{Digest, Sha256};
+
+/// A strong `ETag` for a response body
+pub fn etag_of(body: &[u8]) -> String {
+    format!("\"{}\"", hex::encode(&Sha256::digest(body)[..16]))
+}
+
+/// Whether the client's cached copy is still current. `If-None-Match` wins
+/// when both validators are sent (RFC 9110 §13.2.2); `If-Modified-Since` is
+/// compared to the second, as HTTP dates are.
+pub fn is_fresh(req: &HttpRequest, etag: &str, last_modified: Option<DateTime<Utc>>) -> bool {
+    if let Some(value) = req.headers().get(header::IF_NONE_MATCH) {
+        // Weak comparison, as GET and HEAD use
+        return value
+            .to_str()
+            .unwrap_or_default()
+            .split(',')
+            .map(|tag| tag.trim().trim_start_matches("W/"))
+            .any(|tag| tag == "*" || tag == etag);
+    }
+
+    let since = req
+        .headers()
+        .get(header::IF_MODIFIED_SINCE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<HttpDate>().ok())
+        .map(|since| DateTime::<Utc>::from(SystemTime::from(since)));
+    match (last_modified, since) {
+        (Some(modified), Some(since)) => modified.timestamp() <= since.timestamp(),
+        _ => false,
+    }
+}
+
+/// Finish `builder` with `body`, adding its `ETag` and, when known, a
+/// `Last-Modified`; or with 304 Not Modified and no body when the request
+/// shows the client already has it. Set `Cache-Control` on `builder`.
+pub fn respond(
+    req: &HttpRequest,
+    builder: &mut HttpResponseBuilder,
+    body: impl Into<Bytes>,
+    last_modified: Option<DateTime<Utc>>,
+) -> HttpResponse {
+    let body = body.into();
+    let etag = etag_of(&body);
+    builder.insert_header((header::ETAG, etag.clone()));
+    if let Some(modified) = last_modified {
+        builder.insert_header((header::LAST_MODIFIED, HttpDate::from(SystemTime::from(modified))));
+    }
+
+    if is_fresh(req, &etag, last_modified) {
+        return builder.status(StatusCode::NOT_MODIFIED).finish();
+    }
+    builder.body(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::TestRequest;
+    use chrono::TimeZone;
+
+    use super::*;
+
+    #[test]
+    fn test_etags_and_dates_are_compared() {
+        let etag = etag_of(b"body");
+        let modified = Utc.with_ymd_and_hms(2024, 3, 1, 12, 0, 0).unwrap();
+        let with = |name: header::HeaderName, value: &str| TestRequest::default().insert_header((name, value)).to_http_request();
+
+        assert!(!is_fresh(&TestRequest::default().to_http_request(), &etag, Some(modified)));
+        assert!(is_fresh(&with(header::IF_NONE_MATCH, &etag), &etag, None));
+        assert!(is_fresh(&with(header::IF_NONE_MATCH, &format!("\"other\", W/{}", etag)), &etag, None));
+        assert!(is_fresh(&with(header::IF_NONE_MATCH, "*"), &etag, None));
+        assert!(!is_fresh(&with(header::IF_NONE_MATCH, &etag_of(b"changed")), &etag, None));
+
+        assert!(is_fresh(&with(header::IF_MODIFIED_SINCE, "Fri, 01 Mar 2024 12:00:00 GMT"), &etag, Some(modified)));
+        assert!(!is_fresh(&with(header::IF_MODIFIED_SINCE, "Fri, 01 Mar 2024 11:59:59 GMT"), &etag, Some(modified)));
+        assert!(!is_fresh(&with(header::IF_MODIFIED_SINCE, "Fri, 01 Mar 2024 12:00:00 GMT"), &etag, None));
+
+        // A changed ETag isn't overruled by an old enough date
+        let req = TestRequest::default()
+            .insert_header((header::IF_NONE_MATCH, etag_of(b"changed")))
+            .insert_header((header::IF_MODIFIED_SINCE, "Fri, 01 Mar 2024 12:00:00 GMT"))
+            .to_http_request();
+        assert!(!is_fresh(&req, &etag, Some(modified)));
+    }
+}
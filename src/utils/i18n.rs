@@ -0,0 +1,198 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use fluent_bundle::concurrent::FluentBundle;
+use fluent_bundle::{FluentArgs, FluentResource};
+use unic_langid::LanguageIdentifier;
+
+use crate::config::I18nConfig;
+use crate::errors::AuthError;
+
+// English messages are compiled in so there is always a fallback bundle
+const BUILTIN_EN: &str = include_str!("../../locales/en.ftl");
+const FALLBACK_LOCALE: &str = "en";
+
+/// Locale negotiated for the current request
+#[derive(Debug, Clone)]
+pub struct Locale(pub String);
+
+impl Default for Locale {
+    /// The built-in English bundle, for apps that register no translator
+    fn default() -> Self {
+        Locale(FALLBACK_LOCALE.to_string())
+    }
+}
+
+/// Fluent translation bundles keyed by lowercase language tag
+pub struct Translator {
+    bundles: HashMap<String, FluentBundle<FluentResource>>,
+    default_locale: String,
+}
+
+impl Translator {
+    /// Build a translator from the built-in English bundle plus any `<lang>.ftl`
+    /// files found in the configured locales directory
+    pub fn new(config: &I18nConfig) -> Result<Self, AuthError> {
+        let mut translator = Translator {
+            bundles: HashMap::new(),
+            default_locale: config.default_locale.to_lowercase(),
+        };
+
+        translator.add_bundle(FALLBACK_LOCALE, BUILTIN_EN.to_string())?;
+
+        if let Some(dir) = &config.locales_dir {
+            translator.load_dir(Path::new(dir))?;
+        }
+
+        Ok(translator)
+    }
+
+    /// Load (or replace) bundles from every `<lang>.ftl` file in a directory
+    pub fn load_dir(&mut self, dir: &Path) -> Result<(), AuthError> {
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("ftl") {
+                continue;
+            }
+
+            let lang = match path.file_stem().and_then(|s| s.to_str()) {
+                Some(lang) => lang.to_string(),
+                None => continue,
+            };
+
+            let source = fs::read_to_string(&path)?;
+            self.add_bundle(&lang, source)?;
+        }
+
+        Ok(())
+    }
+
+    fn add_bundle(&mut self, lang: &str, source: String) -> Result<(), AuthError> {
+        let langid: LanguageIdentifier = lang.parse().map_err(|_| {
+            AuthError::InternalServerError(format!("Invalid locale identifier: {}", lang))
+        })?;
+
+        let resource = FluentResource::try_new(source).map_err(|(_, errors)| {
+            AuthError::InternalServerError(format!(
+                "Failed to parse translations for {}: {:?}",
+                lang, errors
+            ))
+        })?;
+
+        let mut bundle = FluentBundle::new_concurrent(vec![langid]);
+        // Unicode isolation marks end up verbatim in JSON bodies and emails
+        bundle.set_use_isolating(false);
+        bundle.add_resource(resource).map_err(|errors| {
+            AuthError::InternalServerError(format!(
+                "Duplicate translations for {}: {:?}",
+                lang, errors
+            ))
+        })?;
+
+        self.bundles.insert(lang.to_lowercase(), bundle);
+        Ok(())
+    }
+
+    /// Pick the best supported locale from an `Accept-Language` header value
+    pub fn negotiate(&self, accept_language: Option<&str>) -> String {
+        let mut candidates: Vec<(String, f32)> = accept_language
+            .unwrap_or("")
+            .split(',')
+            .filter_map(|part| {
+                let mut pieces = part.trim().split(';');
+                let tag = pieces.next()?.trim().to_lowercase();
+                if tag.is_empty() || tag == "*" {
+                    return None;
+                }
+                let quality = pieces
+                    .find_map(|p| p.trim().strip_prefix("q="))
+                    .and_then(|q| q.parse::<f32>().ok())
+                    .unwrap_or(1.0);
+                Some((tag, quality))
+            })
+            .collect();
+
+        // Stable sort keeps header order for equal weights
+        candidates.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+
+        for (tag, _) in candidates {
+            if self.bundles.contains_key(&tag) {
+                return tag;
+            }
+            // "es-mx" falls back to "es"
+            if let Some(primary) = tag.split('-').next() {
+                if self.bundles.contains_key(primary) {
+                    return primary.to_string();
+                }
+            }
+        }
+
+        self.default_locale.clone()
+    }
+
+    /// Translate a message, falling back to English when the locale lacks it
+    pub fn translate(&self, locale: &str, key: &str, args: Option<&FluentArgs>) -> Option<String> {
+        [locale, self.default_locale.as_str(), FALLBACK_LOCALE]
+            .iter()
+            .find_map(|lang| self.format(lang, key, args))
+    }
+
+    /// Translate a message, returning the key itself if no bundle defines it
+    pub fn text(&self, locale: &str, key: &str, args: Option<&FluentArgs>) -> String {
+        self.translate(locale, key, args)
+            .unwrap_or_else(|| key.to_string())
+    }
+
+    fn format(&self, lang: &str, key: &str, args: Option<&FluentArgs>) -> Option<String> {
+        let bundle = self.bundles.get(lang)?;
+        let pattern = bundle.get_message(key)?.value()?;
+        let mut errors = Vec::new();
+        let value = bundle.format_pattern(pattern, args, &mut errors);
+
+        if !errors.is_empty() {
+            log::warn!("Translation errors for {} ({}): {:?}", key, lang, errors);
+        }
+
+        Some(value.into_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn translator() -> Translator {
+        let mut translator = Translator::new(&I18nConfig::default()).unwrap();
+        translator
+            .add_bundle("es", "error-user-not-found = Usuario no encontrado".to_string())
+            .unwrap();
+        translator
+    }
+
+    #[test]
+    fn test_negotiate_locale() {
+        let translator = translator();
+
+        assert_eq!(translator.negotiate(Some("es-MX,es;q=0.9,en;q=0.8")), "es");
+        assert_eq!(translator.negotiate(Some("fr-FR,en;q=0.5")), "en");
+        assert_eq!(translator.negotiate(Some("en;q=0.2,es;q=0.8")), "es");
+        assert_eq!(translator.negotiate(None), "en");
+    }
+
+    #[test]
+    fn test_translate_falls_back_to_english() {
+        let translator = translator();
+
+        assert_eq!(
+            translator.text("es", "error-user-not-found", None),
+            "Usuario no encontrado"
+        );
+        // Missing from the Spanish bundle, so English is used
+        assert_eq!(
+            translator.text("es", "error-invalid-token", None),
+            "Invalid token"
+        );
+        assert_eq!(translator.text("es", "missing-key", None), "missing-key");
+    }
+}
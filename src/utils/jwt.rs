@@ -3,14 +3,85 @@ use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation}
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
+use crate::config::{accepted_audiences, DEFAULT_JWT_ISSUER, DEVELOPMENT_SECRET_KEY};
 use crate::errors::AuthError;
+use crate::utils::dpop::Confirmation;
+
+/// What an access token may be used for. Besides `Full`, `Delegated` and
+/// `ApiKey`, each is an intermediate token for one step of a multi-step flow.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenScope {
+    #[default]
+    Full,
+    MfaPending,
+    EmailUnverified,
+    PasswordReset,
+    MfaEnrollment, // Only good for setting MFA up again after the account risk score reset it
+    AccountStatus, // Lets a suspended or banned user see why and appeal
+    PolicyAcceptance, // Continues a login held until the current policy is accepted
+    Guest, // An anonymous account; only good for routes that allow guests
+    Delegated, // An admin acting as the user; only good for routes that name a permission scope
+    ApiKey, // Never issued as a JWT; marks requests authenticated with an API key
+}
+
+/// Who is acting for the subject of a delegated token (RFC 8693 `act`)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Actor {
+    pub sub: Uuid,
+}
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct JwtClaims {
     pub sub: Uuid,      // Subject (user ID)
+    pub iss: String,    // Issuer, the deployment that signed the token
+    pub aud: String,    // Audience, the client app the token is for
     pub exp: usize,     // Expiration time (as UTC timestamp)
     pub iat: usize,     // Issued at (as UTC timestamp)
     pub is_admin: bool, // Is the user an admin
+    #[serde(default)]
+    pub token_version: i32, // User's token version when issued
+    #[serde(default)]
+    pub scope: TokenScope, // Tokens issued before scopes existed are full tokens
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auth_time: Option<usize>, // When the user last proved who they are, unset after a refresh
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub amr: Vec<String>, // Authentication methods the session was started with, e.g. "pwd", "mfa"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aal: Option<u8>, // Authenticator assurance level of `amr`; see `assurance_level`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub scopes: Vec<String>, // Permission scopes, e.g. "users:read"; see `utils::scopes`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cnf: Option<Confirmation>, // Set on DPoP-bound tokens, which are useless without the client's key
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sid: Option<Uuid>, // Session the token was issued with, whose activity it keeps alive
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jti: Option<Uuid>, // Unique per access token, so one can be revoked on its own
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub act: Option<Actor>, // Set on delegated tokens, naming the admin acting as `sub`
+}
+
+// Authentication method references (RFC 8176) recorded in `amr`
+pub const AMR_PASSWORD: &str = "pwd";
+pub const AMR_OTP: &str = "otp";
+pub const AMR_MFA: &str = "mfa";
+pub const AMR_HWK: &str = "hwk"; // Proof of a hardware-bound key, such as a security key or passkey
+pub const AMR_FEDERATED: &str = "fed"; // Signed in through an organization's identity provider
+pub const AMR_EMAIL: &str = "email"; // Signed in with a code sent to the account's email address
+pub const AMR_KBA: &str = "kba"; // Answered the account's security questions
+
+/// NIST SP 800-63B authenticator assurance level reached with these `amr`
+/// methods: 2 once two factors were proven, whether a password and a code or
+/// key, or a passkey that also checked the user's PIN or biometric; otherwise
+/// 1. `None` when nothing was proven, as for scoped and delegated tokens.
+pub fn assurance_level<S: AsRef<str>>(amr: &[S]) -> Option<u8> {
+    if amr.is_empty() {
+        None
+    } else if amr.iter().any(|m| m.as_ref() == AMR_MFA) {
+        Some(2)
+    } else {
+        Some(1)
+    }
 }
 
 /// Create a JWT token with the given claims
@@ -21,10 +92,33 @@ pub fn create_jwt<T: Serialize>(claims: &T, secret: &str) -> Result<String, Auth
         .map_err(|e| AuthError::InternalServerError(format!("Failed to create JWT: {}", e)))
 }
 
-/// Decode and validate a JWT token with provided secret
-pub fn decode_jwt_with_secret<T: for<'a> Deserialize<'a>>(token: &str, secret: &str) -> Result<T, AuthError> {
+/// Which tokens a deployment accepts: those from its issuer, for any of its audiences
+#[derive(Debug, Clone)]
+pub struct TokenAudience {
+    pub issuer: String,
+    pub audiences: Vec<String>,
+}
+
+impl TokenAudience {
+    pub fn new(issuer: impl Into<String>, audiences: &[String]) -> Self {
+        TokenAudience {
+            issuer: issuer.into(),
+            audiences: audiences.to_vec(),
+        }
+    }
+}
+
+/// Decode and validate a JWT token with provided secret, issuer and audiences
+pub fn decode_jwt_with_secret<T: for<'a> Deserialize<'a>>(
+    token: &str,
+    secret: &str,
+    expected: &TokenAudience,
+) -> Result<T, AuthError> {
     let decoding_key = DecodingKey::from_secret(secret.as_bytes());
-    let validation = Validation::default();
+    let mut validation = Validation::default();
+    validation.set_issuer(&[&expected.issuer]);
+    validation.set_audience(&expected.audiences);
+    validation.set_required_spec_claims(&["exp", "iss", "aud"]);
     
     let token_data = decode::<T>(token, &decoding_key, &validation)
         .map_err(|e| match e.kind() {
@@ -40,16 +134,20 @@ pub fn decode_jwt_with_secret<T: for<'a> Deserialize<'a>>(token: &str, secret: &
 pub fn decode_jwt<T: for<'a> Deserialize<'a>>(token: &str) -> Result<T, AuthError> {
     // In a real implementation, we would read the secret from a config
     // For now, we'll use a default development secret
-    let secret = std::env::var("SECRET_KEY")
-        .unwrap_or_else(|_| "development_secret_key_please_change_in_production".to_string());
+    let secret = std::env::var("SECRET_KEY").unwrap_or_else(|_| DEVELOPMENT_SECRET_KEY.to_string());
+    let issuer = std::env::var("JWT_ISSUER").unwrap_or_else(|_| DEFAULT_JWT_ISSUER.to_string());
     
-    decode_jwt_with_secret(token, &secret)
+    decode_jwt_with_secret(token, &secret, &TokenAudience::new(issuer, &accepted_audiences()))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn expected() -> TokenAudience {
+        TokenAudience::new("better-auth", &["web".to_string(), "mobile".to_string()])
+    }
+
     #[test]
     fn test_jwt_encode_decode() {
         let user_id = Uuid::new_v4();
@@ -57,19 +155,51 @@ mod tests {
         
         let claims = JwtClaims {
             sub: user_id,
+            iss: "better-auth".to_string(),
+            aud: "web".to_string(),
             exp: (Utc::now() + Duration::hours(1)).timestamp() as usize,
             iat: Utc::now().timestamp() as usize,
             is_admin: false,
+            token_version: 0,
+            scope: TokenScope::Full,
+            auth_time: None,
+            amr: Vec::new(),
+            aal: None,
+            scopes: Vec::new(),
+            cnf: None,
+            sid: None,
+            jti: None,
+            act: None,
         };
         
         // Create token
         let token = create_jwt(&claims, secret).unwrap();
         
         // Decode token
-        let decoded: JwtClaims = decode_jwt_with_secret(&token, secret).unwrap();
+        let decoded: JwtClaims = decode_jwt_with_secret(&token, secret, &expected()).unwrap();
         
         assert_eq!(decoded.sub, user_id);
         assert_eq!(decoded.is_admin, false);
+        assert_eq!(decoded.scope, TokenScope::Full);
+    }
+
+    #[test]
+    fn test_scope_defaults_to_full() {
+        let secret = "test_secret_key";
+        let claims = serde_json::json!({
+            "sub": Uuid::new_v4(),
+            "iss": "better-auth",
+            "aud": "web",
+            "exp": (Utc::now() + Duration::hours(1)).timestamp(),
+            "iat": Utc::now().timestamp(),
+            "is_admin": false,
+        });
+
+        let token = create_jwt(&claims, secret).unwrap();
+        let decoded: JwtClaims = decode_jwt_with_secret(&token, secret, &expected()).unwrap();
+
+        assert_eq!(decoded.scope, TokenScope::Full);
+        assert_eq!(decoded.token_version, 0);
     }
 
     #[test]
@@ -79,16 +209,72 @@ mod tests {
         
         let claims = JwtClaims {
             sub: user_id,
+            iss: "better-auth".to_string(),
+            aud: "web".to_string(),
             exp: (Utc::now() - Duration::hours(1)).timestamp() as usize, // Expired 1 hour ago
             iat: (Utc::now() - Duration::hours(2)).timestamp() as usize,
             is_admin: false,
+            token_version: 0,
+            scope: TokenScope::Full,
+            auth_time: None,
+            amr: Vec::new(),
+            aal: None,
+            scopes: Vec::new(),
+            cnf: None,
+            sid: None,
+            jti: None,
+            act: None,
         };
         
         // Create token
         let token = create_jwt(&claims, secret).unwrap();
         
         // Decode token - should fail with TokenExpired
-        let result: Result<JwtClaims, AuthError> = decode_jwt_with_secret(&token, secret);
+        let result: Result<JwtClaims, AuthError> = decode_jwt_with_secret(&token, secret, &expected());
         assert!(matches!(result, Err(AuthError::TokenExpired)));
     }
+
+    #[test]
+    fn test_rejects_other_audience_and_issuer() {
+        let secret = "test_secret_key";
+        let claims = |iss: &str, aud: &str| JwtClaims {
+            sub: Uuid::new_v4(),
+            iss: iss.to_string(),
+            aud: aud.to_string(),
+            exp: (Utc::now() + Duration::hours(1)).timestamp() as usize,
+            iat: Utc::now().timestamp() as usize,
+            is_admin: false,
+            token_version: 0,
+            scope: TokenScope::Full,
+            auth_time: None,
+            amr: Vec::new(),
+            aal: None,
+            scopes: Vec::new(),
+            cnf: None,
+            sid: None,
+            jti: None,
+            act: None,
+        };
+
+        // Any of the accepted audiences is fine
+        let mobile = create_jwt(&claims("better-auth", "mobile"), secret).unwrap();
+        assert!(decode_jwt_with_secret::<JwtClaims>(&mobile, secret, &expected()).is_ok());
+
+        let admin_console = create_jwt(&claims("better-auth", "admin-console"), secret).unwrap();
+        let result: Result<JwtClaims, AuthError> = decode_jwt_with_secret(&admin_console, secret, &expected());
+        assert!(matches!(result, Err(AuthError::InvalidToken)));
+
+        let other_issuer = create_jwt(&claims("someone-else", "web"), secret).unwrap();
+        let result: Result<JwtClaims, AuthError> = decode_jwt_with_secret(&other_issuer, secret, &expected());
+        assert!(matches!(result, Err(AuthError::InvalidToken)));
+    }
+
+    #[test]
+    fn test_assurance_level_counts_factors() {
+        assert_eq!(assurance_level::<&str>(&[]), None);
+        assert_eq!(assurance_level(&[AMR_PASSWORD]), Some(1));
+        assert_eq!(assurance_level(&[AMR_HWK]), Some(1));
+        assert_eq!(assurance_level(&[AMR_PASSWORD, AMR_OTP, AMR_MFA]), Some(2));
+        assert_eq!(assurance_level(&[AMR_HWK, AMR_MFA]), Some(2));
+    }
 }
@@ -0,0 +1,161 @@
+use url::Url;
+
+use crate::config::{FrontendConfig, LinkPurpose};
+
+// Schemes that run or read something where the link is opened, never allowed
+// even when listed in `FRONTEND_URL_SCHEMES`
+const FORBIDDEN_SCHEMES: [&str; 4] = ["javascript", "data", "file", "vbscript"];
+
+/// The link for an emailed token, e.g. `https://example.com/verify-email?token=...`.
+/// Recipients at a domain in `tenant_base_urls` get their organization's
+/// frontend; a purpose configured with a whole URL goes there for everyone.
+pub fn link(config: &FrontendConfig, purpose: LinkPurpose, recipient: &str, token: &str) -> String {
+    let mut url = Url::parse(&target(config, purpose, recipient)).expect("frontend URLs are checked at startup");
+    url.query_pairs_mut().append_pair("token", token);
+    url.to_string()
+}
+
+fn target(config: &FrontendConfig, purpose: LinkPurpose, recipient: &str) -> String {
+    let base = recipient
+        .rsplit_once('@')
+        .and_then(|(_, domain)| config.tenant_base_urls.get(&domain.trim().to_lowercase()))
+        .unwrap_or(&config.base_url);
+
+    match config.links.get(&purpose) {
+        Some(url) if Url::parse(url).is_ok() => url.clone(),
+        Some(path) => join(base, path),
+        None => join(base, purpose.default_path()),
+    }
+}
+
+fn join(base: &str, path: &str) -> String {
+    format!("{}/{}", base.trim_end_matches('/'), path.trim_start_matches('/'))
+}
+
+/// Check every URL a link can be built from, so a typo fails at startup
+/// instead of in someone's inbox
+pub fn validate(config: &FrontendConfig) -> Result<(), String> {
+    for scheme in &config.custom_schemes {
+        let well_formed = scheme.starts_with(|c: char| c.is_ascii_lowercase())
+            && scheme
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || matches!(c, '+' | '-' | '.'));
+        if !well_formed || FORBIDDEN_SCHEMES.contains(&scheme.as_str()) {
+            return Err(format!("{} can't be used as a link scheme", scheme));
+        }
+    }
+
+    let bases = std::iter::once(&config.base_url).chain(config.tenant_base_urls.values());
+    for base in bases.clone() {
+        check_url(config, base)?;
+    }
+    for domain in config.tenant_base_urls.keys() {
+        if domain.is_empty() || !domain.contains('.') {
+            return Err(format!("{} is not an email domain", domain));
+        }
+    }
+
+    for purpose in LinkPurpose::ALL {
+        match config.links.get(&purpose) {
+            Some(url) if Url::parse(url).is_ok() => check_url(config, url)?,
+            path => {
+                let path = path.map_or(purpose.default_path(), String::as_str);
+                for base in bases.clone() {
+                    check_url(config, &join(base, path))?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn check_url(config: &FrontendConfig, value: &str) -> Result<(), String> {
+    let url = Url::parse(value).map_err(|e| format!("{} is not a valid URL: {}", value, e))?;
+    let allowed = match url.scheme() {
+        "http" | "https" => url.has_host(),
+        scheme => config.custom_schemes.iter().any(|allowed| allowed == scheme),
+    };
+    if !allowed {
+        return Err(format!(
+            "{} must be an http(s) URL or use a scheme listed in FRONTEND_URL_SCHEMES",
+            value
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn config() -> FrontendConfig {
+        FrontendConfig {
+            base_url: "https://app.example.com/".to_string(),
+            links: HashMap::new(),
+            tenant_base_urls: HashMap::new(),
+            custom_schemes: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_default_links() {
+        let config = config();
+        assert!(validate(&config).is_ok());
+
+        assert_eq!(
+            link(&config, LinkPurpose::VerifyEmail, "alice@example.com", "abc"),
+            "https://app.example.com/verify-email?token=abc"
+        );
+        // Tokens are escaped
+        assert_eq!(
+            link(&config, LinkPurpose::ResetPassword, "alice@example.com", "a+b/c="),
+            "https://app.example.com/reset-password?token=a%2Bb%2Fc%3D"
+        );
+    }
+
+    #[test]
+    fn test_configured_paths_deep_links_and_tenants() {
+        let mut config = config();
+        config.custom_schemes = vec!["myapp".to_string()];
+        config.links.insert(LinkPurpose::ResetPassword, "/account/reset?source=email".to_string());
+        config.links.insert(LinkPurpose::VerifyEmail, "myapp://verify-email".to_string());
+        config
+            .tenant_base_urls
+            .insert("acme.com".to_string(), "https://login.acme.com".to_string());
+        assert!(validate(&config).is_ok());
+
+        assert_eq!(
+            link(&config, LinkPurpose::ResetPassword, "bob@example.com", "abc"),
+            "https://app.example.com/account/reset?source=email&token=abc"
+        );
+        assert_eq!(
+            link(&config, LinkPurpose::ResetPassword, "bob@ACME.com", "abc"),
+            "https://login.acme.com/account/reset?source=email&token=abc"
+        );
+        assert_eq!(
+            link(&config, LinkPurpose::VerifyEmail, "bob@acme.com", "abc"),
+            "myapp://verify-email?token=abc"
+        );
+    }
+
+    #[test]
+    fn test_invalid_urls_are_refused() {
+        let mut unlisted_scheme = config();
+        unlisted_scheme.links.insert(LinkPurpose::Activate, "myapp://activate".to_string());
+        assert!(validate(&unlisted_scheme).is_err());
+
+        let mut relative_base = config();
+        relative_base.base_url = "/app".to_string();
+        assert!(validate(&relative_base).is_err());
+
+        let mut script = config();
+        script.custom_schemes = vec!["javascript".to_string()];
+        assert!(validate(&script).is_err());
+
+        let mut tenant = config();
+        tenant.tenant_base_urls.insert("acme".to_string(), "https://login.acme.com".to_string());
+        assert!(validate(&tenant).is_err());
+    }
+}
@@ -1,3 +1,16 @@
+pub mod action_token;
+pub mod api_key;
+pub mod avatar;
+pub mod dpop;
+pub mod http_cache;
+pub mod i18n;
 pub mod jwt;
+pub mod links;
+pub mod negotiate;
+pub mod network;
 pub mod password;
+pub mod recovery_sheet;
+pub mod scopes;
+pub mod secret;
+pub mod user_agent;
 pub mod validation;
@@ -0,0 +1,111 @@
+use actix_web::http::header::{self, Header};
+use actix_web::{HttpRequest, HttpResponse, HttpResponseBuilder};
+use serde::Serialize;
+
+use crate::errors::AuthError;
+
+pub const MSGPACK: &str = "application/msgpack";
+
+// Also seen from older clients
+const MSGPACK_ALIASES: [&str; 2] = ["application/x-msgpack", "application/vnd.msgpack"];
+
+/// How a response body is encoded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    MessagePack,
+}
+
+/// The format the `Accept` header ranks highest. JSON unless MessagePack is
+/// asked for explicitly, so existing clients see no change.
+pub fn preferred_format(req: &HttpRequest) -> Format {
+    let accept = match header::Accept::parse(req) {
+        Ok(accept) => accept,
+        Err(_) => return Format::Json,
+    };
+
+    for mime in accept.ranked() {
+        let essence = mime.essence_str();
+        if essence == MSGPACK || MSGPACK_ALIASES.contains(&essence) {
+            return Format::MessagePack;
+        }
+        if matches!(essence, "application/json" | "application/*" | "*/*") {
+            return Format::Json;
+        }
+    }
+    Format::Json
+}
+
+/// Finish `builder` with `body` in the format the caller prefers. MessagePack
+/// keeps field names, so it decodes to the same shape as the JSON.
+pub fn respond<T: Serialize>(
+    req: &HttpRequest,
+    builder: &mut HttpResponseBuilder,
+    body: &T,
+) -> Result<HttpResponse, AuthError> {
+    // Caches must not hand a JSON client the MessagePack body, or the reverse
+    builder.insert_header((header::VARY, "Accept"));
+
+    match preferred_format(req) {
+        Format::Json => Ok(builder.json(body)),
+        Format::MessagePack => {
+            let bytes = rmp_serde::to_vec_named(body)
+                .map_err(|e| AuthError::InternalServerError(format!("Failed to encode response: {}", e)))?;
+            Ok(builder.content_type(MSGPACK).body(bytes))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::TestRequest;
+
+    use super::*;
+
+    fn format_for(accept: &str) -> Format {
+        preferred_format(&TestRequest::default().insert_header((header::ACCEPT, accept)).to_http_request())
+    }
+
+    #[test]
+    fn test_json_unless_msgpack_is_preferred() {
+        assert_eq!(preferred_format(&TestRequest::default().to_http_request()), Format::Json);
+        assert_eq!(format_for("*/*"), Format::Json);
+        assert_eq!(format_for("application/json"), Format::Json);
+        assert_eq!(format_for("text/html"), Format::Json);
+
+        assert_eq!(format_for("application/msgpack"), Format::MessagePack);
+        assert_eq!(format_for("application/x-msgpack"), Format::MessagePack);
+        assert_eq!(format_for("application/json;q=0.5, application/msgpack"), Format::MessagePack);
+        assert_eq!(format_for("application/msgpack;q=0.5, application/json"), Format::Json);
+    }
+
+    #[actix_web::test]
+    async fn test_msgpack_body_keeps_field_names() {
+        #[derive(Serialize)]
+        struct Token {
+            access_token: String,
+            expires_in: u64,
+        }
+
+        let req = TestRequest::default()
+            .insert_header((header::ACCEPT, MSGPACK))
+            .to_http_request();
+        let response = respond(
+            &req,
+            &mut HttpResponse::Ok(),
+            &Token {
+                access_token: "abc".to_string(),
+                expires_in: 900,
+            },
+        )
+        .unwrap();
+
+        assert_eq!(response.headers().get(header::CONTENT_TYPE).unwrap(), MSGPACK);
+        assert_eq!(response.headers().get(header::VARY).unwrap(), "Accept");
+
+        let body = actix_web::body::to_bytes(response.into_body()).await.unwrap();
+        let decoded: serde_json::Value = rmp_serde::from_slice(&body).unwrap();
+        assert_eq!(decoded["access_token"], "abc");
+        assert_eq!(decoded["expires_in"], 900);
+    }
+}
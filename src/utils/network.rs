@@ -0,0 +1,128 @@
+use actix_web::http::header::HeaderMap;
+
+use crate::config::{RefreshBinding, RefreshBindingConfig};
+use crate::risk_scoring::GeoLocation;
+
+/// The coarse network a request came from, as reported by the proxy in
+/// front of the service. Each part is `None` when its header is missing.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct NetworkFingerprint {
+    pub country: Option<String>,       // ISO 3166 alpha-2, upper case
+    pub asn: Option<String>,           // Digits only, e.g. "13335"
+    pub location: Option<GeoLocation>, // Only with both coordinates
+}
+
+impl NetworkFingerprint {
+    pub fn from_headers(headers: &HeaderMap, config: &RefreshBindingConfig) -> Self {
+        let header = |name: &str| {
+            headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::trim)
+                .filter(|value| !value.is_empty())
+        };
+
+        NetworkFingerprint {
+            country: header(&config.country_header).map(|c| c.to_ascii_uppercase()),
+            asn: header(&config.asn_header).map(|asn| {
+                // "AS13335" and "13335" are the same network
+                let digits = asn.strip_prefix("AS").or_else(|| asn.strip_prefix("as")).unwrap_or(asn);
+                digits.to_string()
+            }),
+            location: coordinate(header(&config.latitude_header), 90.0)
+                .zip(coordinate(header(&config.longitude_header), 180.0))
+                .map(|(latitude, longitude)| GeoLocation {
+                    latitude,
+                    longitude,
+                    country: header(&config.country_header).unwrap_or_default().to_ascii_uppercase(),
+                    city: header(&config.city_header).unwrap_or_default().to_string(),
+                }),
+        }
+    }
+
+    /// Whether this request's network differs from the one a session is bound
+    /// to. An unbound part never differs; a bound part the request can't
+    /// report does, so stripping the headers doesn't get around the binding.
+    pub fn differs_from(&self, country: Option<&str>, asn: Option<&str>, binding: RefreshBinding) -> bool {
+        let differs = |bound: Option<&str>, current: Option<&str>| bound.map_or(false, |bound| current != Some(bound));
+
+        match binding {
+            RefreshBinding::Off => false,
+            RefreshBinding::Country => differs(country, self.country.as_deref()),
+            RefreshBinding::Asn => differs(asn, self.asn.as_deref()),
+            RefreshBinding::CountryAndAsn => {
+                differs(country, self.country.as_deref()) || differs(asn, self.asn.as_deref())
+            }
+        }
+    }
+}
+
+// A latitude or longitude in decimal degrees, within `-limit..=limit`
+fn coordinate(value: Option<&str>, limit: f64) -> Option<f64> {
+    value
+        .and_then(|value| value.parse::<f64>().ok())
+        .filter(|degrees| degrees.abs() <= limit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::NetworkMismatchPolicy;
+    use actix_web::http::header::{HeaderName, HeaderValue};
+
+    fn config() -> RefreshBindingConfig {
+        RefreshBindingConfig {
+            binding: RefreshBinding::CountryAndAsn,
+            on_mismatch: NetworkMismatchPolicy::StepUp,
+            country_header: "CF-IPCountry".to_string(),
+            asn_header: "X-Client-ASN".to_string(),
+            latitude_header: "CF-IPLatitude".to_string(),
+            longitude_header: "CF-IPLongitude".to_string(),
+            city_header: "CF-IPCity".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_from_headers_normalizes() {
+        let mut headers = HeaderMap::new();
+        headers.insert(HeaderName::from_static("cf-ipcountry"), HeaderValue::from_static("de"));
+        headers.insert(HeaderName::from_static("x-client-asn"), HeaderValue::from_static("AS3320"));
+
+        let network = NetworkFingerprint::from_headers(&headers, &config());
+        assert_eq!(network.country.as_deref(), Some("DE"));
+        assert_eq!(network.asn.as_deref(), Some("3320"));
+        assert_eq!(network.location, None);
+
+        headers.insert(HeaderName::from_static("cf-iplatitude"), HeaderValue::from_static("52.52"));
+        headers.insert(HeaderName::from_static("cf-iplongitude"), HeaderValue::from_static("13.40"));
+        headers.insert(HeaderName::from_static("cf-ipcity"), HeaderValue::from_static("Berlin"));
+        let location = NetworkFingerprint::from_headers(&headers, &config()).location.unwrap();
+        assert_eq!((location.latitude, location.longitude), (52.52, 13.40));
+        assert_eq!((location.country.as_str(), location.city.as_str()), ("DE", "Berlin"));
+
+        // Coordinates off the globe are dropped rather than scored
+        headers.insert(HeaderName::from_static("cf-iplatitude"), HeaderValue::from_static("152.52"));
+        assert_eq!(NetworkFingerprint::from_headers(&headers, &config()).location, None);
+
+        assert_eq!(NetworkFingerprint::from_headers(&HeaderMap::new(), &config()), NetworkFingerprint::default());
+    }
+
+    #[test]
+    fn test_differs_from() {
+        let network = NetworkFingerprint {
+            country: Some("DE".to_string()),
+            asn: Some("3320".to_string()),
+            location: None,
+        };
+
+        assert!(!network.differs_from(Some("DE"), Some("3320"), RefreshBinding::CountryAndAsn));
+        assert!(network.differs_from(Some("DE"), Some("7922"), RefreshBinding::CountryAndAsn));
+        // Only the bound part counts
+        assert!(!network.differs_from(Some("DE"), Some("7922"), RefreshBinding::Country));
+        assert!(!network.differs_from(Some("US"), None, RefreshBinding::Off));
+        // Sessions from before binding was enabled aren't bound to anything
+        assert!(!network.differs_from(None, None, RefreshBinding::CountryAndAsn));
+        // A missing header doesn't match a bound value
+        assert!(NetworkFingerprint::default().differs_from(Some("DE"), None, RefreshBinding::Country));
+    }
+}
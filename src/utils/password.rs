@@ -3,8 +3,16 @@ use argon2::{
     Argon2,
 };
 
+use lazy_static::lazy_static;
+
 use crate::errors::AuthError;
 
+lazy_static! {
+    // Stands in for the stored hash when there's no account to check
+    static ref DUMMY_HASH: String =
+        hash_password("not-a-real-password").expect("Failed to hash dummy password");
+}
+
 /// Hash a password using Argon2id
 pub fn hash_password(password: &str) -> Result<String, AuthError> {
     let salt = SaltString::generate(&mut OsRng);
@@ -30,6 +38,12 @@ pub fn verify_password(password: &str, password_hash: &str) -> Result<bool, Auth
     Ok(is_valid)
 }
 
+/// Verify `password` against a throwaway hash and discard the result, so an
+/// unknown account costs as much time as a wrong password
+pub fn verify_dummy_password(password: &str) {
+    let _ = verify_password(password, &DUMMY_HASH);
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -0,0 +1,166 @@
+use chrono::{DateTime, Utc};
+
+// A4 in points, with the text starting an inch in from the top left
+const PAGE_WIDTH: u32 = 595;
+const PAGE_HEIGHT: u32 = 842;
+const MARGIN: u32 = 72;
+
+/// A freshly generated set of recovery codes, for saving or printing. Only
+/// ever built when the codes are generated; they can't be fetched again.
+pub struct RecoverySheet {
+    pub issuer: String,  // Shown as the title, e.g. "Better Auth recovery codes"
+    pub account: String, // Username the codes belong to
+    pub generated_at: DateTime<Utc>,
+    pub codes: Vec<String>,
+}
+
+impl RecoverySheet {
+    fn heading(&self) -> [String; 6] {
+        [
+            format!("{} recovery codes", self.issuer),
+            format!("Account: {}", self.account),
+            format!("Generated: {}", self.generated_at.format("%Y-%m-%d %H:%M UTC")),
+            String::new(),
+            "Each code signs you in once if you can't use your authenticator.".to_string(),
+            "Keep them somewhere safe. Generating a new set replaces these.".to_string(),
+        ]
+    }
+
+    fn numbered_codes(&self) -> impl Iterator<Item = String> + '_ {
+        self.codes
+            .iter()
+            .enumerate()
+            .map(|(i, code)| format!("{:>2}. {}", i + 1, code))
+    }
+
+    /// Plain text, one code per line
+    pub fn to_text(&self) -> String {
+        let mut text = self.heading().join("\n");
+        text.push_str("\n\n");
+        for line in self.numbered_codes() {
+            text.push_str(&line);
+            text.push('\n');
+        }
+        text
+    }
+
+    /// A one-page PDF, with the codes in a fixed-width font
+    pub fn to_pdf(&self) -> Vec<u8> {
+        let mut content = String::new();
+        let mut y = PAGE_HEIGHT - MARGIN;
+        for (i, line) in self.heading().iter().enumerate() {
+            let size = if i == 0 { 16 } else { 11 };
+            content.push_str(&text_op("F1", size, y, line));
+            y -= if i == 0 { 24 } else { 16 };
+        }
+        y -= 16;
+        for line in self.numbered_codes() {
+            content.push_str(&text_op("F2", 14, y, &line));
+            y -= 22;
+        }
+
+        let objects = [
+            "<< /Type /Catalog /Pages 2 0 R >>".to_string(),
+            "<< /Type /Pages /Kids [3 0 R] /Count 1 >>".to_string(),
+            format!(
+                "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {} {}] \
+                 /Resources << /Font << /F1 4 0 R /F2 5 0 R >> >> /Contents 6 0 R >>",
+                PAGE_WIDTH, PAGE_HEIGHT
+            ),
+            "<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica /Encoding /WinAnsiEncoding >>".to_string(),
+            "<< /Type /Font /Subtype /Type1 /BaseFont /Courier /Encoding /WinAnsiEncoding >>".to_string(),
+            format!("<< /Length {} >>\nstream\n{}endstream", content.len(), content),
+        ];
+
+        let mut pdf = b"%PDF-1.4\n".to_vec();
+        let mut offsets = Vec::with_capacity(objects.len());
+        for (i, object) in objects.iter().enumerate() {
+            offsets.push(pdf.len());
+            pdf.extend_from_slice(format!("{} 0 obj\n{}\nendobj\n", i + 1, object).as_bytes());
+        }
+
+        // Each cross-reference entry is exactly 20 bytes, end of line included
+        let xref = pdf.len();
+        let mut table = format!("xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1);
+        for offset in offsets {
+            table.push_str(&format!("{:010} 00000 n \n", offset));
+        }
+        table.push_str(&format!(
+            "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{}\n%%EOF\n",
+            objects.len() + 1,
+            xref
+        ));
+        pdf.extend_from_slice(table.as_bytes());
+        pdf
+    }
+}
+
+fn text_op(font: &str, size: u32, y: u32, text: &str) -> String {
+    format!("BT /{} {} Tf {} {} Td ({}) Tj ET\n", font, size, MARGIN, y, pdf_string(text))
+}
+
+// Escaped for a PDF literal string. Latin-1 characters are written as octal
+// escapes, which WinAnsiEncoding maps to the same characters; anything else
+// can't be shown by the standard fonts and becomes "?".
+fn pdf_string(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' | '(' | ')' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            ' '..='~' => escaped.push(c),
+            '\u{a0}'..='\u{ff}' => escaped.push_str(&format!("\\{:03o}", c as u32)),
+            _ => escaped.push('?'),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn sheet() -> RecoverySheet {
+        RecoverySheet {
+            issuer: "Better Auth".to_string(),
+            account: "alice (work)".to_string(),
+            generated_at: Utc.with_ymd_and_hms(2024, 3, 1, 9, 30, 0).unwrap(),
+            codes: vec!["abcd-efgh".to_string(), "ijkl-mnop".to_string()],
+        }
+    }
+
+    #[test]
+    fn test_text_lists_every_code() {
+        let text = sheet().to_text();
+        assert!(text.starts_with("Better Auth recovery codes\nAccount: alice (work)\nGenerated: 2024-03-01 09:30 UTC\n"));
+        assert!(text.ends_with(" 1. abcd-efgh\n 2. ijkl-mnop\n"));
+    }
+
+    #[test]
+    fn test_pdf_cross_references_point_at_objects() {
+        let pdf = sheet().to_pdf();
+        let text = String::from_utf8(pdf.clone()).unwrap();
+        assert!(text.starts_with("%PDF-1.4\n"));
+        assert!(text.ends_with("%%EOF\n"));
+        assert!(text.contains("( 1. abcd-efgh) Tj"));
+        assert!(text.contains("(Account: alice \\(work\\)) Tj"));
+
+        let startxref: usize = text.lines().rev().nth(1).unwrap().parse().unwrap();
+        assert!(text[startxref..].starts_with("xref\n"));
+        for (i, entry) in text[startxref..].lines().skip(3).take(6).enumerate() {
+            let offset: usize = entry[..10].parse().unwrap();
+            assert!(text[offset..].starts_with(&format!("{} 0 obj\n", i + 1)));
+        }
+    }
+
+    #[test]
+    fn test_pdf_strings_are_escaped() {
+        assert_eq!(pdf_string(r"a\b(c)"), r"a\\b\(c\)");
+        assert_eq!(pdf_string("José"), "Jos\\351");
+        assert_eq!(pdf_string("名前"), "??");
+    }
+}
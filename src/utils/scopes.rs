@@ -0,0 +1,124 @@
+use crate::errors::AuthError;
+
+// Permission scopes carried by access tokens and API keys, as `<resource>:<action>`.
+// `<resource>:write` includes `<resource>:read`, and `<resource>:*` grants every
+// action on the resource.
+pub const USERS_READ: &str = "users:read";
+pub const USERS_WRITE: &str = "users:write";
+pub const SESSIONS_READ: &str = "sessions:read";
+pub const SESSIONS_WRITE: &str = "sessions:write";
+pub const ORGANIZATIONS_READ: &str = "organizations:read";
+pub const ORGANIZATIONS_WRITE: &str = "organizations:write";
+pub const ADMIN_READ: &str = "admin:read";
+pub const ADMIN_WRITE: &str = "admin:write";
+pub const ADMIN_ALL: &str = "admin:*";
+
+const RESOURCES: &[&str] = &["users", "sessions", "organizations", "admin"];
+const ACTIONS: &[&str] = &["read", "write", "*"];
+
+// Resources only admins may be granted
+const ADMIN_RESOURCES: &[&str] = &["admin"];
+
+/// Whether `scope` is part of the taxonomy
+pub fn is_valid(scope: &str) -> bool {
+    match scope.split_once(':') {
+        Some((resource, action)) => RESOURCES.contains(&resource) && ACTIONS.contains(&action),
+        None => false,
+    }
+}
+
+/// Whether any of `granted` allows `required`
+pub fn grants(granted: &[String], required: &str) -> bool {
+    let Some((resource, action)) = required.split_once(':') else {
+        return false;
+    };
+
+    granted.iter().any(|scope| match scope.split_once(':') {
+        Some((r, a)) if r == resource => a == "*" || a == action || (a == "write" && action == "read"),
+        _ => false,
+    })
+}
+
+/// Everything a signed-in user's own session may do
+pub fn default_scopes(is_admin: bool) -> Vec<String> {
+    RESOURCES
+        .iter()
+        .filter(|resource| is_admin || !ADMIN_RESOURCES.contains(resource))
+        .map(|resource| format!("{}:*", resource))
+        .collect()
+}
+
+/// The first of the user's own scopes that `granted` falls short of, if any.
+/// Routes without a scope of their own need all of them.
+pub fn missing_default(granted: &[String], is_admin: bool) -> Option<String> {
+    default_scopes(is_admin).into_iter().find(|scope| !grants(granted, scope))
+}
+
+/// Check scopes requested for a credential: known, and within what the user
+/// could do themselves. Returns them sorted and deduplicated.
+pub fn validate_requested(requested: &[String], is_admin: bool) -> Result<Vec<String>, AuthError> {
+    if requested.is_empty() {
+        return Err(AuthError::ValidationError("At least one scope is required".into()));
+    }
+
+    let mut scopes = Vec::new();
+    for scope in requested {
+        let scope = scope.trim();
+        if !is_valid(scope) {
+            return Err(AuthError::ValidationError(format!("Unknown scope: {}", scope)));
+        }
+        let resource = scope.split(':').next().unwrap_or_default();
+        if !is_admin && ADMIN_RESOURCES.contains(&resource) {
+            return Err(AuthError::InsufficientScope { required: scope.to_string() });
+        }
+        scopes.push(scope.to_string());
+    }
+
+    scopes.sort();
+    scopes.dedup();
+    Ok(scopes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scopes(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_grants() {
+        assert!(grants(&scopes(&["users:read"]), USERS_READ));
+        assert!(!grants(&scopes(&["users:read"]), USERS_WRITE));
+        assert!(grants(&scopes(&["users:write"]), USERS_READ));
+        assert!(grants(&scopes(&["admin:*"]), ADMIN_WRITE));
+        assert!(!grants(&scopes(&["sessions:*"]), USERS_READ));
+        assert!(!grants(&[], USERS_READ));
+    }
+
+    #[test]
+    fn test_missing_default() {
+        assert_eq!(missing_default(&default_scopes(false), false), None);
+        assert_eq!(missing_default(&default_scopes(true), false), None);
+        assert_eq!(missing_default(&scopes(&["users:*", "sessions:*"]), false).as_deref(), Some("organizations:*"));
+        assert_eq!(missing_default(&default_scopes(false), true).as_deref(), Some("admin:*"));
+    }
+
+    #[test]
+    fn test_validate_requested() {
+        assert_eq!(
+            validate_requested(&scopes(&["users:read", "sessions:write", "users:read"]), false).unwrap(),
+            scopes(&["sessions:write", "users:read"])
+        );
+        assert!(validate_requested(&scopes(&["users:delete"]), false).is_err());
+        assert!(validate_requested(&[], false).is_err());
+
+        // Only admins can hand out admin scopes
+        assert!(matches!(
+            validate_requested(&scopes(&["admin:read"]), false),
+            Err(AuthError::InsufficientScope { .. })
+        ));
+        assert!(validate_requested(&scopes(&["admin:*"]), true).is_ok());
+    }
+}
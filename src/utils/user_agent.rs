@@ -0,0 +1,84 @@
+use lazy_static::lazy_static;
+use woothee::parser::Parser;
+
+lazy_static! {
+    static ref PARSER: Parser = Parser::new();
+}
+
+/// What a `User-Agent` header says about the client
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DeviceInfo {
+    pub browser: Option<String>,
+    pub os: Option<String>,
+    pub device_class: Option<String>, // desktop, mobile, appliance, bot
+}
+
+impl DeviceInfo {
+    pub fn parse(user_agent: &str) -> Self {
+        let result = match PARSER.parse(user_agent) {
+            Some(result) => result,
+            None => return DeviceInfo::default(),
+        };
+
+        let known = |value: &str| {
+            if value.is_empty() || value == woothee::woothee::VALUE_UNKNOWN {
+                None
+            } else {
+                Some(value.to_string())
+            }
+        };
+
+        let device_class = match result.category {
+            "pc" => Some("desktop"),
+            "smartphone" | "mobilephone" => Some("mobile"),
+            "appliance" => Some("appliance"),
+            "crawler" => Some("bot"),
+            _ => None,
+        };
+
+        DeviceInfo {
+            browser: known(result.name),
+            os: known(result.os),
+            device_class: device_class.map(|c| c.to_string()),
+        }
+    }
+
+    /// e.g. "Chrome on Windows 10", with " — Berlin" when a location is known
+    pub fn describe(&self, location: Option<&str>) -> String {
+        let device = match (&self.browser, &self.os) {
+            (Some(browser), Some(os)) => format!("{} on {}", browser, os),
+            (Some(name), None) | (None, Some(name)) => name.clone(),
+            (None, None) => "Unknown device".to_string(),
+        };
+
+        match location {
+            Some(location) => format!("{} — {}", device, location),
+            None => device,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_desktop_browser() {
+        let info = DeviceInfo::parse(
+            "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 \
+             (KHTML, like Gecko) Chrome/120.0.0.0 Safari/537.36",
+        );
+
+        assert_eq!(info.browser.as_deref(), Some("Chrome"));
+        assert_eq!(info.os.as_deref(), Some("Windows 10"));
+        assert_eq!(info.device_class.as_deref(), Some("desktop"));
+        assert_eq!(info.describe(Some("Berlin")), "Chrome on Windows 10 — Berlin");
+    }
+
+    #[test]
+    fn test_parse_unknown() {
+        let info = DeviceInfo::parse("curl-ish/0.1");
+
+        assert_eq!(info.describe(None), "Unknown device");
+    }
+}
@@ -3,6 +3,11 @@ use lazy_static::lazy_static;
 
 use crate::errors::AuthError;
 
+/// Largest serialized size of a user's profile metadata
+pub const MAX_METADATA_BYTES: usize = 16 * 1024;
+/// Deepest nesting allowed in profile metadata
+pub const MAX_METADATA_DEPTH: usize = 8;
+
 lazy_static! {
     // Username regex: alphanumeric, underscores, hyphens, 3-30 chars
     static ref USERNAME_REGEX: Regex = Regex::new(r"^[a-zA-Z0-9_-]{3,30}$").unwrap();
@@ -12,10 +17,8 @@ lazy_static! {
         r"^[a-zA-Z0-9.!#$%&'*+/=?^_`{|}~-]+@[a-zA-Z0-9](?:[a-zA-Z0-9-]{0,61}[a-zA-Z0-9])?(?:\.[a-zA-Z0-9](?:[a-zA-Z0-9-]{0,61}[a-zA-Z0-9])?)*$"
     ).unwrap();
     
-    // Password regex: at least 8 chars, 1 uppercase, 1 lowercase, 1 number
-    static ref PASSWORD_REGEX: Regex = Regex::new(
-        r"^(?=.*[a-z])(?=.*[A-Z])(?=.*\d).{8,}$"
-    ).unwrap();
+    // Locale regex: BCP 47 language tag, e.g. en, pt-BR, zh-Hant-TW
+    static ref LOCALE_REGEX: Regex = Regex::new(r"^[a-zA-Z]{2,3}(-[a-zA-Z0-9]{2,8})*$").unwrap();
 }
 
 /// Validate a username
@@ -28,9 +31,51 @@ pub fn validate_username(username: &str) -> Result<(), AuthError> {
     Ok(())
 }
 
-/// Validate an email address
+/// An email address in the form it's shown in and the form it's compared in
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EmailAddress {
+    pub display: String,   // As entered, trimmed, e.g. "Anna@Bücher.example"
+    pub canonical: String, // Domain lowercased and punycoded, e.g. "Anna@xn--bcher-kva.example"
+}
+
+impl EmailAddress {
+    /// The display form, when it differs from the canonical one
+    pub fn display_if_different(&self) -> Option<String> {
+        Some(self.display.clone()).filter(|display| *display != self.canonical)
+    }
+}
+
+/// Trim an address and canonicalize its domain. Internationalized domains
+/// are accepted and stored in their ASCII (punycode) form; the local part
+/// must be ASCII.
+pub fn normalize_email(email: &str) -> Result<EmailAddress, AuthError> {
+    let invalid = || AuthError::ValidationError("Invalid email format".into());
+
+    let display = email.trim();
+    if display.contains(char::is_whitespace) {
+        return Err(invalid());
+    }
+    let (local, domain) = display.rsplit_once('@').ok_or_else(invalid)?;
+    let domain = match url::Host::parse(domain) {
+        Ok(url::Host::Domain(domain)) if domain.contains('.') => domain,
+        _ => return Err(invalid()),
+    };
+
+    let canonical = format!("{}@{}", local, domain);
+    if !EMAIL_REGEX.is_match(&canonical) {
+        return Err(invalid());
+    }
+
+    Ok(EmailAddress {
+        display: display.to_string(),
+        canonical,
+    })
+}
+
+/// Validate an email address as it would be stored: no surrounding whitespace
 pub fn validate_email(email: &str) -> Result<(), AuthError> {
-    if !EMAIL_REGEX.is_match(email) {
+    let address = normalize_email(email)?;
+    if address.display != email {
         return Err(AuthError::ValidationError(
             "Invalid email format".into()
         ));
@@ -38,15 +83,28 @@ pub fn validate_email(email: &str) -> Result<(), AuthError> {
     Ok(())
 }
 
+/// The form to look an address up by. Anything that isn't an address, such
+/// as a username, comes back trimmed but otherwise as it was.
+pub fn canonical_email(input: &str) -> String {
+    normalize_email(input)
+        .map(|address| address.canonical)
+        .unwrap_or_else(|_| input.trim().to_string())
+}
+
 /// Validate a password
 pub fn validate_password(password: &str) -> Result<(), AuthError> {
-    if password.len() < 8 {
+    // Counted in characters, not bytes, so accented passwords aren't let through short
+    if password.chars().count() < 8 {
         return Err(AuthError::ValidationError(
             "Password must be at least 8 characters long".into()
         ));
     }
 
-    if !PASSWORD_REGEX.is_match(password) {
+    // Checked by hand: the regex crate has no lookahead to express "contains each of"
+    let has_lowercase = password.chars().any(|c| c.is_ascii_lowercase());
+    let has_uppercase = password.chars().any(|c| c.is_ascii_uppercase());
+    let has_digit = password.chars().any(|c| c.is_ascii_digit());
+    if !(has_lowercase && has_uppercase && has_digit) {
         return Err(AuthError::ValidationError(
             "Password must contain at least one uppercase letter, one lowercase letter, and one number".into()
         ));
@@ -55,6 +113,85 @@ pub fn validate_password(password: &str) -> Result<(), AuthError> {
     Ok(())
 }
 
+/// Validate a BCP 47 locale
+pub fn validate_locale(locale: &str) -> Result<(), AuthError> {
+    if !LOCALE_REGEX.is_match(locale) {
+        return Err(AuthError::ValidationError(
+            "Locale must be a language tag such as en or pt-BR".into()
+        ));
+    }
+    Ok(())
+}
+
+/// Validate an IANA time zone name
+pub fn validate_timezone(timezone: &str) -> Result<(), AuthError> {
+    if timezone.parse::<chrono_tz::Tz>().is_err() {
+        return Err(AuthError::ValidationError(
+            "Time zone must be an IANA name such as Europe/Berlin".into()
+        ));
+    }
+    Ok(())
+}
+
+/// Validate an http(s) URL
+pub fn validate_http_url(value: &str) -> Result<(), AuthError> {
+    match url::Url::parse(value) {
+        Ok(url) if matches!(url.scheme(), "http" | "https") && url.has_host() => Ok(()),
+        _ => Err(AuthError::ValidationError(
+            "URL must be an absolute http or https URL".into()
+        )),
+    }
+}
+
+/// Validate a client's redirect URI: absolute, without a fragment, and over
+/// https unless it's on the client's own machine
+pub fn validate_redirect_uri(value: &str) -> Result<(), AuthError> {
+    validate_http_url(value)?;
+    let url = url::Url::parse(value).map_err(|_| AuthError::ValidationError("Invalid redirect URI".into()))?;
+
+    if url.fragment().is_some() {
+        return Err(AuthError::ValidationError("Redirect URIs can't have a fragment".into()));
+    }
+    let loopback = matches!(url.host_str(), Some("localhost" | "127.0.0.1" | "[::1]"));
+    if url.scheme() != "https" && !loopback {
+        return Err(AuthError::ValidationError(
+            "Redirect URIs must use https, except on localhost".into()
+        ));
+    }
+    Ok(())
+}
+
+/// Validate profile metadata: a JSON object within the size and depth limits
+pub fn validate_metadata(metadata: &serde_json::Value) -> Result<(), AuthError> {
+    fn depth(value: &serde_json::Value) -> usize {
+        match value {
+            serde_json::Value::Object(map) => 1 + map.values().map(depth).max().unwrap_or(0),
+            serde_json::Value::Array(items) => 1 + items.iter().map(depth).max().unwrap_or(0),
+            _ => 0,
+        }
+    }
+    
+    if !metadata.is_object() {
+        return Err(AuthError::ValidationError("Metadata must be a JSON object".into()));
+    }
+    
+    if metadata.to_string().len() > MAX_METADATA_BYTES {
+        return Err(AuthError::ValidationError(format!(
+            "Metadata must be at most {} bytes",
+            MAX_METADATA_BYTES
+        )));
+    }
+    
+    if depth(metadata) > MAX_METADATA_DEPTH {
+        return Err(AuthError::ValidationError(format!(
+            "Metadata must be nested at most {} levels deep",
+            MAX_METADATA_DEPTH
+        )));
+    }
+    
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -82,6 +219,28 @@ mod tests {
         assert!(validate_email("user@").is_err());
         assert!(validate_email("user@example").is_err());
         assert!(validate_email("user.example.com").is_err());
+        assert!(validate_email(" user@example.com").is_err());
+    }
+
+    #[test]
+    fn test_normalize_email() {
+        let address = normalize_email("  Anna@Example.COM ").unwrap();
+        assert_eq!(address.display, "Anna@Example.COM");
+        assert_eq!(address.canonical, "Anna@example.com");
+
+        // Internationalized domains are compared in their ASCII form
+        let address = normalize_email("anna@bücher.example").unwrap();
+        assert_eq!(address.canonical, "anna@xn--bcher-kva.example");
+        assert_eq!(address.display_if_different().as_deref(), Some("anna@bücher.example"));
+        assert_eq!(normalize_email("anna@BÜCHER.example").unwrap().canonical, address.canonical);
+        assert!(normalize_email("anna@xn--bcher-kva.example").unwrap().display_if_different().is_none());
+
+        assert!(normalize_email("änna@example.com").is_err());
+        assert!(normalize_email("anna@exa mple.com").is_err());
+        assert!(normalize_email("anna@127.0.0.1").is_err());
+
+        assert_eq!(canonical_email(" Bob@EXAMPLE.com"), "Bob@example.com");
+        assert_eq!(canonical_email(" bob "), "bob");
     }
 
     #[test]
@@ -96,4 +255,36 @@ mod tests {
         assert!(validate_password("PASSWORD123").is_err()); // no lowercase
         assert!(validate_password("Password").is_err()); // no number
     }
+
+    #[test]
+    fn test_validate_profile_fields() {
+        assert!(validate_locale("pt-BR").is_ok());
+        assert!(validate_locale("english").is_err());
+
+        assert!(validate_timezone("Europe/Berlin").is_ok());
+        assert!(validate_timezone("Mars/Olympus_Mons").is_err());
+
+        assert!(validate_http_url("https://cdn.example.com/a.png").is_ok());
+        assert!(validate_http_url("javascript:alert(1)").is_err());
+
+        assert!(validate_redirect_uri("https://app.example/callback?from=login").is_ok());
+        assert!(validate_redirect_uri("http://127.0.0.1:8080/callback").is_ok());
+        assert!(validate_redirect_uri("http://app.example/callback").is_err());
+        assert!(validate_redirect_uri("https://app.example/callback#tokens").is_err());
+    }
+
+    #[test]
+    fn test_validate_metadata() {
+        assert!(validate_metadata(&serde_json::json!({"plan": "pro", "tags": ["a"]})).is_ok());
+        assert!(validate_metadata(&serde_json::json!(["not", "an", "object"])).is_err());
+
+        let too_big = "x".repeat(MAX_METADATA_BYTES);
+        assert!(validate_metadata(&serde_json::json!({ "blob": too_big })).is_err());
+
+        let mut nested = serde_json::json!({});
+        for _ in 0..MAX_METADATA_DEPTH {
+            nested = serde_json::json!({ "a": nested });
+        }
+        assert!(validate_metadata(&nested).is_err());
+    }
 }
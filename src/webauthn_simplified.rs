@@ -13,6 +13,7 @@ pub struct WebAuthnCredential {
     pub counter: u32,
     pub created_at: DateTime<Utc>,
     pub last_used_at: Option<DateTime<Utc>>,
+    pub aaguid: Option<Uuid>, // Authenticator model, from the attestation
 }
 
 #[derive(Debug, Serialize)]
@@ -37,7 +38,7 @@ pub struct WebAuthnRegisterCompleteRequest {
     pub credential: WebAuthnCredentialResponse,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct WebAuthnCredentialResponse {
     pub id: String,
     pub raw_id: String,
@@ -46,7 +47,7 @@ pub struct WebAuthnCredentialResponse {
     pub credential_type: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct WebAuthnAuthenticatorResponse {
     pub client_data_json: String,
     pub attestation_object: Option<String>,
@@ -135,6 +136,13 @@ impl WebAuthnContext {
         // In a real implementation, we would validate the credential
         // For demo, just create a credential with the ID from the request
         
+        let aaguid = req
+            .credential
+            .response
+            .attestation_object
+            .as_deref()
+            .and_then(attested_aaguid);
+
         // Create our credential model
         let credential = WebAuthnCredential {
             credential_id: req.credential.id.clone(),
@@ -142,6 +150,7 @@ impl WebAuthnContext {
             counter: 0,
             created_at: Utc::now(),
             last_used_at: None,
+            aaguid,
         };
         
         Ok(credential)
@@ -205,4 +214,263 @@ impl WebAuthnContext {
         
         Ok(updated_cred)
     }
-}
\ No newline at end of file
+}
+
+// Flag in `authData` set when the authenticator checked the user's PIN or biometric
+const USER_VERIFIED: u8 = 0x04;
+
+// Flag in `authData` set when attested credential data follows the counter
+const ATTESTED_CREDENTIAL_DATA: u8 = 0x40;
+
+// Deeper than any real attestation statement nests
+const MAX_CBOR_DEPTH: usize = 16;
+
+/// The AAGUID naming the authenticator model, read from the attested
+/// credential data in a base64url attestation object. `None` when the object
+/// doesn't parse or carries no credential data, and for the all-zero AAGUID
+/// authenticators send when they won't say what they are.
+pub fn attested_aaguid(attestation_object: &str) -> Option<Uuid> {
+    use base64::Engine;
+
+    let trimmed = attestation_object.trim_end_matches('=');
+    let bytes = base64::engine::general_purpose::URL_SAFE_NO_PAD
+        .decode(trimmed)
+        .or_else(|_| base64::engine::general_purpose::STANDARD_NO_PAD.decode(trimmed))
+        .ok()?;
+
+    // rpIdHash (32 bytes), flags (1), signCount (4), then the AAGUID (16)
+    let auth_data = cbor_bytes_field(&bytes, "authData")?;
+    if auth_data.get(32)? & ATTESTED_CREDENTIAL_DATA == 0 {
+        return None;
+    }
+    let aaguid = Uuid::from_slice(auth_data.get(37..53)?).ok()?;
+    (!aaguid.is_nil()).then_some(aaguid)
+}
+
+/// Whether the authenticator verified its user, from the flags in a base64url
+/// assertion's `authenticatorData`. Anything that doesn't parse is unverified.
+pub fn user_verified(authenticator_data: &str) -> bool {
+    use base64::Engine;
+
+    let trimmed = authenticator_data.trim_end_matches('=');
+    base64::engine::general_purpose::URL_SAFE_NO_PAD
+        .decode(trimmed)
+        .or_else(|_| base64::engine::general_purpose::STANDARD_NO_PAD.decode(trimmed))
+        .ok()
+        .and_then(|bytes| bytes.get(32).copied())
+        .is_some_and(|flags| flags & USER_VERIFIED != 0)
+}
+
+// The byte string under `key` in a top-level CBOR map. Just enough CBOR to
+// find `authData` in an attestation object; anything unexpected is `None`.
+fn cbor_bytes_field<'a>(bytes: &'a [u8], key: &str) -> Option<&'a [u8]> {
+    let mut reader = CborReader { bytes, pos: 0 };
+    let (major, entries) = reader.header()?;
+    if major != 5 {
+        return None;
+    }
+
+    for _ in 0..entries {
+        let (key_major, key_len) = reader.header()?;
+        if key_major != 3 {
+            reader.skip_value(key_major, key_len, 0)?;
+            reader.skip()?;
+            continue;
+        }
+        let found = reader.take(key_len)? == key.as_bytes();
+
+        let (value_major, value_len) = reader.header()?;
+        if found {
+            return if value_major == 2 { reader.take(value_len) } else { None };
+        }
+        reader.skip_value(value_major, value_len, 0)?;
+    }
+    None
+}
+
+struct CborReader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> CborReader<'a> {
+    fn take(&mut self, len: u64) -> Option<&'a [u8]> {
+        let end = self.pos.checked_add(usize::try_from(len).ok()?)?;
+        let taken = self.bytes.get(self.pos..end)?;
+        self.pos = end;
+        Some(taken)
+    }
+
+    // Major type and argument of the next item. Indefinite lengths aren't
+    // allowed in attestation objects, so they don't parse.
+    fn header(&mut self) -> Option<(u8, u64)> {
+        let initial = *self.take(1)?.first()?;
+        let major = initial >> 5;
+        let argument = match initial & 0x1f {
+            info @ 0..=23 => info as u64,
+            24 => self.take(1)?[0] as u64,
+            25 => u16::from_be_bytes(self.take(2)?.try_into().ok()?) as u64,
+            26 => u32::from_be_bytes(self.take(4)?.try_into().ok()?) as u64,
+            27 => u64::from_be_bytes(self.take(8)?.try_into().ok()?),
+            _ => return None,
+        };
+        Some((major, argument))
+    }
+
+    fn skip(&mut self) -> Option<()> {
+        let (major, argument) = self.header()?;
+        self.skip_value(major, argument, 0)
+    }
+
+    fn skip_nested(&mut self, depth: usize) -> Option<()> {
+        let (major, argument) = self.header()?;
+        self.skip_value(major, argument, depth)
+    }
+
+    // Skip the rest of an item whose header was just read
+    fn skip_value(&mut self, major: u8, argument: u64, depth: usize) -> Option<()> {
+        if depth > MAX_CBOR_DEPTH {
+            return None;
+        }
+        match major {
+            0 | 1 | 7 => Some(()),
+            2 | 3 => self.take(argument).map(|_| ()),
+            4 => (0..argument).try_for_each(|_| self.skip_nested(depth + 1)),
+            5 => (0..argument).try_for_each(|_| {
+                self.skip_nested(depth + 1)?;
+                self.skip_nested(depth + 1)
+            }),
+            6 => self.skip_nested(depth + 1),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use base64::Engine;
+    use proptest::prelude::*;
+
+    use super::*;
+
+    const AAGUID: &str = "cb69481e-8ff7-4039-93ec-0a2729a154a8";
+
+    // {"fmt": "none", "attStmt": {}, "authData": <auth_data>}
+    fn attestation_object(auth_data: &[u8]) -> String {
+        let mut cbor = vec![0xa3];
+        cbor.extend_from_slice(&[0x63, b'f', b'm', b't', 0x64, b'n', b'o', b'n', b'e']);
+        cbor.extend_from_slice(&[0x67, b'a', b't', b't', b'S', b't', b'm', b't', 0xa0]);
+        cbor.extend_from_slice(&[0x68, b'a', b'u', b't', b'h', b'D', b'a', b't', b'a']);
+        cbor.extend_from_slice(&[0x58, auth_data.len() as u8]);
+        cbor.extend_from_slice(auth_data);
+        base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(cbor)
+    }
+
+    fn auth_data(flags: u8, aaguid: Uuid) -> Vec<u8> {
+        let mut data = vec![0u8; 32];
+        data.push(flags);
+        data.extend_from_slice(&[0, 0, 0, 0]);
+        data.extend_from_slice(aaguid.as_bytes());
+        data.extend_from_slice(&[0, 1, 0xaa]); // Credential ID length and ID
+        data
+    }
+
+    #[test]
+    fn test_aaguid_is_read_from_attested_credential_data() {
+        let aaguid = Uuid::parse_str(AAGUID).unwrap();
+        assert_eq!(attested_aaguid(&attestation_object(&auth_data(0x45, aaguid))), Some(aaguid));
+
+        // No attested credential data, or a model that won't say
+        assert_eq!(attested_aaguid(&attestation_object(&auth_data(0x05, aaguid))), None);
+        assert_eq!(attested_aaguid(&attestation_object(&auth_data(0x45, Uuid::nil()))), None);
+    }
+
+    #[test]
+    fn test_user_verification_is_read_from_assertion_flags() {
+        let encode = |data: &[u8]| base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(data);
+        let aaguid = Uuid::parse_str(AAGUID).unwrap();
+        assert!(user_verified(&encode(&auth_data(0x05, aaguid))));
+        assert!(!user_verified(&encode(&auth_data(0x01, aaguid))));
+        assert!(!user_verified(&encode(&[0x05; 16])));
+        assert!(!user_verified("not base64!"));
+    }
+
+    #[test]
+    fn test_malformed_attestation_objects_have_no_aaguid() {
+        let aaguid = Uuid::parse_str(AAGUID).unwrap();
+        assert_eq!(attested_aaguid("not base64!"), None);
+        assert_eq!(attested_aaguid(&attestation_object(&auth_data(0x45, aaguid)[..40])), None);
+
+        // Nested past the depth limit
+        let mut deep = vec![0xa1, 0x61, b'x'];
+        deep.extend(std::iter::repeat(0x81).take(100));
+        deep.push(0x00);
+        let deep = base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(deep);
+        assert_eq!(attested_aaguid(&deep), None);
+    }
+
+    // Base64url, as browsers encode WebAuthn binary fields
+    fn b64() -> impl Strategy<Value = String> {
+        "[A-Za-z0-9_-]{0,128}"
+    }
+
+    fn credential_json() -> impl Strategy<Value = serde_json::Value> {
+        (b64(), b64(), b64(), prop::option::of(b64()), prop::option::of(b64())).prop_map(
+            |(id, client_data_json, attestation_object, signature, user_handle)| {
+                serde_json::json!({
+                    "id": id,
+                    "raw_id": id,
+                    "type": "public-key",
+                    "response": {
+                        "client_data_json": client_data_json,
+                        "attestation_object": attestation_object,
+                        "authenticator_data": null,
+                        "signature": signature,
+                        "user_handle": user_handle,
+                    },
+                })
+            },
+        )
+    }
+
+    proptest! {
+        #[test]
+        fn prop_deserialize_never_panics(body in any::<Vec<u8>>()) {
+            let _ = serde_json::from_slice::<WebAuthnRegisterCompleteRequest>(&body);
+            let _ = serde_json::from_slice::<WebAuthnAuthenticateCompleteRequest>(&body);
+        }
+
+        #[test]
+        fn prop_deserialize_never_panics_on_json(value in "\\PC{0,200}") {
+            let _ = serde_json::from_str::<WebAuthnRegisterCompleteRequest>(&value);
+            let _ = serde_json::from_str::<WebAuthnAuthenticateCompleteRequest>(&value);
+        }
+
+        #[test]
+        fn prop_well_formed_requests_roundtrip(id in "[a-f0-9-]{1,36}", credential in credential_json()) {
+            let body = serde_json::json!({ "authentication_id": id, "credential": credential });
+            let request: WebAuthnAuthenticateCompleteRequest = serde_json::from_value(body.clone()).unwrap();
+            prop_assert_eq!(&request.authentication_id, &id);
+            prop_assert_eq!(&request.credential.id, credential["id"].as_str().unwrap());
+            prop_assert_eq!(&request.credential.credential_type, "public-key");
+
+            // Serializing gives back the same credential
+            let reserialized = serde_json::to_value(&request.credential).unwrap();
+            prop_assert_eq!(&reserialized, &body["credential"]);
+        }
+
+        #[test]
+        fn prop_missing_credential_fields_rejected(credential in credential_json(), field in prop::sample::select(vec!["id", "raw_id", "type", "response"])) {
+            let mut credential = credential;
+            credential.as_object_mut().unwrap().remove(field);
+            let body = serde_json::json!({ "registration_id": "r", "credential": credential });
+            prop_assert!(serde_json::from_value::<WebAuthnRegisterCompleteRequest>(body).is_err());
+        }
+
+        #[test]
+        fn prop_authenticator_data_parsing_never_panics(data in b64()) {
+            let _ = attested_aaguid(&data);
+            let _ = user_verified(&data);
+        }
+    }
+}
@@ -0,0 +1,171 @@
+//! Property tests for the service layer's parsers and validators. Needs the
+//! service modules, so run with `cargo test --features server --test properties`.
+
+use better_auth_rust::errors::AuthError;
+use better_auth_rust::utils::jwt::{create_jwt, decode_jwt_with_secret, JwtClaims, TokenAudience, TokenScope};
+use better_auth_rust::utils::validation::{
+    validate_email, validate_http_url, validate_locale, validate_metadata, validate_password, validate_timezone,
+    validate_username, MAX_METADATA_BYTES,
+};
+use chrono::{Duration, Utc};
+use proptest::prelude::*;
+use uuid::Uuid;
+
+fn expected() -> TokenAudience {
+    TokenAudience::new("better-auth", &["web".to_string(), "mobile".to_string()])
+}
+
+fn valid_claims(is_admin: bool, token_version: i32, amr: Vec<String>) -> JwtClaims {
+    JwtClaims {
+        sub: Uuid::new_v4(),
+        iss: "better-auth".to_string(),
+        aud: "web".to_string(),
+        exp: (Utc::now() + Duration::hours(1)).timestamp() as usize,
+        iat: Utc::now().timestamp() as usize,
+        is_admin,
+        token_version,
+        scope: TokenScope::Full,
+        auth_time: None,
+        amr,
+        aal: None,
+        scopes: Vec::new(),
+        cnf: None,
+        sid: None,
+        jti: None,
+        act: None,
+    }
+}
+
+proptest! {
+    #[test]
+    fn prop_decode_never_panics(token in any::<String>()) {
+        let result: Result<JwtClaims, AuthError> = decode_jwt_with_secret(&token, "test_secret_key", &expected());
+        prop_assert!(result.is_err());
+    }
+
+    #[test]
+    fn prop_decode_rejects_garbage_segments(
+        header in "[A-Za-z0-9_-]{0,40}",
+        payload in "[A-Za-z0-9_-]{0,80}",
+        signature in "[A-Za-z0-9_-]{0,43}",
+    ) {
+        let token = format!("{}.{}.{}", header, payload, signature);
+        let result: Result<JwtClaims, AuthError> = decode_jwt_with_secret(&token, "test_secret_key", &expected());
+        prop_assert!(result.is_err());
+    }
+
+    #[test]
+    fn prop_roundtrip(
+        is_admin in any::<bool>(),
+        token_version in any::<i32>(),
+        amr in prop::collection::vec("[a-z]{1,8}", 0..4),
+    ) {
+        let secret = "test_secret_key";
+        let claims = valid_claims(is_admin, token_version, amr.clone());
+        let token = create_jwt(&claims, secret).unwrap();
+
+        let decoded: JwtClaims = decode_jwt_with_secret(&token, secret, &expected()).unwrap();
+        prop_assert_eq!(decoded.sub, claims.sub);
+        prop_assert_eq!(decoded.is_admin, is_admin);
+        prop_assert_eq!(decoded.token_version, token_version);
+        prop_assert_eq!(decoded.amr, amr);
+    }
+
+    #[test]
+    fn prop_tampered_token_rejected(index in any::<prop::sample::Index>(), replacement in "[A-Za-z0-9_-]") {
+        let secret = "test_secret_key";
+        let token = create_jwt(&valid_claims(false, 0, Vec::new()), secret).unwrap();
+
+        let position = index.index(token.len());
+        let original = &token[position..position + 1];
+        // Dots split the segments; changing one is covered by the garbage test
+        prop_assume!(original != "." && original != replacement);
+
+        let mut tampered = token.clone();
+        tampered.replace_range(position..position + 1, &replacement);
+
+        // Only accepted if the change decodes to the same bytes, which
+        // can happen in a base64 character's unused trailing bits
+        if let Ok(decoded) = decode_jwt_with_secret::<JwtClaims>(&tampered, secret, &expected()) {
+            prop_assert_eq!(position + 1, token.len());
+            prop_assert_eq!(decoded.is_admin, false);
+        }
+    }
+
+    #[test]
+    fn prop_wrong_secret_rejected(secret in "[ -~]{1,64}") {
+        prop_assume!(secret != "test_secret_key");
+        let token = create_jwt(&valid_claims(true, 0, Vec::new()), "test_secret_key").unwrap();
+        prop_assert!(decode_jwt_with_secret::<JwtClaims>(&token, &secret, &expected()).is_err());
+    }
+}
+
+// Arbitrary JSON, nested a few levels
+fn json_value() -> impl Strategy<Value = serde_json::Value> {
+    let leaf = prop_oneof![
+        Just(serde_json::Value::Null),
+        any::<bool>().prop_map(serde_json::Value::from),
+        any::<i64>().prop_map(serde_json::Value::from),
+        ".{0,20}".prop_map(serde_json::Value::from),
+    ];
+    leaf.prop_recursive(12, 64, 4, |inner| {
+        prop_oneof![
+            prop::collection::vec(inner.clone(), 0..4).prop_map(serde_json::Value::from),
+            prop::collection::hash_map("[a-z]{1,8}", inner, 0..4)
+                .prop_map(|map| serde_json::Value::Object(map.into_iter().collect())),
+        ]
+    })
+}
+
+proptest! {
+    #[test]
+    fn prop_validators_never_panic(input in any::<String>()) {
+        let _ = validate_username(&input);
+        let _ = validate_email(&input);
+        let _ = validate_password(&input);
+        let _ = validate_locale(&input);
+        let _ = validate_timezone(&input);
+        let _ = validate_http_url(&input);
+    }
+
+    #[test]
+    fn prop_username_matches_rule(input in any::<String>()) {
+        let expected = (3..=30).contains(&input.chars().count())
+            && input.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+        prop_assert_eq!(validate_username(&input).is_ok(), expected);
+    }
+
+    #[test]
+    fn prop_password_matches_rule(input in any::<String>()) {
+        let expected = input.chars().count() >= 8
+            && input.chars().any(|c| c.is_ascii_lowercase())
+            && input.chars().any(|c| c.is_ascii_uppercase())
+            && input.chars().any(|c| c.is_ascii_digit());
+        prop_assert_eq!(validate_password(&input).is_ok(), expected);
+    }
+
+    #[test]
+    fn prop_strong_passwords_accepted(password in "[a-z][A-Z][0-9]\\PC{5,40}") {
+        prop_assert!(validate_password(&password).is_ok());
+    }
+
+    #[test]
+    fn prop_accepted_emails_have_one_at(input in "\\PC{0,40}") {
+        if validate_email(&input).is_ok() {
+            prop_assert_eq!(input.matches('@').count(), 1);
+            prop_assert!(!input.contains(char::is_whitespace));
+        }
+    }
+
+    #[test]
+    fn prop_metadata_limits(value in json_value()) {
+        let accepted = validate_metadata(&value).is_ok();
+        if accepted {
+            prop_assert!(value.is_object());
+            prop_assert!(value.to_string().len() <= MAX_METADATA_BYTES);
+        }
+        if !value.is_object() {
+            prop_assert!(!accepted);
+        }
+    }
+}